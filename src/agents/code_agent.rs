@@ -3,17 +3,18 @@ use async_trait::async_trait;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
-use crate::context::ContextManager;
+use crate::context::SharedContext;
 use crate::llm::{self, ModelConfig};
 use crate::memory::MemoryManager;
 use crate::sr_parser;
 use crate::editor;
 
 use super::traits::{Agent, AgentCapability, AgentMessage, AgentTask, TaskStatus};
+use super::transcript::{AgentTranscript, TranscriptEntryKind};
 
 pub struct CodeAgent {
     name: String,
-    context_manager: Option<Arc<Mutex<ContextManager>>>,
+    context_manager: Option<SharedContext>,
     model_config: Option<ModelConfig>,
     memory_manager: Option<Arc<Mutex<MemoryManager>>>,
 }
@@ -47,7 +48,7 @@ impl Agent for CodeAgent {
     
     async fn initialize(
         &mut self,
-        context_manager: Arc<Mutex<ContextManager>>,
+        context_manager: SharedContext,
         model_config: ModelConfig,
         memory_manager: Arc<Mutex<MemoryManager>>,
     ) -> Result<()> {
@@ -82,39 +83,31 @@ impl Agent for CodeAgent {
     
     async fn execute_task(&mut self, task: &mut AgentTask) -> Result<()> {
         task.update_status(TaskStatus::InProgress);
+        let mut transcript = AgentTranscript::new(&task.id, &self.name, &task.description);
         
         // Extract key information from task description
-        let description = &task.description;
+        let description = task.description.clone();
         
-        if description.contains("implement") || description.contains("create") {
-            match self.generate_code(description).await {
-                Ok(result) => {
-                    task.update_status(TaskStatus::Completed(result));
-                }
-                Err(e) => {
-                    task.update_status(TaskStatus::Failed(e.to_string()));
-                }
-            }
+        let result = if description.contains("implement") || description.contains("create") {
+            Some(self.generate_code(&description, &mut transcript).await)
         } else if description.contains("refactor") || description.contains("improve") {
-            match self.refactor_code(description).await {
-                Ok(result) => {
-                    task.update_status(TaskStatus::Completed(result));
-                }
-                Err(e) => {
-                    task.update_status(TaskStatus::Failed(e.to_string()));
-                }
-            }
+            Some(self.refactor_code(&description, &mut transcript).await)
         } else if description.contains("test") {
-            match self.generate_tests(description).await {
-                Ok(result) => {
-                    task.update_status(TaskStatus::Completed(result));
-                }
-                Err(e) => {
-                    task.update_status(TaskStatus::Failed(e.to_string()));
-                }
-            }
+            Some(self.generate_tests(&description, &mut transcript).await)
         } else {
-            task.update_status(TaskStatus::Completed("Task analyzed and ready for implementation".to_string()));
+            None
+        };
+        
+        match result {
+            Some(Ok(result)) => task.update_status(TaskStatus::Completed(result)),
+            Some(Err(e)) => task.update_status(TaskStatus::Failed(e.to_string())),
+            None => task.update_status(TaskStatus::Completed("Task analyzed and ready for implementation".to_string())),
+        }
+        
+        task.usage.add(&transcript.usage);
+        transcript.record_status(&task.status);
+        if let Err(e) = transcript.save() {
+            eprintln!("Warning: Failed to save agent transcript: {}", e);
         }
         
         Ok(())
@@ -185,8 +178,7 @@ impl Agent for CodeAgent {
 impl CodeAgent {
     async fn analyze_code_request(&self, query: &str) -> Result<String> {
         let context = if let Some(cm) = &self.context_manager {
-            let cm = cm.lock().await;
-            cm.get_formatted_context()
+            cm.formatted().await.to_string()
         } else {
             String::new()
         };
@@ -203,10 +195,9 @@ impl CodeAgent {
         llm::ask_model_with_config(&prompt, &context, model_config).await
     }
     
-    async fn generate_code(&self, description: &str) -> Result<String> {
+    async fn generate_code(&self, description: &str, transcript: &mut AgentTranscript) -> Result<String> {
         let context = if let Some(cm) = &self.context_manager {
-            let cm = cm.lock().await;
-            cm.get_formatted_context()
+            cm.formatted().await.to_string()
         } else {
             String::new()
         };
@@ -219,15 +210,21 @@ impl CodeAgent {
             description
         );
         
-        let response = llm::ask_model_with_config(&prompt, &context, model_config).await?;
+        let response = transcript.ask(&prompt, &context, model_config).await?;
         
         // Check for S/R blocks and apply them
         if sr_parser::contains_sr_blocks(&response) {
             if let Ok(blocks) = sr_parser::parse_sr_blocks(&response) {
                 if !blocks.is_empty() {
-                    if let Some(cm) = &self.context_manager {
-                        let cm = cm.lock().await;
-                        editor::confirm_and_apply_blocks(blocks, &prompt, &cm).await?;
+                    if let Some(shared) = &self.context_manager {
+                        let cm = shared.snapshot().await;
+                        let files: Vec<String> = blocks.iter().map(|b| b.file_path.clone()).collect();
+                        transcript.record(
+                            TranscriptEntryKind::Action,
+                            &format!("Submitted {} S/R block(s) for review: {}", files.len(), files.join(", ")),
+                        );
+                        let applied = editor::confirm_and_apply_blocks(blocks, &prompt, &cm).await?;
+                        shared.write().await.record_edits(&applied);
                         return Ok("Code generated and applied successfully".to_string());
                     }
                 }
@@ -237,10 +234,9 @@ impl CodeAgent {
         Ok(response)
     }
     
-    async fn refactor_code(&self, description: &str) -> Result<String> {
+    async fn refactor_code(&self, description: &str, transcript: &mut AgentTranscript) -> Result<String> {
         let context = if let Some(cm) = &self.context_manager {
-            let cm = cm.lock().await;
-            cm.get_formatted_context()
+            cm.formatted().await.to_string()
         } else {
             String::new()
         };
@@ -253,13 +249,12 @@ impl CodeAgent {
             description
         );
         
-        llm::ask_model_with_config(&prompt, &context, model_config).await
+        transcript.ask(&prompt, &context, model_config).await
     }
     
-    async fn generate_tests(&self, description: &str) -> Result<String> {
+    async fn generate_tests(&self, description: &str, transcript: &mut AgentTranscript) -> Result<String> {
         let context = if let Some(cm) = &self.context_manager {
-            let cm = cm.lock().await;
-            cm.get_formatted_context()
+            cm.formatted().await.to_string()
         } else {
             String::new()
         };
@@ -272,6 +267,6 @@ impl CodeAgent {
             description
         );
         
-        llm::ask_model_with_config(&prompt, &context, model_config).await
+        transcript.ask(&prompt, &context, model_config).await
     }
 }
\ No newline at end of file
@@ -0,0 +1,278 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration as ChronoDuration, Local};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::clock::clock;
+use crate::db::Database;
+use crate::dynamic_prompts;
+use crate::llm::ModelConfig;
+use crate::memory::MemoryManager;
+use crate::prompts::PromptsConfig;
+
+use super::session;
+use super::traits::TaskStatus;
+use super::transcript::{AgentTranscript, TranscriptEntryKind};
+
+const MAINTENANCE_DIR: &str = ".kota/maintenance";
+const INDEX_PATH: &str = ".kota/index/files.tsv";
+
+// Rate limits: one run per night, and only a handful of model calls per run
+const MIN_HOURS_BETWEEN_RUNS: i64 = 20;
+const MAX_LLM_CALLS_PER_RUN: u32 = 3;
+const MAX_SUMMARY_INPUT_CHARS: usize = 20_000;
+
+const CONVERSATION_RETENTION_DAYS: u64 = 30;
+const TRANSCRIPT_RETENTION_DAYS: u64 = 30;
+
+#[derive(Debug)]
+pub struct MaintenanceReport {
+    pub transcript_id: Option<String>,
+    pub briefing_path: Option<PathBuf>,
+    /// Set when the run was skipped by the rate limit
+    pub skipped: Option<String>,
+}
+
+/// Nightly housekeeping: re-index the repo, prune memory, summarize recent
+/// sessions and write a morning briefing. Meant to be run from cron with
+/// `kota --maintenance`; every action is recorded in an agent transcript.
+pub struct MaintenanceAgent {
+    model_config: ModelConfig,
+    memory: MemoryManager,
+}
+
+impl MaintenanceAgent {
+    pub fn new(model_config: ModelConfig) -> Result<Self> {
+        Ok(Self {
+            model_config,
+            memory: MemoryManager::new()?,
+        })
+    }
+
+    pub async fn run(&self, force: bool) -> Result<MaintenanceReport> {
+        let last_run = read_last_run();
+        if let Some(last) = last_run {
+            let elapsed = Local::now() - last;
+            if !force && elapsed < ChronoDuration::hours(MIN_HOURS_BETWEEN_RUNS) {
+                return Ok(MaintenanceReport {
+                    transcript_id: None,
+                    briefing_path: None,
+                    skipped: Some(format!(
+                        "Last run was {} hour(s) ago; maintenance runs at most every {} hours (use --force to override)",
+                        elapsed.num_hours(),
+                        MIN_HOURS_BETWEEN_RUNS
+                    )),
+                });
+            }
+        }
+
+        let started = Local::now();
+        let transcript_id = format!("maintenance-{}", started.format("%Y%m%d-%H%M"));
+        let mut transcript = AgentTranscript::new(&transcript_id, "MaintenanceAgent", "Nightly maintenance");
+        let mut briefing = format!("# Morning Briefing - {}, {}\n", clock().to_user(&started).format("%A"), clock().date(&started));
+        if let Some(weather) = dynamic_prompts::refresh_weather().await {
+            let max_age = PromptsConfig::load().unwrap_or_default().weather.cache_minutes;
+            briefing.push_str(&format!("\n## Weather\n\n- {}\n", weather.describe(max_age)));
+        }
+
+        // Re-index the repository
+        match reindex_repo() {
+            Ok(count) => {
+                transcript.record(TranscriptEntryKind::Action, &format!("Indexed {} file(s) into {}", count, INDEX_PATH));
+                briefing.push_str(&format!("\n## Repository\n\n- {} tracked file(s) indexed\n", count));
+            }
+            Err(e) => transcript.record(TranscriptEntryKind::Error, &format!("Re-index failed: {}", e)),
+        }
+        briefing.push_str(&git_activity_since(last_run));
+
+        // Prune memory and old transcripts
+        match self.memory.prune_conversations(CONVERSATION_RETENTION_DAYS) {
+            Ok(archived) => transcript.record(
+                TranscriptEntryKind::Action,
+                &format!("Archived {} conversation entries older than {} days", archived, CONVERSATION_RETENTION_DAYS),
+            ),
+            Err(e) => transcript.record(TranscriptEntryKind::Error, &format!("Pruning conversations failed: {}", e)),
+        }
+        match archive_old_transcripts(TRANSCRIPT_RETENTION_DAYS) {
+            Ok(count) => transcript.record(
+                TranscriptEntryKind::Action,
+                &format!("Archived {} agent transcript(s) older than {} days", count, TRANSCRIPT_RETENTION_DAYS),
+            ),
+            Err(e) => transcript.record(TranscriptEntryKind::Error, &format!("Archiving transcripts failed: {}", e)),
+        }
+
+        // Summarize the sessions since the last run
+        let since = last_run.unwrap_or_else(|| started - ChronoDuration::hours(24));
+        briefing.push_str("\n## Recent Sessions\n\n");
+        match self.summarize_sessions(since, &mut transcript).await {
+            Ok(Some(summary)) => briefing.push_str(&format!("{}\n", summary.trim())),
+            Ok(None) => briefing.push_str("No sessions since the last run.\n"),
+            Err(e) => {
+                transcript.record(TranscriptEntryKind::Error, &format!("Session summary failed: {}", e));
+                briefing.push_str("Session summary unavailable.\n");
+            }
+        }
+
+        // Pending agent work
+        if let Ok(sessions) = session::list_resumable_sessions() {
+            if !sessions.is_empty() {
+                briefing.push_str("\n## Paused Agent Sessions\n\n");
+                for (id, description, _) in sessions {
+                    briefing.push_str(&format!("- {} (`/agent resume {}`)\n", description, id));
+                }
+            }
+        }
+
+        let briefing_path = PathBuf::from(MAINTENANCE_DIR).join(format!("briefing-{}.md", started.format("%Y-%m-%d")));
+        fs::create_dir_all(MAINTENANCE_DIR)
+            .with_context(|| format!("Failed to create {}", MAINTENANCE_DIR))?;
+        fs::write(&briefing_path, &briefing)
+            .with_context(|| format!("Failed to write briefing to {}", briefing_path.display()))?;
+        transcript.record(TranscriptEntryKind::Action, &format!("Wrote morning briefing to {}", briefing_path.display()));
+
+        fs::write(last_run_path(), started.to_rfc3339())
+            .context("Failed to record maintenance run time")?;
+
+        transcript.record_status(&TaskStatus::Completed("Maintenance finished".to_string()));
+        if let Err(e) = transcript.save() {
+            eprintln!("Warning: Failed to save agent transcript: {}", e);
+        }
+
+        Ok(MaintenanceReport {
+            transcript_id: Some(transcript_id),
+            briefing_path: Some(briefing_path),
+            skipped: None,
+        })
+    }
+
+    async fn summarize_sessions(&self, since: DateTime<Local>, transcript: &mut AgentTranscript) -> Result<Option<String>> {
+        let conversations = self.memory.conversations_since(since)?;
+        if conversations.is_empty() {
+            return Ok(None);
+        }
+        if transcript.usage.llm_calls >= MAX_LLM_CALLS_PER_RUN {
+            return Err(anyhow::anyhow!("LLM call limit for this run reached"));
+        }
+
+        let mut logs = String::new();
+        for (name, content) in conversations {
+            logs.push_str(&format!("--- {} ---\n{}\n", name, content));
+        }
+        if logs.len() > MAX_SUMMARY_INPUT_CHARS {
+            // Keep the most recent part of the logs
+            let mut start = logs.len() - MAX_SUMMARY_INPUT_CHARS;
+            while !logs.is_char_boundary(start) {
+                start += 1;
+            }
+            logs = logs[start..].to_string();
+        }
+
+        let prompt = format!(
+            "Summarize these coding assistant session logs for a morning briefing. \
+            List what was worked on, decisions made and open threads as short bullet points.\n\n{}",
+            logs
+        );
+        let summary = transcript.ask_quick(&prompt, &self.model_config).await?;
+
+        self.memory.store_learning("daily-summaries", &summary)?;
+        transcript.record(TranscriptEntryKind::Action, "Stored session summary under daily-summaries");
+
+        Ok(Some(summary))
+    }
+}
+
+fn last_run_path() -> PathBuf {
+    PathBuf::from(MAINTENANCE_DIR).join("last_run")
+}
+
+fn read_last_run() -> Option<DateTime<Local>> {
+    let content = fs::read_to_string(last_run_path()).ok()?;
+    DateTime::parse_from_rfc3339(content.trim())
+        .ok()
+        .map(|dt| dt.with_timezone(&Local))
+}
+
+/// Record every tracked file with its size and line count in `.kota/index/files.tsv`
+fn reindex_repo() -> Result<usize> {
+    let output = Command::new("git")
+        .args(["ls-files"])
+        .output()
+        .context("Failed to run git ls-files")?;
+    if !output.status.success() {
+        return Err(anyhow::anyhow!("git ls-files failed: {}", String::from_utf8_lossy(&output.stderr).trim()));
+    }
+
+    let mut index = String::from("path\tbytes\tlines\n");
+    let mut count = 0;
+    for file in String::from_utf8_lossy(&output.stdout).lines() {
+        let Ok(metadata) = fs::metadata(file) else { continue };
+        // Binary files get an empty line count
+        let lines = fs::read_to_string(file)
+            .map(|content| content.lines().count().to_string())
+            .unwrap_or_default();
+        index.push_str(&format!("{}\t{}\t{}\n", file, metadata.len(), lines));
+        count += 1;
+    }
+
+    let path = Path::new(INDEX_PATH);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, index).with_context(|| format!("Failed to write {}", INDEX_PATH))?;
+
+    Ok(count)
+}
+
+fn git_activity_since(since: Option<DateTime<Local>>) -> String {
+    let since_arg = match since {
+        Some(since) => format!("--since={}", since.to_rfc3339()),
+        None => "--since=24 hours ago".to_string(),
+    };
+
+    let mut section = String::new();
+    if let Ok(output) = Command::new("git").args(["log", "--oneline", &since_arg]).output() {
+        let commits: Vec<String> = String::from_utf8_lossy(&output.stdout).lines().map(String::from).collect();
+        section.push_str(&format!("- {} commit(s) since the last run\n", commits.len()));
+        for commit in commits.iter().take(10) {
+            section.push_str(&format!("  - {}\n", commit));
+        }
+    }
+    if let Ok(output) = Command::new("git").args(["status", "--short"]).output() {
+        let changed = String::from_utf8_lossy(&output.stdout).lines().count();
+        if changed > 0 {
+            section.push_str(&format!("- {} uncommitted change(s) in the working tree\n", changed));
+        }
+    }
+
+    section
+}
+
+/// Archive finished-task transcripts older than `days`
+fn archive_old_transcripts(days: u64) -> Result<usize> {
+    let cutoff = Local::now() - ChronoDuration::days(days as i64);
+    Database::open()?.archive_transcripts_before(cutoff)
+}
+
+/// Most recent morning briefing, as (file name, content)
+pub fn latest_briefing() -> Result<Option<(String, String)>> {
+    let dir = PathBuf::from(MAINTENANCE_DIR);
+    if !dir.exists() {
+        return Ok(None);
+    }
+
+    // Briefing names embed the date, so the lexically last one is the newest
+    let latest = fs::read_dir(&dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .filter(|name| name.starts_with("briefing-") && name.ends_with(".md"))
+        .max();
+
+    match latest {
+        Some(name) => {
+            let content = fs::read_to_string(dir.join(&name))?;
+            Ok(Some((name, content)))
+        }
+        None => Ok(None),
+    }
+}
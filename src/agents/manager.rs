@@ -0,0 +1,377 @@
+use anyhow::Result;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::Mutex;
+
+use crate::context::{ContextManager, Origin, SharedContext};
+use crate::llm::{self, ModelConfig};
+use crate::memory::MemoryManager;
+use crate::{editor, sr_parser};
+use crate::webhooks::{self, Event};
+
+use super::code_agent::CodeAgent;
+use super::planning_agent::PlanningAgent;
+use super::research_agent::ResearchAgent;
+use super::session::AgentSession;
+use super::traits::{Agent, AgentCapability, AgentTask, TaskStatus};
+
+/// Below this confidence the user is asked to pick an agent instead
+const ROUTING_CONFIDENCE_THRESHOLD: f32 = 0.6;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RoutingDecision {
+    Agent {
+        name: String,
+        confidence: f32,
+        reason: String,
+    },
+    /// Classification was inconclusive; candidates are ordered best match first
+    AskUser {
+        candidates: Vec<String>,
+        reason: String,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub enum DelegationOutcome {
+    Completed {
+        agent: String,
+        task: AgentTask,
+    },
+    NeedsUserChoice {
+        task: AgentTask,
+        candidates: Vec<String>,
+        reason: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StepOutcome {
+    Completed,
+    /// The session was paused before the step because a budget limit was reached
+    BudgetExhausted(String),
+}
+
+/// Owns the registered agents and routes tasks to the one best suited for them
+pub struct AgentManager {
+    agents: Vec<Box<dyn Agent>>,
+    context_manager: SharedContext,
+    model_config: ModelConfig,
+    memory_manager: Arc<Mutex<MemoryManager>>,
+}
+
+impl AgentManager {
+    pub async fn new(context_manager: ContextManager, model_config: ModelConfig) -> Result<Self> {
+        let mut manager = Self {
+            agents: Vec::new(),
+            context_manager: SharedContext::new(context_manager),
+            model_config,
+            memory_manager: Arc::new(Mutex::new(MemoryManager::new()?)),
+        };
+
+        manager.register(Box::new(CodeAgent::new())).await?;
+        manager.register(Box::new(PlanningAgent::new())).await?;
+        manager.register(Box::new(ResearchAgent::new())).await?;
+
+        Ok(manager)
+    }
+
+    pub async fn register(&mut self, mut agent: Box<dyn Agent>) -> Result<()> {
+        agent.initialize(
+            self.context_manager.clone(),
+            self.model_config.clone(),
+            self.memory_manager.clone(),
+        ).await?;
+        self.agents.push(agent);
+        Ok(())
+    }
+
+    pub fn agent_names(&self) -> Vec<String> {
+        self.agents.iter().map(|a| a.name().to_string()).collect()
+    }
+
+    /// Refresh the context and model the agents work with before running a task
+    pub async fn sync_state(&mut self, context_manager: ContextManager, model_config: &ModelConfig) -> Result<()> {
+        *self.context_manager.write().await = context_manager;
+        self.model_config = model_config.clone();
+
+        for agent in self.agents.iter_mut() {
+            agent.initialize(
+                self.context_manager.clone(),
+                self.model_config.clone(),
+                self.memory_manager.clone(),
+            ).await?;
+        }
+        Ok(())
+    }
+
+    /// Route a task to the best agent and run it, or report that the user needs to choose
+    pub async fn delegate_task(&mut self, task: AgentTask) -> Result<DelegationOutcome> {
+        match self.route_task(&task).await {
+            RoutingDecision::Agent { name, .. } => self.delegate_to(&name, task).await,
+            RoutingDecision::AskUser { candidates, reason } => {
+                Ok(DelegationOutcome::NeedsUserChoice { task, candidates, reason })
+            }
+        }
+    }
+
+    /// Run a task on a specific agent, bypassing classification
+    pub async fn delegate_to(&mut self, agent_name: &str, mut task: AgentTask) -> Result<DelegationOutcome> {
+        let agent = self.agents
+            .iter_mut()
+            .find(|a| a.name().eq_ignore_ascii_case(agent_name))
+            .ok_or_else(|| anyhow::anyhow!("Unknown agent: {}", agent_name))?;
+
+        let started = Instant::now();
+        let result = agent.execute_task(&mut task).await;
+        task.usage.wall_time_secs += started.elapsed().as_secs();
+        let (success, status) = match (&result, &task.status) {
+            (Err(e), _) => (false, e.to_string()),
+            (Ok(_), TaskStatus::Failed(err)) => (false, err.clone()),
+            (Ok(_), TaskStatus::Completed(msg)) => (true, msg.clone()),
+            (Ok(_), status) => (true, format!("{:?}", status)),
+        };
+        webhooks::fire(Event::AgentFinished { agent: agent.name().to_string(), task: task.description.clone(), success, status });
+        result?;
+
+        Ok(DelegationOutcome::Completed {
+            agent: agent.name().to_string(),
+            task,
+        })
+    }
+
+    /// Run the next step of a session's plan and checkpoint the result.
+    /// The session pauses instead if its budget is already used up.
+    pub async fn run_session_step(&mut self, session: &mut AgentSession) -> Result<StepOutcome> {
+        let step = session.next_step()
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("Session {} has no remaining steps", session.id()))?;
+
+        if let Some(reason) = session.task.budget.exhausted(&session.task.usage) {
+            session.pause(&format!("Budget exhausted: {}", reason));
+            session.save()?;
+            return Ok(StepOutcome::BudgetExhausted(reason));
+        }
+
+        // Steps run unattended, so take the best candidate instead of asking
+        let agent_name = match self.route_task(&step).await {
+            RoutingDecision::Agent { name, .. } => name,
+            RoutingDecision::AskUser { candidates, .. } => candidates
+                .into_iter()
+                .next()
+                .ok_or_else(|| anyhow::anyhow!("No agents registered"))?,
+        };
+
+        let step_task = match self.delegate_to(&agent_name, step).await? {
+            DelegationOutcome::Completed { task, .. } => task,
+            DelegationOutcome::NeedsUserChoice { task, .. } => task,
+        };
+
+        let result = match &step_task.status {
+            TaskStatus::Completed(msg) => msg.clone(),
+            TaskStatus::Failed(err) => format!("Failed: {}", err),
+            status => format!("{:?}", status),
+        };
+
+        if sr_parser::contains_sr_blocks(&result) {
+            session.pending_diffs.push(result.clone());
+        }
+
+        let note = format!(
+            "Step {} ({}): {}\n{}",
+            session.plan_position + 1,
+            agent_name,
+            step_task.description,
+            result
+        );
+        self.context_manager.write().await.add_snippet_from(note.clone(), Origin::Agent);
+        session.gathered_context.push(note);
+
+        session.task.usage.add(&step_task.usage);
+        session.task.subtasks[session.plan_position] = step_task;
+        session.plan_position += 1;
+        if session.is_finished() {
+            let total = session.total_steps();
+            session.task.update_status(TaskStatus::Completed(format!("Completed {} step(s)", total)));
+        }
+
+        session.save()?;
+        Ok(StepOutcome::Completed)
+    }
+
+    /// Walk the user through S/R blocks produced by earlier steps
+    pub async fn review_pending_diffs(&mut self, session: &mut AgentSession) -> Result<()> {
+        let pending = std::mem::take(&mut session.pending_diffs);
+        let cm = self.context_manager.snapshot().await;
+        for diff in pending {
+            let blocks = sr_parser::parse_sr_blocks(&diff)?;
+            let applied = editor::confirm_and_apply_blocks(blocks, &session.task.description, &cm).await?;
+            self.context_manager.write().await.record_edits(&applied);
+        }
+
+        session.save()?;
+        Ok(())
+    }
+
+    /// Bring back the context a paused session was working with
+    pub async fn restore_session_context(&mut self, session: &AgentSession) -> Result<()> {
+        let mut cm = self.context_manager.write().await;
+        for file in &session.context_files {
+            if !cm.is_file_in_context(file) {
+                if let Err(e) = cm.add_file(file) {
+                    eprintln!("Warning: Could not restore {} to context: {}", file, e);
+                }
+            }
+        }
+        for note in &session.gathered_context {
+            cm.add_snippet_from(note.clone(), Origin::Agent);
+        }
+        Ok(())
+    }
+
+    /// Classify the task against each agent's declared capabilities with a cheap model call
+    pub async fn route_task(&self, task: &AgentTask) -> RoutingDecision {
+        let candidates = rank_agents_by_keywords(&task.description, &self.agent_capabilities());
+        let prompt = self.build_routing_prompt(task);
+
+        match llm::ask_model_quick(&prompt, &self.model_config).await {
+            Ok(response) => match parse_routing_response(&response, &self.agent_names()) {
+                Some(RoutingDecision::Agent { name, confidence, reason }) if confidence >= ROUTING_CONFIDENCE_THRESHOLD => {
+                    RoutingDecision::Agent { name, confidence, reason }
+                }
+                Some(RoutingDecision::Agent { name, confidence, reason }) => {
+                    // Keep the model's pick at the top of the list it offers the user
+                    let mut ordered: Vec<String> = candidates.into_iter().filter(|c| c != &name).collect();
+                    ordered.insert(0, name);
+                    RoutingDecision::AskUser {
+                        candidates: ordered,
+                        reason: format!("Low routing confidence ({:.2}): {}", confidence, reason),
+                    }
+                }
+                _ => RoutingDecision::AskUser {
+                    candidates,
+                    reason: "Could not parse the routing response".to_string(),
+                },
+            },
+            Err(e) => RoutingDecision::AskUser {
+                candidates,
+                reason: format!("Routing call failed: {}", e),
+            },
+        }
+    }
+
+    fn agent_capabilities(&self) -> Vec<(String, Vec<AgentCapability>)> {
+        self.agents
+            .iter()
+            .map(|a| (a.name().to_string(), a.capabilities()))
+            .collect()
+    }
+
+    fn build_routing_prompt(&self, task: &AgentTask) -> String {
+        let mut agents_section = String::new();
+        for (name, capabilities) in self.agent_capabilities() {
+            let caps: Vec<String> = capabilities.iter().map(|c| format!("{:?}", c)).collect();
+            agents_section.push_str(&format!("- {}: {}\n", name, caps.join(", ")));
+        }
+
+        format!(
+            "You route tasks to specialized agents. Available agents and their capabilities:\n\
+            {}\n\
+            Task: {}\n\n\
+            Reply with a single JSON object and nothing else:\n\
+            {{\"agent\": \"<agent name>\", \"confidence\": <0.0-1.0>, \"reason\": \"<one sentence>\"}}",
+            agents_section,
+            task.description
+        )
+    }
+}
+
+/// Extract the routing decision from the model's JSON reply
+fn parse_routing_response(response: &str, agent_names: &[String]) -> Option<RoutingDecision> {
+    let start = response.find('{')?;
+    let end = response.rfind('}')?;
+    if end < start {
+        return None;
+    }
+
+    let value: serde_json::Value = serde_json::from_str(&response[start..=end]).ok()?;
+    let requested = value.get("agent")?.as_str()?;
+    let name = agent_names.iter().find(|n| n.eq_ignore_ascii_case(requested))?.clone();
+    let confidence = value.get("confidence").and_then(|c| c.as_f64()).unwrap_or(0.0) as f32;
+    let reason = value.get("reason").and_then(|r| r.as_str()).unwrap_or("").to_string();
+
+    Some(RoutingDecision::Agent {
+        name,
+        confidence: confidence.clamp(0.0, 1.0),
+        reason,
+    })
+}
+
+/// Order agents by how many of the task's implied capabilities they declare
+fn rank_agents_by_keywords(description: &str, agents: &[(String, Vec<AgentCapability>)]) -> Vec<String> {
+    let description = description.to_lowercase();
+    let keyword_map: &[(&[&str], AgentCapability)] = &[
+        (&["implement", "write", "add", "fix", "create", "function"], AgentCapability::CodeGeneration),
+        (&["refactor", "review", "analyze", "improve"], AgentCapability::CodeAnalysis),
+        (&["edit", "change", "modify", "update"], AgentCapability::FileEditing),
+        (&["test"], AgentCapability::Testing),
+        (&["document", "docs", "readme"], AgentCapability::Documentation),
+        (&["research", "investigate", "compare", "find out", "learn about"], AgentCapability::Research),
+        (&["plan", "design", "break down", "roadmap", "steps"], AgentCapability::Planning),
+    ];
+
+    let implied: Vec<&AgentCapability> = keyword_map
+        .iter()
+        .filter(|(keywords, _)| keywords.iter().any(|k| description.contains(k)))
+        .map(|(_, capability)| capability)
+        .collect();
+
+    let mut scored: Vec<(usize, usize, &String)> = agents
+        .iter()
+        .enumerate()
+        .map(|(i, (name, caps))| (implied.iter().filter(|c| caps.contains(c)).count(), i, name))
+        .collect();
+    // Highest score first; registration order breaks ties
+    scored.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+
+    scored.into_iter().map(|(_, _, name)| name.clone()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names() -> Vec<String> {
+        vec!["CodeAgent".to_string(), "PlanningAgent".to_string(), "ResearchAgent".to_string()]
+    }
+
+    #[test]
+    fn test_parse_routing_response() {
+        let response = "Sure:\n```json\n{\"agent\": \"researchagent\", \"confidence\": 0.85, \"reason\": \"Needs investigation\"}\n```";
+        let decision = parse_routing_response(response, &names()).unwrap();
+        assert_eq!(decision, RoutingDecision::Agent {
+            name: "ResearchAgent".to_string(),
+            confidence: 0.85,
+            reason: "Needs investigation".to_string(),
+        });
+
+        assert!(parse_routing_response("{\"agent\": \"Unknown\", \"confidence\": 1.0}", &names()).is_none());
+        assert!(parse_routing_response("no json here", &names()).is_none());
+    }
+
+    #[test]
+    fn test_rank_agents_by_keywords() {
+        let agents = vec![
+            ("CodeAgent".to_string(), vec![AgentCapability::CodeGeneration, AgentCapability::Testing]),
+            ("PlanningAgent".to_string(), vec![AgentCapability::Planning]),
+            ("ResearchAgent".to_string(), vec![AgentCapability::Research]),
+        ];
+
+        let ranked = rank_agents_by_keywords("Research and compare HTTP client crates", &agents);
+        assert_eq!(ranked[0], "ResearchAgent");
+
+        let ranked = rank_agents_by_keywords("Write unit tests for the parser", &agents);
+        assert_eq!(ranked[0], "CodeAgent");
+        assert_eq!(ranked.len(), 3);
+    }
+}
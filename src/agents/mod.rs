@@ -3,4 +3,8 @@ pub mod traits;
 pub mod code_agent;
 pub mod planning_agent;
 pub mod research_agent;
+pub mod maintenance_agent;
+pub mod transcript;
+pub mod manager;
+pub mod session;
 
@@ -1,17 +1,44 @@
 use anyhow::Result;
 use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
-use crate::context::ContextManager;
+use crate::context::SharedContext;
 use crate::llm::{self, ModelConfig};
 use crate::memory::MemoryManager;
 
 use super::traits::{Agent, AgentCapability, AgentMessage, AgentTask, TaskStatus, TaskPriority};
+use super::transcript::{AgentTranscript, TranscriptEntryKind};
+
+/// The plan as the model returns it
+#[derive(Debug, Serialize, Deserialize)]
+struct PlanResponse {
+    subtasks: Vec<PlannedStep>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PlannedStep {
+    description: String,
+    #[serde(default)]
+    priority: String,
+    /// 1-based numbers of earlier steps
+    #[serde(default)]
+    depends_on: Vec<usize>,
+}
+
+fn parse_priority(priority: &str) -> TaskPriority {
+    match priority.trim().to_lowercase().as_str() {
+        "critical" => TaskPriority::Critical,
+        "high" => TaskPriority::High,
+        "low" => TaskPriority::Low,
+        _ => TaskPriority::Normal,
+    }
+}
 
 pub struct PlanningAgent {
     name: String,
-    context_manager: Option<Arc<Mutex<ContextManager>>>,
+    context_manager: Option<SharedContext>,
     model_config: Option<ModelConfig>,
     memory_manager: Option<Arc<Mutex<MemoryManager>>>,
     active_plans: Vec<AgentTask>,
@@ -45,7 +72,7 @@ impl Agent for PlanningAgent {
     
     async fn initialize(
         &mut self,
-        context_manager: Arc<Mutex<ContextManager>>,
+        context_manager: SharedContext,
         model_config: ModelConfig,
         memory_manager: Arc<Mutex<MemoryManager>>,
     ) -> Result<()> {
@@ -78,12 +105,25 @@ impl Agent for PlanningAgent {
     
     async fn execute_task(&mut self, task: &mut AgentTask) -> Result<()> {
         task.update_status(TaskStatus::InProgress);
+        let mut transcript = AgentTranscript::new(&task.id, &self.name, &task.description);
         
         // Create a comprehensive plan for the task
-        let subtasks = self.plan_task(task).await?;
+        let subtasks = match self.build_plan(task, &mut transcript).await {
+            Ok(subtasks) => subtasks,
+            Err(e) => {
+                task.update_status(TaskStatus::Failed(e.to_string()));
+                task.usage.add(&transcript.usage);
+                transcript.record_status(&task.status);
+                if let Err(save_err) = transcript.save() {
+                    eprintln!("Warning: Failed to save agent transcript: {}", save_err);
+                }
+                return Err(e);
+            }
+        };
         
         // Add subtasks to the main task
         for subtask in subtasks {
+            transcript.record(TranscriptEntryKind::Action, &format!("Added subtask: {}", subtask.description));
             task.add_subtask(subtask);
         }
         
@@ -91,38 +131,18 @@ impl Agent for PlanningAgent {
             format!("Created comprehensive plan with {} subtasks", task.subtasks.len())
         ));
         
+        task.usage.add(&transcript.usage);
+        transcript.record_status(&task.status);
+        if let Err(e) = transcript.save() {
+            eprintln!("Warning: Failed to save agent transcript: {}", e);
+        }
+        
         Ok(())
     }
     
     async fn plan_task(&mut self, task: &AgentTask) -> Result<Vec<AgentTask>> {
-        let context = if let Some(cm) = &self.context_manager {
-            let cm = cm.lock().await;
-            cm.get_formatted_context()
-        } else {
-            String::new()
-        };
-        
-        let model_config = self.model_config.as_ref()
-            .ok_or_else(|| anyhow::anyhow!("Model config not initialized"))?;
-        
-        let prompt = format!(
-            r#"Create a detailed execution plan for this task: {}
-
-            Break it down into specific, actionable subtasks. For each subtask, provide:
-            1. A clear description
-            2. Priority level (Critical/High/Normal/Low)
-            3. Any dependencies on other subtasks
-            
-            Format your response as a numbered list."#,
-            task.description
-        );
-        
-        let response = llm::ask_model_with_config(&prompt, &context, model_config).await?;
-        
-        // Parse the response into subtasks
-        let subtasks = self.parse_plan_response(&response)?;
-        
-        Ok(subtasks)
+        let mut transcript = AgentTranscript::new(&task.id, &self.name, &task.description);
+        self.build_plan(task, &mut transcript).await
     }
     
     fn get_status(&self) -> String {
@@ -160,6 +180,29 @@ impl Agent for PlanningAgent {
 }
 
 impl PlanningAgent {
+    async fn build_plan(&self, task: &AgentTask, transcript: &mut AgentTranscript) -> Result<Vec<AgentTask>> {
+        let context = if let Some(cm) = &self.context_manager {
+            cm.formatted().await.to_string()
+        } else {
+            String::new()
+        };
+        
+        let model_config = self.model_config.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Model config not initialized"))?;
+        
+        let prompt = format!(
+            r#"Create a detailed execution plan for this task: {}
+
+            Break it down into specific, actionable subtasks, in the order they should be done.
+            Reply with JSON in this form:
+            {{"subtasks": [{{"description": "<clear, actionable step>", "priority": "critical|high|normal|low", "depends_on": [<numbers of earlier steps, starting at 1>]}}]}}"#,
+            task.description
+        );
+        
+        let plan: PlanResponse = transcript.ask_json(&prompt, &context, model_config).await?;
+        Ok(Self::plan_to_subtasks(plan))
+    }
+    
     async fn create_comprehensive_plan(&mut self, task: &AgentTask) -> Result<Vec<AgentTask>> {
         // Use the plan_task method to create a plan
         let subtasks = self.plan_task(task).await?;
@@ -176,8 +219,7 @@ impl PlanningAgent {
     
     async fn analyze_planning_request(&self, query: &str) -> Result<String> {
         let context = if let Some(cm) = &self.context_manager {
-            let cm = cm.lock().await;
-            cm.get_formatted_context()
+            cm.formatted().await.to_string()
         } else {
             String::new()
         };
@@ -193,46 +235,27 @@ impl PlanningAgent {
         llm::ask_model_with_config(&prompt, &context, model_config).await
     }
     
-    fn parse_plan_response(&self, response: &str) -> Result<Vec<AgentTask>> {
-        let mut subtasks = Vec::new();
-        let mut current_priority = TaskPriority::Normal;
-        
-        for line in response.lines() {
-            let line = line.trim();
-            
-            // Skip empty lines
-            if line.is_empty() {
+    /// Turn the model's plan into subtasks, with step-number dependencies resolved to task ids
+    fn plan_to_subtasks(plan: PlanResponse) -> Vec<AgentTask> {
+        let mut subtasks: Vec<AgentTask> = Vec::new();
+        for step in plan.subtasks {
+            let description = step.description.trim();
+            if description.is_empty() {
                 continue;
             }
-            
-            // Check for priority indicators
-            if line.to_lowercase().contains("critical") {
-                current_priority = TaskPriority::Critical;
-            } else if line.to_lowercase().contains("high priority") {
-                current_priority = TaskPriority::High;
-            } else if line.to_lowercase().contains("low priority") {
-                current_priority = TaskPriority::Low;
-            }
-            
-            // Look for numbered items
-            if line.starts_with(char::is_numeric) || line.starts_with("- ") || line.starts_with("* ") {
-                let description = line
-                    .trim_start_matches(char::is_numeric)
-                    .trim_start_matches('.')
-                    .trim_start_matches('-')
-                    .trim_start_matches('*')
-                    .trim();
-                
-                if !description.is_empty() {
-                    subtasks.push(AgentTask::new(
-                        description.to_string(),
-                        current_priority.clone(),
-                    ));
-                }
-            }
+            // Only earlier steps count, so a plan can't depend on itself in a loop
+            let dependencies = step.depends_on
+                .iter()
+                .filter_map(|number| number.checked_sub(1).and_then(|index| subtasks.get(index)))
+                .map(|dependency| dependency.id.clone())
+                .collect();
+            subtasks.push(
+                AgentTask::new(description.to_string(), parse_priority(&step.priority))
+                    .with_dependencies(dependencies)
+            );
         }
         
-        // If no subtasks were parsed, create some default ones
+        // If the plan came back empty, fall back to a generic one
         if subtasks.is_empty() {
             subtasks.push(AgentTask::new(
                 "Analyze requirements and constraints".to_string(),
@@ -252,6 +275,30 @@ impl PlanningAgent {
             ));
         }
         
-        Ok(subtasks)
+        subtasks
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plan_to_subtasks() {
+        let plan: PlanResponse = serde_json::from_str(r#"{"subtasks": [
+            {"description": "Write the parser", "priority": "High"},
+            {"description": "Test the parser", "depends_on": [1, 2, 7]},
+            {"description": "  "}
+        ]}"#).unwrap();
+        let subtasks = PlanningAgent::plan_to_subtasks(plan);
+
+        assert_eq!(subtasks.len(), 2);
+        assert_eq!(subtasks[0].priority, TaskPriority::High);
+        assert_eq!(subtasks[1].priority, TaskPriority::Normal);
+        // Only the earlier step resolves; itself and unknown steps are dropped
+        assert_eq!(subtasks[1].dependencies, vec![subtasks[0].id.clone()]);
+
+        let empty: PlanResponse = serde_json::from_str(r#"{"subtasks": []}"#).unwrap();
+        assert_eq!(PlanningAgent::plan_to_subtasks(empty).len(), 4);
+    }
+}
@@ -3,15 +3,18 @@ use async_trait::async_trait;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
-use crate::context::ContextManager;
-use crate::llm::{self, ModelConfig};
-use crate::memory::MemoryManager;
+use crate::context::SharedContext;
+use crate::llm::ModelConfig;
+use crate::memory::{Citation, MemoryManager};
+use crate::prompts::PromptsConfig;
+use crate::web_search::{self, SearchResult};
 
 use super::traits::{Agent, AgentCapability, AgentMessage, AgentTask, TaskStatus};
+use super::transcript::{AgentTranscript, TranscriptEntryKind};
 
 pub struct ResearchAgent {
     name: String,
-    context_manager: Option<Arc<Mutex<ContextManager>>>,
+    context_manager: Option<SharedContext>,
     model_config: Option<ModelConfig>,
     memory_manager: Option<Arc<Mutex<MemoryManager>>>,
 }
@@ -43,7 +46,7 @@ impl Agent for ResearchAgent {
     
     async fn initialize(
         &mut self,
-        context_manager: Arc<Mutex<ContextManager>>,
+        context_manager: SharedContext,
         model_config: ModelConfig,
         memory_manager: Arc<Mutex<MemoryManager>>,
     ) -> Result<()> {
@@ -56,7 +59,9 @@ impl Agent for ResearchAgent {
     async fn process_message(&mut self, message: AgentMessage) -> Result<Option<AgentMessage>> {
         match message {
             AgentMessage::QueryRequest(query) => {
-                let response = self.research_topic(&query).await?;
+                // Ad-hoc queries aren't tasks, so their transcript is not persisted
+                let mut transcript = AgentTranscript::new("query", &self.name, &query);
+                let response = self.research_topic(&query, &mut transcript).await?;
                 Ok(Some(AgentMessage::QueryResponse(query, response)))
             }
             AgentMessage::TaskRequest(task) => {
@@ -79,19 +84,38 @@ impl Agent for ResearchAgent {
     
     async fn execute_task(&mut self, task: &mut AgentTask) -> Result<()> {
         task.update_status(TaskStatus::InProgress);
+        let mut transcript = AgentTranscript::new(&task.id, &self.name, &task.description);
         
         // Extract the research topic from the task description
-        let findings = self.research_topic(&task.description).await?;
+        let findings = match self.research_topic(&task.description, &mut transcript).await {
+            Ok(findings) => findings,
+            Err(e) => {
+                task.update_status(TaskStatus::Failed(e.to_string()));
+                task.usage.add(&transcript.usage);
+                transcript.record_status(&task.status);
+                if let Err(save_err) = transcript.save() {
+                    eprintln!("Warning: Failed to save agent transcript: {}", save_err);
+                }
+                return Err(e);
+            }
+        };
         
-        // Store findings in memory
+        // Store the cited brief so it can be added to later coding prompts
+        let mut completion = "Research completed.".to_string();
         if let Some(memory) = &self.memory_manager {
             let mm = memory.lock().await;
-            mm.store_learning("research_findings", &findings)?;
+            let path = mm.store_research_brief(&task.description, &findings)?;
+            transcript.record(TranscriptEntryKind::Action, &format!("Saved research brief to {}", path.display()));
+            completion = format!("Research brief saved to {}. Add it to context with /brief {}", path.display(), task.description);
         }
         
-        task.update_status(TaskStatus::Completed(
-            "Research completed. Key findings stored in knowledge base.".to_string()
-        ));
+        task.update_status(TaskStatus::Completed(completion));
+        
+        task.usage.add(&transcript.usage);
+        transcript.record_status(&task.status);
+        if let Err(e) = transcript.save() {
+            eprintln!("Warning: Failed to save agent transcript: {}", e);
+        }
         
         Ok(())
     }
@@ -153,18 +177,41 @@ impl Agent for ResearchAgent {
 }
 
 impl ResearchAgent {
-    async fn research_topic(&self, topic: &str) -> Result<String> {
+    async fn research_topic(&self, topic: &str, transcript: &mut AgentTranscript) -> Result<String> {
         // First, check memory for existing knowledge
         let existing_knowledge = if let Some(memory) = &self.memory_manager {
-            let mm = memory.lock().await;
-            mm.search_knowledge(topic).unwrap_or_default()
+            let mm = memory.lock().await.clone();
+            let embeddings = PromptsConfig::load().unwrap_or_default().embeddings;
+            match mm.semantic_search(&embeddings, topic, 10).await {
+                Ok(results) => results,
+                Err(_) => mm.search_knowledge(topic).unwrap_or_default(),
+            }
         } else {
             Vec::new()
         };
         
+        let model_config = self.model_config.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Model config not initialized"))?
+            .clone();
+        
+        // Gather sources from several angles, then extract notes from each one
+        let queries = self.generate_queries(topic, transcript, &model_config).await;
+        let sources = self.search_sources(&queries, transcript).await;
+        let citations = self.collect_citations(topic, sources, transcript, &model_config).await;
+        
+        if let Some(memory) = &self.memory_manager {
+            let mm = memory.lock().await;
+            match mm.store_citations(&citations) {
+                Ok(added) => transcript.record(
+                    TranscriptEntryKind::Action,
+                    &format!("Stored {} new citation(s) in the citation store", added),
+                ),
+                Err(e) => transcript.record(TranscriptEntryKind::Error, &format!("Failed to store citations: {}", e)),
+            }
+        }
+        
         let context = if let Some(cm) = &self.context_manager {
-            let cm = cm.lock().await;
-            let mut full_context = cm.get_formatted_context();
+            let mut full_context = cm.formatted().await.to_string();
             
             // Add existing knowledge to context
             if !existing_knowledge.is_empty() {
@@ -179,23 +226,148 @@ impl ResearchAgent {
             String::new()
         };
         
-        let model_config = self.model_config.as_ref()
-            .ok_or_else(|| anyhow::anyhow!("Model config not initialized"))?;
+        let mut sources_section = String::new();
+        for (i, citation) in citations.iter().enumerate() {
+            sources_section.push_str(&format!(
+                "[{}] {} ({})\n{}\n\n",
+                i + 1, citation.title, citation.url, citation.notes
+            ));
+        }
+        if sources_section.is_empty() {
+            sources_section.push_str("No web sources were found; rely on the provided context and your own knowledge, and say so.\n");
+        }
         
         let prompt = format!(
-            r#"Research the following topic: {}
+            r#"Write a research brief on: {}
 
-            Provide a comprehensive analysis including:
-            1. Key concepts and definitions
-            2. Current best practices
-            3. Common patterns and approaches
-            4. Potential pitfalls and considerations
-            5. Relevant examples from the codebase (if any)
-            
-            Base your research on the provided context and your knowledge."#,
-            topic
+            Use the numbered sources below. Cite them inline as [n] after every claim
+            that comes from a source. Cover key concepts, current best practices,
+            pitfalls, and anything directly relevant to the codebase in context.
+            Keep it concise enough to paste into a coding prompt. Do not emit S/R blocks or commands.
+
+            Sources:
+            {}"#,
+            topic,
+            sources_section
         );
         
-        llm::ask_model_with_config(&prompt, &context, model_config).await
+        let brief = transcript.ask(&prompt, &context, &model_config).await?;
+        
+        Ok(format!("{}\n\n{}", brief.trim_end(), format_sources(&citations)))
     }
-}
\ No newline at end of file
+    
+    /// Ask the fast model for a few distinct search queries, falling back to the topic itself
+    async fn generate_queries(&self, topic: &str, transcript: &mut AgentTranscript, model_config: &ModelConfig) -> Vec<String> {
+        let prompt = format!(
+            "Write {} short web search queries that together cover this research topic from different angles. \
+            Reply with one query per line and nothing else.\n\nTopic: {}",
+            MAX_QUERIES, topic
+        );
+        
+        let mut queries: Vec<String> = match transcript.ask_quick(&prompt, model_config).await {
+            Ok(response) => response
+                .lines()
+                .map(|line| line.trim().trim_start_matches(|c: char| c.is_ascii_digit() || c == '.' || c == '-' || c == '*').trim().trim_matches('"').to_string())
+                .filter(|line| !line.is_empty())
+                .take(MAX_QUERIES)
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        
+        if !queries.iter().any(|q| q.eq_ignore_ascii_case(topic)) {
+            queries.insert(0, topic.to_string());
+        }
+        queries
+    }
+    
+    async fn search_sources(&self, queries: &[String], transcript: &mut AgentTranscript) -> Vec<SearchResult> {
+        let mut seen = Vec::new();
+        let mut sources = Vec::new();
+        
+        for query in queries {
+            match web_search::search(query).await {
+                Ok(results) => {
+                    transcript.record(TranscriptEntryKind::Action, &format!("Searched '{}': {} result(s)", query, results.len()));
+                    for result in results {
+                        let key = web_search::normalize_url(&result.url);
+                        if !seen.contains(&key) {
+                            seen.push(key);
+                            sources.push(result);
+                        }
+                    }
+                }
+                Err(e) => transcript.record(TranscriptEntryKind::Error, &format!("Search '{}' failed: {}", query, e)),
+            }
+        }
+        
+        sources.truncate(MAX_SOURCES);
+        sources
+    }
+    
+    /// Extract notes from each source, reusing notes already in the citation store
+    async fn collect_citations(
+        &self,
+        topic: &str,
+        sources: Vec<SearchResult>,
+        transcript: &mut AgentTranscript,
+        model_config: &ModelConfig,
+    ) -> Vec<Citation> {
+        let mut citations = Vec::new();
+        
+        for source in sources {
+            let known = match &self.memory_manager {
+                Some(memory) => memory.lock().await.find_citation(&source.url).unwrap_or(None),
+                None => None,
+            };
+            if let Some(citation) = known {
+                transcript.record(TranscriptEntryKind::Action, &format!("Reused stored notes for {}", source.url));
+                citations.push(citation);
+                continue;
+            }
+            
+            let page_text = match web_search::fetch_page_text(&source.url).await {
+                Ok(text) if !text.is_empty() => text,
+                Ok(_) => source.snippet.clone(),
+                Err(e) => {
+                    transcript.record(TranscriptEntryKind::Error, &format!("Could not fetch {}: {}", source.url, e));
+                    source.snippet.clone()
+                }
+            };
+            
+            let prompt = format!(
+                "Extract the facts relevant to \"{}\" from this page as 3-6 short bullet points. \
+                Reply with only the bullets.\n\nPage: {}\n\n{}",
+                topic, source.url, page_text
+            );
+            let notes = match transcript.ask_quick(&prompt, model_config).await {
+                Ok(notes) => notes.trim().to_string(),
+                Err(_) => source.snippet.clone(),
+            };
+            
+            citations.push(Citation {
+                url: source.url,
+                title: source.title,
+                notes,
+                topic: topic.to_string(),
+                retrieved_at: chrono::Local::now().format("%Y-%m-%d").to_string(),
+            });
+        }
+        
+        citations
+    }
+}
+
+const MAX_QUERIES: usize = 3;
+const MAX_SOURCES: usize = 6;
+
+fn format_sources(citations: &[Citation]) -> String {
+    if citations.is_empty() {
+        return "## Sources\n\nNo web sources found.".to_string();
+    }
+    
+    let mut sources = String::from("## Sources\n\n");
+    for (i, citation) in citations.iter().enumerate() {
+        sources.push_str(&format!("[{}] {} - {}\n", i + 1, citation.title, citation.url));
+    }
+    sources
+}
@@ -0,0 +1,125 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::db::Database;
+use crate::issue::IssueLink;
+
+use super::traits::{AgentTask, TaskStatus};
+
+/// Checkpoint of a multi-step agent task, saved after every step so it can be
+/// paused and resumed later, including after a restart
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentSession {
+    /// Root task; its subtasks are the plan being worked through
+    pub task: AgentTask,
+    /// Index of the next subtask to run
+    pub plan_position: usize,
+    /// Files that were in context, restored on resume
+    pub context_files: Vec<String>,
+    /// Results of completed steps, fed to the agents running later steps
+    pub gathered_context: Vec<String>,
+    /// Step output containing S/R blocks that have not been reviewed yet
+    pub pending_diffs: Vec<String>,
+    pub paused_at: Option<DateTime<Utc>>,
+    /// The GitHub issue the session was started from with `/issue`
+    #[serde(default)]
+    pub issue: Option<IssueLink>,
+}
+
+impl AgentSession {
+    pub fn new(task: AgentTask, context_files: Vec<String>) -> Self {
+        Self {
+            task,
+            plan_position: 0,
+            context_files,
+            gathered_context: Vec::new(),
+            pending_diffs: Vec::new(),
+            paused_at: None,
+            issue: None,
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.task.id
+    }
+
+    pub fn total_steps(&self) -> usize {
+        self.task.subtasks.len()
+    }
+
+    pub fn next_step(&self) -> Option<&AgentTask> {
+        self.task.subtasks.get(self.plan_position)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.plan_position >= self.task.subtasks.len()
+    }
+
+    pub fn pause(&mut self, reason: &str) {
+        self.task.update_status(TaskStatus::Paused(reason.to_string()));
+        self.paused_at = Some(Utc::now());
+    }
+
+    pub fn resume(&mut self) {
+        self.task.update_status(TaskStatus::InProgress);
+        self.paused_at = None;
+    }
+
+    /// Write the checkpoint to the database
+    pub fn save(&self) -> Result<()> {
+        let json = serde_json::to_string(self)?;
+        Database::open()?
+            .save_session(self.id(), &self.task.description, self.is_finished(), &json)
+            .with_context(|| format!("Failed to save agent session {}", self.id()))
+    }
+
+    pub fn load(task_id: &str) -> Result<Self> {
+        let json = Database::open()?
+            .load_session(task_id)?
+            .with_context(|| format!("No saved session for task {}", task_id))?;
+        serde_json::from_str(&json)
+            .with_context(|| format!("Failed to parse agent session {}", task_id))
+    }
+}
+
+/// Saved sessions that have not finished, as (task id, description, status)
+pub fn list_resumable_sessions() -> Result<Vec<(String, String, TaskStatus)>> {
+    let mut sessions = Vec::new();
+    for (task_id, json) in Database::open()?.unfinished_sessions()? {
+        match serde_json::from_str::<AgentSession>(&json) {
+            Ok(session) => sessions.push((task_id, session.task.description.clone(), session.task.status.clone())),
+            Err(e) => eprintln!("Warning: Skipping unreadable agent session {}: {}", task_id, e),
+        }
+    }
+
+    Ok(sessions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::traits::TaskPriority;
+
+    #[test]
+    fn test_session_roundtrip() {
+        let mut task = AgentTask::new("Add config loading".to_string(), TaskPriority::Normal);
+        task.add_subtask(AgentTask::new("Write loader".to_string(), TaskPriority::Normal));
+        task.add_subtask(AgentTask::new("Add tests".to_string(), TaskPriority::Normal));
+
+        let mut session = AgentSession::new(task, vec!["src/main.rs".to_string()]);
+        session.plan_position = 1;
+        session.gathered_context.push("Step 1: loader written".to_string());
+        session.pause("user requested");
+
+        let json = serde_json::to_string(&session).unwrap();
+        let restored: AgentSession = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(restored.plan_position, 1);
+        assert_eq!(restored.next_step().unwrap().description, "Add tests");
+        assert!(restored.task.is_paused());
+        assert!(restored.paused_at.is_some());
+        assert_eq!(restored.context_files, vec!["src/main.rs".to_string()]);
+        assert!(!restored.is_finished());
+    }
+}
@@ -2,12 +2,13 @@ use anyhow::Result;
 use async_trait::async_trait;
 use std::sync::Arc;
 use tokio::sync::Mutex;
+use serde::{Deserialize, Serialize};
 
-use crate::context::ContextManager;
+use crate::context::SharedContext;
 use crate::llm::ModelConfig;
 use crate::memory::MemoryManager;
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum TaskPriority {
     Critical,
     High,
@@ -15,16 +16,17 @@ pub enum TaskPriority {
     Low,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum TaskStatus {
     Pending,
     InProgress,
     Completed(String),  // Success message
     Failed(String),     // Error message
     Blocked(String),    // Reason for block
+    Paused(String),     // Reason for pause; resumable from its session checkpoint
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AgentTask {
     pub id: String,
     pub description: String,
@@ -34,6 +36,78 @@ pub struct AgentTask {
     pub subtasks: Vec<AgentTask>,
     pub created_at: chrono::DateTime<chrono::Utc>,
     pub updated_at: chrono::DateTime<chrono::Utc>,
+    #[serde(default)]
+    pub budget: TaskBudget,
+    #[serde(default)]
+    pub usage: TaskUsage,
+}
+
+/// Limits an autonomous task may consume before it is paused for the user
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TaskBudget {
+    pub max_llm_calls: u32,
+    pub max_tokens: u64,
+    pub max_wall_time_secs: u64,
+}
+
+impl Default for TaskBudget {
+    fn default() -> Self {
+        Self {
+            max_llm_calls: 25,
+            max_tokens: 200_000,
+            max_wall_time_secs: 30 * 60,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TaskUsage {
+    pub llm_calls: u32,
+    pub tokens: u64,          // Estimated from prompt and response size
+    pub wall_time_secs: u64,  // Time spent executing, excluding time paused
+}
+
+impl TaskUsage {
+    pub fn add(&mut self, other: &TaskUsage) {
+        self.llm_calls += other.llm_calls;
+        self.tokens += other.tokens;
+        self.wall_time_secs += other.wall_time_secs;
+    }
+}
+
+impl TaskBudget {
+    /// Describe every limit the usage has reached, or None if there is budget left
+    pub fn exhausted(&self, usage: &TaskUsage) -> Option<String> {
+        let mut reasons = Vec::new();
+        if usage.llm_calls >= self.max_llm_calls {
+            reasons.push(format!("LLM calls {}/{}", usage.llm_calls, self.max_llm_calls));
+        }
+        if usage.tokens >= self.max_tokens {
+            reasons.push(format!("tokens {}/{}", usage.tokens, self.max_tokens));
+        }
+        if usage.wall_time_secs >= self.max_wall_time_secs {
+            reasons.push(format!("wall time {}s/{}s", usage.wall_time_secs, self.max_wall_time_secs));
+        }
+        
+        if reasons.is_empty() {
+            None
+        } else {
+            Some(reasons.join(", "))
+        }
+    }
+    
+    /// Double every limit that has been reached so the task can continue
+    pub fn extend_exhausted(&mut self, usage: &TaskUsage) {
+        if usage.llm_calls >= self.max_llm_calls {
+            self.max_llm_calls = self.max_llm_calls.max(1) * 2;
+        }
+        if usage.tokens >= self.max_tokens {
+            self.max_tokens = self.max_tokens.max(1) * 2;
+        }
+        if usage.wall_time_secs >= self.max_wall_time_secs {
+            self.max_wall_time_secs = self.max_wall_time_secs.max(1) * 2;
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -76,7 +150,7 @@ pub trait Agent: Send + Sync {
     /// Initialize the agent with necessary resources
     async fn initialize(
         &mut self,
-        context_manager: Arc<Mutex<ContextManager>>,
+        context_manager: SharedContext,
         model_config: ModelConfig,
         memory_manager: Arc<Mutex<MemoryManager>>,
     ) -> Result<()>;
@@ -112,6 +186,8 @@ impl AgentTask {
             subtasks: Vec::new(),
             created_at: now,
             updated_at: now,
+            budget: TaskBudget::default(),
+            usage: TaskUsage::default(),
         }
     }
     
@@ -141,4 +217,31 @@ impl AgentTask {
     pub fn is_failed(&self) -> bool {
         matches!(self.status, TaskStatus::Failed(_))
     }
-}
\ No newline at end of file
+    
+    pub fn is_paused(&self) -> bool {
+        matches!(self.status, TaskStatus::Paused(_))
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_budget_exhaustion_and_extension() {
+        let mut budget = TaskBudget {
+            max_llm_calls: 2,
+            max_tokens: 1000,
+            max_wall_time_secs: 60,
+        };
+        let mut usage = TaskUsage { llm_calls: 1, tokens: 400, ..Default::default() };
+        assert!(budget.exhausted(&usage).is_none());
+
+        usage.add(&TaskUsage { llm_calls: 1, tokens: 100, ..Default::default() });
+        assert_eq!(budget.exhausted(&usage), Some("LLM calls 2/2".to_string()));
+
+        budget.extend_exhausted(&usage);
+        assert_eq!(budget.max_llm_calls, 4);
+        assert_eq!(budget.max_tokens, 1000);
+        assert!(budget.exhausted(&usage).is_none());
+    }
+}
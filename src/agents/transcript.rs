@@ -0,0 +1,201 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::clock::clock;
+use crate::db::{Database, StoredTranscript};
+use crate::llm::{self, ModelConfig};
+
+use super::traits::{TaskStatus, TaskUsage};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TranscriptEntryKind {
+    Prompt,
+    Response,
+    Action,
+    Status,
+    Error,
+}
+
+impl TranscriptEntryKind {
+    fn label(&self) -> &str {
+        match self {
+            TranscriptEntryKind::Prompt => "Prompt",
+            TranscriptEntryKind::Response => "Response",
+            TranscriptEntryKind::Action => "Action",
+            TranscriptEntryKind::Status => "Status",
+            TranscriptEntryKind::Error => "Error",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TranscriptEntry {
+    pub timestamp: DateTime<Local>,
+    pub kind: TranscriptEntryKind,
+    pub content: String,
+}
+
+/// Full record of the prompts, responses and actions an agent took for one task
+#[derive(Debug, Clone)]
+pub struct AgentTranscript {
+    pub task_id: String,
+    pub agent_name: String,
+    pub task_description: String,
+    pub started_at: DateTime<Local>,
+    pub entries: Vec<TranscriptEntry>,
+    pub usage: TaskUsage,
+}
+
+impl AgentTranscript {
+    pub fn new(task_id: &str, agent_name: &str, task_description: &str) -> Self {
+        Self {
+            task_id: task_id.to_string(),
+            agent_name: agent_name.to_string(),
+            task_description: task_description.to_string(),
+            started_at: Local::now(),
+            entries: Vec::new(),
+            usage: TaskUsage::default(),
+        }
+    }
+
+    pub fn record(&mut self, kind: TranscriptEntryKind, content: &str) {
+        self.entries.push(TranscriptEntry {
+            timestamp: Local::now(),
+            kind,
+            content: content.to_string(),
+        });
+    }
+
+    pub fn record_status(&mut self, status: &TaskStatus) {
+        let content = match status {
+            TaskStatus::Pending => "Pending".to_string(),
+            TaskStatus::InProgress => "In progress".to_string(),
+            TaskStatus::Completed(msg) => format!("Completed: {}", msg),
+            TaskStatus::Failed(err) => format!("Failed: {}", err),
+            TaskStatus::Blocked(reason) => format!("Blocked: {}", reason),
+            TaskStatus::Paused(reason) => format!("Paused: {}", reason),
+        };
+        self.record(TranscriptEntryKind::Status, &content);
+    }
+
+    /// Send a prompt to the model and record both sides of the exchange
+    pub async fn ask(&mut self, prompt: &str, context: &str, model_config: &ModelConfig) -> Result<String> {
+        self.record(TranscriptEntryKind::Prompt, prompt);
+
+        let result = llm::ask_model_with_config(prompt, context, model_config).await;
+        self.usage.llm_calls += 1;
+        self.usage.tokens += llm::estimate_tokens(prompt) + llm::estimate_tokens(context);
+        match &result {
+            Ok(response) => {
+                self.usage.tokens += llm::estimate_tokens(response);
+                self.record(TranscriptEntryKind::Response, response);
+            }
+            Err(e) => self.record(TranscriptEntryKind::Error, &e.to_string()),
+        }
+
+        result
+    }
+
+    /// Like `ask`, but a bare prompt to the provider's fast model
+    pub async fn ask_quick(&mut self, prompt: &str, model_config: &ModelConfig) -> Result<String> {
+        self.record(TranscriptEntryKind::Prompt, prompt);
+
+        let result = llm::ask_model_quick(prompt, model_config).await;
+        self.usage.llm_calls += 1;
+        self.usage.tokens += llm::estimate_tokens(prompt);
+        match &result {
+            Ok(response) => {
+                self.usage.tokens += llm::estimate_tokens(response);
+                self.record(TranscriptEntryKind::Response, response);
+            }
+            Err(e) => self.record(TranscriptEntryKind::Error, &e.to_string()),
+        }
+
+        result
+    }
+
+    /// Like `ask`, but for a JSON reply parsed as `T`
+    pub async fn ask_json<T: DeserializeOwned + Serialize>(&mut self, prompt: &str, context: &str, model_config: &ModelConfig) -> Result<T> {
+        self.record(TranscriptEntryKind::Prompt, prompt);
+
+        let result = llm::ask_model_json::<T>(prompt, context, model_config).await;
+        self.usage.llm_calls += 1;
+        self.usage.tokens += llm::estimate_tokens(prompt) + llm::estimate_tokens(context);
+        match &result {
+            Ok(value) => {
+                let response = serde_json::to_string_pretty(value).unwrap_or_default();
+                self.usage.tokens += llm::estimate_tokens(&response);
+                self.record(TranscriptEntryKind::Response, &response);
+            }
+            Err(e) => self.record(TranscriptEntryKind::Error, &e.to_string()),
+        }
+
+        result
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let mut md = String::new();
+        md.push_str(&format!("# Agent Transcript: {}\n\n", self.task_id));
+        md.push_str(&format!("- Agent: {}\n", self.agent_name));
+        md.push_str(&format!("- Task: {}\n", self.task_description));
+        md.push_str(&format!("- Started: {}\n", clock().date_time(&self.started_at)));
+
+        for entry in &self.entries {
+            md.push_str(&format!(
+                "\n## [{}] {}\n\n{}\n",
+                clock().time(&entry.timestamp),
+                entry.kind.label(),
+                entry.content.trim_end()
+            ));
+        }
+
+        md
+    }
+
+    /// Store the transcript and its entries in the database
+    pub fn save(&self) -> Result<()> {
+        let transcript = StoredTranscript {
+            task_id: &self.task_id,
+            agent_name: &self.agent_name,
+            description: &self.task_description,
+            markdown: self.to_markdown(),
+            entries: self.entries
+                .iter()
+                .map(|entry| (entry.timestamp, entry.kind.label(), entry.content.as_str()))
+                .collect(),
+        };
+        Database::open()?
+            .save_transcript(&transcript)
+            .with_context(|| format!("Failed to save agent transcript {}", self.task_id))
+    }
+}
+
+pub fn load_transcript(task_id: &str) -> Result<String> {
+    Database::open()?
+        .load_transcript(task_id)?
+        .with_context(|| format!("No transcript found for task {}", task_id))
+}
+
+/// List saved transcripts, newest first
+pub fn list_transcripts() -> Result<Vec<String>> {
+    Database::open()?.list_transcripts()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transcript_markdown() {
+        let mut transcript = AgentTranscript::new("abc-123", "CodeAgent", "implement parser");
+        transcript.record(TranscriptEntryKind::Prompt, "Generate code");
+        transcript.record(TranscriptEntryKind::Status, "Completed");
+
+        let md = transcript.to_markdown();
+        assert!(md.starts_with("# Agent Transcript: abc-123"));
+        assert!(md.contains("- Agent: CodeAgent"));
+        assert!(md.contains("] Prompt\n\nGenerate code"));
+        assert!(md.contains("] Status\n\nCompleted"));
+    }
+}
@@ -0,0 +1,137 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+pub const TRUST_FILE: &str = ".kota/trust.json";
+
+/// Files whose edits were approved for the rest of this run
+fn session_edit_approvals() -> &'static Mutex<BTreeSet<String>> {
+    static APPROVED: OnceLock<Mutex<BTreeSet<String>>> = OnceLock::new();
+    APPROVED.get_or_init(|| Mutex::new(BTreeSet::new()))
+}
+
+/// Whether edits to `file_path` were approved with "approve all similar edits this session"
+pub fn edits_approved_for(file_path: &str) -> bool {
+    session_edit_approvals().lock().map(|approved| approved.contains(file_path)).unwrap_or(false)
+}
+
+pub fn approve_edits_for(file_path: &str) {
+    if let Ok(mut approved) = session_edit_approvals().lock() {
+        approved.insert(file_path.to_string());
+    }
+}
+
+pub fn session_approved_files() -> Vec<String> {
+    session_edit_approvals().lock().map(|approved| approved.iter().cloned().collect()).unwrap_or_default()
+}
+
+pub fn clear_session_approvals() {
+    if let Ok(mut approved) = session_edit_approvals().lock() {
+        approved.clear();
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct TrustFile {
+    #[serde(default)]
+    commands: BTreeSet<String>,
+}
+
+/// Commands the user chose to always allow in this project, kept in `.kota/trust.json`
+pub struct TrustStore {
+    path: PathBuf,
+    trusted: TrustFile,
+}
+
+impl TrustStore {
+    pub fn load() -> Self {
+        Self::load_from(TRUST_FILE)
+    }
+
+    /// A missing or unreadable file means nothing is trusted yet
+    pub fn load_from(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let trusted = match fs::read_to_string(&path) {
+            Ok(json) => serde_json::from_str(&json).unwrap_or_else(|e| {
+                eprintln!("Warning: Ignoring malformed trust store {}: {}", path.display(), e);
+                TrustFile::default()
+            }),
+            Err(_) => TrustFile::default(),
+        };
+        Self { path, trusted }
+    }
+
+    pub fn allows_command(&self, command: &str) -> bool {
+        self.trusted.commands.contains(command.trim())
+    }
+
+    pub fn commands(&self) -> impl Iterator<Item = &String> {
+        self.trusted.commands.iter()
+    }
+
+    pub fn allow_command(&mut self, command: &str) -> Result<()> {
+        self.trusted.commands.insert(command.trim().to_string());
+        self.save()
+    }
+
+    /// Returns whether the command was trusted
+    pub fn revoke_command(&mut self, command: &str) -> Result<bool> {
+        let removed = self.trusted.commands.remove(command.trim());
+        if removed {
+            self.save()?;
+        }
+        Ok(removed)
+    }
+
+    pub fn clear(&mut self) -> Result<usize> {
+        let count = self.trusted.commands.len();
+        self.trusted.commands.clear();
+        self.save()?;
+        Ok(count)
+    }
+
+    fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+        }
+        fs::write(&self.path, serde_json::to_string_pretty(&self.trusted)?)
+            .with_context(|| format!("Failed to write trust store {}", self.path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_trust_store_persists_commands() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join(".kota/trust.json");
+
+        let mut store = TrustStore::load_from(&path);
+        assert!(!store.allows_command("cargo test"));
+        store.allow_command("  cargo test ").unwrap();
+
+        let mut reloaded = TrustStore::load_from(&path);
+        assert!(reloaded.allows_command("cargo test"));
+        assert!(!reloaded.allows_command("cargo test --release"));
+
+        assert!(reloaded.revoke_command("cargo test").unwrap());
+        assert!(!reloaded.revoke_command("cargo test").unwrap());
+        assert!(!TrustStore::load_from(&path).allows_command("cargo test"));
+    }
+
+    #[test]
+    fn test_session_edit_approvals() {
+        assert!(!edits_approved_for("src/approvals_test_only.rs"));
+        approve_edits_for("src/approvals_test_only.rs");
+        assert!(edits_approved_for("src/approvals_test_only.rs"));
+        assert!(session_approved_files().contains(&"src/approvals_test_only.rs".to_string()));
+    }
+}
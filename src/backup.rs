@@ -0,0 +1,476 @@
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Component, Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local, Utc};
+use colored::*;
+use serde::{Deserialize, Serialize};
+
+use crate::db::{encryption, Database};
+
+/// Bumped when the archive layout changes; archives from a newer KOTA aren't restored
+const FORMAT_VERSION: u32 = 1;
+const MANIFEST_FILE: &str = "manifest.json";
+const DATABASE_FILE: &str = "kota.db";
+// Tried in order for checksums; each prints the digest of stdin first
+const SHA256_COMMANDS: &[(&str, &[&str])] = &[("sha256sum", &[]), ("shasum", &["-a", "256"]), ("openssl", &["dgst", "-sha256", "-r"])];
+
+/// Which `.kota` directory a file was backed up from
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Root {
+    /// `~/.kota`
+    Home,
+    /// `.kota` in the project, with the database and cache
+    Project,
+}
+
+impl Root {
+    fn name(self) -> &'static str {
+        match self {
+            Root::Home => "home",
+            Root::Project => "project",
+        }
+    }
+}
+
+/// What can be restored on its own
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Part {
+    /// Style, trust decisions, provider prompts and the context preset
+    Config,
+    /// Learnings in the database
+    Memory,
+    /// Conversations, command history, agent sessions, transcripts, usage and the audit log
+    Sessions,
+    /// The vector index, file index and cached explanations
+    Indexes,
+}
+
+impl Part {
+    const ALL: [Part; 4] = [Part::Config, Part::Memory, Part::Sessions, Part::Indexes];
+
+    fn parse(name: &str) -> Result<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "config" => Ok(Part::Config),
+            "memory" | "memories" => Ok(Part::Memory),
+            "sessions" => Ok(Part::Sessions),
+            "indexes" => Ok(Part::Indexes),
+            other => Err(anyhow::anyhow!("Unknown part {}; use config, memory, sessions or indexes", other)),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Part::Config => "config",
+            Part::Memory => "memory",
+            Part::Sessions => "sessions",
+            Part::Indexes => "indexes",
+        }
+    }
+
+    /// Database tables restored with this part
+    fn tables(self) -> &'static [&'static str] {
+        match self {
+            Part::Memory => &["learnings"],
+            Part::Sessions => &["conversations", "command_outputs", "agent_sessions", "transcripts", "llm_usage", "audit_log"],
+            Part::Config | Part::Indexes => &[],
+        }
+    }
+
+    /// The part a file under `.kota/` belongs to, by its top-level name
+    fn of(relative: &str) -> Part {
+        match relative.split('/').next().unwrap_or("") {
+            "transcripts" | "agents" | "maintenance" | "todos.json" => Part::Sessions,
+            "vectors" | "index" | "cache" | "explanations.json" => Part::Indexes,
+            _ => Part::Config,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Manifest {
+    format: u32,
+    kota_version: String,
+    created: DateTime<Utc>,
+    /// The `.kota` directories that were backed up
+    source: String,
+    files: Vec<ManifestFile>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ManifestFile {
+    root: Root,
+    /// Relative to the root's `.kota` directory
+    path: String,
+    /// None for the database, whose tables are split between parts
+    part: Option<Part>,
+    size: u64,
+    checksum: String,
+}
+
+/// `kota backup create|restore|verify`
+pub fn run(args: &[String]) -> Result<()> {
+    let dirs = dirs(args)?;
+    let shown = dirs.iter().map(|(_, dir)| dir.display().to_string()).collect::<Vec<_>>().join(" and ");
+    match args.first().map(String::as_str) {
+        Some("create") => {
+            let output = option(args, "--output").map(PathBuf::from).unwrap_or_else(|| {
+                let extension = if args.iter().any(|arg| arg == "--encrypt") { "tar.gz.enc" } else { "tar.gz" };
+                PathBuf::from(format!("kota-backup-{}.{}", Local::now().format("%Y%m%d-%H%M%S"), extension))
+            });
+            let manifest = create(&dirs, &output, args.iter().any(|arg| arg == "--encrypt"))?;
+            println!("{} {} ({} files) to {}", "Backed up".bright_green(), shown, manifest.files.len(), output.display());
+            Ok(())
+        }
+        Some("verify") => {
+            let archive = positional(args).context("Usage: kota backup verify <archive>")?;
+            let (manifest, staging) = unpack(Path::new(archive))?;
+            let _ = fs::remove_dir_all(&staging);
+            println!("{} backup of {} from {}, {} files intact", "OK:".bright_green(), manifest.source, manifest.created.with_timezone(&Local).format("%Y-%m-%d %H:%M"), manifest.files.len());
+            Ok(())
+        }
+        Some("restore") => {
+            let archive = positional(args).context("Usage: kota backup restore <archive> [--only memory,sessions] [--yes]")?;
+            let parts = match option(args, "--only") {
+                Some(only) => only.split(',').map(Part::parse).collect::<Result<Vec<_>>>()?,
+                None => Part::ALL.to_vec(),
+            };
+            let names: Vec<&str> = parts.iter().map(|part| part.name()).collect();
+            let assume_yes = args.iter().any(|arg| arg == "--yes" || arg == "-y");
+            if !assume_yes && !confirm(&format!("Replace {} in {} with the backup? [y/N] ", names.join(", "), shown))? {
+                println!("Restore cancelled");
+                return Ok(());
+            }
+            let restored = restore(Path::new(archive), &dirs, &parts)?;
+            println!("{} {} ({})", "Restored".bright_green(), names.join(", "), restored);
+            Ok(())
+        }
+        _ => Err(anyhow::anyhow!("Usage: kota backup create [--encrypt] [--output <file>] | restore <archive> [--only <parts>] [--yes] | verify <archive>, with --dir <home .kota> and --project <project .kota>")),
+    }
+}
+
+/// `~/.kota` and the project's `.kota`, which holds the database, unless they're the same
+fn dirs(args: &[String]) -> Result<Vec<(Root, PathBuf)>> {
+    let home = option(args, "--dir").map(PathBuf::from).map_or_else(default_dir, Ok)?;
+    let project = PathBuf::from(option(args, "--project").unwrap_or(".kota"));
+    let same = matches!((home.canonicalize(), project.canonicalize()), (Ok(home), Ok(project)) if home == project);
+    let mut dirs = vec![(Root::Home, home)];
+    if !same {
+        dirs.push((Root::Project, project));
+    }
+    Ok(dirs)
+}
+
+/// Where a root's files go; a project that is `~/.kota` here shares its directory
+fn dir_for(dirs: &[(Root, PathBuf)], root: Root) -> &Path {
+    dirs.iter().find(|(candidate, _)| *candidate == root).or(dirs.first()).map(|(_, dir)| dir.as_path()).unwrap_or(Path::new(".kota"))
+}
+
+/// Where a file sits in the extracted archive
+fn staged(files_dir: &Path, root: Root, path: &str) -> PathBuf {
+    files_dir.join(root.name()).join(path)
+}
+
+/// `~/.kota`
+fn default_dir() -> Result<PathBuf> {
+    let home = env::var_os("HOME").ok_or_else(|| anyhow::anyhow!("HOME is not set; pass --dir"))?;
+    Ok(PathBuf::from(home).join(".kota"))
+}
+
+fn option<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    args.iter().position(|arg| arg == name).and_then(|i| args.get(i + 1)).map(String::as_str)
+}
+
+/// The archive argument: the first one that isn't an option or an option's value
+fn positional(args: &[String]) -> Option<&str> {
+    let mut rest = args.iter().skip(1);
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--dir" | "--project" | "--only" | "--output" => {
+                rest.next();
+            }
+            arg if arg.starts_with('-') => {}
+            arg => return Some(arg),
+        }
+    }
+    None
+}
+
+fn confirm(question: &str) -> Result<bool> {
+    print!("{}", question.bright_white());
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Archive `dirs` to `output`: a gzipped tar of the files, a snapshot of each database and a
+/// manifest of checksums, encrypted with the storage key when asked
+fn create(dirs: &[(Root, PathBuf)], output: &Path, encrypt: bool) -> Result<Manifest> {
+    if !dirs.iter().any(|(_, dir)| dir.is_dir()) {
+        let shown: Vec<String> = dirs.iter().map(|(_, dir)| dir.display().to_string()).collect();
+        return Err(anyhow::anyhow!("Nothing to back up: {} don't exist", shown.join(" and ")));
+    }
+    let staging = staging_dir("backup")?;
+    let result = (|| {
+        let files_dir = staging.join("files");
+        let mut files = Vec::new();
+        for (root, dir) in dirs {
+            for relative in files_under(dir) {
+                // The database is snapshotted separately, which leaves its journal files out
+                if relative.starts_with(DATABASE_FILE) {
+                    continue;
+                }
+                let target = staged(&files_dir, *root, &relative);
+                fs::create_dir_all(target.parent().unwrap_or(&files_dir))?;
+                fs::copy(dir.join(&relative), &target).with_context(|| format!("Failed to copy {}", dir.join(&relative).display()))?;
+                let part = Part::of(&relative);
+                files.push(manifest_file(&files_dir, *root, relative, Some(part))?);
+            }
+            if dir.join(DATABASE_FILE).is_file() {
+                let target = staged(&files_dir, *root, DATABASE_FILE);
+                fs::create_dir_all(target.parent().unwrap_or(&files_dir))?;
+                Database::open_at(dir.join(DATABASE_FILE))?.snapshot_to(&target)?;
+                files.push(manifest_file(&files_dir, *root, DATABASE_FILE.to_string(), None)?);
+            }
+        }
+        let manifest = Manifest {
+            format: FORMAT_VERSION,
+            kota_version: env!("CARGO_PKG_VERSION").to_string(),
+            created: Utc::now(),
+            source: dirs.iter().map(|(_, dir)| dir.display().to_string()).collect::<Vec<_>>().join(" and "),
+            files,
+        };
+        fs::write(staging.join(MANIFEST_FILE), serde_json::to_string_pretty(&manifest)?)?;
+
+        let mut archive = tar(&["-czf", "-", MANIFEST_FILE, "files"], &staging, None)?;
+        if encrypt {
+            archive = encryption::key_cipher()?.encrypt_bytes(&archive)?;
+        }
+        fs::write(output, archive).with_context(|| format!("Failed to write {}", output.display()))?;
+        Ok(manifest)
+    })();
+    let _ = fs::remove_dir_all(&staging);
+    result
+}
+
+/// Restore `parts` of the backup at `archive` into `dirs`. Every checksum is verified before
+/// anything is replaced. Returns a summary of what was restored.
+fn restore(archive: &Path, dirs: &[(Root, PathBuf)], parts: &[Part]) -> Result<String> {
+    let (manifest, staging) = unpack(archive)?;
+    let result = (|| {
+        let files_dir = staging.join("files");
+        let mut restored_files = 0;
+        for file in &manifest.files {
+            let Some(part) = file.part else { continue };
+            if !parts.contains(&part) {
+                continue;
+            }
+            let dir = dir_for(dirs, file.root);
+            let target = dir.join(&file.path);
+            fs::create_dir_all(target.parent().unwrap_or(dir))?;
+            fs::copy(staged(&files_dir, file.root, &file.path), &target).with_context(|| format!("Failed to restore {}", file.path))?;
+            restored_files += 1;
+        }
+
+        let tables: Vec<&str> = parts.iter().flat_map(|part| part.tables()).copied().collect();
+        let mut restored_rows = 0;
+        for file in manifest.files.iter().filter(|file| file.path == DATABASE_FILE && !tables.is_empty()) {
+            let dir = dir_for(dirs, file.root);
+            fs::create_dir_all(dir)?;
+            restored_rows += Database::open_at(dir.join(DATABASE_FILE))?.restore_tables(&staged(&files_dir, file.root, DATABASE_FILE), &tables)?;
+        }
+        Ok(format!("{} files, {} database rows", restored_files, restored_rows))
+    })();
+    let _ = fs::remove_dir_all(&staging);
+    result
+}
+
+/// Decrypt and extract `archive` to a staging directory, and check it against its manifest
+fn unpack(archive: &Path) -> Result<(Manifest, PathBuf)> {
+    let mut bytes = fs::read(archive).with_context(|| format!("Failed to read {}", archive.display()))?;
+    // Anything that isn't gzip was encrypted with the storage key
+    if !bytes.starts_with(&[0x1f, 0x8b]) {
+        bytes = encryption::key_cipher()?
+            .decrypt_bytes(&bytes)
+            .context("Couldn't decrypt the backup; it needs the storage key it was made with")?;
+    }
+    let staging = staging_dir("restore")?;
+    let checked = tar(&["-xzf", "-"], &staging, Some(bytes)).and_then(|_| check(&staging));
+    match checked {
+        Ok(manifest) => Ok((manifest, staging)),
+        Err(e) => {
+            let _ = fs::remove_dir_all(&staging);
+            Err(e)
+        }
+    }
+}
+
+fn check(staging: &Path) -> Result<Manifest> {
+    let manifest: Manifest = serde_json::from_str(&fs::read_to_string(staging.join(MANIFEST_FILE)).context("The backup has no manifest")?)
+        .context("The backup's manifest is unreadable")?;
+    if manifest.format > FORMAT_VERSION {
+        return Err(anyhow::anyhow!("The backup was made by KOTA {}, which is newer; update to restore it", manifest.kota_version));
+    }
+    let mut problems = Vec::new();
+    for file in &manifest.files {
+        let safe = Path::new(&file.path).components().all(|component| matches!(component, Component::Normal(_)));
+        let bytes = if safe { fs::read(staged(&staging.join("files"), file.root, &file.path)).ok() } else { None };
+        match bytes {
+            Some(bytes) if bytes.len() as u64 == file.size && checksum(&bytes)? == file.checksum => {}
+            Some(_) => problems.push(format!("{} is corrupted", file.path)),
+            None => problems.push(format!("{} is missing", file.path)),
+        }
+    }
+    if !problems.is_empty() {
+        return Err(anyhow::anyhow!("The backup failed its integrity check; nothing was restored:\n  {}", problems.join("\n  ")));
+    }
+    Ok(manifest)
+}
+
+fn manifest_file(files_dir: &Path, root: Root, path: String, part: Option<Part>) -> Result<ManifestFile> {
+    let bytes = fs::read(staged(files_dir, root, &path))?;
+    Ok(ManifestFile { root, part, size: bytes.len() as u64, checksum: checksum(&bytes)?, path })
+}
+
+/// SHA-256 of `bytes` in hex, from whichever of sha256sum, shasum or openssl is installed
+fn checksum(bytes: &[u8]) -> Result<String> {
+    for (program, args) in SHA256_COMMANDS {
+        let output = match pipe(program, args, Path::new("."), Some(bytes.to_vec())) {
+            Ok(output) => output,
+            Err(e) if e.to_string().starts_with("Failed to run") => continue,
+            Err(e) => return Err(e),
+        };
+        let digest = String::from_utf8_lossy(&output).split_whitespace().next().unwrap_or_default().to_lowercase();
+        if digest.len() == 64 && digest.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Ok(digest);
+        }
+        return Err(anyhow::anyhow!("{} printed an unexpected checksum", program));
+    }
+    Err(anyhow::anyhow!("Checksumming the backup needs sha256sum, shasum or openssl"))
+}
+
+/// Files under `dir` relative to it, skipping symlinks
+fn files_under(dir: &Path) -> Vec<String> {
+    fn walk(root: &Path, relative: &Path, files: &mut Vec<String>) {
+        let Ok(entries) = fs::read_dir(root.join(relative)) else { return };
+        for entry in entries.flatten() {
+            let path = relative.join(entry.file_name());
+            match entry.file_type() {
+                Ok(kind) if kind.is_dir() => walk(root, &path, files),
+                Ok(kind) if kind.is_file() => files.push(path.to_string_lossy().to_string()),
+                _ => {}
+            }
+        }
+    }
+    let mut files = Vec::new();
+    walk(dir, Path::new(""), &mut files);
+    files.sort();
+    files
+}
+
+fn staging_dir(purpose: &str) -> Result<PathBuf> {
+    let dir = env::temp_dir().join(format!("kota-{}-{}", purpose, uuid::Uuid::new_v4()));
+    fs::create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    Ok(dir)
+}
+
+/// Run tar in `dir`, feeding it `input` and returning what it writes
+fn tar(args: &[&str], dir: &Path, input: Option<Vec<u8>>) -> Result<Vec<u8>> {
+    pipe("tar", args, dir, input)
+}
+
+/// Run `program` in `dir`, feeding it `input` and returning what it writes
+fn pipe(program: &str, args: &[&str], dir: &Path, input: Option<Vec<u8>>) -> Result<Vec<u8>> {
+    let mut child = Command::new(program)
+        .args(args)
+        .current_dir(dir)
+        .stdin(if input.is_some() { Stdio::piped() } else { Stdio::null() })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to run {}", program))?;
+    let writer = match (input, child.stdin.take()) {
+        (Some(input), Some(mut stdin)) => Some(std::thread::spawn(move || stdin.write_all(&input))),
+        _ => None,
+    };
+    let output = child.wait_with_output()?;
+    if let Some(writer) = writer {
+        // A broken pipe here just means the program stopped early; its exit status says why
+        let _ = writer.join();
+    }
+    if !output.status.success() {
+        return Err(anyhow::anyhow!("{} failed: {}", program, String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    Ok(output.stdout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_parts() {
+        assert_eq!(Part::of("style.json"), Part::Config);
+        assert_eq!(Part::of("prompts/openai.md"), Part::Config);
+        assert_eq!(Part::of("transcripts/2026-10-16.md"), Part::Sessions);
+        assert_eq!(Part::of("vectors/index.bin"), Part::Indexes);
+        assert_eq!(Part::of("cache/llm/entry"), Part::Indexes);
+        assert_eq!(Part::parse("Memories").unwrap(), Part::Memory);
+        assert!(Part::parse("everything").is_err());
+        let args: Vec<String> = ["restore", "--only", "memory", "backup.tar.gz"].map(String::from).to_vec();
+        assert_eq!(positional(&args), Some("backup.tar.gz"));
+        assert_eq!(option(&args, "--only"), Some("memory"));
+    }
+
+    #[test]
+    fn test_checksum_is_sha256() {
+        assert_eq!(checksum(b"abc").unwrap(), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+    }
+
+    // Needs tar and one of sha256sum, shasum or openssl
+    #[test]
+    fn test_backup_round_trip() {
+        let dir = TempDir::new().unwrap();
+        let home = dir.path().join("home/.kota");
+        let project = dir.path().join("project/.kota");
+        fs::create_dir_all(home.join("transcripts")).unwrap();
+        fs::create_dir_all(project.join("cache/llm")).unwrap();
+        fs::write(home.join("style.json"), "{\"emoji\":false}").unwrap();
+        fs::write(home.join("transcripts/today.md"), "# Session").unwrap();
+        fs::write(project.join("cache/llm/entry"), "cached").unwrap();
+        let db = Database::open_at(project.join(DATABASE_FILE)).unwrap();
+        db.insert_learning("style", "Prefers tabs").unwrap();
+        let dirs = vec![(Root::Home, home.clone()), (Root::Project, project.clone())];
+
+        let archive = dir.path().join("backup.tar.gz");
+        let manifest = create(&dirs, &archive, false).unwrap();
+        let paths: Vec<(&str, &str)> = manifest.files.iter().map(|file| (file.root.name(), file.path.as_str())).collect();
+        assert_eq!(paths, vec![("home", "style.json"), ("home", "transcripts/today.md"), ("project", "cache/llm/entry"), ("project", "kota.db")]);
+
+        // Memories only: the learning comes back, config files are left alone
+        fs::write(home.join("style.json"), "{\"emoji\":true}").unwrap();
+        db.insert_learning("style", "Prefers spaces").unwrap();
+        drop(db);
+        restore(&archive, &dirs, &[Part::Memory]).unwrap();
+        assert_eq!(fs::read_to_string(home.join("style.json")).unwrap(), "{\"emoji\":true}");
+        let learnings = Database::open_at(project.join(DATABASE_FILE)).unwrap().all_learnings().unwrap();
+        assert_eq!(learnings.iter().map(|(_, _, content)| content.as_str()).collect::<Vec<_>>(), vec!["Prefers tabs"]);
+
+        restore(&archive, &dirs, &[Part::Config]).unwrap();
+        assert_eq!(fs::read_to_string(home.join("style.json")).unwrap(), "{\"emoji\":false}");
+
+        // A damaged archive is refused before anything is touched
+        let mut bytes = fs::read(&archive).unwrap();
+        let middle = bytes.len() / 2;
+        bytes.truncate(middle);
+        fs::write(&archive, bytes).unwrap();
+        assert!(restore(&archive, &dirs, &Part::ALL).is_err());
+    }
+}
@@ -0,0 +1,207 @@
+use std::process::Command;
+
+use crate::memory::MemoryManager;
+use crate::sr_parser::{FileOperation, SearchReplaceBlock};
+
+const TEST_WORDS: [&str; 7] = ["test", "tests", "pytest", "jest", "vitest", "nextest", "rspec"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileChange {
+    pub path: String,
+    /// Lines added and removed, when git could count them
+    pub lines: Option<(u64, u64)>,
+}
+
+/// What a multi-step operation changed: files touched, commands run and how tests went.
+/// Files come from git, diffed against the commit checked out when the operation started,
+/// so edits that were auto-committed along the way still count. Files that were already
+/// modified at the start are left out unless they changed further.
+#[derive(Debug, Clone)]
+pub struct ChangeReport {
+    title: String,
+    start_commit: Option<String>,
+    // Uncommitted changes at the start
+    baseline: Vec<FileChange>,
+    edited: Vec<String>,
+    commands: Vec<(String, bool)>,
+    files: Vec<FileChange>,
+}
+
+impl ChangeReport {
+    pub fn start(title: &str) -> Self {
+        Self {
+            title: title.lines().next().unwrap_or("").chars().take(80).collect(),
+            start_commit: git_output(&["rev-parse", "HEAD"]).map(|head| head.trim().to_string()),
+            baseline: git_output(&["diff", "--numstat", "HEAD"]).map(|numstat| parse_numstat(&numstat)).unwrap_or_default(),
+            edited: Vec::new(),
+            commands: Vec::new(),
+            files: Vec::new(),
+        }
+    }
+
+    /// Edits applied during the operation; only used for the file list outside a git repository
+    pub fn record_edits(&mut self, applied: &[SearchReplaceBlock]) {
+        for block in applied {
+            if !self.edited.contains(&block.file_path) {
+                self.edited.push(block.file_path.clone());
+            }
+        }
+    }
+
+    /// Deletes and renames, recorded like edits
+    pub fn record_file_operations(&mut self, applied: &[FileOperation]) {
+        for path in applied.iter().flat_map(FileOperation::paths) {
+            if !self.edited.iter().any(|edited| edited == path) {
+                self.edited.push(path.to_string());
+            }
+        }
+    }
+
+    pub fn record_command(&mut self, command: &str, success: bool) {
+        self.commands.push((command.trim().to_string(), success));
+    }
+
+    /// Work out which files changed since the start
+    pub fn finish(&mut self) {
+        let numstat = self.start_commit.as_ref().and_then(|start| git_output(&["diff", "--numstat", start]));
+        self.files = match numstat {
+            Some(numstat) => parse_numstat(&numstat)
+                .into_iter()
+                .filter(|file| !self.baseline.contains(file))
+                .collect(),
+            None => self.edited.iter().map(|path| FileChange { path: path.clone(), lines: None }).collect(),
+        };
+    }
+
+    /// More than one thing happened, so a summary is worth showing
+    pub fn is_multi_step(&self) -> bool {
+        self.files.len() + self.commands.len() > 1
+    }
+
+    /// Whether test commands passed: None when none ran, false if any failed
+    pub fn tests_passed(&self) -> Option<bool> {
+        let mut tests = self.commands.iter().filter(|(command, _)| is_test_command(command)).peekable();
+        tests.peek()?;
+        Some(tests.all(|(_, success)| *success))
+    }
+
+    pub fn render(&self) -> String {
+        let mut report = format!("What changed: {}\n", self.title);
+        if self.files.is_empty() {
+            report.push_str("Files: none\n");
+        } else {
+            report.push_str(&format!("Files ({}):\n", self.files.len()));
+            for file in &self.files {
+                match file.lines {
+                    Some((added, removed)) => report.push_str(&format!("  {} (+{} -{})\n", file.path, added, removed)),
+                    None => report.push_str(&format!("  {}\n", file.path)),
+                }
+            }
+        }
+        if !self.commands.is_empty() {
+            report.push_str(&format!("Commands ({}):\n", self.commands.len()));
+            for (command, success) in &self.commands {
+                report.push_str(&format!("  {} {}\n", if *success { "✓" } else { "✗" }, command));
+            }
+        }
+        report.push_str(match self.tests_passed() {
+            Some(true) => "Tests: passed",
+            Some(false) => "Tests: FAILED",
+            None => "Tests: not run",
+        });
+        report
+    }
+
+    /// One-line version for memory, so it turns up in /history search
+    pub fn summary(&self) -> String {
+        let files: Vec<&str> = self.files.iter().map(|file| file.path.as_str()).collect();
+        let commands: Vec<String> = self
+            .commands
+            .iter()
+            .map(|(command, success)| format!("{} ({})", command, if *success { "ok" } else { "failed" }))
+            .collect();
+        format!(
+            "Change report for '{}': files [{}]; commands [{}]; {}",
+            self.title,
+            files.join(", "),
+            commands.join(", "),
+            self.render().lines().last().unwrap_or_default()
+        )
+    }
+
+    pub fn store(&self) {
+        if let Err(e) = MemoryManager::default().store_conversation_summary(&self.summary()) {
+            eprintln!("Warning: Failed to store change report in memory: {}", e);
+        }
+    }
+}
+
+fn is_test_command(command: &str) -> bool {
+    command.split_whitespace().any(|word| TEST_WORDS.contains(&word))
+}
+
+fn git_output(args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).output().ok()?;
+    output.status.success().then(|| String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// `git diff --numstat` lines; binary files show `-` for both counts
+fn parse_numstat(numstat: &str) -> Vec<FileChange> {
+    numstat
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.splitn(3, '\t');
+            let (added, removed, path) = (parts.next()?, parts.next()?, parts.next()?);
+            let lines = added.parse().ok().zip(removed.parse().ok());
+            Some(FileChange { path: path.to_string(), lines })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report() -> ChangeReport {
+        ChangeReport { title: "Rename the parser".to_string(), start_commit: None, baseline: Vec::new(), edited: Vec::new(), commands: Vec::new(), files: Vec::new() }
+    }
+
+    #[test]
+    fn test_parse_numstat() {
+        let files = parse_numstat("3\t1\tsrc/parser.rs\n-\t-\tassets/logo.png\n");
+        assert_eq!(files[0], FileChange { path: "src/parser.rs".to_string(), lines: Some((3, 1)) });
+        assert_eq!(files[1].lines, None);
+    }
+
+    #[test]
+    fn test_report_without_git() {
+        let mut report = report();
+        report.record_edits(&[
+            SearchReplaceBlock { file_path: "src/a.rs".to_string(), search_lines: "a".to_string(), replace_lines: "b".to_string() },
+            SearchReplaceBlock { file_path: "src/a.rs".to_string(), search_lines: "c".to_string(), replace_lines: "d".to_string() },
+        ]);
+        report.finish();
+        assert_eq!(report.files.len(), 1);
+        assert!(!report.is_multi_step());
+
+        report.record_command("cargo build", true);
+        report.record_command("cargo test", false);
+        assert!(report.is_multi_step());
+        assert_eq!(report.tests_passed(), Some(false));
+
+        let rendered = report.render();
+        assert!(rendered.starts_with("What changed: Rename the parser\nFiles (1):\n  src/a.rs\n"));
+        assert!(rendered.contains("  ✓ cargo build\n  ✗ cargo test\n"));
+        assert!(rendered.ends_with("Tests: FAILED"));
+    }
+
+    #[test]
+    fn test_test_commands() {
+        assert!(is_test_command("cargo test --workspace"));
+        assert!(is_test_command("npm run test"));
+        assert!(is_test_command("pytest -q"));
+        assert!(!is_test_command("cargo build"));
+        assert!(!is_test_command("cat latest.log"));
+        assert_eq!(report().tests_passed(), None);
+    }
+}
@@ -0,0 +1,229 @@
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use regex::Regex;
+
+use crate::clock;
+use crate::context::ContextManager;
+use crate::db::Database;
+
+pub const CHANGELOG_FILE: &str = "CHANGELOG.md";
+const CHANGELOG_HEADER: &str = "# Changelog\n\nAll notable changes to this project are documented in this file.\n";
+// How change reports are stored in conversation memory
+const CHANGE_REPORT_PREFIX: &str = "Change report for ";
+
+/// Changelog sections, in the order they're listed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ChangeKind {
+    Added,
+    Changed,
+    Fixed,
+    Removed,
+    Documentation,
+    Maintenance,
+}
+
+impl ChangeKind {
+    pub fn label(&self) -> &'static str {
+        match self {
+            ChangeKind::Added => "Added",
+            ChangeKind::Changed => "Changed",
+            ChangeKind::Fixed => "Fixed",
+            ChangeKind::Removed => "Removed",
+            ChangeKind::Documentation => "Documentation",
+            ChangeKind::Maintenance => "Maintenance",
+        }
+    }
+}
+
+/// What happened since a ref: its commits, and the edits KOTA applied in that time
+#[derive(Debug, Clone)]
+pub struct Changes {
+    pub since: String,
+    /// Short hash and subject, newest first
+    pub commits: Vec<(String, String)>,
+    pub ai_edits: Vec<String>,
+}
+
+impl Changes {
+    pub fn summary(&self) -> String {
+        format!("{} commit(s) and {} KOTA edit(s) since {}", self.commits.len(), self.ai_edits.len(), self.since)
+    }
+
+    /// Commit subjects by section, oldest first within each
+    pub fn grouped(&self) -> Vec<(ChangeKind, Vec<String>)> {
+        let mut groups: Vec<(ChangeKind, Vec<String>)> = Vec::new();
+        for (hash, subject) in self.commits.iter().rev() {
+            let (kind, subject) = classify(subject);
+            let entry = format!("{} ({})", subject, hash);
+            match groups.iter_mut().find(|(group, _)| *group == kind) {
+                Some((_, entries)) => entries.push(entry),
+                None => groups.push((kind, vec![entry])),
+            }
+        }
+        groups.sort_by_key(|(kind, _)| *kind);
+        groups
+    }
+
+    /// The request that has the model draft the new section as an edit to CHANGELOG.md
+    pub fn request(&self) -> String {
+        let mut request = format!(
+            "Draft a new section at the top of {file} for the changes since {since}, in Keep a Changelog style: \
+a \"## [Unreleased] - {date}\" heading, then \"### Added\", \"### Changed\", \"### Fixed\" and \"### Removed\" \
+subsections as needed, with one short user-facing bullet per change. Merge related commits, leave out internal \
+maintenance unless users would notice it, and don't invent anything that isn't listed below. Apply it with a \
+search/replace block on {file} that inserts the section below the title and above any earlier releases.\n\n\
+Commits since {since}, grouped by type:\n",
+            file = CHANGELOG_FILE,
+            since = self.since,
+            date = clock::clock().now().format("%Y-%m-%d"),
+        );
+        for (kind, entries) in self.grouped() {
+            request.push_str(&format!("{}:\n", kind.label()));
+            for entry in entries {
+                request.push_str(&format!("- {}\n", entry));
+            }
+        }
+        if !self.ai_edits.is_empty() {
+            request.push_str("\nEdits KOTA applied in this period:\n");
+            for edit in &self.ai_edits {
+                request.push_str(&format!("- {}\n", edit));
+            }
+        }
+        request
+    }
+}
+
+/// Which section a commit belongs in, from a conventional-commit type like `feat(ui):` or
+/// else the subject's first word, and the subject without the type or a leading `[tag]`
+pub fn classify(subject: &str) -> (ChangeKind, String) {
+    let subject = subject.trim();
+    let subject = Regex::new(r"^\[[^\]]*\]\s*").unwrap().replace(subject, "").to_string();
+    let conventional = Regex::new(r"^(\w+)(?:\([^)]*\))?!?:\s*(.+)$").unwrap();
+    if let Some(captures) = conventional.captures(&subject) {
+        let kind = match captures[1].to_lowercase().as_str() {
+            "feat" | "feature" => Some(ChangeKind::Added),
+            "fix" | "bugfix" | "hotfix" => Some(ChangeKind::Fixed),
+            "docs" | "doc" => Some(ChangeKind::Documentation),
+            "perf" | "refactor" | "style" | "revert" => Some(ChangeKind::Changed),
+            "test" | "tests" | "chore" | "build" | "ci" | "deps" | "release" => Some(ChangeKind::Maintenance),
+            _ => None,
+        };
+        if let Some(kind) = kind {
+            return (kind, captures[2].to_string());
+        }
+    }
+
+    let first_word = subject.split_whitespace().next().unwrap_or("").to_lowercase();
+    let kind = match first_word.trim_end_matches(':') {
+        "add" | "adds" | "added" | "implement" | "implements" | "introduce" | "support" | "create" | "new" => ChangeKind::Added,
+        "fix" | "fixes" | "fixed" | "correct" | "resolve" | "resolves" | "handle" => ChangeKind::Fixed,
+        "remove" | "removes" | "removed" | "delete" | "drop" | "deprecate" => ChangeKind::Removed,
+        "doc" | "docs" | "document" | "readme" => ChangeKind::Documentation,
+        "bump" | "chore" | "ci" | "test" | "tests" | "release" | "merge" | "format" | "lint" => ChangeKind::Maintenance,
+        _ => ChangeKind::Changed,
+    };
+    (kind, subject)
+}
+
+/// Commits since `since` (the latest tag when empty) and the KOTA change reports stored
+/// since that commit was made
+pub fn gather(since: &str) -> Result<Changes> {
+    let since = match since.trim() {
+        "" => git(&["describe", "--tags", "--abbrev=0"])
+            .map(|tag| tag.trim().to_string())
+            .map_err(|_| anyhow::anyhow!("No tags to start from; name a ref, e.g. /changelog HEAD~20"))?,
+        since => since.to_string(),
+    };
+    let date = git(&["show", "-s", "--format=%cI", &format!("{}^{{commit}}", since)])
+        .with_context(|| format!("Unknown ref: {}", since))?;
+    let date = DateTime::parse_from_rfc3339(date.trim())
+        .with_context(|| format!("Can't read the date of {}", since))?
+        .with_timezone(&Local);
+
+    let log = git(&["log", "--no-merges", "--format=%h%x1f%s", &format!("{}..HEAD", since)])?;
+    let commits = log
+        .lines()
+        .filter_map(|line| line.split_once('\x1f'))
+        .map(|(hash, subject)| (hash.to_string(), subject.to_string()))
+        .collect();
+
+    // Memory is optional; without it the changelog comes from commits alone
+    let ai_edits = Database::open()
+        .and_then(|db| db.conversations_since(date))
+        .map(|entries| change_reports(entries.iter().map(|(_, content)| content.as_str())))
+        .unwrap_or_default();
+    Ok(Changes { since, commits, ai_edits })
+}
+
+fn change_reports<'a>(entries: impl Iterator<Item = &'a str>) -> Vec<String> {
+    entries
+        .filter_map(|content| content.strip_prefix(CHANGE_REPORT_PREFIX))
+        .map(|report| report.to_string())
+        .collect()
+}
+
+/// Gather the changes, make sure CHANGELOG.md exists and is in context so the model can
+/// edit it, and return them; the caller sends `Changes::request` through the usual chat
+/// and edit review
+pub fn prepare(since: &str, context: &mut ContextManager) -> Result<Changes> {
+    let changes = gather(since)?;
+    if changes.commits.is_empty() && changes.ai_edits.is_empty() {
+        return Err(anyhow::anyhow!("Nothing has changed since {}", changes.since));
+    }
+    if !Path::new(CHANGELOG_FILE).exists() {
+        fs::write(CHANGELOG_FILE, CHANGELOG_HEADER).with_context(|| format!("Failed to create {}", CHANGELOG_FILE))?;
+        println!("Created {}", CHANGELOG_FILE);
+    }
+    context.add_file(CHANGELOG_FILE)?;
+    Ok(changes)
+}
+
+fn git(args: &[&str]) -> Result<String> {
+    let output = Command::new("git").args(args).output().context("Failed to run git")?;
+    if !output.status.success() {
+        return Err(anyhow::anyhow!("git {} failed: {}", args.join(" "), String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify() {
+        assert_eq!(classify("feat(tui): add a context panel"), (ChangeKind::Added, "add a context panel".to_string()));
+        assert_eq!(classify("fix!: stop dropping the last chunk"), (ChangeKind::Fixed, "stop dropping the last chunk".to_string()));
+        assert_eq!(classify("ci: cache cargo builds").0, ChangeKind::Maintenance);
+        assert_eq!(classify("[org/repo#12] Add /style command"), (ChangeKind::Added, "Add /style command".to_string()));
+        assert_eq!(classify("Remove the legacy parser").0, ChangeKind::Removed);
+        assert_eq!(classify("Speed up context search").0, ChangeKind::Changed);
+        assert_eq!(classify("wip: something").0, ChangeKind::Changed);
+    }
+
+    #[test]
+    fn test_grouped_request() {
+        let changes = Changes {
+            since: "v0.2.0".to_string(),
+            commits: vec![
+                ("c3".to_string(), "Fix crash on empty context".to_string()),
+                ("b2".to_string(), "feat: add /changelog".to_string()),
+                ("a1".to_string(), "Add repo map".to_string()),
+            ],
+            ai_edits: change_reports(["User: hi", "Change report for 'add tests': files [src/a.rs]"].into_iter()),
+        };
+        let grouped = changes.grouped();
+        assert_eq!(grouped[0], (ChangeKind::Added, vec!["Add repo map (a1)".to_string(), "add /changelog (b2)".to_string()]));
+        assert_eq!(grouped[1], (ChangeKind::Fixed, vec!["Fix crash on empty context (c3)".to_string()]));
+
+        let request = changes.request();
+        assert!(request.contains("changes since v0.2.0"));
+        assert!(request.contains("Added:\n- Add repo map (a1)\n- add /changelog (b2)\nFixed:\n"));
+        assert!(request.ends_with("Edits KOTA applied in this period:\n- 'add tests': files [src/a.rs]\n"));
+        assert_eq!(changes.summary(), "3 commit(s) and 1 KOTA edit(s) since v0.2.0");
+    }
+}
@@ -1,25 +1,48 @@
-use std::io;
+use std::io::{self, Write};
+use std::sync::Arc;
 use anyhow::Result;
 use colored::*;
 
-use crate::context::ContextManager;
-use crate::llm::{LlmProvider, ModelConfig};
+use crate::compact::Compaction;
+use crate::focus;
+use crate::context::{ContextManager, Origin};
+use crate::llm::{self, LlmProvider, ModelConfig};
+use crate::llm::tools::ToolCall;
 use crate::commands::{CommandRegistry, CommandResult};
-use crate::{input, thinking, sr_parser, editor, cmd_parser, tui, render_markdown};
+use crate::agents::manager::{AgentManager, DelegationOutcome, RoutingDecision, StepOutcome};
+use crate::agents::session::{self, AgentSession};
+use crate::agents::traits::{AgentTask, TaskPriority, TaskStatus};
+use crate::memory::MemoryManager;
+use crate::approvals::{self, TrustStore};
+use crate::sandbox::{ExecOutcome, SecureExecutor};
+use crate::secrets::{self, ReviewDecision, SecretFinding};
+use crate::{input, thinking, editor, tui, render_markdown, history, response_pipeline, compare, validate, project, explain, changelog, instructions, lint, crash, trace};
+use crate::prompts::{AuthScheme, EmbeddingsConfig, PromptsConfig};
+use crate::todos::TodoList;
+use crate::change_report::ChangeReport;
+use crate::issue::{self, IssueLink, IssueRef};
+
+// Context items shown by `/show_context <query>`
+const CONTEXT_SEARCH_LIMIT: usize = 5;
 
 /// Runs the classic CLI interface
-pub async fn run_classic_cli(_context_manager: ContextManager, _model_config: ModelConfig) -> Result<()> {
+pub async fn run_classic_cli(context_manager: ContextManager, _model_config: ModelConfig) -> Result<()> {
     let header_width = 60;
     println!("{}", "═".repeat(header_width).bright_blue());
     println!("{}", "KOTA - AI Coding Assistant".bright_white().bold());
     println!("{}", "═".repeat(header_width).bright_blue());
     
-    let mut context_manager = ContextManager::new();
+    let mut context_manager = context_manager;
     let mut model_config = ModelConfig::default();
     let command_registry = CommandRegistry::new();
+    let mut agent_manager: Option<AgentManager> = None;
+    secrets::set_review_handler(Arc::new(|findings| Box::pin(async move { thinking::suspend(|| review_secrets(&findings)) })));
     
     // Show provider status and check API key
     show_provider_status(&model_config);
+    if let LlmProvider::Ollama = model_config.provider {
+        check_ollama_model(&model_config).await?;
+    }
     
     println!("{}", "─".repeat(header_width).dimmed());
     println!("{} Type '/help' for available commands", "💡".yellow());
@@ -27,7 +50,11 @@ pub async fn run_classic_cli(_context_manager: ContextManager, _model_config: Mo
     println!();
 
     loop {
-        let user_input = input::read_line_with_shortcuts()?;
+        if let Some(summary) = focus::finish_if_due() {
+            println!("{}\n", summary.bright_green());
+        }
+        let focus_label = focus::current().map(|session| format!(", focus {}m left", session.minutes_left(chrono::Utc::now()))).unwrap_or_default();
+        let user_input = input::read_line_with_shortcuts(&format!("context {}{}", context_manager.usage_label(), focus_label))?;
         let trimmed_input = user_input.trim();
 
         if trimmed_input.is_empty() {
@@ -35,11 +62,25 @@ pub async fn run_classic_cli(_context_manager: ContextManager, _model_config: Mo
         }
         
         if trimmed_input.starts_with('/') {
-            if let Err(e) = handle_command(trimmed_input, &mut context_manager, &mut model_config, &command_registry).await {
+            crash::log(trimmed_input);
+            if let Err(e) = handle_command(trimmed_input, &mut context_manager, &mut model_config, &command_registry, &mut agent_manager).await {
+                crash::log(&format!("Command error: {}", e));
                 eprintln!("Command error: {}", e);
             }
-        } else if let Err(e) = handle_ai_interaction(trimmed_input, &mut context_manager, &model_config).await {
-            eprintln!("Error in AI interaction: {}", e);
+        } else {
+            crash::log(&format!("prompt ({} chars) to {}", trimmed_input.len(), model_config.display_name()));
+            if let Err(e) = handle_ai_interaction(trimmed_input, &mut context_manager, &model_config).await {
+                crash::log(&format!("Error in AI interaction: {}", e));
+                eprintln!("Error in AI interaction: {}", e);
+            }
+        }
+        
+        // Fix-it follow-ups for edits that stopped a file parsing
+        while let Some(fix) = validate::take_fix_request() {
+            println!("{} {}", "Asking for a fix:".yellow().bold(), fix.lines().next().unwrap_or_default());
+            if let Err(e) = handle_ai_interaction(&fix, &mut context_manager, &model_config).await {
+                eprintln!("Error in AI interaction: {}", e);
+            }
         }
         
         println!(); // Add spacing between interactions
@@ -67,6 +108,41 @@ fn show_provider_status(model_config: &ModelConfig) {
                 println!("{} Use /provider ollama to switch to local Ollama", "Alternative:".dimmed());
             }
         }
+        LlmProvider::OpenRouter => {
+            if std::env::var("OPENROUTER_API_KEY").is_ok() {
+                println!("{} {}", "Provider:".dimmed(), "OpenRouter (cloud)".cyan());
+            } else {
+                println!("{} {}", "Provider:".dimmed(), "OpenRouter (cloud) - Missing API key".yellow());
+                println!("{} export OPENROUTER_API_KEY=your_api_key", "Set with:".dimmed());
+                println!("{} Use /provider ollama to switch to local Ollama", "Alternative:".dimmed());
+            }
+        }
+        LlmProvider::Groq | LlmProvider::Together => {
+            if let Some(endpoint) = crate::llm::fast::endpoint(&model_config.provider) {
+                if std::env::var(endpoint.key_env).is_ok() {
+                    println!("{} {}", "Provider:".dimmed(), format!("{} (cloud)", endpoint.label).cyan());
+                } else {
+                    println!("{} {}", "Provider:".dimmed(), format!("{} (cloud) - Missing API key", endpoint.label).yellow());
+                    println!("{} export {}=your_api_key", "Set with:".dimmed(), endpoint.key_env);
+                }
+            }
+        }
+        LlmProvider::Azure | LlmProvider::Bedrock => {
+            let providers = PromptsConfig::load().unwrap_or_default().providers;
+            let (label, auth, key_env, missing_endpoint) = match model_config.provider {
+                LlmProvider::Azure => ("Azure OpenAI", providers.azure.auth, providers.azure.api_key_env, providers.azure.endpoint.trim().is_empty()),
+                _ => ("AWS Bedrock", providers.bedrock.auth, providers.bedrock.api_key_env, false),
+            };
+            if missing_endpoint {
+                println!("{} {}", "Provider:".dimmed(), format!("{} - Missing endpoint", label).yellow());
+                println!("{} endpoint under [providers.azure] in prompts.toml", "Set:".dimmed());
+            } else if auth != AuthScheme::None && std::env::var(&key_env).is_err() {
+                println!("{} {}", "Provider:".dimmed(), format!("{} - Missing credentials", label).yellow());
+                println!("{} export {}=your_key", "Set with:".dimmed(), key_env);
+            } else {
+                println!("{} {}", "Provider:".dimmed(), label.cyan());
+            }
+        }
     }
 }
 
@@ -75,6 +151,7 @@ async fn handle_command(
     context_manager: &mut ContextManager,
     model_config: &mut ModelConfig,
     command_registry: &CommandRegistry,
+    agent_manager: &mut Option<AgentManager>,
 ) -> Result<()> {
     let parts: Vec<&str> = input.splitn(2, ' ').collect();
     let command = parts[0];
@@ -89,13 +166,41 @@ async fn handle_command(
         "/tui" => {
             println!("Switching to TUI mode...");
             // Create new instances for TUI mode
-            let new_context = ContextManager::new();
+            let mut new_context = ContextManager::new();
+            project::load_context_preset(&mut new_context);
             let new_config = ModelConfig::default();
             tui::run_tui(new_context, new_config).await
         }
+        "/agent" if arg.trim_start().starts_with("run") => {
+            let task_arg = arg.trim_start().trim_start_matches("run").trim();
+            handle_agent_run(task_arg, context_manager, model_config, agent_manager, None).await
+        }
+        "/provider" if arg.trim().eq_ignore_ascii_case("ollama") => {
+            if let Some(result) = command_registry.execute(command, arg, context_manager, model_config)? {
+                display_command_result(result);
+            }
+            check_ollama_model(model_config).await
+        }
+        "/models" => handle_ollama_models(model_config).await,
+        "/compare" => handle_compare(arg.trim(), context_manager).await,
+        "/changelog" => handle_changelog(arg.trim(), context_manager, model_config).await,
+        "/compact" => handle_compact(context_manager, model_config).await,
+        "/replay" if !arg.trim().is_empty() => handle_replay(arg.trim(), model_config).await,
+        "/init" => handle_init(arg.trim(), model_config).await,
+        "/issue" => handle_issue(arg.trim(), context_manager, model_config, agent_manager).await,
+        "/show_context" if !arg.trim().is_empty() => handle_context_search(arg.trim(), context_manager).await,
+        "/model" if arg.trim_start().starts_with("list") => {
+            let filter = arg.trim_start().trim_start_matches("list").trim();
+            handle_model_list(filter, model_config).await
+        }
+        "/agent" if arg.trim_start().starts_with("resume") => {
+            let task_id = arg.trim_start().trim_start_matches("resume").trim();
+            handle_agent_resume(task_id, context_manager, model_config, agent_manager).await
+        }
         "/quit" => {
             println!("{}", "─".repeat(60).dimmed());
             println!("{}", "Goodbye!".bright_white());
+            trace::finish();
             std::process::exit(0);
         }
         _ => {
@@ -113,6 +218,380 @@ async fn handle_command(
     }
 }
 
+/// Browse the OpenRouter catalog and optionally pick a model: `/model list [<filter>]`
+async fn handle_model_list(filter: &str, model_config: &mut ModelConfig) -> Result<()> {
+    let spinner = thinking::ThinkingIndicator::new("Fetching OpenRouter models...");
+    let models = crate::llm::list_openrouter_models().await;
+    spinner.finish();
+    
+    let models = match models {
+        Ok(models) => models,
+        Err(e) => {
+            eprintln!("Error fetching model list: {}", e);
+            return Ok(());
+        }
+    };
+    
+    let matches = crate::llm::filter_openrouter_models(&models, filter);
+    if matches.is_empty() {
+        println!("No OpenRouter models match '{}'", filter);
+        return Ok(());
+    }
+    
+    println!("{} ({} of {})", "OpenRouter models".bright_cyan().bold(), matches.len(), models.len());
+    for (i, model) in matches.iter().enumerate() {
+        let context = model.context_length
+            .map(|tokens| format!("{}k ctx", tokens / 1000))
+            .unwrap_or_default();
+        let price = model.prompt_price
+            .map(|price| format!("${:.2}/M in", price))
+            .unwrap_or_default();
+        println!("{:>3}. {} {}", i + 1, model.id.cyan(), format!("{} {} {}", model.name, context, price).dimmed());
+    }
+    
+    print!("{}", "Select a model number (Enter to keep the current model): ".yellow());
+    io::stdout().flush()?;
+    let mut choice = String::new();
+    io::stdin().read_line(&mut choice)?;
+    
+    if let Ok(n) = choice.trim().parse::<usize>() {
+        match matches.get(n.wrapping_sub(1)) {
+            Some(model) => {
+                model_config.provider = LlmProvider::OpenRouter;
+                model_config.model_name = Some(model.id.clone());
+                model_config.context_window = model.context_length;
+                println!("{} {}", "Model set to:".green(), model_config.display_name());
+            }
+            None => println!("{}", "Invalid selection".red()),
+        }
+    }
+    
+    Ok(())
+}
+
+/// Make sure the configured Ollama model is available locally, offering to pull it if not
+async fn check_ollama_model(model_config: &ModelConfig) -> Result<()> {
+    let model = model_config.get_model_name();
+    let models = match crate::llm::list_ollama_models().await {
+        Ok(models) => models,
+        Err(e) => {
+            println!("{} {}", "Warning:".yellow(), e);
+            return Ok(());
+        }
+    };
+    
+    if crate::llm::ollama_has_model(&models, &model) {
+        println!("{} {} ({} models available, see /models)", "Ollama model:".dimmed(), model.cyan(), models.len());
+        return Ok(());
+    }
+    
+    println!("{} {} hasn't been pulled into Ollama", "Warning:".yellow(), model.cyan());
+    print!("{}", format!("Pull {} now? [y/N] ", model).yellow());
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    if !matches!(answer.trim().to_lowercase().as_str(), "y" | "yes") {
+        println!("{} ollama pull {}", "Pull it later with:".dimmed(), model);
+        return Ok(());
+    }
+    
+    // Progress updates overwrite each other on one line
+    let result = crate::llm::pull_ollama_model(&model, |status| {
+        print!("\r\x1b[2K{}", status.dimmed());
+        let _ = io::stdout().flush();
+    }).await;
+    println!();
+    match result {
+        Ok(()) => println!("{} {}", "Pulled".green(), model),
+        Err(e) => eprintln!("Error pulling {}: {}", model, e),
+    }
+    Ok(())
+}
+
+/// List locally pulled Ollama models and optionally switch to one: `/models`
+async fn handle_ollama_models(model_config: &mut ModelConfig) -> Result<()> {
+    let models = match crate::llm::list_ollama_models().await {
+        Ok(models) => models,
+        Err(e) => {
+            eprintln!("Error fetching Ollama models: {}", e);
+            return Ok(());
+        }
+    };
+    if models.is_empty() {
+        println!("No Ollama models pulled yet. Get one with: ollama pull <model>");
+        return Ok(());
+    }
+    
+    let current = match model_config.provider {
+        LlmProvider::Ollama => Some(model_config.get_model_name()),
+        _ => None,
+    };
+    println!("{} ({})", "Ollama models".bright_cyan().bold(), models.len());
+    for (i, model) in models.iter().enumerate() {
+        let marker = if current.as_deref().is_some_and(|name| crate::llm::ollama_has_model(std::slice::from_ref(model), name)) { "*" } else { " " };
+        let details = format!("{:.1} GB {}", model.size as f64 / 1e9, model.parameter_size.as_deref().unwrap_or_default());
+        println!("{}{:>3}. {} {}", marker, i + 1, model.name.cyan(), details.dimmed());
+    }
+    
+    print!("{}", "Select a model number (Enter to keep the current model): ".yellow());
+    io::stdout().flush()?;
+    let mut choice = String::new();
+    io::stdin().read_line(&mut choice)?;
+    
+    if let Ok(n) = choice.trim().parse::<usize>() {
+        match models.get(n.wrapping_sub(1)) {
+            Some(model) => {
+                model_config.provider = LlmProvider::Ollama;
+                model_config.model_name = Some(model.name.clone());
+                model_config.context_window = None;
+                println!("{} {}", "Model set to:".green(), model_config.display_name());
+            }
+            None => println!("{}", "Invalid selection".red()),
+        }
+    }
+    
+    Ok(())
+}
+
+/// Run a task through the agent manager: `/agent run [@AgentName] <task>`
+async fn handle_agent_run(
+    arg: &str,
+    context_manager: &ContextManager,
+    model_config: &ModelConfig,
+    agent_manager: &mut Option<AgentManager>,
+    issue: Option<IssueLink>,
+) -> Result<()> {
+    if arg.is_empty() {
+        println!("{} /agent run [@AgentName] <task>", "Usage:".yellow());
+        return Ok(());
+    }
+    
+    // An explicit @AgentName skips routing
+    let (forced_agent, description) = match arg.strip_prefix('@') {
+        Some(rest) => {
+            let mut split = rest.splitn(2, char::is_whitespace);
+            let name = split.next().unwrap_or("").to_string();
+            (Some(name), split.next().unwrap_or("").trim().to_string())
+        }
+        None => (None, arg.to_string()),
+    };
+    if description.is_empty() {
+        println!("{} /agent run [@AgentName] <task>", "Usage:".yellow());
+        return Ok(());
+    }
+    
+    let manager = ensure_agent_manager(agent_manager, context_manager, model_config).await?;
+    
+    let mut report = ChangeReport::start(&description);
+    let task = AgentTask::new(description, TaskPriority::Normal);
+    
+    let outcome = match forced_agent {
+        Some(name) => manager.delegate_to(&name, task).await?,
+        None => {
+            let indicator = thinking::ThinkingIndicator::new("Routing task...");
+            let decision = manager.route_task(&task).await;
+            indicator.finish();
+            
+            match decision {
+                RoutingDecision::Agent { name, confidence, reason } => {
+                    println!("{} {} ({:.2}) - {}", "Routing to".dimmed(), name.cyan(), confidence, reason.dimmed());
+                    manager.delegate_to(&name, task).await?
+                }
+                RoutingDecision::AskUser { candidates, reason } => {
+                    DelegationOutcome::NeedsUserChoice { task, candidates, reason }
+                }
+            }
+        }
+    };
+    
+    let outcome = match outcome {
+        DelegationOutcome::NeedsUserChoice { task, candidates, reason } => {
+            println!("{} {}", "Could not pick an agent confidently:".yellow(), reason);
+            for (i, name) in candidates.iter().enumerate() {
+                println!("  {}. {}", i + 1, name.cyan());
+            }
+            println!("{}", "Choose an agent [1] (q to cancel):".yellow());
+            
+            let mut choice = String::new();
+            io::stdin().read_line(&mut choice)?;
+            let choice = choice.trim();
+            if choice.eq_ignore_ascii_case("q") {
+                println!("{}", "Agent task cancelled".dimmed());
+                return Ok(());
+            }
+            let index = if choice.is_empty() { 0 } else { choice.parse::<usize>().unwrap_or(0).saturating_sub(1) };
+            let name = candidates.get(index).cloned()
+                .ok_or_else(|| anyhow::anyhow!("Invalid choice: {}", choice))?;
+            
+            manager.delegate_to(&name, task).await?
+        }
+        completed => completed,
+    };
+    
+    if let DelegationOutcome::Completed { agent, task } = outcome {
+        match &task.status {
+            TaskStatus::Completed(msg) => println!("{} [{}] {}", "Done:".green().bold(), agent, msg),
+            TaskStatus::Failed(err) => println!("{} [{}] {}", "Failed:".red().bold(), agent, err),
+            status => println!("{} [{}] {:?}", "Status:".yellow().bold(), agent, status),
+        }
+        println!("{} {} LLM call(s), ~{} tokens, {}s", "Usage:".dimmed(), task.usage.llm_calls, task.usage.tokens, task.usage.wall_time_secs);
+        println!("{} /agent log {}", "Transcript:".dimmed(), task.id);
+        
+        // A plan becomes a resumable session worked through one step at a time
+        if task.is_complete() && !task.subtasks.is_empty() {
+            let mut session = AgentSession::new(task, context_manager.file_paths().to_vec());
+            session.issue = issue;
+            session.save()?;
+            println!("{} {} step(s) planned", "Plan:".bright_blue().bold(), session.total_steps());
+            run_agent_session(manager, session).await?;
+        } else {
+            report.finish();
+            print_change_report(&report);
+        }
+    }
+    
+    Ok(())
+}
+
+/// `/issue <url|id>`: put a GitHub issue in context and have the planner turn it into a
+/// session, linked back to the issue
+async fn handle_issue(
+    spec: &str,
+    context_manager: &mut ContextManager,
+    model_config: &ModelConfig,
+    agent_manager: &mut Option<AgentManager>,
+) -> Result<()> {
+    if spec.is_empty() {
+        println!("{} /issue <url|owner/repo#number|number>", "Usage:".yellow());
+        return Ok(());
+    }
+    let reference = IssueRef::parse(spec, issue::origin_remote().as_deref())?;
+    let indicator = thinking::ThinkingIndicator::new(&format!("Fetching {}...", reference));
+    let fetched = issue::fetch(&reference).await;
+    indicator.finish();
+    let fetched = fetched?;
+    
+    context_manager.add_snippet_from(fetched.to_context(&reference), Origin::Bridge);
+    println!("{} {} {}", "Issue:".bright_blue().bold(), reference, fetched.title);
+    let task = format!(
+        "@PlanningAgent Plan the implementation of GitHub issue {}: {}. The issue and its comments are in context.",
+        reference, fetched.title
+    );
+    handle_agent_run(&task, context_manager, model_config, agent_manager, Some(IssueLink::new(&reference))).await
+}
+
+/// Resume a paused or interrupted session: `/agent resume [<task-id>]`
+async fn handle_agent_resume(
+    task_id: &str,
+    context_manager: &ContextManager,
+    model_config: &ModelConfig,
+    agent_manager: &mut Option<AgentManager>,
+) -> Result<()> {
+    if task_id.is_empty() {
+        let sessions = session::list_resumable_sessions()?;
+        if sessions.is_empty() {
+            println!("No resumable agent sessions");
+        } else {
+            println!("{}", "Resumable agent sessions:".bright_white().bold());
+            for (id, description, status) in sessions {
+                println!("  {} {} {}", id.cyan(), description, format!("({:?})", status).dimmed());
+            }
+        }
+        return Ok(());
+    }
+    
+    let mut session = AgentSession::load(task_id)?;
+    if session.is_finished() {
+        println!("Session {} has already finished", task_id);
+        return Ok(());
+    }
+    
+    let manager = ensure_agent_manager(agent_manager, context_manager, model_config).await?;
+    manager.restore_session_context(&session).await?;
+    
+    println!("{} {} (step {}/{})", "Resuming:".bright_blue().bold(), session.task.description, session.plan_position + 1, session.total_steps());
+    if !session.pending_diffs.is_empty() {
+        manager.review_pending_diffs(&mut session).await?;
+    }
+    
+    run_agent_session(manager, session).await
+}
+
+async fn ensure_agent_manager<'a>(
+    agent_manager: &'a mut Option<AgentManager>,
+    context_manager: &ContextManager,
+    model_config: &ModelConfig,
+) -> Result<&'a mut AgentManager> {
+    if agent_manager.is_none() {
+        *agent_manager = Some(AgentManager::new(context_manager.clone(), model_config.clone()).await?);
+    }
+    let manager = agent_manager.as_mut().expect("agent manager initialized above");
+    manager.sync_state(context_manager.clone(), model_config).await?;
+    Ok(manager)
+}
+
+/// Step through a session's plan, checkpointing after each step and offering to pause before the next
+async fn run_agent_session(manager: &mut AgentManager, mut session: AgentSession) -> Result<()> {
+    session.resume();
+    session.save()?;
+    let mut report = ChangeReport::start(&session.task.description);
+    
+    while let Some(step) = session.next_step() {
+        println!("\n{} {}/{}: {}", "Step".bright_blue().bold(), session.plan_position + 1, session.total_steps(), step.description);
+        print!("{} ", "Run this step? (y = run, p = pause):".bright_white());
+        io::stdout().flush()?;
+        
+        let choice = input::read_single_char()?.to_ascii_lowercase();
+        println!();
+        match choice {
+            'y' => match manager.run_session_step(&mut session).await? {
+                StepOutcome::Completed => {
+                    if let Some(note) = session.gathered_context.last() {
+                        println!("{}", note.dimmed());
+                    }
+                }
+                StepOutcome::BudgetExhausted(reason) => {
+                    println!("{} {}", "Budget exhausted:".yellow().bold(), reason);
+                    print!("{} ", "Extend the budget and continue? (y/n):".bright_white());
+                    io::stdout().flush()?;
+                    let extend = input::read_single_char()?.eq_ignore_ascii_case(&'y');
+                    println!();
+                    
+                    if !extend {
+                        println!("{} Resume with /agent resume {}", "Paused.".yellow().bold(), session.id());
+                        return Ok(());
+                    }
+                    let usage = session.task.usage.clone();
+                    session.task.budget.extend_exhausted(&usage);
+                    session.resume();
+                    session.save()?;
+                }
+            },
+            'p' => {
+                session.pause("Paused by user");
+                session.save()?;
+                println!("{} Resume with /agent resume {}", "Paused.".yellow().bold(), session.id());
+                return Ok(());
+            }
+            _ => println!("Please enter 'y' (run) or 'p' (pause)"),
+        }
+    }
+    
+    if !session.pending_diffs.is_empty() {
+        manager.review_pending_diffs(&mut session).await?;
+    }
+    println!("{} {}", "Session complete:".green().bold(), session.task.description);
+    if let Some(mut link) = session.issue.take() {
+        link.refresh().await;
+        println!("{} {}", "Issue:".bright_blue().bold(), link.summary());
+        session.issue = Some(link);
+        session.save()?;
+    }
+    report.finish();
+    print_change_report(&report);
+    Ok(())
+}
+
 fn display_command_result(result: CommandResult) {
     match result {
         CommandResult { success: true, output, .. } => {
@@ -132,24 +611,37 @@ async fn handle_ai_interaction(
     context_manager: &mut ContextManager,
     model_config: &ModelConfig,
 ) -> Result<()> {
-    let spinner = thinking::show_llm_thinking();
+    print_local_only_note(context_manager, model_config);
+    let routing = PromptsConfig::load().unwrap_or_default().llm.routing;
+    if let Some(note) = crate::llm::router::routing_note(model_config, input, &routing) {
+        println!("{} {}", "Routing:".cyan().bold(), note);
+    }
+    if model_config.native_tools {
+        return handle_tool_interaction(input, context_manager, model_config).await;
+    }
+    
+    // The spinner runs until the first streamed text arrives
+    let mut spinner = Some(thinking::show_llm_thinking());
     
     // Get the formatted context
     let context_string = context_manager.get_formatted_context();
     
-    let llm_response = crate::llm::ask_model_with_config(input, &context_string, model_config).await;
-    spinner.finish();
+    let mut printer = StreamingMarkdownPrinter::default();
+    let llm_response = crate::llm::ask_model_streaming(input, &context_string, model_config, |chunk| {
+        if let Some(spinner) = spinner.take() {
+            spinner.finish();
+        }
+        printer.push(chunk);
+    }).await;
+    if let Some(spinner) = spinner.take() {
+        spinner.finish();
+    }
+    printer.finish();
     
     match llm_response {
         Ok(response) => {
-            // Render the response using termimad
-            let _ = render_markdown(&response);
-            
-            // Handle S/R blocks
-            handle_sr_blocks(&response, context_manager).await?;
-            
-            // Handle command blocks
-            handle_command_blocks(&response, context_manager).await?;
+            store_exchange(input, &response);
+            handle_response(input, &response, &[], context_manager, model_config).await?;
         }
         Err(e) => {
             eprintln!("Error sending request to LLM: {}", e);
@@ -159,39 +651,394 @@ async fn handle_ai_interaction(
     Ok(())
 }
 
-async fn handle_sr_blocks(response: &str, context_manager: &ContextManager) -> Result<()> {
-    let sr_blocks = sr_parser::parse_sr_blocks(response)?;
-    if !sr_blocks.is_empty() {
-        match editor::confirm_and_apply_blocks(sr_blocks, response, context_manager).await {
-            Ok(()) => {
-                // S/R blocks processed successfully, the editor handles notifications
+/// `/changelog [<since>]`: have the model draft a CHANGELOG.md section, reviewed like any other edit
+async fn handle_changelog(since: &str, context_manager: &mut ContextManager, model_config: &ModelConfig) -> Result<()> {
+    let changes = match changelog::prepare(since, context_manager) {
+        Ok(changes) => changes,
+        Err(e) => {
+            println!("{} {}", "Changelog:".yellow(), e);
+            return Ok(());
+        }
+    };
+    println!("{} drafting from {}", "Changelog:".cyan().bold(), changes.summary());
+    handle_ai_interaction(&changes.request(), context_manager, model_config).await
+}
+
+/// `/compact`: replace old turns and idle context items with a model-written summary
+async fn handle_compact(context_manager: &mut ContextManager, model_config: &ModelConfig) -> Result<()> {
+    let Some(compaction) = Compaction::plan(context_manager) else {
+        println!("{} nothing old enough to compact yet", "Compact:".yellow());
+        return Ok(());
+    };
+    println!("{} summarizing {}", "Compact:".cyan().bold(), compaction.describe());
+    for (label, _) in &compaction.items {
+        println!("  - {}", label);
+    }
+    let spinner = thinking::show_llm_thinking();
+    let summary = compaction.summarize(model_config).await;
+    spinner.finish();
+    match summary {
+        Ok(summary) => println!("{}", compaction.apply(context_manager, summary).green()),
+        Err(e) => println!("{} {}", "Compact:".yellow(), e),
+    }
+    Ok(())
+}
+
+/// `/replay <turn> [--current]`: send an earlier request again and compare the replies
+async fn handle_replay(arg: &str, model_config: &ModelConfig) -> Result<()> {
+    let Some((turn, current)) = llm::replay::parse_args(arg) else {
+        println!("Usage: /replay [<turn>] [--current]");
+        return Ok(());
+    };
+    println!("{} sending request #{} again", "Replay:".cyan().bold(), turn);
+    let spinner = thinking::show_llm_thinking();
+    let replay = llm::replay::replay(turn, current.then_some(model_config)).await;
+    spinner.finish();
+    match replay {
+        Ok(replay) => {
+            println!("{}", "─".repeat(60).dimmed());
+            println!("{} {}", replay.model.bright_cyan().bold(), format!("(originally {})", replay.record.describe()).dimmed());
+            println!("{}", "─".repeat(60).dimmed());
+            let _ = render_markdown(&replay.response);
+            println!();
+            println!("{} {}", "Replay:".cyan().bold(), replay.comparison());
+            println!("{}", "Edits and commands in a replayed reply aren't applied.".dimmed());
+        }
+        Err(e) => println!("{} {}", "Replay:".yellow(), e),
+    }
+    Ok(())
+}
+
+/// `/init [--force]`: write KOTA.md from a survey of the repository
+async fn handle_init(arg: &str, model_config: &ModelConfig) -> Result<()> {
+    if !arg.is_empty() && arg != "--force" {
+        println!("Usage: /init [--force]");
+        return Ok(());
+    }
+    println!("{} surveying the repository for KOTA.md", "Init:".cyan().bold());
+    let spinner = thinking::show_llm_thinking();
+    let written = instructions::init(model_config, arg == "--force").await;
+    spinner.finish();
+    match written {
+        Ok(path) => println!("{} wrote {}; it's now part of every system prompt. Review and edit it, then commit it for your team", "Init:".green(), path),
+        Err(e) => println!("{} {}", "Init:".yellow(), e),
+    }
+    Ok(())
+}
+
+/// Ask on stdin what to do about possible secrets in a prompt for a cloud provider
+fn review_secrets(findings: &[SecretFinding]) -> ReviewDecision {
+    println!("\n{}", "Possible secrets in the prompt:".red().bold());
+    for finding in findings {
+        println!("  - {}", finding);
+    }
+    println!("{}", "[R]edact and send / [s]end anyway / [c]ancel (remembered for this session)".yellow());
+    
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer).is_err() {
+        return ReviewDecision::Cancel;
+    }
+    match answer.trim().to_lowercase().as_str() {
+        "s" | "send" => ReviewDecision::SendAnyway,
+        "c" | "cancel" | "q" => ReviewDecision::Cancel,
+        _ => ReviewDecision::Redact,
+    }
+}
+
+/// Show the context items most relevant to `query`, falling back to text matching when
+/// the embeddings API can't be reached
+async fn handle_context_search(query: &str, context_manager: &ContextManager) -> Result<()> {
+    let embeddings = PromptsConfig::load().unwrap_or_default().embeddings;
+    let items = match context_manager.relevant_items(&embeddings, query, CONTEXT_SEARCH_LIMIT).await {
+        Ok(items) => items,
+        Err(e) => {
+            eprintln!("Warning: Semantic search failed, using text search: {}", e);
+            let text_search = EmbeddingsConfig { enabled: false, ..embeddings };
+            context_manager.relevant_items(&text_search, query, CONTEXT_SEARCH_LIMIT).await?
+        }
+    };
+    context_manager.show_items(&items);
+    Ok(())
+}
+
+/// Ask the `[compare]` models the same question at once and print their answers one after another.
+/// Answers are only shown; blocks in them aren't applied.
+async fn handle_compare(prompt: &str, context_manager: &ContextManager) -> Result<()> {
+    if prompt.is_empty() {
+        println!("{}", "Usage: /compare <prompt>".yellow());
+        return Ok(());
+    }
+    let targets = match compare::compare_targets(&PromptsConfig::load().unwrap_or_default().compare) {
+        Ok(targets) => targets,
+        Err(e) => {
+            eprintln!("{} {}", "Error:".red(), e);
+            return Ok(());
+        }
+    };
+    
+    let names: Vec<String> = targets.iter().map(|target| target.display_name()).collect();
+    println!("{} {}", "Comparing:".bright_yellow().bold(), names.join(", "));
+    let spinner = thinking::show_llm_thinking();
+    let answers = compare::compare(prompt, &context_manager.get_formatted_context(), &targets).await;
+    spinner.finish();
+    
+    for answer in answers {
+        println!();
+        println!("{}", "─".repeat(60).dimmed());
+        println!("{} {}", answer.model.bright_cyan().bold(), format!("({:.1}s)", answer.elapsed.as_secs_f64()).dimmed());
+        println!("{}", "─".repeat(60).dimmed());
+        match answer.result {
+            Ok(text) => {
+                let _ = render_markdown(&text);
+            }
+            Err(e) => eprintln!("{} {}", "Error:".red(), e),
+        }
+    }
+    println!();
+    println!("{}", "Edits and commands in compared answers aren't applied; ask again with /provider and /model set to the one you prefer.".dimmed());
+    Ok(())
+}
+
+/// Requests with local-only files in context are sent to Ollama whatever the provider;
+/// say so before sending
+fn print_local_only_note(context_manager: &ContextManager, model_config: &ModelConfig) {
+    if let LlmProvider::Ollama = model_config.provider {
+        return;
+    }
+    let local_only = PromptsConfig::load().unwrap_or_default().local_only;
+    if let Some(reason) = crate::llm::router::local_only_reason(&local_only, context_manager.file_paths()) {
+        println!("{} {}; using Ollama instead of {}", "Local only:".yellow().bold(), reason, model_config.display_name());
+    }
+}
+
+/// Native tool calling: the response arrives in one piece, then each tool call goes through
+/// the same approval prompts as S/R and command blocks
+async fn handle_tool_interaction(
+    input: &str,
+    context_manager: &mut ContextManager,
+    model_config: &ModelConfig,
+) -> Result<()> {
+    let spinner = thinking::show_llm_thinking();
+    let context_string = context_manager.get_formatted_context();
+    let llm_response = crate::llm::ask_model_with_tools(input, &context_string, model_config).await;
+    spinner.finish();
+    
+    let response = match llm_response {
+        Ok(response) => response,
+        Err(e) => {
+            eprintln!("Error sending request to LLM: {}", e);
+            return Ok(());
+        }
+    };
+    
+    if !response.text.trim().is_empty() {
+        let _ = render_markdown(&response.text);
+    }
+    store_exchange(input, &response.to_text_with_blocks());
+    handle_response(input, &response.text, &response.tool_calls, context_manager, model_config).await
+}
+
+/// Act on a response through the shared post-processing pipeline: context requests
+/// first, then edits, then commands, each behind its usual approval prompt
+async fn handle_response(
+    input: &str,
+    text: &str,
+    tool_calls: &[ToolCall],
+    context_manager: &mut ContextManager,
+    model_config: &ModelConfig,
+) -> Result<()> {
+    let processed = response_pipeline::process_response_as(model_config.edit_format(), input, text, tool_calls);
+    for problem in &processed.problems {
+        eprintln!("Warning: {}", problem);
+    }
+    for warning in &processed.warnings {
+        eprintln!("{} {}", "Filter:".yellow(), warning);
+    }
+    
+    if !processed.citations.is_empty() {
+        if let Err(e) = MemoryManager::default().store_citations(&processed.citations) {
+            eprintln!("Warning: Failed to store citations: {}", e);
+        }
+    }
+    
+    if let Some(items) = processed.todos {
+        let mut todos = TodoList::load();
+        match todos.replace(items) {
+            Ok(()) => print_todos(&todos),
+            Err(e) => eprintln!("Warning: Failed to save the todo list: {}", e),
+        }
+    }
+    
+    for path in processed.reads {
+        print!("{} {} {}", "The AI wants to read".yellow(), path.bright_cyan(), "- add it to context? [y/N] ".yellow());
+        io::stdout().flush()?;
+        let mut answer = String::new();
+        io::stdin().read_line(&mut answer)?;
+        if matches!(answer.trim().to_lowercase().as_str(), "y" | "yes") {
+            match context_manager.add_file_from(&path, Origin::Retrieved) {
+                Ok(_) => println!("{} {}", "Added to context:".green(), path),
+                Err(e) => eprintln!("Error adding {} to context: {}", path, e),
+            }
+        }
+    }
+    
+    if processed.edits.is_empty() && processed.file_operations.is_empty() && processed.commands.is_empty() {
+        return Ok(());
+    }
+    let mut report = ChangeReport::start(input);
+    
+    if !processed.edits.is_empty() || !processed.file_operations.is_empty() {
+        match editor::confirm_and_apply(processed.edits, processed.file_operations, input, context_manager, Some(model_config)).await {
+            Ok((applied, operations)) => {
+                report.record_edits(&applied);
+                report.record_file_operations(&operations);
+                context_manager.record_edits(&applied);
+                context_manager.record_file_operations(&operations);
+                if let Some(note) = lint::take_note() {
+                    context_manager.add_snippet_from(note, Origin::Command);
+                }
+                let mut files: Vec<String> = applied.iter().map(|block| block.file_path.clone()).collect();
+                files.extend(operations.iter().map(|operation| operation.path().to_string()));
+                print_checked_off(TodoList::load().complete_edits(&files));
             }
             Err(e) => eprintln!("Error applying edits: {}", e),
         }
     }
+    
+    run_suggested_commands(&processed.commands, context_manager, model_config, &mut report).await?;
+    
+    report.finish();
+    if report.is_multi_step() {
+        print_change_report(&report);
+    }
     Ok(())
 }
 
-async fn handle_command_blocks(response: &str, context_manager: &mut ContextManager) -> Result<()> {
-    let command_blocks = cmd_parser::parse_command_blocks(response)?;
-    if !command_blocks.is_empty() {
+/// Show a "what changed" summary after a multi-step operation and keep it in memory
+fn print_change_report(report: &ChangeReport) {
+    println!("\n{}", "─".repeat(60).dimmed());
+    for (i, line) in report.render().lines().enumerate() {
+        if i == 0 {
+            println!("{}", line.bright_white().bold());
+        } else if line.ends_with("FAILED") || line.starts_with("  ✗") {
+            println!("{}", line.red());
+        } else {
+            println!("{}", line);
+        }
+    }
+    report.store();
+}
+
+fn print_todos(todos: &TodoList) {
+    println!("\n{} ({} open)", "Todo:".bright_yellow().bold(), todos.remaining());
+    for item in todos.items() {
+        let mark = if item.done { "✓".green() } else { "○".dimmed() };
+        println!("  {} {}", mark, item.text);
+    }
+}
+
+fn print_checked_off(completed: Result<Vec<String>>) {
+    match completed {
+        Ok(items) => {
+            for item in items {
+                println!("{} {}", "Todo done:".green(), item);
+            }
+        }
+        Err(e) => eprintln!("Warning: Failed to update the todo list: {}", e),
+    }
+}
+
+/// Log an exchange so it can be found with /history search
+fn store_exchange(input: &str, response: &str) {
+    let memory = MemoryManager::default();
+    for entry in [format!("User: {}", input), format!("KOTA: {}", response.chars().take(500).collect::<String>())] {
+        if let Err(e) = memory.store_conversation_summary(&entry) {
+            eprintln!("Warning: Failed to store conversation in memory: {}", e);
+        }
+    }
+}
+
+/// Renders a streamed response one complete line at a time. Lines inside code
+/// fences are printed as-is, since a fence can't be rendered until it closes.
+#[derive(Default)]
+struct StreamingMarkdownPrinter {
+    pending: String,
+    in_code_block: bool,
+}
+
+impl StreamingMarkdownPrinter {
+    fn push(&mut self, chunk: &str) {
+        self.pending.push_str(chunk);
+        while let Some(newline) = self.pending.find('\n') {
+            let line: String = self.pending.drain(..=newline).collect();
+            self.print_line(line.trim_end_matches('\n'));
+        }
+    }
+    
+    fn finish(&mut self) {
+        if !self.pending.is_empty() {
+            let line = std::mem::take(&mut self.pending);
+            self.print_line(&line);
+        }
+    }
+    
+    fn print_line(&mut self, line: &str) {
+        if line.trim_start().starts_with("```") {
+            self.in_code_block = !self.in_code_block;
+            println!("{}", line.dimmed());
+        } else if self.in_code_block || line.trim().is_empty() {
+            println!("{}", line);
+        } else {
+            let _ = render_markdown(line);
+        }
+        let _ = io::stdout().flush();
+    }
+}
+
+async fn run_suggested_commands(commands: &[String], context_manager: &mut ContextManager, model_config: &ModelConfig, report: &mut ChangeReport) -> Result<()> {
+    if !commands.is_empty() {
+        let mut trust_store = TrustStore::load();
+        let untrusted = commands.iter().filter(|command| !trust_store.allows_command(command)).count();
+        
         println!("\n{}", "The AI suggested the following commands:".yellow().bold());
-        for (i, cmd_block) in command_blocks.iter().enumerate() {
-            println!("{}. {}", i + 1, cmd_block.command.bright_cyan());
+        for (i, command) in commands.iter().enumerate() {
+            let trusted = if trust_store.allows_command(command) { " (always allowed in this project)".dimmed() } else { "".normal() };
+            println!("{}. {}{}", i + 1, command.bright_cyan(), trusted);
         }
         
-        println!("\n{}", "Do you want to execute these commands? [y/N/a(ll)/q(uit)]".yellow());
+        // Nothing to ask when every command was already trusted
+        let user_response = if untrusted == 0 {
+            "y".to_string()
+        } else {
+            loop {
+                println!("\n{}", "Do you want to execute these commands? [y/N/a(ll)/e(xplain)/t(rust, always allow in this project)/q(uit)]".yellow());
+                let mut user_response = String::new();
+                io::stdin().read_line(&mut user_response)?;
+                let user_response = user_response.trim().to_lowercase();
+                if user_response != "e" && user_response != "explain" {
+                    break user_response;
+                }
+                print_explanations(commands, model_config).await;
+            }
+        };
         
-        let mut user_response = String::new();
-        io::stdin().read_line(&mut user_response)?;
-        let user_response = user_response.trim().to_lowercase();
+        if user_response == "t" || user_response == "trust" {
+            for command in commands {
+                if let Err(e) = trust_store.allow_command(command) {
+                    eprintln!("Warning: Failed to save trusted command: {}", e);
+                }
+            }
+            println!("{} {}", "Always allowed in this project:".dimmed(), approvals::TRUST_FILE.dimmed());
+        }
         
-        if user_response == "y" || user_response == "yes" || user_response == "a" || user_response == "all" {
-            for cmd_block in &command_blocks {
-                println!("\n{} {}", "Executing:".green().bold(), cmd_block.command);
-                let output = execute_shell_command(&cmd_block.command).await;
+        if matches!(user_response.as_str(), "y" | "yes" | "a" | "all" | "t" | "trust") {
+            for command in commands {
+                println!("\n{} {}", "Executing:".green().bold(), command);
+                let output = execute_shell_command(command).await;
                 match output {
-                    Ok((stdout, stderr, success)) => {
+                    Ok(outcome) => {
+                        let ExecOutcome { stdout, stderr, success, limit, .. } = outcome;
+                        report.record_command(command, success && limit.is_none());
                         if !stdout.trim().is_empty() {
                             println!("--- stdout ---\n{}\n--- end stdout ---", stdout);
                         }
@@ -200,39 +1047,64 @@ async fn handle_command_blocks(response: &str, context_manager: &mut ContextMana
                         }
                         // Add command output to context for potential follow-up
                         if !stdout.trim().is_empty() {
-                            context_manager.add_snippet(format!("Output of command '{}': \n{}", cmd_block.command, stdout));
+                            context_manager.add_snippet_from(format!("Output of command '{}': \n{}", command, stdout), Origin::Command);
                         }
                         if !stderr.trim().is_empty() {
-                            context_manager.add_snippet(format!("Error output of command '{}': \n{}", cmd_block.command, stderr));
+                            context_manager.add_snippet_from(format!("Error output of command '{}': \n{}", command, stderr), Origin::Command);
                         }
-                        if !success {
-                            eprintln!("Command '{}' failed", cmd_block.command);
+                        if let Some(limit) = limit {
+                            eprintln!("{} Command '{}' {}", "Limit:".red().bold(), command, limit);
+                            context_manager.add_snippet_from(format!("Command '{}' {}", command, limit), Origin::Command);
+                        } else if !success {
+                            eprintln!("Command '{}' failed", command);
+                        } else {
+                            print_checked_off(TodoList::load().complete_command(command));
                         }
                     }
                     Err(e) => {
                         eprintln!("Error executing command: {}", e);
+                        report.record_command(command, false);
                         // Add error to context as well
-                        context_manager.add_snippet(format!("Error executing command '{}': {}", cmd_block.command, e));
+                        context_manager.add_snippet_from(format!("Error executing command '{}': {}", command, e), Origin::Command);
                     }
                 }
             }
         } else if user_response == "q" || user_response == "quit" {
+            trace::finish();
             std::process::exit(0);
         }
     }
     Ok(())
 }
 
-async fn execute_shell_command(command: &str) -> Result<(String, String, bool)> {
-    let output = tokio::process::Command::new("sh")
-        .arg("-c")
-        .arg(command)
-        .output()
-        .await?;
+/// What each command does and its risks, from the `[explain]` model or the cache
+async fn print_explanations(commands: &[String], model_config: &ModelConfig) {
+    for (i, command) in commands.iter().enumerate() {
+        let spinner = thinking::show_llm_thinking();
+        let explanation = explain::explain(command, model_config).await;
+        spinner.finish();
+        println!("\n{}. {}", i + 1, command.bright_cyan());
+        match explanation {
+            Ok(explanation) => {
+                for line in explanation.lines() {
+                    println!("   {}", line);
+                }
+            }
+            Err(e) => eprintln!("   Couldn't explain it: {}", e),
+        }
+    }
+}
+
+/// Run a suggested command under the `[sandbox]` limits without blocking the runtime
+async fn execute_shell_command(command: &str) -> Result<ExecOutcome> {
+    let owned = command.to_string();
+    let outcome = tokio::task::spawn_blocking(move || SecureExecutor::from_config().run(&owned)).await??;
     
-    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
-    let stderr = String::from_utf8_lossy(&output.stderr).to_string();
-    let success = output.status.success();
+    let mut output = format!("{}{}", outcome.stdout, outcome.stderr);
+    if let Some(limit) = outcome.limit {
+        output.push_str(&format!("\nCommand {}", limit));
+    }
+    history::record_command(command, &output, outcome.success);
     
-    Ok((stdout, stderr, success))
+    Ok(outcome)
 }
\ No newline at end of file
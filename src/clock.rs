@@ -0,0 +1,198 @@
+use std::sync::OnceLock;
+
+use chrono::{DateTime, Duration, FixedOffset, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc};
+
+use crate::prompts::{PromptsConfig, TimeConfig};
+
+// How far past a skipped midnight to look for the first time that exists; DST gaps are at most an hour or two
+const MAX_GAP_MINUTES: i64 = 180;
+
+/// The user's timezone: the system's, or a fixed UTC offset set in `[time] timezone`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Zone {
+    Local,
+    Fixed(FixedOffset),
+}
+
+/// Where all user-facing times come from: the current time in the user's zone, formatted
+/// for their locale. Stored timestamps stay in UTC; only what's shown or grouped by day
+/// goes through here.
+#[derive(Debug, Clone)]
+pub struct Clock {
+    zone: Zone,
+    date_format: String,
+    time_format: String,
+}
+
+// Read once per session, like the provider prompts
+static CLOCK: OnceLock<Clock> = OnceLock::new();
+
+/// The session's clock, from `[time]` in prompts.toml
+pub fn clock() -> &'static Clock {
+    CLOCK.get_or_init(|| Clock::from_config(&PromptsConfig::load().unwrap_or_default().time))
+}
+
+impl Clock {
+    pub fn from_config(config: &TimeConfig) -> Self {
+        let zone = match config.timezone.as_deref().map(str::trim) {
+            None | Some("") => Zone::Local,
+            Some(spec) => parse_zone(spec).unwrap_or_else(|| {
+                eprintln!("Warning: Unknown timezone '{}' in [time]; use \"local\", \"UTC\" or an offset like \"+05:30\"", spec);
+                Zone::Local
+            }),
+        };
+        let (date_format, time_format) = locale_formats(&system_locale());
+        Self {
+            zone,
+            date_format: config.date_format.clone().unwrap_or_else(|| date_format.to_string()),
+            time_format: config.time_format.clone().unwrap_or_else(|| time_format.to_string()),
+        }
+    }
+
+    pub fn now(&self) -> DateTime<FixedOffset> {
+        self.to_user(&Utc::now())
+    }
+
+    pub fn to_user<Tz: TimeZone>(&self, at: &DateTime<Tz>) -> DateTime<FixedOffset> {
+        match self.zone {
+            Zone::Local => at.with_timezone(&Local).fixed_offset(),
+            Zone::Fixed(offset) => at.with_timezone(&offset),
+        }
+    }
+
+    pub fn date<Tz: TimeZone>(&self, at: &DateTime<Tz>) -> String {
+        self.to_user(at).format(&self.date_format).to_string()
+    }
+
+    pub fn time<Tz: TimeZone>(&self, at: &DateTime<Tz>) -> String {
+        self.to_user(at).format(&self.time_format).to_string()
+    }
+
+    pub fn date_time<Tz: TimeZone>(&self, at: &DateTime<Tz>) -> String {
+        format!("{} {}", self.date(at), self.time(at))
+    }
+
+    /// "UTC+02:00", for prompts and status lines
+    pub fn zone_label(&self) -> String {
+        format!("UTC{}", self.now().format("%:z"))
+    }
+
+    /// The instant the user's `date` began. When a DST change skips midnight, the day
+    /// starts at the first local time that exists; when midnight happens twice, the first.
+    pub fn start_of_day(&self, date: NaiveDate) -> DateTime<Utc> {
+        let midnight = date.and_time(NaiveTime::MIN);
+        let resolved = match self.zone {
+            Zone::Local => first_existing(midnight, |naive| naive.and_local_timezone(Local).earliest().map(|at| at.with_timezone(&Utc))),
+            Zone::Fixed(offset) => first_existing(midnight, |naive| naive.and_local_timezone(offset).earliest().map(|at| at.with_timezone(&Utc))),
+        };
+        // Unreachable in practice; the current offset is close enough
+        resolved.unwrap_or_else(|| midnight.and_utc() - Duration::seconds(self.now().offset().local_minus_utc() as i64))
+    }
+}
+
+fn first_existing(naive: NaiveDateTime, resolve: impl Fn(NaiveDateTime) -> Option<DateTime<Utc>>) -> Option<DateTime<Utc>> {
+    (0..=MAX_GAP_MINUTES).find_map(|minutes| resolve(naive + Duration::minutes(minutes)))
+}
+
+/// "local", "UTC", or an offset like "+05:30", "-0800" or "UTC+2"
+fn parse_zone(spec: &str) -> Option<Zone> {
+    let lower = spec.to_lowercase();
+    if lower == "local" {
+        return Some(Zone::Local);
+    }
+    let offset = lower.strip_prefix("utc").or_else(|| lower.strip_prefix("gmt")).unwrap_or(&lower);
+    if offset.is_empty() || offset == "z" {
+        return FixedOffset::east_opt(0).map(Zone::Fixed);
+    }
+
+    let (sign, digits) = match offset.as_bytes().first()? {
+        b'+' => (1, &offset[1..]),
+        b'-' => (-1, &offset[1..]),
+        _ => return None,
+    };
+    let (hours, minutes) = match digits.split_once(':') {
+        Some((hours, minutes)) => (hours, minutes),
+        None if digits.len() > 2 => digits.split_at(digits.len() - 2),
+        None => (digits, "0"),
+    };
+    let (hours, minutes): (i32, i32) = (hours.parse().ok()?, minutes.parse().ok()?);
+    if hours > 14 || minutes >= 60 {
+        return None;
+    }
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60)).map(Zone::Fixed)
+}
+
+/// The locale dates and times are shown in, from the usual environment variables
+fn system_locale() -> String {
+    ["LC_ALL", "LC_TIME", "LANG"]
+        .iter()
+        .filter_map(|name| std::env::var(name).ok())
+        .find(|value| !value.is_empty())
+        .unwrap_or_default()
+}
+
+/// (date, time) strftime formats for a locale like "de_DE.UTF-8". Unknown and POSIX
+/// locales get ISO 8601.
+fn locale_formats(locale: &str) -> (&'static str, &'static str) {
+    let locale = locale.split(['.', '@']).next().unwrap_or("").replace('-', "_");
+    let language = locale.split('_').next().unwrap_or("");
+    match (language, locale.as_str()) {
+        (_, "en_US") => ("%m/%d/%Y", "%-I:%M:%S %p"),
+        (_, "en_CA") | ("sv", _) | ("lt", _) => ("%Y-%m-%d", "%H:%M:%S"),
+        ("ja", _) | ("zh", _) | ("ko", _) | ("hu", _) => ("%Y/%m/%d", "%H:%M:%S"),
+        ("de", _) | ("ru", _) | ("pl", _) | ("cs", _) | ("fi", _) | ("nb", _) | ("da", _) | ("tr", _) | ("uk", _) => ("%d.%m.%Y", "%H:%M:%S"),
+        ("nl", _) => ("%d-%m-%Y", "%H:%M:%S"),
+        ("en", _) | ("fr", _) | ("es", _) | ("it", _) | ("pt", _) | ("el", _) => ("%d/%m/%Y", "%H:%M:%S"),
+        _ => ("%Y-%m-%d", "%H:%M:%S"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixed(offset: &str) -> Clock {
+        Clock::from_config(&TimeConfig {
+            timezone: Some(offset.to_string()),
+            date_format: Some("%Y-%m-%d".to_string()),
+            time_format: Some("%H:%M".to_string()),
+        })
+    }
+
+    #[test]
+    fn test_parse_zone() {
+        assert_eq!(parse_zone("local"), Some(Zone::Local));
+        assert_eq!(parse_zone("UTC"), FixedOffset::east_opt(0).map(Zone::Fixed));
+        assert_eq!(parse_zone("+05:30"), FixedOffset::east_opt(19_800).map(Zone::Fixed));
+        assert_eq!(parse_zone("-0800"), FixedOffset::west_opt(28_800).map(Zone::Fixed));
+        assert_eq!(parse_zone("UTC+2"), FixedOffset::east_opt(7_200).map(Zone::Fixed));
+        assert_eq!(parse_zone("Europe/Berlin"), None);
+        assert_eq!(parse_zone("+25:00"), None);
+    }
+
+    #[test]
+    fn test_locale_formats() {
+        assert_eq!(locale_formats("en_US.UTF-8").0, "%m/%d/%Y");
+        assert_eq!(locale_formats("de_DE.UTF-8").0, "%d.%m.%Y");
+        assert_eq!(locale_formats("en_GB").0, "%d/%m/%Y");
+        assert_eq!(locale_formats("C.UTF-8").0, "%Y-%m-%d");
+        assert_eq!(locale_formats("").1, "%H:%M:%S");
+    }
+
+    #[test]
+    fn test_fixed_zone_days_and_formats() {
+        let clock = fixed("+05:30");
+        let at = Utc.with_ymd_and_hms(2026, 3, 1, 20, 0, 0).unwrap();
+        assert_eq!(clock.date_time(&at), "2026-03-02 01:30");
+        assert_eq!(clock.start_of_day(NaiveDate::from_ymd_opt(2026, 3, 2).unwrap()), Utc.with_ymd_and_hms(2026, 3, 1, 18, 30, 0).unwrap());
+    }
+
+    #[test]
+    fn test_skipped_midnight_starts_at_first_existing_time() {
+        // A zone that springs forward from 00:00 to 01:00
+        let midnight = NaiveDate::from_ymd_opt(2026, 3, 29).unwrap().and_time(NaiveTime::MIN);
+        let gap_end = midnight + Duration::hours(1);
+        let start = first_existing(midnight, |naive| (naive >= gap_end).then(|| naive.and_utc()));
+        assert_eq!(start, Some(gap_end.and_utc()));
+    }
+}
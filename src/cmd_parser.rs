@@ -2,12 +2,15 @@ use std::process::Command;
 use regex::Regex;
 use anyhow::Result;
 
+use crate::trace::{self, SpanExt};
+
 #[derive(Debug, Clone)]
 pub struct CommandBlock {
     pub command: String,
 }
 
 pub fn parse_command_blocks(response: &str) -> Result<Vec<CommandBlock>> {
+    let _span = trace::span("parse.command_blocks").with("bytes", response.len());
     let mut blocks = Vec::new();
     let lines: Vec<&str> = response.lines().collect();
     let mut i = 0;
@@ -2,8 +2,30 @@ use std::process::Command;
 use anyhow::Result;
 use colored::*;
 
-use crate::context::ContextManager;
-use crate::llm::{LlmProvider, ModelConfig};
+use crate::agents::maintenance_agent;
+use crate::agents::session::AgentSession;
+use crate::memory::MemoryManager;
+use crate::agents::transcript;
+use crate::context::{documents, related, ContextManager, Origin};
+use crate::file_select;
+use crate::undo;
+use crate::llm::{self, LlmProvider, ModelConfig};
+use crate::llm::cache::{ResponseCache, CACHE_DIR};
+use crate::llm::request_log;
+use crate::llm::conversation;
+use crate::llm::router;
+use crate::prompts::{self, BudgetAction, PromptsConfig};
+use crate::clock::clock;
+use crate::instructions;
+use crate::focus;
+use crate::style;
+use crate::usage;
+use crate::history;
+use crate::db::Database;
+use crate::approvals::{self, TrustStore, TRUST_FILE};
+use crate::sandbox::SecureExecutor;
+use crate::todos::TodoList;
+use crate::trace;
 
 /// Represents the result of executing a command
 #[derive(Debug, Clone)]
@@ -55,7 +77,13 @@ impl CommandRegistry {
         registry.register(Box::new(AddFileCommand));
         registry.register(Box::new(AddSnippetCommand));
         registry.register(Box::new(ShowContextCommand));
+        registry.register(Box::new(ContextCommand));
         registry.register(Box::new(ClearContextCommand));
+        registry.register(Box::new(DropFileCommand));
+        registry.register(Box::new(PinCommand));
+        registry.register(Box::new(UnpinCommand));
+        registry.register(Box::new(RehydrateCommand));
+        registry.register(Box::new(SensitiveCommand));
         registry.register(Box::new(RunCommand));
         registry.register(Box::new(RunAddCommand));
         registry.register(Box::new(GitAddCommand));
@@ -65,7 +93,35 @@ impl CommandRegistry {
         registry.register(Box::new(HelpCommand));
         registry.register(Box::new(ProviderCommand));
         registry.register(Box::new(ModelCommand));
+        registry.register(Box::new(ModelsCommand));
         registry.register(Box::new(VersionCommand));
+        registry.register(Box::new(AgentCommand));
+        registry.register(Box::new(BriefCommand));
+        registry.register(Box::new(BriefingCommand));
+        registry.register(Box::new(FocusCommand));
+        registry.register(Box::new(UsageCommand));
+        registry.register(Box::new(CacheCommand));
+        registry.register(Box::new(HistoryCommand));
+        registry.register(Box::new(CompareCommand));
+        registry.register(Box::new(ToolsCommand));
+        registry.register(Box::new(VerifyCommand));
+        registry.register(Box::new(TrustCommand));
+        registry.register(Box::new(TempCommand));
+        registry.register(Box::new(TopPCommand));
+        registry.register(Box::new(MaxTokensCommand));
+        registry.register(Box::new(SeedCommand));
+        registry.register(Box::new(UndoCommand));
+        registry.register(Box::new(RedoCommand));
+        registry.register(Box::new(ReplayCommand));
+        registry.register(Box::new(PromptCommand));
+        registry.register(Box::new(BudgetCommand));
+        registry.register(Box::new(TodoCommand));
+        registry.register(Box::new(TranscriptCommand));
+        registry.register(Box::new(StyleCommand));
+        registry.register(Box::new(ChangelogCommand));
+        registry.register(Box::new(IssueCommand));
+        registry.register(Box::new(CompactCommand));
+        registry.register(Box::new(InitCommand));
         
         registry
     }
@@ -91,11 +147,12 @@ impl CommandRegistry {
         
         // Group commands by category
         let categories = vec![
-            ("Context Management", vec!["/add_file", "/add_snippet", "/show_context", "/clear_context"]),
+            ("Context Management", vec!["/add_file", "/add_snippet", "/show_context", "/context", "/clear_context", "/rehydrate", "/compact", "/sensitive", "/todo"]),
             ("Command Execution", vec!["/run", "/run_add"]),
-            ("Git Operations", vec!["/git_add", "/git_commit", "/git_status", "/git_diff"]),
-            ("Configuration", vec!["/provider", "/model"]),
-            ("General", vec!["/help", "/version", "/quit"]),
+            ("Git Operations", vec!["/git_add", "/git_commit", "/git_status", "/git_diff", "/undo", "/redo"]),
+            ("Configuration", vec!["/provider", "/model", "/models", "/compare", "/tools", "/verify", "/temp", "/top_p", "/max_tokens", "/seed", "/replay", "/prompt", "/init", "/budget", "/cache", "/trust", "/transcript"]),
+            ("Agents", vec!["/agent", "/brief", "/briefing"]),
+            ("General", vec!["/focus", "/history", "/usage", "/help", "/version", "/quit"]),
         ];
         
         for (category, commands) in categories {
@@ -121,6 +178,37 @@ impl CommandRegistry {
     }
 }
 
+/// Run a `/run` style shell command under the sandbox limits and store it in the searchable history
+fn run_recorded_shell_command(command: &str) -> Result<CommandResult> {
+    let outcome = SecureExecutor::from_config().run(command)?;
+    let result = if let Some(limit) = outcome.limit {
+        CommandResult::error(format!("Command {}", limit))
+    } else if outcome.success {
+        CommandResult::success(format_command_output(&outcome.stdout, &outcome.stderr))
+    } else {
+        CommandResult::error(format!("Command failed with status: {}", outcome.status))
+    };
+    let output = result.error.as_deref().unwrap_or(&result.output);
+    history::record_command(command, output, result.success);
+    Ok(result)
+}
+
+fn format_command_output(stdout: &str, stderr: &str) -> String {
+    let mut result_output = String::new();
+    
+    // Format stdout
+    if !stdout.trim().is_empty() {
+        result_output.push_str(&format!("--- stdout ---\n{}\n--- end stdout ---\n", stdout.trim()));
+    }
+    
+    // Format stderr
+    if !stderr.trim().is_empty() {
+        result_output.push_str(&format!("--- stderr ---\n{}\n--- end stderr ---\n", stderr.trim()));
+    }
+    
+    result_output
+}
+
 /// Helper function to execute shell commands with consistent output formatting
 pub fn execute_shell_command(command: &str, args: &[&str]) -> Result<CommandResult> {
     let mut cmd = Command::new(command);
@@ -129,20 +217,7 @@ pub fn execute_shell_command(command: &str, args: &[&str]) -> Result<CommandResu
     let output = cmd.output()
         .map_err(|e| anyhow::anyhow!("Failed to execute {}: {}", command, e))?;
     
-    let stdout_str = String::from_utf8_lossy(&output.stdout);
-    let stderr_str = String::from_utf8_lossy(&output.stderr);
-    
-    let mut result_output = String::new();
-    
-    // Format stdout
-    if !stdout_str.trim().is_empty() {
-        result_output.push_str(&format!("--- stdout ---\n{}\n--- end stdout ---\n", stdout_str.trim()));
-    }
-    
-    // Format stderr
-    if !stderr_str.trim().is_empty() {
-        result_output.push_str(&format!("--- stderr ---\n{}\n--- end stderr ---\n", stderr_str.trim()));
-    }
+    let result_output = format_command_output(&String::from_utf8_lossy(&output.stdout), &String::from_utf8_lossy(&output.stderr));
     
     if output.status.success() {
         Ok(CommandResult::success(result_output))
@@ -160,6 +235,7 @@ impl CommandHandler for QuitCommand {
     fn usage(&self) -> &str { "/quit" }
     fn description(&self) -> &str { "Exit KOTA" }
     fn execute(&self, _arg: &str, _context: &mut ContextManager, _model_config: &mut ModelConfig) -> Result<CommandResult> {
+        trace::finish();
         std::process::exit(0);
     }
 }
@@ -167,17 +243,74 @@ impl CommandHandler for QuitCommand {
 struct AddFileCommand;
 impl CommandHandler for AddFileCommand {
     fn name(&self) -> &str { "/add_file" }
-    fn usage(&self) -> &str { "/add_file <path>" }
-    fn description(&self) -> &str { "Add file contents to context" }
+    fn usage(&self) -> &str { "/add_file <path[:lines]|dir|glob> [--ro] [--truncate] [--yes] [--pages <range>]" }
+    fn description(&self) -> &str { "Add a file, some of its lines (src/big.rs:100-250), a directory or a glob like src/**/*.rs to context; --ro adds it for reference only, so edits to it are refused, and --truncate keeps the start and end of files over the size limit" }
     fn execute(&self, arg: &str, context: &mut ContextManager, _model_config: &mut ModelConfig) -> Result<CommandResult> {
-        if arg.is_empty() {
-            return Ok(CommandResult::error("Usage: /add_file <path_to_file>".to_string()));
+        let (arg, read_only) = file_select::split_read_only_option(arg);
+        let (arg, truncate) = file_select::split_flag(&arg, "--truncate");
+        let (arg, pages) = match documents::split_pages_option(&arg) {
+            Ok(split) => split,
+            Err(e) => return Ok(CommandResult::error(format!("Error: {}", e))),
+        };
+        let (pattern, confirmed) = match arg.strip_suffix("--yes") {
+            Some(pattern) => (pattern.trim(), true),
+            None => (arg.as_str(), false),
+        };
+        if pattern.is_empty() {
+            return Ok(CommandResult::error(format!("Usage: {}", self.usage())));
+        }
+        if let Some(pages) = pages {
+            return match context.add_document(pattern, Some(pages)) {
+                Ok(_) => Ok(CommandResult::success(format!("Added pages {} of {}", pages, pattern))),
+                Err(e) => Ok(CommandResult::error(format!("Error: {}", e))),
+            };
+        }
+        let (pattern, lines) = match file_select::split_line_range(pattern) {
+            Ok(split) => split,
+            Err(e) => return Ok(CommandResult::error(format!("Error: {}", e))),
+        };
+        let pattern = pattern.as_str();
+        if let Some(range) = lines {
+            return match context.add_file_lines(pattern, range) {
+                Ok(tokens) => {
+                    context.set_read_only(&[pattern.to_string()], read_only);
+                    let note = if read_only { ", read-only" } else { "" };
+                    Ok(CommandResult::success(format!("Added lines {} of {} (~{} tokens{})", range, pattern, tokens, note)))
+                }
+                Err(e) => Ok(CommandResult::error(format!("Error: {}", e))),
+            };
         }
         
-        match context.add_file(arg) {
-            Ok(_) => Ok(CommandResult::success(format!("Added file: {}", arg))),
-            Err(e) => Ok(CommandResult::error(format!("Error: {}", e))),
+        match file_select::select(pattern) {
+            Ok(selection) if selection.needs_confirmation() && !confirmed => {
+                return Ok(CommandResult::error(format!(
+                    "{} matches {}. Run /add_file {} --yes to add them all",
+                    pattern, selection.summary(), pattern
+                )));
+            }
+            Ok(_) => {}
+            Err(e) => return Ok(CommandResult::error(format!("Error: {}", e))),
         }
+        let added = if truncate { context.add_file_truncated(pattern) } else { context.add_file(pattern) };
+        let added = match added {
+            Ok(added) => added,
+            Err(e) => return Ok(CommandResult::error(format!("Error: {}", e))),
+        };
+        // Adding again without --ro makes a read-only file editable
+        context.set_read_only(&added.files, read_only);
+        let note = if read_only { " (read-only)" } else { "" };
+        if added.expanded {
+            let mut message = format!("Added {} from {}{}", added.summary(), pattern, note);
+            if !added.skipped.is_empty() {
+                message.push_str(&format!("\nSkipped {}: {}", added.skipped.len(), added.skipped.join(", ")));
+            }
+            return Ok(CommandResult::success(message));
+        }
+        let mut message = format!("Added file: {}{}", pattern, note);
+        if let Some(related) = related::follow(context, pattern, read_only) {
+            message.push_str(&format!("\n{}", related));
+        }
+        Ok(CommandResult::success(message))
     }
 }
 
@@ -199,22 +332,160 @@ impl CommandHandler for AddSnippetCommand {
 struct ShowContextCommand;
 impl CommandHandler for ShowContextCommand {
     fn name(&self) -> &str { "/show_context" }
-    fn usage(&self) -> &str { "/show_context" }
-    fn description(&self) -> &str { "Display current context" }
+    fn usage(&self) -> &str { "/show_context [<query>]" }
+    fn description(&self) -> &str { "Display current context, or the items most relevant to a query" }
     fn execute(&self, _arg: &str, context: &mut ContextManager, _model_config: &mut ModelConfig) -> Result<CommandResult> {
         context.show_context();
         Ok(CommandResult::success("Context displayed".to_string()))
     }
 }
 
+struct ContextCommand;
+impl CommandHandler for ContextCommand {
+    fn name(&self) -> &str { "/context" }
+    fn usage(&self) -> &str { "/context [export <file> | import <file>]" }
+    fn description(&self) -> &str { "Save the context to a Markdown bundle, or load one a teammate or another machine exported" }
+    fn execute(&self, arg: &str, context: &mut ContextManager, _model_config: &mut ModelConfig) -> Result<CommandResult> {
+        let (action, file) = arg.trim().split_once(char::is_whitespace).unwrap_or((arg.trim(), ""));
+        let file = file.trim();
+        match (action, file.is_empty()) {
+            ("", _) => {
+                context.show_context();
+                Ok(CommandResult::success("Context displayed".to_string()))
+            }
+            ("export", false) => match context.export_to(file) {
+                Ok(items) => Ok(CommandResult::success(format!("Exported {} item(s) to {}", items, file))),
+                Err(e) => Ok(CommandResult::error(e.to_string())),
+            },
+            ("import", false) => match context.import_from(file) {
+                Ok(report) => Ok(CommandResult::success(report.summary())),
+                Err(e) => Ok(CommandResult::error(e.to_string())),
+            },
+            _ => Ok(CommandResult::error(format!("Usage: {}", self.usage()))),
+        }
+    }
+}
+
 struct ClearContextCommand;
 impl CommandHandler for ClearContextCommand {
     fn name(&self) -> &str { "/clear_context" }
-    fn usage(&self) -> &str { "/clear_context" }
-    fn description(&self) -> &str { "Clear all context" }
-    fn execute(&self, _arg: &str, context: &mut ContextManager, _model_config: &mut ModelConfig) -> Result<CommandResult> {
-        context.clear_context();
-        Ok(CommandResult::success("Context cleared".to_string()))
+    fn usage(&self) -> &str { "/clear_context [--origin <user|retrieved|agent|command|bridge>]" }
+    fn description(&self) -> &str { "Clear all context, or only unpinned items from one origin, archiving it for /rehydrate" }
+    fn execute(&self, arg: &str, context: &mut ContextManager, _model_config: &mut ModelConfig) -> Result<CommandResult> {
+        let arg = arg.trim();
+        if arg.is_empty() {
+            context.clear_context();
+            return Ok(CommandResult::success("Context cleared".to_string()));
+        }
+        let Some(origin) = arg.strip_prefix("--origin").and_then(Origin::parse) else {
+            return Ok(CommandResult::error(format!("Usage: {}", self.usage())));
+        };
+        match context.clear_origin(origin) {
+            0 => Ok(CommandResult::success(format!("No unpinned {} items in context", origin.label()))),
+            cleared => Ok(CommandResult::success(format!("Cleared {} {} item(s), archived for /rehydrate", cleared, origin.label()))),
+        }
+    }
+}
+
+struct DropFileCommand;
+impl CommandHandler for DropFileCommand {
+    fn name(&self) -> &str { "/drop_file" }
+    fn usage(&self) -> &str { "/drop_file <path|item number>" }
+    fn description(&self) -> &str { "Remove one file or item from context, keeping the rest" }
+    fn execute(&self, arg: &str, context: &mut ContextManager, _model_config: &mut ModelConfig) -> Result<CommandResult> {
+        if arg.trim().is_empty() {
+            return Ok(CommandResult::error(format!("Usage: {}", self.usage())));
+        }
+        match context.drop_item(arg) {
+            Ok(label) => Ok(CommandResult::success(format!("Dropped {} from context", label))),
+            Err(e) => Ok(CommandResult::error(e.to_string())),
+        }
+    }
+}
+
+struct PinCommand;
+impl CommandHandler for PinCommand {
+    fn name(&self) -> &str { "/pin" }
+    fn usage(&self) -> &str { "/pin [<path|item number>]" }
+    fn description(&self) -> &str { "Keep a context item from being evicted or shortened; lists pinned items without an argument" }
+    fn execute(&self, arg: &str, context: &mut ContextManager, _model_config: &mut ModelConfig) -> Result<CommandResult> {
+        if arg.trim().is_empty() {
+            let pinned: Vec<String> = context.item_summaries()
+                .into_iter()
+                .filter(|item| item.pinned)
+                .map(|item| format!("  {} (~{} tokens)", item.label, item.tokens))
+                .collect();
+            if pinned.is_empty() {
+                return Ok(CommandResult::success("Nothing is pinned".to_string()));
+            }
+            return Ok(CommandResult::success(format!("Pinned:\n{}", pinned.join("\n"))));
+        }
+        match context.set_pinned(arg, true) {
+            Ok(label) => Ok(CommandResult::success(format!("Pinned {}; it won't be evicted or shortened", label))),
+            Err(e) => Ok(CommandResult::error(e.to_string())),
+        }
+    }
+}
+
+struct UnpinCommand;
+impl CommandHandler for UnpinCommand {
+    fn name(&self) -> &str { "/unpin" }
+    fn usage(&self) -> &str { "/unpin <path|item number>" }
+    fn description(&self) -> &str { "Let a pinned context item be evicted or shortened again" }
+    fn execute(&self, arg: &str, context: &mut ContextManager, _model_config: &mut ModelConfig) -> Result<CommandResult> {
+        if arg.trim().is_empty() {
+            return Ok(CommandResult::error(format!("Usage: {}", self.usage())));
+        }
+        match context.set_pinned(arg, false) {
+            Ok(label) => Ok(CommandResult::success(format!("Unpinned {}", label))),
+            Err(e) => Ok(CommandResult::error(e.to_string())),
+        }
+    }
+}
+
+struct RehydrateCommand;
+impl CommandHandler for RehydrateCommand {
+    fn name(&self) -> &str { "/rehydrate" }
+    fn usage(&self) -> &str { "/rehydrate [<path|archived number>]" }
+    fn description(&self) -> &str { "Bring back an item cleared, dropped or evicted from context; lists archived items without an argument" }
+    fn execute(&self, arg: &str, context: &mut ContextManager, _model_config: &mut ModelConfig) -> Result<CommandResult> {
+        if arg.trim().is_empty() {
+            let archived: Vec<String> = context.archived_summaries()
+                .into_iter()
+                .enumerate()
+                .map(|(i, item)| format!("  {}. {} (~{} tokens)", i + 1, item.label, item.tokens))
+                .collect();
+            if archived.is_empty() {
+                return Ok(CommandResult::success("Nothing is archived".to_string()));
+            }
+            return Ok(CommandResult::success(format!("Archived:\n{}", archived.join("\n"))));
+        }
+        match context.rehydrate(arg) {
+            Ok(label) => Ok(CommandResult::success(format!("Rehydrated {}", label))),
+            Err(e) => Ok(CommandResult::error(e.to_string())),
+        }
+    }
+}
+
+struct SensitiveCommand;
+impl CommandHandler for SensitiveCommand {
+    fn name(&self) -> &str { "/sensitive" }
+    fn usage(&self) -> &str { "/sensitive [on|off]" }
+    fn description(&self) -> &str { "Send only context you added yourself, leaving out retrieved files, command output and synced content" }
+    fn execute(&self, arg: &str, context: &mut ContextManager, _model_config: &mut ModelConfig) -> Result<CommandResult> {
+        let user_only = match arg.trim() {
+            "" => !context.user_only(),
+            "on" => true,
+            "off" => false,
+            _ => return Ok(CommandResult::error(format!("Usage: {}", self.usage()))),
+        };
+        context.set_user_only(user_only);
+        let left_out = context.item_summaries().iter().filter(|item| item.origin != Origin::User).count();
+        if user_only {
+            Ok(CommandResult::success(format!("Sensitive mode on: prompts include only user-added context ({} item(s) left out)", left_out)))
+        } else {
+            Ok(CommandResult::success("Sensitive mode off: prompts include all context".to_string()))
+        }
     }
 }
 
@@ -228,7 +499,7 @@ impl CommandHandler for RunCommand {
             return Ok(CommandResult::error("Usage: /run <shell_command_here>".to_string()));
         }
         
-        execute_shell_command("sh", &["-c", arg])
+        run_recorded_shell_command(arg)
     }
 }
 
@@ -242,13 +513,13 @@ impl CommandHandler for RunAddCommand {
             return Ok(CommandResult::error("Usage: /run_add <shell_command_here>".to_string()));
         }
         
-        let result = execute_shell_command("sh", &["-c", arg])?;
+        let result = run_recorded_shell_command(arg)?;
         
         // Add command output to context
         if !result.output.trim().is_empty() {
-            context.add_snippet(format!("Output of command '{}': \n{}", arg, result.output));
+            context.add_snippet_from(format!("Output of command '{}': \n{}", arg, result.output), Origin::Command);
         } else if let Some(error) = &result.error {
-            context.add_snippet(format!("Error output of command '{}': \n{}", arg, error));
+            context.add_snippet_from(format!("Error output of command '{}': \n{}", arg, error), Origin::Command);
         }
         
         Ok(result)
@@ -321,7 +592,7 @@ impl CommandHandler for HelpCommand {
 struct ProviderCommand;
 impl CommandHandler for ProviderCommand {
     fn name(&self) -> &str { "/provider" }
-    fn usage(&self) -> &str { "/provider <ollama|gemini|anthropic>" }
+    fn usage(&self) -> &str { "/provider <ollama|gemini|anthropic|openrouter|azure|bedrock|groq|together>" }
     fn description(&self) -> &str { "Switch LLM provider" }
     fn execute(&self, arg: &str, _context: &mut ContextManager, model_config: &mut ModelConfig) -> Result<CommandResult> {
         if arg.is_empty() {
@@ -329,24 +600,51 @@ impl CommandHandler for ProviderCommand {
                 LlmProvider::Ollama => "Ollama",
                 LlmProvider::Gemini => "Google Gemini",
                 LlmProvider::Anthropic => "Anthropic Claude",
+                LlmProvider::OpenRouter => "OpenRouter",
+                LlmProvider::Azure => "Azure OpenAI",
+                LlmProvider::Bedrock => "AWS Bedrock",
+                LlmProvider::Groq => "Groq",
+                LlmProvider::Together => "Together.ai",
             };
-            return Ok(CommandResult::success(format!("Current provider: {}\nUsage: /provider <ollama|gemini|anthropic>", current)));
+            return Ok(CommandResult::success(format!("Current provider: {}\nUsage: {}", current, self.usage())));
         }
         
         match arg.to_lowercase().as_str() {
             "ollama" => {
                 model_config.provider = LlmProvider::Ollama;
+                model_config.context_window = None;
                 Ok(CommandResult::success("Switched to Ollama provider".to_string()))
             }
             "gemini" => {
                 model_config.provider = LlmProvider::Gemini;
+                model_config.context_window = None;
                 Ok(CommandResult::success("Switched to Gemini provider".to_string()))
             }
             "anthropic" => {
                 model_config.provider = LlmProvider::Anthropic;
+                model_config.context_window = None;
                 Ok(CommandResult::success("Switched to Anthropic provider".to_string()))
             }
-            _ => Ok(CommandResult::error("Invalid provider. Use: ollama, gemini, or anthropic".to_string()))
+            "openrouter" => {
+                model_config.provider = LlmProvider::OpenRouter;
+                model_config.context_window = None;
+                model_config.model_name = None;
+                Ok(CommandResult::success("Switched to OpenRouter provider (browse models with /model list)".to_string()))
+            }
+            "azure" | "bedrock" => {
+                let provider = LlmProvider::from_name(arg).unwrap_or_default();
+                model_config.model_name = llm::configured_model(&provider);
+                model_config.provider = provider;
+                model_config.context_window = None;
+                Ok(CommandResult::success(format!("Switched to {} (endpoint and auth from [providers] in prompts.toml)", model_config.display_name())))
+            }
+            "groq" | "together" => {
+                model_config.provider = LlmProvider::from_name(arg).unwrap_or_default();
+                model_config.context_window = None;
+                model_config.model_name = None;
+                Ok(CommandResult::success(format!("Switched to {}", model_config.display_name())))
+            }
+            _ => Ok(CommandResult::error("Invalid provider. Use: ollama, gemini, anthropic, openrouter, azure, bedrock, groq, or together".to_string()))
         }
     }
 }
@@ -354,18 +652,31 @@ impl CommandHandler for ProviderCommand {
 struct ModelCommand;
 impl CommandHandler for ModelCommand {
     fn name(&self) -> &str { "/model" }
-    fn usage(&self) -> &str { "/model <model_name>" }
-    fn description(&self) -> &str { "Set model for current provider" }
+    fn usage(&self) -> &str { "/model <model_name> | list [<filter>]" }
+    fn description(&self) -> &str { "Set model for current provider, or browse the OpenRouter catalog" }
     fn execute(&self, arg: &str, _context: &mut ContextManager, model_config: &mut ModelConfig) -> Result<CommandResult> {
         if arg.is_empty() {
-            return Ok(CommandResult::success(format!("Current model: {}\nUsage: /model <model_name>", model_config.get_model_name())));
+            return Ok(CommandResult::success(format!("Current model: {}\nUsage: {}", model_config.get_model_name(), self.usage())));
         }
         
         model_config.model_name = Some(arg.to_string());
+        
+        model_config.context_window = None;
         Ok(CommandResult::success(format!("Model set to: {}", arg)))
     }
 }
 
+struct ModelsCommand;
+impl CommandHandler for ModelsCommand {
+    fn name(&self) -> &str { "/models" }
+    fn usage(&self) -> &str { "/models" }
+    fn description(&self) -> &str { "List models pulled into the local Ollama server and pick one" }
+    fn execute(&self, _arg: &str, _context: &mut ContextManager, _model_config: &mut ModelConfig) -> Result<CommandResult> {
+        // Querying Ollama is async, so the CLI and TUI handle this command themselves
+        Ok(CommandResult::error("/models is only available from the CLI and TUI prompts".to_string()))
+    }
+}
+
 struct VersionCommand;
 impl CommandHandler for VersionCommand {
     fn name(&self) -> &str { "/version" }
@@ -374,4 +685,743 @@ impl CommandHandler for VersionCommand {
     fn execute(&self, _arg: &str, _context: &mut ContextManager, _model_config: &mut ModelConfig) -> Result<CommandResult> {
         Ok(CommandResult::success(format!("KOTA version: {}", env!("CARGO_PKG_VERSION"))))
     }
-}
\ No newline at end of file
+}
+
+struct AgentCommand;
+impl CommandHandler for AgentCommand {
+    fn name(&self) -> &str { "/agent" }
+    fn usage(&self) -> &str { "/agent run [@AgentName] <task> | pause <id> | resume [<id>] | budget <id> [<limit> <n>] | log [<id>]" }
+    fn description(&self) -> &str { "Delegate a task to the best-suited agent, manage its session, or show its transcript" }
+    fn execute(&self, arg: &str, _context: &mut ContextManager, _model_config: &mut ModelConfig) -> Result<CommandResult> {
+        let parts: Vec<&str> = arg.split_whitespace().collect();
+        
+        match parts.as_slice() {
+            ["log"] => {
+                let task_ids = transcript::list_transcripts()?;
+                if task_ids.is_empty() {
+                    return Ok(CommandResult::success("No agent transcripts found".to_string()));
+                }
+                let mut output = String::from("Agent transcripts (newest first):\n");
+                for id in task_ids {
+                    output.push_str(&format!("  {}\n", id));
+                }
+                Ok(CommandResult::success(output))
+            }
+            ["log", task_id] => {
+                match transcript::load_transcript(task_id) {
+                    Ok(content) => Ok(CommandResult::success(content)),
+                    Err(e) => Ok(CommandResult::error(format!("Error: {}", e))),
+                }
+            }
+            ["pause", task_id] => {
+                let mut session = match AgentSession::load(task_id) {
+                    Ok(session) => session,
+                    Err(e) => return Ok(CommandResult::error(format!("Error: {}", e))),
+                };
+                if session.is_finished() {
+                    return Ok(CommandResult::error(format!("Session {} has already finished", task_id)));
+                }
+                session.pause("Paused with /agent pause");
+                session.save()?;
+                Ok(CommandResult::success(format!("Paused {} at step {}/{}. Resume with /agent resume {}", task_id, session.plan_position + 1, session.total_steps(), task_id)))
+            }
+            ["budget", task_id] => {
+                match AgentSession::load(task_id) {
+                    Ok(session) => {
+                        let (budget, usage) = (&session.task.budget, &session.task.usage);
+                        Ok(CommandResult::success(format!(
+                            "Budget for {}:\n  LLM calls: {}/{}\n  Tokens:    {}/{}\n  Wall time: {}s/{}s",
+                            task_id,
+                            usage.llm_calls, budget.max_llm_calls,
+                            usage.tokens, budget.max_tokens,
+                            usage.wall_time_secs, budget.max_wall_time_secs,
+                        )))
+                    }
+                    Err(e) => Ok(CommandResult::error(format!("Error: {}", e))),
+                }
+            }
+            ["budget", task_id, limit, value] => {
+                let mut session = match AgentSession::load(task_id) {
+                    Ok(session) => session,
+                    Err(e) => return Ok(CommandResult::error(format!("Error: {}", e))),
+                };
+                let Ok(value) = value.parse::<u64>() else {
+                    return Ok(CommandResult::error(format!("Invalid budget value: {}", value)));
+                };
+                
+                let budget = &mut session.task.budget;
+                match *limit {
+                    "calls" => budget.max_llm_calls = value.min(u32::MAX as u64) as u32,
+                    "tokens" => budget.max_tokens = value,
+                    "time" => budget.max_wall_time_secs = value,
+                    _ => return Ok(CommandResult::error("Budget limit must be one of: calls, tokens, time".to_string())),
+                }
+                session.save()?;
+                Ok(CommandResult::success(format!("Set {} budget for {} to {}", limit, task_id, value)))
+            }
+            ["run", ..] | ["resume", ..] => {
+                // Agents run asynchronously and prompt for edit approval, so the CLI loop handles them
+                Ok(CommandResult::error(format!("/agent {} is only available in the classic CLI", parts[0])))
+            }
+            _ => Ok(CommandResult::error(format!("Usage: {}", self.usage()))),
+        }
+    }
+}
+
+struct BriefCommand;
+impl CommandHandler for BriefCommand {
+    fn name(&self) -> &str { "/brief" }
+    fn usage(&self) -> &str { "/brief [<topic>]" }
+    fn description(&self) -> &str { "Add a cited research brief to context (lists briefs without a topic)" }
+    fn execute(&self, arg: &str, context: &mut ContextManager, _model_config: &mut ModelConfig) -> Result<CommandResult> {
+        let memory = MemoryManager::new()?;
+        let topic = arg.trim();
+        
+        if topic.is_empty() {
+            let briefs = memory.list_research_briefs()?;
+            if briefs.is_empty() {
+                return Ok(CommandResult::success("No research briefs yet. Create one with /agent run @ResearchAgent <topic>".to_string()));
+            }
+            let mut output = String::from("Research briefs:\n");
+            for brief in briefs {
+                output.push_str(&format!("  {}\n", brief));
+            }
+            return Ok(CommandResult::success(output));
+        }
+        
+        match memory.load_research_brief(topic) {
+            Ok(brief) => {
+                context.add_snippet(brief);
+                Ok(CommandResult::success(format!("Added research brief '{}' to context", topic)))
+            }
+            Err(e) => Ok(CommandResult::error(format!("Error: {}", e))),
+        }
+    }
+}
+
+struct BriefingCommand;
+impl CommandHandler for BriefingCommand {
+    fn name(&self) -> &str { "/briefing" }
+    fn usage(&self) -> &str { "/briefing" }
+    fn description(&self) -> &str { "Show the latest morning briefing from nightly maintenance" }
+    fn execute(&self, _arg: &str, _context: &mut ContextManager, _model_config: &mut ModelConfig) -> Result<CommandResult> {
+        match maintenance_agent::latest_briefing()? {
+            Some((_, content)) => Ok(CommandResult::success(content)),
+            None => Ok(CommandResult::success("No briefing yet. Schedule `kota --maintenance` to generate one nightly".to_string())),
+        }
+    }
+}
+
+struct FocusCommand;
+impl CommandHandler for FocusCommand {
+    fn name(&self) -> &str { "/focus" }
+    fn usage(&self) -> &str { "/focus [<length>] [<goal>] | stop | log" }
+    fn description(&self) -> &str { "Start a focus block like /focus 50m, holding back webhooks and suggestions until it ends; stop ends it early, log lists past blocks" }
+    fn execute(&self, arg: &str, _context: &mut ContextManager, _model_config: &mut ModelConfig) -> Result<CommandResult> {
+        let arg = arg.trim();
+        match arg {
+            "" => {
+                let Some(session) = focus::current() else {
+                    return Ok(CommandResult::success("No focus block running. Start one with /focus 50m [goal]".to_string()));
+                };
+                let goal = session.goal.as_ref().map(|goal| format!(" on {}", goal)).unwrap_or_default();
+                Ok(CommandResult::success(format!(
+                    "Focus{}: {} min left, {} held back, {} of {} interruptions used",
+                    goal, session.minutes_left(chrono::Utc::now()), session.held.len(), session.interruptions, session.budget
+                )))
+            }
+            "stop" => Ok(CommandResult::success(focus::stop().unwrap_or_else(|| "No focus block running".to_string()))),
+            "log" => {
+                let blocks = focus::history(10)?;
+                if blocks.is_empty() {
+                    return Ok(CommandResult::success("No focus blocks recorded yet".to_string()));
+                }
+                Ok(CommandResult::success(format!("Recent focus blocks:\n{}", blocks.iter().map(|block| format!("  {}", block)).collect::<Vec<_>>().join("\n"))))
+            }
+            _ => {
+                let config = PromptsConfig::load().unwrap_or_default().focus;
+                let (first, rest) = arg.split_once(char::is_whitespace).unwrap_or((arg, ""));
+                let (length, goal) = match focus::parse_length(first) {
+                    Some(length) => (length, rest.trim()),
+                    None => match focus::parse_length(&config.default_length) {
+                        Some(length) => (length, arg),
+                        None => return Ok(CommandResult::error(format!("Invalid [focus] default_length {}; use a length like 50m", config.default_length))),
+                    },
+                };
+                let goal = Some(goal.to_string()).filter(|goal| !goal.is_empty());
+                match focus::start(length, goal, config.distraction_budget) {
+                    Ok(session) => Ok(CommandResult::success(format!(
+                        "Focusing for {} min, until {}. Webhooks and suggestions are held back; {} urgent one(s) may still get through",
+                        length.num_minutes(), clock().time(&session.ends), session.budget
+                    ))),
+                    Err(e) => Ok(CommandResult::error(format!("Error: {}", e))),
+                }
+            }
+        }
+    }
+}
+
+struct UsageCommand;
+impl CommandHandler for UsageCommand {
+    fn name(&self) -> &str { "/usage" }
+    fn usage(&self) -> &str { "/usage [all]" }
+    fn description(&self) -> &str { "Show token usage and estimated cost for this session (or all time)" }
+    fn execute(&self, arg: &str, _context: &mut ContextManager, _model_config: &mut ModelConfig) -> Result<CommandResult> {
+        match arg.trim() {
+            "" => Ok(CommandResult::success(usage::format_usage_report(&usage::session_usage(), "this session"))),
+            "all" => Ok(CommandResult::success(usage::format_usage_report(&usage::all_time_usage()?, "across all sessions"))),
+            _ => Ok(CommandResult::error(format!("Usage: {}", self.usage()))),
+        }
+    }
+}
+
+struct CacheCommand;
+impl CommandHandler for CacheCommand {
+    fn name(&self) -> &str { "/cache" }
+    fn usage(&self) -> &str { "/cache [clear]" }
+    fn description(&self) -> &str { "Show or clear the LLM response cache" }
+    fn execute(&self, arg: &str, _context: &mut ContextManager, _model_config: &mut ModelConfig) -> Result<CommandResult> {
+        let cache_config = PromptsConfig::load().unwrap_or_default().cache;
+        let cache = ResponseCache::new(cache_config.ttl_hours);
+        
+        match arg.trim() {
+            "" => Ok(CommandResult::success(format!(
+                "Response cache {} ({} entries in {}, expire after {}h). Toggle with [cache] enabled in prompts.toml",
+                if cache_config.enabled { "enabled" } else { "disabled" },
+                cache.len(),
+                CACHE_DIR,
+                cache_config.ttl_hours,
+            ))),
+            "clear" => {
+                let removed = cache.clear()?;
+                Ok(CommandResult::success(format!("Cleared {} cached responses", removed)))
+            }
+            _ => Ok(CommandResult::error(format!("Usage: {}", self.usage()))),
+        }
+    }
+}
+
+struct CompareCommand;
+impl CommandHandler for CompareCommand {
+    fn name(&self) -> &str { "/compare" }
+    fn usage(&self) -> &str { "/compare <prompt>" }
+    fn description(&self) -> &str { "Ask the [compare] models in prompts.toml the same question side by side" }
+    fn execute(&self, _arg: &str, _context: &mut ContextManager, _model_config: &mut ModelConfig) -> Result<CommandResult> {
+        // The requests run concurrently, so the CLI and TUI handle this command themselves
+        Ok(CommandResult::error("/compare is only available from the CLI and TUI prompts".to_string()))
+    }
+}
+
+struct CompactCommand;
+impl CommandHandler for CompactCommand {
+    fn name(&self) -> &str { "/compact" }
+    fn usage(&self) -> &str { "/compact" }
+    fn description(&self) -> &str { "Summarize old conversation turns and rarely used context into one snippet, replacing them" }
+    fn execute(&self, _arg: &str, _context: &mut ContextManager, _model_config: &mut ModelConfig) -> Result<CommandResult> {
+        // The summary comes from the model, so the CLI and TUI handle this command themselves
+        Ok(CommandResult::error("/compact is only available from the CLI and TUI prompts".to_string()))
+    }
+}
+
+struct InitCommand;
+impl CommandHandler for InitCommand {
+    fn name(&self) -> &str { "/init" }
+    fn usage(&self) -> &str { "/init [--force]" }
+    fn description(&self) -> &str { "Write a KOTA.md of project conventions from a survey of the repository; it's added to every system prompt" }
+    fn execute(&self, _arg: &str, _context: &mut ContextManager, _model_config: &mut ModelConfig) -> Result<CommandResult> {
+        // The file is written by the model, so the CLI and TUI handle this command themselves
+        Ok(CommandResult::error("/init is only available from the CLI and TUI prompts".to_string()))
+    }
+}
+
+struct ChangelogCommand;
+impl CommandHandler for ChangelogCommand {
+    fn name(&self) -> &str { "/changelog" }
+    fn usage(&self) -> &str { "/changelog [<since-ref>]" }
+    fn description(&self) -> &str { "Draft a CHANGELOG.md section from commits and KOTA edits since a ref (default: the latest tag)" }
+    fn execute(&self, _arg: &str, _context: &mut ContextManager, _model_config: &mut ModelConfig) -> Result<CommandResult> {
+        // The draft goes through the model and edit review, so the CLI and TUI handle this command themselves
+        Ok(CommandResult::error("/changelog is only available from the CLI and TUI prompts".to_string()))
+    }
+}
+
+struct IssueCommand;
+impl CommandHandler for IssueCommand {
+    fn name(&self) -> &str { "/issue" }
+    fn usage(&self) -> &str { "/issue <url|owner/repo#number|number>" }
+    fn description(&self) -> &str { "Add a GitHub issue to context and plan its implementation as an agent session" }
+    fn execute(&self, _arg: &str, _context: &mut ContextManager, _model_config: &mut ModelConfig) -> Result<CommandResult> {
+        // Planning runs an agent session, which only the CLI drives
+        Ok(CommandResult::error("/issue is only available from the CLI prompt".to_string()))
+    }
+}
+
+struct ToolsCommand;
+impl CommandHandler for ToolsCommand {
+    fn name(&self) -> &str { "/tools" }
+    fn usage(&self) -> &str { "/tools [on|off]" }
+    fn description(&self) -> &str { "Use native function calling for edits, commands and context requests" }
+    fn execute(&self, arg: &str, _context: &mut ContextManager, model_config: &mut ModelConfig) -> Result<CommandResult> {
+        match arg.trim() {
+            "" => {}
+            "on" => model_config.native_tools = true,
+            "off" => model_config.native_tools = false,
+            _ => return Ok(CommandResult::error(format!("Usage: {}", self.usage()))),
+        }
+        
+        let status = if model_config.native_tools { "on" } else { "off" };
+        let note = if model_config.native_tools && matches!(model_config.provider, LlmProvider::Gemini) {
+            " (Gemini has no function calling here; S/R and command blocks are used instead)"
+        } else {
+            ""
+        };
+        Ok(CommandResult::success(format!("Native tool calling is {}{}", status, note)))
+    }
+}
+
+struct VerifyCommand;
+impl CommandHandler for VerifyCommand {
+    fn name(&self) -> &str { "/verify" }
+    fn usage(&self) -> &str { "/verify [on [<provider>[:<model>]]|off]" }
+    fn description(&self) -> &str { "Draft edits with a cheap model and have a stronger one check them before they're applied" }
+    fn execute(&self, arg: &str, _context: &mut ContextManager, model_config: &mut ModelConfig) -> Result<CommandResult> {
+        let (action, spec) = arg.trim().split_once(' ').map(|(action, spec)| (action, spec.trim())).unwrap_or((arg.trim(), ""));
+        match (action, spec) {
+            ("", "") => {}
+            ("on", spec) => {
+                let spec = if spec.is_empty() { PromptsConfig::load().unwrap_or_default().providers.verify.unwrap_or_default() } else { spec.to_string() };
+                let verifier = if spec.trim().is_empty() {
+                    // The current model verifies; its provider's fast model drafts
+                    let verifier = ModelConfig { verify_model: None, ..model_config.clone() };
+                    model_config.model_name = Some(model_config.get_fast_model_name());
+                    model_config.context_window = None;
+                    verifier
+                } else {
+                    match router::spec_model(&spec) {
+                        Ok(verifier) => verifier,
+                        Err(e) => return Ok(CommandResult::error(format!("{}. Usage: {}", e, self.usage()))),
+                    }
+                };
+                model_config.verify_model = Some(Box::new(verifier));
+            }
+            ("off", "") => {
+                let Some(verifier) = model_config.verify_model.take() else {
+                    return Ok(CommandResult::success("Verify mode is already off".to_string()));
+                };
+                // Back to the model that was verifying, if /verify on switched to its fast model
+                let drafting_fast = model_config.model_name.as_deref() == Some(verifier.get_fast_model_name().as_str());
+                if verifier.provider.name() == model_config.provider.name() && drafting_fast {
+                    model_config.model_name = verifier.model_name;
+                    model_config.context_window = verifier.context_window;
+                }
+                return Ok(CommandResult::success(format!("Verify mode off. Using {}", model_config.display_name())));
+            }
+            _ => return Ok(CommandResult::error(format!("Usage: {}", self.usage()))),
+        }
+        
+        Ok(CommandResult::success(match &model_config.verify_model {
+            Some(verifier) => format!("Verify mode on: {} drafts edits, {} checks them", model_config.display_name(), verifier.display_name()),
+            None => "Verify mode is off".to_string(),
+        }))
+    }
+}
+
+/// What a `/temp`-style argument asks for
+enum SamplingArg<T> {
+    Show,
+    Reset,
+    Set(T),
+}
+
+/// Empty shows the current value, `default` clears it, anything else must parse and be in range
+fn parse_sampling_arg<T: std::str::FromStr + PartialOrd>(arg: &str, min: T, max: T) -> Option<SamplingArg<T>> {
+    match arg.trim() {
+        "" => Some(SamplingArg::Show),
+        "default" | "reset" => Some(SamplingArg::Reset),
+        value => value.parse::<T>().ok().filter(|value| *value >= min && *value <= max).map(SamplingArg::Set),
+    }
+}
+
+/// The sampling settings requests will use, with where each one comes from
+fn sampling_status(model_config: &ModelConfig) -> String {
+    let defaults = PromptsConfig::load().unwrap_or_default().sampling;
+    let describe = |session: Option<String>, config: Option<String>, fallback: &str| match (session, config) {
+        (Some(value), _) => value,
+        (None, Some(value)) => format!("{} (prompts.toml)", value),
+        (None, None) => fallback.to_string(),
+    };
+    let sampling = &model_config.sampling;
+    let mut status = format!(
+        "Sampling: temperature {}, top_p {}, max_tokens {}",
+        describe(sampling.temperature.map(|v| v.to_string()), defaults.temperature.map(|v| v.to_string()), "provider default"),
+        describe(sampling.top_p.map(|v| v.to_string()), defaults.top_p.map(|v| v.to_string()), "provider default"),
+        describe(sampling.max_tokens.map(|v| v.to_string()), defaults.max_tokens.map(|v| v.to_string()), &llm::DEFAULT_MAX_TOKENS.to_string()),
+    );
+    if let Some(seed) = sampling.seed.or(defaults.seed) {
+        status.push_str(&format!(", seed {}", seed));
+        if !llm::replay::supports_seed(&model_config.provider) {
+            status.push_str(&format!(" ({} doesn't take a seed)", model_config.provider.name()));
+        }
+    }
+    if matches!(model_config.provider, LlmProvider::Gemini) {
+        status.push_str(" (the Gemini client here doesn't take sampling settings, so Gemini uses its defaults)");
+    }
+    status
+}
+
+struct TempCommand;
+impl CommandHandler for TempCommand {
+    fn name(&self) -> &str { "/temp" }
+    fn usage(&self) -> &str { "/temp [<0-2>|default]" }
+    fn description(&self) -> &str { "Show or set the sampling temperature for this session" }
+    fn execute(&self, arg: &str, _context: &mut ContextManager, model_config: &mut ModelConfig) -> Result<CommandResult> {
+        match parse_sampling_arg(arg, 0.0, 2.0) {
+            Some(SamplingArg::Show) => {}
+            Some(SamplingArg::Reset) => model_config.sampling.temperature = None,
+            Some(SamplingArg::Set(value)) => model_config.sampling.temperature = Some(value),
+            None => return Ok(CommandResult::error(format!("Usage: {}", self.usage()))),
+        }
+        Ok(CommandResult::success(sampling_status(model_config)))
+    }
+}
+
+struct TopPCommand;
+impl CommandHandler for TopPCommand {
+    fn name(&self) -> &str { "/top_p" }
+    fn usage(&self) -> &str { "/top_p [<0-1>|default]" }
+    fn description(&self) -> &str { "Show or set nucleus sampling (top_p) for this session" }
+    fn execute(&self, arg: &str, _context: &mut ContextManager, model_config: &mut ModelConfig) -> Result<CommandResult> {
+        match parse_sampling_arg(arg, 0.0, 1.0) {
+            Some(SamplingArg::Show) => {}
+            Some(SamplingArg::Reset) => model_config.sampling.top_p = None,
+            Some(SamplingArg::Set(value)) => model_config.sampling.top_p = Some(value),
+            None => return Ok(CommandResult::error(format!("Usage: {}", self.usage()))),
+        }
+        Ok(CommandResult::success(sampling_status(model_config)))
+    }
+}
+
+struct MaxTokensCommand;
+impl CommandHandler for MaxTokensCommand {
+    fn name(&self) -> &str { "/max_tokens" }
+    fn usage(&self) -> &str { "/max_tokens [<n>|default]" }
+    fn description(&self) -> &str { "Show or set the longest reply, in tokens, for this session" }
+    fn execute(&self, arg: &str, _context: &mut ContextManager, model_config: &mut ModelConfig) -> Result<CommandResult> {
+        match parse_sampling_arg(arg, 1, u32::MAX) {
+            Some(SamplingArg::Show) => {}
+            Some(SamplingArg::Reset) => model_config.sampling.max_tokens = None,
+            Some(SamplingArg::Set(value)) => model_config.sampling.max_tokens = Some(value),
+            None => return Ok(CommandResult::error(format!("Usage: {}", self.usage()))),
+        }
+        Ok(CommandResult::success(sampling_status(model_config)))
+    }
+}
+
+/// Describe an undo or redo and read the restored files into context again
+fn report_restored(action: &str, restored: undo::Restored, context: &mut ContextManager, left: &str) -> CommandResult {
+    let paths = restored.batch.paths();
+    context.reload_files(&paths);
+    let mut output = format!("{} the edits for \"{}\": {}", action, restored.batch.label(), paths.join(", "));
+    output.push_str(&format!("\n{} more to {}. The restored files aren't committed; review them with /git_diff", restored.remaining, left));
+    CommandResult::success(output)
+}
+
+struct UndoCommand;
+impl CommandHandler for UndoCommand {
+    fn name(&self) -> &str { "/undo" }
+    fn usage(&self) -> &str { "/undo" }
+    fn description(&self) -> &str { "Put back every file the last applied batch of edits changed, created, deleted or renamed" }
+    fn execute(&self, _arg: &str, context: &mut ContextManager, _model_config: &mut ModelConfig) -> Result<CommandResult> {
+        match undo::undo() {
+            Ok(restored) => Ok(report_restored("Undid", restored, context, "undo")),
+            Err(e) => Ok(CommandResult::error(e.to_string())),
+        }
+    }
+}
+
+struct RedoCommand;
+impl CommandHandler for RedoCommand {
+    fn name(&self) -> &str { "/redo" }
+    fn usage(&self) -> &str { "/redo" }
+    fn description(&self) -> &str { "Apply the last undone batch of edits again" }
+    fn execute(&self, _arg: &str, context: &mut ContextManager, _model_config: &mut ModelConfig) -> Result<CommandResult> {
+        match undo::redo() {
+            Ok(restored) => Ok(report_restored("Redid", restored, context, "redo")),
+            Err(e) => Ok(CommandResult::error(e.to_string())),
+        }
+    }
+}
+
+struct SeedCommand;
+impl CommandHandler for SeedCommand {
+    fn name(&self) -> &str { "/seed" }
+    fn usage(&self) -> &str { "/seed [<n>|default]" }
+    fn description(&self) -> &str { "Show or set the sampling seed for this session, so providers that take one repeat their replies" }
+    fn execute(&self, arg: &str, _context: &mut ContextManager, model_config: &mut ModelConfig) -> Result<CommandResult> {
+        match parse_sampling_arg(arg, 0, u64::MAX) {
+            Some(SamplingArg::Show) => {}
+            Some(SamplingArg::Reset) => model_config.sampling.seed = None,
+            Some(SamplingArg::Set(value)) => model_config.sampling.seed = Some(value),
+            None => return Ok(CommandResult::error(format!("Usage: {}", self.usage()))),
+        }
+        Ok(CommandResult::success(sampling_status(model_config)))
+    }
+}
+
+struct ReplayCommand;
+impl CommandHandler for ReplayCommand {
+    fn name(&self) -> &str { "/replay" }
+    fn usage(&self) -> &str { "/replay [<turn>] [--current]" }
+    fn description(&self) -> &str { "List this session's requests with their model, sampling, seed and prompt hash, or send one again as it went out (--current: to the current model) and compare the replies" }
+    fn execute(&self, arg: &str, _context: &mut ContextManager, _model_config: &mut ModelConfig) -> Result<CommandResult> {
+        if arg.trim().is_empty() {
+            return Ok(CommandResult::success(llm::replay::list()));
+        }
+        // Sending the request again is async, so the CLI and TUI handle a turn themselves
+        Ok(CommandResult::error("/replay <turn> is only available from the CLI and TUI prompts".to_string()))
+    }
+}
+
+struct PromptCommand;
+impl CommandHandler for PromptCommand {
+    fn name(&self) -> &str { "/prompt" }
+    fn usage(&self) -> &str { "/prompt [reload]" }
+    fn description(&self) -> &str { "Show which system prompt the provider gets, or reload ~/.kota/prompts and KOTA.md" }
+    fn execute(&self, arg: &str, _context: &mut ContextManager, model_config: &mut ModelConfig) -> Result<CommandResult> {
+        let dir = prompts::provider_prompts_dir()
+            .map(|dir| dir.display().to_string())
+            .unwrap_or_else(|| format!("~/{}", prompts::PROVIDER_PROMPTS_DIR));
+        
+        match arg.trim() {
+            "" => {
+                let provider = model_config.provider.name();
+                let source = if prompts::provider_system_prompt(provider).is_some() {
+                    format!("{}/{}.md", dir, provider)
+                } else {
+                    format!("[system] instructions in prompts.toml (add {}/{}.md to override)", dir, provider)
+                };
+                let project = match instructions::project_instructions() {
+                    Some((path, _)) => format!("\nProject instructions: {}", path),
+                    None => "\nProject instructions: none (add KOTA.md, or run /init)".to_string(),
+                };
+                Ok(CommandResult::success(format!("{} system prompt: {}{}", model_config.display_name(), source, project)))
+            }
+            "reload" => {
+                let providers = prompts::reload_provider_prompts();
+                let project = match instructions::reload() {
+                    Some(path) => format!("\nReloaded project instructions from {}", path),
+                    None => String::new(),
+                };
+                if providers.is_empty() {
+                    Ok(CommandResult::success(format!("No provider prompts in {}; every provider uses prompts.toml{}", dir, project)))
+                } else {
+                    Ok(CommandResult::success(format!("Reloaded provider prompts from {}: {}{}", dir, providers.join(", "), project)))
+                }
+            }
+            _ => Ok(CommandResult::error(format!("Usage: {}", self.usage()))),
+        }
+    }
+}
+
+struct BudgetCommand;
+impl CommandHandler for BudgetCommand {
+    fn name(&self) -> &str { "/budget" }
+    fn usage(&self) -> &str { "/budget [override|enforce]" }
+    fn description(&self) -> &str { "Show spend against the [budget] limits, or lift them for this session" }
+    fn execute(&self, arg: &str, _context: &mut ContextManager, _model_config: &mut ModelConfig) -> Result<CommandResult> {
+        match arg.trim() {
+            "" => {
+                let budget = PromptsConfig::load().unwrap_or_default().budget;
+                let providers = [LlmProvider::Anthropic, LlmProvider::Gemini, LlmProvider::OpenRouter, LlmProvider::Azure, LlmProvider::Bedrock, LlmProvider::Groq, LlmProvider::Together];
+                let mut output = String::new();
+                for provider in &providers {
+                    for status in usage::budget_statuses(provider, &budget)? {
+                        let marker = if status.exceeded() { " (reached)" } else { "" };
+                        output.push_str(&format!("  {}{}\n", status, marker));
+                    }
+                }
+                if output.is_empty() {
+                    return Ok(CommandResult::success("No spend budgets set. Add daily or weekly limits under [budget] in prompts.toml".to_string()));
+                }
+                let action = match budget.action {
+                    BudgetAction::Local => "switch to Ollama",
+                    BudgetAction::Cheaper => "switch to the provider's cheaper model",
+                    BudgetAction::Stop => "refuse requests",
+                };
+                let state = if usage::budget_overridden() {
+                    "Overridden for this session; /budget enforce to turn the limits back on".to_string()
+                } else {
+                    format!("When a budget is reached, KOTA will {}", action)
+                };
+                Ok(CommandResult::success(format!("Spend budgets (estimated):\n{}{}", output, state)))
+            }
+            "override" => {
+                usage::set_budget_override(true);
+                Ok(CommandResult::success("Spend budgets lifted for the rest of this session".to_string()))
+            }
+            "enforce" => {
+                usage::set_budget_override(false);
+                Ok(CommandResult::success("Spend budgets enforced again".to_string()))
+            }
+            _ => Ok(CommandResult::error(format!("Usage: {}", self.usage()))),
+        }
+    }
+}
+
+struct TrustCommand;
+impl CommandHandler for TrustCommand {
+    fn name(&self) -> &str { "/trust" }
+    fn usage(&self) -> &str { "/trust [revoke <command>|clear]" }
+    fn description(&self) -> &str { "Show or reset approvals remembered for this session and project" }
+    fn execute(&self, arg: &str, _context: &mut ContextManager, _model_config: &mut ModelConfig) -> Result<CommandResult> {
+        let mut store = TrustStore::load();
+        let arg = arg.trim();
+        
+        if let Some(command) = arg.strip_prefix("revoke ") {
+            return if store.revoke_command(command)? {
+                Ok(CommandResult::success(format!("No longer always allowed: {}", command.trim())))
+            } else {
+                Ok(CommandResult::error(format!("Not a trusted command: {}", command.trim())))
+            };
+        }
+        
+        match arg {
+            "" => {
+                let mut output = String::from("Edits approved for this session:\n");
+                let files = approvals::session_approved_files();
+                if files.is_empty() {
+                    output.push_str("  (none)\n");
+                }
+                for file in files {
+                    output.push_str(&format!("  {}\n", file));
+                }
+                output.push_str(&format!("Commands always allowed in this project ({}):\n", TRUST_FILE));
+                let mut commands = store.commands().peekable();
+                if commands.peek().is_none() {
+                    output.push_str("  (none)\n");
+                }
+                for command in commands {
+                    output.push_str(&format!("  {}\n", command));
+                }
+                Ok(CommandResult::success(output.trim_end().to_string()))
+            }
+            "clear" => {
+                approvals::clear_session_approvals();
+                let removed = store.clear()?;
+                Ok(CommandResult::success(format!("Cleared session edit approvals and {} trusted command(s)", removed)))
+            }
+            _ => Ok(CommandResult::error(format!("Usage: {}", self.usage()))),
+        }
+    }
+}
+
+struct TranscriptCommand;
+impl CommandHandler for TranscriptCommand {
+    fn name(&self) -> &str { "/transcript" }
+    fn usage(&self) -> &str { "/transcript [on|off]" }
+    fn description(&self) -> &str { "Log every prompt and raw response to ~/.kota/transcripts for this session" }
+    fn execute(&self, arg: &str, _context: &mut ContextManager, _model_config: &mut ModelConfig) -> Result<CommandResult> {
+        let path = request_log::transcript_path()
+            .map(|path| path.display().to_string())
+            .unwrap_or_else(|| "(HOME is not set)".to_string());
+        match arg.trim() {
+            "" => {
+                let state = if request_log::enabled() { "on" } else { "off" };
+                Ok(CommandResult::success(format!("Transcript logging is {}: {}", state, path)))
+            }
+            "on" => {
+                request_log::set_enabled(true);
+                Ok(CommandResult::success(format!("Logging requests and responses to {}", path)))
+            }
+            "off" => {
+                request_log::set_enabled(false);
+                Ok(CommandResult::success("Transcript logging off for this session".to_string()))
+            }
+            _ => Ok(CommandResult::error(format!("Usage: {}", self.usage()))),
+        }
+    }
+}
+
+struct StyleCommand;
+impl CommandHandler for StyleCommand {
+    fn name(&self) -> &str { "/style" }
+    fn usage(&self) -> &str { "/style [terse|normal|verbose | language <name>|off | emoji on|off | comments <style>|off | reset]" }
+    fn description(&self) -> &str { "Show or change how replies are written: length, language, emoji and code comments" }
+    fn execute(&self, arg: &str, _context: &mut ContextManager, _model_config: &mut ModelConfig) -> Result<CommandResult> {
+        let config = PromptsConfig::load().unwrap_or_default().style;
+        match arg.trim() {
+            "" => Ok(CommandResult::success(format!("Response style:\n{}", style::effective(&config).describe()))),
+            "reset" => {
+                let path = style::save_overrides(&Default::default())?;
+                Ok(CommandResult::success(format!("Cleared /style changes in {}; using [style] from prompts.toml:\n{}", path.display(), config.describe())))
+            }
+            arg => {
+                let mut overrides = style::load_overrides();
+                match style::apply(&mut overrides, arg) {
+                    Ok(change) => {
+                        style::save_overrides(&overrides)?;
+                        Ok(CommandResult::success(change))
+                    }
+                    Err(e) => Ok(CommandResult::error(format!("{}\nUsage: {}", e, self.usage()))),
+                }
+            }
+        }
+    }
+}
+
+struct TodoCommand;
+impl CommandHandler for TodoCommand {
+    fn name(&self) -> &str { "/todo" }
+    fn usage(&self) -> &str { "/todo [clear]" }
+    fn description(&self) -> &str { "Show or clear the task list the AI keeps with ```todo blocks" }
+    fn execute(&self, arg: &str, _context: &mut ContextManager, _model_config: &mut ModelConfig) -> Result<CommandResult> {
+        let mut list = TodoList::load();
+        match arg.trim() {
+            "" if list.is_empty() => Ok(CommandResult::success("No todo list yet. The AI starts one with a ```todo block for multi-step work".to_string())),
+            "" => Ok(CommandResult::success(format!(
+                "Todo list ({} of {} done):\n{}",
+                list.items().len() - list.remaining(),
+                list.items().len(),
+                list.to_block()
+            ))),
+            "clear" => {
+                let removed = list.clear()?;
+                Ok(CommandResult::success(format!("Cleared {} todo item(s)", removed)))
+            }
+            _ => Ok(CommandResult::error(format!("Usage: {}", self.usage()))),
+        }
+    }
+}
+
+struct HistoryCommand;
+impl CommandHandler for HistoryCommand {
+    fn name(&self) -> &str { "/history" }
+    fn usage(&self) -> &str { "/history search <query> | show <session-id> | clear" }
+    fn description(&self) -> &str { "Search past conversations, command outputs and memories, replay a session, or reset the conversation sent to the model" }
+    fn execute(&self, arg: &str, _context: &mut ContextManager, _model_config: &mut ModelConfig) -> Result<CommandResult> {
+        let (subcommand, rest) = arg.trim().split_once(' ').unwrap_or((arg.trim(), ""));
+        let rest = rest.trim();
+        
+        match subcommand {
+            "search" if !rest.is_empty() => {
+                let Some(query) = history::fts_query(rest) else {
+                    return Ok(CommandResult::error("Search query needs at least one word".to_string()));
+                };
+                let hits = Database::open()?.search_history(&query, 20)?;
+                Ok(CommandResult::success(history::format_search_results(rest, &hits)))
+            }
+            "show" if !rest.is_empty() => {
+                let entries = Database::open()?.session_history(rest)?;
+                Ok(CommandResult::success(history::format_session(rest, &entries)))
+            }
+            "clear" if rest.is_empty() => {
+                let cleared = conversation::clear();
+                Ok(CommandResult::success(format!(
+                    "Cleared {} turn(s) of conversation history; the next request starts fresh",
+                    cleared
+                )))
+            }
+            _ => Ok(CommandResult::error(format!("Usage: {}", self.usage()))),
+        }
+    }
+}
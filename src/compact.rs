@@ -0,0 +1,121 @@
+use anyhow::Result;
+
+use crate::context::{ContextManager, Origin};
+use crate::llm::conversation::{self, Turn};
+use crate::llm::{estimate_tokens, router, ModelConfig};
+use crate::prompts::PromptsConfig;
+use crate::summarize;
+
+// The newest turns stay word for word; everything before them can be summarized
+const KEEP_RECENT_TURNS: usize = 4;
+// Context items not touched in this many adds or edits count as rarely referenced
+const IDLE_OPERATIONS: u64 = 5;
+
+const COMPACT_INSTRUCTIONS: &str = "Condense the earlier conversation and context below into notes a later \
+request can rely on instead of the originals. Keep every decision made and why, file paths, function and type \
+names, constraints the user set, errors still unresolved and unfinished work. Drop pleasantries, ideas that were \
+superseded and file contents that can simply be read again. Reply with short Markdown bullet points only.";
+
+/// What `/compact` would replace: old conversation turns and idle context items
+pub struct Compaction {
+    pub turns: Vec<Turn>,
+    /// Label and full text of each idle item
+    pub items: Vec<(String, String)>,
+}
+
+impl Compaction {
+    /// Old turns and idle items in `context`; None when there's nothing worth condensing
+    pub fn plan(context: &ContextManager) -> Option<Self> {
+        let turns = conversation::older_than(KEEP_RECENT_TURNS);
+        let items: Vec<(String, String)> = context
+            .idle_items(IDLE_OPERATIONS)
+            .into_iter()
+            .map(|(summary, text)| (summary.label, text))
+            .collect();
+        if turns.is_empty() && items.is_empty() {
+            return None;
+        }
+        Some(Self { turns, items })
+    }
+
+    pub fn tokens(&self) -> u64 {
+        self.turns.iter().map(Turn::tokens).sum::<u64>() + self.items.iter().map(|(_, text)| estimate_tokens(text)).sum::<u64>()
+    }
+
+    pub fn describe(&self) -> String {
+        format!("{} conversation turn(s) and {} context item(s), ~{} tokens", self.turns.len(), self.items.len(), self.tokens())
+    }
+
+    /// The turns and items to condense, without the instructions
+    fn material(&self) -> String {
+        let mut prompt = String::new();
+        if !self.turns.is_empty() {
+            prompt.push_str(&format!("\n## Earlier conversation\n\n{}\n", conversation::as_text(&self.turns)));
+        }
+        if !self.items.is_empty() {
+            prompt.push_str("\n## Context items\n");
+            for (_, text) in &self.items {
+                prompt.push_str(&format!("\n{}\n", text));
+            }
+        }
+        prompt
+    }
+
+    /// Have `model` write the condensed notes. A long session is summarized in chunks, whose
+    /// notes are then merged.
+    pub async fn summarize(&self, model: &ModelConfig) -> Result<String> {
+        let material = self.material();
+        // Routed on everything, file headers included, since a chunk can hold part of a
+        // local-only file without its header
+        let model = router::route(model, &material, &PromptsConfig::load().unwrap_or_default())?;
+        let summary = summarize::summarize(material.trim(), COMPACT_INSTRUCTIONS, &model).await?;
+        if summary.is_empty() {
+            return Err(anyhow::anyhow!("The model returned an empty summary; nothing was compacted"));
+        }
+        Ok(summary)
+    }
+
+    /// Swap the originals for `summary`. Replaced items are archived, so `/rehydrate` can bring
+    /// one back. Returns a line describing what was replaced.
+    pub fn apply(self, context: &mut ContextManager, summary: String) -> String {
+        let turn_tokens: u64 = self.turns.iter().map(Turn::tokens).sum();
+        let before = context.total_tokens() + turn_tokens;
+        let forgotten = conversation::forget_oldest(&self.turns);
+        let subject = match (self.turns.is_empty(), self.items.is_empty()) {
+            (false, false) => "earlier conversation and context",
+            (false, true) => "earlier conversation",
+            _ => "earlier context",
+        };
+        let originals: Vec<String> = self.items.into_iter().map(|(_, text)| text).collect();
+        let replaced = context.replace_with_summary(&originals, format!("Summary of {} (from /compact):\n{}", subject, summary), Origin::Retrieved);
+        // Turns the conversation moved past while the summary was written are still there
+        let after = context.total_tokens() + if forgotten == 0 { turn_tokens } else { 0 };
+        format!("Compacted {} turn(s) and {} item(s) into one summary: ~{} -> ~{} tokens", forgotten, replaced, before, after)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_material_includes_turns_and_items() {
+        let compaction = Compaction {
+            turns: vec![Turn { user: "Use sqlite for the cache".to_string(), assistant: "Agreed, rusqlite it is".to_string() }],
+            items: vec![("snippet".to_string(), "--- Snippet --- \nbenchmarks\n--- End Snippet ---".to_string())],
+        };
+        let material = compaction.material();
+        assert!(material.contains("## Earlier conversation\n\nUser: Use sqlite for the cache"));
+        assert!(material.contains("## Context items\n\n--- Snippet --- \nbenchmarks"));
+        assert!(compaction.describe().starts_with("1 conversation turn(s) and 1 context item(s)"));
+    }
+
+    #[test]
+    fn test_material_keeps_file_headers_for_routing() {
+        let compaction = Compaction {
+            turns: Vec::new(),
+            items: vec![("clients/acme.rs".to_string(), "--- File: clients/acme.rs ---\nconst RATE: u32 = 7;\n--- End File ---".to_string())],
+        };
+        assert_eq!(router::context_files(&compaction.material()), vec!["clients/acme.rs".to_string()]);
+    }
+}
@@ -0,0 +1,81 @@
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+
+use crate::llm::{self, LlmProvider, ModelConfig};
+use crate::prompts::CompareConfig;
+
+pub const MAX_COMPARE_MODELS: usize = 3;
+
+/// One model's answer to a compared prompt
+pub struct ComparisonAnswer {
+    pub model: String,
+    pub result: Result<String>,
+    pub elapsed: Duration,
+}
+
+/// Parse `provider` or `provider:model`. Only the first colon splits, so Ollama tags survive.
+pub fn parse_target(spec: &str) -> Result<ModelConfig> {
+    let (provider, model) = match spec.split_once(':') {
+        Some((provider, model)) => (provider, Some(model.trim().to_string()).filter(|model| !model.is_empty())),
+        None => (spec, None),
+    };
+    let provider = LlmProvider::from_name(provider)
+        .ok_or_else(|| anyhow::anyhow!("Unknown provider '{}' in [compare] models", provider.trim()))?;
+    let model_name = model.or_else(|| llm::configured_model(&provider));
+    Ok(ModelConfig { provider, model_name, ..Default::default() })
+}
+
+/// The configured models, capped at three. Fewer than two valid entries is an error.
+pub fn compare_targets(config: &CompareConfig) -> Result<Vec<ModelConfig>> {
+    let mut targets = Vec::new();
+    for spec in &config.models {
+        match parse_target(spec) {
+            Ok(target) => targets.push(target),
+            Err(e) => eprintln!("Warning: {}", e),
+        }
+    }
+    if targets.len() > MAX_COMPARE_MODELS {
+        eprintln!("Warning: /compare uses the first {} of {} configured models", MAX_COMPARE_MODELS, targets.len());
+        targets.truncate(MAX_COMPARE_MODELS);
+    }
+    if targets.len() < 2 {
+        return Err(anyhow::anyhow!("/compare needs at least two models under [compare] models in prompts.toml"));
+    }
+    Ok(targets)
+}
+
+/// Ask every target concurrently; answers come back in the configured order
+pub async fn compare(prompt: &str, context: &str, targets: &[ModelConfig]) -> Vec<ComparisonAnswer> {
+    let requests = targets.iter().map(|target| async move {
+        let started = Instant::now();
+        let result = llm::ask_model_with_config(prompt, context, target).await;
+        ComparisonAnswer { model: target.display_name(), result, elapsed: started.elapsed() }
+    });
+    futures::future::join_all(requests).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_compare_targets() {
+        let target = parse_target("ollama:qwen3:8b").unwrap();
+        assert!(matches!(target.provider, LlmProvider::Ollama));
+        assert_eq!(target.get_model_name(), "qwen3:8b");
+
+        let target = parse_target("openrouter:anthropic/claude-sonnet-4").unwrap();
+        assert_eq!(target.get_model_name(), "anthropic/claude-sonnet-4");
+        assert!(parse_target("Anthropic").unwrap().model_name.is_none());
+        assert!(parse_target("mistral:large").is_err());
+
+        let config = CompareConfig {
+            models: ["anthropic", "bogus", "gemini", "ollama", "openrouter"].iter().map(|m| m.to_string()).collect(),
+        };
+        assert_eq!(compare_targets(&config).unwrap().len(), MAX_COMPARE_MODELS);
+
+        let config = CompareConfig { models: vec!["anthropic".to_string(), "bogus".to_string()] };
+        assert!(compare_targets(&config).is_err());
+    }
+}
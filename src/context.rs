@@ -1,18 +1,198 @@
 use std::fs;
+use std::io::Read;
 use std::path::Path;
+use std::sync::{Arc, OnceLock};
 use anyhow::Context;
+use chrono::{DateTime, Utc};
 use colored::*;
+use tokio::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
 
+use crate::clock::clock;
+use crate::file_browser::FileBrowser;
+use crate::file_select::{self, LineRange, Selection};
+use documents::PageRange;
+use crate::llm::cache::fnv1a;
+use crate::llm::estimate_tokens;
+use crate::prompts::{EmbeddingsConfig, PromptsConfig};
+use crate::response_pipeline::READ_FILE_MARKER;
+use crate::sr_parser::{FileOperation, SearchReplaceBlock};
+use crate::trace::{self, SpanExt};
+use crate::vector_index::VectorIndex;
+
+pub mod bundle;
+pub mod documents;
+pub mod related;
+pub mod repo_map;
+
+/// Written on the line before each pinned item in the formatted context, so fitting a prompt
+/// to the model's window leaves those items whole
+pub const PINNED_MARKER: &str = "[Pinned]";
+// Oldest archived items are forgotten past this many
+const MAX_ARCHIVED: usize = 50;
+// A NUL byte this close to the start means a binary file, as git decides it
+const BINARY_SNIFF_BYTES: u64 = 8000;
+// Skipped files named when adding a directory or glob; the rest are only counted
+const MAX_SKIPPED_SHOWN: usize = 10;
+
+/// Why a file can't go into context as it is
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FileProblem {
+    Binary,
+    /// Its size in bytes, over `[context] max_file_kb`
+    TooLarge(u64),
+}
+
+/// Whether `path` is binary or bigger than `limit` bytes. Unreadable files have no problem
+/// here; reading them reports the error.
+pub fn file_problem(path: &str, limit: u64) -> Option<FileProblem> {
+    let mut head = Vec::new();
+    let mut file = fs::File::open(path).ok()?;
+    let size = file.metadata().ok()?.len();
+    (&mut file).take(BINARY_SNIFF_BYTES).read_to_end(&mut head).ok()?;
+    if head.contains(&0) {
+        Some(FileProblem::Binary)
+    } else if size > limit {
+        Some(FileProblem::TooLarge(size))
+    } else {
+        None
+    }
+}
+
+/// The start and end of `content`, cut at line breaks, when it's over `limit` bytes
+pub fn truncate_middle(content: &str, limit: usize) -> String {
+    if content.len() <= limit {
+        return content.to_string();
+    }
+    let half = limit / 2;
+    let mut head_end = half;
+    while !content.is_char_boundary(head_end) {
+        head_end -= 1;
+    }
+    let head_end = content[..head_end].rfind('\n').map_or(head_end, |i| i + 1);
+    let mut tail_start = content.len() - half;
+    while !content.is_char_boundary(tail_start) {
+        tail_start += 1;
+    }
+    let tail_start = content[tail_start..].find('\n').map_or(tail_start, |i| tail_start + i + 1);
+    let omitted = content[head_end..tail_start].lines().count();
+    format!(
+        "{}[... {} lines omitted; the file is {} ...]\n{}",
+        &content[..head_end],
+        omitted,
+        FileBrowser::format_size(content.len() as u64),
+        &content[tail_start..]
+    )
+}
+
+/// Where a context item came from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Origin {
+    /// Added by the user: files, snippets, documents, prompt files and the project preset
+    User,
+    /// Read at the model's request, or written by /compact
+    Retrieved,
+    /// Step notes gathered by agents
+    Agent,
+    /// Output of a shell command
+    Command,
+    /// Synced from an outside service, like a GitHub issue
+    Bridge,
+}
+
+impl Origin {
+    pub const ALL: [Origin; 5] = [Origin::User, Origin::Retrieved, Origin::Agent, Origin::Command, Origin::Bridge];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Origin::User => "user",
+            Origin::Retrieved => "retrieved",
+            Origin::Agent => "agent",
+            Origin::Command => "command",
+            Origin::Bridge => "bridge",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Origin> {
+        Origin::ALL.into_iter().find(|origin| origin.label().eq_ignore_ascii_case(name.trim()))
+    }
+}
+
+/// One line of the context pane: what an item is, its size, whether it's pinned and where
+/// it came from
+#[derive(Debug, Clone, PartialEq)]
+pub struct ItemSummary {
+    pub label: String,
+    pub tokens: u64,
+    pub pinned: bool,
+    pub read_only: bool,
+    pub origin: Origin,
+    pub added: DateTime<Utc>,
+    pub updated: DateTime<Utc>,
+}
+
+/// One piece of context, with its estimated size and when it was last used
+#[derive(Clone)]
+struct ContextItem {
+    text: String,
+    tokens: u64,
+    /// Pinned items are never evicted to stay under the budget, or summarized to fit a model
+    pinned: bool,
+    /// Reference files, like code from dependencies, that edits are refused for
+    read_only: bool,
+    /// The operation that last added, re-read or edited it, for least-recently-used eviction
+    used: u64,
+    origin: Origin,
+    added: DateTime<Utc>,
+    /// Last re-read or edit
+    updated: DateTime<Utc>,
+}
+
+impl ContextItem {
+    fn summary(&self) -> ItemSummary {
+        ItemSummary {
+            label: item_label(&self.text),
+            tokens: self.tokens,
+            pinned: self.pinned,
+            read_only: self.read_only,
+            origin: self.origin,
+            added: self.added,
+            updated: self.updated,
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct ContextManager {
-    pub items: Vec<String>,
-    pub file_paths: Vec<String>, // Track added file paths
+    items: Vec<ContextItem>,
+    file_paths: Vec<String>, // Track added file paths
+    /// Items cleared, dropped or evicted, oldest first, kept so they can be rehydrated
+    archived: Vec<ContextItem>,
+    // Formatted context, rebuilt lazily after items or files change
+    formatted: OnceLock<Arc<str>>,
+    revision: u64,
+    // Bumped once per add or edit; items it touches are stamped with it
+    operation: u64,
+    /// `[context] max_context_tokens`; past it the least recently used items are evicted
+    max_tokens: Option<u64>,
+    /// `[context] max_file_kb` in bytes; bigger files are refused or truncated
+    max_file_bytes: u64,
+    /// Leave everything but user-added items out of prompts, for sensitive requests
+    user_only: bool,
 }
 
 impl ContextManager {
     pub fn new() -> Self {
+        let config = PromptsConfig::load().unwrap_or_default().context;
         let mut context = Self { 
             items: Vec::new(),
             file_paths: Vec::new(),
+            archived: Vec::new(),
+            formatted: OnceLock::new(),
+            revision: 0,
+            operation: 0,
+            max_tokens: config.max_context_tokens,
+            max_file_bytes: config.max_file_kb.saturating_mul(1024),
+            user_only: false,
         };
         
         // Auto-load prompts directory if it exists
@@ -23,21 +203,473 @@ impl ContextManager {
         context
     }
 
-    pub fn add_file(&mut self, file_path: &str) -> anyhow::Result<()> {
+    /// Add a file, or every file a directory or glob like `src/**/*.rs` matches. Files already
+    /// in context are re-read, replacing their earlier copy or edit diff.
+    /// Binary files are refused, and so are files over `[context] max_file_kb`.
+    pub fn add_file(&mut self, file_path: &str) -> anyhow::Result<Selection> {
+        self.add_files(file_path, Origin::User, false)
+    }
+
+    /// `add_file`, but files over `[context] max_file_kb` go in as their start and end
+    pub fn add_file_truncated(&mut self, file_path: &str) -> anyhow::Result<Selection> {
+        self.add_files(file_path, Origin::User, true)
+    }
+
+    /// `add_file` for files that didn't come from the user, like ones the model asked to read.
+    /// Nobody is there to choose, so large files are truncated rather than refused.
+    pub fn add_file_from(&mut self, file_path: &str, origin: Origin) -> anyhow::Result<Selection> {
+        self.add_files(file_path, origin, origin != Origin::User)
+    }
+
+    fn add_files(&mut self, file_path: &str, origin: Origin, truncate: bool) -> anyhow::Result<Selection> {
+        let selection = file_select::select(file_path)?;
+        self.operation += 1;
+        if !selection.expanded {
+            let tokens = self.insert_file(file_path, origin, truncate)?;
+            println!("{} [x] {}", "Context:".dimmed(), file_path);
+            self.enforce_budget();
+            return Ok(Selection { tokens, ..selection });
+        }
+        
+        // Binary, oversized and unreadable files in a directory are skipped rather than failing the rest
+        let mut added = Vec::new();
+        let mut skipped = Vec::new();
+        let mut tokens = 0;
+        for file in &selection.files {
+            let problem = if documents::is_document(file) { None } else { file_problem(file, self.max_file_bytes) };
+            match problem {
+                Some(FileProblem::Binary) => skipped.push(format!("{} (binary)", file)),
+                Some(FileProblem::TooLarge(size)) if !truncate => {
+                    skipped.push(format!("{} ({})", file, FileBrowser::format_size(size)));
+                }
+                _ => match self.insert_file(file, origin, truncate) {
+                    Ok(file_tokens) => {
+                        added.push(file.clone());
+                        tokens += file_tokens;
+                    }
+                    Err(_) => skipped.push(format!("{} (unreadable)", file)),
+                },
+            }
+        }
+        if added.is_empty() {
+            return Err(anyhow::anyhow!("None of the files in {} could be added: {}", file_path, skipped_list(&skipped)));
+        }
+        let added = Selection { files: added, tokens, expanded: true, skipped };
+        let note = if added.skipped.is_empty() { String::new() } else { format!(", skipped {}", skipped_list(&added.skipped)) };
+        println!("{} [x] {} from {}{}", "Context:".dimmed(), added.summary(), file_path, note);
+        self.enforce_budget();
+        Ok(added)
+    }
+    
+    /// Add only some lines of a file. The header says which, and the lines are kept exactly as
+    /// they are in the file, so search/replace blocks written against them still apply.
+    pub fn add_file_lines(&mut self, file_path: &str, range: LineRange) -> anyhow::Result<u64> {
+        self.operation += 1;
+        let tokens = self.insert_file_lines(file_path, range, Origin::User)?;
+        println!("{} [x] {}:{}", "Context:".dimmed(), file_path, range);
+        self.enforce_budget();
+        Ok(tokens)
+    }
+
+    fn insert_file_lines(&mut self, file_path: &str, range: LineRange, origin: Origin) -> anyhow::Result<u64> {
+        if documents::is_document(file_path) {
+            return Err(anyhow::anyhow!("Line ranges are for text files; use --pages for {}", file_path));
+        }
+        if file_problem(file_path, u64::MAX) == Some(FileProblem::Binary) {
+            return Err(anyhow::anyhow!("{} is a binary file; only text files can be added to context", file_path));
+        }
+        let content = fs::read_to_string(file_path)
+            .with_context(|| format!("Failed to read file: {}", file_path))?;
+        let (lines, end) = range.slice(&content)?;
+        let total = content.lines().count();
+        let item = format!(
+            "--- File: {} (lines {}-{} of {}) ---\n{}\n--- End File: {} ---",
+            file_path, range.start, end, total, lines.join("\n"), file_path
+        );
+        let tokens = estimate_tokens(&item);
+        match self.file_item(file_path) {
+            Some(i) => {
+                self.replace_item(i, item);
+                self.items[i].origin = origin;
+            }
+            None => self.push_item(item, false, origin),
+        }
+        if !self.is_file_in_context(file_path) {
+            self.file_paths.push(file_path.to_string());
+        }
+        self.archived.retain(|item| item_file_path(&item.text) != Some(file_path));
+        self.mark_changed();
+        Ok(tokens)
+    }
+
+    /// Add the text of a PDF or .docx, optionally only some of a PDF's pages
+    pub fn add_document(&mut self, file_path: &str, pages: Option<PageRange>) -> anyhow::Result<u64> {
+        self.operation += 1;
+        let tokens = self.insert_document(file_path, pages, Origin::User)?;
+        match pages {
+            Some(pages) => println!("{} [x] {} (pages {})", "Context:".dimmed(), file_path, pages),
+            None => println!("{} [x] {}", "Context:".dimmed(), file_path),
+        }
+        self.enforce_budget();
+        Ok(tokens)
+    }
+
+    /// Extracted text goes in as a document item rather than a file, since it can't be edited.
+    /// Adding the same pages again replaces the earlier copy.
+    fn insert_document(&mut self, file_path: &str, pages: Option<PageRange>, origin: Origin) -> anyhow::Result<u64> {
+        let text = documents::extract(file_path, pages)?;
+        let name = match pages {
+            Some(pages) => format!("{} (pages {})", file_path, pages),
+            None => file_path.to_string(),
+        };
+        let item = format!("--- Document: {} ---\n{}\n--- End Document: {} ---", name, text, file_path);
+        let tokens = estimate_tokens(&item);
+        let header = format!("--- Document: {} ---\n", name);
+        match self.items.iter().position(|item| item.text.starts_with(&header)) {
+            Some(i) => {
+                self.replace_item(i, item);
+                self.items[i].origin = origin;
+            }
+            None => self.push_item(item, false, origin),
+        }
+        self.mark_changed();
+        Ok(tokens)
+    }
+    
+    /// Add or replace one file's item, returning its estimated tokens. Files over the size
+    /// limit are refused unless `truncate`, which keeps only their start and end.
+    fn insert_file(&mut self, file_path: &str, origin: Origin, truncate: bool) -> anyhow::Result<u64> {
+        if documents::is_document(file_path) {
+            return self.insert_document(file_path, None, origin);
+        }
+        match file_problem(file_path, self.max_file_bytes) {
+            Some(FileProblem::Binary) => {
+                return Err(anyhow::anyhow!("{} is a binary file; only text files can be added to context", file_path));
+            }
+            Some(FileProblem::TooLarge(size)) if !truncate => {
+                return Err(anyhow::anyhow!(
+                    "{} is {}, over the {} limit for one file ([context] max_file_kb). Add it with --truncate to keep only its start and end",
+                    file_path, FileBrowser::format_size(size), FileBrowser::format_size(self.max_file_bytes)
+                ));
+            }
+            _ => {}
+        }
         let content = fs::read_to_string(file_path)
             .with_context(|| format!("Failed to read file: {}", file_path))?;
-        self.items.push(format!("--- File: {} ---\n{}\n--- End File: {} ---", file_path, content, file_path));
+        let content = truncate_middle(&content, self.max_file_bytes as usize);
+        let item = format!("--- File: {} ---\n{}\n--- End File: {} ---", file_path, content, file_path);
+        let tokens = estimate_tokens(&item);
+        
+        match self.file_item(file_path) {
+            Some(i) => {
+                self.replace_item(i, item);
+                self.items[i].origin = origin;
+            }
+            None => self.push_item(item, false, origin),
+        }
         
         // Track the file path
-        self.file_paths.push(file_path.to_string());
+        if !self.is_file_in_context(file_path) {
+            self.file_paths.push(file_path.to_string());
+        }
+        self.archived.retain(|item| item_file_path(&item.text) != Some(file_path));
+        self.mark_changed();
+        Ok(tokens)
+    }
+
+    /// Swap the full text of edited files for the edits made to them. Follow-up prompts carry
+    /// only the changes; the model asks to read a file again when it needs the whole thing.
+    /// Files the edits created are added whole.
+    pub fn record_edits(&mut self, applied: &[SearchReplaceBlock]) {
+        self.operation += 1;
+        let mut edited = Vec::new();
+        for block in applied {
+            let path = &block.file_path;
+            let Some(i) = self.file_item(path) else {
+                if block.search_lines.trim().is_empty() {
+                    match self.insert_file(path, Origin::Retrieved, true) {
+                        Ok(tokens) => println!("{} [+] {} (new file, ~{} tokens)", "Context:".dimmed(), path, tokens),
+                        Err(e) => eprintln!("Warning: Failed to add {} to context: {}", path, e),
+                    }
+                }
+                continue;
+            };
+            let current = fs::read_to_string(path).unwrap_or_default();
+            let hunk = edit_hunk(block, &current);
+            
+            let end = format!("\n--- End Diff: {} ---", path);
+            let diff = match self.items[i].text.strip_suffix(&end) {
+                Some(diff) => format!("{}\n{}{}", diff, hunk, end),
+                None => format!(
+                    "--- Diff: {} ---\nEdited since it was added to context; only the changes are shown. \
+                    To see the whole updated file, call add_to_context with its path or reply with the line: {}{}\n{}{}",
+                    path, READ_FILE_MARKER, path, hunk, end
+                ),
+            };
+            self.replace_item(i, diff);
+            if !edited.contains(path) {
+                edited.push(path.clone());
+            }
+        }
         
-        println!("{} [x] {}", "Context:".dimmed(), file_path);
-        Ok(())
+        if !edited.is_empty() {
+            self.mark_changed();
+            for path in edited {
+                println!("{} [~] {} (diff)", "Context:".dimmed(), path);
+            }
+            self.enforce_budget();
+        }
+    }
+    
+    /// Follow applied deletes and renames: deleted files leave the context, and renamed ones
+    /// are read again under their new path, staying pinned if they were
+    pub fn record_file_operations(&mut self, applied: &[FileOperation]) {
+        self.operation += 1;
+        for operation in applied {
+            let Some(i) = self.file_item(operation.path()) else {
+                continue;
+            };
+            let item = self.remove_item(i);
+            match operation {
+                FileOperation::Delete { path } => println!("{} [-] {} (deleted)", "Context:".dimmed(), path),
+                FileOperation::Rename { from, to } => match self.insert_file(to, item.origin, true) {
+                    Ok(_) => {
+                        if let Some(j) = self.file_item(to) {
+                            self.items[j].pinned = item.pinned;
+                        }
+                        println!("{} [~] {} -> {}", "Context:".dimmed(), from, to);
+                    }
+                    Err(e) => eprintln!("Warning: Failed to add {} to context: {}", to, e),
+                },
+            }
+        }
+        self.enforce_budget();
+    }
+    
+    /// Read files in context again after they changed outside an edit, like `/undo`. Files that
+    /// no longer exist are dropped.
+    pub fn reload_files(&mut self, paths: &[String]) {
+        self.operation += 1;
+        for path in paths {
+            let Some(i) = self.file_item(path) else {
+                continue;
+            };
+            if !Path::new(path).exists() {
+                self.remove_item(i);
+                continue;
+            }
+            let (origin, pinned) = (self.items[i].origin, self.items[i].pinned);
+            match self.insert_file(path, origin, true) {
+                Ok(_) => {
+                    if let Some(j) = self.file_item(path) {
+                        self.items[j].pinned = pinned;
+                    }
+                }
+                Err(e) => eprintln!("Warning: Failed to read {} again: {}", path, e),
+            }
+        }
+        self.enforce_budget();
+    }
+    
+    /// Index of the item holding `file_path`, as a full file or an edit diff
+    fn file_item(&self, file_path: &str) -> Option<usize> {
+        self.items.iter().position(|item| item_file_path(&item.text) == Some(file_path))
+    }
+    
+    fn push_item(&mut self, text: String, pinned: bool, origin: Origin) {
+        let tokens = estimate_tokens(&text);
+        let now = Utc::now();
+        self.items.push(ContextItem { text, tokens, pinned, read_only: false, used: self.operation, origin, added: now, updated: now });
+    }
+    
+    /// New text for an item, keeping it pinned if it was
+    fn replace_item(&mut self, index: usize, text: String) {
+        let item = &mut self.items[index];
+        item.tokens = estimate_tokens(&text);
+        item.text = text;
+        item.used = self.operation;
+        item.updated = Utc::now();
+    }
+
+    /// The item `target` names: its number as shown by /show_context, or the path of a file,
+    /// edit diff or prompt file, or a document's label
+    fn find_item(&self, target: &str) -> Option<usize> {
+        let target = target.trim();
+        if let Ok(number) = target.parse::<usize>() {
+            return (1..=self.items.len()).contains(&number).then(|| number - 1);
+        }
+        let path = target.strip_prefix("./").unwrap_or(target);
+        self.file_item(path).or_else(|| self.items.iter().position(|item| item_label(&item.text) == path))
+    }
+
+    /// Remove one item, returning its label
+    pub fn drop_item(&mut self, target: &str) -> anyhow::Result<String> {
+        let index = self.find_item(target).ok_or_else(|| anyhow::anyhow!("Nothing in context matches {}", target.trim()))?;
+        let item = self.remove_item(index);
+        let label = item_label(&item.text);
+        println!("{} [-] {} (~{} tokens, archived)", "Context:".dimmed(), label, item.tokens);
+        self.archive(item);
+        Ok(label)
+    }
+
+    /// Take an item out of context, forgetting its path if it's a file
+    fn remove_item(&mut self, index: usize) -> ContextItem {
+        let item = self.items.remove(index);
+        if let Some(path) = item_file_path(&item.text) {
+            self.file_paths.retain(|file_path| file_path != path);
+        }
+        self.mark_changed();
+        item
+    }
+
+    /// Keep a removed item for `rehydrate`, replacing an older copy with the same label
+    fn archive(&mut self, item: ContextItem) {
+        let label = item_label(&item.text);
+        self.archived.retain(|archived| item_label(&archived.text) != label);
+        self.archived.push(item);
+        if self.archived.len() > MAX_ARCHIVED {
+            let excess = self.archived.len() - MAX_ARCHIVED;
+            self.archived.drain(..excess);
+        }
+        self.mark_changed();
+    }
+
+    /// Bring back an archived item by its number in the archived list or its path. Files are
+    /// read again so they come back current; snippets come back as they were.
+    pub fn rehydrate(&mut self, target: &str) -> anyhow::Result<String> {
+        let target = target.trim();
+        let index = match target.parse::<usize>() {
+            Ok(number) => (1..=self.archived.len()).contains(&number).then(|| number - 1),
+            Err(_) => {
+                let path = target.strip_prefix("./").unwrap_or(target);
+                self.archived.iter().position(|item| item_label(&item.text) == path)
+            }
+        };
+        let index = index.ok_or_else(|| anyhow::anyhow!("Nothing archived matches {}", target))?;
+        let item = self.archived.remove(index);
+        let label = item_label(&item.text);
+        self.operation += 1;
+        match item_file_path(&item.text) {
+            Some(path) => {
+                let path = path.to_string();
+                // It was in context before, so a file that has since grown comes back truncated;
+                // a line range comes back as the same lines
+                let inserted = match item_line_range(&item.text) {
+                    Some(range) => self.insert_file_lines(&path, range, item.origin),
+                    None => self.insert_file(&path, item.origin, true),
+                };
+                if let Err(e) = inserted {
+                    self.archived.insert(index, item);
+                    return Err(e);
+                }
+                if let Some(i) = self.file_item(&path) {
+                    self.items[i].pinned = item.pinned;
+                    self.items[i].added = item.added;
+                }
+            }
+            None => {
+                self.items.push(ContextItem { used: self.operation, ..item });
+                self.mark_changed();
+            }
+        }
+        println!("{} [x] {} (rehydrated)", "Context:".dimmed(), label);
+        self.enforce_budget();
+        Ok(label)
+    }
+
+    /// Unpinned items not added, re-read or edited in the last `idle_operations` adds and
+    /// edits, with their text, for `/compact`
+    pub fn idle_items(&self, idle_operations: u64) -> Vec<(ItemSummary, String)> {
+        self.items
+            .iter()
+            .filter(|item| !item.pinned && item.used + idle_operations <= self.operation)
+            .map(|item| (item.summary(), item.text.clone()))
+            .collect()
+    }
+
+    /// Archive the items whose text is in `originals` and add `summary` in their place.
+    /// Items changed since they were picked are left alone. Returns how many were replaced.
+    pub fn replace_with_summary(&mut self, originals: &[String], summary: String, origin: Origin) -> usize {
+        let mut replaced = 0;
+        while let Some(index) = self.items.iter().position(|item| !item.pinned && originals.contains(&item.text)) {
+            let item = self.remove_item(index);
+            self.archive(item);
+            replaced += 1;
+        }
+        self.add_snippet_from(summary, origin);
+        replaced
+    }
+
+    pub fn archived_summaries(&self) -> Vec<ItemSummary> {
+        self.archived
+            .iter()
+            .map(ContextItem::summary)
+            .collect()
+    }
+
+    /// Pin or unpin one item, returning its label
+    pub fn set_pinned(&mut self, target: &str, pinned: bool) -> anyhow::Result<String> {
+        let index = self.find_item(target).ok_or_else(|| anyhow::anyhow!("Nothing in context matches {}", target.trim()))?;
+        self.items[index].pinned = pinned;
+        self.mark_changed();
+        Ok(item_label(&self.items[index].text))
+    }
+
+    pub fn item_summaries(&self) -> Vec<ItemSummary> {
+        self.items
+            .iter()
+            .map(ContextItem::summary)
+            .collect()
     }
 
     pub fn add_snippet(&mut self, snippet: String) {
-        self.items.push(format!("--- Snippet --- \n{}\n--- End Snippet ---", snippet));
-        println!("{} [x] snippet", "Context:".dimmed());
+        self.add_snippet_from(snippet, Origin::User);
+    }
+
+    /// A snippet the user didn't add themselves, like command output or an agent's notes
+    pub fn add_snippet_from(&mut self, snippet: String, origin: Origin) {
+        self.operation += 1;
+        self.push_item(format!("--- Snippet --- \n{}\n--- End Snippet ---", snippet), false, origin);
+        self.mark_changed();
+        match origin {
+            Origin::User => println!("{} [x] snippet", "Context:".dimmed()),
+            other => println!("{} [x] snippet ({})", "Context:".dimmed(), other.label()),
+        }
+        self.enforce_budget();
+    }
+    
+    /// Estimated tokens across all items
+    pub fn total_tokens(&self) -> u64 {
+        self.items.iter().map(|item| item.tokens).sum()
+    }
+    
+    /// "~12000 tokens", or "~12000/50000 tokens" with a budget
+    pub fn usage_label(&self) -> String {
+        match self.max_tokens {
+            Some(max) => format!("~{}/{} tokens", self.total_tokens(), max),
+            None => format!("~{} tokens", self.total_tokens()),
+        }
+    }
+    
+    /// Drop the least recently used unpinned items until the context fits `max_context_tokens`.
+    /// Items from the add or edit that went over are kept, even when they alone are too big.
+    fn enforce_budget(&mut self) {
+        let Some(max) = self.max_tokens else { return };
+        while self.total_tokens() > max {
+            let oldest = self.items
+                .iter()
+                .enumerate()
+                .filter(|(_, item)| !item.pinned && item.used < self.operation)
+                .min_by_key(|(_, item)| item.used)
+                .map(|(i, _)| i);
+            let Some(i) = oldest else {
+                eprintln!("Warning: Context is ~{} tokens, over the {} budget, but nothing else can be evicted", self.total_tokens(), max);
+                break;
+            };
+            let item = self.remove_item(i);
+            println!("{} [-] {} (evicted, ~{} tokens)", "Context:".dimmed(), item_label(&item.text), item.tokens);
+            self.archive(item);
+        }
     }
 
     pub fn show_context(&self) {
@@ -46,41 +678,225 @@ impl ContextManager {
         } else {
             println!("--- Current Context ---");
             for (i, item) in self.items.iter().enumerate() {
-                println!("\n[Item {}]\n{}", i + 1, item);
+                let pinned = if item.pinned { ", pinned" } else { "" };
+                let read_only = if item.read_only { ", read-only" } else { "" };
+                let excluded = if self.excludes(item) { ", left out of prompts" } else { "" };
+                let updated = if item.updated != item.added {
+                    format!(", updated {}", clock().date_time(&item.updated))
+                } else {
+                    String::new()
+                };
+                let details = format!(
+                    "(~{} tokens{}{}, {}, added {}{}{})",
+                    item.tokens, pinned, read_only, item.origin.label(), clock().date_time(&item.added), updated, excluded
+                );
+                println!("\n[Item {}] {}\n{}", i + 1, details.dimmed(), item.text);
+            }
+            let totals: Vec<String> = self
+                .origin_totals()
+                .iter()
+                .map(|(origin, count, tokens)| format!("{} {} (~{} tokens)", origin.label(), count, tokens))
+                .collect();
+            println!("\n{} {}", "By origin:".dimmed(), totals.join(", "));
+            println!("--- End Context ({}) ---", self.usage_label());
+        }
+    }
+
+    /// Items closest in meaning to `query` as (item number, similarity), best first, using
+    /// the `context` vector index. Items are keyed by a hash of their text, so edited or
+    /// re-added files are embedded again. Without embeddings, items containing `query` match.
+    pub async fn relevant_items(&self, config: &EmbeddingsConfig, query: &str, limit: usize) -> anyhow::Result<Vec<(usize, Option<f32>)>> {
+        if !config.enabled {
+            let query = query.to_lowercase();
+            return Ok(self.items
+                .iter()
+                .enumerate()
+                .filter(|(_, item)| item.text.to_lowercase().contains(&query))
+                .take(limit)
+                .map(|(i, _)| (i + 1, None))
+                .collect());
+        }
+        
+        let ids: Vec<String> = self.items.iter().map(|item| format!("{:016x}", fnv1a(&[&item.text]))).collect();
+        let documents: Vec<(String, String)> = ids.iter().cloned().zip(self.items.iter().map(|item| item.text.clone())).collect();
+        let mut index = VectorIndex::open("context", &config.model);
+        let hits = index.refresh_and_search(config, &documents, query, limit).await?;
+        Ok(hits
+            .into_iter()
+            .filter_map(|(score, id)| ids.iter().position(|item_id| *item_id == id).map(|i| (i + 1, Some(score))))
+            .collect())
+    }
+    
+    /// Print the items picked by `relevant_items`
+    pub fn show_items(&self, items: &[(usize, Option<f32>)]) {
+        if items.is_empty() {
+            println!("No context items match.");
+            return;
+        }
+        for (number, score) in items {
+            let Some(item) = self.items.get(number - 1) else {
+                continue;
+            };
+            match score {
+                Some(score) => println!("\n[Item {}] {}\n{}", number, format!("(similarity {:.2})", score).dimmed(), item.text),
+                None => println!("\n[Item {}]\n{}", number, item.text),
             }
-            println!("--- End Context ---");
         }
     }
 
+    /// Move every item to the archive, where `rehydrate` can bring any of them back
     pub fn clear_context(&mut self) {
-        self.items.clear();
+        let items = std::mem::take(&mut self.items);
+        let cleared = items.len();
         self.file_paths.clear();
-        println!("{} [ ] (all cleared)", "Context:".dimmed());
+        for item in items {
+            self.archive(item);
+        }
+        self.mark_changed();
+        println!("{} [ ] (all cleared, {} archived)", "Context:".dimmed(), cleared);
+    }
+    
+    /// Archive every unpinned item that came from `origin`, like the command output that piled
+    /// up over a long session. Returns how many were removed.
+    pub fn clear_origin(&mut self, origin: Origin) -> usize {
+        let mut cleared = 0;
+        let mut i = 0;
+        while i < self.items.len() {
+            if self.items[i].origin == origin && !self.items[i].pinned {
+                let item = self.remove_item(i);
+                self.archive(item);
+                cleared += 1;
+            } else {
+                i += 1;
+            }
+        }
+        if cleared > 0 {
+            println!("{} [ ] ({} {} item(s) cleared and archived)", "Context:".dimmed(), cleared, origin.label());
+        }
+        cleared
+    }
+    
+    /// How many items and estimated tokens came from each origin, skipping origins with none
+    pub fn origin_totals(&self) -> Vec<(Origin, usize, u64)> {
+        Origin::ALL
+            .into_iter()
+            .map(|origin| {
+                let items: Vec<&ContextItem> = self.items.iter().filter(|item| item.origin == origin).collect();
+                (origin, items.len(), items.iter().map(|item| item.tokens).sum())
+            })
+            .filter(|(_, count, _)| *count > 0)
+            .collect()
     }
     
     pub fn is_file_in_context(&self, file_path: &str) -> bool {
         self.file_paths.contains(&file_path.to_string())
     }
+    
+    /// Mark files just added as reference only, or editable again
+    pub fn set_read_only(&mut self, files: &[String], read_only: bool) {
+        for file in files {
+            if let Some(i) = self.file_item(file) {
+                self.items[i].read_only = read_only;
+            }
+        }
+        self.mark_changed();
+    }
+    
+    pub fn is_read_only(&self, file_path: &str) -> bool {
+        self.file_item(file_path).is_some_and(|i| self.items[i].read_only)
+    }
+    
+    pub fn file_paths(&self) -> &[String] {
+        &self.file_paths
+    }
+    
+    /// Bumped on every change, so callers can tell when a cached view is stale
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+    
+    fn mark_changed(&mut self) {
+        self.formatted = OnceLock::new();
+        self.revision += 1;
+    }
 
     pub fn get_formatted_context(&self) -> String {
+        self.formatted_context().to_string()
+    }
+    
+    /// Formatted context shared from the cache; only rebuilt after the context changes
+    pub fn formatted_context(&self) -> Arc<str> {
+        self.formatted
+            .get_or_init(|| Arc::from(self.format_context()))
+            .clone()
+    }
+    
+    /// Only send items the user added, leaving out retrieved files, command output and
+    /// synced content until it's turned off again
+    pub fn set_user_only(&mut self, user_only: bool) {
+        self.user_only = user_only;
+        self.mark_changed();
+    }
+
+    pub fn user_only(&self) -> bool {
+        self.user_only
+    }
+
+    fn excludes(&self, item: &ContextItem) -> bool {
+        self.user_only && item.origin != Origin::User
+    }
+
+    fn format_context(&self) -> String {
+        let _span = trace::span("context.format").with("files", self.file_paths.len());
         let mut full_context = String::new();
+        let file_paths: Vec<&String> = self.file_paths
+            .iter()
+            .filter(|path| !self.file_item(path).is_some_and(|i| self.excludes(&self.items[i])))
+            .collect();
+        
+        let (read_only, file_paths): (Vec<&String>, Vec<&String>) = file_paths.into_iter().partition(|path| self.is_read_only(path));
         
         // Always include the list of accessible files
-        if !self.file_paths.is_empty() {
+        if !file_paths.is_empty() {
             full_context.push_str("Files currently in context (you have read access to these files):\n");
-            for file_path in &self.file_paths {
+            for file_path in file_paths {
                 full_context.push_str(&format!("- {}\n", file_path));
             }
             full_context.push_str("\nIMPORTANT: You can only suggest edits to files listed above. If you need to edit a file not in this list, tell the user to run: :add <filename>\n\n");
         } else {
             full_context.push_str("No files in context. To edit files, the user must first add them with: :add <filename>\n\n");
         }
+        if !read_only.is_empty() {
+            full_context.push_str("Read-only reference files (use them to understand the code, but never suggest edits to them):\n");
+            for file_path in read_only {
+                full_context.push_str(&format!("- {}\n", file_path));
+            }
+            full_context.push('\n');
+        }
+        
+        // Archived files are named so the model can ask for one back instead of guessing at it
+        let archived: Vec<&str> = self.archived.iter().rev().filter_map(|item| item_file_path(&item.text)).collect();
+        if !archived.is_empty() {
+            full_context.push_str(&format!(
+                "Archived files (cleared from context; to bring one back, call add_to_context with its path or reply with the line: {}<path>):\n",
+                READ_FILE_MARKER
+            ));
+            for path in archived {
+                full_context.push_str(&format!("- {}\n", path));
+            }
+            full_context.push('\n');
+        }
         
         // Add the actual context items
-        if !self.items.is_empty() {
+        let items: Vec<&ContextItem> = self.items.iter().filter(|item| !self.excludes(item)).collect();
+        if !items.is_empty() {
             full_context.push_str("Relevant context:\n");
-            for item in &self.items {
-                full_context.push_str(item);
+            for item in items {
+                if item.pinned {
+                    full_context.push_str(PINNED_MARKER);
+                    full_context.push('\n');
+                }
+                full_context.push_str(&item.text);
                 full_context.push_str("\n\n");
             }
         }
@@ -115,9 +931,10 @@ impl ContextManager {
                 let content = fs::read_to_string(&path)
                     .with_context(|| format!("Failed to read prompt file: {}", file_path_str))?;
                 
-                // Add to context as a prompt file
-                self.items.push(format!("--- Prompt File: {} ---\n{}\n--- End Prompt File: {} ---", 
-                    file_path_str, content, file_path_str));
+                // Add to context as a prompt file, pinned since it's standing instructions
+                self.push_item(format!("--- Prompt File: {} ---\n{}\n--- End Prompt File: {} ---", 
+                    file_path_str, content, file_path_str), true, Origin::User);
+                self.mark_changed();
                 
                 // Don't track prompt files in file_paths as they shouldn't be edited
                 // Instead, just note that we loaded them
@@ -137,3 +954,453 @@ impl ContextManager {
     
 }
 
+/// `a.png (binary), b.json (2.1 MB)`, naming only the first few
+fn skipped_list(skipped: &[String]) -> String {
+    let mut list = skipped.iter().take(MAX_SKIPPED_SHOWN).cloned().collect::<Vec<_>>().join(", ");
+    if skipped.len() > MAX_SKIPPED_SHOWN {
+        list.push_str(&format!(" and {} more", skipped.len() - MAX_SKIPPED_SHOWN));
+    }
+    list
+}
+
+/// The path of a file or edit diff item
+fn item_file_path(text: &str) -> Option<&str> {
+    let rest = text.strip_prefix("--- File: ").or_else(|| text.strip_prefix("--- Diff: "))?;
+    rest.split_once(" ---\n").map(|(path, _)| file_select::strip_line_range(path))
+}
+
+/// The lines a partial file item holds, from its `(lines 100-250 of 900)` header
+fn item_line_range(text: &str) -> Option<LineRange> {
+    let header = text.strip_prefix("--- File: ")?.split_once(" ---\n")?.0;
+    let note = header.rsplit_once(" (lines ")?.1.strip_suffix(')')?;
+    LineRange::parse(note.split_once(" of ")?.0).ok()
+}
+
+/// A short name for an item: its path, or its kind for snippets
+fn item_label(text: &str) -> String {
+    if let Some(path) = item_file_path(text) {
+        return path.to_string();
+    }
+    let header = text.lines().next().unwrap_or("");
+    let named = header.strip_prefix("--- Prompt File: ").or_else(|| header.strip_prefix("--- Document: "));
+    if let Some(path) = named.and_then(|rest| rest.strip_suffix(" ---")) {
+        return path.to_string();
+    }
+    header.trim_matches(|c: char| c == '-' || c.is_whitespace()).to_lowercase()
+}
+
+/// An applied edit as a diff hunk, located by where the replacement now sits in `current`
+fn edit_hunk(block: &SearchReplaceBlock, current: &str) -> String {
+    let mut hunk = match current.find(&block.replace_lines) {
+        Some(offset) if !block.replace_lines.is_empty() => format!("@@ line {} @@\n", current[..offset].matches('\n').count() + 1),
+        _ => "@@\n".to_string(),
+    };
+    for line in block.search_lines.lines() {
+        hunk.push_str(&format!("-{}\n", line));
+    }
+    for line in block.replace_lines.lines() {
+        hunk.push_str(&format!("+{}\n", line));
+    }
+    hunk.trim_end().to_string()
+}
+
+/// Context shared by the TUI, background LLM tasks and agents. Readers don't block each
+/// other, and waiting for the lock yields to the runtime instead of blocking a thread.
+#[derive(Clone)]
+pub struct SharedContext {
+    inner: Arc<RwLock<ContextManager>>,
+}
+
+impl SharedContext {
+    pub fn new(context_manager: ContextManager) -> Self {
+        Self { inner: Arc::new(RwLock::new(context_manager)) }
+    }
+    
+    pub async fn write(&self) -> RwLockWriteGuard<'_, ContextManager> {
+        self.inner.write().await
+    }
+    
+    /// For synchronous UI code. None while a writer holds the lock.
+    pub fn try_read(&self) -> Option<RwLockReadGuard<'_, ContextManager>> {
+        self.inner.try_read().ok()
+    }
+    
+    /// For synchronous UI code. None while anyone else holds the lock.
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<'_, ContextManager>> {
+        self.inner.try_write().ok()
+    }
+    
+    /// Formatted context for prompt assembly; the lock is released before this returns
+    pub async fn formatted(&self) -> Arc<str> {
+        self.inner.read().await.formatted_context()
+    }
+    
+    /// A copy of the context to hold across long waits, such as approval prompts,
+    /// without keeping writers out
+    pub async fn snapshot(&self) -> ContextManager {
+        self.inner.read().await.clone()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_formatted_context_cache() {
+        let mut context = ContextManager::new();
+        let revision = context.revision();
+        
+        // Repeated reads share the cached string
+        let first = context.formatted_context();
+        let second = context.formatted_context();
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(context.revision(), revision);
+        
+        // Changes invalidate it
+        context.add_snippet("fn cached() {}".to_string());
+        assert_eq!(context.revision(), revision + 1);
+        let third = context.formatted_context();
+        assert!(!Arc::ptr_eq(&first, &third));
+        assert!(third.contains("fn cached() {}"));
+        
+        context.clear_context();
+        assert!(!context.formatted_context().contains("fn cached() {}"));
+    }
+
+    #[test]
+    fn test_edits_replace_file_with_diff() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("lib.rs").to_string_lossy().to_string();
+        fs::write(&path, "fn a() {}\nfn b() {}\n").unwrap();
+        
+        let mut context = ContextManager::new();
+        context.add_file(&path).unwrap();
+        let edit = |search: &str, replace: &str| SearchReplaceBlock {
+            file_path: path.clone(),
+            search_lines: search.to_string(),
+            replace_lines: replace.to_string(),
+        };
+        
+        fs::write(&path, "fn a() {}\nfn b() -> u8 { 1 }\n").unwrap();
+        context.record_edits(&[edit("fn b() {}", "fn b() -> u8 { 1 }")]);
+        let formatted = context.formatted_context();
+        assert!(formatted.contains(&format!("--- Diff: {} ---", path)));
+        assert!(formatted.contains("@@ line 2 @@\n-fn b() {}\n+fn b() -> u8 { 1 }"));
+        assert!(!formatted.contains("fn a() {}"));
+        
+        // Further edits add hunks to the same diff
+        fs::write(&path, "fn a2() {}\nfn b() -> u8 { 1 }\n").unwrap();
+        context.record_edits(&[edit("fn a() {}", "fn a2() {}")]);
+        assert_eq!(context.formatted_context().matches("--- Diff: ").count(), 1);
+        assert!(context.formatted_context().contains("@@ line 1 @@\n-fn a() {}\n+fn a2() {}"));
+        
+        // Reading the file again brings back the full text in place of the diff
+        context.add_file(&path).unwrap();
+        let formatted = context.formatted_context();
+        assert!(!formatted.contains("--- Diff: "));
+        assert_eq!(formatted.matches(&format!("--- File: {} ---", path)).count(), 1);
+        assert_eq!(context.file_paths().len(), 1);
+    }
+
+    #[test]
+    fn test_created_deleted_and_renamed_files() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = |name: &str| dir.path().join(name).to_string_lossy().to_string();
+        fs::write(path("old.rs"), "fn old() {}\n").unwrap();
+        fs::write(path("lib.rs"), "fn lib() {}\n").unwrap();
+        let mut context = ContextManager::new();
+        context.add_file(&path("old.rs")).unwrap();
+        context.add_file(&path("lib.rs")).unwrap();
+        context.set_pinned(&path("lib.rs"), true).unwrap();
+        
+        fs::write(path("new.rs"), "fn new() {}\n").unwrap();
+        context.record_edits(&[SearchReplaceBlock { file_path: path("new.rs"), search_lines: String::new(), replace_lines: "fn new() {}".to_string() }]);
+        assert!(context.is_file_in_context(&path("new.rs")));
+        
+        fs::remove_file(path("old.rs")).unwrap();
+        fs::create_dir_all(path("core")).unwrap();
+        fs::rename(path("lib.rs"), path("core/lib.rs")).unwrap();
+        context.record_file_operations(&[
+            FileOperation::Delete { path: path("old.rs") },
+            FileOperation::Rename { from: path("lib.rs"), to: path("core/lib.rs") },
+        ]);
+        assert_eq!(context.file_paths(), &[path("new.rs"), path("core/lib.rs")]);
+        assert!(context.item_summaries().iter().any(|item| item.label == path("core/lib.rs") && item.pinned));
+        assert!(context.formatted_context().contains("fn lib() {}"));
+    }
+
+    #[test]
+    fn test_budget_evicts_least_recently_used_unpinned_items() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = |name: &str| dir.path().join(name).to_string_lossy().to_string();
+        for name in ["a.rs", "b.rs", "c.rs"] {
+            fs::write(path(name), "x".repeat(400)).unwrap();
+        }
+        
+        let mut context = ContextManager::new();
+        context.items.clear();
+        context.push_item("--- Prompt File: prompts/style.toml ---\nrules\n--- End Prompt File ---".to_string(), true, Origin::User);
+        context.add_file(&path("a.rs")).unwrap();
+        context.add_file(&path("b.rs")).unwrap();
+        // Re-reading a.rs makes b.rs the least recently used
+        context.add_file(&path("a.rs")).unwrap();
+        let per_file = context.items[1].tokens;
+        
+        context.max_tokens = Some(context.total_tokens() + per_file / 2);
+        context.add_file(&path("c.rs")).unwrap();
+        assert_eq!(context.file_paths(), [path("a.rs"), path("c.rs")]);
+        assert!(context.items[0].pinned);
+        assert!(context.total_tokens() <= context.max_tokens.unwrap());
+        
+        // What was just added stays even when it doesn't fit on its own
+        context.max_tokens = Some(1);
+        context.add_snippet("y".repeat(100));
+        assert_eq!(context.items.len(), 2);
+        assert!(context.items[0].pinned);
+        assert!(context.file_paths().is_empty());
+        assert_eq!(item_label(&context.items[1].text), "snippet");
+    }
+
+    #[test]
+    fn test_drop_and_pin_items() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = |name: &str| dir.path().join(name).to_string_lossy().to_string();
+        for name in ["a.rs", "b.rs"] {
+            fs::write(path(name), "x".repeat(400)).unwrap();
+        }
+        
+        let mut context = ContextManager::new();
+        context.items.clear();
+        context.add_file(&path("a.rs")).unwrap();
+        context.add_file(&path("b.rs")).unwrap();
+        context.add_snippet("notes".to_string());
+        
+        assert_eq!(context.set_pinned(&path("b.rs"), true).unwrap(), path("b.rs"));
+        assert!(context.formatted_context().contains(&format!("{}\n--- File: {} ---", PINNED_MARKER, path("b.rs"))));
+        assert_eq!(context.drop_item("3").unwrap(), "snippet");
+        assert_eq!(context.drop_item(&path("a.rs")).unwrap(), path("a.rs"));
+        assert!(context.drop_item("missing.rs").is_err());
+        assert!(context.drop_item("7").is_err());
+        
+        assert_eq!(context.file_paths(), [path("b.rs")]);
+        assert_eq!(context.item_summaries(), vec![context.items[0].summary()]);
+        assert_eq!(context.item_summaries()[0].label, path("b.rs"));
+        assert!(context.item_summaries()[0].pinned);
+    }
+
+    #[test]
+    fn test_clear_archives_and_rehydrates() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = |name: &str| dir.path().join(name).to_string_lossy().to_string();
+        fs::write(path("a.rs"), "fn a() {}\n").unwrap();
+        
+        let mut context = ContextManager::new();
+        context.items.clear();
+        context.add_file(&path("a.rs")).unwrap();
+        context.set_pinned(&path("a.rs"), true).unwrap();
+        context.add_snippet("notes".to_string());
+        context.clear_context();
+        assert!(context.item_summaries().is_empty());
+        assert_eq!(context.archived_summaries().len(), 2);
+        assert!(context.formatted_context().contains("Archived files (cleared from context;"));
+        
+        // Files come back as they are now, still pinned
+        fs::write(path("a.rs"), "fn a() { changed() }\n").unwrap();
+        assert_eq!(context.rehydrate(&path("a.rs")).unwrap(), path("a.rs"));
+        assert!(context.items[0].pinned);
+        assert!(context.items[0].text.contains("changed()"));
+        assert_eq!(context.file_paths(), [path("a.rs")]);
+        assert_eq!(context.rehydrate("1").unwrap(), "snippet");
+        assert!(context.archived_summaries().is_empty());
+        assert!(context.rehydrate("1").is_err());
+        
+        // Re-adding an archived file takes it out of the archive
+        context.drop_item(&path("a.rs")).unwrap();
+        assert_eq!(context.archived_summaries().len(), 1);
+        context.add_file(&path("a.rs")).unwrap();
+        assert!(context.archived_summaries().is_empty());
+    }
+
+    #[test]
+    fn test_provenance_and_user_only_prompts() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = |name: &str| dir.path().join(name).to_string_lossy().to_string();
+        fs::write(path("mine.rs"), "fn mine() {}\n").unwrap();
+        fs::write(path("asked.rs"), "fn asked() {}\n").unwrap();
+        
+        let mut context = ContextManager::new();
+        context.items.clear();
+        context.add_file(&path("mine.rs")).unwrap();
+        context.add_file_from(&path("asked.rs"), Origin::Retrieved).unwrap();
+        context.add_snippet_from("Output of command 'ls': \nsrc".to_string(), Origin::Command);
+        let origins: Vec<Origin> = context.item_summaries().iter().map(|item| item.origin).collect();
+        assert_eq!(origins, [Origin::User, Origin::Retrieved, Origin::Command]);
+        assert!(context.formatted_context().contains("fn asked()"));
+        
+        context.set_user_only(true);
+        let formatted = context.formatted_context();
+        assert!(formatted.contains("fn mine()"));
+        assert!(!formatted.contains("fn asked()"));
+        assert!(!formatted.contains(&format!("- {}\n", path("asked.rs"))));
+        assert!(!formatted.contains("Output of command"));
+        
+        // Re-adding a file yourself makes it user-added
+        context.add_file(&path("asked.rs")).unwrap();
+        assert!(context.formatted_context().contains("fn asked()"));
+        assert_eq!(context.item_summaries()[1].origin, Origin::User);
+    }
+
+    #[test]
+    fn test_clear_by_origin() {
+        let mut context = ContextManager::new();
+        context.items.clear();
+        context.add_snippet("Keep this note".to_string());
+        context.add_snippet_from("Output of command 'ls': \nsrc".to_string(), Origin::Command);
+        context.add_snippet_from("Step 1 (CodeAgent): read the parser".to_string(), Origin::Agent);
+        context.add_snippet_from("Output of command 'pwd': \n/tmp".to_string(), Origin::Command);
+        let totals: Vec<(Origin, usize)> = context.origin_totals().iter().map(|(origin, count, _)| (*origin, *count)).collect();
+        assert_eq!(totals, [(Origin::User, 1), (Origin::Agent, 1), (Origin::Command, 2)]);
+        
+        assert_eq!(context.clear_origin(Origin::Command), 2);
+        assert_eq!(context.clear_origin(Origin::Bridge), 0);
+        let origins: Vec<Origin> = context.item_summaries().iter().map(|item| item.origin).collect();
+        assert_eq!(origins, [Origin::User, Origin::Agent]);
+        // Snippets share a label, so only the newest is archived
+        assert_eq!(context.archived_summaries().len(), 1);
+        assert_eq!(Origin::parse("Command"), Some(Origin::Command));
+        assert_eq!(Origin::parse("mcp"), None);
+    }
+
+    #[test]
+    fn test_read_only_files() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = |name: &str| dir.path().join(name).to_string_lossy().to_string();
+        fs::write(path("lib.rs"), "pub fn dep() {}\n").unwrap();
+        fs::write(path("main.rs"), "fn main() {}\n").unwrap();
+        
+        let mut context = ContextManager::new();
+        context.items.clear();
+        let added = context.add_file(&path("lib.rs")).unwrap();
+        context.set_read_only(&added.files, true);
+        context.add_file(&path("main.rs")).unwrap();
+        assert!(context.is_read_only(&path("lib.rs")));
+        assert!(!context.is_read_only(&path("main.rs")));
+        assert!(context.is_file_in_context(&path("lib.rs")));
+        let formatted = context.formatted_context();
+        let reference = formatted.find("Read-only reference files").unwrap();
+        assert!(formatted[reference..].contains(&path("lib.rs")));
+        assert!(!formatted[..reference].contains(&format!("- {}\n", path("lib.rs"))));
+        
+        // Re-reading keeps it read-only until it's marked editable
+        context.add_file(&path("lib.rs")).unwrap();
+        assert!(context.is_read_only(&path("lib.rs")));
+        context.set_read_only(&[path("lib.rs")], false);
+        assert!(!context.formatted_context().contains("Read-only reference files"));
+    }
+
+    #[test]
+    fn test_binary_and_large_files() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = |name: &str| dir.path().join(name).to_string_lossy().to_string();
+        fs::write(path("logo.png"), [0x89, b'P', b'N', b'G', 0, 0, 1]).unwrap();
+        let big: String = (0..2000).map(|i| format!("line {}\n", i)).collect();
+        fs::write(path("big.log"), &big).unwrap();
+        fs::write(path("small.rs"), "fn main() {}\n").unwrap();
+
+        let mut context = ContextManager::new();
+        context.items.clear();
+        context.max_file_bytes = 1024;
+        assert_eq!(file_problem(&path("logo.png"), 1024), Some(FileProblem::Binary));
+        assert_eq!(file_problem(&path("big.log"), 1024), Some(FileProblem::TooLarge(big.len() as u64)));
+        assert!(context.add_file(&path("logo.png")).unwrap_err().to_string().contains("binary"));
+        assert!(context.add_file(&path("big.log")).unwrap_err().to_string().contains("--truncate"));
+        assert!(context.items.is_empty());
+
+        let added = context.add_file(&dir.path().to_string_lossy()).unwrap();
+        assert_eq!(added.files, vec![path("small.rs")]);
+        assert_eq!(added.skipped.len(), 2);
+        assert!(added.skipped.contains(&format!("{} (binary)", path("logo.png"))));
+
+        context.add_file_truncated(&path("big.log")).unwrap();
+        let text = &context.items.last().unwrap().text;
+        assert!(text.contains("line 0\n") && text.contains("line 1999\n"));
+        assert!(!text.contains("line 1000\n"));
+        assert!(text.contains("lines omitted"));
+        assert!(text.len() < 1200);
+    }
+
+    #[test]
+    fn test_line_range_items() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("big.rs").to_string_lossy().to_string();
+        let source: String = (1..=10).map(|i| format!("fn f{}() {{}}\n", i)).collect();
+        fs::write(&path, &source).unwrap();
+
+        let mut context = ContextManager::new();
+        context.items.clear();
+        context.add_file_lines(&path, LineRange::parse("3-5").unwrap()).unwrap();
+        let formatted = context.formatted_context();
+        assert!(formatted.contains(&format!("--- File: {} (lines 3-5 of 10) ---\nfn f3() {{}}\nfn f4() {{}}\nfn f5() {{}}\n--- End File: {} ---", path, path)));
+        assert!(!formatted.contains("fn f6()"));
+        assert!(context.is_file_in_context(&path));
+        assert_eq!(context.item_summaries()[0].label, path);
+        assert_eq!(crate::llm::router::context_files(&formatted), vec![path.clone()]);
+
+        // Archived ranges come back as the same lines; adding the whole file replaces them
+        context.clear_context();
+        context.rehydrate(&path).unwrap();
+        assert!(context.formatted_context().contains("(lines 3-5 of 10)"));
+        context.add_file(&path).unwrap();
+        assert_eq!(context.items.len(), 1);
+        assert!(context.formatted_context().contains("fn f10()"));
+        assert!(context.add_file_lines(&path, LineRange::parse("11-").unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_truncate_middle() {
+        assert_eq!(truncate_middle("short", 100), "short");
+        let text = "aaaa\nbbbb\ncccc\ndddd\neeee\n";
+        assert_eq!(truncate_middle(text, 12), "aaaa\n[... 3 lines omitted; the file is 25 B ...]\neeee\n");
+    }
+
+    #[test]
+    fn test_idle_items_replaced_with_summary() {
+        let mut context = ContextManager::new();
+        context.items.clear();
+        context.add_snippet("old notes".to_string());
+        for i in 0..5 {
+            context.add_snippet(format!("note {}", i));
+        }
+        let idle = context.idle_items(5);
+        assert_eq!(idle.len(), 1);
+        assert!(idle[0].1.contains("old notes"));
+        
+        let originals: Vec<String> = idle.into_iter().map(|(_, text)| text).collect();
+        assert_eq!(context.replace_with_summary(&originals, "- kept the decision".to_string(), Origin::Retrieved), 1);
+        assert_eq!(context.items.len(), 6);
+        assert!(context.items[5].text.contains("- kept the decision"));
+        assert_eq!(context.archived_summaries().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_shared_context_readers_and_writers() {
+        let shared = SharedContext::new(ContextManager::new());
+        
+        // Readers don't exclude each other, but they do keep writers out
+        let first = shared.try_read().expect("first read");
+        let second = shared.try_read().expect("concurrent read");
+        assert!(shared.try_write().is_none());
+        drop((first, second));
+        
+        shared.write().await.add_snippet("shared note".to_string());
+        assert!(shared.formatted().await.contains("shared note"));
+        
+        // A snapshot doesn't see later changes
+        let snapshot = shared.snapshot().await;
+        shared.write().await.clear_context();
+        assert!(snapshot.get_formatted_context().contains("shared note"));
+        assert!(!shared.formatted().await.contains("shared note"));
+    }
+}
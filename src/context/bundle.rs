@@ -0,0 +1,255 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+use super::{item_file_path, item_label, item_line_range, ContextManager, Origin};
+use crate::clock::clock;
+
+const BUNDLE_TITLE: &str = "# KOTA context bundle";
+// Each item's flags, on the line before its fenced text
+const ITEM_MARKER: &str = "<!-- kota-item ";
+const ITEM_MARKER_END: &str = " -->";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct ItemMeta {
+    origin: String,
+    #[serde(default)]
+    pinned: bool,
+    #[serde(default)]
+    read_only: bool,
+}
+
+/// What `/context import` brought in
+#[derive(Debug, Default, PartialEq)]
+pub struct ImportReport {
+    /// Files found on this machine, read again so they match what's on disk
+    pub from_disk: Vec<String>,
+    /// Files missing here, kept as the bundle had them and marked read-only
+    pub from_bundle: Vec<String>,
+    /// Snippets and documents
+    pub other: usize,
+    /// Items already in context
+    pub duplicates: usize,
+}
+
+impl ImportReport {
+    pub fn summary(&self) -> String {
+        let mut summary = format!(
+            "Imported {} item(s): {} file(s) read from disk, {} snippet(s) or document(s)",
+            self.from_disk.len() + self.from_bundle.len() + self.other,
+            self.from_disk.len(),
+            self.other
+        );
+        if !self.from_bundle.is_empty() {
+            summary.push_str(&format!(
+                "\n  Not on this machine, kept read-only from the bundle: {}",
+                self.from_bundle.join(", ")
+            ));
+        }
+        if self.duplicates > 0 {
+            summary.push_str(&format!("\n  Skipped {} item(s) already in context", self.duplicates));
+        }
+        summary
+    }
+}
+
+/// A fence longer than any run of backticks in `text`, so the text can't close it early
+fn fence_for(text: &str) -> String {
+    let longest = text.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    "`".repeat(longest.max(2) + 1)
+}
+
+impl ContextManager {
+    /// The context as Markdown another session can import: every item's text, where it came
+    /// from and whether it was pinned or read-only. Prompt files are left out; they're loaded
+    /// from the prompts directory wherever the bundle is imported.
+    pub fn export_bundle(&self) -> String {
+        let items: Vec<_> = self.items.iter().filter(|item| !item.text.starts_with("--- Prompt File: ")).collect();
+        let now = Utc::now();
+        let mut bundle = format!(
+            "{}\n\nExported {} {}: {} item(s), ~{} tokens. Load it with `/context import <file>`.\n",
+            BUNDLE_TITLE,
+            clock().date(&now),
+            clock().time(&now),
+            items.len(),
+            items.iter().map(|item| item.tokens).sum::<u64>()
+        );
+        for item in items {
+            let meta = ItemMeta { origin: item.origin.label().to_string(), pinned: item.pinned, read_only: item.read_only };
+            let fence = fence_for(&item.text);
+            bundle.push_str(&format!(
+                "\n## {}\n\n{}{}{}\n{}\n{}\n{}\n",
+                item_label(&item.text),
+                ITEM_MARKER,
+                serde_json::to_string(&meta).unwrap_or_default(),
+                ITEM_MARKER_END,
+                fence,
+                item.text,
+                fence
+            ));
+        }
+        bundle
+    }
+
+    /// Write `export_bundle` to `path`, returning how many items it holds
+    pub fn export_to(&self, path: &str) -> Result<usize> {
+        let bundle = self.export_bundle();
+        if let Some(parent) = Path::new(path).parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, &bundle).with_context(|| format!("Failed to write {}", path))?;
+        Ok(bundle.matches(ITEM_MARKER).count())
+    }
+
+    /// Add a bundle's items to the context. Files that exist here are read again, keeping their
+    /// line range; the rest keep the bundle's copy and are made read-only, since there's nothing
+    /// on disk for an edit to apply to.
+    pub fn import_bundle(&mut self, bundle: &str) -> Result<ImportReport> {
+        let items = parse_bundle(bundle)?;
+        self.operation += 1;
+        let mut report = ImportReport::default();
+        for (meta, text) in items {
+            let origin = Origin::parse(&meta.origin).unwrap_or(Origin::User);
+            if text.starts_with("--- Prompt File: ") {
+                continue;
+            }
+            if self.items.iter().any(|item| item.text == text) {
+                report.duplicates += 1;
+                continue;
+            }
+
+            let Some(path) = item_file_path(&text).map(str::to_string) else {
+                self.push_item(text, meta.pinned, origin);
+                self.items.last_mut().expect("item was just pushed").read_only = meta.read_only;
+                report.other += 1;
+                continue;
+            };
+            let reread = if Path::new(&path).is_file() {
+                match item_line_range(&text) {
+                    Some(range) => self.insert_file_lines(&path, range, origin),
+                    None => self.insert_file(&path, origin, true),
+                }
+            } else {
+                Err(anyhow::anyhow!("{} doesn't exist", path))
+            };
+            let read_only = match reread {
+                Ok(_) => {
+                    report.from_disk.push(path.clone());
+                    meta.read_only
+                }
+                Err(_) => {
+                    match self.file_item(&path) {
+                        Some(i) => self.replace_item(i, text),
+                        None => self.push_item(text, meta.pinned, origin),
+                    }
+                    if !self.is_file_in_context(&path) {
+                        self.file_paths.push(path.clone());
+                    }
+                    report.from_bundle.push(path.clone());
+                    true
+                }
+            };
+            if let Some(i) = self.file_item(&path) {
+                self.items[i].pinned = meta.pinned;
+                self.items[i].read_only = read_only;
+            }
+        }
+        self.mark_changed();
+        self.enforce_budget();
+        Ok(report)
+    }
+
+    /// Read a bundle written by `export_to` and import it
+    pub fn import_from(&mut self, path: &str) -> Result<ImportReport> {
+        let bundle = fs::read_to_string(path).with_context(|| format!("Failed to read {}", path))?;
+        self.import_bundle(&bundle)
+    }
+}
+
+/// Each item's flags and text. Anything outside the marked, fenced blocks is ignored, so
+/// notes added to the bundle by hand don't get in the way.
+fn parse_bundle(bundle: &str) -> Result<Vec<(ItemMeta, String)>> {
+    if !bundle.trim_start().starts_with(BUNDLE_TITLE) {
+        return Err(anyhow::anyhow!("Not a context bundle; it should start with \"{}\"", BUNDLE_TITLE));
+    }
+    let mut items = Vec::new();
+    let mut lines = bundle.lines();
+    while let Some(line) = lines.next() {
+        let Some(json) = line.trim().strip_prefix(ITEM_MARKER).and_then(|rest| rest.strip_suffix(ITEM_MARKER_END)) else {
+            continue;
+        };
+        let meta: ItemMeta = serde_json::from_str(json).with_context(|| format!("Bad item marker: {}", line.trim()))?;
+        let fence = lines.next().unwrap_or_default().trim_end();
+        if fence.len() < 3 || !fence.chars().all(|c| c == '`') {
+            return Err(anyhow::anyhow!("Expected a ``` fence after: {}", line.trim()));
+        }
+        let mut text = Vec::new();
+        loop {
+            match lines.next() {
+                Some(line) if line.trim_end() == fence => break,
+                Some(line) => text.push(line),
+                None => return Err(anyhow::anyhow!("The bundle ends inside an item; was it cut short?")),
+            }
+        }
+        items.push((meta, text.join("\n")));
+    }
+    Ok(items)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_select::LineRange;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_fence_for() {
+        assert_eq!(fence_for("no backticks"), "```");
+        assert_eq!(fence_for("```rust\nfn main() {}\n```"), "````");
+        assert_eq!(fence_for("`````"), "``````");
+    }
+
+    #[test]
+    fn test_export_and_import() {
+        let dir = TempDir::new().unwrap();
+        let here = dir.path().join("lib.rs").to_string_lossy().to_string();
+        let range_file = dir.path().join("big.rs").to_string_lossy().to_string();
+        let gone = dir.path().join("gone.rs").to_string_lossy().to_string();
+        fs::write(&here, "fn a() {}\n").unwrap();
+        fs::write(&range_file, "one\ntwo\nthree\nfour\n").unwrap();
+        fs::write(&gone, "fn gone() {}\n").unwrap();
+
+        let mut context = ContextManager::new();
+        context.add_file(&here).unwrap();
+        context.add_file_lines(&range_file, LineRange::parse("2-3").unwrap()).unwrap();
+        context.add_file(&gone).unwrap();
+        context.add_snippet("Use this:\n```rust\nlet x = 1;\n```".to_string());
+        context.set_pinned(&here, true).unwrap();
+        let bundle = context.export_bundle();
+        assert!(bundle.starts_with(BUNDLE_TITLE));
+        assert_eq!(parse_bundle(&bundle).unwrap().len(), 4);
+
+        fs::remove_file(&gone).unwrap();
+        fs::write(&here, "fn a() { changed(); }\n").unwrap();
+        let mut imported = ContextManager::new();
+        let report = imported.import_bundle(&bundle).unwrap();
+        assert_eq!(report.from_disk, vec![here.clone(), range_file.clone()]);
+        assert_eq!(report.from_bundle, vec![gone.clone()]);
+        assert_eq!(report.other, 1);
+        assert!(imported.is_read_only(&gone));
+        assert!(!imported.is_read_only(&here));
+        let formatted = imported.get_formatted_context();
+        assert!(formatted.contains("changed();"));
+        assert!(formatted.contains("(lines 2-3 of 4)"));
+        assert!(formatted.contains("```rust\nlet x = 1;\n```"));
+        assert!(imported.item_summaries().iter().any(|item| item.label == here && item.pinned));
+
+        let again = imported.import_bundle(&bundle).unwrap();
+        assert_eq!(again.duplicates, 3);
+        assert!(parse_bundle("# Notes\n").is_err());
+        assert!(parse_bundle(&bundle[..bundle.len() - 5]).is_err());
+    }
+}
@@ -0,0 +1,166 @@
+use std::fmt;
+use std::path::Path;
+use std::process::Command;
+
+use anyhow::{Context, Result};
+use regex::Regex;
+
+/// Pages of a PDF to extract, counted from 1. `last` of None runs to the end.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PageRange {
+    pub first: u32,
+    pub last: Option<u32>,
+}
+
+impl PageRange {
+    /// "3-7", "5" or "3-"
+    pub fn parse(spec: &str) -> Result<Self> {
+        let spec = spec.trim();
+        let number = |part: &str| {
+            part.trim()
+                .parse::<u32>()
+                .ok()
+                .filter(|page| *page > 0)
+                .ok_or_else(|| anyhow::anyhow!("Invalid page range {}; use a page like 5 or a range like 3-7", spec))
+        };
+        let range = match spec.split_once('-') {
+            Some((first, "")) => Self { first: number(first)?, last: None },
+            Some((first, last)) => Self { first: number(first)?, last: Some(number(last)?) },
+            None => {
+                let page = number(spec)?;
+                Self { first: page, last: Some(page) }
+            }
+        };
+        if range.last.is_some_and(|last| last < range.first) {
+            return Err(anyhow::anyhow!("Invalid page range {}; the last page comes before the first", spec));
+        }
+        Ok(range)
+    }
+}
+
+impl fmt::Display for PageRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.last {
+            Some(last) if last == self.first => write!(f, "{}", self.first),
+            Some(last) => write!(f, "{}-{}", self.first, last),
+            None => write!(f, "{}-", self.first),
+        }
+    }
+}
+
+/// Files added by extracting their text rather than reading them as-is
+pub fn is_document(path: &str) -> bool {
+    let extension = Path::new(path).extension().map(|ext| ext.to_string_lossy().to_lowercase());
+    matches!(extension.as_deref(), Some("pdf" | "docx"))
+}
+
+/// Split `--pages <range>` off an `/add_file` argument
+pub fn split_pages_option(arg: &str) -> Result<(String, Option<PageRange>)> {
+    let Some((path, rest)) = arg.split_once("--pages") else {
+        return Ok((arg.trim().to_string(), None));
+    };
+    let spec = rest.split_whitespace().next().ok_or_else(|| anyhow::anyhow!("--pages needs a range like 3-7"))?;
+    Ok((path.trim().to_string(), Some(PageRange::parse(spec)?)))
+}
+
+/// The text of a PDF (via poppler's pdftotext) or a .docx, with each PDF page marked
+pub fn extract(path: &str, pages: Option<PageRange>) -> Result<String> {
+    let text = if path.to_lowercase().ends_with(".pdf") {
+        pdf_text(path, pages)?
+    } else if pages.is_some() {
+        return Err(anyhow::anyhow!("Page ranges only apply to PDFs; {} has no fixed pages", path));
+    } else {
+        docx_text(path)?
+    };
+    if text.trim().is_empty() {
+        return Err(anyhow::anyhow!("No text found in {}; scanned documents need OCR first", path));
+    }
+    Ok(text)
+}
+
+fn pdf_text(path: &str, pages: Option<PageRange>) -> Result<String> {
+    let mut command = Command::new("pdftotext");
+    command.arg("-layout");
+    if let Some(range) = pages {
+        command.arg("-f").arg(range.first.to_string());
+        if let Some(last) = range.last {
+            command.arg("-l").arg(last.to_string());
+        }
+    }
+    let output = command
+        .arg(path)
+        .arg("-")
+        .output()
+        .context("Failed to run pdftotext; install poppler-utils to add PDFs")?;
+    if !output.status.success() {
+        return Err(anyhow::anyhow!("pdftotext failed on {}: {}", path, String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    Ok(mark_pages(&String::from_utf8_lossy(&output.stdout), pages.map_or(1, |range| range.first)))
+}
+
+/// pdftotext ends each page with a form feed; label them so the model can cite pages
+fn mark_pages(text: &str, first: u32) -> String {
+    text.split('\u{c}')
+        .enumerate()
+        .filter(|(_, page)| !page.trim().is_empty())
+        .map(|(i, page)| format!("[Page {}]\n{}", first + i as u32, page.trim_end()))
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+fn docx_text(path: &str) -> Result<String> {
+    let output = Command::new("unzip")
+        .args(["-p", path, "word/document.xml"])
+        .output()
+        .context("Failed to run unzip; install it to add .docx files")?;
+    if !output.status.success() {
+        return Err(anyhow::anyhow!("{} doesn't look like a .docx file", path));
+    }
+    Ok(document_xml_text(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Paragraphs, tabs and line breaks from WordprocessingML, with every other tag dropped
+fn document_xml_text(xml: &str) -> String {
+    let breaks = Regex::new(r"</w:p>|<w:br\s*/>|<w:cr\s*/>").unwrap();
+    let tabs = Regex::new(r"<w:tab\s*/>").unwrap();
+    let tags = Regex::new(r"<[^>]*>").unwrap();
+    let text = breaks.replace_all(xml, "\n");
+    let text = tabs.replace_all(&text, "\t");
+    let text = tags.replace_all(&text, "");
+    let text = text
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&");
+    text.lines().map(str::trim_end).collect::<Vec<_>>().join("\n").trim().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_page_ranges() {
+        assert_eq!(PageRange::parse("3-7").unwrap(), PageRange { first: 3, last: Some(7) });
+        assert_eq!(PageRange::parse("5").unwrap().to_string(), "5");
+        assert_eq!(PageRange::parse("3-").unwrap().to_string(), "3-");
+        assert!(PageRange::parse("7-3").is_err());
+        assert!(PageRange::parse("0").is_err());
+
+        let (path, pages) = split_pages_option("docs/spec.pdf --pages 2-4").unwrap();
+        assert_eq!(path, "docs/spec.pdf");
+        assert_eq!(pages, Some(PageRange { first: 2, last: Some(4) }));
+        assert_eq!(split_pages_option("notes.md").unwrap(), ("notes.md".to_string(), None));
+        assert!(split_pages_option("spec.pdf --pages").is_err());
+        assert!(is_document("Design.PDF") && is_document("spec.docx") && !is_document("main.rs"));
+    }
+
+    #[test]
+    fn test_extracted_text() {
+        assert_eq!(mark_pages("Intro\n\u{c}Details\n\u{c}", 3), "[Page 3]\nIntro\n\n[Page 4]\nDetails");
+        let xml = r#"<w:document><w:body><w:p><w:r><w:t>Goals &amp; scope</w:t></w:r></w:p><w:p><w:r><w:t>a</w:t><w:tab/><w:t>b</w:t></w:r></w:p></w:body></w:document>"#;
+        assert_eq!(document_xml_text(xml), "Goals & scope\na\tb");
+        assert!(extract("spec.docx", Some(PageRange { first: 1, last: None })).is_err());
+    }
+}
@@ -0,0 +1,267 @@
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use regex::Regex;
+
+use super::ContextManager;
+use crate::focus::{self, Category};
+use crate::prompts::{PromptsConfig, RelatedFiles};
+
+// More than this and the related files would crowd out the one that was asked for
+const MAX_RELATED_FILES: usize = 8;
+
+const SCRIPT_EXTENSIONS: &[&str] = &["ts", "tsx", "js", "jsx", "mjs", "cjs"];
+
+/// Local files `path` references directly through `mod` and `use crate::` declarations,
+/// relative imports and requires, Python imports or quoted `#include`s. Only files that
+/// exist are returned, as paths in the same form as `path`.
+pub fn referenced_files(path: &str, source: &str) -> Vec<String> {
+    let file = Path::new(path);
+    let dir = file.parent().unwrap_or(Path::new(""));
+    let extension = file.extension().and_then(|ext| ext.to_str()).unwrap_or("").to_lowercase();
+    let candidates: Vec<Vec<PathBuf>> = match extension.as_str() {
+        "rs" => rust_references(file, source),
+        "py" => python_references(dir, source),
+        "c" | "h" | "cc" | "cpp" | "hpp" => captures(r#"(?m)^\s*#\s*include\s*"([^"]+)""#, source)
+            .into_iter()
+            .map(|include| vec![dir.join(include)])
+            .collect(),
+        ext if SCRIPT_EXTENSIONS.contains(&ext) => script_references(dir, source),
+        _ => Vec::new(),
+    };
+
+    let mut files = Vec::new();
+    for alternatives in candidates {
+        let Some(found) = alternatives.into_iter().find(|candidate| candidate.is_file()) else { continue };
+        let found = normalize(&found);
+        if found != normalize(file) && !files.contains(&found) {
+            files.push(found);
+        }
+    }
+    files
+}
+
+/// After `path` is added, suggest or add the files it references that aren't in context yet,
+/// as `[context] related_files` says. Returns a line to show the user, if any.
+pub fn follow(context: &mut ContextManager, path: &str, read_only: bool) -> Option<String> {
+    let mode = PromptsConfig::load().unwrap_or_default().context.related_files;
+    if mode == RelatedFiles::Off {
+        return None;
+    }
+    let source = fs::read_to_string(path).ok()?;
+    let mut related: Vec<String> = referenced_files(path, &source)
+        .into_iter()
+        .filter(|file| !context.is_file_in_context(file))
+        .collect();
+    if related.is_empty() {
+        return None;
+    }
+    let more = related.len().saturating_sub(MAX_RELATED_FILES);
+    related.truncate(MAX_RELATED_FILES);
+    let more = if more > 0 { format!(" and {} more", more) } else { String::new() };
+
+    if mode == RelatedFiles::Suggest {
+        let suggestion = format!("Related files not in context: {}{} (add with /add_file <path>)", related.join(", "), more);
+        return Some(suggestion).filter(|suggestion| !focus::intercept(Category::Info, suggestion));
+    }
+    let added: Vec<String> = related.into_iter().filter(|file| context.add_file(file).is_ok()).collect();
+    if added.is_empty() {
+        return None;
+    }
+    context.set_read_only(&added, read_only);
+    Some(format!("Also added related files: {}{}", added.join(", "), more))
+}
+
+fn captures(pattern: &str, source: &str) -> Vec<String> {
+    Regex::new(pattern)
+        .unwrap()
+        .captures_iter(source)
+        .map(|caps| caps[1].to_string())
+        .collect()
+}
+
+fn rust_references(file: &Path, source: &str) -> Vec<Vec<PathBuf>> {
+    let dir = file.parent().unwrap_or(Path::new(""));
+    let is_root = matches!(file.file_name().and_then(|name| name.to_str()), Some("main.rs" | "lib.rs" | "mod.rs"));
+    // Children of src/tui.rs live in src/tui/; children of main.rs and mod.rs beside them
+    let module_dir = if is_root { dir.to_path_buf() } else { dir.join(file.file_stem().unwrap_or_default()) };
+
+    let mut candidates: Vec<Vec<PathBuf>> = captures(r"(?m)^\s*(?:pub(?:\([^)]*\))?\s+)?mod\s+(\w+)\s*;", source)
+        .into_iter()
+        .map(|name| vec![module_dir.join(format!("{}.rs", name)), module_dir.join(&name).join("mod.rs")])
+        .collect();
+
+    let Some(crate_root) = crate_root(dir) else { return candidates };
+    for tree in captures(r"(?m)^\s*(?:pub(?:\([^)]*\))?\s+)?use\s+crate::([^;]+);", source) {
+        for segments in use_paths(&tree) {
+            // The deepest module that exists; the rest of the path names items inside it
+            let alternatives = (1..=segments.len())
+                .rev()
+                .flat_map(|depth| {
+                    let module = crate_root.join(segments[..depth].join("/"));
+                    [module.with_extension("rs"), module.join("mod.rs")]
+                })
+                .collect();
+            candidates.push(alternatives);
+        }
+    }
+    candidates
+}
+
+/// The nearest directory at or above `dir` with a main.rs or lib.rs
+fn crate_root(dir: &Path) -> Option<PathBuf> {
+    let mut dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+    loop {
+        if dir.join("main.rs").is_file() || dir.join("lib.rs").is_file() {
+            return Some(dir.to_path_buf());
+        }
+        dir = dir.parent().filter(|parent| !parent.as_os_str().is_empty())?;
+    }
+}
+
+/// The module paths in a use tree: `llm::{router, cache::fnv1a}` is
+/// `[llm, router]` and `[llm, cache, fnv1a]`
+fn use_paths(tree: &str) -> Vec<Vec<String>> {
+    let tree = tree.trim();
+    let (prefix, group) = match tree.find('{') {
+        Some(open) => (&tree[..open], Some(tree[open + 1..].trim_end().trim_end_matches('}'))),
+        None => (tree, None),
+    };
+    let prefix: Vec<String> = prefix
+        .split("::")
+        .map(|segment| segment.split_whitespace().next().unwrap_or("").to_string())
+        .filter(|segment| !segment.is_empty() && segment != "*" && segment != "self")
+        .collect();
+    let Some(group) = group else { return vec![prefix] };
+
+    let mut paths = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in group.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            ',' if depth == 0 => {
+                paths.push(&group[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    paths.push(&group[start..]);
+    paths
+        .into_iter()
+        .filter(|item| !item.trim().is_empty())
+        .flat_map(use_paths)
+        .map(|rest| prefix.iter().cloned().chain(rest).collect())
+        .collect()
+}
+
+fn python_references(dir: &Path, source: &str) -> Vec<Vec<PathBuf>> {
+    let module_files = |base: &Path, module: &str| {
+        let path = base.join(module.replace('.', "/"));
+        vec![path.with_extension("py"), path.join("__init__.py")]
+    };
+    let mut candidates = Vec::new();
+    let from = Regex::new(r"(?m)^\s*from\s+(\.*)([\w.]*)\s+import\s+\(?([\w ,]+)").unwrap();
+    for caps in from.captures_iter(source) {
+        let dots = caps[1].len();
+        // Absolute imports are only local when they resolve from the file's own directory
+        let base = (1..dots).fold(dir.to_path_buf(), |base, _| base.parent().map(Path::to_path_buf).unwrap_or_default());
+        let module = &caps[2];
+        if !module.is_empty() {
+            candidates.push(module_files(&base, module));
+        } else {
+            // `from . import a, b` names modules in the package
+            candidates.extend(caps[3].split(',').filter_map(|name| name.split_whitespace().next()).map(|name| module_files(&base, name)));
+        }
+    }
+    for modules in captures(r"(?m)^\s*import\s+([\w., ]+)", source) {
+        candidates.extend(modules.split(',').filter_map(|name| name.split_whitespace().next()).map(|name| module_files(dir, name)));
+    }
+    candidates
+}
+
+fn script_references(dir: &Path, source: &str) -> Vec<Vec<PathBuf>> {
+    let specifiers = Regex::new(r#"(?:\bfrom\s*|\bimport\s*\(?\s*|\brequire\(\s*)['"](\.{1,2}/[^'"]+)['"]"#).unwrap();
+    specifiers
+        .captures_iter(source)
+        .map(|caps| {
+            let path = dir.join(&caps[1]);
+            let mut alternatives = vec![path.clone()];
+            for ext in SCRIPT_EXTENSIONS {
+                alternatives.push(PathBuf::from(format!("{}.{}", path.display(), ext)));
+            }
+            for ext in SCRIPT_EXTENSIONS {
+                alternatives.push(path.join(format!("index.{}", ext)));
+            }
+            alternatives
+        })
+        .collect()
+}
+
+/// `src/tui/../llm/./router.rs` as `src/llm/router.rs`, without touching the filesystem
+fn normalize(path: &Path) -> String {
+    let mut parts: Vec<Component> = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir if matches!(parts.last(), Some(Component::Normal(_))) => {
+                parts.pop();
+            }
+            component => parts.push(component),
+        }
+    }
+    parts.iter().collect::<PathBuf>().to_string_lossy().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_use_paths() {
+        assert_eq!(use_paths("llm::router"), vec![vec!["llm", "router"]]);
+        assert_eq!(
+            use_paths("llm::{self, router::spec_model, cache::{fnv1a, Cache}}"),
+            vec![vec!["llm"], vec!["llm", "router", "spec_model"], vec!["llm", "cache", "fnv1a"], vec!["llm", "cache", "Cache"]]
+        );
+        assert_eq!(use_paths("context::ContextManager as Manager"), vec![vec!["context", "ContextManager"]]);
+        assert_eq!(normalize(Path::new("src/tui/../llm/./router.rs")), "src/llm/router.rs");
+    }
+
+    #[test]
+    fn test_referenced_files() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().to_string_lossy().to_string();
+        let write = |name: &str, text: &str| {
+            let path = dir.path().join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, text).unwrap();
+        };
+        write("src/main.rs", "mod tui;\nmod llm;\n");
+        write("src/tui.rs", "pub mod app;\nuse crate::llm::router::route;\nuse crate::missing::Thing;\n");
+        write("src/tui/app.rs", "");
+        write("src/llm/mod.rs", "");
+        write("src/llm/router.rs", "");
+        write("web/app.ts", "import { api } from './api';\nconst util = require(\"../lib/util\");\nimport React from 'react';\n");
+        write("web/api/index.ts", "");
+        write("lib/util.js", "");
+        write("pkg/main.py", "from .models import User\nfrom . import views\nimport json\n");
+        write("pkg/models.py", "");
+        write("pkg/views/__init__.py", "");
+
+        let related = |name: &str| {
+            let path = format!("{}/{}", root, name);
+            referenced_files(&path, &fs::read_to_string(&path).unwrap())
+                .into_iter()
+                .map(|file| file.trim_start_matches(&format!("{}/", root)).to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(related("src/main.rs"), vec!["src/tui.rs", "src/llm/mod.rs"]);
+        assert_eq!(related("src/tui.rs"), vec!["src/tui/app.rs", "src/llm/router.rs"]);
+        assert_eq!(related("web/app.ts"), vec!["web/api/index.ts", "lib/util.js"]);
+        assert_eq!(related("pkg/main.py"), vec!["pkg/models.py", "pkg/views/__init__.py"]);
+    }
+}
@@ -0,0 +1,297 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{Mutex, OnceLock};
+use std::time::SystemTime;
+
+use regex::Regex;
+
+use crate::file_select;
+
+// Generated, vendored or minified files this big say little about the project's structure
+const MAX_MAPPED_FILE_BYTES: u64 = 256 * 1024;
+const MAX_MAPPED_FILES: usize = 5000;
+
+// ctags kinds worth a line in the map; variables, fields and the like are left out
+const CTAGS_KINDS: &[&str] = &[
+    "function", "method", "struct", "class", "enum", "trait", "interface", "type", "typedef",
+    "implementation", "union", "module", "namespace",
+];
+
+/// Source languages the map covers. The built-in extractor reads the first five;
+/// the rest are only mapped when universal-ctags is installed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Language {
+    Rust,
+    Python,
+    JavaScript,
+    Go,
+    Other,
+}
+
+fn language(path: &str) -> Option<Language> {
+    let extension = Path::new(path).extension()?.to_str()?.to_lowercase();
+    match extension.as_str() {
+        "rs" => Some(Language::Rust),
+        "py" => Some(Language::Python),
+        "js" | "jsx" | "mjs" | "cjs" | "ts" | "tsx" => Some(Language::JavaScript),
+        "go" => Some(Language::Go),
+        "c" | "h" | "cc" | "cpp" | "hpp" | "java" | "kt" | "swift" | "rb" | "cs" | "php" | "scala" => Some(Language::Other),
+        _ => None,
+    }
+}
+
+/// Signatures extracted from one file, kept until it changes on disk
+struct MappedFile {
+    modified: Option<SystemTime>,
+    len: u64,
+    signatures: Vec<String>,
+}
+
+// Shared by every prompt in the process, so only edited files are read again
+static MAPPED: Mutex<Option<HashMap<PathBuf, MappedFile>>> = Mutex::new(None);
+
+/// A compact map of the functions, types and traits defined in the project under `root`,
+/// one indented signature per line under each file. Files in `skip` (already in context)
+/// are left out, and files whose paths or symbols share words with `message` come first.
+pub fn build(root: &Path, skip: &[String], message: &str) -> String {
+    let skip: Vec<&str> = skip.iter().map(|path| path.trim_start_matches("./")).collect();
+    let files: Vec<String> = file_select::project_files(root)
+        .into_iter()
+        .filter(|path| language(path).is_some() && !skip.contains(&path.as_str()) && !skip.contains(&root.join(path).to_string_lossy().as_ref()))
+        .take(MAX_MAPPED_FILES)
+        .collect();
+
+    let mut mapped = MAPPED.lock().unwrap_or_else(|e| e.into_inner());
+    let mapped = mapped.get_or_insert_with(HashMap::new);
+    let stale: Vec<(PathBuf, fs::Metadata)> = files
+        .iter()
+        .map(|path| root.join(path))
+        .filter_map(|path| fs::metadata(&path).ok().map(|metadata| (path, metadata)))
+        .filter(|(path, metadata)| {
+            mapped.get(path).is_none_or(|known| known.len != metadata.len() || known.modified != metadata.modified().ok())
+        })
+        .collect();
+    let paths: Vec<PathBuf> = stale.iter().map(|(path, _)| path.clone()).collect();
+    let mut extracted = extract(&paths);
+    for (path, metadata) in stale {
+        let signatures = extracted.remove(&path).unwrap_or_default();
+        mapped.insert(path, MappedFile { modified: metadata.modified().ok(), len: metadata.len(), signatures });
+    }
+
+    let entries: Vec<(&str, &[String])> = files
+        .iter()
+        .filter_map(|path| {
+            let file = mapped.get(&root.join(path))?;
+            (!file.signatures.is_empty()).then_some((path.as_str(), file.signatures.as_slice()))
+        })
+        .collect();
+    render(rank(entries, message))
+}
+
+fn render(entries: Vec<(&str, &[String])>) -> String {
+    let mut map = String::new();
+    for (path, signatures) in entries {
+        map.push_str(path);
+        map.push_str(":\n");
+        for signature in signatures {
+            map.push_str(signature);
+            map.push('\n');
+        }
+    }
+    map
+}
+
+/// Files sharing the most words with the message first, then in path order, so the
+/// relevant part of the map survives the stage's token budget
+fn rank<'a>(mut entries: Vec<(&'a str, &'a [String])>, message: &str) -> Vec<(&'a str, &'a [String])> {
+    let words: Vec<String> = message
+        .split(|c: char| !c.is_alphanumeric() && c != '_')
+        .filter(|word| word.len() >= 3)
+        .map(str::to_lowercase)
+        .collect();
+    if words.is_empty() {
+        return entries;
+    }
+    let score = |(path, signatures): &(&str, &[String])| {
+        let path = path.to_lowercase();
+        words
+            .iter()
+            .filter(|word| path.contains(word.as_str()) || signatures.iter().any(|signature| signature.to_lowercase().contains(word.as_str())))
+            .count()
+    };
+    entries.sort_by_key(|entry| std::cmp::Reverse(score(entry)));
+    entries
+}
+
+fn extract(paths: &[PathBuf]) -> HashMap<PathBuf, Vec<String>> {
+    let readable: Vec<&PathBuf> = paths
+        .iter()
+        .filter(|path| fs::metadata(path).is_ok_and(|metadata| metadata.len() <= MAX_MAPPED_FILE_BYTES))
+        .collect();
+    let mut extracted = if ctags_available() { ctags_signatures(&readable) } else { HashMap::new() };
+    for path in readable {
+        if extracted.contains_key(path.as_path()) {
+            continue;
+        }
+        let Some(language) = language(&path.to_string_lossy()) else { continue };
+        if let Ok(source) = fs::read_to_string(path) {
+            extracted.insert(path.clone(), signatures(language, &source));
+        }
+    }
+    extracted
+}
+
+fn ctags_available() -> bool {
+    static AVAILABLE: OnceLock<bool> = OnceLock::new();
+    *AVAILABLE.get_or_init(|| {
+        Command::new("ctags")
+            .arg("--version")
+            .output()
+            .is_ok_and(|output| String::from_utf8_lossy(&output.stdout).contains("Universal Ctags"))
+    })
+}
+
+/// Tags for `paths` from universal-ctags, by file, in line order
+fn ctags_signatures(paths: &[&PathBuf]) -> HashMap<PathBuf, Vec<String>> {
+    let mut extracted: HashMap<PathBuf, Vec<(u64, String)>> = HashMap::new();
+    if paths.is_empty() {
+        return HashMap::new();
+    }
+    let child = Command::new("ctags")
+        .args(["-f", "-", "--output-format=json", "--fields=+nK", "-L", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn();
+    let Ok(mut child) = child else { return HashMap::new() };
+    if let Some(mut stdin) = child.stdin.take() {
+        let list: String = paths.iter().map(|path| format!("{}\n", path.display())).collect();
+        let _ = stdin.write_all(list.as_bytes());
+    }
+    let Ok(output) = child.wait_with_output() else { return HashMap::new() };
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        if let Some((path, line_number, signature)) = parse_ctags_line(line) {
+            extracted.entry(PathBuf::from(path)).or_default().push((line_number, signature));
+        }
+    }
+    extracted
+        .into_iter()
+        .map(|(path, mut tags)| {
+            tags.sort();
+            tags.dedup();
+            (path, tags.into_iter().map(|(_, signature)| signature).collect())
+        })
+        .collect()
+}
+
+/// The file, line and source line of one JSON tag, for the kinds the map keeps
+fn parse_ctags_line(line: &str) -> Option<(String, u64, String)> {
+    let tag: serde_json::Value = serde_json::from_str(line).ok()?;
+    if tag["_type"] != "tag" || !CTAGS_KINDS.contains(&tag["kind"].as_str()?) {
+        return None;
+    }
+    let pattern = tag["pattern"].as_str()?;
+    let source = pattern.strip_prefix("/^")?;
+    let source = source.strip_suffix("$/").or_else(|| source.strip_suffix('/'))?;
+    let signature = signature_line(&source.replace("\\/", "/").replace("\\\\", "\\"))?;
+    Some((tag["path"].as_str()?.to_string(), tag["line"].as_u64().unwrap_or(0), signature))
+}
+
+fn definition_regex(language: Language) -> Option<&'static Regex> {
+    static PATTERNS: OnceLock<Vec<(Language, Regex)>> = OnceLock::new();
+    let patterns = PATTERNS.get_or_init(|| {
+        [
+            (Language::Rust, r#"^\s*(?:pub(?:\([^)]*\))?\s+)?(?:(?:async|const|unsafe|extern\s+"[^"]*")\s+)*(?:fn|struct|enum|trait|impl|type|union)\b"#),
+            (Language::Python, r"^\s*(?:async\s+)?(?:def|class)\s+\w+"),
+            (Language::JavaScript, r"^\s*(?:export\s+)?(?:default\s+)?(?:declare\s+)?(?:abstract\s+)?(?:async\s+)?(?:function\*?|class|interface|type|enum)\s+\w+"),
+            (Language::Go, r"^(?:func|type)\s+"),
+        ]
+        .into_iter()
+        .map(|(language, pattern)| (language, Regex::new(pattern).unwrap()))
+        .collect()
+    });
+    patterns.iter().find(|(candidate, _)| *candidate == language).map(|(_, regex)| regex)
+}
+
+/// Definition lines from `source`, found line by line without parsing
+fn signatures(language: Language, source: &str) -> Vec<String> {
+    let Some(regex) = definition_regex(language) else { return Vec::new() };
+    let mut signatures = Vec::new();
+    for line in source.lines() {
+        let trimmed = line.trim_start();
+        // Test modules are noise in a map of the project
+        if language == Language::Rust && trimmed.starts_with("mod tests") {
+            break;
+        }
+        if regex.is_match(line) {
+            signatures.extend(signature_line(line));
+        }
+    }
+    signatures
+}
+
+/// A definition's first line, re-indented two spaces per level under its file
+fn signature_line(line: &str) -> Option<String> {
+    let indent: usize = line
+        .chars()
+        .take_while(|c| c.is_whitespace())
+        .map(|c| if c == '\t' { 4 } else { 1 })
+        .sum();
+    let signature = line
+        .trim()
+        .trim_end_matches(['{', ':', ';'])
+        .trim_end()
+        .trim_end_matches(" where");
+    if signature.is_empty() {
+        return None;
+    }
+    Some(format!("{}{}", "  ".repeat(indent / 4 + 1), signature))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_builtin_signatures() {
+        let rust = "use std::fs;\n\npub struct Map {\n    files: Vec<String>,\n}\n\nimpl Map {\n    pub async fn build(&self) -> String {\n        let x = 1;\n    }\n}\n\n#[cfg(test)]\nmod tests {\n    fn test_build() {}\n}\n";
+        assert_eq!(signatures(Language::Rust, rust), vec!["  pub struct Map", "  impl Map", "    pub async fn build(&self) -> String"]);
+
+        let python = "import os\n\nclass Store:\n    def get(self, key):\n        return None\n";
+        assert_eq!(signatures(Language::Python, python), vec!["  class Store", "    def get(self, key)"]);
+
+        let typescript = "export default async function load(id: string) {\n}\nconst x = 1;\nexport interface Options {\n}\n";
+        assert_eq!(signatures(Language::JavaScript, typescript), vec!["  export default async function load(id: string)", "  export interface Options"]);
+        assert!(signatures(Language::Other, "int main() {}").is_empty());
+    }
+
+    #[test]
+    fn test_ctags_lines() {
+        let function = r#"{"_type": "tag", "name": "main", "path": "src/main.c", "pattern": "/^int main(int argc, char **argv) {$/", "line": 12, "kind": "function"}"#;
+        assert_eq!(parse_ctags_line(function), Some(("src/main.c".to_string(), 12, "  int main(int argc, char **argv)".to_string())));
+        let variable = r#"{"_type": "tag", "name": "count", "path": "src/main.c", "pattern": "/^static int count;$/", "line": 3, "kind": "variable"}"#;
+        assert_eq!(parse_ctags_line(variable), None);
+    }
+
+    #[test]
+    fn test_build_skips_context_files_and_ranks_by_message() {
+        let dir = TempDir::new().unwrap();
+        fs::create_dir_all(dir.path().join("src")).unwrap();
+        fs::write(dir.path().join("src/auth.rs"), "pub fn login(user: &str) -> bool {\n    true\n}\n").unwrap();
+        fs::write(dir.path().join("src/main.rs"), "fn main() {}\n").unwrap();
+        fs::write(dir.path().join("README.md"), "# Project\n").unwrap();
+
+        let map = build(dir.path(), &[], "");
+        assert_eq!(map, "src/auth.rs:\n  pub fn login(user: &str) -> bool\nsrc/main.rs:\n  fn main() {}\n");
+
+        let map = build(dir.path(), &["src/auth.rs".to_string()], "fix main");
+        assert_eq!(map, "src/main.rs:\n  fn main() {}\n");
+
+        let ranked = build(dir.path(), &[], "why does main exit early");
+        assert!(ranked.starts_with("src/main.rs:"));
+    }
+}
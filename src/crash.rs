@@ -0,0 +1,183 @@
+use std::backtrace::Backtrace;
+use std::collections::VecDeque;
+use std::fs;
+use std::panic::PanicHookInfo;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use anyhow::{Context, Result};
+use chrono::Local;
+use serde_json::Value;
+
+use crate::prompts::PromptsConfig;
+use crate::secrets;
+use crate::trace;
+
+pub const CRASHES_DIR: &str = ".kota/crashes";
+// Recent activity kept for the report
+const MAX_LOG_LINES: usize = 50;
+
+static LOG: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+// Whether the TUI owns the terminal, which has to be given back before anything is printed
+static TUI_ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Remember a line of activity for a crash report. Only the last few are kept, in memory.
+pub fn log(line: &str) {
+    let mut log = LOG.lock().unwrap_or_else(|e| e.into_inner());
+    if log.len() == MAX_LOG_LINES {
+        log.pop_front();
+    }
+    log.push_back(format!("{} {}", Local::now().format("%H:%M:%S"), line));
+}
+
+pub fn set_tui_active(active: bool) {
+    TUI_ACTIVE.store(active, Ordering::Relaxed);
+}
+
+pub fn tui_active() -> bool {
+    TUI_ACTIVE.load(Ordering::Relaxed)
+}
+
+/// Write a report to `~/.kota/crashes/` on any panic, after the usual panic message, and say
+/// how to attach it to an issue. Reports have secrets and the home directory stripped.
+pub fn install() {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let in_tui = TUI_ACTIVE.swap(false, Ordering::Relaxed);
+        if in_tui {
+            let _ = crossterm::terminal::disable_raw_mode();
+            let _ = crossterm::execute!(
+                std::io::stdout(),
+                crossterm::terminal::LeaveAlternateScreen,
+                crossterm::event::DisableMouseCapture,
+                crossterm::cursor::Show
+            );
+        }
+        default_hook(info);
+        trace::finish();
+        let report = report(info, in_tui, &Backtrace::force_capture().to_string());
+        match write_report(&report) {
+            Ok(path) => {
+                let repo = PromptsConfig::load().map(|config| config.update.repo).unwrap_or_else(|_| "jayminwest/kota-rust-cli".to_string());
+                eprintln!("\nKOTA crashed. A report, with secrets and your home directory removed, was saved to:\n  {}", path.display());
+                eprintln!("To report it, look it over and attach it to a new issue:\n  {}", issue_url(&repo, &panic_message(info)));
+            }
+            Err(e) => eprintln!("Warning: Failed to save a crash report: {}", e),
+        }
+    }));
+}
+
+fn panic_message(info: &PanicHookInfo) -> String {
+    let payload = info.payload();
+    payload
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "panic".to_string())
+}
+
+fn report(info: &PanicHookInfo, in_tui: bool, backtrace: &str) -> String {
+    let location = info.location().map(|location| format!("{}:{}", location.file(), location.line())).unwrap_or_default();
+    let mut report = format!(
+        "KOTA crash report\nVersion: {}\nTime: {}\nPlatform: {} {}\nInterface: {}\nThread: {}\nPanic: {}\nAt: {}\n",
+        env!("CARGO_PKG_VERSION"),
+        Local::now().to_rfc3339(),
+        std::env::consts::OS,
+        std::env::consts::ARCH,
+        if in_tui { "tui" } else { "cli" },
+        std::thread::current().name().unwrap_or("unnamed"),
+        panic_message(info),
+        location,
+    );
+    report.push_str("\nConfig flags:\n");
+    match PromptsConfig::load() {
+        Ok(config) => {
+            for flag in config_flags(&serde_json::to_value(config).unwrap_or_default()) {
+                report.push_str(&format!("  {}\n", flag));
+            }
+        }
+        Err(_) => report.push_str("  (prompts.toml doesn't parse)\n"),
+    }
+    report.push_str("\nRecent activity:\n");
+    for line in LOG.lock().unwrap_or_else(|e| e.into_inner()).iter() {
+        report.push_str(&format!("  {}\n", line));
+    }
+    report.push_str(&format!("\nBacktrace:\n{}\n", backtrace));
+    scrub(&report, std::env::var("HOME").ok().as_deref())
+}
+
+/// Only the on/off settings: the rest of the config can hold URLs, addresses and prompts
+fn config_flags(value: &Value) -> Vec<String> {
+    fn walk(value: &Value, path: &str, flags: &mut Vec<String>) {
+        match value {
+            Value::Bool(on) => flags.push(format!("{} = {}", path, on)),
+            Value::Object(fields) => {
+                for (key, value) in fields {
+                    let path = if path.is_empty() { key.clone() } else { format!("{}.{}", path, key) };
+                    walk(value, &path, flags);
+                }
+            }
+            _ => {}
+        }
+    }
+    let mut flags = Vec::new();
+    walk(value, "", &mut flags);
+    flags
+}
+
+/// `text` with secrets redacted and the home directory, which usually holds the user's
+/// name, shortened to `~`
+fn scrub(text: &str, home: Option<&str>) -> String {
+    let text = secrets::redact_all(text);
+    match home.filter(|home| home.len() > 1) {
+        Some(home) => text.replace(home, "~"),
+        None => text,
+    }
+}
+
+fn write_report(report: &str) -> Result<PathBuf> {
+    let home = std::env::var_os("HOME").ok_or_else(|| anyhow::anyhow!("HOME is not set"))?;
+    let dir = PathBuf::from(home).join(CRASHES_DIR);
+    fs::create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    let path = dir.join(format!("crash-{}.txt", Local::now().format("%Y%m%d-%H%M%S")));
+    fs::write(&path, report).with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(path)
+}
+
+/// New-issue link with the title filled in; the report itself is only attached by the user
+fn issue_url(repo: &str, message: &str) -> String {
+    let first_line = message.lines().next().unwrap_or_default();
+    let title: String = format!("Crash: {}", first_line).chars().take(100).collect();
+    let body = "What I was doing when KOTA crashed:\n\n\n(Attach the crash report from ~/.kota/crashes/ after looking it over.)";
+    format!("https://github.com/{}/issues/new?title={}&body={}", repo, encode(&secrets::redact_all(&title)), encode(body))
+}
+
+fn encode(text: &str) -> String {
+    text.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_report_is_scrubbed() {
+        let flags = config_flags(&json!({"lint": {"enabled": true, "formatters": {"rs": "rustfmt"}}, "webhooks": {"url": "https://example.com/hook"}, "cache": {"enabled": false}}));
+        assert_eq!(flags, vec!["cache.enabled = false".to_string(), "lint.enabled = true".to_string()]);
+
+        let text = "at /home/alice/src/kota/src/main.rs\nkey sk-ant-REDACTED";
+        let scrubbed = scrub(text, Some("/home/alice"));
+        assert!(scrubbed.contains("at ~/src/kota/src/main.rs"));
+        assert!(!scrubbed.contains("AbCdEf"));
+
+        let url = issue_url("owner/repo", "index out of bounds: the len is 3\nmore");
+        assert!(url.starts_with("https://github.com/owner/repo/issues/new?title=Crash%3A%20index%20out%20of%20bounds%3A%20the%20len%20is%203&body="));
+    }
+}
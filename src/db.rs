@@ -0,0 +1,895 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local, NaiveDate, TimeZone, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::history::HistoryHit;
+use crate::usage::{ModelUsage, TokenUsage};
+use encryption::{Cipher, ENCRYPTED_PREFIX};
+
+pub mod encryption;
+
+/// Conversations, learnings, agent sessions, token usage and agent transcripts all live here
+pub const DATABASE_PATH: &str = ".kota/kota.db";
+
+// Where sessions and transcripts were kept before the database, imported until it succeeds
+const LEGACY_AGENT_DIR: &str = ".kota/agents";
+const LEGACY_KNOWLEDGE_BASE: &str = "knowledge-base";
+
+/// Schema migrations, applied in order. The database's `user_version` records how many
+/// have run, so new migrations must only ever be appended.
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE conversations (
+        id INTEGER PRIMARY KEY,
+        created_at TEXT NOT NULL,
+        content TEXT NOT NULL,
+        archived INTEGER NOT NULL DEFAULT 0
+    );
+    CREATE INDEX idx_conversations_created_at ON conversations(created_at);
+
+    CREATE TABLE learnings (
+        id INTEGER PRIMARY KEY,
+        topic TEXT NOT NULL,
+        content TEXT NOT NULL,
+        created_at TEXT NOT NULL
+    );
+    CREATE INDEX idx_learnings_topic ON learnings(topic);
+
+    CREATE TABLE agent_sessions (
+        task_id TEXT PRIMARY KEY,
+        description TEXT NOT NULL,
+        finished INTEGER NOT NULL,
+        data TEXT NOT NULL,
+        updated_at TEXT NOT NULL
+    );
+
+    CREATE TABLE llm_usage (
+        id INTEGER PRIMARY KEY,
+        created_at TEXT NOT NULL,
+        provider TEXT NOT NULL,
+        model TEXT NOT NULL,
+        prompt_tokens INTEGER NOT NULL,
+        completion_tokens INTEGER NOT NULL,
+        estimated INTEGER NOT NULL
+    );
+
+    CREATE TABLE transcripts (
+        task_id TEXT PRIMARY KEY,
+        agent_name TEXT NOT NULL,
+        description TEXT NOT NULL,
+        markdown TEXT NOT NULL,
+        updated_at TEXT NOT NULL,
+        archived INTEGER NOT NULL DEFAULT 0
+    );
+
+    CREATE TABLE audit_log (
+        id INTEGER PRIMARY KEY,
+        task_id TEXT NOT NULL,
+        created_at TEXT NOT NULL,
+        kind TEXT NOT NULL,
+        content TEXT NOT NULL
+    );
+    CREATE INDEX idx_audit_log_task_id ON audit_log(task_id);",
+    // Full-text index over conversations, command outputs and learnings, kept current by triggers
+    "ALTER TABLE conversations ADD COLUMN session_id TEXT;
+    CREATE INDEX idx_conversations_session_id ON conversations(session_id);
+
+    CREATE TABLE command_outputs (
+        id INTEGER PRIMARY KEY,
+        session_id TEXT NOT NULL,
+        created_at TEXT NOT NULL,
+        command TEXT NOT NULL,
+        output TEXT NOT NULL,
+        success INTEGER NOT NULL
+    );
+    CREATE INDEX idx_command_outputs_session_id ON command_outputs(session_id);
+
+    CREATE VIRTUAL TABLE history_fts USING fts5(
+        content,
+        kind UNINDEXED,
+        session_id UNINDEXED,
+        created_at UNINDEXED,
+        tokenize = 'porter unicode61'
+    );
+    INSERT INTO history_fts (content, kind, session_id, created_at)
+        SELECT content, 'conversation', session_id, created_at FROM conversations;
+    INSERT INTO history_fts (content, kind, session_id, created_at)
+        SELECT topic || ': ' || content, 'memory', NULL, created_at FROM learnings;
+
+    CREATE TRIGGER conversations_fts AFTER INSERT ON conversations BEGIN
+        INSERT INTO history_fts (content, kind, session_id, created_at)
+        VALUES (new.content, 'conversation', new.session_id, new.created_at);
+    END;
+    CREATE TRIGGER learnings_fts AFTER INSERT ON learnings BEGIN
+        INSERT INTO history_fts (content, kind, session_id, created_at)
+        VALUES (new.topic || ': ' || new.content, 'memory', NULL, new.created_at);
+    END;
+    CREATE TRIGGER command_outputs_fts AFTER INSERT ON command_outputs BEGIN
+        INSERT INTO history_fts (content, kind, session_id, created_at)
+        VALUES ('$ ' || new.command || char(10) || new.output, 'command', new.session_id, new.created_at);
+    END;",
+    // Databases created before this imported the legacy files when they were new
+    "CREATE TABLE meta (
+        key TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    );
+    INSERT INTO meta (key, value) VALUES ('legacy_imported', '1');",
+];
+
+/// (table, key, column) for each column `[storage] encrypt` seals
+const ENCRYPTED_COLUMNS: [(&str, &str, &str); 6] = [
+    ("conversations", "id", "content"),
+    ("learnings", "id", "content"),
+    ("command_outputs", "id", "output"),
+    ("agent_sessions", "task_id", "data"),
+    ("transcripts", "task_id", "markdown"),
+    ("audit_log", "id", "content"),
+];
+
+/// A transcript as stored: metadata, rendered markdown and its individual entries
+pub struct StoredTranscript<'a> {
+    pub task_id: &'a str,
+    pub agent_name: &'a str,
+    pub description: &'a str,
+    pub markdown: String,
+    /// (timestamp, kind, content)
+    pub entries: Vec<(DateTime<Local>, &'a str, &'a str)>,
+}
+
+pub struct Database {
+    conn: Connection,
+    /// Set when `[storage] encrypt` is on; text columns are sealed with it
+    cipher: Option<Arc<dyn Cipher>>,
+}
+
+impl Database {
+    /// Open the project database, creating and migrating it as needed
+    pub fn open() -> Result<Self> {
+        Self::open_at(DATABASE_PATH)
+    }
+
+    pub fn open_at(path: impl AsRef<Path>) -> Result<Self> {
+        Self::open_with(path, encryption::configured()?)
+    }
+
+    pub fn open_with(path: impl AsRef<Path>, cipher: Option<Arc<dyn Cipher>>) -> Result<Self> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create database directory: {}", parent.display()))?;
+        }
+
+        let conn = Connection::open(path)
+            .with_context(|| format!("Failed to open database {}", path.display()))?;
+        // The TUI, CLI and background writer may all have the database open at once
+        conn.busy_timeout(std::time::Duration::from_secs(5))?;
+
+        let mut db = Self { conn, cipher };
+        db.migrate()?;
+        if path == Path::new(DATABASE_PATH) {
+            if let Err(e) = db.import_legacy_once(Path::new(LEGACY_KNOWLEDGE_BASE), Path::new(LEGACY_AGENT_DIR)) {
+                eprintln!("Warning: Failed to import existing memory into the database, will retry next time: {}", e);
+            }
+        }
+
+        Ok(db)
+    }
+
+    /// Run pending migrations
+    fn migrate(&mut self) -> Result<()> {
+        let version: usize = self.conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        if version >= MIGRATIONS.len() {
+            return Ok(());
+        }
+
+        let tx = self.conn.transaction()?;
+        for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            tx.execute_batch(migration)
+                .with_context(|| format!("Database migration {} failed", i + 1))?;
+        }
+        // A new database hasn't imported anything yet
+        if version == 0 {
+            tx.execute("DELETE FROM meta WHERE key = 'legacy_imported'", [])?;
+        }
+        tx.pragma_update(None, "user_version", MIGRATIONS.len())?;
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.cipher.is_some()
+    }
+
+    /// Text as it should be stored: encrypted and prefixed when there's a cipher
+    fn seal(&self, text: &str) -> Result<String> {
+        match &self.cipher {
+            Some(cipher) => Ok(format!("{}{}", ENCRYPTED_PREFIX, cipher.encrypt(text)?)),
+            None => Ok(text.to_string()),
+        }
+    }
+
+    /// Stored text as plain text. Rows written before encryption was turned on are read as-is.
+    fn unseal(&self, stored: String) -> Result<String> {
+        let Some(sealed) = stored.strip_prefix(ENCRYPTED_PREFIX) else {
+            return Ok(stored);
+        };
+        let cipher = self.cipher.as_ref().ok_or_else(|| {
+            anyhow::anyhow!("The database has encrypted entries; turn on [storage] encrypt and make the key available")
+        })?;
+        cipher.decrypt(sealed)
+    }
+
+    fn unseal_all<T>(&self, rows: Vec<(T, String)>) -> Result<Vec<(T, String)>> {
+        rows.into_iter().map(|(key, text)| Ok((key, self.unseal(text)?))).collect()
+    }
+
+    /// Values in the encrypted text columns as (encrypted, still in plain text)
+    pub fn encryption_counts(&self) -> Result<(usize, usize)> {
+        let mut counts = (0, 0);
+        for (table, _, column) in ENCRYPTED_COLUMNS {
+            let (encrypted, plain): (i64, i64) = self.conn.query_row(
+                &format!("SELECT COUNT(*) FILTER (WHERE {column} LIKE 'enc:v1:%'), COUNT(*) FILTER (WHERE {column} NOT LIKE 'enc:v1:%') FROM {table}"),
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )?;
+            counts.0 += encrypted as usize;
+            counts.1 += plain as usize;
+        }
+        Ok(counts)
+    }
+
+    /// Encrypt every text column still in plain text, and drop the plain text search index
+    /// and free pages that held it. Returns how many values were encrypted.
+    pub fn encrypt_existing(&mut self) -> Result<usize> {
+        let cipher = self.cipher.clone().ok_or_else(|| anyhow::anyhow!("Turn on [storage] encrypt first"))?;
+        let mut encrypted = 0;
+        let tx = self.conn.transaction()?;
+        for (table, key, column) in ENCRYPTED_COLUMNS {
+            let rows: Vec<(rusqlite::types::Value, String)> = {
+                let mut stmt = tx.prepare(&format!("SELECT {key}, {column} FROM {table} WHERE {column} NOT LIKE 'enc:v1:%'"))?;
+                let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+                rows.collect::<rusqlite::Result<Vec<_>>>()?
+            };
+            for (id, text) in rows {
+                let sealed = format!("{}{}", ENCRYPTED_PREFIX, cipher.encrypt(&text)?);
+                tx.execute(&format!("UPDATE {table} SET {column} = ?1 WHERE {key} = ?2"), params![sealed, id])?;
+                encrypted += 1;
+            }
+        }
+        tx.execute("DELETE FROM history_fts", [])?;
+        tx.commit()?;
+        self.conn.execute_batch("VACUUM")?;
+        Ok(encrypted)
+    }
+}
+
+// Backups
+impl Database {
+    /// A consistent copy of the whole database at `path`, even while other processes write to it
+    pub fn snapshot_to(&self, path: &Path) -> Result<()> {
+        self.conn
+            .execute("VACUUM INTO ?1", params![path.to_string_lossy()])
+            .with_context(|| format!("Failed to copy the database to {}", path.display()))?;
+        Ok(())
+    }
+
+    /// Replace `tables` with their rows from the database at `backup`, which is migrated first
+    /// so its columns match. Stored text is copied as-is, so encrypted rows need the same key.
+    /// Returns how many rows were restored.
+    pub fn restore_tables(&mut self, backup: &Path, tables: &[&str]) -> Result<usize> {
+        Self::open_with(backup, None)?;
+        self.conn.execute("ATTACH DATABASE ?1 AS backup", params![backup.to_string_lossy()])?;
+        let restored = self.copy_tables(tables);
+        self.conn.execute("DETACH DATABASE backup", [])?;
+        restored
+    }
+
+    fn copy_tables(&mut self, tables: &[&str]) -> Result<usize> {
+        let encrypted = self.is_encrypted();
+        let tx = self.conn.transaction()?;
+        let mut restored = 0;
+        for table in tables {
+            // The insert triggers index the restored rows again
+            let kind = match *table {
+                "conversations" => Some("conversation"),
+                "learnings" => Some("memory"),
+                "command_outputs" => Some("command"),
+                _ => None,
+            };
+            if let Some(kind) = kind {
+                tx.execute("DELETE FROM history_fts WHERE kind = ?1", params![kind])?;
+            }
+            tx.execute(&format!("DELETE FROM main.{table}"), [])?;
+            restored += tx.execute(&format!("INSERT INTO main.{table} SELECT * FROM backup.{table}"), [])?;
+        }
+        if encrypted {
+            tx.execute("DELETE FROM history_fts", [])?;
+        }
+        tx.commit()?;
+        Ok(restored)
+    }
+}
+
+// Conversations and learnings
+impl Database {
+    pub fn insert_conversations(&mut self, entries: &[(DateTime<Local>, String)], session_id: Option<&str>) -> Result<()> {
+        let entries = entries
+            .iter()
+            .map(|(at, content)| Ok((at, self.seal(content)?)))
+            .collect::<Result<Vec<_>>>()?;
+        // A savepoint, so this also nests inside the legacy import's
+        let tx = self.conn.savepoint()?;
+        {
+            let mut stmt = tx.prepare("INSERT INTO conversations (created_at, content, session_id) VALUES (?1, ?2, ?3)")?;
+            for (at, content) in &entries {
+                stmt.execute(params![at.with_timezone(&Utc), content, session_id])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Most recent unarchived conversation entries, newest first
+    pub fn recent_conversations(&self, limit: usize) -> Result<Vec<(DateTime<Local>, String)>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT created_at, content FROM conversations WHERE archived = 0 ORDER BY created_at DESC, id DESC LIMIT ?1",
+        )?;
+        let rows = stmt.query_map(params![limit as i64], conversation_row)?;
+        self.unseal_all(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+    }
+
+    /// Conversation entries since `since`, oldest first
+    pub fn conversations_since(&self, since: DateTime<Local>) -> Result<Vec<(DateTime<Local>, String)>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT created_at, content FROM conversations WHERE created_at >= ?1 ORDER BY created_at, id",
+        )?;
+        let rows = stmt.query_map(params![since.with_timezone(&Utc)], conversation_row)?;
+        self.unseal_all(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+    }
+
+    /// Mark conversation entries older than `cutoff` as archived. Returns how many were archived.
+    pub fn archive_conversations_before(&self, cutoff: DateTime<Local>) -> Result<usize> {
+        Ok(self.conn.execute(
+            "UPDATE conversations SET archived = 1 WHERE archived = 0 AND created_at < ?1",
+            params![cutoff.with_timezone(&Utc)],
+        )?)
+    }
+
+    pub fn insert_learning(&self, topic: &str, content: &str) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO learnings (topic, content, created_at) VALUES (?1, ?2, ?3)",
+            params![topic, self.seal(content)?, Utc::now()],
+        )?;
+        Ok(())
+    }
+
+    /// Topics whose name or entries contain `query` (case-insensitive), with their first entry
+    pub fn search_learnings(&self, query: &str) -> Result<Vec<(String, String)>> {
+        if self.is_encrypted() {
+            // Encrypted entries can't be matched in SQL, so they're searched once decrypted
+            let query = query.to_lowercase();
+            let mut topics: Vec<(String, String)> = Vec::new();
+            let mut matched: Vec<String> = Vec::new();
+            for (_, topic, content) in self.all_learnings()? {
+                if topic.to_lowercase().contains(&query) || content.to_lowercase().contains(&query) {
+                    matched.push(topic.clone());
+                }
+                if !topics.iter().any(|(known, _)| *known == topic) {
+                    topics.push((topic, content));
+                }
+            }
+            topics.retain(|(topic, _)| matched.contains(topic));
+            topics.sort();
+            return Ok(topics);
+        }
+        let pattern = format!("%{}%", escape_like(query));
+        let mut stmt = self.conn.prepare(
+            "SELECT topic, content FROM learnings WHERE id IN (
+                SELECT MIN(id) FROM learnings GROUP BY topic
+                HAVING SUM(topic LIKE ?1 ESCAPE '\\' OR content LIKE ?1 ESCAPE '\\') > 0
+            ) ORDER BY topic",
+        )?;
+        let rows = stmt.query_map(params![pattern], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+    }
+
+    /// Every learning as (id, topic, content), oldest first
+    pub fn all_learnings(&self) -> Result<Vec<(i64, String, String)>> {
+        let mut stmt = self.conn.prepare("SELECT id, topic, content FROM learnings ORDER BY id")?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+        rows.collect::<rusqlite::Result<Vec<(i64, String, String)>>>()?
+            .into_iter()
+            .map(|(id, topic, content)| Ok((id, topic, self.unseal(content)?)))
+            .collect()
+    }
+}
+
+// Command history and full-text search
+impl Database {
+    pub fn insert_command_output(&self, session_id: &str, command: &str, output: &str, success: bool) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO command_outputs (session_id, created_at, command, output, success) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![session_id, Utc::now(), command, self.seal(output)?, success],
+        )?;
+        Ok(())
+    }
+
+    /// Ranked matches for an FTS5 query (see `history::fts_query`), best first
+    pub fn search_history(&self, fts_query: &str, limit: usize) -> Result<Vec<HistoryHit>> {
+        if self.is_encrypted() {
+            return Err(anyhow::anyhow!("History search isn't available while [storage] encrypt is on"));
+        }
+        let mut stmt = self.conn.prepare(
+            "SELECT kind, session_id, created_at, snippet(history_fts, 0, '[', ']', '...', 16)
+             FROM history_fts WHERE history_fts MATCH ?1 ORDER BY rank LIMIT ?2",
+        )?;
+        let rows = stmt.query_map(params![fts_query, limit as i64], |row| {
+            let created_at: DateTime<Utc> = row.get(2)?;
+            Ok(HistoryHit {
+                kind: row.get(0)?,
+                session_id: row.get(1)?,
+                created_at: created_at.with_timezone(&Local),
+                snippet: row.get(3)?,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+    }
+
+    /// A session's conversation entries and commands in order, as (timestamp, kind, content)
+    pub fn session_history(&self, session_id: &str) -> Result<Vec<(DateTime<Local>, String, String)>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT created_at, 'conversation', NULL, content FROM conversations WHERE session_id = ?1
+             UNION ALL
+             SELECT created_at, 'command', command, output FROM command_outputs WHERE session_id = ?1
+             ORDER BY 1",
+        )?;
+        let rows = stmt.query_map(params![session_id], |row| {
+            let created_at: DateTime<Utc> = row.get(0)?;
+            Ok((created_at.with_timezone(&Local), row.get::<_, String>(1)?, row.get::<_, Option<String>>(2)?, row.get::<_, String>(3)?))
+        })?;
+        rows.collect::<rusqlite::Result<Vec<_>>>()?
+            .into_iter()
+            .map(|(at, kind, command, content)| {
+                let content = self.unseal(content)?;
+                Ok(match command {
+                    Some(command) => (at, kind, format!("$ {}\n{}", command, content)),
+                    None => (at, kind, content),
+                })
+            })
+            .collect()
+    }
+}
+
+// Agent sessions and transcripts
+impl Database {
+    /// Insert or replace a session checkpoint; `data` is the serialized session
+    pub fn save_session(&self, task_id: &str, description: &str, finished: bool, data: &str) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO agent_sessions (task_id, description, finished, data, updated_at) VALUES (?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT(task_id) DO UPDATE SET description = ?2, finished = ?3, data = ?4, updated_at = ?5",
+            params![task_id, description, finished, self.seal(data)?, Utc::now()],
+        )?;
+        Ok(())
+    }
+
+    pub fn load_session(&self, task_id: &str) -> Result<Option<String>> {
+        let data = self.conn
+            .query_row("SELECT data FROM agent_sessions WHERE task_id = ?1", params![task_id], |row| row.get(0))
+            .optional()?;
+        data.map(|data| self.unseal(data)).transpose()
+    }
+
+    /// Serialized sessions that have not finished, most recently updated first
+    pub fn unfinished_sessions(&self) -> Result<Vec<(String, String)>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT task_id, data FROM agent_sessions WHERE finished = 0 ORDER BY updated_at DESC",
+        )?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        self.unseal_all(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+    }
+
+    /// Store a transcript, replacing any earlier save of the same task
+    pub fn save_transcript(&mut self, transcript: &StoredTranscript) -> Result<()> {
+        let markdown = self.seal(&transcript.markdown)?;
+        let entries = transcript.entries
+            .iter()
+            .map(|(at, kind, content)| Ok((at, kind, self.seal(content)?)))
+            .collect::<Result<Vec<_>>>()?;
+        // A savepoint, so this also nests inside the legacy import's
+        let tx = self.conn.savepoint()?;
+        tx.execute(
+            "INSERT INTO transcripts (task_id, agent_name, description, markdown, updated_at) VALUES (?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT(task_id) DO UPDATE SET agent_name = ?2, description = ?3, markdown = ?4, updated_at = ?5, archived = 0",
+            params![transcript.task_id, transcript.agent_name, transcript.description, markdown, Utc::now()],
+        )?;
+        tx.execute("DELETE FROM audit_log WHERE task_id = ?1", params![transcript.task_id])?;
+        {
+            let mut stmt = tx.prepare("INSERT INTO audit_log (task_id, created_at, kind, content) VALUES (?1, ?2, ?3, ?4)")?;
+            for (at, kind, content) in &entries {
+                stmt.execute(params![transcript.task_id, at.with_timezone(&Utc), kind, content])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn load_transcript(&self, task_id: &str) -> Result<Option<String>> {
+        let markdown = self.conn
+            .query_row("SELECT markdown FROM transcripts WHERE task_id = ?1", params![task_id], |row| row.get(0))
+            .optional()?;
+        markdown.map(|markdown| self.unseal(markdown)).transpose()
+    }
+
+    /// Ids of unarchived transcripts, newest first
+    pub fn list_transcripts(&self) -> Result<Vec<String>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT task_id FROM transcripts WHERE archived = 0 ORDER BY updated_at DESC",
+        )?;
+        let rows = stmt.query_map([], |row| row.get(0))?;
+        Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+    }
+
+    /// Archive transcripts last updated before `cutoff`, keeping those whose session can
+    /// still be resumed. Returns how many were archived.
+    pub fn archive_transcripts_before(&self, cutoff: DateTime<Local>) -> Result<usize> {
+        Ok(self.conn.execute(
+            "UPDATE transcripts SET archived = 1 WHERE archived = 0 AND updated_at < ?1
+             AND task_id NOT IN (SELECT task_id FROM agent_sessions WHERE finished = 0)",
+            params![cutoff.with_timezone(&Utc)],
+        )?)
+    }
+}
+
+// Token usage
+impl Database {
+    pub fn insert_usage(&self, provider: &str, model: &str, tokens: &TokenUsage, estimated: bool) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO llm_usage (created_at, provider, model, prompt_tokens, completion_tokens, estimated)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![Utc::now(), provider, model, tokens.prompt_tokens as i64, tokens.completion_tokens as i64, estimated],
+        )?;
+        Ok(())
+    }
+
+    /// Usage per provider/model across all sessions, most used first
+    pub fn usage_totals(&self) -> Result<Vec<ModelUsage>> {
+        self.usage_totals_since(DateTime::<Utc>::MIN_UTC)
+    }
+
+    /// Like `usage_totals`, counting only calls made at or after `since`
+    pub fn usage_totals_since(&self, since: DateTime<Utc>) -> Result<Vec<ModelUsage>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT provider, model, COUNT(*), SUM(prompt_tokens), SUM(completion_tokens), MAX(estimated)
+             FROM llm_usage WHERE created_at >= ?1 GROUP BY provider, model
+             ORDER BY SUM(prompt_tokens) + SUM(completion_tokens) DESC",
+        )?;
+        let rows = stmt.query_map(params![since], |row| {
+            Ok(ModelUsage {
+                provider: row.get(0)?,
+                model: row.get(1)?,
+                requests: row.get::<_, i64>(2)? as u64,
+                tokens: TokenUsage {
+                    prompt_tokens: row.get::<_, i64>(3)? as u64,
+                    completion_tokens: row.get::<_, i64>(4)? as u64,
+                },
+                estimated: row.get(5)?,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+    }
+}
+
+// One-time import of the files the database replaces. The files are left in place.
+impl Database {
+    /// Import the legacy files unless that already succeeded. It's all or nothing, and only
+    /// marked done with the data, so a failure is retried on the next open.
+    fn import_legacy_once(&mut self, knowledge_base: &Path, agent_dir: &Path) -> Result<()> {
+        let imported: Option<String> = self.conn
+            .query_row("SELECT value FROM meta WHERE key = 'legacy_imported'", [], |row| row.get(0))
+            .optional()?;
+        if imported.is_some() {
+            return Ok(());
+        }
+        self.conn.execute_batch("SAVEPOINT legacy_import")?;
+        let result = self.import_legacy_files(knowledge_base, agent_dir).and_then(|_| {
+            self.conn.execute("INSERT INTO meta (key, value) VALUES ('legacy_imported', '1')", [])?;
+            Ok(())
+        });
+        match result {
+            Ok(()) => self.conn.execute_batch("RELEASE legacy_import")?,
+            Err(e) => {
+                self.conn.execute_batch("ROLLBACK TO legacy_import; RELEASE legacy_import")?;
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    fn import_legacy_files(&mut self, knowledge_base: &Path, agent_dir: &Path) -> Result<()> {
+        // Daily conversation logs become one entry per day
+        let mut conversations = Vec::new();
+        for (path, content) in markdown_files(&knowledge_base.join("core/conversation")) {
+            let date = path.file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.strip_prefix("session-"))
+                .and_then(|date| NaiveDate::parse_from_str(date, "%Y-%m-%d").ok())
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+                .and_then(|date| Local.from_local_datetime(&date).earliest());
+            if let Some(date) = date {
+                conversations.push((date, content));
+            }
+        }
+        self.insert_conversations(&conversations, None)?;
+
+        for (path, content) in markdown_files(&knowledge_base.join("core/knowledge-management")) {
+            if let Some(topic) = path.file_stem().and_then(|stem| stem.to_str()) {
+                self.insert_learning(topic, &content)?;
+            }
+        }
+
+        let Ok(entries) = fs::read_dir(agent_dir) else {
+            return Ok(());
+        };
+        for path in entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()) {
+            let Some(task_id) = path.file_stem().and_then(|stem| stem.to_str()) else { continue };
+            let Ok(content) = fs::read_to_string(&path) else { continue };
+
+            match path.extension().and_then(|ext| ext.to_str()) {
+                Some("json") => {
+                    let Ok(session) = serde_json::from_str::<serde_json::Value>(&content) else { continue };
+                    let description = session["task"]["description"].as_str().unwrap_or_default();
+                    let steps = session["task"]["subtasks"].as_array().map_or(0, |steps| steps.len());
+                    let finished = session["plan_position"].as_u64().unwrap_or(0) as usize >= steps;
+                    self.save_session(task_id, description, finished, &content)?;
+                }
+                Some("md") => {
+                    self.save_transcript(&StoredTranscript {
+                        task_id,
+                        agent_name: "",
+                        description: "",
+                        markdown: content,
+                        entries: Vec::new(),
+                    })?;
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn conversation_row(row: &rusqlite::Row) -> rusqlite::Result<(DateTime<Local>, String)> {
+    let at: DateTime<Utc> = row.get(0)?;
+    Ok((at.with_timezone(&Local), row.get(1)?))
+}
+
+fn escape_like(query: &str) -> String {
+    query.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
+
+fn markdown_files(dir: &Path) -> Vec<(PathBuf, String)> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().and_then(|ext| ext.to_str()) == Some("md"))
+        .filter_map(|path| fs::read_to_string(&path).ok().map(|content| (path, content)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    use tempfile::TempDir;
+
+    fn schema_version(db: &Database) -> usize {
+        db.conn.query_row("PRAGMA user_version", [], |row| row.get(0)).unwrap()
+    }
+
+    #[test]
+    fn test_migrations_are_idempotent() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("kota.db");
+
+        let db = Database::open_at(&path).unwrap();
+        assert_eq!(schema_version(&db), MIGRATIONS.len());
+        drop(db);
+
+        // Reopening doesn't rerun migrations
+        let db = Database::open_at(&path).unwrap();
+        assert_eq!(schema_version(&db), MIGRATIONS.len());
+    }
+
+    #[test]
+    fn test_restore_tables_from_snapshot() {
+        let dir = TempDir::new().unwrap();
+        let mut db = Database::open_at(dir.path().join("kota.db")).unwrap();
+        db.insert_learning("style", "Prefers tabs").unwrap();
+        db.insert_conversations(&[(Local::now(), "User: kept question".to_string())], None).unwrap();
+        let snapshot = dir.path().join("snapshot.db");
+        db.snapshot_to(&snapshot).unwrap();
+
+        db.insert_learning("style", "Prefers spaces").unwrap();
+        db.insert_conversations(&[(Local::now(), "User: newer question".to_string())], None).unwrap();
+        assert_eq!(db.restore_tables(&snapshot, &["learnings"]).unwrap(), 1);
+
+        let learnings = db.all_learnings().unwrap();
+        assert_eq!(learnings.len(), 1);
+        assert_eq!(learnings[0].2, "Prefers tabs");
+        assert_eq!(db.recent_conversations(5).unwrap().len(), 2);
+        assert_eq!(db.search_history("spaces", 5).unwrap().len(), 0);
+        assert_eq!(db.search_history("tabs", 5).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_conversations_and_learnings() {
+        let dir = TempDir::new().unwrap();
+        let mut db = Database::open_at(dir.path().join("kota.db")).unwrap();
+
+        let now = Local::now();
+        db.insert_conversations(&[
+            (now - Duration::days(40), "User: old question".to_string()),
+            (now, "User: new question".to_string()),
+        ], None).unwrap();
+
+        assert_eq!(db.archive_conversations_before(now - Duration::days(30)).unwrap(), 1);
+        let recent = db.recent_conversations(5).unwrap();
+        assert_eq!(recent.len(), 1);
+        assert_eq!(recent[0].1, "User: new question");
+        assert_eq!(db.conversations_since(now - Duration::hours(1)).unwrap().len(), 1);
+
+        db.insert_learning("rust", "Ownership moves values").unwrap();
+        db.insert_learning("rust", "Borrowing 100% avoids copies").unwrap();
+        db.insert_learning("tokio", "Async runtime").unwrap();
+        assert_eq!(db.search_learnings("BORROWING").unwrap(), vec![("rust".to_string(), "Ownership moves values".to_string())]);
+        assert_eq!(db.search_learnings("100%").unwrap().len(), 1);
+        assert!(db.search_learnings("python").unwrap().is_empty());
+    }
+
+    /// Reverses text, so tests can tell sealed values apart without openssl
+    struct ReversingCipher;
+
+    impl Cipher for ReversingCipher {
+        fn encrypt(&self, plain: &str) -> Result<String> {
+            Ok(plain.chars().rev().collect())
+        }
+
+        fn decrypt(&self, sealed: &str) -> Result<String> {
+            Ok(sealed.chars().rev().collect())
+        }
+    }
+
+    #[test]
+    fn test_encrypted_columns() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("kota.db");
+        let plain = Database::open_with(&path, None).unwrap();
+        plain.insert_learning("rust", "Ownership moves values").unwrap();
+        plain.insert_command_output("s1", "ls", "Cargo.toml", true).unwrap();
+        drop(plain);
+
+        let mut db = Database::open_with(&path, Some(Arc::new(ReversingCipher))).unwrap();
+        db.save_session("task-1", "Add config loading", false, "{\"step\":1}").unwrap();
+        db.insert_learning("tokio", "Async runtime").unwrap();
+        assert_eq!(db.encryption_counts().unwrap(), (2, 2));
+        let stored: String = db.conn.query_row("SELECT data FROM agent_sessions", [], |row| row.get(0)).unwrap();
+        assert_eq!(stored, "enc:v1:}1:\"pets\"{");
+
+        // Old plain rows and new sealed ones read back the same
+        assert_eq!(db.load_session("task-1").unwrap().as_deref(), Some("{\"step\":1}"));
+        assert_eq!(db.search_learnings("async").unwrap(), vec![("tokio".to_string(), "Async runtime".to_string())]);
+        assert_eq!(db.session_history("s1").unwrap()[0].2, "$ ls\nCargo.toml");
+        assert!(db.search_history("ownership", 5).is_err());
+
+        assert_eq!(db.encrypt_existing().unwrap(), 2);
+        assert_eq!(db.encryption_counts().unwrap(), (4, 0));
+        assert_eq!(db.all_learnings().unwrap()[0].2, "Ownership moves values");
+        drop(db);
+        assert!(Database::open_with(&path, None).unwrap().load_session("task-1").is_err());
+    }
+
+    #[test]
+    fn test_sessions_transcripts_and_usage() {
+        let dir = TempDir::new().unwrap();
+        let mut db = Database::open_at(dir.path().join("kota.db")).unwrap();
+
+        db.save_session("task-1", "Add config loading", false, "{\"step\":1}").unwrap();
+        db.save_session("task-1", "Add config loading", false, "{\"step\":2}").unwrap();
+        db.save_session("task-2", "Done task", true, "{}").unwrap();
+        assert_eq!(db.load_session("task-1").unwrap(), Some("{\"step\":2}".to_string()));
+        assert_eq!(db.unfinished_sessions().unwrap().len(), 1);
+
+        let transcript = StoredTranscript {
+            task_id: "task-1",
+            agent_name: "CodeAgent",
+            description: "Add config loading",
+            markdown: "# Agent Transcript: task-1".to_string(),
+            entries: vec![(Local::now(), "Prompt", "Write the loader")],
+        };
+        db.save_transcript(&transcript).unwrap();
+        db.save_transcript(&transcript).unwrap();
+        assert_eq!(db.list_transcripts().unwrap(), vec!["task-1".to_string()]);
+        assert_eq!(db.load_transcript("task-1").unwrap().as_deref(), Some("# Agent Transcript: task-1"));
+
+        // Resumable sessions keep their transcripts out of the archive
+        assert_eq!(db.archive_transcripts_before(Local::now() + Duration::hours(1)).unwrap(), 0);
+
+        let tokens = TokenUsage { prompt_tokens: 100, completion_tokens: 20 };
+        db.insert_usage("Anthropic", "claude-sonnet-4-20250514", &tokens, false).unwrap();
+        db.insert_usage("Anthropic", "claude-sonnet-4-20250514", &tokens, true).unwrap();
+        let totals = db.usage_totals().unwrap();
+        assert_eq!(totals.len(), 1);
+        assert_eq!(totals[0].requests, 2);
+        assert_eq!(totals[0].tokens.total(), 240);
+        assert!(totals[0].estimated);
+        assert_eq!(db.usage_totals_since(Utc::now() - Duration::hours(1)).unwrap().len(), 1);
+        assert!(db.usage_totals_since(Utc::now() + Duration::hours(1)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_history_search_ranks_and_groups_by_session() {
+        let dir = TempDir::new().unwrap();
+        let mut db = Database::open_at(dir.path().join("kota.db")).unwrap();
+
+        db.insert_conversations(&[
+            (Local::now(), "User: why does the parser panic on empty input?".to_string()),
+            (Local::now(), "KOTA: The parser indexes the first token without checking".to_string()),
+        ], Some("session-a")).unwrap();
+        db.insert_command_output("session-a", "cargo test parser", "test parser::empty ... FAILED", false).unwrap();
+        db.insert_learning("parsing", "Always check for empty input before indexing tokens").unwrap();
+        db.insert_conversations(&[(Local::now(), "User: add a README section".to_string())], Some("session-b")).unwrap();
+
+        let hits = db.search_history(&crate::history::fts_query("parser").unwrap(), 10).unwrap();
+        assert_eq!(hits.len(), 3);
+        assert!(hits.iter().all(|hit| hit.session_id.as_deref() == Some("session-a")));
+        assert!(hits.iter().any(|hit| hit.kind == "command" && hit.snippet.contains("[parser]")));
+
+        // Stemming matches "indexing" against "indexes"
+        let kinds: Vec<String> = db.search_history(&crate::history::fts_query("index").unwrap(), 10).unwrap()
+            .into_iter().map(|hit| hit.kind).collect();
+        assert_eq!(kinds.len(), 2);
+        assert!(kinds.contains(&"memory".to_string()));
+
+        let session = db.session_history("session-a").unwrap();
+        assert_eq!(session.len(), 3);
+        assert_eq!(session[2].1, "command");
+        assert!(session[2].2.starts_with("$ cargo test parser"));
+    }
+
+    #[test]
+    fn test_import_legacy_files() {
+        let dir = TempDir::new().unwrap();
+        let knowledge_base = dir.path().join("knowledge-base");
+        let agent_dir = dir.path().join("agents");
+        fs::create_dir_all(knowledge_base.join("core/conversation")).unwrap();
+        fs::create_dir_all(knowledge_base.join("core/knowledge-management")).unwrap();
+        fs::create_dir_all(&agent_dir).unwrap();
+        fs::write(knowledge_base.join("core/conversation/session-2024-01-02.md"), "# Conversation Log").unwrap();
+        fs::write(knowledge_base.join("core/knowledge-management/rust.md"), "# rust\n\nOwnership").unwrap();
+        fs::write(agent_dir.join("abc-123.json"), r#"{"task":{"description":"Resume me","subtasks":[{}, {}]},"plan_position":1}"#).unwrap();
+        fs::write(agent_dir.join("abc-123.md"), "# Agent Transcript: abc-123").unwrap();
+
+        let mut db = Database::open_at(dir.path().join("kota.db")).unwrap();
+        // A failure part way leaves nothing behind and isn't marked done
+        db.conn.execute_batch("CREATE TRIGGER fail_sessions BEFORE INSERT ON agent_sessions BEGIN SELECT RAISE(ABORT, 'disk full'); END;").unwrap();
+        assert!(db.import_legacy_once(&knowledge_base, &agent_dir).is_err());
+        assert_eq!(db.search_learnings("ownership").unwrap().len(), 0);
+        db.conn.execute_batch("DROP TRIGGER fail_sessions").unwrap();
+
+        db.import_legacy_once(&knowledge_base, &agent_dir).unwrap();
+        db.import_legacy_once(&knowledge_base, &agent_dir).unwrap();
+
+        let since = Local.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        assert_eq!(db.conversations_since(since).unwrap().len(), 1);
+        assert_eq!(db.search_learnings("ownership").unwrap().len(), 1);
+        assert_eq!(db.unfinished_sessions().unwrap()[0].0, "abc-123");
+        assert!(db.load_transcript("abc-123").unwrap().is_some());
+    }
+}
@@ -0,0 +1,249 @@
+use std::env;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, OnceLock};
+
+use anyhow::{Context, Result};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+
+use super::Database;
+use crate::prompts::PromptsConfig;
+
+/// Overrides the keychain
+pub const KEY_ENV: &str = "KOTA_STORAGE_KEY";
+/// Stored text starting with this is ciphertext
+pub const ENCRYPTED_PREFIX: &str = "enc:v1:";
+// Starts encrypted files, so they're told apart from plain ones
+const FILE_MAGIC: &[u8] = b"KOTAENC1";
+const KEYCHAIN_SERVICE: &str = "kota-storage";
+const KEYCHAIN_ACCOUNT: &str = "kota";
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
+/// Turns text into ciphertext that's safe to keep in a TEXT column, and back
+pub trait Cipher: Send + Sync {
+    fn encrypt(&self, plain: &str) -> Result<String>;
+    fn decrypt(&self, sealed: &str) -> Result<String>;
+}
+
+/// ChaCha20-Poly1305 with the storage key. Each value gets a random nonce, and a value that
+/// was altered or sealed with another key fails to decrypt.
+pub struct KeyCipher {
+    cipher: ChaCha20Poly1305,
+}
+
+impl KeyCipher {
+    /// `key` is 64 hex characters, as `kota storage init` creates
+    pub fn new(key: &str) -> Result<Self> {
+        let bytes = from_hex(key.trim()).filter(|bytes| bytes.len() == 32).ok_or_else(|| {
+            anyhow::anyhow!("The storage key must be 64 hex characters; create one with `kota storage init` or `openssl rand -hex 32`")
+        })?;
+        Ok(Self { cipher: ChaCha20Poly1305::new(Key::from_slice(&bytes)) })
+    }
+
+    /// Encrypt arbitrary bytes, like a backup archive
+    pub fn encrypt_bytes(&self, plain: &[u8]) -> Result<Vec<u8>> {
+        let mut sealed = FILE_MAGIC.to_vec();
+        sealed.extend(self.seal(plain, FILE_MAGIC)?);
+        Ok(sealed)
+    }
+
+    pub fn decrypt_bytes(&self, sealed: &[u8]) -> Result<Vec<u8>> {
+        let sealed = sealed.strip_prefix(FILE_MAGIC).ok_or_else(|| anyhow::anyhow!("Not a file KOTA encrypted"))?;
+        self.open(sealed, FILE_MAGIC)
+    }
+
+    /// nonce, then ciphertext and tag. `associated` says what kind of data it is, so a stored
+    /// value can't be passed off as a file or the other way around.
+    fn seal(&self, plain: &[u8], associated: &[u8]) -> Result<Vec<u8>> {
+        let nonce = random_bytes(NONCE_LEN)?;
+        let ciphertext = self
+            .cipher
+            .encrypt(Nonce::from_slice(&nonce), Payload { msg: plain, aad: associated })
+            .map_err(|_| anyhow::anyhow!("Failed to encrypt"))?;
+        let mut sealed = nonce;
+        sealed.extend(ciphertext);
+        Ok(sealed)
+    }
+
+    fn open(&self, sealed: &[u8], associated: &[u8]) -> Result<Vec<u8>> {
+        if sealed.len() < NONCE_LEN + TAG_LEN {
+            return Err(anyhow::anyhow!("Encrypted value is truncated"));
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        self.cipher
+            .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: associated })
+            .map_err(|_| anyhow::anyhow!("Encrypted value failed authentication; it was changed or sealed with another key"))
+    }
+}
+
+impl Cipher for KeyCipher {
+    fn encrypt(&self, plain: &str) -> Result<String> {
+        Ok(to_hex(&self.seal(plain.as_bytes(), ENCRYPTED_PREFIX.as_bytes())?))
+    }
+
+    fn decrypt(&self, sealed: &str) -> Result<String> {
+        let sealed = from_hex(sealed.trim()).ok_or_else(|| anyhow::anyhow!("Encrypted value isn't hex"))?;
+        String::from_utf8(self.open(&sealed, ENCRYPTED_PREFIX.as_bytes())?).map_err(|_| anyhow::anyhow!("Decrypted value isn't text"))
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) || !text.is_ascii() {
+        return None;
+    }
+    (0..text.len()).step_by(2).map(|i| u8::from_str_radix(&text[i..i + 2], 16).ok()).collect()
+}
+
+/// Bytes from the OS random source
+fn random_bytes(len: usize) -> Result<Vec<u8>> {
+    let mut bytes = vec![0u8; len];
+    getrandom::getrandom(&mut bytes).map_err(|e| anyhow::anyhow!("Failed to get random bytes from the OS: {}", e))?;
+    Ok(bytes)
+}
+
+/// The cipher `[storage]` asks for, found once per process. None when encryption is off;
+/// an error when it's on but there's no key, so nothing is written in plain text by mistake.
+pub fn configured() -> Result<Option<Arc<dyn Cipher>>> {
+    static CIPHER: OnceLock<Result<Option<Arc<dyn Cipher>>, String>> = OnceLock::new();
+    CIPHER
+        .get_or_init(|| {
+            let config = PromptsConfig::load().unwrap_or_default().storage;
+            if !config.encrypt {
+                return Ok(None);
+            }
+            let (key, _) = find_key().ok_or_else(|| {
+                format!("[storage] encrypt is on but there's no key; run `kota storage init` or set {}", KEY_ENV)
+            })?;
+            let cipher = KeyCipher::new(&key).map_err(|e| e.to_string())?;
+            Ok(Some(Arc::new(cipher) as Arc<dyn Cipher>))
+        })
+        .clone()
+        .map_err(|e| anyhow::anyhow!(e))
+}
+
+/// The cipher with the storage key, whether or not the database is encrypted
+pub fn key_cipher() -> Result<KeyCipher> {
+    let (key, _) = find_key()
+        .ok_or_else(|| anyhow::anyhow!("No storage key; run `kota storage init` or set {}", KEY_ENV))?;
+    KeyCipher::new(&key)
+}
+
+/// `kota storage [status|init|encrypt]`: show whether storage is encrypted, create a key in
+/// the OS keychain, or encrypt what's already in the database
+pub fn run(args: &[String]) -> Result<()> {
+    match args.first().map(String::as_str).unwrap_or("status") {
+        "status" => {
+            let config = PromptsConfig::load().unwrap_or_default().storage;
+            println!("Encryption: {}", if config.encrypt { "on" } else { "off ([storage] encrypt in prompts.toml)" });
+            match find_key() {
+                Some((_, source)) => println!("Key: found in {}", source),
+                None => println!("Key: none (run `kota storage init` or set {})", KEY_ENV),
+            }
+            if config.encrypt {
+                let (encrypted, plain) = Database::open()?.encryption_counts()?;
+                println!("Stored values: {} encrypted, {} in plain text", encrypted, plain);
+                if plain > 0 {
+                    println!("Run `kota storage encrypt` to encrypt the rest");
+                }
+            }
+            Ok(())
+        }
+        "init" => {
+            let source = create_key()?;
+            println!("Storage key created in {}. Set encrypt = true under [storage] in prompts.toml to use it", source);
+            Ok(())
+        }
+        "encrypt" => {
+            let encrypted = Database::open()?.encrypt_existing()?;
+            println!("Encrypted {} stored value(s)", encrypted);
+            Ok(())
+        }
+        other => Err(anyhow::anyhow!("Unknown storage command {}; use status, init or encrypt", other)),
+    }
+}
+
+/// The storage key and where it came from
+pub fn find_key() -> Option<(String, &'static str)> {
+    if let Some(key) = env::var(KEY_ENV).ok().filter(|key| !key.trim().is_empty()) {
+        return Some((key, KEY_ENV));
+    }
+    keychain_lookup().map(|key| (key, keychain_name()))
+}
+
+fn keychain_name() -> &'static str {
+    if cfg!(target_os = "macos") { "the macOS keychain" } else { "the Secret Service keyring" }
+}
+
+fn keychain_lookup() -> Option<String> {
+    let output = if cfg!(target_os = "macos") {
+        Command::new("security")
+            .args(["find-generic-password", "-s", KEYCHAIN_SERVICE, "-a", KEYCHAIN_ACCOUNT, "-w"])
+            .output()
+    } else {
+        Command::new("secret-tool").args(["lookup", "service", KEYCHAIN_SERVICE]).output()
+    };
+    let output = output.ok().filter(|output| output.status.success())?;
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string()).filter(|key| !key.is_empty())
+}
+
+/// Generate a random key and keep it in the OS keychain. The key goes in on stdin, never as
+/// an argument other processes could see. Returns where it was stored.
+pub fn create_key() -> Result<&'static str> {
+    if let Some((_, source)) = find_key() {
+        return Err(anyhow::anyhow!("A storage key already exists in {}", source));
+    }
+    let key = to_hex(&random_bytes(32)?);
+
+    let (program, args, input) = if cfg!(target_os = "macos") {
+        let command = format!("add-generic-password -U -s {} -a {} -w {}\n", KEYCHAIN_SERVICE, KEYCHAIN_ACCOUNT, key);
+        ("security", vec!["-i".to_string()], command)
+    } else {
+        let args = ["store", "--label=KOTA storage key", "service", KEYCHAIN_SERVICE].map(String::from).to_vec();
+        ("secret-tool", args, key.clone())
+    };
+    let mut child = Command::new(program)
+        .args(&args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .with_context(|| format!("Failed to run {}; set {} instead", program, KEY_ENV))?;
+    child.stdin.take().context("No stdin for the keychain")?.write_all(input.as_bytes())?;
+    if !child.wait()?.success() || keychain_lookup().as_deref() != Some(key.as_str()) {
+        return Err(anyhow::anyhow!("Couldn't store the key in {}; set {} instead", keychain_name(), KEY_ENV));
+    }
+    Ok(keychain_name())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_cipher_round_trip() {
+        assert!(KeyCipher::new("not hex").is_err());
+        let cipher = KeyCipher::new(&"0123456789abcdef".repeat(4)).unwrap();
+        let sealed = cipher.encrypt("Prefers tabs; deploys on Fridays").unwrap();
+        assert!(!sealed.contains("Fridays"));
+        assert_ne!(sealed, cipher.encrypt("Prefers tabs; deploys on Fridays").unwrap());
+        assert_eq!(cipher.decrypt(&sealed).unwrap(), "Prefers tabs; deploys on Fridays");
+
+        let wrong_key = KeyCipher::new(&"fedcba9876543210".repeat(4)).unwrap();
+        assert!(wrong_key.decrypt(&sealed).is_err());
+        let mut tampered = from_hex(&sealed).unwrap();
+        tampered[NONCE_LEN] ^= 1;
+        assert!(cipher.decrypt(&to_hex(&tampered)).is_err());
+        // Sealed as a value, so it doesn't open as a file
+        let as_file = [FILE_MAGIC, from_hex(&sealed).unwrap().as_slice()].concat();
+        assert!(cipher.decrypt_bytes(&as_file).is_err());
+
+        let archive = cipher.encrypt_bytes(&[0x1f, 0x8b, 0, 1, 2]).unwrap();
+        assert!(archive.starts_with(FILE_MAGIC));
+        assert_eq!(cipher.decrypt_bytes(&archive).unwrap(), vec![0x1f, 0x8b, 0, 1, 2]);
+    }
+}
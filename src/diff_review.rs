@@ -0,0 +1,391 @@
+use crate::sr_parser::{FileOperation, SearchReplaceBlock};
+
+// Blocks bigger than this (search lines x replace lines) are shown as a single hunk
+// rather than building the whole LCS table
+const MAX_DIFF_CELLS: usize = 4_000_000;
+
+/// One run of changed lines in a block: what the search text had there and what the
+/// replacement puts in its place
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hunk {
+    pub removed: Vec<String>,
+    pub added: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DiffLine {
+    Same(String),
+    /// Index into the block's hunks
+    Changed(usize),
+}
+
+/// A line of a block's diff as the CLI shows it, numbered as in the file before and after
+#[derive(Debug, Clone, PartialEq)]
+pub enum DiffRow {
+    Same { old: usize, new: usize, text: String },
+    Removed { old: usize, text: String },
+    Added { new: usize, text: String },
+}
+
+/// A row of the side-by-side view: the old text's line on the left, the new text's on the right
+#[derive(Debug, Clone, PartialEq)]
+pub struct SideBySideRow {
+    pub left: Option<(usize, String)>,
+    pub right: Option<(usize, String)>,
+    pub changed: bool,
+}
+
+/// A search/replace block split into hunks that can be accepted one at a time
+#[derive(Debug, Clone)]
+pub struct BlockReview {
+    pub block: SearchReplaceBlock,
+    pub lines: Vec<DiffLine>,
+    pub hunks: Vec<Hunk>,
+    pub accepted: Vec<bool>,
+}
+
+impl BlockReview {
+    pub fn new(block: SearchReplaceBlock) -> Self {
+        let (lines, hunks) = diff_lines(&block.search_lines, &block.replace_lines);
+        let accepted = vec![true; hunks.len()];
+        Self { block, lines, hunks, accepted }
+    }
+
+    /// The block with only the accepted hunks applied, or None when none were accepted
+    pub fn patched(&self) -> Option<SearchReplaceBlock> {
+        if !self.accepted.contains(&true) {
+            return None;
+        }
+        let mut replace: Vec<&str> = Vec::new();
+        for line in &self.lines {
+            match line {
+                DiffLine::Same(text) => replace.push(text),
+                DiffLine::Changed(index) => {
+                    let hunk = &self.hunks[*index];
+                    let side = if self.accepted[*index] { &hunk.added } else { &hunk.removed };
+                    replace.extend(side.iter().map(String::as_str));
+                }
+            }
+        }
+        Some(SearchReplaceBlock { replace_lines: replace.join("\n"), ..self.block.clone() })
+    }
+
+    /// Every line of the diff, numbered from `first_line`, where the search text starts in
+    /// the file. A block creating a file is all added lines.
+    pub fn unified_rows(&self, first_line: usize) -> Vec<DiffRow> {
+        let (mut old, mut new) = (first_line, first_line);
+        let mut rows = Vec::new();
+        for line in &self.lines {
+            match line {
+                DiffLine::Same(text) => {
+                    rows.push(DiffRow::Same { old, new, text: text.clone() });
+                    old += 1;
+                    new += 1;
+                }
+                DiffLine::Changed(index) => {
+                    let hunk = &self.hunks[*index];
+                    for text in &hunk.removed {
+                        if !self.block.search_lines.is_empty() {
+                            rows.push(DiffRow::Removed { old, text: text.clone() });
+                        }
+                        old += 1;
+                    }
+                    for text in &hunk.added {
+                        rows.push(DiffRow::Added { new, text: text.clone() });
+                        new += 1;
+                    }
+                }
+            }
+        }
+        rows
+    }
+
+    /// `unified_rows` with each hunk's removed and added lines paired up side by side
+    pub fn side_by_side_rows(&self, first_line: usize) -> Vec<SideBySideRow> {
+        let mut rows = Vec::new();
+        let mut removed = Vec::new();
+        let mut added = Vec::new();
+        let flush = |removed: &mut Vec<(usize, String)>, added: &mut Vec<(usize, String)>, rows: &mut Vec<SideBySideRow>| {
+            let count = removed.len().max(added.len());
+            let mut removed = std::mem::take(removed).into_iter();
+            let mut added = std::mem::take(added).into_iter();
+            for _ in 0..count {
+                rows.push(SideBySideRow { left: removed.next(), right: added.next(), changed: true });
+            }
+        };
+        for row in self.unified_rows(first_line) {
+            match row {
+                DiffRow::Same { old, new, text } => {
+                    flush(&mut removed, &mut added, &mut rows);
+                    rows.push(SideBySideRow { left: Some((old, text.clone())), right: Some((new, text)), changed: false });
+                }
+                DiffRow::Removed { old, text } => removed.push((old, text)),
+                DiffRow::Added { new, text } => added.push((new, text)),
+            }
+        }
+        flush(&mut removed, &mut added, &mut rows);
+        rows
+    }
+}
+
+/// Hunk-by-hunk review of a response's edits, followed by its deletes and renames. Every
+/// hunk and operation starts accepted.
+#[derive(Debug, Clone)]
+pub struct DiffReview {
+    pub original_prompt: String,
+    pub blocks: Vec<BlockReview>,
+    /// Each delete or rename and whether it's accepted
+    pub operations: Vec<(FileOperation, bool)>,
+    /// The selected hunk, as (block, hunk) indexes; blocks past the edits are the operations,
+    /// with a single "hunk" each
+    pub block: usize,
+    pub hunk: usize,
+    /// How many times these edits were already asked for again after not matching
+    pub retry: u32,
+}
+
+impl DiffReview {
+    /// Blocks that change nothing are dropped
+    pub fn new(original_prompt: &str, blocks: Vec<SearchReplaceBlock>) -> Self {
+        Self {
+            original_prompt: original_prompt.to_string(),
+            blocks: blocks.into_iter().map(BlockReview::new).filter(|review| !review.hunks.is_empty()).collect(),
+            operations: Vec::new(),
+            block: 0,
+            hunk: 0,
+            retry: 0,
+        }
+    }
+
+    /// Review `operations` after the edits
+    pub fn with_operations(mut self, operations: Vec<FileOperation>) -> Self {
+        self.operations = operations.into_iter().map(|operation| (operation, true)).collect();
+        self
+    }
+
+    /// Nothing left to review
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty() && self.operations.is_empty()
+    }
+
+    pub fn current(&self) -> Option<&BlockReview> {
+        self.blocks.get(self.block)
+    }
+
+    /// The selected operation, once the selection is past the edits
+    pub fn current_operation(&self) -> Option<&(FileOperation, bool)> {
+        self.block.checked_sub(self.blocks.len()).and_then(|i| self.operations.get(i))
+    }
+
+    fn item_count(&self) -> usize {
+        self.blocks.len() + self.operations.len()
+    }
+
+    fn hunk_count(&self, block: usize) -> usize {
+        self.blocks.get(block).map_or(1, |block| block.hunks.len())
+    }
+
+    /// Select the next hunk, moving on to the next block after a block's last
+    pub fn next(&mut self) {
+        if self.hunk + 1 < self.hunk_count(self.block) {
+            self.hunk += 1;
+        } else if self.block + 1 < self.item_count() {
+            self.block += 1;
+            self.hunk = 0;
+        }
+    }
+
+    pub fn previous(&mut self) {
+        if self.hunk > 0 {
+            self.hunk -= 1;
+        } else if self.block > 0 {
+            self.block -= 1;
+            self.hunk = self.hunk_count(self.block).saturating_sub(1);
+        }
+    }
+
+    /// Accept or reject the selected hunk and move to the next one
+    pub fn decide(&mut self, accept: bool) {
+        let operation = self.block.checked_sub(self.blocks.len());
+        if let Some((_, decision)) = operation.and_then(|i| self.operations.get_mut(i)) {
+            *decision = accept;
+        } else if let Some(decision) = self.blocks.get_mut(self.block).and_then(|block| block.accepted.get_mut(self.hunk)) {
+            *decision = accept;
+        }
+        self.next();
+    }
+
+    /// Accept or reject every hunk in the selected block and move to the next block
+    pub fn decide_block(&mut self, accept: bool) {
+        match self.blocks.get_mut(self.block) {
+            Some(block) => block.accepted.iter_mut().for_each(|decision| *decision = accept),
+            None => {
+                let Some((_, decision)) = self.operations.get_mut(self.block - self.blocks.len()) else { return };
+                *decision = accept;
+            }
+        }
+        if self.block + 1 < self.item_count() {
+            self.block += 1;
+            self.hunk = 0;
+        }
+    }
+
+    /// (accepted, total) hunks across all blocks
+    pub fn counts(&self) -> (usize, usize) {
+        self.blocks.iter().fold((0, 0), |(accepted, total), block| {
+            (accepted + block.accepted.iter().filter(|decision| **decision).count(), total + block.hunks.len())
+        })
+    }
+
+    /// The blocks to apply, each rebuilt from its accepted hunks
+    pub fn accepted_blocks(&self) -> Vec<SearchReplaceBlock> {
+        self.blocks.iter().filter_map(BlockReview::patched).collect()
+    }
+
+    pub fn accepted_operations(&self) -> Vec<FileOperation> {
+        self.operations.iter().filter(|(_, accepted)| *accepted).map(|(operation, _)| operation.clone()).collect()
+    }
+}
+
+/// Line diff of `old` against `new`, splitting on '\n' so joining the lines back gives
+/// the text exactly, trailing newline included
+fn diff_lines(old: &str, new: &str) -> (Vec<DiffLine>, Vec<Hunk>) {
+    let old: Vec<&str> = old.split('\n').collect();
+    let new: Vec<&str> = new.split('\n').collect();
+    let mut lines = Vec::new();
+    let mut hunks = Vec::new();
+
+    if old.len() * new.len() > MAX_DIFF_CELLS {
+        lines.push(DiffLine::Changed(0));
+        hunks.push(Hunk { removed: old.iter().map(|line| line.to_string()).collect(), added: new.iter().map(|line| line.to_string()).collect() });
+        return (lines, hunks);
+    }
+
+    // common[i][j]: length of the longest common subsequence of old[i..] and new[j..]
+    let mut common = vec![vec![0u32; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = if old[i] == new[j] { common[i + 1][j + 1] + 1 } else { common[i + 1][j].max(common[i][j + 1]) };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            lines.push(DiffLine::Same(old[i].to_string()));
+            i += 1;
+            j += 1;
+            continue;
+        }
+        // Changed lines next to each other share a hunk
+        if !matches!(lines.last(), Some(DiffLine::Changed(_))) {
+            lines.push(DiffLine::Changed(hunks.len()));
+            hunks.push(Hunk { removed: Vec::new(), added: Vec::new() });
+        }
+        let Some(hunk) = hunks.last_mut() else { break };
+        if j == new.len() || (i < old.len() && common[i + 1][j] >= common[i][j + 1]) {
+            hunk.removed.push(old[i].to_string());
+            i += 1;
+        } else {
+            hunk.added.push(new[j].to_string());
+            j += 1;
+        }
+    }
+    (lines, hunks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(search: &str, replace: &str) -> SearchReplaceBlock {
+        SearchReplaceBlock { file_path: "src/lib.rs".to_string(), search_lines: search.to_string(), replace_lines: replace.to_string() }
+    }
+
+    #[test]
+    fn test_hunks_and_recombining() {
+        let search = "fn a() {\n    old_a();\n}\n\nfn b() {\n    old_b();\n}\n";
+        let replace = "fn a() {\n    new_a();\n}\n\nfn b() {\n    new_b();\n    more_b();\n}\n";
+        let mut review = BlockReview::new(block(search, replace));
+        assert_eq!(review.hunks.len(), 2);
+        assert_eq!(review.hunks[1], Hunk { removed: vec!["    old_b();".to_string()], added: vec!["    new_b();".to_string(), "    more_b();".to_string()] });
+        assert_eq!(review.patched().unwrap().replace_lines, replace);
+
+        review.accepted[1] = false;
+        let patched = review.patched().unwrap();
+        assert_eq!(patched.search_lines, search);
+        assert_eq!(patched.replace_lines, "fn a() {\n    new_a();\n}\n\nfn b() {\n    old_b();\n}\n");
+
+        review.accepted[0] = false;
+        assert!(review.patched().is_none());
+    }
+
+    #[test]
+    fn test_unified_and_side_by_side_rows() {
+        let review = BlockReview::new(block("fn a() {\n    old();\n}", "fn a() {\n    new();\n    more();\n}"));
+        assert_eq!(review.unified_rows(10), vec![
+            DiffRow::Same { old: 10, new: 10, text: "fn a() {".to_string() },
+            DiffRow::Removed { old: 11, text: "    old();".to_string() },
+            DiffRow::Added { new: 11, text: "    new();".to_string() },
+            DiffRow::Added { new: 12, text: "    more();".to_string() },
+            DiffRow::Same { old: 12, new: 13, text: "}".to_string() },
+        ]);
+        let rows = review.side_by_side_rows(10);
+        assert_eq!(rows.len(), 4);
+        assert_eq!(rows[1], SideBySideRow { left: Some((11, "    old();".to_string())), right: Some((11, "    new();".to_string())), changed: true });
+        assert_eq!(rows[2], SideBySideRow { left: None, right: Some((12, "    more();".to_string())), changed: true });
+        assert_eq!(rows[3].left, Some((12, "}".to_string())));
+
+        let new_file = BlockReview::new(block("", "line one\nline two"));
+        assert_eq!(new_file.unified_rows(1), vec![
+            DiffRow::Added { new: 1, text: "line one".to_string() },
+            DiffRow::Added { new: 2, text: "line two".to_string() },
+        ]);
+    }
+
+    #[test]
+    fn test_review_navigation_and_decisions() {
+        let mut review = DiffReview::new("Rename", vec![
+            block("a\nb\nc", "A\nb\nC"),
+            block("same", "same"),
+            block("x", "y"),
+        ]);
+        assert_eq!(review.blocks.len(), 2);
+        assert_eq!(review.counts(), (3, 3));
+
+        review.decide(false);
+        assert_eq!((review.block, review.hunk), (0, 1));
+        review.next();
+        assert_eq!((review.block, review.hunk), (1, 0));
+        review.next();
+        assert_eq!((review.block, review.hunk), (1, 0));
+        review.previous();
+        assert_eq!((review.block, review.hunk), (0, 1));
+
+        review.decide_block(false);
+        assert_eq!(review.counts(), (1, 3));
+        let accepted = review.accepted_blocks();
+        assert_eq!(accepted.len(), 1);
+        assert_eq!(accepted[0].replace_lines, "y");
+    }
+
+    #[test]
+    fn test_operations_follow_the_edits() {
+        let mut review = DiffReview::new("Split", vec![block("x", "y")]).with_operations(vec![
+            FileOperation::Delete { path: "src/old.rs".to_string() },
+            FileOperation::Rename { from: "src/a.rs".to_string(), to: "src/b/a.rs".to_string() },
+        ]);
+        assert!(review.current_operation().is_none());
+        review.next();
+        assert_eq!(review.current_operation().unwrap().0.path(), "src/old.rs");
+        review.decide(false);
+        assert_eq!((review.block, review.hunk), (2, 0));
+        review.decide_block(true);
+        assert_eq!(review.block, 2);
+        review.previous();
+        review.previous();
+        assert_eq!((review.block, review.hunk), (0, 0));
+        assert_eq!(review.accepted_operations(), vec![FileOperation::Rename { from: "src/a.rs".to_string(), to: "src/b/a.rs".to_string() }]);
+        assert!(DiffReview::new("x", vec![block("same", "same")]).is_empty());
+    }
+}
@@ -1,14 +1,38 @@
-use chrono::{Local, DateTime};
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use reqwest::ClientBuilder;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::clock::clock;
 use crate::context::ContextManager;
+use crate::focus;
+use crate::prompts::{PromptsConfig, TemperatureUnit, WeatherConfig};
+
+const WEATHER_CACHE_FILE: &str = ".kota/cache/weather.json";
+const FORECAST_URL: &str = "https://api.open-meteo.com/v1/forecast";
+const GEOCODING_URL: &str = "https://geocoding-api.open-meteo.com/v1/search";
+const WEATHER_TIMEOUT_SECS: u64 = 10;
+// The TUI asks this often at most, so an unreachable API isn't retried every tick
+const WEATHER_CHECK_INTERVAL: Duration = Duration::from_secs(5 * 60);
 
 pub struct DynamicPromptData {
     pub date: String,
     pub time: String,
+    pub timezone: String,
     pub context_file_count: usize,
     pub working_directory: String,
     pub git_branch: Option<String>,
     pub system_info: SystemInfo,
+    /// The last weather fetched, when `[weather]` is on
+    pub weather: Option<Weather>,
+    /// Minutes left in the running `/focus` block
+    pub focus_minutes_left: Option<i64>,
 }
 
 #[derive(Clone)]
@@ -18,7 +42,8 @@ pub struct SystemInfo {
 
 impl DynamicPromptData {
     pub fn new(context_manager: &ContextManager) -> Self {
-        let now: DateTime<Local> = Local::now();
+        let clock = clock();
+        let now = clock.now();
         
         // Get git branch if in a git repo
         let git_branch = get_git_branch();
@@ -29,15 +54,254 @@ impl DynamicPromptData {
         };
         
         Self {
-            date: now.format("%Y-%m-%d").to_string(),
-            time: now.format("%H:%M:%S").to_string(),
-            context_file_count: context_manager.file_paths.len(),
+            date: clock.date(&now),
+            time: clock.time(&now),
+            timezone: clock.zone_label(),
+            context_file_count: context_manager.file_paths().len(),
             working_directory: std::env::current_dir()
                 .map(|p| p.display().to_string())
                 .unwrap_or_else(|_| "unknown".to_string()),
             git_branch,
             system_info,
+            weather: cached_weather(),
+            focus_minutes_left: focus::current().map(|session| session.minutes_left(Utc::now())),
+        }
+    }
+}
+
+/// Conditions from Open-Meteo for `[weather]`'s location
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Weather {
+    pub location: String,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub temperature: f64,
+    pub high: f64,
+    pub low: f64,
+    /// Today's highest chance of rain or snow, in percent
+    pub precipitation_chance: Option<u8>,
+    pub description: String,
+    /// °C or °F
+    pub unit: String,
+    pub fetched_at: DateTime<Utc>,
+}
+
+impl Weather {
+    /// `Berlin: 14°C, partly cloudy; today 9-16°C, 40% chance of rain`
+    pub fn summary(&self) -> String {
+        let mut summary = format!(
+            "{}: {:.0}{}, {}; today {:.0}-{:.0}{}",
+            self.location, self.temperature, self.unit, self.description, self.low, self.high, self.unit
+        );
+        if let Some(chance) = self.precipitation_chance {
+            summary.push_str(&format!(", {}% chance of rain", chance));
         }
+        summary
+    }
+
+    /// The summary with how old it is when it's out of date, e.g. after fetching failed offline
+    pub fn describe(&self, max_age_minutes: u64) -> String {
+        let age = (Utc::now() - self.fetched_at).num_minutes();
+        if age > max_age_minutes as i64 {
+            format!("{} (as of {}, {} min ago)", self.summary(), clock().time(&self.fetched_at), age)
+        } else {
+            self.summary()
+        }
+    }
+
+    fn is_for(&self, place: &Place) -> bool {
+        (self.latitude - place.latitude).abs() < 0.01 && (self.longitude - place.longitude).abs() < 0.01
+    }
+}
+
+struct WeatherState {
+    weather: Option<Weather>,
+    /// Whether the cache file has been read yet
+    loaded: bool,
+    last_check: Option<Instant>,
+}
+
+static WEATHER: Mutex<WeatherState> = Mutex::new(WeatherState { weather: None, loaded: false, last_check: None });
+
+/// The last weather fetched, from memory or the cache file, without touching the network
+pub fn cached_weather() -> Option<Weather> {
+    let mut state = WEATHER.lock().unwrap_or_else(|e| e.into_inner());
+    if !state.loaded {
+        state.loaded = true;
+        state.weather = read_cache(Path::new(WEATHER_CACHE_FILE));
+    }
+    state.weather.clone()
+}
+
+fn read_cache(path: &Path) -> Option<Weather> {
+    serde_json::from_str(&fs::read_to_string(path).ok()?).ok()
+}
+
+/// Refresh the weather in the background when `[weather]` is on and the cached copy is old,
+/// checking at most every few minutes. Needs a tokio runtime; without one it does nothing.
+pub fn refresh_weather_in_background() {
+    {
+        let mut state = WEATHER.lock().unwrap_or_else(|e| e.into_inner());
+        if state.last_check.is_some_and(|checked| checked.elapsed() < WEATHER_CHECK_INTERVAL) {
+            return;
+        }
+        state.last_check = Some(Instant::now());
+    }
+    let Ok(runtime) = tokio::runtime::Handle::try_current() else { return };
+    runtime.spawn(async {
+        refresh_weather().await;
+    });
+}
+
+/// The current weather: the cached copy while it's fresher than `cache_minutes`, otherwise
+/// fetched again. When fetching fails, as it does offline, the last copy is used however old.
+/// None when `[weather]` is off or nothing was ever fetched.
+pub async fn refresh_weather() -> Option<Weather> {
+    let config = PromptsConfig::load().unwrap_or_default().weather;
+    if !config.enabled {
+        return None;
+    }
+    let cached = cached_weather();
+    let fresh = |weather: &Weather| (Utc::now() - weather.fetched_at).num_minutes() < config.cache_minutes as i64;
+    if let Some(weather) = cached.as_ref().filter(|weather| fresh(weather) && config.location_matches(weather)) {
+        return Some(weather.clone());
+    }
+    match fetch_weather(&config).await {
+        Ok(weather) => {
+            if let Err(e) = write_cache(Path::new(WEATHER_CACHE_FILE), &weather) {
+                eprintln!("Warning: Failed to cache the weather: {}", e);
+            }
+            WEATHER.lock().unwrap_or_else(|e| e.into_inner()).weather = Some(weather.clone());
+            Some(weather)
+        }
+        Err(e) => {
+            eprintln!("Warning: Couldn't fetch the weather, using the last known conditions: {}", e);
+            cached
+        }
+    }
+}
+
+fn write_cache(path: &Path, weather: &Weather) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, serde_json::to_string_pretty(weather)?)?;
+    Ok(())
+}
+
+/// Coordinates and a display name for them
+struct Place {
+    name: String,
+    latitude: f64,
+    longitude: f64,
+}
+
+impl WeatherConfig {
+    /// Whether `weather` is for the place configured now, so a move isn't answered from cache
+    fn location_matches(&self, weather: &Weather) -> bool {
+        match (self.latitude, self.longitude, &self.location) {
+            (Some(latitude), Some(longitude), _) => weather.is_for(&Place { name: String::new(), latitude, longitude }),
+            (_, _, Some(location)) => weather.location.to_lowercase().starts_with(&location.split(',').next().unwrap_or("").trim().to_lowercase()),
+            _ => false,
+        }
+    }
+}
+
+async fn fetch_weather(config: &WeatherConfig) -> Result<Weather> {
+    let client = ClientBuilder::new()
+        .timeout(Duration::from_secs(WEATHER_TIMEOUT_SECS))
+        .build()
+        .context("Failed to create HTTP client")?;
+    let place = match (config.latitude, config.longitude, &config.location) {
+        (Some(latitude), Some(longitude), location) => Place {
+            name: location.clone().unwrap_or_else(|| format!("{:.2}, {:.2}", latitude, longitude)),
+            latitude,
+            longitude,
+        },
+        (_, _, Some(location)) => {
+            let body: Value = client
+                .get(GEOCODING_URL)
+                .query(&[("name", location.as_str()), ("count", "1")])
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+            parse_place(&body).ok_or_else(|| anyhow::anyhow!("Open-Meteo doesn't know a place called {}", location))?
+        }
+        _ => return Err(anyhow::anyhow!("Set location, or latitude and longitude, under [weather] in prompts.toml")),
+    };
+
+    let unit = match config.units {
+        TemperatureUnit::Celsius => "celsius",
+        TemperatureUnit::Fahrenheit => "fahrenheit",
+    };
+    let body: Value = client
+        .get(FORECAST_URL)
+        .query(&[
+            ("latitude", place.latitude.to_string().as_str()),
+            ("longitude", place.longitude.to_string().as_str()),
+            ("current", "temperature_2m,weather_code"),
+            ("daily", "temperature_2m_max,temperature_2m_min,precipitation_probability_max"),
+            ("temperature_unit", unit),
+            ("timezone", "auto"),
+            ("forecast_days", "1"),
+        ])
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    parse_forecast(&body, &place, config.units).ok_or_else(|| anyhow::anyhow!("Unexpected response from Open-Meteo"))
+}
+
+fn parse_place(body: &Value) -> Option<Place> {
+    let result = body["results"].get(0)?;
+    let name = result["name"].as_str()?;
+    let name = match result["country"].as_str() {
+        Some(country) => format!("{}, {}", name, country),
+        None => name.to_string(),
+    };
+    Some(Place { name, latitude: result["latitude"].as_f64()?, longitude: result["longitude"].as_f64()? })
+}
+
+fn parse_forecast(body: &Value, place: &Place, units: TemperatureUnit) -> Option<Weather> {
+    let current = &body["current"];
+    let daily = &body["daily"];
+    Some(Weather {
+        location: place.name.clone(),
+        latitude: place.latitude,
+        longitude: place.longitude,
+        temperature: current["temperature_2m"].as_f64()?,
+        high: daily["temperature_2m_max"].get(0)?.as_f64()?,
+        low: daily["temperature_2m_min"].get(0)?.as_f64()?,
+        precipitation_chance: daily["precipitation_probability_max"].get(0).and_then(Value::as_u64).map(|chance| chance.min(100) as u8),
+        description: weather_description(current["weather_code"].as_u64()?).to_string(),
+        unit: if units == TemperatureUnit::Fahrenheit { "°F" } else { "°C" }.to_string(),
+        fetched_at: Utc::now(),
+    })
+}
+
+/// The WMO weather interpretation codes Open-Meteo reports
+fn weather_description(code: u64) -> &'static str {
+    match code {
+        0 => "clear",
+        1 => "mostly clear",
+        2 => "partly cloudy",
+        3 => "overcast",
+        45 | 48 => "fog",
+        51 | 53 | 55 => "drizzle",
+        56 | 57 => "freezing drizzle",
+        61 | 63 => "rain",
+        65 => "heavy rain",
+        66 | 67 => "freezing rain",
+        71 | 73 | 77 => "snow",
+        75 => "heavy snow",
+        80..=82 => "rain showers",
+        85 | 86 => "snow showers",
+        95 => "thunderstorms",
+        96 | 99 => "thunderstorms with hail",
+        _ => "unknown conditions",
     }
 }
 
@@ -58,4 +322,60 @@ fn get_git_branch() -> Option<String> {
 
 // Add these dependencies to Cargo.toml:
 // hostname = "0.4"
-// whoami = "1.5"
\ No newline at end of file
+// whoami = "1.5"
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_open_meteo_responses() {
+        let place = parse_place(&json!({"results": [{"name": "Berlin", "country": "Germany", "latitude": 52.52, "longitude": 13.41}]})).unwrap();
+        assert_eq!(place.name, "Berlin, Germany");
+        assert!(parse_place(&json!({"generationtime_ms": 0.5})).is_none());
+
+        let body = json!({
+            "current": {"temperature_2m": 14.2, "weather_code": 2},
+            "daily": {"temperature_2m_max": [16.4], "temperature_2m_min": [8.6], "precipitation_probability_max": [40]}
+        });
+        let weather = parse_forecast(&body, &place, TemperatureUnit::Celsius).unwrap();
+        assert_eq!(weather.summary(), "Berlin, Germany: 14°C, partly cloudy; today 9-16°C, 40% chance of rain");
+        assert_eq!(weather.describe(30), weather.summary());
+        let old = Weather { fetched_at: Utc::now() - chrono::Duration::hours(3), ..weather.clone() };
+        assert!(old.describe(30).contains("180 min ago"));
+        assert!(parse_forecast(&json!({"current": {}}), &place, TemperatureUnit::Celsius).is_none());
+    }
+
+    #[test]
+    fn test_cache_round_trip_and_location() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("cache/weather.json");
+        assert!(read_cache(&path).is_none());
+        let weather = Weather {
+            location: "Berlin, Germany".to_string(),
+            latitude: 52.52,
+            longitude: 13.41,
+            temperature: 14.0,
+            high: 16.0,
+            low: 9.0,
+            precipitation_chance: None,
+            description: weather_description(61).to_string(),
+            unit: "°C".to_string(),
+            fetched_at: Utc::now(),
+        };
+        write_cache(&path, &weather).unwrap();
+        assert_eq!(read_cache(&path), Some(weather.clone()));
+
+        let config = |location: Option<&str>, latitude: Option<f64>| WeatherConfig {
+            location: location.map(String::from),
+            latitude,
+            longitude: latitude.map(|_| 13.41),
+            ..WeatherConfig::default()
+        };
+        assert!(config(Some("berlin"), None).location_matches(&weather));
+        assert!(!config(Some("Paris"), None).location_matches(&weather));
+        assert!(config(None, Some(52.52)).location_matches(&weather));
+        assert!(!config(None, Some(48.85)).location_matches(&weather));
+        assert!(!config(None, None).location_matches(&weather));
+    }
+}
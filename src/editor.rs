@@ -1,14 +1,42 @@
+use std::collections::HashSet;
 use std::fs;
 use std::io::{self, Write};
+use std::path::Path;
 use std::process::Command;
 use anyhow::Result;
 use colored::*;
-use crate::sr_parser::SearchReplaceBlock;
-use crate::llm;
+use crate::sr_parser::{self, FileOperation, SearchReplaceBlock};
+use crate::llm::{self, ModelConfig};
 use crate::input;
+use crate::approvals;
+use crate::prompts::{PromptsConfig, ReviewLayout};
+use crate::diff_review::{BlockReview, DiffRow};
 use crate::thinking;
+use crate::validate::{self, FailureAction, FileSnapshots, ParseFailure};
+use crate::focus;
+use crate::webhooks::{self, Event};
+use crate::undo;
+use crate::lint;
+use crate::trace::{self, SpanExt};
+
+/// Whether `block` creates a file: an empty search for a path that doesn't exist yet
+pub fn creates_file(block: &SearchReplaceBlock) -> bool {
+    block.search_lines.trim().is_empty() && !Path::new(&block.file_path).exists()
+}
 
 pub fn apply_sr_block(block: &SearchReplaceBlock) -> Result<()> {
+    let _span = trace::span("edit.apply_block").with("file", block.file_path.as_str());
+    if creates_file(block) {
+        create_parent_dirs(&block.file_path)?;
+        let content = if block.replace_lines.is_empty() || block.replace_lines.ends_with('\n') {
+            block.replace_lines.clone()
+        } else {
+            format!("{}\n", block.replace_lines)
+        };
+        return fs::write(&block.file_path, content)
+            .map_err(|e| anyhow::anyhow!("Failed to create file '{}': {}", block.file_path, e));
+    }
+
     // Read the file content
     let content = fs::read_to_string(&block.file_path)
         .map_err(|e| anyhow::anyhow!("Failed to read file '{}': {}", block.file_path, e))?;
@@ -18,7 +46,7 @@ pub fn apply_sr_block(block: &SearchReplaceBlock) -> Result<()> {
     
     // Check if any replacement was made
     if new_content == content {
-        return Err(anyhow::anyhow!("Search content not found in file '{}'", block.file_path));
+        return Err(anyhow::anyhow!("{} '{}': {}", NOT_FOUND, block.file_path, mismatch_reason(&content, &block.search_lines)));
     }
 
     // Write the modified content back to the file
@@ -28,16 +56,183 @@ pub fn apply_sr_block(block: &SearchReplaceBlock) -> Result<()> {
     Ok(())
 }
 
+/// Why `search` isn't in `content`: where the closest match stops, or that only whitespace differs
+fn mismatch_reason(content: &str, search: &str) -> String {
+    let file: Vec<&str> = content.lines().collect();
+    let wanted: Vec<&str> = search.lines().collect();
+    let Some(first) = wanted.first() else {
+        return "the search text is empty".to_string();
+    };
+    let shorten = |line: &str| {
+        let line = line.trim();
+        if line.chars().count() > 60 {
+            format!("{}...", line.chars().take(57).collect::<String>())
+        } else {
+            line.to_string()
+        }
+    };
+    // The start with the most search lines matching, ignoring surrounding whitespace
+    let best = (0..file.len())
+        .map(|start| {
+            let matching = wanted.iter().zip(&file[start..]).take_while(|(want, have)| want.trim() == have.trim()).count();
+            (start, matching)
+        })
+        .max_by_key(|&(start, matching)| (matching, std::cmp::Reverse(start)));
+    match best {
+        Some((start, matching)) if matching == wanted.len() => format!(
+            "it matches at line {} only when whitespace is ignored; check the indentation and trailing spaces",
+            start + 1
+        ),
+        Some((start, matching)) if matching > 0 => {
+            let found = file.get(start + matching).map(|line| format!("`{}`", shorten(line))).unwrap_or_else(|| "the end of the file".to_string());
+            format!(
+                "the first {} line(s) match at line {}, but line {} is {} where the search has `{}`",
+                matching, start + 1, start + matching + 1, found, shorten(wanted[matching])
+            )
+        }
+        _ => format!("its first line `{}` isn't in the file", shorten(first)),
+    }
+}
+
+// Start of the error `apply_sr_block` gives when a block's SEARCH text isn't in its file
+const NOT_FOUND: &str = "Search content not found in file";
+// Lines of the file shown around the closest match when asking for a block again
+const REGION_CONTEXT_LINES: usize = 10;
+// Most of a file shown when nothing in it resembles the SEARCH text
+const MAX_REGION_LINES: usize = 200;
+
+/// Whether `error` from `apply_sr_block` means the SEARCH text wasn't found, which asking
+/// the model again with the current file can fix
+pub fn is_mismatch(error: &anyhow::Error) -> bool {
+    error.to_string().starts_with(NOT_FOUND)
+}
+
+/// The lines of `content`, as a start and end index, most likely meant by `search`: the
+/// stretch with the most of its lines, plus some around it
+fn nearest_region(content: &str, search: &str) -> (usize, usize) {
+    let file: Vec<&str> = content.lines().collect();
+    let wanted: HashSet<&str> = search.lines().map(str::trim).filter(|line| !line.is_empty()).collect();
+    let window = search.lines().count().max(1);
+    let hits: Vec<usize> = file.iter().map(|line| usize::from(wanted.contains(line.trim()))).collect();
+    let mut best = (0, 0);
+    let mut count = 0;
+    for end in 0..file.len() {
+        count += hits[end];
+        if end >= window {
+            count -= hits[end - window];
+        }
+        if count > best.1 {
+            best = ((end + 1).saturating_sub(window), count);
+        }
+    }
+    match best {
+        (_, 0) => (0, file.len().min(MAX_REGION_LINES)),
+        (start, _) => (start.saturating_sub(REGION_CONTEXT_LINES), (start + window + REGION_CONTEXT_LINES).min(file.len())),
+    }
+}
+
+/// Ask `model` to write `block` again for its file as it is now, after its SEARCH text wasn't
+/// found. The part of the file it most likely meant goes along as context.
+pub async fn regenerate_block(block: &SearchReplaceBlock, model: &ModelConfig) -> Result<SearchReplaceBlock> {
+    let content = fs::read_to_string(&block.file_path)
+        .map_err(|e| anyhow::anyhow!("Failed to read file '{}': {}", block.file_path, e))?;
+    let (start, end) = nearest_region(&content, &block.search_lines);
+    let lines: Vec<&str> = content.lines().collect();
+    let context = format!(
+        "--- File: {} (lines {}-{} of {}) ---\n{}\n--- End File: {} ---",
+        block.file_path, start + 1, end, lines.len(), lines[start..end].join("\n"), block.file_path
+    );
+    let prompt = format!(
+        "Your SEARCH/REPLACE block for {path} couldn't be applied: {reason}. The SEARCH section has to copy lines of the file exactly, \
+including indentation. Lines {first}-{last} of the file as it is now are in context.\n\n\
+Your block was:\n{path}\n<<<<<<< SEARCH\n{search}\n=======\n{replace}\n>>>>>>> REPLACE\n\n\
+Write it again for the file as it is now, making the same change. Reply with only the corrected SEARCH/REPLACE block.",
+        path = block.file_path,
+        reason = mismatch_reason(&content, &block.search_lines),
+        first = start + 1,
+        last = end,
+        search = block.search_lines,
+        replace = block.replace_lines,
+    );
+    let reply = llm::ask_model_with_config(&prompt, &context, model).await?;
+    sr_parser::parse_sr_blocks(&reply)?
+        .into_iter()
+        .find(|corrected| corrected.file_path == block.file_path)
+        .ok_or_else(|| anyhow::anyhow!("The reply had no SEARCH/REPLACE block for {}", block.file_path))
+}
+
+/// Put every file in `files` back as `snapshots` recorded it, after a change later in the same
+/// batch failed. Returns the files that couldn't be restored, with why.
+pub fn roll_back(snapshots: &FileSnapshots, files: &[String]) -> Vec<String> {
+    files.iter().filter_map(|file| snapshots.restore(file).err().map(|e| e.to_string())).collect()
+}
+
+/// Put back the files that failed validation, and every other path of a file operation on
+/// one of them, so a rename isn't left half undone. Those operations are dropped from
+/// `operations`. Returns each path with how restoring it went.
+pub fn revert_failed(snapshots: &FileSnapshots, failed: &[String], operations: &mut Vec<FileOperation>) -> Vec<(String, Result<()>)> {
+    let mut paths = failed.to_vec();
+    let (undone, kept): (Vec<FileOperation>, Vec<FileOperation>) = std::mem::take(operations)
+        .into_iter()
+        .partition(|operation| operation.paths().iter().any(|path| failed.iter().any(|file| file == path)));
+    *operations = kept;
+    for path in undone.iter().flat_map(FileOperation::paths) {
+        if !paths.iter().any(|file| file == path) {
+            paths.push(path.to_string());
+        }
+    }
+    paths
+        .into_iter()
+        .map(|path| {
+            let result = snapshots.restore(&path);
+            (path, result)
+        })
+        .collect()
+}
+
+/// Delete or rename a file. Renames create the new path's directories and never replace
+/// an existing file.
+pub fn apply_file_operation(operation: &FileOperation) -> Result<()> {
+    let _span = trace::span("edit.file_operation").with("operation", operation.describe());
+    match operation {
+        FileOperation::Delete { path } => {
+            fs::remove_file(path).map_err(|e| anyhow::anyhow!("Failed to delete file '{}': {}", path, e))
+        }
+        FileOperation::Rename { from, to } => {
+            if !Path::new(from).is_file() {
+                return Err(anyhow::anyhow!("Failed to rename '{}': the file doesn't exist", from));
+            }
+            if Path::new(to).exists() {
+                return Err(anyhow::anyhow!("Failed to rename '{}': '{}' already exists", from, to));
+            }
+            create_parent_dirs(to)?;
+            fs::rename(from, to).map_err(|e| anyhow::anyhow!("Failed to rename '{}' to '{}': {}", from, to, e))
+        }
+    }
+}
+
+fn create_parent_dirs(path: &str) -> Result<()> {
+    match Path::new(path).parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        Some(parent) => fs::create_dir_all(parent)
+            .map_err(|e| anyhow::anyhow!("Failed to create directory '{}': {}", parent.display(), e)),
+        None => Ok(()),
+    }
+}
+
 async fn create_auto_commit(original_prompt: &str, modified_files: &[String]) -> Result<bool> {
     println!();
     println!("{}", "─".repeat(60).dimmed());
     println!("{}", "Creating commit...".bright_yellow());
     
-    // Stage the modified files
+    // Stage the modified files; removing deleted and renamed ones from the index
     for file in modified_files {
-        let output = Command::new("git")
-            .arg("add")
-            .arg(file)
+        let mut git = Command::new("git");
+        if Path::new(file).exists() {
+            git.arg("add").arg(file);
+        } else {
+            git.args(["rm", "--cached", "--quiet", "--ignore-unmatch", "--"]).arg(file);
+        }
+        let output = git
             .output()
             .map_err(|e| anyhow::anyhow!("Failed to stage file '{}': {}", file, e))?;
         
@@ -126,112 +321,490 @@ async fn create_auto_commit(original_prompt: &str, modified_files: &[String]) ->
     }
 }
 
-pub async fn confirm_and_apply_blocks(blocks: Vec<SearchReplaceBlock>, original_prompt: &str, context_manager: &crate::context::ContextManager) -> Result<()> {
-    if blocks.is_empty() {
-        return Ok(());
+/// Review and apply `blocks`, returning the ones that were applied so callers can record
+/// them in the context with `ContextManager::record_edits`
+pub async fn confirm_and_apply_blocks(blocks: Vec<SearchReplaceBlock>, original_prompt: &str, context_manager: &crate::context::ContextManager) -> Result<Vec<SearchReplaceBlock>> {
+    confirm_and_apply(blocks, Vec::new(), original_prompt, context_manager, None).await.map(|(applied, _)| applied)
+}
+
+/// Review and apply `blocks`, then the deletes and renames in `operations`, and commit them
+/// together. Returns what was applied, for `record_edits` and `record_file_operations`.
+/// With `model`, blocks whose SEARCH text isn't found are asked for again, up to
+/// `[edits] match_retries` times.
+pub async fn confirm_and_apply(blocks: Vec<SearchReplaceBlock>, operations: Vec<FileOperation>, original_prompt: &str, context_manager: &crate::context::ContextManager, model: Option<&ModelConfig>) -> Result<(Vec<SearchReplaceBlock>, Vec<FileOperation>)> {
+    if blocks.is_empty() && operations.is_empty() {
+        return Ok((Vec::new(), Vec::new()));
     }
 
     println!("{}", "─".repeat(60).dimmed());
-    println!("{} {}", "File edits:".bright_yellow().bold(), blocks.len());
+    if !blocks.is_empty() {
+        println!("{} {}", "File edits:".bright_yellow().bold(), blocks.len());
+    }
+    if !operations.is_empty() {
+        println!("{} {}", "File operations:".bright_yellow().bold(), operations.len());
+    }
 
+    let prompts_config = PromptsConfig::load().unwrap_or_default();
+    let sandbox = prompts_config.sandbox;
+    let mut snapshots = FileSnapshots::default();
     let mut apply_all = false;
     let mut quit_applying = false;
     let mut applied_files = Vec::new();
+    let mut applied_blocks = Vec::new();
+    let mut applied_operations = Vec::new();
+    // Files created above, which later edits in the same response may change
+    let mut created: Vec<String> = Vec::new();
+    let transactional = prompts_config.edits.transactional;
+    let mut failed = false;
+    // Changes shown so far, for how many were left when the user quit
+    let mut reviewed = 0;
 
-    for block in blocks.iter() {
-        if quit_applying {
+    for (index, block) in blocks.iter().enumerate() {
+        if quit_applying || failed {
             break;
         }
+        reviewed += 1;
+        let mut block = block.clone();
         
-        // Check if the file is in context
-        let file_in_context = context_manager.is_file_in_context(&block.file_path);
+        // Paths outside the write roots are rejected without showing the edit
+        if let Err(e) = sandbox.check_write(&block.file_path) {
+            println!();
+            println!("{}{}", block.file_path.bright_white().bold(), " (OUTSIDE WRITE ROOTS - BLOCKED)".red().bold());
+            println!("{} {}", "❌ REJECTED:".red().bold(), e);
+            println!("{} {}", "Skipped:".red(), block.file_path);
+            continue;
+        }
+        
+        // Check if the file is in context; new files can't be, and need no context to write
+        let new_file = creates_file(&block);
+        let file_in_context = new_file || created.contains(&block.file_path) || context_manager.is_file_in_context(&block.file_path);
+        let read_only = context_manager.is_read_only(&block.file_path);
         
         println!();
-        let warning = if !file_in_context { " (NOT IN CONTEXT - BLOCKED)".red().bold() } else { "".normal() };
+        let warning = if !file_in_context {
+            " (NOT IN CONTEXT - BLOCKED)".red().bold()
+        } else if read_only {
+            " (READ-ONLY - BLOCKED)".red().bold()
+        } else if new_file {
+            " (NEW FILE)".green().bold()
+        } else {
+            "".normal()
+        };
         println!("{}{}", block.file_path.bright_white().bold(), warning);
-        println!("{}", "─".repeat(40).dimmed());
+        print_block_diff(&block, prompts_config.edits.review_layout);
         
-        // Display search content
-        println!("{}", "Search:".dimmed());
-        for line in block.search_lines.lines() {
-            println!("  {}", line.red());
-        }
-        
-        println!("{}", "Replace:".dimmed());
-        for line in block.replace_lines.lines() {
-            println!("  {}", line.green());
+        if !file_in_context || read_only {
+            explain_blocked(&block.file_path, read_only);
+            continue;
         }
         
-        println!("{}", "─".repeat(40).dimmed());
-        
-        // Block edits to files not in context
-        if !file_in_context {
-            println!("{}", "❌ BLOCKED: Cannot edit file not in context!".red().bold());
-            println!("{}", "To edit this file, first run:".yellow());
-            println!("  {} {}", "/add_file".bright_cyan(), block.file_path.bright_white());
-            println!("{} {}", "Skipped:".red(), block.file_path);
-            continue;
+        let mut answer = ask_to_apply(&block.file_path, true, &mut apply_all, &mut quit_applying)?;
+        // Each edit in $EDITOR is shown again before asking once more
+        while answer == Answer::Edit {
+            match edit_in_editor(&block.replace_lines, &block.file_path) {
+                Ok(replace) => block.replace_lines = replace,
+                Err(e) => println!("{} {}", "Failed:".red(), e),
+            }
+            println!();
+            println!("{}{}", block.file_path.bright_white().bold(), " (EDITED)".cyan().bold());
+            print_block_diff(&block, prompts_config.edits.review_layout);
+            answer = ask_to_apply(&block.file_path, true, &mut apply_all, &mut quit_applying)?;
         }
         
-        // Get user confirmation unless apply_all is set
-        let should_apply = if apply_all {
-            true
-        } else {
-            loop {
-                // No need for warning since we already block files not in context
-                
-                print!("{} ", "Apply? (y/n/a/q):".bright_white());
-                io::stdout().flush()?;
-                
-                let choice = match input::read_single_char() {
-                    Ok(c) => c.to_lowercase().to_string(),
-                    Err(_) => continue,
+        if answer == Answer::Apply {
+            snapshots.record(&block.file_path);
+            let mut result = apply_sr_block(&block);
+            let mut attempt = 0;
+            let mut skipped = false;
+            // A block that doesn't match is written again for the file as it is now
+            while let (Err(e), Some(model)) = (&result, model) {
+                if attempt >= prompts_config.edits.match_retries || !is_mismatch(e) {
+                    break;
+                }
+                attempt += 1;
+                println!("{} {} - {}", "No match:".yellow(), block.file_path, e);
+                println!("{}", format!("Asking {} for the block again (attempt {} of {})...", model.display_name(), attempt, prompts_config.edits.match_retries).dimmed());
+                let corrected = match regenerate_block(&block, model).await {
+                    Ok(corrected) => corrected,
+                    Err(e) => {
+                        result = Err(e);
+                        break;
+                    }
                 };
-                
-                match choice.as_str() {
-                    "y" | "yes" => break true,
-                    "n" | "no" => break false,
-                    "a" | "apply_all" => {
-                        apply_all = true;
-                        break true;
-                    },
-                    "q" | "quit" => {
-                        quit_applying = true;
-                        break false;
-                    },
-                    _ => {
-                        println!("Please enter 'y' (yes), 'n' (no), 'a' (apply all), or 'q' (quit)");
-                        continue;
+                println!();
+                println!("{}{}", block.file_path.bright_white().bold(), " (REGENERATED)".cyan().bold());
+                print_block_diff(&corrected, prompts_config.edits.review_layout);
+                if ask_to_apply(&corrected.file_path, false, &mut apply_all, &mut quit_applying)? != Answer::Apply {
+                    skipped = true;
+                    break;
+                }
+                block = corrected;
+                result = apply_sr_block(&block);
+            }
+            // Turning down the rewrite leaves the response half applied, which all-or-nothing
+            // apply doesn't allow
+            if skipped && transactional {
+                println!("{} edit {} of {} ({}) - its rewrite was declined", "Failed:".red(), index + 1, blocks.len(), block.file_path);
+                report_roll_back(&snapshots, &applied_files, applied_blocks.len());
+                applied_files.clear();
+                applied_blocks.clear();
+                created.clear();
+                failed = true;
+                continue;
+            }
+            if skipped {
+                println!("{} {}", "Skipped:".dimmed(), block.file_path);
+                continue;
+            }
+            match result {
+                Ok(()) => {
+                    println!("{} {}", if new_file { "Created:" } else { "Applied:" }.green(), block.file_path);
+                    if new_file {
+                        created.push(block.file_path.clone());
+                    }
+                    if !applied_files.contains(&block.file_path) {
+                        applied_files.push(block.file_path.clone());
+                    }
+                    applied_blocks.push(block.clone());
+                }
+                Err(e) => {
+                    println!("{} edit {} of {} ({}) - {}", "Failed:".red(), index + 1, blocks.len(), block.file_path, e);
+                    if transactional {
+                        report_roll_back(&snapshots, &applied_files, applied_blocks.len());
+                        applied_files.clear();
+                        applied_blocks.clear();
+                        created.clear();
+                        failed = true;
                     }
                 }
             }
-        };
+        } else {
+            println!("{} {}", "Skipped:".dimmed(), block.file_path);
+        }
+    }
 
-        if should_apply {
-            match apply_sr_block(block) {
+    // Deletes and renames come after the edits, which name files by their current paths
+    for (index, operation) in operations.iter().enumerate() {
+        if quit_applying || failed {
+            break;
+        }
+        reviewed += 1;
+        let path = operation.path();
+        println!();
+        if let Some(e) = operation.paths().into_iter().find_map(|path| sandbox.check_write(path).err()) {
+            println!("{}{}", operation.describe().bright_white().bold(), " (OUTSIDE WRITE ROOTS - BLOCKED)".red().bold());
+            println!("{} {}", "❌ REJECTED:".red().bold(), e);
+            println!("{} {}", "Skipped:".red(), path);
+            continue;
+        }
+        
+        let file_in_context = context_manager.is_file_in_context(path);
+        let read_only = context_manager.is_read_only(path);
+        let warning = if !file_in_context {
+            " (NOT IN CONTEXT - BLOCKED)".red().bold()
+        } else if read_only {
+            " (READ-ONLY - BLOCKED)".red().bold()
+        } else {
+            "".normal()
+        };
+        println!("{}{}", operation.describe().bright_white().bold(), warning);
+        if !file_in_context || read_only {
+            explain_blocked(path, read_only);
+            continue;
+        }
+        
+        if ask_to_apply(path, false, &mut apply_all, &mut quit_applying)? == Answer::Apply {
+            for path in operation.paths() {
+                snapshots.record(path);
+            }
+            match apply_file_operation(operation) {
                 Ok(()) => {
-                    println!("{} {}", "Applied:".green(), block.file_path);
-                    applied_files.push(block.file_path.clone());
+                    println!("{} {}", "Applied:".green(), operation.describe());
+                    for path in operation.paths() {
+                        if !applied_files.iter().any(|file| file == path) {
+                            applied_files.push(path.to_string());
+                        }
+                    }
+                    applied_operations.push(operation.clone());
                 }
                 Err(e) => {
-                    println!("{} {} - {}", "Failed:".red(), block.file_path, e);
+                    println!("{} file operation {} of {} ({}) - {}", "Failed:".red(), index + 1, operations.len(), operation.describe(), e);
+                    if transactional {
+                        report_roll_back(&snapshots, &applied_files, applied_blocks.len() + applied_operations.len());
+                        applied_files.clear();
+                        applied_blocks.clear();
+                        applied_operations.clear();
+                        failed = true;
+                    }
                 }
             }
         } else {
-            println!("{} {}", "Skipped:".dimmed(), block.file_path);
+            println!("{} {}", "Skipped:".dimmed(), path);
+        }
+    }
+
+    let total = blocks.len() + operations.len();
+    if failed {
+        println!("{}", "Stopped: the rest of the response's changes weren't applied. Set transactional = false under [edits] to keep the ones that apply".yellow());
+    } else if quit_applying && total > reviewed {
+        println!("\nWarning: Stopped applying changes (remaining {} changes were skipped)", total - reviewed);
+    }
+
+    // Edits that broke a file can be undone before anything is committed
+    if prompts_config.validation.enabled {
+        let existing: Vec<String> = applied_files.iter().filter(|file| Path::new(file.as_str()).is_file()).cloned().collect();
+        let failures = validate::check_all(&existing);
+        if !failures.is_empty() {
+            match ask_about_parse_failures(&failures)? {
+                FailureAction::Revert => {
+                    let failed: Vec<String> = failures.iter().map(|failure| failure.path.clone()).collect();
+                    for (path, result) in revert_failed(&snapshots, &failed, &mut applied_operations) {
+                        match result {
+                            Ok(()) => {
+                                println!("{} {}", "Reverted:".yellow(), path);
+                                applied_files.retain(|file| file != &path);
+                                applied_blocks.retain(|block: &SearchReplaceBlock| block.file_path != path);
+                            }
+                            Err(e) => println!("{} {}", "Failed:".red(), e),
+                        }
+                    }
+                }
+                FailureAction::Fix => validate::request_fix(validate::fix_prompt(&failures)),
+                FailureAction::Keep => {}
+            }
         }
     }
 
-    if quit_applying && blocks.len() > 1 {
-        println!("\nWarning: Stopped applying changes (remaining {} changes were skipped)", 
-                 blocks.len() - blocks.iter().position(|_| quit_applying).unwrap_or(0));
+    if prompts_config.lint.enabled {
+        print_lint_run(&lint::run_all(&applied_files, &prompts_config.lint));
     }
 
     println!("\nFile editing session complete.");
     
-    // Create automatic commit if any files were modified
+    undo::record(original_prompt, &snapshots, &applied_files);
+    commit_applied(original_prompt, &applied_files).await;
+    
+    Ok((applied_blocks, applied_operations))
+}
+
+/// Show what the formatters and checks did, keeping their errors for the next turn's context
+fn print_lint_run(run: &lint::LintRun) {
+    for file in &run.formatted {
+        println!("{} {}", "Formatted:".dimmed(), file);
+    }
+    for finding in &run.findings {
+        println!("{} {}", "Lint failed:".red(), finding.command);
+        for line in finding.output.lines().take(5) {
+            println!("  {}", line.dimmed());
+        }
+    }
+    if let Some(note) = run.context_note() {
+        println!("{}", "The errors were added to context for the next request.".yellow());
+        lint::defer_note(note);
+    }
+}
+
+/// Undo the changes a transactional batch applied before one failed, and say so
+fn report_roll_back(snapshots: &FileSnapshots, applied_files: &[String], changes: usize) {
+    if applied_files.is_empty() {
+        return;
+    }
+    let problems = roll_back(snapshots, applied_files);
+    println!("{} the {} change(s) applied before it: {}", "Rolled back".yellow(), changes, applied_files.join(", "));
+    for problem in problems {
+        println!("{} {}", "Failed:".red(), problem);
+    }
+}
+
+/// Tell the user why a change to `path` was skipped and how to allow it
+fn explain_blocked(path: &str, read_only: bool) {
+    if read_only {
+        // Read-only files are reference material, like code from dependencies
+        println!("{}", "❌ BLOCKED: This file was added read-only (/add_file --ro) for reference, so it can't be edited.".red().bold());
+        println!("{}", "To allow edits, add it again without --ro:".yellow());
+    } else {
+        println!("{}", "❌ BLOCKED: Cannot edit file not in context!".red().bold());
+        println!("{}", "To edit this file, first run:".yellow());
+    }
+    println!("  {} {}", "/add_file".bright_cyan(), path.bright_white());
+    println!("{} {}", "Skipped:".red(), path);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Answer {
+    Apply,
+    Skip,
+    Edit,
+}
+
+/// Ask whether to apply a change to `path`, unless everything is being applied or edits to the
+/// file were approved earlier this session. `a` and `q` answer for the rest of the changes;
+/// `e`, offered when `can_edit`, asks to change the edit in $EDITOR first.
+fn ask_to_apply(path: &str, can_edit: bool, apply_all: &mut bool, quit_applying: &mut bool) -> Result<Answer> {
+    if *apply_all {
+        return Ok(Answer::Apply);
+    }
+    if approvals::edits_approved_for(path) {
+        println!("{}", "Approved: edits to this file were approved for this session".dimmed());
+        return Ok(Answer::Apply);
+    }
+    let question = if can_edit { "Apply? (y/n/e/a/s/q):" } else { "Apply? (y/n/a/s/q):" };
+    loop {
+        print!("{} ", question.bright_white());
+        io::stdout().flush()?;
+        
+        let choice = match input::read_single_char() {
+            Ok(c) => c.to_lowercase().to_string(),
+            Err(_) => continue,
+        };
+        
+        match choice.as_str() {
+            "y" | "yes" => return Ok(Answer::Apply),
+            "n" | "no" => return Ok(Answer::Skip),
+            "e" | "edit" if can_edit => return Ok(Answer::Edit),
+            "a" | "apply_all" => {
+                *apply_all = true;
+                return Ok(Answer::Apply);
+            },
+            "s" | "session" => {
+                approvals::approve_edits_for(path);
+                println!("{} {}", "Edits to this file are approved for the rest of the session:".dimmed(), path);
+                return Ok(Answer::Apply);
+            },
+            "q" | "quit" => {
+                *quit_applying = true;
+                return Ok(Answer::Skip);
+            },
+            _ => {
+                if can_edit {
+                    println!("Please enter 'y' (yes), 'n' (no), 'e' (edit the replacement first), 'a' (apply all), 's' (approve all edits to this file this session), or 'q' (quit)");
+                } else {
+                    println!("Please enter 'y' (yes), 'n' (no), 'a' (apply all), 's' (approve all edits to this file this session), or 'q' (quit)");
+                }
+                continue;
+            }
+        }
+    }
+}
+
+/// Show a block as a colored diff, numbered from where its search text starts in the file
+fn print_block_diff(block: &SearchReplaceBlock, layout: ReviewLayout) {
+    let first_line = fs::read_to_string(&block.file_path)
+        .ok()
+        .filter(|_| !block.search_lines.is_empty())
+        .and_then(|content| content.find(&block.search_lines).map(|i| content[..i].matches('\n').count() + 1))
+        .unwrap_or(1);
+    let review = BlockReview::new(block.clone());
+    println!("{}", "─".repeat(40).dimmed());
+    match layout {
+        ReviewLayout::Unified => {
+            for row in review.unified_rows(first_line) {
+                match row {
+                    DiffRow::Same { old, new, text } => println!("{} {}", format!("{:>4} {:>4}", old, new).dimmed(), format!("  {}", text).dimmed()),
+                    DiffRow::Removed { old, text } => println!("{} {}", format!("{:>4}     ", old).dimmed(), format!("- {}", text).red()),
+                    DiffRow::Added { new, text } => println!("{} {}", format!("     {:>4}", new).dimmed(), format!("+ {}", text).green()),
+                }
+            }
+        }
+        ReviewLayout::SideBySide => {
+            let width = crossterm::terminal::size().map(|(width, _)| width as usize).unwrap_or(120);
+            // Each side is a 4-digit line number, a space and the text; " │ " separates them
+            let column = (width.saturating_sub(3) / 2).max(20) - 5;
+            let side = |cell: Option<(usize, String)>| match cell {
+                Some((number, text)) => (format!("{:>4} ", number), fit_column(&text, column)),
+                None => ("     ".to_string(), " ".repeat(column)),
+            };
+            for row in review.side_by_side_rows(first_line) {
+                let (left_number, left) = side(row.left.clone());
+                let (right_number, right) = side(row.right.clone());
+                let (left, right) = if !row.changed {
+                    (left.dimmed(), right.dimmed())
+                } else {
+                    (if row.left.is_some() { left.red() } else { left.normal() }, if row.right.is_some() { right.green() } else { right.normal() })
+                };
+                println!("{}{} {} {}{}", left_number.dimmed(), left, "│".dimmed(), right_number.dimmed(), right);
+            }
+        }
+    }
+    println!("{}", "─".repeat(40).dimmed());
+}
+
+/// `text` with tabs expanded, cut or padded to exactly `width` characters
+fn fit_column(text: &str, width: usize) -> String {
+    let text = text.replace('\t', "    ");
+    let count = text.chars().count();
+    if count > width {
+        format!("{}…", text.chars().take(width.saturating_sub(1)).collect::<String>())
+    } else {
+        format!("{}{}", text, " ".repeat(width - count))
+    }
+}
+
+/// Open `text` in $VISUAL or $EDITOR (vi if neither is set) and return it as saved. The temp
+/// file keeps `file_path`'s extension so the editor highlights it.
+pub fn edit_in_editor(text: &str, file_path: &str) -> Result<String> {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .ok()
+        .filter(|editor| !editor.trim().is_empty())
+        .unwrap_or_else(|| "vi".to_string());
+    let extension = Path::new(file_path).extension().map(|ext| format!(".{}", ext.to_string_lossy())).unwrap_or_default();
+    let temp = std::env::temp_dir().join(format!("kota-edit-{}{}", uuid::Uuid::new_v4(), extension));
+    fs::write(&temp, text)?;
+
+    let mut parts = editor.split_whitespace();
+    let program = parts.next().unwrap_or("vi");
+    let status = Command::new(program).args(parts).arg(&temp).status();
+    let edited = fs::read_to_string(&temp);
+    let _ = fs::remove_file(&temp);
+    let status = status.map_err(|e| anyhow::anyhow!("Failed to run {}: {}", editor, e))?;
+    if !status.success() {
+        return Err(anyhow::anyhow!("{} exited with {}; the edit is unchanged", editor, status));
+    }
+    let mut edited = edited?;
+    // Editors add a final newline the block's text didn't have
+    if !text.ends_with('\n') && edited.ends_with('\n') {
+        edited.pop();
+        if edited.ends_with('\r') {
+            edited.pop();
+        }
+    }
+    Ok(edited)
+}
+
+/// Show files that no longer parse and ask whether to revert them, ask for a fix, or keep them
+fn ask_about_parse_failures(failures: &[ParseFailure]) -> Result<FailureAction> {
+    println!();
+    println!("{}", "These files no longer parse:".red().bold());
+    for failure in failures {
+        println!("{} ({})", failure.path.bright_white().bold(), failure.tool.dimmed());
+        for line in failure.message.lines() {
+            println!("  {}", line.red());
+        }
+    }
+    
+    loop {
+        print!("{} ", "Revert them, ask for a fix, or keep the edits? (r/f/k):".bright_white());
+        io::stdout().flush()?;
+        let choice = match input::read_single_char() {
+            Ok(c) => c.to_lowercase().to_string(),
+            Err(_) => continue,
+        };
+        match choice.as_str() {
+            "r" => return Ok(FailureAction::Revert),
+            "f" => return Ok(FailureAction::Fix),
+            "k" => return Ok(FailureAction::Keep),
+            _ => println!("Please enter 'r' (revert), 'f' (keep and ask the AI to fix them) or 'k' (keep)"),
+        }
+    }
+}
+
+/// Auto-commit the files that were edited, exiting with code 123 to be rebuilt when KOTA changed itself
+pub async fn commit_applied(original_prompt: &str, applied_files: &[String]) {
     if !applied_files.is_empty() {
-        match create_auto_commit(original_prompt, &applied_files).await {
+        webhooks::fire(Event::EditApplied { files: applied_files.to_vec(), prompt: original_prompt.to_string() });
+        focus::note_edits(applied_files.len());
+        match create_auto_commit(original_prompt, applied_files).await {
             Ok(is_self_modifying) => {
                 if is_self_modifying {
                     println!();
@@ -239,6 +812,7 @@ pub async fn confirm_and_apply_blocks(blocks: Vec<SearchReplaceBlock>, original_
                     println!("{}", "Self-modification complete!".bright_green().bold());
                     println!("KOTA needs to be rebuilt and restarted for changes to take effect.");
                     println!("Exiting with code 123 to signal restart...");
+                    trace::finish();
                     std::process::exit(123);
                 }
             }
@@ -248,8 +822,6 @@ pub async fn confirm_and_apply_blocks(blocks: Vec<SearchReplaceBlock>, original_
             }
         }
     }
-    
-    Ok(())
 }
 
 #[cfg(test)]
@@ -276,6 +848,42 @@ mod tests {
         assert_eq!(new_content, "line1\nnew content\nline3");
     }
 
+    #[test]
+    fn test_apply_sr_block_creates_file() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("src/parser/mod.rs").to_string_lossy().to_string();
+        let block = SearchReplaceBlock {
+            file_path: path.clone(),
+            search_lines: String::new(),
+            replace_lines: "pub mod lexer;".to_string(),
+        };
+
+        assert!(creates_file(&block));
+        assert!(apply_sr_block(&block).is_ok());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "pub mod lexer;\n");
+        assert!(!creates_file(&block));
+    }
+
+    #[test]
+    fn test_apply_file_operations() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = |name: &str| dir.path().join(name).to_string_lossy().to_string();
+        fs::write(path("a.rs"), "fn a() {}\n").unwrap();
+        fs::write(path("b.rs"), "fn b() {}\n").unwrap();
+
+        let rename = FileOperation::Rename { from: path("a.rs"), to: path("core/a.rs") };
+        assert!(apply_file_operation(&rename).is_ok());
+        assert_eq!(fs::read_to_string(path("core/a.rs")).unwrap(), "fn a() {}\n");
+        assert!(apply_file_operation(&rename).unwrap_err().to_string().contains("doesn't exist"));
+
+        let onto_existing = FileOperation::Rename { from: path("b.rs"), to: path("core/a.rs") };
+        assert!(apply_file_operation(&onto_existing).unwrap_err().to_string().contains("already exists"));
+
+        assert!(apply_file_operation(&FileOperation::Delete { path: path("b.rs") }).is_ok());
+        assert!(!Path::new(&path("b.rs")).exists());
+        assert!(apply_file_operation(&FileOperation::Delete { path: path("b.rs") }).is_err());
+    }
+
     #[test]
     fn test_apply_sr_block_not_found() {
         let temp_file = NamedTempFile::new().unwrap();
@@ -457,4 +1065,89 @@ mod tests {
         let unchanged_content = fs::read_to_string(temp_file.path()).unwrap();
         assert_eq!(unchanged_content, "line1\nsome content here\nline3");
     }
+
+    #[test]
+    fn test_mismatch_reason() {
+        let content = "fn main() {\n    let x = 1;\n    println!(\"{}\", x);\n}\n";
+        assert!(mismatch_reason(content, "fn main() {\n  let x = 1;").contains("matches at line 1 only when whitespace is ignored"));
+        assert_eq!(
+            mismatch_reason(content, "    let x = 1;\n    println!(\"{}\", y);"),
+            "the first 1 line(s) match at line 2, but line 3 is `println!(\"{}\", x);` where the search has `println!(\"{}\", y);`"
+        );
+        assert_eq!(mismatch_reason(content, "fn other() {"), "its first line `fn other() {` isn't in the file");
+    }
+
+    #[test]
+    fn test_nearest_region() {
+        let content: String = (1..=40).map(|i| format!("line {}\n", i)).collect();
+        // The first three-line stretch holding lines 20 and 21, with 10 lines either side
+        assert_eq!(nearest_region(&content, "line 20\n  line 21\nline 22x"), (8, 31));
+        assert_eq!(nearest_region(&content, "line 1\nline 2"), (0, 12));
+        assert_eq!(nearest_region(&content, "nothing like it"), (0, 40));
+
+        let temp_file = NamedTempFile::new().unwrap();
+        fs::write(temp_file.path(), &content).unwrap();
+        let block = SearchReplaceBlock {
+            file_path: temp_file.path().to_string_lossy().to_string(),
+            search_lines: "line 99".to_string(),
+            replace_lines: "line 100".to_string(),
+        };
+        assert!(is_mismatch(&apply_sr_block(&block).unwrap_err()));
+        let missing = SearchReplaceBlock { file_path: "/nonexistent/a.rs".to_string(), ..block };
+        assert!(!is_mismatch(&apply_sr_block(&missing).unwrap_err()));
+    }
+
+    #[test]
+    fn test_roll_back() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let edited = dir.path().join("a.rs").to_string_lossy().to_string();
+        let created = dir.path().join("b.rs").to_string_lossy().to_string();
+        fs::write(&edited, "old\n").unwrap();
+
+        let mut snapshots = FileSnapshots::default();
+        snapshots.record(&edited);
+        snapshots.record(&created);
+        fs::write(&edited, "new\n").unwrap();
+        fs::write(&created, "fn b() {}\n").unwrap();
+
+        assert!(roll_back(&snapshots, &[edited.clone(), created.clone()]).is_empty());
+        assert_eq!(fs::read_to_string(&edited).unwrap(), "old\n");
+        assert!(!Path::new(&created).exists());
+        assert_eq!(roll_back(&snapshots, &["unrecorded.rs".to_string()]).len(), 1);
+    }
+
+    #[test]
+    fn test_revert_failed_undoes_whole_operations() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let from = dir.path().join("old.py").to_string_lossy().to_string();
+        let to = dir.path().join("new.py").to_string_lossy().to_string();
+        let other = dir.path().join("other.py").to_string_lossy().to_string();
+        fs::write(&from, "x = 1\n").unwrap();
+        fs::write(&other, "y = 2\n").unwrap();
+
+        let rename = FileOperation::Rename { from: from.clone(), to: to.clone() };
+        let delete = FileOperation::Delete { path: other.clone() };
+        let mut snapshots = FileSnapshots::default();
+        for path in rename.paths().into_iter().chain(delete.paths()) {
+            snapshots.record(path);
+        }
+        apply_file_operation(&rename).unwrap();
+        apply_file_operation(&delete).unwrap();
+        fs::write(&to, "x = (\n").unwrap();
+
+        let mut operations = vec![rename, delete.clone()];
+        let reverted = revert_failed(&snapshots, std::slice::from_ref(&to), &mut operations);
+        assert_eq!(reverted.iter().map(|(path, _)| path.clone()).collect::<Vec<_>>(), vec![to.clone(), from.clone()]);
+        assert!(reverted.iter().all(|(_, result)| result.is_ok()));
+        assert_eq!(fs::read_to_string(&from).unwrap(), "x = 1\n");
+        assert!(!Path::new(&to).exists());
+        assert_eq!(operations, vec![delete]);
+    }
+
+    #[test]
+    fn test_fit_column() {
+        assert_eq!(fit_column("\tlet x = 1;", 16), "    let x = 1;  ");
+        assert_eq!(fit_column("a long line of text", 10), "a long li…");
+        assert_eq!(fit_column("", 3), "   ");
+    }
 }
\ No newline at end of file
@@ -0,0 +1,471 @@
+use std::env;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use colored::*;
+
+use crate::db::Database;
+use crate::prompts::{EmailConfig, PromptsConfig};
+use crate::todos::{TodoItem, TodoList};
+
+// Learnings saved from email are kept under this topic
+const NOTE_TOPIC: &str = "email";
+// A runaway message body shouldn't end up in memory whole
+const MAX_NOTE_CHARS: usize = 4000;
+
+/// What a tagged message becomes, from the bridge message type after the tag
+#[derive(Debug, Clone, PartialEq)]
+pub enum Capture {
+    /// `REQUEST` (or no type): tasks for the todo list
+    Tasks(Vec<TodoItem>),
+    /// `UPDATE` or `INFO`: a memory entry
+    Note(String),
+}
+
+/// The parts of a message the poller looks at
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Message {
+    pub from: String,
+    pub subject: String,
+    pub body: String,
+    /// The topmost Authentication-Results header, the one the receiving server added
+    pub authentication: Option<String>,
+}
+
+/// `kota email [status|poll|watch]`: show the mailbox settings, read tagged mail once,
+/// or keep reading it every `poll_minutes`
+pub fn run(args: &[String]) -> Result<()> {
+    let config = PromptsConfig::load().unwrap_or_default().email;
+    match args.first().map(String::as_str).unwrap_or("status") {
+        "status" => {
+            println!("Email capture: {}", if config.enabled { "on" } else { "off ([email] enabled in prompts.toml)" });
+            println!("Mailbox: {}", if config.url.is_empty() { "(not set)" } else { &config.url });
+            println!("User: {}", if config.username.is_empty() { "(not set)" } else { &config.username });
+            let password = env::var(&config.password_env).is_ok_and(|password| !password.is_empty());
+            println!("Password: {} {}", config.password_env, if password { "is set" } else { "is not set" });
+            println!("Tag: {}", config.tag);
+            if config.allowed_senders.is_empty() {
+                println!("Allowed senders: none, so every message is ignored");
+            } else {
+                println!("Allowed senders: {}", config.allowed_senders.join(", "));
+            }
+            let secret = env::var(&config.secret_env).is_ok_and(|secret| !secret.trim().is_empty());
+            println!("Subject secret: {} {}", config.secret_env, if secret { "is set" } else { "is not set, so senders need DKIM, SPF or DMARC to pass" });
+            Ok(())
+        }
+        "poll" => poll(&config).map(|_| ()),
+        "watch" => {
+            let minutes = config.poll_minutes.max(1);
+            println!("Checking {} every {} minute(s); press Ctrl+C to stop", config.url, minutes);
+            loop {
+                if let Err(e) = poll(&config) {
+                    eprintln!("Warning: Email poll failed: {}", e);
+                }
+                thread::sleep(Duration::from_secs(minutes * 60));
+            }
+        }
+        other => Err(anyhow::anyhow!("Unknown email command {}; use status, poll or watch", other)),
+    }
+}
+
+/// Read the unseen messages tagged for KOTA and capture the ones from allowed senders.
+/// Fetching marks a message seen, so each is handled once. Returns how many were captured.
+pub fn poll(config: &EmailConfig) -> Result<usize> {
+    if !config.enabled {
+        return Err(anyhow::anyhow!("Email capture is off; set enabled = true under [email] in prompts.toml"));
+    }
+    if config.url.is_empty() || config.username.is_empty() {
+        return Err(anyhow::anyhow!("Set url and username under [email] in prompts.toml"));
+    }
+    let password = env::var(&config.password_env)
+        .ok()
+        .filter(|password| !password.is_empty())
+        .ok_or_else(|| anyhow::anyhow!("Set {} to the mailbox password", config.password_env))?;
+    let mailbox = config.url.trim_end_matches('/');
+    let secret = env::var(&config.secret_env).ok().map(|secret| secret.trim().to_string()).filter(|secret| !secret.is_empty());
+
+    let search = format!("UID SEARCH UNSEEN SUBJECT \"{}\"", config.tag.replace('"', ""));
+    let uids = search_uids(&curl(mailbox, &config.username, &password, Some(&search))?);
+    let mut captured = 0;
+    for uid in uids {
+        let raw = curl(&format!("{};UID={}", mailbox, uid), &config.username, &password, None)?;
+        let mut message = parse_message(&raw);
+        if !sender_allowed(&message.from, &config.allowed_senders) {
+            println!("{} message from {}: sender isn't in allowed_senders", "Ignored".yellow(), message.from);
+            continue;
+        }
+        if !sender_verified(&message, secret.as_deref()) {
+            println!(
+                "{} message from {}: no passing DKIM, SPF or DMARC result for its domain, and no {} in the subject",
+                "Ignored".yellow(),
+                message.from,
+                config.secret_env
+            );
+            continue;
+        }
+        if let Some(secret) = &secret {
+            message.subject = message.subject.replace(secret.as_str(), "");
+        }
+        match capture(&message, &config.tag) {
+            Some(Capture::Tasks(items)) => {
+                let mut todos = TodoList::load();
+                let mut all = todos.items().to_vec();
+                for item in &items {
+                    println!("{} {}", "Queued task:".green(), item.text);
+                }
+                all.extend(items);
+                todos.replace(all)?;
+            }
+            Some(Capture::Note(note)) => {
+                Database::open()?.insert_learning(NOTE_TOPIC, &note)?;
+                println!("{} {}", "Saved to memory:".green(), note.lines().next().unwrap_or(""));
+            }
+            None => {
+                println!("{} \"{}\" from {}: nothing to capture", "Skipped".yellow(), message.subject, message.from);
+                continue;
+            }
+        }
+        captured += 1;
+    }
+    if captured == 0 {
+        println!("No new messages tagged {}", config.tag);
+    }
+    Ok(captured)
+}
+
+/// Run curl against an IMAP URL. The credentials go in on stdin as a curl config, never as
+/// an argument other processes could see.
+fn curl(url: &str, username: &str, password: &str, request: Option<&str>) -> Result<String> {
+    let mut command = Command::new("curl");
+    command.args(["--silent", "--show-error", "--max-time", "60", "--config", "-", "--url", url]);
+    if let Some(request) = request {
+        command.args(["--request", request]);
+    }
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Failed to run curl for IMAP")?;
+    let quote = |text: &str| text.replace('\\', "\\\\").replace('"', "\\\"");
+    let credentials = format!("user = \"{}:{}\"\n", quote(username), quote(password));
+    child.stdin.take().context("No stdin for curl")?.write_all(credentials.as_bytes())?;
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(anyhow::anyhow!("curl failed for {}: {}", url, String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// The UIDs in a `* SEARCH 4 7 9` response
+fn search_uids(response: &str) -> Vec<u64> {
+    response
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("* SEARCH"))
+        .flat_map(|uids| uids.split_whitespace().filter_map(|uid| uid.parse().ok()).collect::<Vec<_>>())
+        .collect()
+}
+
+/// `allowed` holds addresses, or domains written as `@example.com`
+pub fn sender_allowed(from: &str, allowed: &[String]) -> bool {
+    let from = from.trim().to_lowercase();
+    allowed.iter().map(|sender| sender.trim().to_lowercase()).any(|sender| {
+        if sender.starts_with('@') { from.ends_with(&sender) } else { from == sender }
+    })
+}
+
+/// Whether the From address can be believed: the receiving server's Authentication-Results
+/// show a DKIM, SPF or DMARC pass for its domain, or the subject has the shared secret
+pub fn sender_verified(message: &Message, secret: Option<&str>) -> bool {
+    if secret.is_some_and(|secret| message.subject.contains(secret)) {
+        return true;
+    }
+    let Some(domain) = message.from.rsplit_once('@').map(|(_, domain)| domain.trim().to_lowercase()) else {
+        return false;
+    };
+    let Some(results) = &message.authentication else {
+        return false;
+    };
+    // `mx.example.net; dkim=pass header.d=example.com header.s=s1; spf=fail smtp.mailfrom=...`
+    results.split(';').skip(1).any(|result| {
+        let mut words = result.split_whitespace();
+        let Some((method, outcome)) = words.next().and_then(|word| word.split_once('=')) else {
+            return false;
+        };
+        if !outcome.eq_ignore_ascii_case("pass") {
+            return false;
+        }
+        let property = match method.to_lowercase().as_str() {
+            "dkim" => "header.d",
+            "spf" => "smtp.mailfrom",
+            "dmarc" => "header.from",
+            _ => return false,
+        };
+        words.filter_map(|word| word.split_once('=')).any(|(key, value)| {
+            let value = value.trim_matches('"').to_lowercase();
+            let signed = value.rsplit_once('@').map_or(value.as_str(), |(_, domain)| domain);
+            // A signature for the parent domain covers its subdomains
+            key.eq_ignore_ascii_case(property) && (domain == signed || domain.ends_with(&format!(".{}", signed)))
+        })
+    })
+}
+
+/// Route a message by the bridge message type after `tag` in its subject:
+/// `[kota] REQUEST: ...` queues tasks and `[kota] UPDATE: ...` or `INFO` saves a note.
+/// RESPONSE and ERROR are replies, not something to act on.
+pub fn capture(message: &Message, tag: &str) -> Option<Capture> {
+    let subject = message.subject.trim();
+    let start = subject.to_ascii_lowercase().find(&tag.to_ascii_lowercase())?;
+    let rest = subject[start + tag.len()..].trim_start();
+    let word = rest.split(|c: char| c == ':' || c.is_whitespace()).next().unwrap_or("");
+    let kind = word.to_uppercase();
+    let (kind, text) = match kind.as_str() {
+        "REQUEST" | "UPDATE" | "INFO" | "RESPONSE" | "ERROR" => (kind.as_str(), rest[word.len()..].trim_start_matches(':').trim()),
+        _ => ("REQUEST", rest.trim()),
+    };
+    let body = plain_body(&message.body);
+
+    match kind {
+        "REQUEST" => {
+            let mut items: Vec<TodoItem> = body.lines().filter_map(TodoItem::parse).filter(|item| !item.done).collect();
+            if items.is_empty() && !text.is_empty() {
+                items.push(TodoItem { text: text.to_string(), done: false, file: None, command: None });
+            }
+            if items.is_empty() {
+                return None;
+            }
+            Some(Capture::Tasks(items))
+        }
+        "UPDATE" | "INFO" => {
+            let note = [text, body.as_str()].iter().filter(|part| !part.is_empty()).copied().collect::<Vec<_>>().join("\n\n");
+            if note.is_empty() {
+                return None;
+            }
+            Some(Capture::Note(note.chars().take(MAX_NOTE_CHARS).collect()))
+        }
+        _ => None,
+    }
+}
+
+/// The body without quoted replies or a signature
+fn plain_body(body: &str) -> String {
+    body.lines()
+        .take_while(|line| line.trim_end() != "--")
+        .filter(|line| !line.starts_with('>'))
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim()
+        .to_string()
+}
+
+/// Headers and the first text/plain part of a raw RFC 5322 message
+pub fn parse_message(raw: &str) -> Message {
+    let raw = raw.replace("\r\n", "\n");
+    let (headers, body) = split_headers(&raw);
+    let from = header(&headers, "from").unwrap_or_default();
+    let from = match (from.rfind('<'), from.rfind('>')) {
+        (Some(open), Some(close)) if open < close => from[open + 1..close].to_string(),
+        _ => from.trim().to_string(),
+    };
+    let subject = decode_words(&header(&headers, "subject").unwrap_or_default());
+    let authentication = header(&headers, "authentication-results");
+    Message { from, subject, body: text_part(&headers, body).unwrap_or_default(), authentication }
+}
+
+/// Unfolded header lines and the body after the blank line
+fn split_headers(raw: &str) -> (Vec<(String, String)>, &str) {
+    let (head, body) = raw.split_once("\n\n").unwrap_or((raw, ""));
+    let mut headers: Vec<(String, String)> = Vec::new();
+    for line in head.lines() {
+        if line.starts_with([' ', '\t']) {
+            if let Some((_, value)) = headers.last_mut() {
+                value.push(' ');
+                value.push_str(line.trim());
+            }
+        } else if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_lowercase(), value.trim().to_string()));
+        }
+    }
+    (headers, body)
+}
+
+fn header(headers: &[(String, String)], name: &str) -> Option<String> {
+    headers.iter().find(|(key, _)| key == name).map(|(_, value)| value.clone())
+}
+
+/// `name=value` or `name="value"` from a header like Content-Type
+fn parameter(value: &str, name: &str) -> Option<String> {
+    value.split(';').skip(1).find_map(|param| {
+        let (key, value) = param.split_once('=')?;
+        (key.trim().eq_ignore_ascii_case(name)).then(|| value.trim().trim_matches('"').to_string())
+    })
+}
+
+fn text_part(headers: &[(String, String)], body: &str) -> Option<String> {
+    let content_type = header(headers, "content-type").unwrap_or_else(|| "text/plain".to_string());
+    let mime = content_type.split(';').next().unwrap_or("").trim().to_lowercase();
+    if mime.starts_with("multipart/") {
+        let boundary = format!("--{}", parameter(&content_type, "boundary")?);
+        return body.split(&boundary).skip(1).take_while(|part| !part.starts_with("--")).find_map(|part| {
+            let (part_headers, part_body) = split_headers(part.trim_start_matches('\n'));
+            text_part(&part_headers, part_body)
+        });
+    }
+    if mime != "text/plain" {
+        return None;
+    }
+    let bytes = match header(headers, "content-transfer-encoding").map(|e| e.to_lowercase()).as_deref() {
+        Some("base64") => base64_decode(body)?,
+        Some("quoted-printable") => quoted_printable_decode(body, false),
+        _ => body.as_bytes().to_vec(),
+    };
+    Some(String::from_utf8_lossy(&bytes).trim().to_string())
+}
+
+/// RFC 2047 encoded words like `=?UTF-8?B?...?=` in a header. Other charsets are read as UTF-8.
+fn decode_words(value: &str) -> String {
+    let mut decoded = String::new();
+    let mut rest = value;
+    while let Some(start) = rest.find("=?") {
+        let word = &rest[start + 2..];
+        let parts: Vec<&str> = word.splitn(3, '?').collect();
+        let end = parts.get(2).and_then(|text| text.find("?="));
+        let (Some(end), [_, encoding, text]) = (end, parts.as_slice()) else { break };
+        let bytes = match encoding.to_uppercase().as_str() {
+            "B" => base64_decode(&text[..end]),
+            "Q" => Some(quoted_printable_decode(&text[..end], true)),
+            _ => None,
+        };
+        let Some(bytes) = bytes else { break };
+        let before = &rest[..start];
+        // Whitespace between two encoded words isn't part of the text
+        if decoded.is_empty() || !before.trim().is_empty() {
+            decoded.push_str(before);
+        }
+        decoded.push_str(&String::from_utf8_lossy(&bytes));
+        rest = &word[parts[0].len() + encoding.len() + end + 4..];
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    let value = |c: u8| match c {
+        b'A'..=b'Z' => Some(c - b'A'),
+        b'a'..=b'z' => Some(c - b'a' + 26),
+        b'0'..=b'9' => Some(c - b'0' + 52),
+        b'+' | b'-' => Some(62),
+        b'/' | b'_' => Some(63),
+        _ => None,
+    };
+    let mut bytes = Vec::new();
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for c in text.bytes().filter(|c| !c.is_ascii_whitespace() && *c != b'=') {
+        buffer = (buffer << 6) | value(c)? as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+        }
+    }
+    Some(bytes)
+}
+
+/// Quoted-printable; in headers (`underscores`) `_` is a space
+fn quoted_printable_decode(text: &str, underscores: bool) -> Vec<u8> {
+    let text = text.replace("=\n", "");
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'=' if i + 2 < bytes.len() && bytes[i + 1].is_ascii_hexdigit() && bytes[i + 2].is_ascii_hexdigit() => {
+                decoded.push(u8::from_str_radix(&text[i + 1..i + 3], 16).unwrap_or(b'='));
+                i += 3;
+                continue;
+            }
+            b'_' if underscores => decoded.push(b' '),
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    decoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(subject: &str, body: &str) -> Message {
+        Message { from: "me@example.com".to_string(), subject: subject.to_string(), body: body.to_string(), authentication: None }
+    }
+
+    #[test]
+    fn test_capture_routes_by_message_type() {
+        let task = |text: &str| TodoItem { text: text.to_string(), done: false, file: None, command: None };
+        assert_eq!(
+            capture(&message("[kota] REQUEST: Add rate limiting", ""), "[kota]"),
+            Some(Capture::Tasks(vec![task("Add rate limiting")]))
+        );
+        assert_eq!(
+            capture(&message("Fwd: [KOTA] fix the flaky test", "Thanks\n-- \nSent from my phone"), "[kota]"),
+            Some(Capture::Tasks(vec![task("fix the flaky test")]))
+        );
+        assert_eq!(
+            capture(&message("[kota] request: release prep", "- [ ] Bump the version\n- [x] Write notes\n- [ ] Tag it (run: git tag v1)"), "[kota]"),
+            Some(Capture::Tasks(vec![
+                task("Bump the version"),
+                TodoItem { command: Some("git tag v1".to_string()), ..task("Tag it") },
+            ]))
+        );
+        assert_eq!(
+            capture(&message("[kota] UPDATE: Deploys moved to Thursdays", "Staging first.\n> old quoted text"), "[kota]"),
+            Some(Capture::Note("Deploys moved to Thursdays\n\nStaging first.".to_string()))
+        );
+        assert_eq!(capture(&message("[kota] RESPONSE: done", "ok"), "[kota]"), None);
+        assert_eq!(capture(&message("[kota] REQUEST:", ""), "[kota]"), None);
+        assert_eq!(capture(&message("Lunch on Friday?", ""), "[kota]"), None);
+    }
+
+    #[test]
+    fn test_sender_allowlist() {
+        let allowed = vec!["me@example.com".to_string(), "@team.dev".to_string()];
+        assert!(sender_allowed("Me@Example.com", &allowed));
+        assert!(sender_allowed("ana@team.dev", &allowed));
+        assert!(!sender_allowed("me@example.com.evil.io", &allowed));
+        assert!(!sender_allowed("me@example.com", &[]));
+
+        let raw = "Authentication-Results: mx.example.net; dkim=pass header.d=example.com header.s=s1; spf=fail smtp.mailfrom=evil.io\n\
+            Authentication-Results: evil.io; dkim=pass header.d=team.dev\nFrom: ana@team.dev\nSubject: [kota] hi\n\nbody\n";
+        let forged = parse_message(raw);
+        assert!(!sender_verified(&forged, None));
+        assert!(!sender_verified(&forged, Some("s3cret")));
+        let signed = Message { from: "me@mail.example.com".to_string(), ..forged.clone() };
+        assert!(sender_verified(&signed, None));
+        let spf = Message { authentication: Some("mx; spf=pass smtp.mailfrom=ana@team.dev".to_string()), ..forged.clone() };
+        assert!(sender_verified(&spf, None));
+        let tagged = Message { subject: "[kota] s3cret hi".to_string(), ..forged };
+        assert!(sender_verified(&tagged, Some("s3cret")));
+        assert_eq!(search_uids("* SEARCH 4 7 9\r\n"), vec![4, 7, 9]);
+        assert!(search_uids("* SEARCH\r\n").is_empty());
+    }
+
+    #[test]
+    fn test_parse_message() {
+        let raw = "From: \"Me\" <me@example.com>\r\nSubject: =?UTF-8?B?W2tvdGFdIElORk86IGNhZsOp?=\r\n \
+            =?UTF-8?Q?_menu?=\r\nContent-Type: multipart/alternative; boundary=\"b1\"\r\n\r\n--b1\r\n\
+            Content-Type: text/html\r\n\r\n<p>Hi</p>\r\n--b1\r\nContent-Type: text/plain; charset=utf-8\r\n\
+            Content-Transfer-Encoding: quoted-printable\r\n\r\nCaf=C3=A9 opens at 9=\r\n:00.\r\n--b1--\r\n";
+        let parsed = parse_message(raw);
+        assert_eq!(parsed.from, "me@example.com");
+        assert_eq!(parsed.subject, "[kota] INFO: café menu");
+        assert_eq!(parsed.body, "Café opens at 9:00.");
+
+        let plain = parse_message("From: me@example.com\nSubject: [kota] hi\nContent-Transfer-Encoding: base64\n\naGVsbG8gd29ybGQ=\n");
+        assert_eq!(plain.body, "hello world");
+    }
+}
@@ -0,0 +1,103 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+
+use crate::llm::{self, router, ModelConfig};
+use crate::prompts::PromptsConfig;
+
+pub const EXPLANATIONS_FILE: &str = ".kota/explanations.json";
+
+const EXPLAIN_INSTRUCTIONS: &str = "Explain this shell command for someone deciding whether to run it. \
+Reply in plain text with two short parts: \"What it does:\" in one or two sentences, then \"Risks:\" naming \
+anything it deletes, overwrites, installs, sends over the network or runs with elevated privileges, or \
+\"None\" if it only reads. No more than five lines.";
+
+/// Explanations already fetched, by command, kept in `.kota/explanations.json` so a command
+/// suggested again isn't explained twice
+pub struct ExplanationCache {
+    path: PathBuf,
+    entries: BTreeMap<String, String>,
+}
+
+impl ExplanationCache {
+    pub fn load() -> Self {
+        Self::load_from(EXPLANATIONS_FILE)
+    }
+
+    /// A missing or unreadable file is an empty cache
+    pub fn load_from(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let entries = fs::read_to_string(&path)
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default();
+        Self { path, entries }
+    }
+
+    pub fn get(&self, command: &str) -> Option<&String> {
+        self.entries.get(command.trim())
+    }
+
+    pub fn insert(&mut self, command: &str, explanation: &str) -> Result<()> {
+        self.entries.insert(command.trim().to_string(), explanation.to_string());
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+        }
+        fs::write(&self.path, serde_json::to_string_pretty(&self.entries)?)
+            .with_context(|| format!("Failed to write {}", self.path.display()))
+    }
+}
+
+/// The model from `[explain] model`, or the session's current one
+pub fn explain_model(current: &ModelConfig) -> ModelConfig {
+    let config = PromptsConfig::load().unwrap_or_default().explain;
+    match config.model.as_deref().map(str::trim).filter(|spec| !spec.is_empty()) {
+        Some(spec) => router::spec_model(spec).unwrap_or_else(|e| {
+            eprintln!("Warning: {} in [explain] model; using {}", e, current.display_name());
+            current.clone()
+        }),
+        None => current.clone(),
+    }
+}
+
+pub fn explain_prompt(command: &str) -> String {
+    format!("{}\n\nCommand:\n{}", EXPLAIN_INSTRUCTIONS, command.trim())
+}
+
+/// What `command` does and its risks, from the cache or the explain model
+pub async fn explain(command: &str, current: &ModelConfig) -> Result<String> {
+    if let Some(explanation) = ExplanationCache::load().get(command) {
+        return Ok(explanation.clone());
+    }
+    let explanation = llm::ask_model_pinned(&explain_prompt(command), &explain_model(current)).await?;
+    let explanation = explanation.trim().to_string();
+    if explanation.is_empty() {
+        return Err(anyhow::anyhow!("The model gave no explanation"));
+    }
+    if let Err(e) = ExplanationCache::load().insert(command, &explanation) {
+        eprintln!("Warning: Failed to cache the explanation: {}", e);
+    }
+    Ok(explanation)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_explanation_cache_persists() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join(".kota/explanations.json");
+        let mut cache = ExplanationCache::load_from(&path);
+        assert!(cache.get("rm -rf build").is_none());
+        cache.insert(" rm -rf build ", "What it does: deletes build/.\nRisks: removes files.").unwrap();
+
+        let reloaded = ExplanationCache::load_from(&path);
+        assert_eq!(reloaded.get("rm -rf build").map(String::as_str), Some("What it does: deletes build/.\nRisks: removes files."));
+        assert!(explain_prompt("  ls -la ").ends_with("Command:\nls -la"));
+    }
+}
@@ -1,9 +1,13 @@
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
 use anyhow::{Context, Result};
 use chrono::{DateTime, Local};
 use crossterm::event::{KeyCode, KeyEvent};
+use tokio::sync::mpsc;
 
 #[derive(Clone, Debug)]
 pub struct FileItem {
@@ -15,6 +19,16 @@ pub struct FileItem {
     pub permissions: String,
     pub is_symlink: bool,
     pub requires_sudo: bool,
+    /// False until size, modified time and permissions have been read
+    pub metadata_loaded: bool,
+}
+
+/// Result of a background directory read, tagged with the request it answers
+pub struct DirectoryLoad {
+    generation: u64,
+    result: Result<Vec<FileItem>>,
+    // False for the quick name-only listing, true once metadata is filled in
+    complete: bool,
 }
 
 pub struct FileBrowser {
@@ -25,6 +39,14 @@ pub struct FileBrowser {
     pub show_hidden: bool,
     pub sort_by: SortBy,
     pub use_sudo: bool,
+    pub loading: bool,
+    pub load_error: Option<String>,
+    
+    // Directory reads run on a background thread; each new read cancels the last
+    load_generation: u64,
+    cancel_load: Arc<AtomicBool>,
+    load_tx: mpsc::UnboundedSender<DirectoryLoad>,
+    load_rx: mpsc::UnboundedReceiver<DirectoryLoad>,
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -38,6 +60,7 @@ pub enum SortBy {
 impl FileBrowser {
     pub fn new() -> Result<Self> {
         let current_dir = std::env::current_dir()?;
+        let (load_tx, load_rx) = mpsc::unbounded_channel();
         let mut browser = Self {
             current_dir: current_dir.clone(),
             items: Vec::new(),
@@ -46,156 +69,68 @@ impl FileBrowser {
             show_hidden: false,
             sort_by: SortBy::Name,
             use_sudo: false,
+            loading: false,
+            load_error: None,
+            load_generation: 0,
+            cancel_load: Arc::new(AtomicBool::new(false)),
+            load_tx,
+            load_rx,
         };
         browser.refresh()?;
         Ok(browser)
     }
     
+    /// Start reading the current directory in the background. Entries arrive
+    /// through `next_load` and are applied with `apply_load`.
     pub fn refresh(&mut self) -> Result<()> {
-        self.items = self.read_directory(&self.current_dir)?;
-        self.sort_items();
-        self.selected_index = self.selected_index.min(self.items.len().saturating_sub(1));
-        Ok(())
-    }
-    
-    fn read_directory(&self, path: &Path) -> Result<Vec<FileItem>> {
-        let mut items = Vec::new();
+        // Abandon any read still running for a directory we've left
+        self.cancel_load.store(true, Ordering::Relaxed);
+        self.cancel_load = Arc::new(AtomicBool::new(false));
+        self.load_generation += 1;
+        self.loading = true;
+        self.load_error = None;
         
-        // Try normal read first
-        let entries = match fs::read_dir(path) {
-            Ok(entries) => entries,
-            Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied && self.use_sudo => {
-                // Try with sudo if permission denied and sudo is enabled
-                return self.read_directory_with_sudo(path);
-            }
-            Err(e) => return Err(e.into()),
-        };
-        
-        // Add parent directory entry if not at root
-        if path.parent().is_some() {
-            items.push(FileItem {
-                name: "..".to_string(),
-                path: path.parent().unwrap().to_path_buf(),
-                is_dir: true,
-                size: 0,
-                modified: Local::now(),
-                permissions: String::new(),
-                is_symlink: false,
-                requires_sudo: false,
-            });
-        }
+        let generation = self.load_generation;
+        let cancel = self.cancel_load.clone();
+        let tx = self.load_tx.clone();
+        let path = self.current_dir.clone();
+        let show_hidden = self.show_hidden;
+        let use_sudo = self.use_sudo;
         
-        for entry in entries {
-            let entry = entry?;
-            let metadata = entry.metadata()?;
-            let name = entry.file_name().to_string_lossy().to_string();
-            
-            // Skip hidden files unless show_hidden is true
-            if !self.show_hidden && name.starts_with('.') {
-                continue;
-            }
-            
-            let modified = metadata.modified()
-                .map(DateTime::<Local>::from)
-                .unwrap_or_else(|_| Local::now());
-            
-            let permissions = self.format_permissions(&metadata);
-            
-            items.push(FileItem {
-                name,
-                path: entry.path(),
-                is_dir: metadata.is_dir(),
-                size: metadata.len(),
-                modified,
-                permissions,
-                is_symlink: metadata.file_type().is_symlink(),
-                requires_sudo: false,
-            });
-        }
+        thread::Builder::new()
+            .name("file-browser-load".to_string())
+            .spawn(move || load_directory(generation, &path, show_hidden, use_sudo, &cancel, &tx))
+            .context("Failed to start directory read")?;
         
-        Ok(items)
+        Ok(())
     }
     
-    fn read_directory_with_sudo(&self, path: &Path) -> Result<Vec<FileItem>> {
-        // Use sudo ls to read directory contents
-        let output = Command::new("sudo")
-            .args(["ls", "-la", path.to_str().unwrap()])
-            .output()
-            .context("Failed to execute sudo ls")?;
-        
-        if !output.status.success() {
-            return Err(anyhow::anyhow!("sudo ls failed: {}", String::from_utf8_lossy(&output.stderr)));
-        }
-        
-        let mut items = Vec::new();
-        
-        // Add parent directory
-        if path.parent().is_some() {
-            items.push(FileItem {
-                name: "..".to_string(),
-                path: path.parent().unwrap().to_path_buf(),
-                is_dir: true,
-                size: 0,
-                modified: Local::now(),
-                permissions: String::new(),
-                is_symlink: false,
-                requires_sudo: false,
-            });
-        }
-        
-        // Parse ls output
-        let output_str = String::from_utf8_lossy(&output.stdout);
-        for line in output_str.lines().skip(1) { // Skip total line
-            let parts: Vec<&str> = line.split_whitespace().collect();
-            if parts.len() < 9 {
-                continue;
-            }
-            
-            let permissions = parts[0].to_string();
-            let size: u64 = parts[4].parse().unwrap_or(0);
-            let name = parts[8..].join(" ");
-            
-            // Skip . and .. entries
-            if name == "." || name == ".." {
-                continue;
-            }
-            
-            // Skip hidden files unless show_hidden is true
-            if !self.show_hidden && name.starts_with('.') {
-                continue;
-            }
-            
-            let is_dir = permissions.starts_with('d');
-            let is_symlink = permissions.starts_with('l');
-            
-            items.push(FileItem {
-                name: name.clone(),
-                path: path.join(&name),
-                is_dir,
-                size,
-                modified: Local::now(), // Can't easily parse date from ls
-                permissions,
-                is_symlink,
-                requires_sudo: true,
-            });
-        }
-        
-        Ok(items)
+    /// Wait for the next background read result
+    pub async fn next_load(&mut self) -> Option<DirectoryLoad> {
+        self.load_rx.recv().await
     }
     
-    fn format_permissions(&self, metadata: &fs::Metadata) -> String {
-        #[cfg(unix)]
-        {
-            use std::os::unix::fs::PermissionsExt;
-            let mode = metadata.permissions().mode();
-            format!("{:o}", mode & 0o777)
+    /// Apply a background read result, ignoring results for directories we've since left
+    pub fn apply_load(&mut self, load: DirectoryLoad) {
+        if load.generation != self.load_generation {
+            return;
         }
-        #[cfg(not(unix))]
-        {
-            if metadata.permissions().readonly() {
-                "r--".to_string()
-            } else {
-                "rw-".to_string()
+        
+        match load.result {
+            Ok(items) => {
+                // Keep the cursor on the same entry when metadata arrives and re-sorts the list
+                let selected_path = self.get_selected().map(|item| item.path.clone());
+                self.items = items;
+                self.sort_items();
+                self.selected_index = selected_path
+                    .and_then(|path| self.items.iter().position(|item| item.path == path))
+                    .unwrap_or(self.selected_index)
+                    .min(self.items.len().saturating_sub(1));
+                self.loading = !load.complete;
+            }
+            Err(e) => {
+                self.load_error = Some(e.to_string());
+                self.loading = false;
             }
         }
     }
@@ -233,6 +168,7 @@ impl FileBrowser {
     
     pub fn navigate_to(&mut self, path: PathBuf) -> Result<()> {
         self.current_dir = path;
+        self.items.clear();
         self.selected_index = 0;
         self.scroll_offset = 0;
         self.refresh()
@@ -373,4 +309,230 @@ impl FileBrowser {
             format!("{:.1} {}", size, UNITS[unit_index])
         }
     }
-}
\ No newline at end of file
+}
+
+fn load_directory(
+    generation: u64,
+    path: &Path,
+    show_hidden: bool,
+    use_sudo: bool,
+    cancel: &AtomicBool,
+    tx: &mpsc::UnboundedSender<DirectoryLoad>,
+) {
+    let send = |result: Result<Vec<FileItem>>, complete: bool| {
+        if !cancel.load(Ordering::Relaxed) {
+            let _ = tx.send(DirectoryLoad { generation, result, complete });
+        }
+    };
+    
+    let mut items = match list_directory(path, show_hidden, cancel) {
+        Ok(items) => items,
+        Err(e) if use_sudo && is_permission_denied(&e) => {
+            // sudo ls gives us metadata in one go
+            send(read_directory_with_sudo(path, show_hidden), true);
+            return;
+        }
+        Err(e) => {
+            send(Err(e), true);
+            return;
+        }
+    };
+    if cancel.load(Ordering::Relaxed) {
+        return;
+    }
+    
+    // Names first so the listing shows up right away, then metadata
+    send(Ok(items.clone()), false);
+    
+    for item in items.iter_mut().filter(|item| item.name != "..") {
+        if cancel.load(Ordering::Relaxed) {
+            return;
+        }
+        if let Ok(metadata) = fs::symlink_metadata(&item.path) {
+            item.size = metadata.len();
+            item.modified = metadata.modified()
+                .map(DateTime::<Local>::from)
+                .unwrap_or_else(|_| Local::now());
+            item.permissions = format_permissions(&metadata);
+        }
+        item.metadata_loaded = true;
+    }
+    
+    send(Ok(items), true);
+}
+
+fn is_permission_denied(error: &anyhow::Error) -> bool {
+    error
+        .downcast_ref::<std::io::Error>()
+        .is_some_and(|e| e.kind() == std::io::ErrorKind::PermissionDenied)
+}
+
+/// List entry names and types only; stat calls are left for the metadata pass
+fn list_directory(path: &Path, show_hidden: bool, cancel: &AtomicBool) -> Result<Vec<FileItem>> {
+    let mut items = Vec::new();
+    let entries = fs::read_dir(path)?;
+    
+    // Add parent directory entry if not at root
+    if let Some(parent) = path.parent() {
+        items.push(FileItem {
+            name: "..".to_string(),
+            path: parent.to_path_buf(),
+            is_dir: true,
+            size: 0,
+            modified: Local::now(),
+            permissions: String::new(),
+            is_symlink: false,
+            requires_sudo: false,
+            metadata_loaded: true,
+        });
+    }
+    
+    for entry in entries {
+        if cancel.load(Ordering::Relaxed) {
+            break;
+        }
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        
+        // Skip hidden files unless show_hidden is true
+        if !show_hidden && name.starts_with('.') {
+            continue;
+        }
+        
+        let file_type = entry.file_type()?;
+        items.push(FileItem {
+            name,
+            path: entry.path(),
+            is_dir: file_type.is_dir(),
+            size: 0,
+            modified: Local::now(),
+            permissions: String::new(),
+            is_symlink: file_type.is_symlink(),
+            requires_sudo: false,
+            metadata_loaded: false,
+        });
+    }
+    
+    Ok(items)
+}
+
+fn read_directory_with_sudo(path: &Path, show_hidden: bool) -> Result<Vec<FileItem>> {
+    // Use sudo ls to read directory contents
+    let output = Command::new("sudo")
+        .args(["ls", "-la", path.to_str().unwrap()])
+        .output()
+        .context("Failed to execute sudo ls")?;
+    
+    if !output.status.success() {
+        return Err(anyhow::anyhow!("sudo ls failed: {}", String::from_utf8_lossy(&output.stderr)));
+    }
+    
+    let mut items = Vec::new();
+    
+    // Add parent directory
+    if path.parent().is_some() {
+        items.push(FileItem {
+            name: "..".to_string(),
+            path: path.parent().unwrap().to_path_buf(),
+            is_dir: true,
+            size: 0,
+            modified: Local::now(),
+            permissions: String::new(),
+            is_symlink: false,
+            requires_sudo: false,
+            metadata_loaded: true,
+        });
+    }
+    
+    // Parse ls output
+    let output_str = String::from_utf8_lossy(&output.stdout);
+    for line in output_str.lines().skip(1) { // Skip total line
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        if parts.len() < 9 {
+            continue;
+        }
+        
+        let permissions = parts[0].to_string();
+        let size: u64 = parts[4].parse().unwrap_or(0);
+        let name = parts[8..].join(" ");
+        
+        // Skip . and .. entries
+        if name == "." || name == ".." {
+            continue;
+        }
+        
+        // Skip hidden files unless show_hidden is true
+        if !show_hidden && name.starts_with('.') {
+            continue;
+        }
+        
+        let is_dir = permissions.starts_with('d');
+        let is_symlink = permissions.starts_with('l');
+        
+        items.push(FileItem {
+            name: name.clone(),
+            path: path.join(&name),
+            is_dir,
+            size,
+            modified: Local::now(), // Can't easily parse date from ls
+            permissions,
+            is_symlink,
+            requires_sudo: true,
+            metadata_loaded: true,
+        });
+    }
+    
+    Ok(items)
+}
+
+fn format_permissions(metadata: &fs::Metadata) -> String {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = metadata.permissions().mode();
+        format!("{:o}", mode & 0o777)
+    }
+    #[cfg(not(unix))]
+    {
+        if metadata.permissions().readonly() {
+            "r--".to_string()
+        } else {
+            "rw-".to_string()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_background_load_with_lazy_metadata() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("b.txt"), "hello").unwrap();
+        fs::create_dir(temp_dir.path().join("a-dir")).unwrap();
+        
+        let mut browser = FileBrowser::new().unwrap();
+        browser.navigate_to(temp_dir.path().to_path_buf()).unwrap();
+        
+        // Loads for the previous directory are ignored; the names-only listing comes first
+        let mut load = browser.next_load().await.unwrap();
+        while load.generation != browser.load_generation {
+            load = browser.next_load().await.unwrap();
+        }
+        assert!(!load.complete);
+        browser.apply_load(load);
+        assert!(browser.loading);
+        let names: Vec<&str> = browser.items.iter().map(|item| item.name.as_str()).collect();
+        assert_eq!(names, vec!["..", "a-dir", "b.txt"]);
+        
+        let load = browser.next_load().await.unwrap();
+        assert!(load.complete);
+        browser.apply_load(load);
+        assert!(!browser.loading);
+        let file = browser.items.iter().find(|item| item.name == "b.txt").unwrap();
+        assert!(file.metadata_loaded);
+        assert_eq!(file.size, 5);
+    }
+}
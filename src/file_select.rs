@@ -0,0 +1,335 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::Result;
+use regex::Regex;
+
+// Directory and glob additions bigger than this need `--yes`
+const LARGE_ADD_FILES: usize = 40;
+const LARGE_ADD_TOKENS: u64 = 60_000;
+
+/// The files a path, directory or glob like `src/**/*.rs` names
+#[derive(Debug, Clone, PartialEq)]
+pub struct Selection {
+    pub files: Vec<String>,
+    /// Estimated tokens across all the files, from their sizes
+    pub tokens: u64,
+    /// Whether it came from a directory or glob rather than a single file
+    pub expanded: bool,
+    /// Files left out of a directory or glob, with why: binary, too large or unreadable
+    pub skipped: Vec<String>,
+}
+
+impl Selection {
+    /// Large directory or glob additions are confirmed first; single files never are
+    pub fn needs_confirmation(&self) -> bool {
+        self.expanded && (self.files.len() > LARGE_ADD_FILES || self.tokens > LARGE_ADD_TOKENS)
+    }
+
+    pub fn summary(&self) -> String {
+        let noun = if self.files.len() == 1 { "file" } else { "files" };
+        format!("{} {} (~{} tokens)", self.files.len(), noun, self.tokens)
+    }
+}
+
+/// Split `--ro` off an `/add_file` argument; read-only files are context the model can read
+/// but never edit, like reference code from dependencies
+pub fn split_read_only_option(arg: &str) -> (String, bool) {
+    split_flag(arg, "--ro")
+}
+
+/// Split a flag like `--truncate` off an argument, and say whether it was there
+pub fn split_flag(arg: &str, flag: &str) -> (String, bool) {
+    let words: Vec<&str> = arg.split_whitespace().collect();
+    if !words.contains(&flag) {
+        return (arg.trim().to_string(), false);
+    }
+    (words.into_iter().filter(|word| *word != flag).collect::<Vec<_>>().join(" "), true)
+}
+
+/// Lines of a file, counted from 1; `end` of None runs to the end of the file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LineRange {
+    pub start: usize,
+    pub end: Option<usize>,
+}
+
+impl LineRange {
+    /// "100-250", "100-" or "42"
+    pub fn parse(spec: &str) -> Result<Self> {
+        let invalid = || anyhow::anyhow!("Invalid line range {}; use a range like 100-250, 100- or 42", spec);
+        let (start, end) = match spec.split_once('-') {
+            Some((start, end)) => (start, Some(end).filter(|end| !end.is_empty())),
+            None => (spec, Some(spec)),
+        };
+        let start: usize = start.parse().map_err(|_| invalid())?;
+        let end = end.map(|end| end.parse::<usize>()).transpose().map_err(|_| invalid())?;
+        if start == 0 || end.is_some_and(|end| end < start) {
+            return Err(invalid());
+        }
+        Ok(Self { start, end })
+    }
+
+    /// The range's lines of `content`, with the last line number actually shown
+    pub fn slice<'a>(&self, content: &'a str) -> Result<(Vec<&'a str>, usize)> {
+        let lines: Vec<&str> = content.lines().collect();
+        if self.start > lines.len() {
+            return Err(anyhow::anyhow!("The file has only {} lines; line {} is past the end", lines.len(), self.start));
+        }
+        let end = self.end.unwrap_or(lines.len()).min(lines.len());
+        Ok((lines[self.start - 1..end].to_vec(), end))
+    }
+}
+
+impl std::fmt::Display for LineRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.end {
+            Some(end) if end == self.start => write!(f, "{}", self.start),
+            Some(end) => write!(f, "{}-{}", self.start, end),
+            None => write!(f, "{}-", self.start),
+        }
+    }
+}
+
+/// Split a `:100-250` line range off an `/add_file` path. Paths whose name really ends that
+/// way, and anything after the colon that isn't a range, are left alone.
+pub fn split_line_range(arg: &str) -> Result<(String, Option<LineRange>)> {
+    let arg = arg.trim();
+    let Some((path, spec)) = arg.rsplit_once(':') else { return Ok((arg.to_string(), None)) };
+    let looks_like_range = !spec.is_empty() && spec.chars().all(|c| c.is_ascii_digit() || c == '-');
+    if !looks_like_range || path.is_empty() || Path::new(arg).exists() {
+        return Ok((arg.to_string(), None));
+    }
+    Ok((path.to_string(), Some(LineRange::parse(spec)?)))
+}
+
+/// The path in a `--- File: x ---` header, without the `(lines 100-250 of 900)` of a partial file
+pub fn strip_line_range(name: &str) -> &str {
+    match name.find(" (lines ") {
+        Some(i) if name.ends_with(')') => &name[..i],
+        _ => name,
+    }
+}
+
+pub fn is_glob(pattern: &str) -> bool {
+    pattern.contains(['*', '?', '['])
+}
+
+/// Expand `pattern` to the files it names. Directories and globs skip whatever git
+/// ignores, and hidden files when the directory isn't in a git repository.
+pub fn select(pattern: &str) -> Result<Selection> {
+    let pattern = pattern.trim();
+    let (files, expanded) = if is_glob(pattern) {
+        (glob_files(pattern)?, true)
+    } else if Path::new(pattern).is_dir() {
+        (listed_files(Path::new(pattern)), true)
+    } else {
+        (vec![pattern.to_string()], false)
+    };
+    if files.is_empty() {
+        return Err(anyhow::anyhow!("No files match {}", pattern));
+    }
+    let tokens = files
+        .iter()
+        .filter_map(|file| fs::metadata(file).ok())
+        .map(|metadata| metadata.len().div_ceil(4))
+        .sum();
+    Ok(Selection { files, tokens, expanded, skipped: Vec::new() })
+}
+
+fn glob_files(pattern: &str) -> Result<Vec<String>> {
+    // The literal directories before the first wildcard are where the search starts
+    let parts: Vec<&str> = pattern.split('/').collect();
+    let literal = parts.iter().take_while(|part| !is_glob(part)).count();
+    let base = parts[..literal].join("/");
+    let rest = parts[literal..].join("/");
+    let matcher = glob_regex(&rest)?;
+
+    let base_dir = if base.is_empty() && pattern.starts_with('/') { "/".to_string() } else { base };
+    let dir = if base_dir.is_empty() { Path::new(".") } else { Path::new(&base_dir) };
+    if !dir.is_dir() {
+        return Err(anyhow::anyhow!("No such directory: {}", dir.display()));
+    }
+    let mut files: Vec<String> = listed_paths(dir)
+        .into_iter()
+        .filter(|relative| matcher.is_match(&relative.to_string_lossy()))
+        .map(|relative| display_path(&base_dir, &relative))
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+/// `**` spans directories, `*` and `?` stay within one, `[abc]` is a character class
+fn glob_regex(glob: &str) -> Result<Regex> {
+    let mut regex = String::from("^");
+    let chars: Vec<char> = glob.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '*' if chars.get(i + 1) == Some(&'*') => {
+                if chars.get(i + 2) == Some(&'/') {
+                    regex.push_str("(?:.*/)?");
+                    i += 3;
+                } else {
+                    regex.push_str(".*");
+                    i += 2;
+                }
+                continue;
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            '[' => match chars[i..].iter().position(|c| *c == ']') {
+                Some(end) => {
+                    let class: String = chars[i + 1..i + end].iter().collect();
+                    let class = class.strip_prefix('!').map(|rest| format!("^{}", rest)).unwrap_or(class);
+                    regex.push_str(&format!("[{}]", class.replace('\\', "\\\\")));
+                    i += end + 1;
+                    continue;
+                }
+                None => regex.push_str(r"\["),
+            },
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+        i += 1;
+    }
+    regex.push('$');
+    Regex::new(&regex).map_err(|e| anyhow::anyhow!("Invalid pattern {}: {}", glob, e))
+}
+
+fn listed_files(dir: &Path) -> Vec<String> {
+    let base = dir.to_string_lossy().trim_end_matches('/').to_string();
+    let mut files: Vec<String> = listed_paths(dir).iter().map(|relative| display_path(&base, relative)).collect();
+    files.sort();
+    files
+}
+
+/// Files under `dir` as sorted paths relative to it, skipping what git ignores
+pub fn project_files(dir: &Path) -> Vec<String> {
+    let mut files: Vec<String> = listed_paths(dir).iter().map(|relative| relative.to_string_lossy().to_string()).collect();
+    files.sort();
+    files
+}
+
+/// Files under `dir`, relative to it. Inside a git repository that's tracked and untracked
+/// files git doesn't ignore; elsewhere, every file outside hidden directories.
+fn listed_paths(dir: &Path) -> Vec<PathBuf> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["ls-files", "--cached", "--others", "--exclude-standard", "-z"])
+        .output();
+    if let Ok(output) = output {
+        if output.status.success() {
+            return String::from_utf8_lossy(&output.stdout)
+                .split('\0')
+                .filter(|path| !path.is_empty())
+                .map(PathBuf::from)
+                // Deleted files are still listed until the deletion is committed
+                .filter(|path| dir.join(path).is_file())
+                .collect();
+        }
+    }
+    let mut paths = Vec::new();
+    walk(dir, Path::new(""), &mut paths);
+    paths
+}
+
+fn walk(root: &Path, relative: &Path, paths: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(root.join(relative)) else { return };
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        if name.to_string_lossy().starts_with('.') {
+            continue;
+        }
+        let path = relative.join(&name);
+        match entry.file_type() {
+            Ok(kind) if kind.is_dir() => walk(root, &path, paths),
+            Ok(kind) if kind.is_file() => paths.push(path),
+            _ => {}
+        }
+    }
+}
+
+fn display_path(base: &str, relative: &Path) -> String {
+    let relative = relative.to_string_lossy();
+    match base {
+        "" | "." => relative.to_string(),
+        "/" => format!("/{}", relative),
+        base => format!("{}/{}", base.trim_end_matches('/'), relative),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_glob_regex() {
+        let rust = glob_regex("**/*.rs").unwrap();
+        assert!(rust.is_match("main.rs"));
+        assert!(rust.is_match("llm/router.rs"));
+        assert!(!rust.is_match("main.rs.bak"));
+
+        let top = glob_regex("*.toml").unwrap();
+        assert!(top.is_match("Cargo.toml"));
+        assert!(!top.is_match("crates/Cargo.toml"));
+
+        assert!(glob_regex("test_?.[!c]").unwrap().is_match("test_1.h"));
+        assert!(!glob_regex("test_?.[!c]").unwrap().is_match("test_1.c"));
+    }
+
+    #[test]
+    fn test_line_ranges() {
+        assert_eq!(LineRange::parse("100-250").unwrap(), LineRange { start: 100, end: Some(250) });
+        assert_eq!(LineRange::parse("42").unwrap().to_string(), "42");
+        assert_eq!(LineRange::parse("100-").unwrap().to_string(), "100-");
+        assert!(LineRange::parse("250-100").is_err());
+        assert!(LineRange::parse("0-3").is_err());
+
+        let (lines, end) = LineRange::parse("2-9").unwrap().slice("a\nb\nc\n").unwrap();
+        assert_eq!((lines, end), (vec!["b", "c"], 3));
+        assert!(LineRange::parse("4-").unwrap().slice("a\nb\nc\n").is_err());
+
+        let (path, range) = split_line_range("src/big.rs:100-250").unwrap();
+        assert_eq!((path.as_str(), range), ("src/big.rs", Some(LineRange { start: 100, end: Some(250) })));
+        assert_eq!(split_line_range("src/main.rs").unwrap(), ("src/main.rs".to_string(), None));
+        assert_eq!(split_line_range("C:notes.txt").unwrap(), ("C:notes.txt".to_string(), None));
+        assert!(split_line_range("src/big.rs:9-3").is_err());
+        assert_eq!(strip_line_range("src/big.rs (lines 100-250 of 900)"), "src/big.rs");
+        assert_eq!(strip_line_range("src/main.rs"), "src/main.rs");
+    }
+
+    #[test]
+    fn test_split_read_only_option() {
+        assert_eq!(split_read_only_option("vendor/serde --ro --yes"), ("vendor/serde --yes".to_string(), true));
+        assert_eq!(split_read_only_option("--ro src/lib.rs"), ("src/lib.rs".to_string(), true));
+        assert_eq!(split_read_only_option(" src/main.rs "), ("src/main.rs".to_string(), false));
+    }
+
+    #[test]
+    fn test_select_directories_and_globs() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().to_string_lossy().to_string();
+        fs::create_dir_all(dir.path().join("src/llm")).unwrap();
+        fs::create_dir_all(dir.path().join(".cache")).unwrap();
+        fs::write(dir.path().join("src/main.rs"), "fn main() {}\n").unwrap();
+        fs::write(dir.path().join("src/llm/router.rs"), "pub fn route() {}\n").unwrap();
+        fs::write(dir.path().join("src/notes.md"), "notes\n").unwrap();
+        fs::write(dir.path().join(".cache/skip.rs"), "\n").unwrap();
+
+        let rust = select(&format!("{}/src/**/*.rs", root)).unwrap();
+        assert_eq!(rust.files, vec![format!("{}/src/llm/router.rs", root), format!("{}/src/main.rs", root)]);
+        assert!(rust.expanded);
+        assert!(!rust.needs_confirmation());
+
+        let all = select(&root).unwrap();
+        assert_eq!(all.files.len(), 3);
+        assert_eq!(all.summary(), "3 files (~11 tokens)");
+
+        let single = select(&format!("{}/src/main.rs", root)).unwrap();
+        assert!(!single.expanded);
+        assert!(select(&format!("{}/src/*.py", root)).is_err());
+    }
+}
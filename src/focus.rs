@@ -0,0 +1,292 @@
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::clock::clock;
+use crate::db::Database;
+
+/// The running focus block, kept on disk so it survives a restart
+pub const FOCUS_FILE: &str = ".kota/focus.json";
+// Finished blocks are recorded as learnings under this topic
+const FOCUS_TOPIC: &str = "focus";
+const MAX_HELD: usize = 200;
+
+/// The bridge's message types, used to sort what a focus block holds back. ERROR and
+/// REQUEST need action, so they may interrupt while the distraction budget lasts.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Category {
+    Info,
+    Request,
+    Response,
+    Update,
+    Error,
+}
+
+impl Category {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Category::Info => "INFO",
+            Category::Request => "REQUEST",
+            Category::Response => "RESPONSE",
+            Category::Update => "UPDATE",
+            Category::Error => "ERROR",
+        }
+    }
+
+    fn may_interrupt(&self) -> bool {
+        matches!(self, Category::Error | Category::Request)
+    }
+}
+
+/// A notification or insight kept back until the block ends
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Held {
+    pub at: DateTime<Utc>,
+    pub category: Category,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FocusSession {
+    pub started: DateTime<Utc>,
+    pub ends: DateTime<Utc>,
+    #[serde(default)]
+    pub goal: Option<String>,
+    /// How many ERROR or REQUEST notifications may still get through
+    pub budget: u32,
+    #[serde(default)]
+    pub interruptions: u32,
+    #[serde(default)]
+    pub edits: u32,
+    #[serde(default)]
+    pub commands: u32,
+    #[serde(default)]
+    pub held: Vec<Held>,
+}
+
+impl FocusSession {
+    pub fn new(now: DateTime<Utc>, length: Duration, goal: Option<String>, budget: u32) -> Self {
+        Self { started: now, ends: now + length, goal, budget, interruptions: 0, edits: 0, commands: 0, held: Vec::new() }
+    }
+
+    /// Whether a notification gets through; the ones that don't are held for the summary
+    fn admit(&mut self, category: Category, text: &str, now: DateTime<Utc>) -> bool {
+        if category.may_interrupt() && self.interruptions < self.budget {
+            self.interruptions += 1;
+            return true;
+        }
+        if self.held.len() < MAX_HELD {
+            self.held.push(Held { at: now, category, text: text.to_string() });
+        }
+        false
+    }
+
+    pub fn minutes_left(&self, now: DateTime<Utc>) -> i64 {
+        (self.ends - now).num_minutes().max(0)
+    }
+
+    /// What happened during the block, shown when it ends
+    pub fn summary(&self, ended: DateTime<Utc>) -> String {
+        let minutes = (ended - self.started).num_minutes().max(0);
+        let mut summary = format!("Focus block finished: {} min", minutes);
+        if let Some(goal) = &self.goal {
+            summary.push_str(&format!(" on {}", goal));
+        }
+        summary.push_str(&format!("\n  {} edit(s) applied, {} command(s) run", self.edits, self.commands));
+        summary.push_str(&format!("\n  Interruptions let through: {} of {}", self.interruptions, self.budget));
+        if self.held.is_empty() {
+            summary.push_str("\n  Nothing was held back");
+        } else {
+            summary.push_str(&format!("\n  Held back ({}):", self.held.len()));
+            for held in &self.held {
+                summary.push_str(&format!("\n    {:<8} {} {}", held.category.label(), clock().time(&held.at), held.text));
+            }
+        }
+        summary
+    }
+
+    /// One line for memory, so past blocks can be looked back on
+    fn record(&self, ended: DateTime<Utc>) -> String {
+        let goal = self.goal.as_ref().map(|goal| format!(" on {}", goal)).unwrap_or_default();
+        format!(
+            "{} {}: {} min focus{}; {} edit(s), {} command(s), {} interruption(s), {} held back",
+            clock().date(&self.started),
+            clock().time(&self.started),
+            (ended - self.started).num_minutes().max(0),
+            goal,
+            self.edits,
+            self.commands,
+            self.interruptions,
+            self.held.len()
+        )
+    }
+}
+
+// Read from FOCUS_FILE on first use
+static FOCUS: Mutex<Option<Option<FocusSession>>> = Mutex::new(None);
+
+fn with_session<T>(update: impl FnOnce(&mut Option<FocusSession>) -> T) -> T {
+    let mut state = FOCUS.lock().unwrap_or_else(|e| e.into_inner());
+    let session = state.get_or_insert_with(|| {
+        let json = fs::read_to_string(FOCUS_FILE).ok()?;
+        serde_json::from_str(&json).ok()
+    });
+    let before = session.clone();
+    let result = update(session);
+    if *session != before {
+        if let Err(e) = save(Path::new(FOCUS_FILE), session.as_ref()) {
+            eprintln!("Warning: Failed to save the focus block: {}", e);
+        }
+    }
+    result
+}
+
+fn save(path: &Path, session: Option<&FocusSession>) -> Result<()> {
+    match session {
+        Some(session) => {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(path, serde_json::to_string_pretty(session)?).with_context(|| format!("Failed to write {}", path.display()))
+        }
+        None if path.exists() => fs::remove_file(path).with_context(|| format!("Failed to remove {}", path.display())),
+        None => Ok(()),
+    }
+}
+
+/// The running focus block, if there is one
+pub fn current() -> Option<FocusSession> {
+    with_session(|session| session.clone())
+}
+
+pub fn start(length: Duration, goal: Option<String>, budget: u32) -> Result<FocusSession> {
+    with_session(|session| {
+        if let Some(running) = session.as_ref() {
+            return Err(anyhow::anyhow!("A focus block is already running, {} min left; /focus stop ends it", running.minutes_left(Utc::now())));
+        }
+        let started = FocusSession::new(Utc::now(), length, goal, budget);
+        *session = Some(started.clone());
+        Ok(started)
+    })
+}
+
+/// Called by anything that would notify or suggest. Returns true when a focus block holds it
+/// back; the caller should then stay quiet.
+pub fn intercept(category: Category, text: &str) -> bool {
+    with_session(|session| session.as_mut().is_some_and(|session| !session.admit(category, text, Utc::now())))
+}
+
+pub fn note_edits(files: usize) {
+    with_session(|session| {
+        if let Some(session) = session {
+            session.edits += files as u32;
+        }
+    })
+}
+
+pub fn note_command() {
+    with_session(|session| {
+        if let Some(session) = session {
+            session.commands += 1;
+        }
+    })
+}
+
+/// End the block now. Returns its summary, or None when none was running.
+pub fn stop() -> Option<String> {
+    let (session, ended) = with_session(|session| session.take().map(|session| (session, Utc::now())))?;
+    if let Err(e) = Database::open().and_then(|db| db.insert_learning(FOCUS_TOPIC, &session.record(ended))) {
+        eprintln!("Warning: Failed to record the focus block in memory: {}", e);
+    }
+    Some(session.summary(ended))
+}
+
+/// End the block if its time is up, returning the summary to show
+pub fn finish_if_due() -> Option<String> {
+    let due = with_session(|session| session.as_ref().is_some_and(|session| Utc::now() >= session.ends));
+    if due { stop() } else { None }
+}
+
+/// The most recent finished blocks, newest first
+pub fn history(limit: usize) -> Result<Vec<String>> {
+    let learnings = Database::open()?.all_learnings()?;
+    Ok(learnings.into_iter().rev().filter(|(_, topic, _)| topic == FOCUS_TOPIC).take(limit).map(|(_, _, entry)| entry).collect())
+}
+
+/// `50m`, `1h`, `1h30m` or a bare number of minutes
+pub fn parse_length(text: &str) -> Option<Duration> {
+    let text = text.trim().to_lowercase();
+    if let Ok(minutes) = text.parse::<i64>() {
+        return (minutes > 0).then(|| Duration::minutes(minutes));
+    }
+    let mut total = 0;
+    let mut number = String::new();
+    for c in text.chars() {
+        match c {
+            '0'..='9' => number.push(c),
+            'h' | 'm' if !number.is_empty() => {
+                let value: i64 = number.parse().ok()?;
+                total += if c == 'h' { value * 60 } else { value };
+                number.clear();
+            }
+            _ => return None,
+        }
+    }
+    (number.is_empty() && total > 0).then(|| Duration::minutes(total))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_parse_length() {
+        assert_eq!(parse_length("50m"), Some(Duration::minutes(50)));
+        assert_eq!(parse_length("1h30m"), Some(Duration::minutes(90)));
+        assert_eq!(parse_length("2H"), Some(Duration::minutes(120)));
+        assert_eq!(parse_length("25"), Some(Duration::minutes(25)));
+        assert_eq!(parse_length("0"), None);
+        assert_eq!(parse_length("30s"), None);
+        assert_eq!(parse_length("1h30"), None);
+        assert_eq!(parse_length("fix the parser"), None);
+    }
+
+    #[test]
+    fn test_budget_lets_urgent_notifications_through() {
+        let now = Utc::now();
+        let mut session = FocusSession::new(now, Duration::minutes(50), Some("the parser".to_string()), 1);
+        assert!(!session.admit(Category::Info, "CodeAgent finished: docs", now));
+        assert!(session.admit(Category::Error, "Tests failed: cargo test", now));
+        assert!(!session.admit(Category::Error, "Tests failed: cargo test", now));
+        assert_eq!(session.interruptions, 1);
+        assert_eq!(session.held.iter().map(|held| held.category).collect::<Vec<_>>(), vec![Category::Info, Category::Error]);
+        assert_eq!(session.minutes_left(now + Duration::minutes(20)), 30);
+
+        session.edits = 2;
+        let summary = session.summary(now + Duration::minutes(50));
+        assert!(summary.starts_with("Focus block finished: 50 min on the parser"));
+        assert!(summary.contains("2 edit(s) applied, 0 command(s) run"));
+        assert!(summary.contains("Interruptions let through: 1 of 1"));
+        assert!(summary.contains("Held back (2):"));
+        assert!(session.record(now + Duration::minutes(50)).contains("50 min focus on the parser; 2 edit(s)"));
+    }
+
+    #[test]
+    fn test_save_and_clear() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join(".kota/focus.json");
+        let session = FocusSession::new(Utc::now(), Duration::minutes(25), None, 0);
+        save(&path, Some(&session)).unwrap();
+        let loaded: FocusSession = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(loaded, session);
+        save(&path, None).unwrap();
+        assert!(!path.exists());
+        save(&path, None).unwrap();
+    }
+}
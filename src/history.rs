@@ -0,0 +1,126 @@
+use std::sync::OnceLock;
+
+use chrono::{DateTime, Local};
+
+use crate::clock::clock;
+use crate::db::Database;
+use crate::focus;
+use crate::webhooks::{self, Event};
+
+// Command output beyond this is dropped before it's stored
+const MAX_STORED_OUTPUT_CHARS: usize = 20_000;
+
+/// One ranked full-text search hit
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoryHit {
+    /// "conversation", "command" or "memory"
+    pub kind: String,
+    pub session_id: Option<String>,
+    pub created_at: DateTime<Local>,
+    /// Matching excerpt with the search terms in [brackets]
+    pub snippet: String,
+}
+
+/// Identifies this run of KOTA; conversation entries and command outputs are grouped by it
+pub fn current_session_id() -> &'static str {
+    static SESSION_ID: OnceLock<String> = OnceLock::new();
+    SESSION_ID.get_or_init(|| format!("{}-{}", Local::now().format("%Y%m%d-%H%M%S"), std::process::id()))
+}
+
+/// Store a shell command and its output so it can be found with `/history search`
+pub fn record_command(command: &str, output: &str, success: bool) {
+    let output: String = output.chars().take(MAX_STORED_OUTPUT_CHARS).collect();
+    let result = Database::open()
+        .and_then(|db| db.insert_command_output(current_session_id(), command, &output, success));
+    if let Err(e) = result {
+        eprintln!("Warning: Failed to record command in history: {}", e);
+    }
+    focus::note_command();
+    if !success && webhooks::is_test_command(command) {
+        webhooks::fire(Event::TestsFailed { command: command.to_string(), output });
+    }
+}
+
+/// Turn free text into an FTS5 query: every word must match, the last one as a prefix.
+/// Quoting each word keeps FTS operators and punctuation in the input from being parsed.
+pub fn fts_query(input: &str) -> Option<String> {
+    let words: Vec<&str> = input
+        .split(|c: char| !c.is_alphanumeric() && c != '_')
+        .filter(|word| !word.is_empty())
+        .collect();
+    let (last, rest) = words.split_last()?;
+
+    let mut terms: Vec<String> = rest.iter().map(|word| format!("\"{}\"", word)).collect();
+    terms.push(format!("\"{}\"*", last));
+    Some(terms.join(" "))
+}
+
+/// Looser FTS5 query for finding related entries: any of the longer words may match
+pub fn fts_any_query(input: &str) -> Option<String> {
+    let mut words: Vec<String> = input
+        .split(|c: char| !c.is_alphanumeric() && c != '_')
+        .filter(|word| word.chars().count() >= 4)
+        .map(|word| format!("\"{}\"", word.to_lowercase()))
+        .collect();
+    words.sort();
+    words.dedup();
+    
+    if words.is_empty() {
+        return None;
+    }
+    Some(words.join(" OR "))
+}
+
+pub fn format_search_results(query: &str, hits: &[HistoryHit]) -> String {
+    if hits.is_empty() {
+        return format!("No history matches '{}'", query);
+    }
+
+    let mut output = format!("History matches for '{}', best first:\n", query);
+    for (i, hit) in hits.iter().enumerate() {
+        let session = hit.session_id.as_deref().map(|id| format!(" session {}", id)).unwrap_or_default();
+        output.push_str(&format!(
+            "{:>3}. [{}] {}{}\n     {}\n",
+            i + 1,
+            hit.kind,
+            clock().date_time(&hit.created_at),
+            session,
+            hit.snippet.replace('\n', " ")
+        ));
+    }
+    if hits.iter().any(|hit| hit.session_id.is_some()) {
+        output.push_str("Open a session with /history show <session-id>");
+    }
+
+    output
+}
+
+/// Replay a session's conversation and commands in order
+pub fn format_session(session_id: &str, entries: &[(DateTime<Local>, String, String)]) -> String {
+    if entries.is_empty() {
+        return format!("No history for session {}", session_id);
+    }
+
+    let mut output = format!("Session {}:\n", session_id);
+    for (at, kind, content) in entries {
+        output.push_str(&format!("\n[{}] {}\n{}\n", clock().time(at), kind, content.trim_end()));
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fts_query() {
+        assert_eq!(fts_query("cargo build").as_deref(), Some("\"cargo\" \"build\"*"));
+        // FTS syntax in the input is treated as plain words
+        assert_eq!(fts_query("NOT \"src/main.rs\"").as_deref(), Some("\"NOT\" \"src\" \"main\" \"rs\"*"));
+        assert_eq!(fts_query("  -- "), None);
+        
+        assert_eq!(fts_any_query("Fix the parser, then the Parser tests").as_deref(), Some("\"parser\" OR \"tests\" OR \"then\""));
+        assert_eq!(fts_any_query("do it"), None);
+    }
+}
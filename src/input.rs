@@ -9,6 +9,8 @@ use termimad::crossterm::{
     terminal::{Clear, ClearType},
 };
 
+use crate::trace;
+
 // Custom validator for determining when input should continue on multiple lines
 pub struct KotaValidator;
 
@@ -97,7 +99,10 @@ impl Validator for KotaValidator {
 }
 
 // Custom prompt that shows vim mode
-pub struct KotaPrompt;
+/// The input prompt, with a status such as the context size on the right
+pub struct KotaPrompt {
+    pub status: String,
+}
 
 impl Prompt for KotaPrompt {
     fn render_prompt_left(&self) -> std::borrow::Cow<str> {
@@ -105,7 +110,7 @@ impl Prompt for KotaPrompt {
     }
 
     fn render_prompt_right(&self) -> std::borrow::Cow<str> {
-        "".into()
+        self.status.dimmed().to_string().into()
     }
 
     fn render_prompt_indicator(&self, edit_mode: PromptEditMode) -> std::borrow::Cow<str> {
@@ -138,7 +143,7 @@ impl Prompt for KotaPrompt {
     }
 }
 
-pub fn read_line_with_shortcuts() -> Result<String> {
+pub fn read_line_with_shortcuts(status: &str) -> Result<String> {
     // Create Vi mode with custom keybindings
     let mut normal_keybindings = default_vi_normal_keybindings();
     let mut insert_keybindings = default_vi_insert_keybindings();
@@ -162,7 +167,7 @@ pub fn read_line_with_shortcuts() -> Result<String> {
         .with_edit_mode(Box::new(vi_mode))
         .with_validator(Box::new(KotaValidator));
     
-    let prompt = KotaPrompt;
+    let prompt = KotaPrompt { status: status.to_string() };
     
     // Read input from user with proper signal handling
     let sig = line_editor.read_line(&prompt)?;
@@ -172,12 +177,14 @@ pub fn read_line_with_shortcuts() -> Result<String> {
             // Handle Ctrl+D
             println!();
             println!("{}", "Goodbye!".bright_white());
+            trace::finish();
             std::process::exit(0);
         }
         Signal::CtrlC => {
             // Handle Ctrl+C
             println!();
             println!("{}", "Goodbye!".bright_white());
+            trace::finish();
             std::process::exit(0);
         }
     }
@@ -393,6 +400,7 @@ pub fn read_single_char() -> Result<char> {
                 disable_raw_mode()?;
                 println!();
                 println!("{}", "Goodbye!".bright_white());
+                trace::finish();
                 std::process::exit(0);
             }
             Event::Key(KeyEvent {
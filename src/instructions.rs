@@ -0,0 +1,179 @@
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::sync::Mutex;
+
+use anyhow::{Context, Result};
+
+use crate::context::repo_map;
+use crate::file_select;
+use crate::llm::{self, ModelConfig};
+
+/// Project conventions added to every system prompt; the first one found is used
+pub const INSTRUCTION_FILES: [&str; 2] = ["KOTA.md", ".kota/instructions.md"];
+
+// Past this the file is cut short, so a runaway file can't crowd out the request
+const MAX_INSTRUCTION_CHARS: usize = 20_000;
+// How much of each file /init shows the model
+const SURVEY_FILE_LINES: usize = 60;
+const SURVEY_MAP_CHARS: usize = 8_000;
+const SURVEY_MANIFESTS: &[&str] = &[
+    "Cargo.toml", "package.json", "pyproject.toml", "setup.py", "go.mod", "Gemfile", "pom.xml",
+    "build.gradle", "Makefile", "justfile", "CMakeLists.txt", "docker-compose.yml", ".editorconfig",
+    "rustfmt.toml", ".eslintrc.json", ".prettierrc", "CONTRIBUTING.md",
+];
+
+const INIT_INSTRUCTIONS: &str = "Write a KOTA.md for this repository: instructions an AI coding assistant \
+reads before every request. Cover what the project is in a sentence or two, how to build, test, lint and run \
+it (exact commands), how the code is laid out, and the conventions a change must follow: naming, error \
+handling, tests, formatting and commit style. Only state what the files below show; don't invent commands \
+or rules. Use short Markdown sections and bullet points, under 80 lines, and reply with the file only.";
+
+// Read once, at the first prompt, and kept until /prompt reload or /init
+static INSTRUCTIONS: Mutex<Option<Option<(String, String)>>> = Mutex::new(None);
+
+/// The first instructions file in `dir` with text in it, as its path and contents
+fn read(dir: &Path) -> Option<(String, String)> {
+    INSTRUCTION_FILES.iter().find_map(|name| {
+        let text = fs::read_to_string(dir.join(name)).ok()?;
+        let text = text.trim();
+        if text.is_empty() {
+            return None;
+        }
+        let text = match text.char_indices().nth(MAX_INSTRUCTION_CHARS) {
+            Some((cut, _)) => {
+                eprintln!("Warning: {} is over {} characters; only the start is used", name, MAX_INSTRUCTION_CHARS);
+                &text[..cut]
+            }
+            None => text,
+        };
+        Some((name.to_string(), text.to_string()))
+    })
+}
+
+/// The project's instructions file, as its path and contents
+pub fn project_instructions() -> Option<(String, String)> {
+    let mut instructions = INSTRUCTIONS.lock().unwrap_or_else(|e| e.into_inner());
+    instructions.get_or_insert_with(|| read(Path::new("."))).clone()
+}
+
+/// Read the instructions file again. Returns its path, if there is one.
+pub fn reload() -> Option<String> {
+    let loaded = read(Path::new("."));
+    let path = loaded.as_ref().map(|(path, _)| path.clone());
+    *INSTRUCTIONS.lock().unwrap_or_else(|e| e.into_inner()) = Some(loaded);
+    path
+}
+
+/// The part of the system prompt the instructions file adds
+pub fn system_section() -> Option<String> {
+    let (path, text) = project_instructions()?;
+    Some(format!("Project instructions from {} (follow these conventions in this repository):\n{}", path, text))
+}
+
+/// What `/init` shows the model: the file list, build manifests, the start of the README,
+/// CI workflows, recent commits and a map of the code
+pub fn survey(root: &Path) -> String {
+    let files = file_select::project_files(root);
+    let mut survey = format!("## Files ({})\n", files.len());
+    for file in files.iter().take(200) {
+        survey.push_str(&format!("{}\n", file));
+    }
+    if files.len() > 200 {
+        survey.push_str(&format!("... and {} more\n", files.len() - 200));
+    }
+
+    let readme = files.iter().find(|file| file.to_lowercase() == "readme.md").map(String::as_str);
+    let workflows = files.iter().filter(|file| file.starts_with(".github/workflows/")).map(String::as_str);
+    let shown = SURVEY_MANIFESTS
+        .iter()
+        .copied()
+        .filter(|name| files.iter().any(|file| file == name))
+        .chain(readme)
+        .chain(workflows);
+    for file in shown {
+        let Ok(text) = fs::read_to_string(root.join(file)) else { continue };
+        let head: Vec<&str> = text.lines().take(SURVEY_FILE_LINES).collect();
+        survey.push_str(&format!("\n## {}\n{}\n", file, head.join("\n")));
+    }
+
+    let log = Command::new("git").arg("-C").arg(root).args(["log", "--oneline", "-15"]).output();
+    if let Some(log) = log.ok().filter(|log| log.status.success()) {
+        survey.push_str(&format!("\n## Recent commits\n{}", String::from_utf8_lossy(&log.stdout)));
+    }
+
+    let map = repo_map::build(root, &[], "");
+    if !map.is_empty() {
+        let map: String = map.chars().take(SURVEY_MAP_CHARS).collect();
+        survey.push_str(&format!("\n## Code map\n{}\n", map));
+    }
+    survey
+}
+
+pub fn init_prompt(survey: &str) -> String {
+    format!("{}\n\n{}", INIT_INSTRUCTIONS, survey)
+}
+
+/// `/init`: have `model` write KOTA.md from a survey of the repository, and start using it.
+/// An existing instructions file is only replaced with `force`. Returns the path written.
+pub async fn init(model: &ModelConfig, force: bool) -> Result<String> {
+    if !force {
+        if let Some(existing) = INSTRUCTION_FILES.iter().find(|name| Path::new(name).exists()) {
+            return Err(anyhow::anyhow!("{} already exists; run /init --force to replace it", existing));
+        }
+    }
+    let text = llm::ask_model_pinned(&init_prompt(&survey(Path::new("."))), model).await?;
+    let text = strip_fence(&text);
+    if text.is_empty() {
+        return Err(anyhow::anyhow!("The model returned an empty file; nothing was written"));
+    }
+    fs::write(INSTRUCTION_FILES[0], format!("{}\n", text)).with_context(|| format!("Failed to write {}", INSTRUCTION_FILES[0]))?;
+    reload();
+    Ok(INSTRUCTION_FILES[0].to_string())
+}
+
+/// Replies wrapped whole in a ```markdown fence, without it
+fn strip_fence(text: &str) -> &str {
+    let text = text.trim();
+    let Some(rest) = text.strip_prefix("```") else { return text };
+    match (rest.find('\n'), rest.strip_suffix("```")) {
+        (Some(start), Some(inner)) if start < inner.len() => inner[start..].trim(),
+        _ => text,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_read_prefers_kota_md() {
+        let dir = TempDir::new().unwrap();
+        assert!(read(dir.path()).is_none());
+        fs::create_dir_all(dir.path().join(".kota")).unwrap();
+        fs::write(dir.path().join(".kota/instructions.md"), "Use tabs.\n").unwrap();
+        assert_eq!(read(dir.path()), Some((".kota/instructions.md".to_string(), "Use tabs.".to_string())));
+        fs::write(dir.path().join("KOTA.md"), "  \n").unwrap();
+        assert_eq!(read(dir.path()).unwrap().0, ".kota/instructions.md");
+        fs::write(dir.path().join("KOTA.md"), "# Conventions\nRun cargo fmt.\n").unwrap();
+        assert_eq!(read(dir.path()), Some(("KOTA.md".to_string(), "# Conventions\nRun cargo fmt.".to_string())));
+    }
+
+    #[test]
+    fn test_survey_and_fences() {
+        let dir = TempDir::new().unwrap();
+        fs::create_dir_all(dir.path().join("src")).unwrap();
+        fs::write(dir.path().join("Cargo.toml"), "[package]\nname = \"demo\"\n").unwrap();
+        fs::write(dir.path().join("README.md"), "# Demo\n").unwrap();
+        fs::write(dir.path().join("src/main.rs"), "fn main() {}\n").unwrap();
+        let survey = survey(dir.path());
+        assert!(survey.starts_with("## Files (3)\n"));
+        assert!(survey.contains("## Cargo.toml\n[package]\nname = \"demo\""));
+        assert!(survey.contains("## README.md\n# Demo"));
+        assert!(init_prompt(&survey).starts_with(INIT_INSTRUCTIONS));
+
+        assert_eq!(strip_fence("```markdown\n# KOTA.md\n- Run cargo test\n```"), "# KOTA.md\n- Run cargo test");
+        assert_eq!(strip_fence("# KOTA.md\n```sh\ncargo test\n```"), "# KOTA.md\n```sh\ncargo test\n```");
+    }
+}
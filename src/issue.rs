@@ -0,0 +1,246 @@
+use std::process::Command;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use regex::Regex;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+
+const GITHUB_TIMEOUT_SECS: u64 = 30;
+// Long threads are cut to their latest comments
+const MAX_ISSUE_COMMENTS: usize = 30;
+
+/// An issue as `owner/repo#number`
+#[derive(Debug, Clone, PartialEq)]
+pub struct IssueRef {
+    pub owner: String,
+    pub repo: String,
+    pub number: u64,
+}
+
+impl IssueRef {
+    /// A GitHub issue URL, `owner/repo#12`, or `#12` / `12` in the repository `origin` points at
+    pub fn parse(spec: &str, origin: Option<&str>) -> Result<Self> {
+        let spec = spec.trim();
+        let full = Regex::new(r"^(?:https?://github\.com/)?([\w.-]+)/([\w.-]+)(?:#|/issues/|/pull/)(\d+)/?(?:[#?].*)?$").unwrap();
+        if let Some(captures) = full.captures(spec) {
+            return Ok(Self { owner: captures[1].to_string(), repo: captures[2].to_string(), number: captures[3].parse()? });
+        }
+        let number: u64 = spec
+            .trim_start_matches('#')
+            .parse()
+            .map_err(|_| anyhow::anyhow!("Not an issue URL, owner/repo#number or number: {}", spec))?;
+        let (owner, repo) = origin
+            .and_then(github_repo)
+            .ok_or_else(|| anyhow::anyhow!("The origin remote isn't on GitHub; use owner/repo#{}", number))?;
+        Ok(Self { owner, repo, number })
+    }
+
+    pub fn url(&self) -> String {
+        format!("https://github.com/{}/{}/issues/{}", self.owner, self.repo, self.number)
+    }
+}
+
+impl std::fmt::Display for IssueRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}#{}", self.owner, self.repo, self.number)
+    }
+}
+
+/// Owner and repository from a GitHub remote URL, SSH or HTTPS
+fn github_repo(remote: &str) -> Option<(String, String)> {
+    let remote = remote.trim();
+    let path = remote
+        .strip_prefix("git@github.com:")
+        .or_else(|| remote.strip_prefix("ssh://git@github.com/"))
+        .or_else(|| remote.strip_prefix("https://github.com/"))
+        .or_else(|| remote.strip_prefix("http://github.com/"))?;
+    let path = path.trim_end_matches('/').trim_end_matches(".git");
+    let (owner, repo) = path.split_once('/')?;
+    (!owner.is_empty() && !repo.is_empty() && !repo.contains('/')).then(|| (owner.to_string(), repo.to_string()))
+}
+
+pub fn origin_remote() -> Option<String> {
+    git(&["remote", "get-url", "origin"])
+}
+
+#[derive(Debug, Deserialize)]
+struct User {
+    login: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Issue {
+    pub title: String,
+    #[serde(default)]
+    pub body: Option<String>,
+    pub state: String,
+    user: User,
+    #[serde(skip)]
+    comments: Vec<Comment>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Comment {
+    user: User,
+    created_at: String,
+    #[serde(default)]
+    body: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PullRequest {
+    html_url: String,
+}
+
+fn client() -> Result<Client> {
+    Client::builder()
+        .timeout(Duration::from_secs(GITHUB_TIMEOUT_SECS))
+        .user_agent(format!("kota/{}", env!("CARGO_PKG_VERSION")))
+        .build()
+        .context("Failed to create HTTP client")
+}
+
+/// GET a GitHub API path, authenticated with GITHUB_TOKEN or GH_TOKEN when set so private
+/// repositories work
+async fn github_get<T: serde::de::DeserializeOwned>(client: &Client, path: &str) -> Result<T> {
+    let mut request = client
+        .get(format!("https://api.github.com{}", path))
+        .header("Accept", "application/vnd.github+json");
+    if let Some(token) = std::env::var("GITHUB_TOKEN").ok().or_else(|| std::env::var("GH_TOKEN").ok()) {
+        request = request.bearer_auth(token);
+    }
+    let response = request.send().await.context("Failed to reach GitHub")?;
+    if !response.status().is_success() {
+        let hint = if response.status().as_u16() == 404 { " (private repositories need GITHUB_TOKEN)" } else { "" };
+        return Err(anyhow::anyhow!("GitHub returned {} for {}{}", response.status(), path, hint));
+    }
+    response.json().await.with_context(|| format!("Failed to parse GitHub's reply for {}", path))
+}
+
+/// The issue with its latest comments
+pub async fn fetch(reference: &IssueRef) -> Result<Issue> {
+    let client = client()?;
+    let path = format!("/repos/{}/{}/issues/{}", reference.owner, reference.repo, reference.number);
+    let mut issue: Issue = github_get(&client, &path).await?;
+    let comments: Vec<Comment> = github_get(&client, &format!("{}/comments?per_page=100", path)).await?;
+    let skip = comments.len().saturating_sub(MAX_ISSUE_COMMENTS);
+    issue.comments = comments.into_iter().skip(skip).collect();
+    Ok(issue)
+}
+
+impl Issue {
+    /// The issue as a context snippet
+    pub fn to_context(&self, reference: &IssueRef) -> String {
+        let mut text = format!(
+            "GitHub issue {} ({}, opened by @{}): {}\n{}\n\n{}\n",
+            reference,
+            self.state,
+            self.user.login,
+            self.title,
+            reference.url(),
+            self.body.as_deref().map(str::trim).filter(|body| !body.is_empty()).unwrap_or("(no description)")
+        );
+        if !self.comments.is_empty() {
+            text.push_str(&format!("\nComments ({}):\n", self.comments.len()));
+            for comment in &self.comments {
+                let date = comment.created_at.split('T').next().unwrap_or(&comment.created_at);
+                text.push_str(&format!("\n@{} on {}:\n{}\n", comment.user.login, date, comment.body.as_deref().unwrap_or("").trim()));
+            }
+        }
+        text
+    }
+}
+
+/// The issue an agent session works on, and the branch and pull request that came of it,
+/// kept with the session
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IssueLink {
+    pub issue: String,
+    pub url: String,
+    #[serde(default)]
+    pub branch: Option<String>,
+    #[serde(default)]
+    pub pull_request: Option<String>,
+}
+
+impl IssueLink {
+    pub fn new(reference: &IssueRef) -> Self {
+        Self { issue: reference.to_string(), url: reference.url(), branch: None, pull_request: None }
+    }
+
+    /// Record the branch checked out now and look up a pull request opened from it
+    pub async fn refresh(&mut self) {
+        let Some(branch) = git(&["rev-parse", "--abbrev-ref", "HEAD"]).filter(|branch| branch != "HEAD") else { return };
+        self.branch = Some(branch.clone());
+        let Ok(reference) = IssueRef::parse(&self.issue, None) else { return };
+        let path = format!("/repos/{}/{}/pulls?state=all&head={}:{}", reference.owner, reference.repo, reference.owner, branch);
+        let found = match client() {
+            Ok(client) => github_get::<Vec<PullRequest>>(&client, &path).await,
+            Err(e) => Err(e),
+        };
+        match found {
+            Ok(pulls) => {
+                if let Some(pull) = pulls.into_iter().next() {
+                    self.pull_request = Some(pull.html_url);
+                }
+            }
+            Err(e) => eprintln!("Warning: Couldn't look up a pull request for {}: {}", branch, e),
+        }
+    }
+
+    pub fn summary(&self) -> String {
+        let mut summary = self.issue.clone();
+        if let Some(branch) = &self.branch {
+            summary.push_str(&format!(", branch {}", branch));
+        }
+        if let Some(pull_request) = &self.pull_request {
+            summary.push_str(&format!(", PR {}", pull_request));
+        }
+        summary
+    }
+}
+
+fn git(args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).output().ok()?;
+    output.status.success().then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_issue_refs() {
+        let expected = IssueRef { owner: "jayminwest".to_string(), repo: "kota-rust-cli".to_string(), number: 42 };
+        assert_eq!(IssueRef::parse("https://github.com/jayminwest/kota-rust-cli/issues/42", None).unwrap(), expected);
+        assert_eq!(IssueRef::parse("https://github.com/jayminwest/kota-rust-cli/issues/42#issuecomment-1", None).unwrap(), expected);
+        assert_eq!(IssueRef::parse("jayminwest/kota-rust-cli#42", None).unwrap(), expected);
+        assert_eq!(IssueRef::parse("#42", Some("git@github.com:jayminwest/kota-rust-cli.git")).unwrap(), expected);
+        assert_eq!(IssueRef::parse("42", Some("https://github.com/jayminwest/kota-rust-cli")).unwrap(), expected);
+        assert!(IssueRef::parse("42", Some("https://gitlab.com/a/b.git")).is_err());
+        assert!(IssueRef::parse("fix the bug", None).is_err());
+        assert_eq!(expected.to_string(), "jayminwest/kota-rust-cli#42");
+    }
+
+    #[test]
+    fn test_issue_context_and_link() {
+        let reference = IssueRef { owner: "o".to_string(), repo: "r".to_string(), number: 7 };
+        let issue = Issue {
+            title: "Crash on empty input".to_string(),
+            body: Some("Steps: press enter\n".to_string()),
+            state: "open".to_string(),
+            user: User { login: "ana".to_string() },
+            comments: vec![Comment { user: User { login: "bo".to_string() }, created_at: "2024-05-01T10:00:00Z".to_string(), body: Some("Same here".to_string()) }],
+        };
+        let context = issue.to_context(&reference);
+        assert!(context.starts_with("GitHub issue o/r#7 (open, opened by @ana): Crash on empty input\nhttps://github.com/o/r/issues/7\n\nSteps: press enter\n"));
+        assert!(context.ends_with("Comments (1):\n\n@bo on 2024-05-01:\nSame here\n"));
+
+        let mut link = IssueLink::new(&reference);
+        assert_eq!(link.summary(), "o/r#7");
+        link.branch = Some("fix-7".to_string());
+        link.pull_request = Some("https://github.com/o/r/pull/8".to_string());
+        assert_eq!(link.summary(), "o/r#7, branch fix-7, PR https://github.com/o/r/pull/8");
+    }
+}
@@ -0,0 +1,201 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::sync::Mutex;
+
+use crate::prompts::LintConfig;
+
+// Lines of a failing command's output kept; the first errors are what matter
+const MAX_OUTPUT_LINES: usize = 40;
+
+// What sh exits with when the program isn't installed
+const NOT_FOUND: i32 = 127;
+
+/// A formatter or check that failed on the edited files
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finding {
+    pub command: String,
+    pub output: String,
+}
+
+/// What running the formatters and checks did
+#[derive(Debug, Default)]
+pub struct LintRun {
+    /// Files a formatter changed
+    pub formatted: Vec<String>,
+    pub findings: Vec<Finding>,
+}
+
+impl LintRun {
+    /// Note added to context so the next turn can fix what the checks found
+    pub fn context_note(&self) -> Option<String> {
+        if self.findings.is_empty() {
+            return None;
+        }
+        let mut note = String::from("Formatter and lint errors in the files the last edits changed. Fix them with SEARCH/REPLACE blocks:\n");
+        for finding in &self.findings {
+            note.push_str(&format!("\n$ {}\n{}\n", finding.command, finding.output));
+        }
+        Some(note)
+    }
+}
+
+/// The formatter for files with `extension` when `[lint] formatters` doesn't name one
+fn detected_formatter(extension: &str) -> Option<&'static str> {
+    match extension {
+        "rs" => Some("rustfmt --edition 2021 {files}"),
+        "py" | "pyi" => Some("black -q {files}"),
+        "js" | "jsx" | "mjs" | "cjs" | "ts" | "tsx" | "css" | "scss" | "json" | "html" | "md" | "yaml" | "yml" => {
+            Some("prettier --write --log-level warn {files}")
+        }
+        _ => None,
+    }
+}
+
+/// The check for files with `extension` when `[lint] checkers` doesn't name one. Project-wide
+/// checks only apply where the project has their manifest.
+fn detected_checker(extension: &str) -> Option<&'static str> {
+    match extension {
+        "rs" if Path::new("Cargo.toml").exists() => Some("cargo check --quiet --message-format short"),
+        "ts" | "tsx" if Path::new("tsconfig.json").exists() => Some("tsc --noEmit --pretty false"),
+        _ => None,
+    }
+}
+
+/// `files` grouped under the command that handles them, with configured commands taking
+/// precedence over detected ones. An empty configured command turns the tool off for that
+/// extension. A command without `{files}` runs once for all of its files.
+fn plan(files: &[String], configured: &BTreeMap<String, String>, detected: fn(&str) -> Option<&'static str>) -> BTreeMap<String, Vec<String>> {
+    let mut commands: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for file in files {
+        let Some(extension) = Path::new(file).extension().and_then(|e| e.to_str()).map(str::to_lowercase) else {
+            continue;
+        };
+        let command = match configured.get(&extension) {
+            Some(command) => command.trim().to_string(),
+            None => detected(&extension).unwrap_or_default().to_string(),
+        };
+        if !command.is_empty() {
+            commands.entry(command).or_default().push(file.clone());
+        }
+    }
+    commands
+}
+
+fn shell_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
+
+/// Run `template` on `files` through sh. None when its program isn't installed, otherwise
+/// the command line and, if it failed, its output.
+fn run(template: &str, files: &[String]) -> Option<(String, Option<String>)> {
+    let quoted: Vec<String> = files.iter().map(|file| shell_quote(file)).collect();
+    let command = template.replace("{files}", &quoted.join(" "));
+    let output = match Command::new("sh").arg("-c").arg(&command).output() {
+        Ok(output) => output,
+        Err(e) => return Some((command, Some(format!("Failed to run: {}", e)))),
+    };
+    if output.status.code() == Some(NOT_FOUND) {
+        return None;
+    }
+    if output.status.success() {
+        return Some((command, None));
+    }
+    let text = format!("{}{}", String::from_utf8_lossy(&output.stdout), String::from_utf8_lossy(&output.stderr));
+    let lines: Vec<&str> = text.trim().lines().collect();
+    let mut kept = lines.iter().take(MAX_OUTPUT_LINES).copied().collect::<Vec<_>>().join("\n");
+    if lines.len() > MAX_OUTPUT_LINES {
+        kept.push_str(&format!("\n... ({} more lines)", lines.len() - MAX_OUTPUT_LINES));
+    }
+    Some((command, Some(kept)))
+}
+
+/// Format and check the files the edits touched, as `[lint]` configures. Tools that aren't
+/// installed are skipped.
+pub fn run_all(files: &[String], config: &LintConfig) -> LintRun {
+    let mut result = LintRun::default();
+    let files: Vec<String> = files.iter().filter(|file| Path::new(file.as_str()).is_file()).cloned().collect();
+    if files.is_empty() {
+        return result;
+    }
+
+    if config.format {
+        for (template, files) in plan(&files, &config.formatters, detected_formatter) {
+            let before: Vec<Option<String>> = files.iter().map(|file| fs::read_to_string(file).ok()).collect();
+            let Some((command, failure)) = run(&template, &files) else {
+                continue;
+            };
+            for (file, before) in files.iter().zip(before) {
+                if fs::read_to_string(file).ok() != before {
+                    result.formatted.push(file.clone());
+                }
+            }
+            if let Some(output) = failure {
+                result.findings.push(Finding { command, output });
+            }
+        }
+    }
+    if config.check {
+        for (template, files) in plan(&files, &config.checkers, detected_checker) {
+            if let Some((command, Some(output))) = run(&template, &files) {
+                result.findings.push(Finding { command, output });
+            }
+        }
+    }
+    result
+}
+
+// Lint errors from applying edits in the CLI, added to context once the edits are recorded
+static PENDING_NOTE: Mutex<Option<String>> = Mutex::new(None);
+
+pub fn defer_note(note: String) {
+    *PENDING_NOTE.lock().unwrap_or_else(|e| e.into_inner()) = Some(note);
+}
+
+pub fn take_note() -> Option<String> {
+    PENDING_NOTE.lock().unwrap_or_else(|e| e.into_inner()).take()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_plan_prefers_configured_commands() {
+        let files = vec!["src/a.py".to_string(), "b.PY".to_string(), "c.txt".to_string(), "d.rs".to_string()];
+        let configured = BTreeMap::from([("rs".to_string(), "".to_string())]);
+        let plan = plan(&files, &configured, detected_formatter);
+        assert_eq!(plan.len(), 1);
+        assert_eq!(plan["black -q {files}"], vec!["src/a.py".to_string(), "b.PY".to_string()]);
+
+        let configured = BTreeMap::from([("rs".to_string(), "cargo clippy".to_string())]);
+        let plan = super::plan(&files, &configured, |_| None);
+        assert_eq!(plan["cargo clippy"], vec!["d.rs".to_string()]);
+        assert_eq!(shell_quote("it's.rs"), "'it'\\''s.rs'");
+    }
+
+    #[test]
+    fn test_run_all_formats_and_reports() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.path().join("a b.txt").to_string_lossy().to_string();
+        fs::write(&file, "hello\n").unwrap();
+        let config = LintConfig {
+            enabled: true,
+            format: true,
+            check: true,
+            formatters: BTreeMap::from([("txt".to_string(), "printf 'hi\\n' > {files}".to_string())]),
+            checkers: BTreeMap::from([("txt".to_string(), "echo 'a.txt:1: too short' && false".to_string())]),
+        };
+        let run = run_all(&[file.clone(), "missing.txt".to_string()], &config);
+        assert_eq!(run.formatted, vec![file.clone()]);
+        assert_eq!(fs::read_to_string(&file).unwrap(), "hi\n");
+        assert_eq!(run.findings.len(), 1);
+        assert_eq!(run.findings[0].output, "a.txt:1: too short");
+        assert!(run.context_note().unwrap().contains("$ echo 'a.txt:1: too short' && false"));
+
+        let config = LintConfig { checkers: BTreeMap::from([("txt".to_string(), "no-such-linter-here {files}".to_string())]), ..config };
+        assert!(run_all(&[file], &config).findings.is_empty());
+    }
+}
@@ -1,13 +1,38 @@
-use reqwest::ClientBuilder;
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use anyhow::Context;
 use std::time::Duration;
 use gemini_client_api::gemini::{
     ask::Gemini,
     types::sessions::Session,
 };
-use crate::prompts::PromptsConfig;
+use crate::prompts::{CacheConfig, EditFormat, PromptsConfig};
+use crate::prompt_pipeline::{AssembledPrompt, PromptInput, PromptPipeline};
+use crate::usage::{self, TokenUsage};
+use crate::secrets;
+use crate::trace::{self, SpanExt};
 use tokio::time::timeout;
+use futures::StreamExt;
+
+pub mod cache;
+pub mod connection;
+pub mod context_window;
+pub mod conversation;
+pub mod embeddings;
+pub mod enterprise;
+pub mod fast;
+pub mod request_log;
+pub mod retry;
+pub mod rate_limit;
+pub mod replay;
+pub mod router;
+pub mod structured;
+pub mod tools;
+pub mod verify;
+
+use cache::{CacheKey, ResponseCache};
+use conversation::Turn;
+use retry::{with_retry, HttpStatusError};
+use tools::ToolResponse;
 
 #[derive(Debug, Clone)]
 #[derive(Default)]
@@ -16,12 +41,156 @@ pub enum LlmProvider {
     Gemini,
     #[default]
     Anthropic,
+    OpenRouter,
+    /// Azure OpenAI deployment from `[providers.azure]`
+    Azure,
+    /// Claude on AWS Bedrock, configured under `[providers.bedrock]`
+    Bedrock,
+    /// Fast open-model inference on Groq
+    Groq,
+    /// Fast open-model inference on Together.ai
+    Together,
+}
+
+impl LlmProvider {
+    /// Parse a provider name as typed in commands and prompts.toml
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "ollama" => Some(Self::Ollama),
+            "gemini" => Some(Self::Gemini),
+            "anthropic" | "claude" => Some(Self::Anthropic),
+            "openrouter" => Some(Self::OpenRouter),
+            "azure" | "azure-openai" => Some(Self::Azure),
+            "bedrock" => Some(Self::Bedrock),
+            "groq" => Some(Self::Groq),
+            "together" | "together.ai" | "togetherai" => Some(Self::Together),
+            _ => None,
+        }
+    }
+    
+    /// Lowercase name, as accepted by `from_name`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Ollama => "ollama",
+            Self::Gemini => "gemini",
+            Self::Anthropic => "anthropic",
+            Self::OpenRouter => "openrouter",
+            Self::Azure => "azure",
+            Self::Bedrock => "bedrock",
+            Self::Groq => "groq",
+            Self::Together => "together",
+        }
+    }
 }
 
 #[derive(Debug, Clone, Default)]
 pub struct ModelConfig {
     pub provider: LlmProvider,
     pub model_name: Option<String>,
+    /// Ask for edits and commands through the provider's function calling instead of text blocks
+    pub native_tools: bool,
+    /// Tokens the model accepts, when known from the provider's catalog; otherwise a per-model default
+    pub context_window: Option<u64>,
+    /// Set with /temp, /top_p and /max_tokens; kept across provider and model switches
+    pub sampling: SamplingParams,
+    /// With /verify on, this model checks and corrects the edits this one drafts before they're applied
+    pub verify_model: Option<Box<ModelConfig>>,
+}
+
+/// Reply length when neither the session nor prompts.toml sets max_tokens
+pub const DEFAULT_MAX_TOKENS: u32 = 4096;
+
+/// Plain text, or JSON through the provider's JSON mode where it has one
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ResponseFormat {
+    Text,
+    Json,
+}
+
+/// Sampling settings for a request. Unset values fall back to `[sampling]` in prompts.toml,
+/// then to the provider's own defaults.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SamplingParams {
+    pub temperature: Option<f64>,
+    pub top_p: Option<f64>,
+    pub max_tokens: Option<u32>,
+    /// Asks for the same reply to the same request, from providers that take a seed
+    pub seed: Option<u64>,
+}
+
+impl SamplingParams {
+    /// These values, with unset ones taken from `defaults`
+    pub fn or(&self, defaults: &SamplingParams) -> SamplingParams {
+        SamplingParams {
+            temperature: self.temperature.or(defaults.temperature),
+            top_p: self.top_p.or(defaults.top_p),
+            max_tokens: self.max_tokens.or(defaults.max_tokens),
+            seed: self.seed.or(defaults.seed),
+        }
+    }
+
+    pub fn is_default(&self) -> bool {
+        *self == SamplingParams::default()
+    }
+
+    /// Short form for status lines, e.g. "temp 0.2 top_p 0.9"; empty when nothing is set
+    pub fn summary(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(temperature) = self.temperature {
+            parts.push(format!("temp {}", temperature));
+        }
+        if let Some(top_p) = self.top_p {
+            parts.push(format!("top_p {}", top_p));
+        }
+        if let Some(max_tokens) = self.max_tokens {
+            parts.push(format!("max {}", max_tokens));
+        }
+        if let Some(seed) = self.seed {
+            parts.push(format!("seed {}", seed));
+        }
+        parts.join(" ")
+    }
+
+    /// Add the set values to an Anthropic or OpenAI-style request body. Anthropic and Bedrock
+    /// refuse a seed, so OpenAI-style bodies add it with `apply_seed`.
+    fn apply_to(&self, payload: &mut serde_json::Value) {
+        if let Some(temperature) = self.temperature {
+            payload["temperature"] = serde_json::json!(temperature);
+        }
+        if let Some(top_p) = self.top_p {
+            payload["top_p"] = serde_json::json!(top_p);
+        }
+        if let Some(max_tokens) = self.max_tokens {
+            payload["max_tokens"] = serde_json::json!(max_tokens);
+        }
+    }
+
+    fn apply_seed(&self, payload: &mut serde_json::Value) {
+        if let Some(seed) = self.seed {
+            payload["seed"] = serde_json::json!(seed);
+        }
+    }
+
+    /// Ollama takes these as model options, with max_tokens called num_predict
+    fn ollama_options(&self) -> Option<serde_json::Value> {
+        if self.is_default() {
+            return None;
+        }
+        let mut options = serde_json::json!({});
+        if let Some(temperature) = self.temperature {
+            options["temperature"] = serde_json::json!(temperature);
+        }
+        if let Some(top_p) = self.top_p {
+            options["top_p"] = serde_json::json!(top_p);
+        }
+        if let Some(max_tokens) = self.max_tokens {
+            options["num_predict"] = serde_json::json!(max_tokens);
+        }
+        if let Some(seed) = self.seed {
+            options["seed"] = serde_json::json!(seed);
+        }
+        Some(options)
+    }
 }
 
 impl ModelConfig {
@@ -33,22 +202,69 @@ impl ModelConfig {
                 LlmProvider::Ollama => DEFAULT_OLLAMA_MODEL.to_string(),
                 LlmProvider::Gemini => DEFAULT_GEMINI_MODEL.to_string(),
                 LlmProvider::Anthropic => DEFAULT_ANTHROPIC_MODEL.to_string(),
+                LlmProvider::OpenRouter => DEFAULT_OPENROUTER_MODEL.to_string(),
+                LlmProvider::Azure => DEFAULT_AZURE_DEPLOYMENT.to_string(),
+                LlmProvider::Bedrock => DEFAULT_BEDROCK_MODEL.to_string(),
+                LlmProvider::Groq => fast::DEFAULT_GROQ_MODEL.to_string(),
+                LlmProvider::Together => fast::DEFAULT_TOGETHER_MODEL.to_string(),
             }
         }
     }
 
+    pub fn context_window(&self) -> u64 {
+        self.context_window
+            .unwrap_or_else(|| context_window::default_context_window(&self.provider, &self.get_model_name()))
+    }
+
+    /// Cheaper, faster model used for internal housekeeping calls
+    pub fn get_fast_model_name(&self) -> String {
+        match self.provider {
+            // Ollama runs locally, so there is no cost benefit to switching models;
+            // Azure deployments are named by whoever set them up, so there's no known cheaper one
+            LlmProvider::Ollama | LlmProvider::Azure => self.get_model_name(),
+            LlmProvider::Gemini => GEMINI_COMMIT_MODEL.to_string(),
+            LlmProvider::Anthropic => ANTHROPIC_FAST_MODEL.to_string(),
+            LlmProvider::OpenRouter => OPENROUTER_FAST_MODEL.to_string(),
+            LlmProvider::Bedrock => BEDROCK_FAST_MODEL.to_string(),
+            LlmProvider::Groq => fast::GROQ_FAST_MODEL.to_string(),
+            LlmProvider::Together => fast::TOGETHER_FAST_MODEL.to_string(),
+        }
+    }
+
+    /// Whether this model is asked for search/replace blocks or unified diffs
+    pub fn edit_format(&self) -> EditFormat {
+        PromptsConfig::load().unwrap_or_default().edits.format_for(self.provider.name(), &self.get_model_name())
+    }
+
     pub fn display_name(&self) -> String {
         let model = self.get_model_name();
         match self.provider {
             LlmProvider::Ollama => format!("Ollama/{}", model),
             LlmProvider::Gemini => format!("Gemini/{}", model),
             LlmProvider::Anthropic => format!("Claude/{}", model),
+            LlmProvider::OpenRouter => format!("OpenRouter/{}", model),
+            LlmProvider::Azure => format!("Azure/{}", model),
+            LlmProvider::Bedrock => format!("Bedrock/{}", model),
+            LlmProvider::Groq => format!("Groq/{}", model),
+            LlmProvider::Together => format!("Together/{}", model),
         }
     }
 }
 
+/// The deployment or model set under `[providers]` for Azure and Bedrock, used when
+/// switching to them; other providers have a fixed default model
+pub fn configured_model(provider: &LlmProvider) -> Option<String> {
+    let providers = PromptsConfig::load().unwrap_or_default().providers;
+    match provider {
+        LlmProvider::Azure => providers.azure.deployment,
+        LlmProvider::Bedrock => providers.bedrock.model,
+        _ => None,
+    }
+    .filter(|model| !model.trim().is_empty())
+}
 
-// Structs for Ollama's /api/chat endpoint (non-streaming)
+
+// Structs for Ollama's /api/chat endpoint
 #[derive(Serialize)]
 struct OllamaChatMessage {
     role: String,
@@ -65,6 +281,23 @@ struct AnthropicMessage {
 #[derive(Deserialize)]
 struct AnthropicResponse {
     content: Vec<AnthropicContent>,
+    #[serde(default)]
+    usage: Option<AnthropicUsage>,
+}
+
+#[derive(Deserialize)]
+struct AnthropicUsage {
+    input_tokens: u64,
+    output_tokens: u64,
+}
+
+impl From<AnthropicUsage> for TokenUsage {
+    fn from(usage: AnthropicUsage) -> Self {
+        TokenUsage {
+            prompt_tokens: usage.input_tokens,
+            completion_tokens: usage.output_tokens,
+        }
+    }
 }
 
 #[derive(Deserialize)]
@@ -79,25 +312,49 @@ struct OllamaChatRequest {
     model: String,
     messages: Vec<OllamaChatMessage>,
     stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    options: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    format: Option<String>,
 }
 
 #[derive(Deserialize)]
 struct OllamaChatResponse {
     message: OllamaResponseMessage,
+    #[serde(default)]
+    prompt_eval_count: Option<u64>,
+    #[serde(default)]
+    eval_count: Option<u64>,
     // Add other fields if needed like done, total_duration, etc.
 }
 
+impl OllamaChatResponse {
+    fn token_usage(&self) -> Option<TokenUsage> {
+        ollama_token_usage(self.prompt_eval_count, self.eval_count)
+    }
+}
+
 #[derive(Deserialize)]
 struct OllamaResponseMessage {
     content: String,
 }
 
 const OLLAMA_API_URL: &str = "http://localhost:11434/api/chat";
+const OLLAMA_TAGS_URL: &str = "http://localhost:11434/api/tags";
+const OLLAMA_PULL_URL: &str = "http://localhost:11434/api/pull";
 const DEFAULT_OLLAMA_MODEL: &str = "qwen3:8b";
 const DEFAULT_GEMINI_MODEL: &str = "gemini-2.5-pro-preview-05-06";
 const GEMINI_COMMIT_MODEL: &str = "gemini-2.5-flash-preview-05-20";
 const DEFAULT_ANTHROPIC_MODEL: &str = "claude-sonnet-4-20250514";
+const ANTHROPIC_FAST_MODEL: &str = "claude-3-5-haiku-20241022";
 const ANTHROPIC_API_URL: &str = "https://api.anthropic.com/v1/messages";
+const DEFAULT_OPENROUTER_MODEL: &str = "anthropic/claude-sonnet-4";
+const OPENROUTER_FAST_MODEL: &str = "anthropic/claude-3.5-haiku";
+const OPENROUTER_API_URL: &str = "https://openrouter.ai/api/v1/chat/completions";
+const OPENROUTER_MODELS_URL: &str = "https://openrouter.ai/api/v1/models";
+const DEFAULT_AZURE_DEPLOYMENT: &str = "gpt-4o";
+const DEFAULT_BEDROCK_MODEL: &str = "us.anthropic.claude-sonnet-4-20250514-v1:0";
+const BEDROCK_FAST_MODEL: &str = "us.anthropic.claude-3-5-haiku-20241022-v1:0";
 
 // Timeout configuration
 // Ollama: 120 seconds for main requests, 60 seconds for commits
@@ -105,70 +362,577 @@ const ANTHROPIC_API_URL: &str = "https://api.anthropic.com/v1/messages";
 // Anthropic: 240 seconds for main requests (2x Ollama), 120 seconds for commits
 const GEMINI_TIMEOUT_SECS: u64 = 360;
 const ANTHROPIC_TIMEOUT_SECS: u64 = 240;
+const OPENROUTER_TIMEOUT_SECS: u64 = 240;
+
 
 
+/// Rough token count for budgeting; about four characters per token for English and code
+pub fn estimate_tokens(text: &str) -> u64 {
+    (text.len() as u64).div_ceil(4)
+}
 
 pub async fn ask_model_with_config(user_prompt: &str, context_str: &str, config: &ModelConfig) -> anyhow::Result<String> {
     let prompts_config = PromptsConfig::load().unwrap_or_default();
+    let config = &router::route(config, context_str, &prompts_config)?;
+    let model_name = config.get_model_name();
+    
+    let sampling = config.sampling.or(&prompts_config.sampling);
+    let prompt = prepare_prompt(&prompts_config, config, user_prompt, context_str).await?;
+    let (user_prompt, context_str, system_instructions) = (prompt.user.as_str(), prompt.context.as_str(), prompt.system.as_str());
+    let history = prompt.history.as_slice();
+    let exchange = request_log::Exchange::new("chat", config, &model_name, &prompt);
+    
+    let key = CacheKey::new(&config.provider, &sampled_model_key(&model_name, &sampling), system_instructions, user_prompt, &keyed_context(context_str, history));
+    if let Some(response) = cached_response(&prompts_config.cache, &key) {
+        exchange.record_cached(&response);
+        replay::record("chat", &config.provider, &model_name, &sampling, &prompt, Some(&response), true);
+        return Ok(response);
+    }
+    
+    let span = trace::span("llm.request").with("provider", config.provider.name()).with("model", model_name.clone());
+    let response = match config.provider {
+        LlmProvider::Ollama => ask_ollama_model(user_prompt, context_str, system_instructions, history, &model_name, &sampling, ResponseFormat::Text, None).await,
+        LlmProvider::Gemini => ask_gemini_model(user_prompt, context_str, system_instructions, history, &model_name).await,
+        LlmProvider::Anthropic => ask_anthropic_model(user_prompt, context_str, system_instructions, history, &model_name, &sampling, None).await,
+        LlmProvider::OpenRouter => ask_openrouter_model(user_prompt, context_str, system_instructions, history, &model_name, &sampling, ResponseFormat::Text, None).await,
+        LlmProvider::Groq | LlmProvider::Together => fast::ask_fast_model(&config.provider, user_prompt, context_str, system_instructions, history, &model_name, &sampling, ResponseFormat::Text, None).await,
+        LlmProvider::Azure => enterprise::ask_azure_model(user_prompt, context_str, system_instructions, history, &model_name, &sampling, ResponseFormat::Text, None).await,
+        LlmProvider::Bedrock => enterprise::ask_bedrock_model(user_prompt, context_str, system_instructions, history, &model_name, &sampling).await,
+    };
+    drop(span);
+    exchange.record(response.as_deref());
+    replay::record("chat", &config.provider, &model_name, &sampling, &prompt, response.as_deref().ok(), false);
+    let response = response?;
+    
+    cache_response(&prompts_config.cache, &key, &response);
+    Ok(response)
+}
+
+/// Like `ask_model_with_config`, but hands each piece of the response to `on_chunk` as it
+/// arrives. Returns the full response. Gemini responses arrive as a single chunk.
+/// The exchange becomes part of the conversation history sent with later requests.
+/// Like `ask_model_with_tools`, it goes to the `[llm.routing]` model for the kind of request.
+pub async fn ask_model_streaming<F>(user_prompt: &str, context_str: &str, config: &ModelConfig, mut on_chunk: F) -> anyhow::Result<String>
+where
+    F: FnMut(&str) + Send,
+{
+    let prompts_config = PromptsConfig::load().unwrap_or_default();
+    let config = &router::route(&router::route_task(config, user_prompt, &prompts_config.llm.routing), context_str, &prompts_config)?;
+    let model_name = config.get_model_name();
+    
+    let sampling = config.sampling.or(&prompts_config.sampling);
+    let prompt = prepare_prompt(&prompts_config, config, user_prompt, context_str).await?;
+    let (user_prompt, context_str, system_instructions) = (prompt.user.as_str(), prompt.context.as_str(), prompt.system.as_str());
+    let history = prompt.history.as_slice();
+    let exchange = request_log::Exchange::new("stream", config, &model_name, &prompt);
+    
+    // Cached responses are delivered as a single chunk
+    let key = CacheKey::new(&config.provider, &sampled_model_key(&model_name, &sampling), system_instructions, user_prompt, &keyed_context(context_str, history));
+    if let Some(response) = cached_response(&prompts_config.cache, &key) {
+        exchange.record_cached(&response);
+        replay::record("stream", &config.provider, &model_name, &sampling, &prompt, Some(&response), true);
+        conversation::record(user_prompt, &response);
+        on_chunk(&response);
+        return Ok(response);
+    }
+    
+    let span = trace::span("llm.stream").with("provider", config.provider.name()).with("model", model_name.clone());
+    let response = match config.provider {
+        LlmProvider::Ollama => ask_ollama_model(user_prompt, context_str, system_instructions, history, &model_name, &sampling, ResponseFormat::Text, Some(&mut on_chunk)).await,
+        LlmProvider::Gemini => {
+            let response = ask_gemini_model(user_prompt, context_str, system_instructions, history, &model_name).await?;
+            on_chunk(&response);
+            Ok(response)
+        }
+        LlmProvider::Anthropic => ask_anthropic_model(user_prompt, context_str, system_instructions, history, &model_name, &sampling, Some(&mut on_chunk)).await,
+        LlmProvider::OpenRouter => ask_openrouter_model(user_prompt, context_str, system_instructions, history, &model_name, &sampling, ResponseFormat::Text, Some(&mut on_chunk)).await,
+        LlmProvider::Groq | LlmProvider::Together => fast::ask_fast_model(&config.provider, user_prompt, context_str, system_instructions, history, &model_name, &sampling, ResponseFormat::Text, Some(&mut on_chunk)).await,
+        LlmProvider::Azure => enterprise::ask_azure_model(user_prompt, context_str, system_instructions, history, &model_name, &sampling, ResponseFormat::Text, Some(&mut on_chunk)).await,
+        LlmProvider::Bedrock => {
+            let response = enterprise::ask_bedrock_model(user_prompt, context_str, system_instructions, history, &model_name, &sampling).await?;
+            on_chunk(&response);
+            Ok(response)
+        }
+    };
+    drop(span);
+    exchange.record(response.as_deref());
+    replay::record("stream", &config.provider, &model_name, &sampling, &prompt, response.as_deref().ok(), false);
+    let draft = response?;
+    
+    // A corrected draft follows the streamed one
+    let response = verify_draft(draft.clone(), user_prompt, context_str, config, &prompts_config).await;
+    if response != draft {
+        let verifier = config.verify_model.as_deref().map(ModelConfig::display_name).unwrap_or_default();
+        on_chunk(&format!("\n\n---\nCorrected by {}:\n\n{}", verifier, response));
+    }
+    
+    conversation::record(user_prompt, &response);
+    cache_response(&prompts_config.cache, &key, &response);
+    Ok(response)
+}
+
+/// Have `config.verify_model` check the edits in a drafted response. Returns the corrected
+/// response, or the draft when there's no verifier, no edits, the verifier approves, or
+/// the verifier can't be reached.
+async fn verify_draft(draft: String, user_prompt: &str, context_str: &str, config: &ModelConfig, prompts_config: &PromptsConfig) -> String {
+    let Some(verifier) = config.verify_model.as_deref() else {
+        return draft;
+    };
+    if !verify::has_edits(&draft) {
+        return draft;
+    }
+    match ask_verifier(&draft, user_prompt, context_str, verifier, prompts_config).await {
+        Ok(reply) => verify::accept(draft, &reply),
+        Err(e) => {
+            eprintln!("Warning: {} couldn't verify the draft: {}. Using it unchecked", verifier.display_name(), e);
+            draft
+        }
+    }
+}
+
+async fn ask_verifier(draft: &str, user_prompt: &str, context_str: &str, verifier: &ModelConfig, prompts_config: &PromptsConfig) -> anyhow::Result<String> {
+    let config = &router::route(verifier, context_str, prompts_config)?;
+    let model_name = config.get_model_name();
+    let sampling = config.sampling.or(&prompts_config.sampling);
+    let request = AssembledPrompt {
+        system: verify::VERIFY_INSTRUCTIONS.to_string(),
+        context: context_str.to_string(),
+        user: verify::verify_prompt(user_prompt, draft),
+        ..Default::default()
+    };
+    let (user_prompt, system_instructions) = (request.user.as_str(), request.system.as_str());
+    let exchange = request_log::Exchange::new("verify", config, &model_name, &request);
+    
+    let response = match config.provider {
+        LlmProvider::Ollama => ask_ollama_model(user_prompt, context_str, system_instructions, &[], &model_name, &sampling, ResponseFormat::Text, None).await,
+        LlmProvider::Gemini => ask_gemini_model(user_prompt, context_str, system_instructions, &[], &model_name).await,
+        LlmProvider::Anthropic => ask_anthropic_model(user_prompt, context_str, system_instructions, &[], &model_name, &sampling, None).await,
+        LlmProvider::OpenRouter => ask_openrouter_model(user_prompt, context_str, system_instructions, &[], &model_name, &sampling, ResponseFormat::Text, None).await,
+        LlmProvider::Groq | LlmProvider::Together => fast::ask_fast_model(&config.provider, user_prompt, context_str, system_instructions, &[], &model_name, &sampling, ResponseFormat::Text, None).await,
+        LlmProvider::Azure => enterprise::ask_azure_model(user_prompt, context_str, system_instructions, &[], &model_name, &sampling, ResponseFormat::Text, None).await,
+        LlmProvider::Bedrock => enterprise::ask_bedrock_model(user_prompt, context_str, system_instructions, &[], &model_name, &sampling).await,
+    };
+    exchange.record(response.as_deref());
+    response
+}
+
+/// Send a bare prompt (no KOTA system instructions or context) to the provider's
+/// fastest model, or to the `[providers] quick` model when one is set. Used for cheap
+/// internal calls like classification and routing.
+pub async fn ask_model_quick(prompt: &str, config: &ModelConfig) -> anyhow::Result<String> {
+    let prompts_config = PromptsConfig::load().unwrap_or_default();
+    if let Some(quick) = router::quick_model(&prompts_config) {
+        match ask_quick_with(prompt, &quick, &prompts_config, true).await {
+            Ok(response) => return Ok(response),
+            Err(e) => eprintln!("Warning: Quick request to {} failed: {}. Using {} instead", quick.display_name(), e, config.display_name()),
+        }
+    }
+    ask_quick_with(prompt, config, &prompts_config, false).await
+}
+
+/// Like `ask_model_quick`, but always with the model `config` names
+pub async fn ask_model_pinned(prompt: &str, config: &ModelConfig) -> anyhow::Result<String> {
+    let prompts_config = PromptsConfig::load().unwrap_or_default();
+    ask_quick_with(prompt, config, &prompts_config, true).await
+}
+
+/// `pinned` keeps the model `config` names instead of switching to the provider's fast one.
+/// Callers put files and diffs in the prompt itself, so it's routed on and reviewed for
+/// secrets like context is.
+async fn ask_quick_with(prompt: &str, config: &ModelConfig, prompts_config: &PromptsConfig, pinned: bool) -> anyhow::Result<String> {
+    let config = &router::route(config, prompt, prompts_config)?;
+    let cache_config = &prompts_config.cache;
+    let model_name = if pinned { config.get_model_name() } else { config.get_fast_model_name() };
+    
+    let mut request = AssembledPrompt { user: prompt.to_string(), ..Default::default() };
+    if !matches!(config.provider, LlmProvider::Ollama) {
+        request = secrets::review_prompt(request, &prompts_config.secrets).await?;
+    }
+    let prompt = request.user.as_str();
+    let exchange = request_log::Exchange::new("quick", config, &model_name, &request);
+    
+    let key = CacheKey::new(&config.provider, &model_name, "", prompt, "");
+    if let Some(response) = cached_response(cache_config, &key) {
+        exchange.record_cached(&response);
+        return Ok(response);
+    }
+    
+    let response = match config.provider {
+        LlmProvider::Ollama => ask_ollama_model(prompt, "", "", &[], &model_name, &SamplingParams::default(), ResponseFormat::Text, None).await,
+        LlmProvider::Gemini => ask_gemini_model(prompt, "", "", &[], &model_name).await,
+        LlmProvider::Anthropic => ask_anthropic_model(prompt, "", "", &[], &model_name, &SamplingParams::default(), None).await,
+        LlmProvider::OpenRouter => ask_openrouter_model(prompt, "", "", &[], &model_name, &SamplingParams::default(), ResponseFormat::Text, None).await,
+        LlmProvider::Groq | LlmProvider::Together => fast::ask_fast_model(&config.provider, prompt, "", "", &[], &model_name, &SamplingParams::default(), ResponseFormat::Text, None).await,
+        LlmProvider::Azure => enterprise::ask_azure_model(prompt, "", "", &[], &model_name, &SamplingParams::default(), ResponseFormat::Text, None).await,
+        LlmProvider::Bedrock => enterprise::ask_bedrock_model(prompt, "", "", &[], &model_name, &SamplingParams::default()).await,
+    };
+    exchange.record(response.as_deref());
+    let response = response?;
+    
+    cache_response(cache_config, &key, &response);
+    Ok(response)
+}
+
+/// Ask for a reply in JSON and parse it as `T`. Providers with a JSON mode (Ollama,
+/// OpenRouter and Azure) are asked to use it. A reply that doesn't parse as `T` is sent back with the
+/// error, up to `structured::JSON_MAX_ATTEMPTS` requests in all. The KOTA edit instructions
+/// are left out of the system prompt, and responses are never cached.
+pub async fn ask_model_json<T: DeserializeOwned>(user_prompt: &str, context_str: &str, config: &ModelConfig) -> anyhow::Result<T> {
+    let prompts_config = PromptsConfig::load().unwrap_or_default();
+    let config = &router::route(config, context_str, &prompts_config)?;
+    let model_name = config.get_model_name();
+    
+    let sampling = config.sampling.or(&prompts_config.sampling);
+    // Structured requests stand alone, without the conversation so far
+    let prompt = AssembledPrompt {
+        history: Vec::new(),
+        ..prepare_prompt_with_system(&prompts_config, config, structured::JSON_INSTRUCTIONS, user_prompt, context_str).await?
+    };
+    let (context_str, system_instructions) = (prompt.context.as_str(), prompt.system.as_str());
+    
+    let mut request = prompt.user.clone();
+    let mut last_error = String::new();
+    for _ in 0..structured::JSON_MAX_ATTEMPTS {
+        let response = match config.provider {
+            LlmProvider::Ollama => ask_ollama_model(&request, context_str, system_instructions, &[], &model_name, &sampling, ResponseFormat::Json, None).await,
+            LlmProvider::Gemini => ask_gemini_model(&request, context_str, system_instructions, &[], &model_name).await,
+            LlmProvider::Anthropic => ask_anthropic_model(&request, context_str, system_instructions, &[], &model_name, &sampling, None).await,
+            LlmProvider::OpenRouter => ask_openrouter_model(&request, context_str, system_instructions, &[], &model_name, &sampling, ResponseFormat::Json, None).await,
+            LlmProvider::Groq | LlmProvider::Together => fast::ask_fast_model(&config.provider, &request, context_str, system_instructions, &[], &model_name, &sampling, ResponseFormat::Json, None).await,
+            LlmProvider::Azure => enterprise::ask_azure_model(&request, context_str, system_instructions, &[], &model_name, &sampling, ResponseFormat::Json, None).await,
+            LlmProvider::Bedrock => enterprise::ask_bedrock_model(&request, context_str, system_instructions, &[], &model_name, &sampling).await,
+        };
+        let exchange = request_log::Exchange { user: &request, ..request_log::Exchange::new("json", config, &model_name, &prompt) };
+        exchange.record(response.as_deref());
+        let response = response?;
+        
+        match structured::parse_json::<T>(&response) {
+            Ok(value) => return Ok(value),
+            Err(e) => {
+                last_error = e.to_string();
+                request = structured::repair_prompt(&prompt.user, &response, &last_error);
+            }
+        }
+    }
+    
+    Err(anyhow::anyhow!(
+        "{} didn't return valid JSON in {} attempts: {}",
+        config.display_name(),
+        structured::JSON_MAX_ATTEMPTS,
+        last_error
+    ))
+}
+
+/// Ask with the built-in tools (edit_file, run_command, add_to_context) offered to the model.
+/// Tool calls only describe what the model wants; callers run them through the usual
+/// approval prompts. Gemini has no function calling here, so its calls are read back
+/// out of S/R and command blocks in the text. Responses are never cached, and join the
+/// conversation history like streamed ones. Requests go to the `[llm.routing]` model for their kind.
+pub async fn ask_model_with_tools(user_prompt: &str, context_str: &str, config: &ModelConfig) -> anyhow::Result<ToolResponse> {
+    let prompts_config = PromptsConfig::load().unwrap_or_default();
+    let config = &router::route(&router::route_task(config, user_prompt, &prompts_config.llm.routing), context_str, &prompts_config)?;
     let model_name = config.get_model_name();
+    let prompt = prepare_prompt(&prompts_config, config, user_prompt, context_str).await?;
+    let (user_prompt, context_str, system_instructions) = (prompt.user.as_str(), prompt.context.as_str(), prompt.system.as_str());
+    let history = prompt.history.as_slice();
+    
+    if let LlmProvider::Gemini = config.provider {
+        let text = ask_gemini_model(user_prompt, context_str, system_instructions, history, &model_name).await;
+        request_log::Exchange::new("tools", config, &model_name, &prompt).record(text.as_deref());
+        let text = verify_draft(text?, user_prompt, context_str, config, &prompts_config).await;
+        conversation::record(user_prompt, &text);
+        return Ok(ToolResponse::from_text(text));
+    }
     
+    let system_instructions = format!("{}\n\n{}", system_instructions, tools::TOOL_USE_INSTRUCTIONS);
+    let exchange = request_log::Exchange { system: &system_instructions, ..request_log::Exchange::new("tools", config, &model_name, &prompt) };
+    let system_content = if context_str.is_empty() {
+        system_instructions.clone()
+    } else {
+        format!("{}\n\n{}", system_instructions, context_str)
+    };
+    let prompt_text = format!("{}\n\n{}\n\n{}", system_content, conversation::as_text(history), user_prompt);
+    
+    let sampling = config.sampling.or(&prompts_config.sampling);
+    let timeout_secs = match config.provider {
+        LlmProvider::Anthropic => ANTHROPIC_TIMEOUT_SECS,
+        LlmProvider::Ollama => 120,
+        LlmProvider::Azure | LlmProvider::Bedrock => enterprise::ENTERPRISE_TIMEOUT_SECS,
+        LlmProvider::Groq | LlmProvider::Together => fast::FAST_TIMEOUT_SECS,
+        _ => OPENROUTER_TIMEOUT_SECS,
+    };
+    let mut messages = conversation::messages(history);
+    messages.push(serde_json::json!({ "role": "user", "content": user_prompt }));
+    let mut payload = match config.provider {
+        LlmProvider::Anthropic => serde_json::json!({
+            "model": model_name,
+            "system": system_content,
+            "messages": messages,
+            "tools": tools::anthropic_tools(),
+            "max_tokens": DEFAULT_MAX_TOKENS,
+        }),
+        LlmProvider::Bedrock => {
+            let mut payload = enterprise::bedrock_body(&system_content, history, user_prompt);
+            payload["tools"] = tools::anthropic_tools();
+            payload
+        }
+        LlmProvider::OpenRouter | LlmProvider::Ollama | LlmProvider::Azure | LlmProvider::Groq | LlmProvider::Together => {
+            messages.insert(0, serde_json::json!({ "role": "system", "content": system_content }));
+            let mut payload = serde_json::json!({
+                "messages": messages,
+                "tools": tools::openai_tools(),
+                "stream": false,
+            });
+            // Azure takes the deployment from the URL
+            if !matches!(config.provider, LlmProvider::Azure) {
+                payload["model"] = serde_json::json!(model_name);
+            }
+            payload
+        }
+        LlmProvider::Gemini => unreachable!("Gemini is handled above"),
+    };
     match config.provider {
-        LlmProvider::Ollama => ask_ollama_model(user_prompt, context_str, &prompts_config, &model_name).await,
-        LlmProvider::Gemini => ask_gemini_model(user_prompt, context_str, &prompts_config, &model_name).await,
-        LlmProvider::Anthropic => ask_anthropic_model(user_prompt, context_str, &prompts_config, &model_name).await,
+        LlmProvider::Ollama => {
+            if let Some(options) = sampling.ollama_options() {
+                payload["options"] = options;
+            }
+        }
+        LlmProvider::Anthropic | LlmProvider::Bedrock => sampling.apply_to(&mut payload),
+        _ => {
+            sampling.apply_to(&mut payload);
+            sampling.apply_seed(&mut payload);
+        }
+    }
+    
+    let client = connection::builder(&config.provider)?
+        .timeout(Duration::from_secs(timeout_secs))
+        .connect_timeout(Duration::from_secs(10))
+        .build()
+        .context("Failed to create HTTP client")?;
+    
+    let request = match config.provider {
+        LlmProvider::Anthropic => {
+            let api_key = std::env::var("ANTHROPIC_API_KEY")
+                .map_err(|_| anyhow::anyhow!("ANTHROPIC_API_KEY environment variable not found. Please set it to use Anthropic Claude."))?;
+            client.post(connection::url(&LlmProvider::Anthropic, ANTHROPIC_API_URL))
+                .header("x-api-key", api_key)
+                .header("anthropic-version", "2023-06-01")
+        }
+        LlmProvider::OpenRouter => {
+            let api_key = std::env::var("OPENROUTER_API_KEY")
+                .map_err(|_| anyhow::anyhow!("OPENROUTER_API_KEY environment variable not found. Please set it to use OpenRouter."))?;
+            client.post(connection::url(&LlmProvider::OpenRouter, OPENROUTER_API_URL)).bearer_auth(api_key).header("X-Title", "KOTA")
+        }
+        LlmProvider::Azure => enterprise::azure_request(&client, &prompts_config.providers.azure, &model_name)?,
+        LlmProvider::Bedrock => enterprise::bedrock_request(&client, &prompts_config.providers.bedrock, &model_name)?,
+        LlmProvider::Groq | LlmProvider::Together => fast::request(&client, &config.provider)?,
+        _ => client.post(connection::url(&LlmProvider::Ollama, OLLAMA_API_URL)),
+    };
+    
+    let display_name = config.display_name();
+    let response = with_retry(&display_name, || {
+        // Cloning only fails for streaming bodies, and the JSON body is added after the clone
+        let request = request.try_clone().map(|request| request.json(&payload));
+        let display_name = &display_name;
+        async move {
+            let request = request.ok_or_else(|| anyhow::anyhow!("Tool request to {} can't be retried", display_name))?;
+            let response = request.send().await.map_err(|e| {
+                let message = if e.is_timeout() {
+                    format!("Tool request to {} timed out after {} seconds", display_name, timeout_secs)
+                } else {
+                    format!("Failed to send tool request to {}: {}", display_name, e)
+                };
+                anyhow::Error::new(e).context(message)
+            })?;
+            
+            if !response.status().is_success() {
+                let status = response.status();
+                let status_error = HttpStatusError::from_response(&response);
+                let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
+                return Err(anyhow::Error::new(status_error)
+                    .context(format!("{} tool request failed with status {}: {}", display_name, status, error_text)));
+            }
+            Ok(response)
+        }
+    }).await;
+    
+    // The raw body is what gets logged, tool calls and all
+    let body: anyhow::Result<serde_json::Value> = match response {
+        Ok(response) => response.json().await.context("Failed to parse JSON response with tool calls"),
+        Err(e) => Err(e),
+    };
+    exchange.record(body.as_ref().map(|body| body.to_string()).as_deref());
+    let body = body?;
+    
+    let (tool_response, reported) = match config.provider {
+        LlmProvider::Anthropic | LlmProvider::Bedrock => (
+            tools::parse_anthropic_response(&body),
+            serde_json::from_value::<AnthropicUsage>(body["usage"].clone()).ok().map(TokenUsage::from),
+        ),
+        LlmProvider::Ollama => (
+            tools::parse_openai_message(&body["message"]),
+            ollama_token_usage(body["prompt_eval_count"].as_u64(), body["eval_count"].as_u64()),
+        ),
+        _ => (
+            tools::parse_openai_message(&body["choices"][0]["message"]),
+            openai_token_usage(&body["usage"]),
+        ),
+    };
+    
+    // Tool inputs count towards the completion when usage has to be estimated
+    let completion = tool_response.tool_calls.iter()
+        .fold(tool_response.text.clone(), |text, call| text + &call.input.to_string());
+    record_usage(config.provider.clone(), &model_name, reported, &prompt_text, &completion);
+    
+    // Edit calls are verified as the blocks they stand for; a corrected draft comes back as text
+    let draft = tool_response.to_text_with_blocks();
+    let verified = verify_draft(draft.clone(), user_prompt, context_str, config, &prompts_config).await;
+    conversation::record(user_prompt, &verified);
+    
+    Ok(if verified == draft { tool_response } else { ToolResponse::from_text(verified) })
+}
+
+/// Run a request through the prompt pipeline configured in prompts.toml, shrinking the oldest
+/// context items if it wouldn't fit the model
+fn assemble_prompt(prompts_config: &PromptsConfig, config: &ModelConfig, system_instructions: &str, user_prompt: &str, context_str: &str) -> AssembledPrompt {
+    let prompt = PromptPipeline::from_config(&prompts_config.pipeline).assemble(&PromptInput {
+        system_instructions,
+        context_files: context_str,
+        user_message: user_prompt,
+    });
+    
+    // A max_tokens above the usual reply reserve needs that much more room
+    let max_tokens = config.sampling.or(&prompts_config.sampling).max_tokens.unwrap_or(DEFAULT_MAX_TOKENS) as u64;
+    let window = config.context_window().saturating_sub(max_tokens.saturating_sub(context_window::RESPONSE_RESERVE_TOKENS));
+    let (prompt, report) = context_window::fit_to_window(prompt, window);
+    if report.changed() {
+        eprintln!(
+            "Warning: Prompt (~{} tokens) didn't fit {}'s {}-token context window; shortened {} and left out {} of the oldest context items (now ~{} tokens)",
+            report.original_tokens, config.display_name(), window, report.summarized, report.dropped, report.final_tokens
+        );
+    }
+    if report.final_tokens + context_window::RESPONSE_RESERVE_TOKENS > window {
+        eprintln!("Warning: Prompt still doesn't fit {}'s context window; the request may be rejected", config.display_name());
+    }
+    prompt
+}
+
+/// Assemble the prompt and, for cloud providers, have any secrets in it reviewed before it leaves the machine
+async fn prepare_prompt(prompts_config: &PromptsConfig, config: &ModelConfig, user_prompt: &str, context_str: &str) -> anyhow::Result<AssembledPrompt> {
+    let system_instructions = prompts_config.system_instructions_for(config.provider.name(), &config.get_model_name());
+    prepare_prompt_with_system(prompts_config, config, &system_instructions, user_prompt, context_str).await
+}
+
+async fn prepare_prompt_with_system(prompts_config: &PromptsConfig, config: &ModelConfig, system_instructions: &str, user_prompt: &str, context_str: &str) -> anyhow::Result<AssembledPrompt> {
+    let span = trace::span("prompt.assemble").with("context_bytes", context_str.len());
+    let prompt = assemble_prompt(prompts_config, config, system_instructions, user_prompt, context_str);
+    drop(span);
+    if let LlmProvider::Ollama = config.provider {
+        return Ok(prompt);
+    }
+    secrets::review_prompt(prompt, &prompts_config.secrets).await
+}
+
+/// Cache key model part; responses sampled differently aren't interchangeable
+fn sampled_model_key(model_name: &str, sampling: &SamplingParams) -> String {
+    if sampling.is_default() {
+        model_name.to_string()
+    } else {
+        format!("{} ({})", model_name, sampling.summary())
+    }
+}
+
+/// Cache key context part; the same message means something else after different turns
+fn keyed_context(context_str: &str, history: &[Turn]) -> String {
+    if history.is_empty() {
+        context_str.to_string()
+    } else {
+        format!("{}\n\n{}", context_str, conversation::as_text(history))
+    }
+}
+
+fn cached_response(cache_config: &CacheConfig, key: &CacheKey) -> Option<String> {
+    if !cache_config.enabled {
+        return None;
+    }
+    ResponseCache::new(cache_config.ttl_hours).get(key)
+}
+
+fn cache_response(cache_config: &CacheConfig, key: &CacheKey, response: &str) {
+    if !cache_config.enabled || response.trim().is_empty() {
+        return;
+    }
+    if let Err(e) = ResponseCache::new(cache_config.ttl_hours).put(key, response) {
+        eprintln!("Warning: Failed to cache LLM response: {}", e);
     }
 }
 
-async fn ask_gemini_model(user_prompt: &str, context_str: &str, prompts_config: &PromptsConfig, model_name: &str) -> anyhow::Result<String> {
+async fn ask_gemini_model(user_prompt: &str, context_str: &str, system_instructions: &str, history: &[Turn], model_name: &str) -> anyhow::Result<String> {
     let api_key = std::env::var("GEMINI_API_KEY")
         .map_err(|_| anyhow::anyhow!("GEMINI_API_KEY environment variable not found. Please set it to use Gemini."))?;
     
     let ai = Gemini::new(api_key, model_name, None);
-    let mut session = Session::new(10); // Keep last 10 messages for context
     
-    // Prepare the full prompt with system instructions and context
-    let system_instructions = prompts_config.get_system_instructions();
-    let full_prompt = if context_str.is_empty() {
-        format!("{}\n\nUser: {}", system_instructions, user_prompt)
+    // Prepare the full prompt with system instructions, context and earlier turns
+    let mut full_prompt = [system_instructions.to_string(), context_str.to_string(), conversation::as_text(history)]
+        .into_iter()
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    full_prompt = if full_prompt.is_empty() {
+        user_prompt.to_string()
     } else {
-        format!("{}\n\n{}\n\nUser: {}", system_instructions, context_str, user_prompt)
+        format!("{}\n\nUser: {}", full_prompt, user_prompt)
     };
     
     // Wrap the API call with a timeout
-    let response = timeout(
-        Duration::from_secs(GEMINI_TIMEOUT_SECS),
-        ai.ask(session.ask_string(&full_prompt))
-    )
-    .await
-    .map_err(|_| anyhow::anyhow!("Gemini API request timed out after {} seconds", GEMINI_TIMEOUT_SECS))?
-    .map_err(|e| anyhow::anyhow!("Gemini API error: {}", e))?;
+    let response = with_retry("Gemini", || async {
+        let mut session = Session::new(10); // Keep last 10 messages for context
+        timeout(
+            Duration::from_secs(GEMINI_TIMEOUT_SECS),
+            ai.ask(session.ask_string(&full_prompt))
+        )
+        .await
+        .map_err(|e| anyhow::Error::new(e).context(format!("Gemini API request timed out after {} seconds", GEMINI_TIMEOUT_SECS)))?
+        .map_err(|e| anyhow::anyhow!("Gemini API error: {}", e))
+    }).await?;
+    
+    let text = response.get_text("");
+    // The Gemini client doesn't expose usage metadata, so counts are estimated
+    record_usage(LlmProvider::Gemini, model_name, None, &full_prompt, &text);
     
-    Ok(response.get_text(""))
+    Ok(text)
 }
 
-async fn ask_anthropic_model(user_prompt: &str, context_str: &str, prompts_config: &PromptsConfig, model_name: &str) -> anyhow::Result<String> {
+async fn ask_anthropic_model(user_prompt: &str, context_str: &str, system_instructions: &str, history: &[Turn], model_name: &str, sampling: &SamplingParams, on_chunk: Option<ChunkHandler<'_>>) -> anyhow::Result<String> {
     let api_key = std::env::var("ANTHROPIC_API_KEY")
         .map_err(|_| anyhow::anyhow!("ANTHROPIC_API_KEY environment variable not found. Please set it to use Anthropic Claude."))?;
     
     // Create a client with timeout settings
-    let client = ClientBuilder::new()
+    let client = connection::builder(&LlmProvider::Anthropic)?
         .timeout(Duration::from_secs(ANTHROPIC_TIMEOUT_SECS))
         .connect_timeout(Duration::from_secs(10))
         .build()
         .context("Failed to create HTTP client")?;
+    let api_url = connection::url(&LlmProvider::Anthropic, ANTHROPIC_API_URL);
     
     let mut messages = Vec::new();
     
     // Add system message with instructions and context
-    let system_instructions = prompts_config.get_system_instructions();
     let system_content = if context_str.is_empty() {
         system_instructions.to_string()
     } else {
         format!("{}\n\n{}", system_instructions, context_str)
     };
     
+    let prompt_text = format!("{}\n\n{}\n\n{}", system_content, conversation::as_text(history), user_prompt);
+    
     // For Anthropic, we need to structure messages differently
     // The system prompt goes in the system parameter of the API call
+    for turn in history {
+        messages.push(AnthropicMessage { role: "user".to_string(), content: turn.user.clone() });
+        messages.push(AnthropicMessage { role: "assistant".to_string(), content: turn.assistant.clone() });
+    }
     messages.push(AnthropicMessage {
         role: "user".to_string(),
         content: user_prompt.to_string(),
@@ -176,45 +940,62 @@ async fn ask_anthropic_model(user_prompt: &str, context_str: &str, prompts_confi
     
     // Note: We're using serde_json::json! here because Anthropic API requires
     // the "system" field which is not part of our AnthropicRequest struct
-    let request_payload = serde_json::json!({
+    let mut request_payload = serde_json::json!({
         "model": model_name,
         "messages": messages,
-        "max_tokens": 4096,
-        "system": system_content,
+        "max_tokens": DEFAULT_MAX_TOKENS,
     });
+    sampling.apply_to(&mut request_payload);
+    if !system_content.is_empty() {
+        request_payload["system"] = serde_json::Value::String(system_content);
+    }
+    if on_chunk.is_some() {
+        request_payload["stream"] = serde_json::Value::Bool(true);
+    }
     
-    let response = client
-        .post(ANTHROPIC_API_URL)
-        .header("x-api-key", api_key)
-        .header("anthropic-version", "2023-06-01")
-        .header("content-type", "application/json")
-        .json(&request_payload)
-        .send()
-        .await
-        .map_err(|e| {
-            if e.is_connect() {
-                anyhow::anyhow!("Failed to connect to Anthropic API. Please check your internet connection.")
-            } else if e.is_timeout() {
-                anyhow::anyhow!("Request to Anthropic API timed out after {} seconds", ANTHROPIC_TIMEOUT_SECS)
-            } else {
-                anyhow::anyhow!("Failed to send request to Anthropic API: {}", e)
+    let response = with_retry("Anthropic", || {
+        let request = client
+            .post(&api_url)
+            .header("x-api-key", api_key.as_str())
+            .header("anthropic-version", "2023-06-01")
+            .header("content-type", "application/json")
+            .json(&request_payload);
+        async move {
+            let response = request.send().await.map_err(|e| {
+                let message = if e.is_connect() {
+                    "Failed to connect to Anthropic API. Please check your internet connection.".to_string()
+                } else if e.is_timeout() {
+                    format!("Request to Anthropic API timed out after {} seconds", ANTHROPIC_TIMEOUT_SECS)
+                } else {
+                    format!("Failed to send request to Anthropic API: {}", e)
+                };
+                anyhow::Error::new(e).context(message)
+            })?;
+            
+            if !response.status().is_success() {
+                let status = response.status();
+                let status_error = HttpStatusError::from_response(&response);
+                let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
+                
+                let error_msg = match status.as_u16() {
+                    401 => format!("Authentication failed. Please check your ANTHROPIC_API_KEY. Status {}: {}", status, error_text),
+                    403 => format!("Access forbidden. Your API key may not have access to this model. Status {}: {}", status, error_text),
+                    404 => format!("Model not found. Status {}: {}", status, error_text),
+                    429 => format!("Rate limit exceeded. Please wait before trying again. Status {}: {}", status, error_text),
+                    500 => format!("Anthropic server error. Status {}: {}", status, error_text),
+                    _ => format!("Anthropic API request failed with status {}: {}", status, error_text),
+                };
+                
+                return Err(anyhow::Error::new(status_error).context(error_msg));
             }
-        })?;
+            Ok(response)
+        }
+    }).await?;
     
-    if !response.status().is_success() {
-        let status = response.status();
-        let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-        
-        let error_msg = match status.as_u16() {
-            401 => format!("Authentication failed. Please check your ANTHROPIC_API_KEY. Status {}: {}", status, error_text),
-            403 => format!("Access forbidden. Your API key may not have access to this model. Status {}: {}", status, error_text),
-            404 => format!("Model not found. Status {}: {}", status, error_text),
-            429 => format!("Rate limit exceeded. Please wait before trying again. Status {}: {}", status, error_text),
-            500 => format!("Anthropic server error. Status {}: {}", status, error_text),
-            _ => format!("Anthropic API request failed with status {}: {}", status, error_text),
-        };
-        
-        return Err(anyhow::anyhow!(error_msg));
+    if let Some(on_chunk) = on_chunk {
+        let (text, reported) = read_streamed_lines(response, parse_anthropic_stream_line, on_chunk).await?;
+        record_usage(LlmProvider::Anthropic, model_name, reported, &prompt_text, &text);
+        return Ok(text);
     }
     
     let anthropic_response: AnthropicResponse = response
@@ -230,26 +1011,30 @@ async fn ask_anthropic_model(user_prompt: &str, context_str: &str, prompts_confi
         .map(|c| c.text)
         .unwrap_or_else(|| "No text response from Anthropic".to_string());
     
+    record_usage(LlmProvider::Anthropic, model_name, anthropic_response.usage.map(TokenUsage::from), &prompt_text, &text);
+    
     Ok(text)
 }
 
-async fn ask_ollama_model(user_prompt: &str, context_str: &str, prompts_config: &PromptsConfig, model_name: &str) -> anyhow::Result<String> {
+#[allow(clippy::too_many_arguments)]
+async fn ask_ollama_model(user_prompt: &str, context_str: &str, system_instructions: &str, history: &[Turn], model_name: &str, sampling: &SamplingParams, format: ResponseFormat, on_chunk: Option<ChunkHandler<'_>>) -> anyhow::Result<String> {
     // Create a client with timeout settings
-    let client = ClientBuilder::new()
+    let client = connection::builder(&LlmProvider::Ollama)?
         .timeout(Duration::from_secs(120))  // 2 minute timeout for the entire request
         .connect_timeout(Duration::from_secs(10))  // 10 second timeout for establishing connection
         .build()
         .context("Failed to create HTTP client")?;
+    let api_url = connection::url(&LlmProvider::Ollama, OLLAMA_API_URL);
 
     let mut messages = Vec::new();
 
     // Add S/R and command execution instructions as a system message
-    let system_instructions = prompts_config.get_system_instructions();
-
-    messages.push(OllamaChatMessage {
-        role: "system".to_string(),
-        content: system_instructions.to_string(),
-    });
+    if !system_instructions.is_empty() {
+        messages.push(OllamaChatMessage {
+            role: "system".to_string(),
+            content: system_instructions.to_string(),
+        });
+    }
 
     // Add context as a system message if it's not empty
     if !context_str.is_empty() {
@@ -259,63 +1044,554 @@ async fn ask_ollama_model(user_prompt: &str, context_str: &str, prompts_config:
         });
     }
 
-    // Add the user's prompt
+    // Earlier turns, then the user's prompt
+    for turn in history {
+        messages.push(OllamaChatMessage { role: "user".to_string(), content: turn.user.clone() });
+        messages.push(OllamaChatMessage { role: "assistant".to_string(), content: turn.assistant.clone() });
+    }
     messages.push(OllamaChatMessage {
         role: "user".to_string(),
         content: user_prompt.to_string(),
     });
 
+    let prompt_text = messages.iter().map(|m| m.content.as_str()).collect::<Vec<_>>().join("\n\n");
+    
     let request_payload = OllamaChatRequest {
         model: model_name.to_string(),
         messages,
-        stream: false,
+        stream: on_chunk.is_some(),
+        options: sampling.ollama_options(),
+        format: (format == ResponseFormat::Json).then(|| "json".to_string()),
     };
 
+    let response = with_retry("Ollama", || {
+        let request = client.post(&api_url).json(&request_payload);
+        async move {
+            let response = request.send().await.map_err(|e| {
+                // Provide more specific error messages for common connection issues
+                let message = if e.is_connect() {
+                    "Failed to connect to Ollama API. Please check if Ollama is running (brew services start ollama)".to_string()
+                } else if e.is_timeout() {
+                    "Request to Ollama API timed out. The model might be too large or the server is under heavy load".to_string()
+                } else {
+                    format!("Failed to send request to Ollama API: {}", e)
+                };
+                anyhow::Error::new(e).context(message)
+            })?;
+
+            if !response.status().is_success() {
+                let status = response.status();
+                let status_error = HttpStatusError::from_response(&response);
+                let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
+                
+                // Add more context to specific error codes
+                let error_msg = match status.as_u16() {
+                    404 => format!("Model not found. Status {}: {}", status, error_text),
+                    400 => format!("Bad request to Ollama API. Status {}: {}", status, error_text),
+                    412 => format!("Ollama version incompatibility. Please update Ollama (brew upgrade ollama). Status {}: {}", status, error_text),
+                    500 => format!("Ollama server error. Status {}: {}", status, error_text),
+                    _ => format!("Ollama API request failed with status {}: {}", status, error_text),
+                };
+                
+                return Err(anyhow::Error::new(status_error).context(error_msg));
+            }
+            Ok(response)
+        }
+    }).await?;
+
+    if let Some(on_chunk) = on_chunk {
+        let (text, reported) = read_streamed_lines(response, parse_ollama_stream_line, on_chunk).await?;
+        record_usage(LlmProvider::Ollama, model_name, reported, &prompt_text, &text);
+        return Ok(text);
+    }
+
+    let ollama_response = response
+        .json::<OllamaChatResponse>()
+        .await
+        .context("failed to parse JSON response from Ollama API")?;
+
+    record_usage(LlmProvider::Ollama, model_name, ollama_response.token_usage(), &prompt_text, &ollama_response.message.content);
+
+    Ok(ollama_response.message.content)
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn ask_openrouter_model(user_prompt: &str, context_str: &str, system_instructions: &str, history: &[Turn], model_name: &str, sampling: &SamplingParams, format: ResponseFormat, on_chunk: Option<ChunkHandler<'_>>) -> anyhow::Result<String> {
+    let api_key = std::env::var("OPENROUTER_API_KEY")
+        .map_err(|_| anyhow::anyhow!("OPENROUTER_API_KEY environment variable not found. Please set it to use OpenRouter."))?;
+    
+    let client = connection::builder(&LlmProvider::OpenRouter)?
+        .timeout(Duration::from_secs(OPENROUTER_TIMEOUT_SECS))
+        .connect_timeout(Duration::from_secs(10))
+        .build()
+        .context("Failed to create HTTP client")?;
+    let api_url = connection::url(&LlmProvider::OpenRouter, OPENROUTER_API_URL);
+    
+    // OpenRouter speaks the OpenAI chat format, so instructions and context go in system messages
+    let mut messages = Vec::new();
+    if !system_instructions.is_empty() {
+        messages.push(serde_json::json!({ "role": "system", "content": system_instructions }));
+    }
+    if !context_str.is_empty() {
+        messages.push(serde_json::json!({ "role": "system", "content": context_str }));
+    }
+    messages.extend(conversation::messages(history));
+    messages.push(serde_json::json!({ "role": "user", "content": user_prompt }));
+    let prompt_text = format!("{}\n\n{}\n\n{}\n\n{}", system_instructions, context_str, conversation::as_text(history), user_prompt);
+    
+    // Usage accounting makes OpenRouter append token counts to the response
+    let mut request_payload = serde_json::json!({
+        "model": model_name,
+        "messages": messages,
+        "stream": on_chunk.is_some(),
+        "usage": { "include": true },
+    });
+    sampling.apply_to(&mut request_payload);
+    sampling.apply_seed(&mut request_payload);
+    if format == ResponseFormat::Json {
+        request_payload["response_format"] = serde_json::json!({ "type": "json_object" });
+    }
+    
+    let response = with_retry("OpenRouter", || {
+        let request = client
+            .post(&api_url)
+            .bearer_auth(&api_key)
+            .header("X-Title", "KOTA")
+            .json(&request_payload);
+        async move {
+            let response = request.send().await.map_err(|e| {
+                let message = if e.is_connect() {
+                    "Failed to connect to OpenRouter API. Please check your internet connection.".to_string()
+                } else if e.is_timeout() {
+                    format!("Request to OpenRouter API timed out after {} seconds", OPENROUTER_TIMEOUT_SECS)
+                } else {
+                    format!("Failed to send request to OpenRouter API: {}", e)
+                };
+                anyhow::Error::new(e).context(message)
+            })?;
+            
+            if !response.status().is_success() {
+                let status = response.status();
+                let status_error = HttpStatusError::from_response(&response);
+                let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
+                
+                let error_msg = match status.as_u16() {
+                    401 => format!("Authentication failed. Please check your OPENROUTER_API_KEY. Status {}: {}", status, error_text),
+                    402 => format!("Insufficient OpenRouter credits. Status {}: {}", status, error_text),
+                    404 => format!("Model not found. Use /model list to see available models. Status {}: {}", status, error_text),
+                    429 => format!("Rate limit exceeded. Please wait before trying again. Status {}: {}", status, error_text),
+                    _ => format!("OpenRouter API request failed with status {}: {}", status, error_text),
+                };
+                
+                return Err(anyhow::Error::new(status_error).context(error_msg));
+            }
+            Ok(response)
+        }
+    }).await?;
+    
+    if let Some(on_chunk) = on_chunk {
+        let (text, reported) = read_streamed_lines(response, parse_openrouter_stream_line, on_chunk).await?;
+        record_usage(LlmProvider::OpenRouter, model_name, reported, &prompt_text, &text);
+        return Ok(text);
+    }
+    
+    let body: serde_json::Value = response
+        .json()
+        .await
+        .context("Failed to parse JSON response from OpenRouter API")?;
+    
+    let text = body["choices"][0]["message"]["content"]
+        .as_str()
+        .map(String::from)
+        .ok_or_else(|| anyhow::anyhow!("No text response from OpenRouter"))?;
+    
+    record_usage(LlmProvider::OpenRouter, model_name, openai_token_usage(&body["usage"]), &prompt_text, &text);
+    
+    Ok(text)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OpenRouterModel {
+    pub id: String,
+    pub name: String,
+    pub context_length: Option<u64>,
+    /// USD per million prompt tokens
+    pub prompt_price: Option<f64>,
+}
+
+/// Fetch the OpenRouter model catalog. Listing models doesn't need an API key.
+pub async fn list_openrouter_models() -> anyhow::Result<Vec<OpenRouterModel>> {
+    let client = connection::builder(&LlmProvider::OpenRouter)?
+        .timeout(Duration::from_secs(30))
+        .build()
+        .context("Failed to create HTTP client")?;
+    let api_url = connection::url(&LlmProvider::OpenRouter, OPENROUTER_MODELS_URL);
+    
+    let response = client
+        .get(&api_url)
+        .send()
+        .await
+        .context("Failed to fetch the OpenRouter model list")?;
+    
+    if !response.status().is_success() {
+        return Err(anyhow::anyhow!("OpenRouter model list request failed with status {}", response.status()));
+    }
+    
+    let body: serde_json::Value = response
+        .json()
+        .await
+        .context("Failed to parse the OpenRouter model list")?;
+    
+    Ok(parse_openrouter_models(&body))
+}
+
+fn parse_openrouter_models(body: &serde_json::Value) -> Vec<OpenRouterModel> {
+    let mut models: Vec<OpenRouterModel> = body["data"]
+        .as_array()
+        .map(|items| items.iter().filter_map(|item| {
+            let id = item["id"].as_str()?.to_string();
+            Some(OpenRouterModel {
+                name: item["name"].as_str().unwrap_or(&id).to_string(),
+                context_length: item["context_length"].as_u64(),
+                // Prices are per token, sent as strings
+                prompt_price: item["pricing"]["prompt"].as_str()
+                    .and_then(|price| price.parse::<f64>().ok())
+                    .map(|price| price * 1_000_000.0),
+                id,
+            })
+        }).collect())
+        .unwrap_or_default();
+    
+    models.sort_by(|a, b| a.id.cmp(&b.id));
+    models
+}
+
+/// Catalog entries whose id or name contains `filter` (case-insensitive)
+pub fn filter_openrouter_models<'a>(models: &'a [OpenRouterModel], filter: &str) -> Vec<&'a OpenRouterModel> {
+    let filter = filter.to_lowercase();
+    models
+        .iter()
+        .filter(|model| filter.is_empty()
+            || model.id.to_lowercase().contains(&filter)
+            || model.name.to_lowercase().contains(&filter))
+        .collect()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OllamaModel {
+    pub name: String,
+    /// Size on disk in bytes
+    pub size: u64,
+    pub parameter_size: Option<String>,
+}
+
+/// Models pulled into the local Ollama server
+pub async fn list_ollama_models() -> anyhow::Result<Vec<OllamaModel>> {
+    let client = connection::builder(&LlmProvider::Ollama)?
+        .timeout(Duration::from_secs(5))
+        .build()
+        .context("Failed to create HTTP client")?;
+    let api_url = connection::url(&LlmProvider::Ollama, OLLAMA_TAGS_URL);
+    
     let response = client
-        .post(OLLAMA_API_URL)
-        .json(&request_payload)
+        .get(&api_url)
         .send()
         .await
         .map_err(|e| {
-            // Provide more specific error messages for common connection issues
             if e.is_connect() {
-                anyhow::anyhow!("Failed to connect to Ollama API. Please check if Ollama is running (brew services start ollama)")
-            } else if e.is_timeout() {
-                anyhow::anyhow!("Request to Ollama API timed out. The model might be too large or the server is under heavy load")
+                anyhow::anyhow!("Ollama isn't running. Start it with: ollama serve (or brew services start ollama)")
             } else {
-                anyhow::anyhow!("Failed to send request to Ollama API: {}", e)
+                anyhow::anyhow!("Failed to list Ollama models: {}", e)
             }
         })?;
+    
+    if !response.status().is_success() {
+        return Err(anyhow::anyhow!("Ollama model list request failed with status {}", response.status()));
+    }
+    
+    let body: serde_json::Value = response
+        .json()
+        .await
+        .context("Failed to parse the Ollama model list")?;
+    
+    Ok(parse_ollama_models(&body))
+}
+
+fn parse_ollama_models(body: &serde_json::Value) -> Vec<OllamaModel> {
+    let mut models: Vec<OllamaModel> = body["models"]
+        .as_array()
+        .map(|items| items.iter().filter_map(|item| {
+            Some(OllamaModel {
+                name: item["name"].as_str()?.to_string(),
+                size: item["size"].as_u64().unwrap_or(0),
+                parameter_size: item["details"]["parameter_size"].as_str().map(String::from),
+            })
+        }).collect())
+        .unwrap_or_default();
+    
+    models.sort_by(|a, b| a.name.cmp(&b.name));
+    models
+}
 
+/// Whether `model` has been pulled. Ollama treats a name without a tag as `:latest`.
+pub fn ollama_has_model(models: &[OllamaModel], model: &str) -> bool {
+    let with_tag = |name: &str| if name.contains(':') { name.to_string() } else { format!("{}:latest", name) };
+    let wanted = with_tag(model);
+    models.iter().any(|m| with_tag(&m.name) == wanted)
+}
+
+/// Download a model into Ollama, passing each progress update (e.g. "pulling manifest",
+/// "downloading 42%") to `on_status`
+pub async fn pull_ollama_model<F>(model: &str, mut on_status: F) -> anyhow::Result<()>
+where
+    F: FnMut(&str),
+{
+    // No overall timeout: large models take a while to download
+    let client = connection::builder(&LlmProvider::Ollama)?
+        .connect_timeout(Duration::from_secs(10))
+        .build()
+        .context("Failed to create HTTP client")?;
+    let api_url = connection::url(&LlmProvider::Ollama, OLLAMA_PULL_URL);
+    
+    let response = client
+        .post(&api_url)
+        .json(&serde_json::json!({ "model": model, "stream": true }))
+        .send()
+        .await
+        .with_context(|| format!("Failed to start pulling {}", model))?;
+    
     if !response.status().is_success() {
         let status = response.status();
         let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
+        return Err(anyhow::anyhow!("Pulling {} failed with status {}: {}", model, status, error_text));
+    }
+    
+    let mut stream = response.bytes_stream();
+    let mut buffer: Vec<u8> = Vec::new();
+    while let Some(bytes) = stream.next().await {
+        buffer.extend_from_slice(&bytes.context("Failed to read pull progress")?);
+        while let Some(newline) = buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = buffer.drain(..=newline).collect();
+            if let Some(status) = parse_ollama_pull_line(String::from_utf8_lossy(&line).trim())? {
+                on_status(&status);
+            }
+        }
+    }
+    if let Some(status) = parse_ollama_pull_line(String::from_utf8_lossy(&buffer).trim())? {
+        on_status(&status);
+    }
+    
+    Ok(())
+}
+
+/// Progress text from one line of Ollama's pull stream
+fn parse_ollama_pull_line(line: &str) -> anyhow::Result<Option<String>> {
+    if line.is_empty() {
+        return Ok(None);
+    }
+    let event: serde_json::Value = serde_json::from_str(line)
+        .context("Failed to parse pull progress from Ollama")?;
+    
+    if let Some(error) = event["error"].as_str() {
+        return Err(anyhow::anyhow!("Ollama pull error: {}", error));
+    }
+    
+    let Some(status) = event["status"].as_str() else {
+        return Ok(None);
+    };
+    Ok(Some(match (event["completed"].as_u64(), event["total"].as_u64()) {
+        (Some(completed), Some(total)) if total > 0 => format!("{} {}%", status, completed * 100 / total),
+        _ => status.to_string(),
+    }))
+}
+
+/// Callback that receives streamed response text as it arrives
+type ChunkHandler<'a> = &'a mut (dyn FnMut(&str) + Send);
+
+/// What one line of a streamed response carries
+#[derive(Debug, PartialEq)]
+enum StreamPiece {
+    Text(String),
+    /// Token counts; zero fields mean the line didn't report that count
+    Usage(TokenUsage),
+}
+
+/// Read a streamed response body line by line, passing the text each line carries to
+/// `on_chunk`. Returns the concatenated text and any token usage the stream reported.
+async fn read_streamed_lines(
+    response: reqwest::Response,
+    parse_line: fn(&str) -> anyhow::Result<Option<StreamPiece>>,
+    on_chunk: ChunkHandler<'_>,
+) -> anyhow::Result<(String, Option<TokenUsage>)> {
+    let mut stream = response.bytes_stream();
+    let mut buffer: Vec<u8> = Vec::new();
+    let mut full_text = String::new();
+    let mut reported: Option<TokenUsage> = None;
+    
+    let mut handle_piece = |piece: StreamPiece, full_text: &mut String| match piece {
+        StreamPiece::Text(text) => {
+            full_text.push_str(&text);
+            on_chunk(&text);
+        }
+        StreamPiece::Usage(usage) => {
+            // Anthropic reports prompt and completion counts in separate events
+            let total = reported.get_or_insert_with(TokenUsage::default);
+            if usage.prompt_tokens > 0 {
+                total.prompt_tokens = usage.prompt_tokens;
+            }
+            if usage.completion_tokens > 0 {
+                total.completion_tokens = usage.completion_tokens;
+            }
+        }
+    };
+    
+    while let Some(bytes) = stream.next().await {
+        let bytes = bytes.context("Failed to read streamed response")?;
+        buffer.extend_from_slice(&bytes);
         
-        // Add more context to specific error codes
-        let error_msg = match status.as_u16() {
-            404 => format!("Model not found. Status {}: {}", status, error_text),
-            400 => format!("Bad request to Ollama API. Status {}: {}", status, error_text),
-            412 => format!("Ollama version incompatibility. Please update Ollama (brew upgrade ollama). Status {}: {}", status, error_text),
-            500 => format!("Ollama server error. Status {}: {}", status, error_text),
-            _ => format!("Ollama API request failed with status {}: {}", status, error_text),
-        };
-        
-        return Err(anyhow::anyhow!(error_msg));
+        // Only split on complete lines so multi-byte characters are never cut in half
+        while let Some(newline) = buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = buffer.drain(..=newline).collect();
+            if let Some(piece) = parse_line(String::from_utf8_lossy(&line).trim())? {
+                handle_piece(piece, &mut full_text);
+            }
+        }
+    }
+    
+    if let Some(piece) = parse_line(String::from_utf8_lossy(&buffer).trim())? {
+        handle_piece(piece, &mut full_text);
     }
+    
+    Ok((full_text, reported))
+}
 
-    let ollama_response = response
-        .json::<OllamaChatResponse>()
-        .await
-        .context("failed to parse JSON response from Ollama API")?;
+/// Record a call's token usage, estimating from the text when the provider didn't report it
+fn record_usage(provider: LlmProvider, model_name: &str, reported: Option<TokenUsage>, prompt: &str, response: &str) {
+    match reported {
+        Some(tokens) => usage::record(&provider, model_name, tokens, false),
+        None => usage::record(&provider, model_name, TokenUsage::estimate(prompt, response), true),
+    }
+}
 
-    Ok(ollama_response.message.content)
+/// Usage object in the OpenAI format used by OpenRouter
+fn openai_token_usage(usage: &serde_json::Value) -> Option<TokenUsage> {
+    Some(TokenUsage {
+        prompt_tokens: usage["prompt_tokens"].as_u64()?,
+        completion_tokens: usage["completion_tokens"].as_u64()?,
+    })
+}
+
+fn ollama_token_usage(prompt_eval_count: Option<u64>, eval_count: Option<u64>) -> Option<TokenUsage> {
+    Some(TokenUsage {
+        // Ollama omits prompt_eval_count when the prompt was served from its cache
+        prompt_tokens: prompt_eval_count.unwrap_or(0),
+        completion_tokens: eval_count?,
+    })
+}
+
+/// Text delta or usage from one line of Anthropic's server-sent event stream
+fn parse_anthropic_stream_line(line: &str) -> anyhow::Result<Option<StreamPiece>> {
+    let Some(data) = line.strip_prefix("data:") else {
+        return Ok(None);
+    };
+    let event: serde_json::Value = serde_json::from_str(data.trim())
+        .context("Failed to parse streamed event from Anthropic API")?;
+    
+    match event["type"].as_str() {
+        Some("content_block_delta") => Ok(event["delta"]["text"].as_str().map(|text| StreamPiece::Text(text.to_string()))),
+        Some("message_start") => Ok(event["message"]["usage"]["input_tokens"].as_u64().map(|prompt_tokens| {
+            StreamPiece::Usage(TokenUsage { prompt_tokens, completion_tokens: 0 })
+        })),
+        Some("message_delta") => Ok(event["usage"]["output_tokens"].as_u64().map(|completion_tokens| {
+            StreamPiece::Usage(TokenUsage { prompt_tokens: 0, completion_tokens })
+        })),
+        Some("error") => Err(anyhow::anyhow!(
+            "Anthropic API stream error: {}",
+            event["error"]["message"].as_str().unwrap_or("Unknown error")
+        )),
+        _ => Ok(None),
+    }
+}
+
+/// Text delta or usage from one line of OpenRouter's OpenAI-style server-sent event stream
+fn parse_openrouter_stream_line(line: &str) -> anyhow::Result<Option<StreamPiece>> {
+    let Some(data) = line.strip_prefix("data:") else {
+        return Ok(None);
+    };
+    let data = data.trim();
+    if data == "[DONE]" {
+        return Ok(None);
+    }
+    let event: serde_json::Value = serde_json::from_str(data)
+        .context("Failed to parse streamed event from OpenRouter API")?;
+    
+    if let Some(message) = event["error"]["message"].as_str() {
+        return Err(anyhow::anyhow!("OpenRouter API stream error: {}", message));
+    }
+    
+    // The final chunk carries the usage totals
+    if let Some(usage) = openai_token_usage(&event["usage"]) {
+        return Ok(Some(StreamPiece::Usage(usage)));
+    }
+    
+    Ok(event["choices"][0]["delta"]["content"].as_str()
+        .filter(|content| !content.is_empty())
+        .map(|content| StreamPiece::Text(content.to_string())))
+}
+
+/// Text or usage from one line of Ollama's newline-delimited JSON stream
+fn parse_ollama_stream_line(line: &str) -> anyhow::Result<Option<StreamPiece>> {
+    if line.is_empty() {
+        return Ok(None);
+    }
+    let chunk: serde_json::Value = serde_json::from_str(line)
+        .context("Failed to parse streamed chunk from Ollama API")?;
+    
+    if let Some(error) = chunk["error"].as_str() {
+        return Err(anyhow::anyhow!("Ollama stream error: {}", error));
+    }
+    
+    // The final chunk (done: true) carries the token counts
+    if chunk["done"].as_bool() == Some(true) {
+        if let Some(usage) = ollama_token_usage(chunk["prompt_eval_count"].as_u64(), chunk["eval_count"].as_u64()) {
+            return Ok(Some(StreamPiece::Usage(usage)));
+        }
+    }
+    
+    Ok(chunk["message"]["content"].as_str()
+        .filter(|content| !content.is_empty())
+        .map(|content| StreamPiece::Text(content.to_string())))
 }
 
 pub async fn generate_commit_message(original_prompt: &str, git_diff: &str) -> anyhow::Result<String> {
     let prompts_config = PromptsConfig::load().unwrap_or_default();
     
-    // Try Anthropic first if API key is available
-    if let Ok(api_key) = std::env::var("ANTHROPIC_API_KEY") {
+    // Diffs touching local-only files never leave the machine
+    if let Some(reason) = router::local_only_reason(&prompts_config.local_only, &diff_files(git_diff)) {
+        eprintln!("Note: generating the commit message with Ollama: {}", reason);
+        return generate_commit_message_ollama(original_prompt, git_diff, &prompts_config).await;
+    }
+    
+    // The providers below are mostly in the cloud, and a diff adding a key is the likeliest leak
+    let reviewed = secrets::review_prompt(
+        AssembledPrompt { user: original_prompt.to_string(), context: git_diff.to_string(), ..Default::default() },
+        &prompts_config.secrets,
+    ).await?;
+    let (original_prompt, git_diff) = (reviewed.user.as_str(), reviewed.context.as_str());
+    
+    // A [providers] quick model, e.g. on Groq, keeps commit messages off the main provider
+    if let Some(quick) = router::quick_model(&prompts_config) {
+        let prompt = prompts_config.get_anthropic_commit_prompt(original_prompt, git_diff);
+        match ask_quick_with(&prompt, &quick, &prompts_config, true).await {
+            Ok(message) => return Ok(message.trim().to_string()),
+            Err(e) => {
+                eprintln!("Warning: {} commit generation failed: {}. Trying other providers...", quick.display_name(), e);
+            }
+        }
+    }
+    
+    // Try Anthropic first if API key is available and its budget isn't used up
+    if let Some(api_key) = std::env::var("ANTHROPIC_API_KEY").ok().filter(|_| router::within_budget(&LlmProvider::Anthropic, &prompts_config)) {
         match generate_commit_message_anthropic(original_prompt, git_diff, &api_key, &prompts_config).await {
             Ok(message) => return Ok(message),
             Err(e) => {
@@ -325,11 +1601,22 @@ pub async fn generate_commit_message(original_prompt: &str, git_diff: &str) -> a
     }
     
     // Try Gemini next, fallback to Ollama if API key not available
-    if let Ok(api_key) = std::env::var("GEMINI_API_KEY") {
+    if let Some(api_key) = std::env::var("GEMINI_API_KEY").ok().filter(|_| router::within_budget(&LlmProvider::Gemini, &prompts_config)) {
         match generate_commit_message_gemini(original_prompt, git_diff, &api_key, &prompts_config).await {
             Ok(message) => return Ok(message),
             Err(e) => {
-                eprintln!("Warning: Gemini commit generation failed: {}. Falling back to Ollama...", e);
+                eprintln!("Warning: Gemini commit generation failed: {}. Trying other providers...", e);
+            }
+        }
+    }
+    
+    // OpenRouter uses the same prompt as Anthropic
+    if std::env::var("OPENROUTER_API_KEY").is_ok() && router::within_budget(&LlmProvider::OpenRouter, &prompts_config) {
+        let prompt = prompts_config.get_anthropic_commit_prompt(original_prompt, git_diff);
+        match ask_openrouter_model(&prompt, "", "", &[], OPENROUTER_FAST_MODEL, &SamplingParams::default(), ResponseFormat::Text, None).await {
+            Ok(message) => return Ok(message.trim().to_string()),
+            Err(e) => {
+                eprintln!("Warning: OpenRouter commit generation failed: {}. Falling back to Ollama...", e);
             }
         }
     }
@@ -338,40 +1625,54 @@ pub async fn generate_commit_message(original_prompt: &str, git_diff: &str) -> a
     generate_commit_message_ollama(original_prompt, git_diff, &prompts_config).await
 }
 
+/// Paths named by `diff --git a/x b/x` headers
+fn diff_files(git_diff: &str) -> Vec<String> {
+    git_diff
+        .lines()
+        .filter_map(|line| line.strip_prefix("diff --git a/"))
+        .filter_map(|rest| rest.split(" b/").next())
+        .map(|path| path.to_string())
+        .collect()
+}
+
 async fn generate_commit_message_gemini(original_prompt: &str, git_diff: &str, api_key: &str, prompts_config: &PromptsConfig) -> anyhow::Result<String> {
     let ai = Gemini::new(api_key.to_string(), GEMINI_COMMIT_MODEL, None);
-    let mut session = Session::new(2); // Simple session for commit messages
     
     let prompt = prompts_config.get_gemini_commit_prompt(original_prompt, git_diff);
     
     // Wrap the API call with a timeout (use half the main timeout for commit messages)
-    let response = timeout(
-        Duration::from_secs(GEMINI_TIMEOUT_SECS / 2),
-        ai.ask(session.ask_string(&prompt))
-    )
-    .await
-    .map_err(|_| anyhow::anyhow!("Gemini commit generation timed out after {} seconds", GEMINI_TIMEOUT_SECS / 2))?
-    .map_err(|e| anyhow::anyhow!("Gemini commit generation error: {}", e))?;
+    let response = with_retry("Gemini", || async {
+        let mut session = Session::new(2); // Simple session for commit messages
+        timeout(
+            Duration::from_secs(GEMINI_TIMEOUT_SECS / 2),
+            ai.ask(session.ask_string(&prompt))
+        )
+        .await
+        .map_err(|e| anyhow::Error::new(e).context(format!("Gemini commit generation timed out after {} seconds", GEMINI_TIMEOUT_SECS / 2)))?
+        .map_err(|e| anyhow::anyhow!("Gemini commit generation error: {}", e))
+    }).await?;
     
     // Clean up the response (remove any extra whitespace/newlines)
     let commit_message = response.get_text("").trim().to_string();
+    record_usage(LlmProvider::Gemini, GEMINI_COMMIT_MODEL, None, &prompt, &commit_message);
     
     Ok(commit_message)
 }
 
 async fn generate_commit_message_anthropic(original_prompt: &str, git_diff: &str, api_key: &str, prompts_config: &PromptsConfig) -> anyhow::Result<String> {
-    let client = ClientBuilder::new()
+    let client = connection::builder(&LlmProvider::Anthropic)?
         .timeout(Duration::from_secs(ANTHROPIC_TIMEOUT_SECS / 2))  // Half timeout for commit messages
         .connect_timeout(Duration::from_secs(10))
         .build()
         .context("Failed to create HTTP client")?;
+    let api_url = connection::url(&LlmProvider::Anthropic, ANTHROPIC_API_URL);
     
     let prompt = prompts_config.get_anthropic_commit_prompt(original_prompt, git_diff);
     
     let messages = vec![
         AnthropicMessage {
             role: "user".to_string(),
-            content: prompt,
+            content: prompt.clone(),
         },
     ];
     
@@ -381,25 +1682,30 @@ async fn generate_commit_message_anthropic(original_prompt: &str, git_diff: &str
         "max_tokens": 1024,
     });
     
-    let response = client
-        .post(ANTHROPIC_API_URL)
-        .header("x-api-key", api_key)
-        .header("anthropic-version", "2023-06-01")
-        .header("content-type", "application/json")
-        .json(&request_payload)
-        .send()
-        .await
-        .map_err(|e| {
-            if e.is_timeout() {
-                anyhow::anyhow!("Anthropic commit generation timed out after {} seconds", ANTHROPIC_TIMEOUT_SECS / 2)
-            } else {
-                anyhow::anyhow!("Failed to generate commit message via Anthropic: {}", e)
+    let response = with_retry("Anthropic", || {
+        let request = client
+            .post(&api_url)
+            .header("x-api-key", api_key)
+            .header("anthropic-version", "2023-06-01")
+            .header("content-type", "application/json")
+            .json(&request_payload);
+        async move {
+            let response = request.send().await.map_err(|e| {
+                let message = if e.is_timeout() {
+                    format!("Anthropic commit generation timed out after {} seconds", ANTHROPIC_TIMEOUT_SECS / 2)
+                } else {
+                    format!("Failed to generate commit message via Anthropic: {}", e)
+                };
+                anyhow::Error::new(e).context(message)
+            })?;
+            
+            if !response.status().is_success() {
+                let message = format!("Failed to generate commit message: HTTP {}", response.status());
+                return Err(anyhow::Error::new(HttpStatusError::from_response(&response)).context(message));
             }
-        })?;
-    
-    if !response.status().is_success() {
-        return Err(anyhow::anyhow!("Failed to generate commit message: HTTP {}", response.status()));
-    }
+            Ok(response)
+        }
+    }).await?;
     
     let anthropic_response: AnthropicResponse = response
         .json()
@@ -414,22 +1720,25 @@ async fn generate_commit_message_anthropic(original_prompt: &str, git_diff: &str
         .map(|c| c.text.trim().to_string())
         .unwrap_or_else(|| "No commit message generated".to_string());
     
+    record_usage(LlmProvider::Anthropic, DEFAULT_ANTHROPIC_MODEL, anthropic_response.usage.map(TokenUsage::from), &prompt, &commit_message);
+    
     Ok(commit_message)
 }
 
 async fn generate_commit_message_ollama(original_prompt: &str, git_diff: &str, prompts_config: &PromptsConfig) -> anyhow::Result<String> {
-    let client = ClientBuilder::new()
+    let client = connection::builder(&LlmProvider::Ollama)?
         .timeout(Duration::from_secs(60))  // 1 minute timeout for commit message generation
         .connect_timeout(Duration::from_secs(10))
         .build()
         .context("Failed to create HTTP client")?;
+    let api_url = connection::url(&LlmProvider::Ollama, OLLAMA_API_URL);
 
     let prompt = prompts_config.get_ollama_commit_prompt(original_prompt, git_diff);
 
     let messages = vec![
         OllamaChatMessage {
             role: "user".to_string(),
-            content: prompt,
+            content: prompt.clone(),
         },
     ];
 
@@ -437,26 +1746,31 @@ async fn generate_commit_message_ollama(original_prompt: &str, git_diff: &str, p
         model: DEFAULT_OLLAMA_MODEL.to_string(),
         messages,
         stream: false,
+        options: None,
+        format: None,
     };
 
-    let response = client
-        .post(OLLAMA_API_URL)
-        .json(&request_payload)
-        .send()
-        .await
-        .map_err(|e| {
-            if e.is_connect() {
-                anyhow::anyhow!("Failed to connect to Ollama API for commit message generation")
-            } else if e.is_timeout() {
-                anyhow::anyhow!("Commit message generation timed out")
-            } else {
-                anyhow::anyhow!("Failed to generate commit message: {}", e)
-            }
-        })?;
+    let response = with_retry("Ollama", || {
+        let request = client.post(&api_url).json(&request_payload);
+        async move {
+            let response = request.send().await.map_err(|e| {
+                let message = if e.is_connect() {
+                    "Failed to connect to Ollama API for commit message generation".to_string()
+                } else if e.is_timeout() {
+                    "Commit message generation timed out".to_string()
+                } else {
+                    format!("Failed to generate commit message: {}", e)
+                };
+                anyhow::Error::new(e).context(message)
+            })?;
 
-    if !response.status().is_success() {
-        return Err(anyhow::anyhow!("Failed to generate commit message: HTTP {}", response.status()));
-    }
+            if !response.status().is_success() {
+                let message = format!("Failed to generate commit message: HTTP {}", response.status());
+                return Err(anyhow::Error::new(HttpStatusError::from_response(&response)).context(message));
+            }
+            Ok(response)
+        }
+    }).await?;
 
     let ollama_response = response
         .json::<OllamaChatResponse>()
@@ -465,6 +1779,118 @@ async fn generate_commit_message_ollama(original_prompt: &str, git_diff: &str, p
 
     // Clean up the response (remove any extra whitespace/newlines)
     let commit_message = ollama_response.message.content.trim().to_string();
+    record_usage(LlmProvider::Ollama, DEFAULT_OLLAMA_MODEL, ollama_response.token_usage(), &prompt, &commit_message);
     
     Ok(commit_message)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sampling_params_fall_back_and_apply() {
+        let session = SamplingParams { temperature: Some(0.2), ..Default::default() };
+        let config = SamplingParams { temperature: Some(1.0), top_p: Some(0.9), max_tokens: None, seed: Some(7) };
+        let sampling = session.or(&config);
+        assert_eq!(sampling, SamplingParams { temperature: Some(0.2), top_p: Some(0.9), max_tokens: None, seed: Some(7) });
+        assert_eq!(sampling.summary(), "temp 0.2 top_p 0.9 seed 7");
+
+        let mut payload = serde_json::json!({ "model": "m", "max_tokens": DEFAULT_MAX_TOKENS });
+        sampling.apply_to(&mut payload);
+        assert_eq!(payload["temperature"], 0.2);
+        assert_eq!(payload["max_tokens"], DEFAULT_MAX_TOKENS);
+        assert!(payload.get("seed").is_none());
+        sampling.apply_seed(&mut payload);
+        assert_eq!(payload["seed"], 7);
+
+        let options = SamplingParams { max_tokens: Some(512), ..Default::default() }.ollama_options().unwrap();
+        assert_eq!(options, serde_json::json!({ "num_predict": 512 }));
+        assert!(SamplingParams::default().ollama_options().is_none());
+        assert_eq!(sampled_model_key("m", &SamplingParams::default()), "m");
+    }
+
+    #[test]
+    fn test_parse_anthropic_stream_line() {
+        let delta = r#"data: {"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":"Hello"}}"#;
+        assert_eq!(parse_anthropic_stream_line(delta).unwrap(), Some(StreamPiece::Text("Hello".to_string())));
+        assert_eq!(parse_anthropic_stream_line("event: content_block_delta").unwrap(), None);
+        assert_eq!(parse_anthropic_stream_line(r#"data: {"type":"message_stop"}"#).unwrap(), None);
+        
+        let start = r#"data: {"type":"message_start","message":{"id":"msg_1","usage":{"input_tokens":25,"output_tokens":1}}}"#;
+        assert_eq!(parse_anthropic_stream_line(start).unwrap(), Some(StreamPiece::Usage(TokenUsage { prompt_tokens: 25, completion_tokens: 0 })));
+        let delta = r#"data: {"type":"message_delta","delta":{"stop_reason":"end_turn"},"usage":{"output_tokens":15}}"#;
+        assert_eq!(parse_anthropic_stream_line(delta).unwrap(), Some(StreamPiece::Usage(TokenUsage { prompt_tokens: 0, completion_tokens: 15 })));
+        
+        let error = r#"data: {"type":"error","error":{"type":"overloaded_error","message":"Overloaded"}}"#;
+        assert!(parse_anthropic_stream_line(error).is_err());
+    }
+
+    #[test]
+    fn test_parse_openrouter_stream_line() {
+        let delta = r#"data: {"id":"gen-1","choices":[{"index":0,"delta":{"role":"assistant","content":"Hi"}}]}"#;
+        assert_eq!(parse_openrouter_stream_line(delta).unwrap(), Some(StreamPiece::Text("Hi".to_string())));
+        assert_eq!(parse_openrouter_stream_line(": OPENROUTER PROCESSING").unwrap(), None);
+        assert_eq!(parse_openrouter_stream_line("data: [DONE]").unwrap(), None);
+        
+        let usage = r#"data: {"id":"gen-1","choices":[{"index":0,"delta":{"content":""}}],"usage":{"prompt_tokens":12,"completion_tokens":3,"total_tokens":15}}"#;
+        assert_eq!(parse_openrouter_stream_line(usage).unwrap(), Some(StreamPiece::Usage(TokenUsage { prompt_tokens: 12, completion_tokens: 3 })));
+    }
+
+    #[test]
+    fn test_parse_openrouter_models() {
+        let body = serde_json::json!({
+            "data": [
+                {"id": "openai/gpt-4o", "name": "OpenAI: GPT-4o", "context_length": 128000, "pricing": {"prompt": "0.0000025"}},
+                {"id": "anthropic/claude-3.5-haiku", "name": "Anthropic: Claude 3.5 Haiku", "context_length": 200000},
+                {"name": "missing id"}
+            ]
+        });
+        
+        let models = parse_openrouter_models(&body);
+        assert_eq!(models.len(), 2);
+        assert_eq!(models[0].id, "anthropic/claude-3.5-haiku");
+        assert_eq!(models[0].prompt_price, None);
+        assert!((models[1].prompt_price.unwrap() - 2.5).abs() < 1e-9);
+        
+        let filtered = filter_openrouter_models(&models, "GPT");
+        assert_eq!(filtered.len(), 1);
+        assert_eq!(filtered[0].id, "openai/gpt-4o");
+        assert_eq!(filter_openrouter_models(&models, "").len(), 2);
+    }
+
+    #[test]
+    fn test_parse_ollama_models_and_pull_progress() {
+        let body = serde_json::json!({
+            "models": [
+                {"name": "qwen3:8b", "size": 5_200_000_000u64, "details": {"parameter_size": "8.2B"}},
+                {"name": "llama3:latest", "size": 4_700_000_000u64}
+            ]
+        });
+        
+        let models = parse_ollama_models(&body);
+        assert_eq!(models[0].name, "llama3:latest");
+        assert_eq!(models[1].parameter_size.as_deref(), Some("8.2B"));
+        assert!(ollama_has_model(&models, "qwen3:8b"));
+        assert!(ollama_has_model(&models, "llama3"));
+        assert!(!ollama_has_model(&models, "qwen3:14b"));
+        
+        let progress = r#"{"status":"pulling 6a0746a1ec1a","digest":"sha256:6a07","total":2000,"completed":500}"#;
+        assert_eq!(parse_ollama_pull_line(progress).unwrap().as_deref(), Some("pulling 6a0746a1ec1a 25%"));
+        assert_eq!(parse_ollama_pull_line(r#"{"status":"success"}"#).unwrap().as_deref(), Some("success"));
+        assert!(parse_ollama_pull_line(r#"{"error":"pull model manifest: file does not exist"}"#).is_err());
+    }
+
+    #[test]
+    fn test_parse_ollama_stream_line() {
+        let chunk = r#"{"model":"qwen3:8b","message":{"role":"assistant","content":"Hi"},"done":false}"#;
+        assert_eq!(parse_ollama_stream_line(chunk).unwrap(), Some(StreamPiece::Text("Hi".to_string())));
+        
+        let done = r#"{"model":"qwen3:8b","message":{"role":"assistant","content":""},"done":true}"#;
+        assert_eq!(parse_ollama_stream_line(done).unwrap(), None);
+        
+        let done = r#"{"model":"qwen3:8b","message":{"role":"assistant","content":""},"done":true,"prompt_eval_count":40,"eval_count":8}"#;
+        assert_eq!(parse_ollama_stream_line(done).unwrap(), Some(StreamPiece::Usage(TokenUsage { prompt_tokens: 40, completion_tokens: 8 })));
+        assert_eq!(parse_ollama_stream_line("").unwrap(), None);
+    }
+}
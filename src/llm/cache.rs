@@ -0,0 +1,179 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+
+use super::LlmProvider;
+
+pub const CACHE_DIR: &str = ".kota/cache/llm";
+
+/// Identifies a query: the same provider, model, prompt and context always get the same key
+#[derive(Debug, Clone, PartialEq)]
+pub struct CacheKey {
+    provider: String,
+    model: String,
+    prompt: String,
+    // System instructions are hashed with the context, so editing prompts.toml invalidates entries
+    context_hash: u64,
+}
+
+impl CacheKey {
+    pub fn new(provider: &LlmProvider, model: &str, system_instructions: &str, user_prompt: &str, context_str: &str) -> Self {
+        Self {
+            provider: format!("{:?}", provider),
+            model: model.to_string(),
+            prompt: user_prompt.to_string(),
+            context_hash: fnv1a(&[system_instructions, context_str]),
+        }
+    }
+
+    fn file_name(&self) -> String {
+        let hash = fnv1a(&[&self.provider, &self.model, &self.prompt]) ^ self.context_hash;
+        format!("{:016x}.json", hash)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheEntry {
+    provider: String,
+    model: String,
+    prompt: String,
+    context_hash: u64,
+    response: String,
+    created_at: DateTime<Utc>,
+}
+
+impl CacheEntry {
+    fn matches(&self, key: &CacheKey) -> bool {
+        self.provider == key.provider
+            && self.model == key.model
+            && self.prompt == key.prompt
+            && self.context_hash == key.context_hash
+    }
+}
+
+/// Responses stored as one JSON file per query under `.kota/cache/llm`
+pub struct ResponseCache {
+    dir: PathBuf,
+    ttl: Duration,
+}
+
+impl ResponseCache {
+    pub fn new(ttl_hours: u64) -> Self {
+        Self::with_dir(CACHE_DIR, ttl_hours)
+    }
+
+    pub fn with_dir(dir: impl Into<PathBuf>, ttl_hours: u64) -> Self {
+        Self {
+            dir: dir.into(),
+            ttl: Duration::hours(ttl_hours.min(i64::MAX as u64 / 3600) as i64),
+        }
+    }
+
+    /// Cached response for `key`, if there is one that hasn't expired
+    pub fn get(&self, key: &CacheKey) -> Option<String> {
+        let json = fs::read_to_string(self.dir.join(key.file_name())).ok()?;
+        let entry: CacheEntry = serde_json::from_str(&json).ok()?;
+
+        // Hash collisions and stale entries are treated as misses
+        if !entry.matches(key) || Utc::now() - entry.created_at > self.ttl {
+            return None;
+        }
+        Some(entry.response)
+    }
+
+    pub fn put(&self, key: &CacheKey, response: &str) -> Result<()> {
+        fs::create_dir_all(&self.dir)
+            .with_context(|| format!("Failed to create cache directory: {}", self.dir.display()))?;
+
+        let entry = CacheEntry {
+            provider: key.provider.clone(),
+            model: key.model.clone(),
+            prompt: key.prompt.clone(),
+            context_hash: key.context_hash,
+            response: response.to_string(),
+            created_at: Utc::now(),
+        };
+        let path = self.dir.join(key.file_name());
+        fs::write(&path, serde_json::to_string(&entry)?)
+            .with_context(|| format!("Failed to write cache entry {}", path.display()))
+    }
+
+    /// Number of cached responses on disk
+    pub fn len(&self) -> usize {
+        self.entry_paths().len()
+    }
+
+    /// Delete every cached response. Returns how many were removed.
+    pub fn clear(&self) -> Result<usize> {
+        let paths = self.entry_paths();
+        for path in &paths {
+            fs::remove_file(path)
+                .with_context(|| format!("Failed to remove cache entry {}", path.display()))?;
+        }
+        Ok(paths.len())
+    }
+
+    fn entry_paths(&self) -> Vec<PathBuf> {
+        let Ok(entries) = fs::read_dir(&self.dir) else {
+            return Vec::new();
+        };
+        entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().and_then(|ext| ext.to_str()) == Some("json"))
+            .collect()
+    }
+}
+
+// FNV-1a, so cache file names stay stable across Rust versions
+pub(crate) fn fnv1a(parts: &[&str]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for part in parts {
+        // Separate parts so ("ab", "c") and ("a", "bc") hash differently
+        for byte in part.bytes().chain(std::iter::once(0xff)) {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_cache_roundtrip_and_clear() {
+        let dir = TempDir::new().unwrap();
+        let cache = ResponseCache::with_dir(dir.path(), 24);
+        let key = CacheKey::new(&LlmProvider::Anthropic, "claude-sonnet-4-20250514", "system", "What does main do?", "main.rs contents");
+
+        assert_eq!(cache.get(&key), None);
+        cache.put(&key, "It starts the CLI").unwrap();
+        assert_eq!(cache.get(&key), Some("It starts the CLI".to_string()));
+
+        // Any change to the query is a miss
+        let other_model = CacheKey::new(&LlmProvider::Anthropic, "claude-3-5-haiku-20241022", "system", "What does main do?", "main.rs contents");
+        let other_context = CacheKey::new(&LlmProvider::Anthropic, "claude-sonnet-4-20250514", "system", "What does main do?", "edited main.rs");
+        assert_eq!(cache.get(&other_model), None);
+        assert_eq!(cache.get(&other_context), None);
+
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.clear().unwrap(), 1);
+        assert_eq!(cache.get(&key), None);
+    }
+
+    #[test]
+    fn test_expired_entries_are_misses() {
+        let dir = TempDir::new().unwrap();
+        let cache = ResponseCache::with_dir(dir.path(), 0);
+        let key = CacheKey::new(&LlmProvider::Ollama, "qwen3:8b", "", "hi", "");
+
+        cache.put(&key, "hello").unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        assert_eq!(cache.get(&key), None);
+    }
+}
@@ -0,0 +1,140 @@
+use std::fs;
+
+use anyhow::Context;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Certificate, ClientBuilder, Proxy};
+
+use super::LlmProvider;
+use crate::prompts::{ConnectionConfig, PromptsConfig, ProvidersConfig};
+
+/// The start of each provider's built-in URLs, which `base_url` replaces
+fn default_base(provider: &LlmProvider) -> Option<&'static str> {
+    match provider {
+        LlmProvider::Anthropic => Some("https://api.anthropic.com"),
+        LlmProvider::OpenRouter => Some("https://openrouter.ai/api/v1"),
+        LlmProvider::Ollama => Some("http://localhost:11434"),
+        LlmProvider::Groq => Some("https://api.groq.com/openai/v1"),
+        LlmProvider::Together => Some("https://api.together.xyz/v1"),
+        // Gemini goes through its client library; Azure and Bedrock have their own endpoint settings
+        _ => None,
+    }
+}
+
+fn base_url<'a>(providers: &'a ProvidersConfig, provider: &LlmProvider) -> Option<&'a str> {
+    let endpoint = match provider {
+        LlmProvider::Anthropic => &providers.anthropic,
+        LlmProvider::OpenRouter => &providers.openrouter,
+        LlmProvider::Ollama => &providers.ollama,
+        LlmProvider::Groq => &providers.groq,
+        LlmProvider::Together => &providers.together,
+        _ => return None,
+    };
+    endpoint.base_url.as_deref().map(str::trim).filter(|url| !url.is_empty())
+}
+
+fn connection<'a>(providers: &'a ProvidersConfig, provider: &LlmProvider) -> Option<&'a ConnectionConfig> {
+    match provider {
+        LlmProvider::Anthropic => Some(&providers.anthropic.connection),
+        LlmProvider::OpenRouter => Some(&providers.openrouter.connection),
+        LlmProvider::Ollama => Some(&providers.ollama.connection),
+        LlmProvider::Groq => Some(&providers.groq.connection),
+        LlmProvider::Together => Some(&providers.together.connection),
+        LlmProvider::Azure => Some(&providers.azure.connection),
+        LlmProvider::Bedrock => Some(&providers.bedrock.connection),
+        LlmProvider::Gemini => None,
+    }
+}
+
+/// `default_url` with the provider's default base swapped for `[providers.<name>] base_url`
+pub fn url(provider: &LlmProvider, default_url: &str) -> String {
+    let providers = PromptsConfig::load().unwrap_or_default().providers;
+    rebase(default_url, default_base(provider), base_url(&providers, provider))
+}
+
+fn rebase(default_url: &str, default_base: Option<&str>, base_url: Option<&str>) -> String {
+    match (default_base.and_then(|base| default_url.strip_prefix(base)), base_url) {
+        (Some(path), Some(base)) => format!("{}{}", base.trim_end_matches('/'), path),
+        _ => default_url.to_string(),
+    }
+}
+
+/// A client builder with the provider's proxy, extra headers and TLS settings from
+/// `[providers.<name>]`; callers add their timeouts
+pub fn builder(provider: &LlmProvider) -> anyhow::Result<ClientBuilder> {
+    let providers = PromptsConfig::load().unwrap_or_default().providers;
+    match connection(&providers, provider) {
+        Some(config) => apply(ClientBuilder::new(), config, provider.name()),
+        None => Ok(ClientBuilder::new()),
+    }
+}
+
+fn apply(mut builder: ClientBuilder, config: &ConnectionConfig, name: &str) -> anyhow::Result<ClientBuilder> {
+    match config.proxy.as_deref().map(str::trim) {
+        None | Some("") => {}
+        Some("none") => builder = builder.no_proxy(),
+        Some(proxy) => {
+            let proxy = Proxy::all(proxy).with_context(|| format!("Bad proxy for {} in [providers.{}]: {}", name, name, proxy))?;
+            builder = builder.proxy(proxy);
+        }
+    }
+    if !config.headers.is_empty() {
+        let mut headers = HeaderMap::new();
+        for (header, value) in &config.headers {
+            let header = HeaderName::from_bytes(header.trim().as_bytes())
+                .with_context(|| format!("Bad header name in [providers.{}] headers: {}", name, header))?;
+            let value = HeaderValue::from_str(value).with_context(|| format!("Bad value for header {} in [providers.{}]", header, name))?;
+            headers.insert(header, value);
+        }
+        builder = builder.default_headers(headers);
+    }
+    if let Some(path) = &config.ca_cert {
+        let pem = fs::read(path).with_context(|| format!("Failed to read ca_cert for {}: {}", name, path))?;
+        let certificate = Certificate::from_pem(&pem).with_context(|| format!("{} isn't a PEM certificate", path))?;
+        builder = builder.add_root_certificate(certificate);
+    }
+    if config.accept_invalid_certs {
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+    Ok(builder)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rebase() {
+        let anthropic = default_base(&LlmProvider::Anthropic);
+        assert_eq!(
+            rebase("https://api.anthropic.com/v1/messages", anthropic, Some("https://gateway.corp.example/anthropic/")),
+            "https://gateway.corp.example/anthropic/v1/messages"
+        );
+        assert_eq!(rebase("https://api.anthropic.com/v1/messages", anthropic, None), "https://api.anthropic.com/v1/messages");
+        assert_eq!(
+            rebase("https://openrouter.ai/api/v1/chat/completions", default_base(&LlmProvider::OpenRouter), Some("http://localhost:1234/v1")),
+            "http://localhost:1234/v1/chat/completions"
+        );
+        assert_eq!(rebase("https://example.com/x", anthropic, Some("http://other")), "https://example.com/x");
+    }
+
+    #[test]
+    fn test_provider_settings() {
+        let providers: ProvidersConfig = toml::from_str(
+            "[anthropic]\nbase_url = \"https://gw.example\"\nproxy = \"http://proxy.example:8080\"\nheaders = { \"X-Team\" = \"core\" }\n\
+             [azure]\nendpoint = \"https://r.openai.azure.com\"\nproxy = \"none\"\n",
+        )
+        .unwrap();
+        assert_eq!(base_url(&providers, &LlmProvider::Anthropic), Some("https://gw.example"));
+        assert_eq!(base_url(&providers, &LlmProvider::Ollama), None);
+        assert_eq!(providers.azure.connection.proxy.as_deref(), Some("none"));
+        assert_eq!(providers.azure.endpoint, "https://r.openai.azure.com");
+        let anthropic = connection(&providers, &LlmProvider::Anthropic).unwrap();
+        assert!(apply(ClientBuilder::new(), anthropic, "anthropic").unwrap().build().is_ok());
+
+        let mut bad = anthropic.clone();
+        bad.headers.insert("Bad Header".to_string(), "x".to_string());
+        assert!(apply(ClientBuilder::new(), &bad, "anthropic").is_err());
+        let missing = ConnectionConfig { ca_cert: Some("/nonexistent/ca.pem".to_string()), ..ConnectionConfig::default() };
+        assert!(apply(ClientBuilder::new(), &missing, "ollama").is_err());
+    }
+}
@@ -0,0 +1,213 @@
+use crate::context::PINNED_MARKER;
+use crate::prompt_pipeline::AssembledPrompt;
+
+use super::{estimate_tokens, LlmProvider};
+
+/// Room left for the model's reply; matches the largest `max_tokens` KOTA asks for
+pub const RESPONSE_RESERVE_TOKENS: u64 = 4096;
+
+// Lines of a file kept when it's summarized to save room
+const SUMMARY_HEAD_LINES: usize = 20;
+
+/// Context window of a model when the provider's catalog didn't tell us
+pub fn default_context_window(provider: &LlmProvider, model: &str) -> u64 {
+    match provider {
+        LlmProvider::Anthropic | LlmProvider::Bedrock => 200_000,
+        LlmProvider::Gemini => 1_048_576,
+        LlmProvider::OpenRouter if model.starts_with("anthropic/") => 200_000,
+        LlmProvider::OpenRouter if model.starts_with("google/gemini") => 1_048_576,
+        // Groq and Together serve Llama 3.x models with 128k windows
+        LlmProvider::OpenRouter | LlmProvider::Azure | LlmProvider::Groq | LlmProvider::Together => 128_000,
+        // Ollama's own limit is set per model with num_ctx; this is a common size for local models
+        LlmProvider::Ollama => 32_768,
+    }
+}
+
+/// What had to go to make a prompt fit
+#[derive(Debug, Default, PartialEq)]
+pub struct FitReport {
+    pub original_tokens: u64,
+    pub final_tokens: u64,
+    pub summarized: usize,
+    pub dropped: usize,
+}
+
+impl FitReport {
+    pub fn changed(&self) -> bool {
+        self.summarized > 0 || self.dropped > 0
+    }
+}
+
+/// A stretch of the context: either one item added with /add_file or /add_snippet,
+/// or text around them (and pinned items) that is always kept
+enum Segment {
+    Item(String),
+    Other(String),
+}
+
+/// Shrink the context items, oldest first, until the prompt and a reply fit in `window` tokens.
+/// Items are first cut down to their opening lines, then dropped altogether. Text that isn't a
+/// context item (the file list, memories) and the conversation history are left alone since they have
+/// their own budgets.
+pub fn fit_to_window(mut prompt: AssembledPrompt, window: u64) -> (AssembledPrompt, FitReport) {
+    let limit = window.saturating_sub(RESPONSE_RESERVE_TOKENS);
+    let size = |prompt: &AssembledPrompt| {
+        estimate_tokens(&prompt.system) + estimate_tokens(&prompt.context) + estimate_tokens(&prompt.user) + prompt.history_tokens()
+    };
+    let mut report = FitReport { original_tokens: size(&prompt), ..Default::default() };
+    report.final_tokens = report.original_tokens;
+    if report.original_tokens <= limit {
+        return (prompt, report);
+    }
+
+    let mut segments = split_items(&prompt.context);
+    let fixed = estimate_tokens(&prompt.system) + estimate_tokens(&prompt.user) + prompt.history_tokens();
+    let context_size = |segments: &[Segment]| -> u64 {
+        segments.iter().map(|segment| match segment {
+            Segment::Item(text) | Segment::Other(text) => estimate_tokens(text),
+        }).sum()
+    };
+
+    // First cut the oldest items down to their opening lines, then leave them out entirely
+    let mut summarized = vec![false; segments.len()];
+    for drop in [false, true] {
+        for i in 0..segments.len() {
+            if fixed + context_size(&segments) <= limit {
+                break;
+            }
+            let Segment::Item(text) = &segments[i] else {
+                continue;
+            };
+            if drop {
+                segments[i] = Segment::Other(dropped_note(text));
+                report.dropped += 1;
+                if summarized[i] {
+                    report.summarized -= 1;
+                }
+            } else if let Some(summary) = summarize_item(text) {
+                segments[i] = Segment::Item(summary);
+                summarized[i] = true;
+                report.summarized += 1;
+            }
+        }
+    }
+
+    prompt.context = segments
+        .into_iter()
+        .map(|segment| match segment {
+            Segment::Item(text) | Segment::Other(text) => text,
+        })
+        .collect();
+    report.final_tokens = size(&prompt);
+    (prompt, report)
+}
+
+/// Split context text at the `--- File: x ---` / `--- End File: x ---` style markers
+/// the context manager wraps items in
+fn split_items(context: &str) -> Vec<Segment> {
+    let mut segments = Vec::new();
+    let mut current = String::new();
+    let mut in_item = false;
+    let mut pinned = false;
+
+    for line in context.split_inclusive('\n') {
+        let trimmed = line.trim();
+        if !in_item && is_item_start(trimmed) {
+            pinned = current.trim_end().ends_with(PINNED_MARKER);
+            if !current.is_empty() {
+                segments.push(Segment::Other(std::mem::take(&mut current)));
+            }
+            in_item = true;
+        }
+        current.push_str(line);
+        if in_item && trimmed.starts_with("--- End ") {
+            let item = std::mem::take(&mut current);
+            segments.push(if pinned { Segment::Other(item) } else { Segment::Item(item) });
+            in_item = false;
+        }
+    }
+    if !current.is_empty() {
+        // An item without an end marker is treated like any other text
+        segments.push(Segment::Other(current));
+    }
+    segments
+}
+
+fn is_item_start(line: &str) -> bool {
+    ["--- File: ", "--- Snippet ---", "--- Prompt File: ", "--- Document: "].iter().any(|marker| line.starts_with(marker))
+}
+
+/// Keep the item's opening lines and say how much was left out; None when it's already short
+fn summarize_item(item: &str) -> Option<String> {
+    let lines: Vec<&str> = item.lines().collect();
+    // The first and last lines are the item markers
+    if lines.len() <= SUMMARY_HEAD_LINES + 2 {
+        return None;
+    }
+    let omitted = lines.len() - SUMMARY_HEAD_LINES - 2;
+    Some(format!(
+        "{}\n[... {} more lines left out to fit the model's context window; ask the user to re-add the file if you need them ...]\n{}\n",
+        lines[..=SUMMARY_HEAD_LINES].join("\n"),
+        omitted,
+        lines[lines.len() - 1]
+    ))
+}
+
+/// One line standing in for an item that was left out, so the model knows it exists
+fn dropped_note(item: &str) -> String {
+    let name = item.lines().next().unwrap_or_default().trim().trim_matches('-').trim();
+    format!("[{} left out to fit the model's context window]\n\n", name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file_item(name: &str, lines: usize) -> String {
+        let body: Vec<String> = (0..lines).map(|i| format!("line {} of {}", i, name)).collect();
+        format!("--- File: {} ---\n{}\n--- End File: {} ---\n\n", name, body.join("\n"), name)
+    }
+
+    fn prompt_with(context: String) -> AssembledPrompt {
+        AssembledPrompt { system: "system".to_string(), context, user: "question".to_string(), history: Vec::new(), trace: Vec::new() }
+    }
+
+    #[test]
+    fn test_prompt_within_window_is_untouched() {
+        let context = format!("Files currently in context:\n- a.rs\n\n{}", file_item("a.rs", 10));
+        let (prompt, report) = fit_to_window(prompt_with(context.clone()), 200_000);
+        assert_eq!(prompt.context, context);
+        assert!(!report.changed());
+    }
+
+    #[test]
+    fn test_oldest_items_are_summarized_then_dropped() {
+        let header = "Files currently in context:\n- old.rs\n- new.rs\n\n".to_string();
+        let context = format!("{}{}{}", header, file_item("old.rs", 2000), file_item("new.rs", 200));
+        let full = estimate_tokens(&context);
+
+        // Summarizing the old file is enough
+        let (prompt, report) = fit_to_window(prompt_with(context.clone()), full / 2 + RESPONSE_RESERVE_TOKENS);
+        assert_eq!(report, FitReport { original_tokens: report.original_tokens, final_tokens: report.final_tokens, summarized: 1, dropped: 0 });
+        assert!(prompt.context.contains("more lines left out"));
+        assert!(prompt.context.contains("line 199 of new.rs"));
+        assert!(prompt.context.starts_with(&header));
+
+        // A tiny window drops items, oldest first, but keeps the file list
+        let (prompt, report) = fit_to_window(prompt_with(context), 200 + RESPONSE_RESERVE_TOKENS);
+        assert!(report.dropped >= 1);
+        assert!(!prompt.context.contains("line 0 of old.rs"));
+        assert!(prompt.context.contains("[File: old.rs left out"));
+        assert!(prompt.context.starts_with(&header));
+        assert!(report.final_tokens < report.original_tokens);
+    }
+
+    #[test]
+    fn test_pinned_items_are_kept_whole() {
+        let context = format!("{}{}\n{}", file_item("old.rs", 2000), PINNED_MARKER, file_item("pinned.rs", 2000));
+        let (prompt, report) = fit_to_window(prompt_with(context), 300 + RESPONSE_RESERVE_TOKENS);
+        assert_eq!(report.dropped, 1);
+        assert!(prompt.context.contains("[File: old.rs left out"));
+        assert!(prompt.context.contains("line 1999 of pinned.rs"));
+    }
+}
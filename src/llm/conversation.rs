@@ -0,0 +1,141 @@
+use std::sync::Mutex;
+
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use super::estimate_tokens;
+
+// Turns kept in memory; older ones are forgotten even when the budget would allow them
+const MAX_TURNS: usize = 50;
+
+/// One exchange of the interactive conversation, as it was sent and answered
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Turn {
+    pub user: String,
+    pub assistant: String,
+}
+
+impl Turn {
+    pub fn tokens(&self) -> u64 {
+        estimate_tokens(&self.user) + estimate_tokens(&self.assistant)
+    }
+}
+
+// This session's conversation, oldest first. Cleared with /history clear.
+static TURNS: Mutex<Vec<Turn>> = Mutex::new(Vec::new());
+
+/// Remember a finished exchange so follow-up requests carry it
+pub fn record(user: &str, assistant: &str) {
+    if user.trim().is_empty() || assistant.trim().is_empty() {
+        return;
+    }
+    let mut turns = TURNS.lock().unwrap_or_else(|e| e.into_inner());
+    turns.push(Turn { user: user.to_string(), assistant: assistant.to_string() });
+    let excess = turns.len().saturating_sub(MAX_TURNS);
+    turns.drain(..excess);
+}
+
+/// The newest turns that fit in `budget` estimated tokens, oldest first, and whether any were left out
+pub fn recent(budget: Option<u64>) -> (Vec<Turn>, bool) {
+    let turns = TURNS.lock().unwrap_or_else(|e| e.into_inner());
+    fit_to_budget(&turns, budget)
+}
+
+/// Every turn but the newest `keep`, oldest first
+pub fn older_than(keep: usize) -> Vec<Turn> {
+    let turns = TURNS.lock().unwrap_or_else(|e| e.into_inner());
+    turns[..turns.len().saturating_sub(keep)].to_vec()
+}
+
+/// Forget `oldest`, if the conversation still starts with them; returns how many were forgotten
+pub fn forget_oldest(oldest: &[Turn]) -> usize {
+    let mut turns = TURNS.lock().unwrap_or_else(|e| e.into_inner());
+    strip_oldest(&mut turns, oldest)
+}
+
+fn strip_oldest(turns: &mut Vec<Turn>, oldest: &[Turn]) -> usize {
+    if !turns.starts_with(oldest) {
+        return 0;
+    }
+    turns.drain(..oldest.len());
+    oldest.len()
+}
+
+/// Forget the conversation; returns how many turns there were
+pub fn clear() -> usize {
+    let mut turns = TURNS.lock().unwrap_or_else(|e| e.into_inner());
+    let count = turns.len();
+    turns.clear();
+    count
+}
+
+fn fit_to_budget(turns: &[Turn], budget: Option<u64>) -> (Vec<Turn>, bool) {
+    let Some(budget) = budget else {
+        return (turns.to_vec(), false);
+    };
+    let mut used = 0;
+    let kept = turns
+        .iter()
+        .rev()
+        .take_while(|turn| {
+            used += turn.tokens();
+            used <= budget
+        })
+        .count();
+    (turns[turns.len() - kept..].to_vec(), kept < turns.len())
+}
+
+/// Alternating user and assistant messages in the chat format most providers share
+pub fn messages(history: &[Turn]) -> Vec<Value> {
+    history
+        .iter()
+        .flat_map(|turn| {
+            [
+                json!({ "role": "user", "content": turn.user }),
+                json!({ "role": "assistant", "content": turn.assistant }),
+            ]
+        })
+        .collect()
+}
+
+/// Earlier turns as plain text, for providers called with a single prompt
+pub fn as_text(history: &[Turn]) -> String {
+    history
+        .iter()
+        .map(|turn| format!("User: {}\n\nAssistant: {}", turn.user, turn.assistant))
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn turn(user: &str, assistant: &str) -> Turn {
+        Turn { user: user.to_string(), assistant: assistant.to_string() }
+    }
+
+    #[test]
+    fn test_budget_keeps_newest_turns() {
+        let turns = vec![turn(&"a".repeat(400), "old"), turn("Rename it", "Done"), turn("Now test it", "Tests pass")];
+
+        let (kept, truncated) = fit_to_budget(&turns, Some(20));
+        assert_eq!(kept, turns[1..].to_vec());
+        assert!(truncated);
+        assert_eq!(fit_to_budget(&turns, None), (turns.clone(), false));
+        assert_eq!(fit_to_budget(&turns, Some(1)), (Vec::new(), true));
+
+        let messages = messages(&kept);
+        assert_eq!(messages.len(), 4);
+        assert_eq!(messages[1], json!({ "role": "assistant", "content": "Done" }));
+        assert!(as_text(&kept).starts_with("User: Rename it\n\nAssistant: Done\n\nUser: Now test it"));
+    }
+
+    #[test]
+    fn test_strip_oldest_only_when_unchanged() {
+        let mut turns = vec![turn("a", "1"), turn("b", "2"), turn("c", "3")];
+        assert_eq!(strip_oldest(&mut turns, &[turn("b", "2")]), 0);
+        assert_eq!(strip_oldest(&mut turns, &[turn("a", "1"), turn("b", "2")]), 2);
+        assert_eq!(turns, vec![turn("c", "3")]);
+    }
+}
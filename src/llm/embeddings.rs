@@ -0,0 +1,199 @@
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use reqwest::ClientBuilder;
+use serde_json::{json, Value};
+
+use super::connection;
+use super::retry::{with_retry, HttpStatusError};
+use super::LlmProvider;
+use crate::prompts::EmbeddingsConfig;
+
+const OPENAI_EMBEDDINGS_URL: &str = "https://api.openai.com/v1/embeddings";
+const GEMINI_EMBEDDINGS_URL: &str = "https://generativelanguage.googleapis.com/v1beta/models";
+const OLLAMA_EMBED_URL: &str = "http://localhost:11434/api/embed";
+const EMBEDDINGS_TIMEOUT_SECS: u64 = 60;
+// Texts per request; all three APIs accept at least this many
+const EMBEDDINGS_BATCH_SIZE: usize = 64;
+// Longer texts are cut before embedding; embedding models take a few thousand tokens at most
+const MAX_EMBEDDED_CHARS: usize = 8_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EmbeddingProvider {
+    OpenAi,
+    Gemini,
+    Ollama,
+}
+
+impl EmbeddingProvider {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "openai" => Some(Self::OpenAi),
+            "gemini" => Some(Self::Gemini),
+            "ollama" => Some(Self::Ollama),
+            _ => None,
+        }
+    }
+}
+
+/// Embed `texts` with the model from `[embeddings]` in prompts.toml, one vector per text
+pub async fn embed_texts(config: &EmbeddingsConfig, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+    let provider = EmbeddingProvider::from_name(&config.provider)
+        .ok_or_else(|| anyhow::anyhow!("Unknown embeddings provider '{}'. Use openai, gemini or ollama", config.provider))?;
+
+    let texts: Vec<String> = texts.iter().map(|text| text.chars().take(MAX_EMBEDDED_CHARS).collect()).collect();
+    let mut vectors = Vec::with_capacity(texts.len());
+    for batch in texts.chunks(EMBEDDINGS_BATCH_SIZE) {
+        let batch_vectors = match provider {
+            EmbeddingProvider::OpenAi => embed_openai(&config.model, batch).await?,
+            EmbeddingProvider::Gemini => embed_gemini(&config.model, batch).await?,
+            EmbeddingProvider::Ollama => embed_ollama(&config.model, batch).await?,
+        };
+        if batch_vectors.len() != batch.len() {
+            return Err(anyhow::anyhow!(
+                "Embeddings API returned {} vectors for {} texts",
+                batch_vectors.len(),
+                batch.len()
+            ));
+        }
+        vectors.extend(batch_vectors);
+    }
+    Ok(vectors)
+}
+
+pub async fn embed_text(config: &EmbeddingsConfig, text: &str) -> Result<Vec<f32>> {
+    embed_texts(config, &[text.to_string()])
+        .await?
+        .pop()
+        .ok_or_else(|| anyhow::anyhow!("Embeddings API returned no vector"))
+}
+
+/// Cosine similarity, or 0 when the vectors differ in length or one of them is all zeros
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() || a.is_empty() {
+        return 0.0;
+    }
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm_a = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm_b = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm_a == 0.0 || norm_b == 0.0 {
+        0.0
+    } else {
+        dot / (norm_a * norm_b)
+    }
+}
+
+async fn post_json(label: &str, url: &str, headers: &[(&str, String)], body: &Value) -> Result<Value> {
+    let client = ClientBuilder::new()
+        .timeout(Duration::from_secs(EMBEDDINGS_TIMEOUT_SECS))
+        .build()
+        .context("Failed to create HTTP client")?;
+
+    with_retry(label, || {
+        let mut request = client.post(url).json(body);
+        for (name, value) in headers {
+            request = request.header(*name, value);
+        }
+        async move {
+            let response = request
+                .send()
+                .await
+                .map_err(|e| anyhow::Error::new(e).context(format!("Failed to send {} embeddings request", label)))?;
+            if !response.status().is_success() {
+                let error = HttpStatusError::from_response(&response);
+                let text = response.text().await.unwrap_or_default();
+                return Err(anyhow::Error::new(error).context(format!("{} embeddings request failed: {}", label, text)));
+            }
+            response
+                .json::<Value>()
+                .await
+                .with_context(|| format!("Failed to parse {} embeddings response", label))
+        }
+    })
+    .await
+}
+
+async fn embed_openai(model: &str, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+    let api_key = std::env::var("OPENAI_API_KEY")
+        .context("OPENAI_API_KEY environment variable not set")?;
+    let body = json!({ "model": model, "input": texts });
+    let response = post_json("OpenAI", OPENAI_EMBEDDINGS_URL, &[("Authorization", format!("Bearer {}", api_key))], &body).await?;
+    Ok(parse_openai_embeddings(&response))
+}
+
+async fn embed_gemini(model: &str, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+    let api_key = std::env::var("GEMINI_API_KEY")
+        .context("GEMINI_API_KEY environment variable not set")?;
+    let requests: Vec<Value> = texts
+        .iter()
+        .map(|text| json!({ "model": format!("models/{}", model), "content": { "parts": [{ "text": text }] } }))
+        .collect();
+    let url = format!("{}/{}:batchEmbedContents", GEMINI_EMBEDDINGS_URL, model);
+    let response = post_json("Gemini", &url, &[("x-goog-api-key", api_key)], &json!({ "requests": requests })).await?;
+    Ok(parse_gemini_embeddings(&response))
+}
+
+async fn embed_ollama(model: &str, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+    let body = json!({ "model": model, "input": texts });
+    let response = post_json("Ollama", &connection::url(&LlmProvider::Ollama, OLLAMA_EMBED_URL), &[], &body).await?;
+    Ok(parse_ollama_embeddings(&response))
+}
+
+fn to_vector(values: &Value) -> Option<Vec<f32>> {
+    values.as_array()?.iter().map(|value| value.as_f64().map(|x| x as f32)).collect()
+}
+
+/// `data` may come back out of order; each entry carries its input index
+fn parse_openai_embeddings(body: &Value) -> Vec<Vec<f32>> {
+    let mut entries: Vec<(u64, Vec<f32>)> = body["data"]
+        .as_array()
+        .map(|items| items.iter().filter_map(|item| {
+            Some((item["index"].as_u64().unwrap_or(0), to_vector(&item["embedding"])?))
+        }).collect())
+        .unwrap_or_default();
+    entries.sort_by_key(|(index, _)| *index);
+    entries.into_iter().map(|(_, vector)| vector).collect()
+}
+
+fn parse_gemini_embeddings(body: &Value) -> Vec<Vec<f32>> {
+    body["embeddings"]
+        .as_array()
+        .map(|items| items.iter().filter_map(|item| to_vector(&item["values"])).collect())
+        .unwrap_or_default()
+}
+
+fn parse_ollama_embeddings(body: &Value) -> Vec<Vec<f32>> {
+    body["embeddings"]
+        .as_array()
+        .map(|items| items.iter().filter_map(to_vector).collect())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_embedding_responses() {
+        let openai = json!({"data": [
+            {"index": 1, "embedding": [0.0, 1.0]},
+            {"index": 0, "embedding": [1.0, 0.0]}
+        ]});
+        assert_eq!(parse_openai_embeddings(&openai), vec![vec![1.0, 0.0], vec![0.0, 1.0]]);
+
+        let gemini = json!({"embeddings": [{"values": [0.5, 0.5]}]});
+        assert_eq!(parse_gemini_embeddings(&gemini), vec![vec![0.5, 0.5]]);
+
+        let ollama = json!({"model": "nomic-embed-text", "embeddings": [[0.1, 0.2], [0.3, 0.4]]});
+        assert_eq!(parse_ollama_embeddings(&ollama).len(), 2);
+        assert!(parse_ollama_embeddings(&json!({"error": "model not found"})).is_empty());
+    }
+
+    #[test]
+    fn test_cosine_similarity() {
+        assert!((cosine_similarity(&[1.0, 0.0], &[2.0, 0.0]) - 1.0).abs() < 1e-6);
+        assert!(cosine_similarity(&[1.0, 0.0], &[0.0, 1.0]).abs() < 1e-6);
+        assert_eq!(cosine_similarity(&[1.0], &[1.0, 0.0]), 0.0);
+        assert_eq!(cosine_similarity(&[0.0, 0.0], &[1.0, 0.0]), 0.0);
+    }
+}
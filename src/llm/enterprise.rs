@@ -0,0 +1,250 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use reqwest::{Client, RequestBuilder, Response};
+use serde_json::{json, Value};
+
+use super::connection;
+use super::conversation::{self, Turn};
+use super::retry::{with_retry, HttpStatusError};
+use super::{
+    openai_token_usage, parse_openrouter_stream_line, read_streamed_lines, record_usage, AnthropicResponse,
+    ChunkHandler, LlmProvider, ResponseFormat, SamplingParams, DEFAULT_MAX_TOKENS,
+};
+use crate::prompts::{AuthScheme, AzureConfig, BedrockConfig, PromptsConfig};
+use crate::usage::TokenUsage;
+
+pub const ENTERPRISE_TIMEOUT_SECS: u64 = 240;
+const BEDROCK_ANTHROPIC_VERSION: &str = "bedrock-2023-05-31";
+
+pub fn azure_chat_url(config: &AzureConfig, deployment: &str) -> anyhow::Result<String> {
+    let endpoint = config.endpoint.trim().trim_end_matches('/');
+    if endpoint.is_empty() {
+        return Err(anyhow::anyhow!("Azure OpenAI needs an endpoint; set endpoint under [providers.azure] in prompts.toml"));
+    }
+    Ok(format!(
+        "{}/openai/deployments/{}/chat/completions?api-version={}",
+        endpoint,
+        deployment,
+        config.api_version.trim()
+    ))
+}
+
+pub fn bedrock_invoke_url(config: &BedrockConfig, model: &str) -> String {
+    let base = match config.base_url.as_deref().map(str::trim) {
+        Some(base) if !base.is_empty() => base.trim_end_matches('/').to_string(),
+        _ => format!("https://bedrock-runtime.{}.amazonaws.com", config.region.trim()),
+    };
+    // Model ids end in a version like ":0", which belongs encoded in the path
+    format!("{}/model/{}/invoke", base, model.replace(':', "%3A"))
+}
+
+/// Add the credentials `auth` calls for, read from the `key_env` environment variable
+pub fn authorize(request: RequestBuilder, auth: AuthScheme, key_env: &str, label: &str) -> anyhow::Result<RequestBuilder> {
+    if auth == AuthScheme::None {
+        return Ok(request);
+    }
+    let key = std::env::var(key_env)
+        .map_err(|_| anyhow::anyhow!("{} environment variable not found. Please set it to use {}.", key_env, label))?;
+    Ok(match auth {
+        AuthScheme::ApiKey => request.header("api-key", key),
+        AuthScheme::Bearer => request.bearer_auth(key),
+        AuthScheme::None => request,
+    })
+}
+
+pub fn azure_request(client: &Client, config: &AzureConfig, deployment: &str) -> anyhow::Result<RequestBuilder> {
+    authorize(client.post(azure_chat_url(config, deployment)?), config.auth, &config.api_key_env, "Azure OpenAI")
+}
+
+pub fn bedrock_request(client: &Client, config: &BedrockConfig, model: &str) -> anyhow::Result<RequestBuilder> {
+    authorize(client.post(bedrock_invoke_url(config, model)), config.auth, &config.api_key_env, "AWS Bedrock")
+}
+
+fn client(provider: &LlmProvider) -> anyhow::Result<Client> {
+    connection::builder(provider)?
+        .timeout(Duration::from_secs(ENTERPRISE_TIMEOUT_SECS))
+        .connect_timeout(Duration::from_secs(10))
+        .build()
+        .context("Failed to create HTTP client")
+}
+
+/// Send `body` with retries, turning error statuses into errors that name the key to check
+async fn send(label: &str, request: RequestBuilder, body: &Value, key_env: &str) -> anyhow::Result<Response> {
+    with_retry(label, || {
+        let request = request.try_clone().map(|request| request.json(body));
+        async move {
+            let request = request.ok_or_else(|| anyhow::anyhow!("Request to {} can't be retried", label))?;
+            let response = request.send().await.map_err(|e| {
+                let message = if e.is_connect() {
+                    format!("Failed to connect to {}. Please check the endpoint in prompts.toml and your network.", label)
+                } else if e.is_timeout() {
+                    format!("Request to {} timed out after {} seconds", label, ENTERPRISE_TIMEOUT_SECS)
+                } else {
+                    format!("Failed to send request to {}: {}", label, e)
+                };
+                anyhow::Error::new(e).context(message)
+            })?;
+
+            if !response.status().is_success() {
+                let status = response.status();
+                let status_error = HttpStatusError::from_response(&response);
+                let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
+                let error_msg = match status.as_u16() {
+                    401 | 403 => format!("Authentication failed. Please check {} and the auth scheme in prompts.toml. Status {}: {}", key_env, status, error_text),
+                    404 => format!("Deployment or model not found. Status {}: {}", status, error_text),
+                    429 => format!("Rate limit exceeded. Please wait before trying again. Status {}: {}", status, error_text),
+                    _ => format!("{} request failed with status {}: {}", label, status, error_text),
+                };
+                return Err(anyhow::Error::new(status_error).context(error_msg));
+            }
+            Ok(response)
+        }
+    }).await
+}
+
+/// Azure OpenAI speaks the OpenAI chat format, with the deployment in the URL instead of a model field
+#[allow(clippy::too_many_arguments)]
+pub(super) async fn ask_azure_model(
+    user_prompt: &str,
+    context_str: &str,
+    system_instructions: &str,
+    history: &[Turn],
+    deployment: &str,
+    sampling: &SamplingParams,
+    format: ResponseFormat,
+    on_chunk: Option<ChunkHandler<'_>>,
+) -> anyhow::Result<String> {
+    let mut messages = Vec::new();
+    if !system_instructions.is_empty() {
+        messages.push(json!({ "role": "system", "content": system_instructions }));
+    }
+    if !context_str.is_empty() {
+        messages.push(json!({ "role": "system", "content": context_str }));
+    }
+    messages.extend(conversation::messages(history));
+    messages.push(json!({ "role": "user", "content": user_prompt }));
+    let prompt_text = format!("{}\n\n{}\n\n{}\n\n{}", system_instructions, context_str, conversation::as_text(history), user_prompt);
+
+    let mut body = json!({ "messages": messages, "stream": on_chunk.is_some() });
+    if on_chunk.is_some() {
+        // Without this the stream carries no token counts
+        body["stream_options"] = json!({ "include_usage": true });
+    }
+    sampling.apply_to(&mut body);
+    sampling.apply_seed(&mut body);
+    if format == ResponseFormat::Json {
+        body["response_format"] = json!({ "type": "json_object" });
+    }
+
+    let config = PromptsConfig::load().unwrap_or_default().providers.azure;
+    let request = azure_request(&client(&LlmProvider::Azure)?, &config, deployment)?;
+    let response = send("Azure OpenAI", request, &body, &config.api_key_env).await?;
+
+    if let Some(on_chunk) = on_chunk {
+        let (text, reported) = read_streamed_lines(response, parse_openrouter_stream_line, on_chunk).await?;
+        record_usage(LlmProvider::Azure, deployment, reported, &prompt_text, &text);
+        return Ok(text);
+    }
+
+    let body: Value = response
+        .json()
+        .await
+        .context("Failed to parse JSON response from Azure OpenAI")?;
+    let text = body["choices"][0]["message"]["content"]
+        .as_str()
+        .map(String::from)
+        .ok_or_else(|| anyhow::anyhow!("No text response from Azure OpenAI"))?;
+
+    record_usage(LlmProvider::Azure, deployment, openai_token_usage(&body["usage"]), &prompt_text, &text);
+    Ok(text)
+}
+
+/// Claude on Bedrock takes the Anthropic Messages body, with the model in the URL.
+/// Responses arrive in one piece; Bedrock streams in AWS's binary event format.
+pub(super) async fn ask_bedrock_model(
+    user_prompt: &str,
+    context_str: &str,
+    system_instructions: &str,
+    history: &[Turn],
+    model_name: &str,
+    sampling: &SamplingParams,
+) -> anyhow::Result<String> {
+    let system_content = if context_str.is_empty() {
+        system_instructions.to_string()
+    } else {
+        format!("{}\n\n{}", system_instructions, context_str)
+    };
+    let prompt_text = format!("{}\n\n{}\n\n{}", system_content, conversation::as_text(history), user_prompt);
+
+    let mut body = bedrock_body(&system_content, history, user_prompt);
+    sampling.apply_to(&mut body);
+
+    let config = PromptsConfig::load().unwrap_or_default().providers.bedrock;
+    let request = bedrock_request(&client(&LlmProvider::Bedrock)?, &config, model_name)?;
+    let response = send("AWS Bedrock", request, &body, &config.api_key_env).await?;
+
+    let bedrock_response: AnthropicResponse = response
+        .json()
+        .await
+        .context("Failed to parse JSON response from AWS Bedrock")?;
+    let text = bedrock_response
+        .content
+        .into_iter()
+        .find(|c| c.content_type == "text")
+        .map(|c| c.text)
+        .unwrap_or_else(|| "No text response from AWS Bedrock".to_string());
+
+    record_usage(LlmProvider::Bedrock, model_name, bedrock_response.usage.map(TokenUsage::from), &prompt_text, &text);
+    Ok(text)
+}
+
+/// Anthropic Messages body in Bedrock's dialect: no model field, a Bedrock API version instead
+pub fn bedrock_body(system_content: &str, history: &[Turn], user_prompt: &str) -> Value {
+    let mut messages = conversation::messages(history);
+    messages.push(json!({ "role": "user", "content": user_prompt }));
+    let mut body = json!({
+        "anthropic_version": BEDROCK_ANTHROPIC_VERSION,
+        "max_tokens": DEFAULT_MAX_TOKENS,
+        "messages": messages,
+    });
+    if !system_content.is_empty() {
+        body["system"] = Value::String(system_content.to_string());
+    }
+    body
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_enterprise_urls() {
+        let azure = AzureConfig { endpoint: "https://acme.openai.azure.com/".to_string(), ..Default::default() };
+        assert_eq!(
+            azure_chat_url(&azure, "gpt-4o").unwrap(),
+            "https://acme.openai.azure.com/openai/deployments/gpt-4o/chat/completions?api-version=2024-10-21"
+        );
+        assert!(azure_chat_url(&AzureConfig::default(), "gpt-4o").is_err());
+
+        let bedrock = BedrockConfig { region: "eu-west-1".to_string(), ..Default::default() };
+        assert_eq!(
+            bedrock_invoke_url(&bedrock, "anthropic.claude-sonnet-4-20250514-v1:0"),
+            "https://bedrock-runtime.eu-west-1.amazonaws.com/model/anthropic.claude-sonnet-4-20250514-v1%3A0/invoke"
+        );
+        let gateway = BedrockConfig { base_url: Some("https://llm-gateway.internal/bedrock/".to_string()), ..Default::default() };
+        assert!(bedrock_invoke_url(&gateway, "m").starts_with("https://llm-gateway.internal/bedrock/model/m"));
+    }
+
+    #[test]
+    fn test_bedrock_body() {
+        let history = [Turn { user: "Hello".to_string(), assistant: "Hi there".to_string() }];
+        let body = bedrock_body("Be brief", &history, "Hi");
+        assert_eq!(body["anthropic_version"], BEDROCK_ANTHROPIC_VERSION);
+        assert_eq!(body["system"], "Be brief");
+        assert!(body.get("model").is_none());
+        assert_eq!(body["messages"][1]["role"], "assistant");
+        assert_eq!(body["messages"][2]["content"], "Hi");
+        assert!(bedrock_body("", &[], "Hi").get("system").is_none());
+    }
+}
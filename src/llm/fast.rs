@@ -0,0 +1,148 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use reqwest::{Client, RequestBuilder};
+use serde_json::{json, Value};
+
+use super::connection;
+use super::conversation::{self, Turn};
+use super::retry::{with_retry, HttpStatusError};
+use super::{
+    openai_token_usage, parse_openrouter_stream_line, read_streamed_lines, record_usage, ChunkHandler,
+    LlmProvider, ResponseFormat, SamplingParams,
+};
+
+pub const DEFAULT_GROQ_MODEL: &str = "llama-3.3-70b-versatile";
+pub const GROQ_FAST_MODEL: &str = "llama-3.1-8b-instant";
+pub const DEFAULT_TOGETHER_MODEL: &str = "meta-llama/Llama-3.3-70B-Instruct-Turbo";
+pub const TOGETHER_FAST_MODEL: &str = "meta-llama/Llama-3.2-3B-Instruct-Turbo";
+pub const FAST_TIMEOUT_SECS: u64 = 120;
+
+/// Where a fast-inference provider takes OpenAI-style chat requests
+pub struct Endpoint {
+    pub label: &'static str,
+    pub url: &'static str,
+    pub key_env: &'static str,
+}
+
+pub fn endpoint(provider: &LlmProvider) -> Option<Endpoint> {
+    match provider {
+        LlmProvider::Groq => Some(Endpoint {
+            label: "Groq",
+            url: "https://api.groq.com/openai/v1/chat/completions",
+            key_env: "GROQ_API_KEY",
+        }),
+        LlmProvider::Together => Some(Endpoint {
+            label: "Together.ai",
+            url: "https://api.together.xyz/v1/chat/completions",
+            key_env: "TOGETHER_API_KEY",
+        }),
+        _ => None,
+    }
+}
+
+fn fast_endpoint(provider: &LlmProvider) -> anyhow::Result<Endpoint> {
+    endpoint(provider).ok_or_else(|| anyhow::anyhow!("{} isn't a fast-inference provider", provider.name()))
+}
+
+pub fn request(client: &Client, provider: &LlmProvider) -> anyhow::Result<RequestBuilder> {
+    authorized(client, provider, &fast_endpoint(provider)?)
+}
+
+/// A request to the endpoint, or to `[providers.<name>] base_url` in its place
+fn authorized(client: &Client, provider: &LlmProvider, endpoint: &Endpoint) -> anyhow::Result<RequestBuilder> {
+    let key = std::env::var(endpoint.key_env).map_err(|_| {
+        anyhow::anyhow!("{} environment variable not found. Please set it to use {}.", endpoint.key_env, endpoint.label)
+    })?;
+    Ok(client.post(connection::url(provider, endpoint.url)).bearer_auth(key))
+}
+
+/// Groq and Together.ai both speak the OpenAI chat format
+#[allow(clippy::too_many_arguments)]
+pub(super) async fn ask_fast_model(
+    provider: &LlmProvider,
+    user_prompt: &str,
+    context_str: &str,
+    system_instructions: &str,
+    history: &[Turn],
+    model_name: &str,
+    sampling: &SamplingParams,
+    format: ResponseFormat,
+    on_chunk: Option<ChunkHandler<'_>>,
+) -> anyhow::Result<String> {
+    let client = connection::builder(provider)?
+        .timeout(Duration::from_secs(FAST_TIMEOUT_SECS))
+        .connect_timeout(Duration::from_secs(10))
+        .build()
+        .context("Failed to create HTTP client")?;
+    let endpoint = fast_endpoint(provider)?;
+    let request = authorized(&client, provider, &endpoint)?;
+
+    let mut messages = Vec::new();
+    if !system_instructions.is_empty() {
+        messages.push(json!({ "role": "system", "content": system_instructions }));
+    }
+    if !context_str.is_empty() {
+        messages.push(json!({ "role": "system", "content": context_str }));
+    }
+    messages.extend(conversation::messages(history));
+    messages.push(json!({ "role": "user", "content": user_prompt }));
+    let prompt_text = format!("{}\n\n{}\n\n{}\n\n{}", system_instructions, context_str, conversation::as_text(history), user_prompt);
+
+    let mut body = json!({ "model": model_name, "messages": messages, "stream": on_chunk.is_some() });
+    sampling.apply_to(&mut body);
+    sampling.apply_seed(&mut body);
+    if format == ResponseFormat::Json {
+        body["response_format"] = json!({ "type": "json_object" });
+    }
+
+    let label = endpoint.label;
+    let response = with_retry(label, || {
+        let request = request.try_clone().map(|request| request.json(&body));
+        async move {
+            let request = request.ok_or_else(|| anyhow::anyhow!("Request to {} can't be retried", label))?;
+            let response = request.send().await.map_err(|e| {
+                let message = if e.is_connect() {
+                    format!("Failed to connect to {}. Please check your internet connection.", label)
+                } else if e.is_timeout() {
+                    format!("Request to {} timed out after {} seconds", label, FAST_TIMEOUT_SECS)
+                } else {
+                    format!("Failed to send request to {}: {}", label, e)
+                };
+                anyhow::Error::new(e).context(message)
+            })?;
+
+            if !response.status().is_success() {
+                let status = response.status();
+                let status_error = HttpStatusError::from_response(&response);
+                let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
+                let error_msg = match status.as_u16() {
+                    401 => format!("Authentication failed. Please check your {}. Status {}: {}", endpoint.key_env, status, error_text),
+                    404 => format!("Model not found. Status {}: {}", status, error_text),
+                    429 => format!("Rate limit exceeded. Please wait before trying again. Status {}: {}", status, error_text),
+                    _ => format!("{} request failed with status {}: {}", label, status, error_text),
+                };
+                return Err(anyhow::Error::new(status_error).context(error_msg));
+            }
+            Ok(response)
+        }
+    }).await?;
+
+    if let Some(on_chunk) = on_chunk {
+        let (text, reported) = read_streamed_lines(response, parse_openrouter_stream_line, on_chunk).await?;
+        record_usage(provider.clone(), model_name, reported, &prompt_text, &text);
+        return Ok(text);
+    }
+
+    let body: Value = response
+        .json()
+        .await
+        .with_context(|| format!("Failed to parse JSON response from {}", label))?;
+    let text = body["choices"][0]["message"]["content"]
+        .as_str()
+        .map(String::from)
+        .ok_or_else(|| anyhow::anyhow!("No text response from {}", label))?;
+
+    record_usage(provider.clone(), model_name, openai_token_usage(&body["usage"]), &prompt_text, &text);
+    Ok(text)
+}
@@ -0,0 +1,147 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::crash;
+use crate::prompts::RateLimitConfig;
+
+// Queue waits shorter than this aren't worth mentioning
+const QUIET_WAIT: Duration = Duration::from_secs(1);
+
+/// Requests per minute with room for short bursts. Tokens can go negative, so requests
+/// that arrive while the bucket is empty queue up behind each other in arrival order.
+#[derive(Debug, Clone)]
+struct TokenBucket {
+    per_minute: u32,
+    burst: u32,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn new(per_minute: u32, burst: u32, now: Instant) -> Self {
+        Self { per_minute, burst, tokens: burst.max(1) as f64, updated: now }
+    }
+
+    fn per_second(&self) -> f64 {
+        self.per_minute as f64 / 60.0
+    }
+
+    /// Take a token, returning how long to wait before sending
+    fn reserve(&mut self, now: Instant) -> Duration {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.per_second()).min(self.burst.max(1) as f64);
+        self.updated = now;
+        self.tokens -= 1.0;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.per_second())
+        }
+    }
+}
+
+// One bucket per provider for the whole process, so the UI and every agent share it
+static BUCKETS: Mutex<Vec<(String, TokenBucket)>> = Mutex::new(Vec::new());
+
+/// The provider a request label like "Claude/claude-sonnet-4", "Together.ai" or
+/// "AWS Bedrock" belongs to, as named in `[rate_limit.providers]`
+pub fn provider_key(label: &str) -> String {
+    let name = label.split('/').next().unwrap_or(label).trim().to_lowercase();
+    let name = name.strip_prefix("aws ").unwrap_or(&name);
+    let name = name.split_whitespace().next().unwrap_or("");
+    match name.trim_end_matches(".ai") {
+        "claude" => "anthropic".to_string(),
+        other => other.to_string(),
+    }
+}
+
+/// Wait for the provider's turn under `[rate_limit]`. Returns straight away for
+/// providers without a limit.
+pub async fn acquire(config: &RateLimitConfig, label: &str) {
+    let wait = reserve(config, label, Instant::now());
+    if wait.is_zero() {
+        return;
+    }
+    if wait >= QUIET_WAIT {
+        let notice = format!("{} rate limit reached, request queued for {:.1}s", label, wait.as_secs_f64());
+        // Printing would draw over the TUI, which shows it in the status bar instead
+        if crash::tui_active() {
+            *PENDING_NOTICE.lock().unwrap_or_else(|e| e.into_inner()) = Some(notice);
+        } else {
+            eprintln!("Warning: {}", notice);
+        }
+    }
+    tokio::time::sleep(wait).await;
+}
+
+// The last queue notice while the TUI is up, until the TUI shows it
+static PENDING_NOTICE: Mutex<Option<String>> = Mutex::new(None);
+
+pub fn take_notice() -> Option<String> {
+    PENDING_NOTICE.lock().unwrap_or_else(|e| e.into_inner()).take()
+}
+
+fn reserve(config: &RateLimitConfig, label: &str, now: Instant) -> Duration {
+    let provider = provider_key(label);
+    let per_minute = config.limit_for(&provider);
+    if per_minute == 0 {
+        return Duration::ZERO;
+    }
+
+    let mut buckets = BUCKETS.lock().unwrap_or_else(|e| e.into_inner());
+    let index = match buckets.iter().position(|(name, _)| *name == provider) {
+        Some(index) => index,
+        None => {
+            buckets.push((provider, TokenBucket::new(per_minute, config.burst, now)));
+            buckets.len() - 1
+        }
+    };
+    let bucket = &mut buckets[index].1;
+    // Pick up limits changed in prompts.toml without losing the queue
+    bucket.per_minute = per_minute;
+    bucket.burst = config.burst;
+    bucket.reserve(now)
+}
+
+impl RateLimitConfig {
+    /// Requests per minute allowed for `provider`; 0 means unlimited
+    pub fn limit_for(&self, provider: &str) -> u32 {
+        self.providers.get(provider).copied().unwrap_or(self.requests_per_minute)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket_bursts_then_queues_in_order() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(60, 2, start);
+        assert_eq!(bucket.reserve(start), Duration::ZERO);
+        assert_eq!(bucket.reserve(start), Duration::ZERO);
+        assert_eq!(bucket.reserve(start), Duration::from_secs(1));
+        assert_eq!(bucket.reserve(start), Duration::from_secs(2));
+
+        // After the queue drains the bucket refills, but never past the burst size
+        let later = start + Duration::from_secs(60);
+        assert_eq!(bucket.reserve(later), Duration::ZERO);
+        assert_eq!(bucket.reserve(later), Duration::ZERO);
+        assert_eq!(bucket.reserve(later), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_provider_keys_and_limits() {
+        assert_eq!(provider_key("Claude/claude-sonnet-4-20250514"), "anthropic");
+        assert_eq!(provider_key("Anthropic"), "anthropic");
+        assert_eq!(provider_key("Together.ai"), "together");
+        assert_eq!(provider_key("Together/meta-llama"), "together");
+        assert_eq!(provider_key("AWS Bedrock"), "bedrock");
+        assert_eq!(provider_key("Azure OpenAI"), "azure");
+
+        let config = RateLimitConfig::default();
+        assert_eq!(config.limit_for("ollama"), 0);
+        assert_eq!(config.limit_for("gemini"), config.requests_per_minute);
+        assert_eq!(reserve(&config, "Ollama/llama3", Instant::now()), Duration::ZERO);
+    }
+}
@@ -0,0 +1,315 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::Context;
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+
+use super::cache::fnv1a;
+use super::conversation;
+use super::{enterprise, fast, request_log, router, LlmProvider, ModelConfig, ResponseFormat, SamplingParams};
+use crate::history;
+use crate::prompt_pipeline::AssembledPrompt;
+use crate::prompts::PromptsConfig;
+
+pub const SESSIONS_DIR: &str = ".kota/sessions";
+// Requests whose full prompt is kept for /replay; older ones keep only their metadata
+const MAX_REPLAYABLE: usize = 50;
+
+/// How a conversation request was sent, appended to the session's metadata file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RequestRecord {
+    pub turn: usize,
+    pub timestamp: String,
+    pub session: String,
+    pub kind: String,
+    pub provider: String,
+    pub model: String,
+    pub sampling: SamplingParams,
+    /// Whether the provider was sent the seed; without one, replies can differ run to run
+    pub seeded: bool,
+    pub prompt_hash: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response_hash: Option<String>,
+    #[serde(default)]
+    pub cached: bool,
+}
+
+impl RequestRecord {
+    /// One line for `/replay`, e.g. "#3 14:02:11 anthropic claude-sonnet-4 (temp 0.2) prompt 9f2c…"
+    pub fn describe(&self) -> String {
+        let time = self.timestamp.get(11..19).unwrap_or(&self.timestamp);
+        let mut line = format!("#{} {} {} {}", self.turn, time, self.provider, self.model);
+        let sampling = self.sampling.summary();
+        if !sampling.is_empty() {
+            line.push_str(&format!(" ({})", sampling));
+        }
+        line.push_str(&format!(" prompt {}", self.prompt_hash));
+        if self.sampling.seed.is_some() && !self.seeded {
+            line.push_str(" [seed not supported]");
+        }
+        if self.cached {
+            line.push_str(" [cached]");
+        }
+        if self.response_hash.is_none() {
+            line.push_str(" [failed]");
+        }
+        line
+    }
+}
+
+struct Replayable {
+    record: RequestRecord,
+    prompt: AssembledPrompt,
+    response: Option<String>,
+}
+
+struct SessionRequests {
+    count: usize,
+    replayable: Vec<Replayable>,
+}
+
+static REQUESTS: Mutex<SessionRequests> = Mutex::new(SessionRequests { count: 0, replayable: Vec::new() });
+
+/// Providers that take a seed and try to answer the same request the same way with it
+pub fn supports_seed(provider: &LlmProvider) -> bool {
+    matches!(provider, LlmProvider::Ollama | LlmProvider::OpenRouter | LlmProvider::Groq | LlmProvider::Together | LlmProvider::Azure)
+}
+
+/// Hash of everything the model was sent, so two requests can be told apart at a glance
+pub fn prompt_hash(prompt: &AssembledPrompt) -> String {
+    let history = conversation::as_text(&prompt.history);
+    format!("{:016x}", fnv1a(&[&prompt.system, &prompt.context, &history, &prompt.user]))
+}
+
+fn response_hash(response: &str) -> String {
+    format!("{:016x}", fnv1a(&[response]))
+}
+
+/// This session's metadata file, under the home directory
+pub fn metadata_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| {
+        PathBuf::from(home)
+            .join(SESSIONS_DIR)
+            .join(format!("{}.jsonl", history::current_session_id()))
+    })
+}
+
+/// Remember a conversation request so `/replay` can send it again, and append its metadata to
+/// the session file. Returns its turn number.
+pub fn record(kind: &str, provider: &LlmProvider, model: &str, sampling: &SamplingParams, prompt: &AssembledPrompt, response: Option<&str>, cached: bool) -> usize {
+    let mut requests = REQUESTS.lock().unwrap_or_else(|e| e.into_inner());
+    requests.count += 1;
+    let record = RequestRecord {
+        turn: requests.count,
+        timestamp: Local::now().to_rfc3339(),
+        session: history::current_session_id().to_string(),
+        kind: kind.to_string(),
+        provider: provider.name().to_string(),
+        model: model.to_string(),
+        sampling: sampling.clone(),
+        seeded: sampling.seed.is_some() && supports_seed(provider),
+        prompt_hash: prompt_hash(prompt),
+        response_hash: response.map(response_hash),
+        cached,
+    };
+    if let Some(path) = metadata_path() {
+        if let Err(e) = append_to(&path, &record) {
+            eprintln!("Warning: Failed to write session metadata: {}", e);
+        }
+    }
+    let turn = record.turn;
+    requests.replayable.push(Replayable { record, prompt: prompt.clone(), response: response.map(str::to_string) });
+    let excess = requests.replayable.len().saturating_sub(MAX_REPLAYABLE);
+    requests.replayable.drain(..excess);
+    turn
+}
+
+fn append_to(path: &Path, record: &RequestRecord) -> anyhow::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+    }
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    writeln!(file, "{}", serde_json::to_string(record)?)?;
+    Ok(())
+}
+
+/// The requests `/replay` can still send, oldest first
+pub fn replayable() -> Vec<RequestRecord> {
+    let requests = REQUESTS.lock().unwrap_or_else(|e| e.into_inner());
+    requests.replayable.iter().map(|request| request.record.clone()).collect()
+}
+
+/// `/replay` with no turn: what can be replayed, and where the rest of the metadata is
+pub fn list() -> String {
+    let records = replayable();
+    let file = metadata_path().map(|path| path.display().to_string()).unwrap_or_else(|| format!("~/{}", SESSIONS_DIR));
+    if records.is_empty() {
+        return format!("No requests yet this session. Each one's model, sampling, seed and prompt hash are recorded in {}", file);
+    }
+    let mut text = String::from("Requests this session (/replay <turn> sends one again):\n");
+    for record in &records {
+        text.push_str(&format!("  {}\n", record.describe()));
+    }
+    text.push_str(&format!("Metadata for every request: {}", file));
+    text
+}
+
+/// `/replay` arguments: the turn, and whether `--current` asks for the session's model
+pub fn parse_args(arg: &str) -> Option<(usize, bool)> {
+    let mut turn = None;
+    let mut current = false;
+    for part in arg.split_whitespace() {
+        match part {
+            "--current" => current = true,
+            _ => turn = Some(part.trim_start_matches('#').parse().ok()?),
+        }
+    }
+    turn.map(|turn| (turn, current))
+}
+
+/// The outcome of sending a request again
+pub struct Replay {
+    pub record: RequestRecord,
+    /// The provider and model that answered this time
+    pub model: String,
+    pub response: String,
+    pub original: Option<String>,
+}
+
+impl Replay {
+    /// Whether the reply matches the original, and if not, the first line where they part
+    pub fn comparison(&self) -> String {
+        let Some(original) = &self.original else {
+            return "The original request failed, so there's no reply to compare with".to_string();
+        };
+        if *original == self.response {
+            return "Identical to the original reply".to_string();
+        }
+        let first_difference = original.lines().zip(self.response.lines()).take_while(|(a, b)| a == b).count() + 1;
+        let mut comparison = format!(
+            "Differs from the original reply from line {} ({} lines then, {} now)",
+            first_difference,
+            original.lines().count(),
+            self.response.lines().count()
+        );
+        if !self.record.seeded {
+            comparison.push_str("; the request had no seed the provider uses, so some variation is expected");
+        }
+        comparison
+    }
+}
+
+/// Send request `turn` again exactly as it went out, past the response cache. With `current`,
+/// it goes to that model and its sampling instead, to check a change of provider or model.
+pub async fn replay(turn: usize, current: Option<&ModelConfig>) -> anyhow::Result<Replay> {
+    let (record, prompt, original) = {
+        let requests = REQUESTS.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(request) = requests.replayable.iter().find(|request| request.record.turn == turn) else {
+            return Err(anyhow::anyhow!("Request #{} can't be replayed; only the last {} of this session can. /replay lists them", turn, MAX_REPLAYABLE));
+        };
+        (request.record.clone(), request.prompt.clone(), request.response.clone())
+    };
+    let prompts_config = PromptsConfig::load().unwrap_or_default();
+    let config = match current {
+        Some(config) => config.clone(),
+        None => {
+            let provider = LlmProvider::from_name(&record.provider)
+                .ok_or_else(|| anyhow::anyhow!("Unknown provider {} in request #{}", record.provider, turn))?;
+            ModelConfig { provider, model_name: Some(record.model.clone()), sampling: record.sampling.clone(), ..ModelConfig::default() }
+        }
+    };
+    // Local-only files and budgets apply as they would to a new request
+    let config = &router::route(&config, &prompt.context, &prompts_config)?;
+    let model_name = config.get_model_name();
+    let sampling = match current {
+        Some(_) => config.sampling.or(&prompts_config.sampling),
+        None => config.sampling.clone(),
+    };
+    let (user_prompt, context_str, system_instructions) = (prompt.user.as_str(), prompt.context.as_str(), prompt.system.as_str());
+    let history = prompt.history.as_slice();
+    let exchange = request_log::Exchange::new("replay", config, &model_name, &prompt);
+
+    let response = match config.provider {
+        LlmProvider::Ollama => super::ask_ollama_model(user_prompt, context_str, system_instructions, history, &model_name, &sampling, ResponseFormat::Text, None).await,
+        LlmProvider::Gemini => super::ask_gemini_model(user_prompt, context_str, system_instructions, history, &model_name).await,
+        LlmProvider::Anthropic => super::ask_anthropic_model(user_prompt, context_str, system_instructions, history, &model_name, &sampling, None).await,
+        LlmProvider::OpenRouter => super::ask_openrouter_model(user_prompt, context_str, system_instructions, history, &model_name, &sampling, ResponseFormat::Text, None).await,
+        LlmProvider::Groq | LlmProvider::Together => fast::ask_fast_model(&config.provider, user_prompt, context_str, system_instructions, history, &model_name, &sampling, ResponseFormat::Text, None).await,
+        LlmProvider::Azure => enterprise::ask_azure_model(user_prompt, context_str, system_instructions, history, &model_name, &sampling, ResponseFormat::Text, None).await,
+        LlmProvider::Bedrock => enterprise::ask_bedrock_model(user_prompt, context_str, system_instructions, history, &model_name, &sampling).await,
+    };
+    exchange.record(response.as_deref());
+    Ok(Replay { record, model: format!("{}:{}", config.provider.name(), model_name), response: response?, original })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn record_for(seed: Option<u64>, provider: &LlmProvider) -> RequestRecord {
+        let sampling = SamplingParams { temperature: Some(0.0), seed, ..Default::default() };
+        RequestRecord {
+            turn: 3,
+            timestamp: "2026-01-05T14:02:11+00:00".to_string(),
+            session: "s".to_string(),
+            kind: "stream".to_string(),
+            provider: provider.name().to_string(),
+            model: "m".to_string(),
+            seeded: seed.is_some() && supports_seed(provider),
+            sampling,
+            prompt_hash: "00ff".to_string(),
+            response_hash: Some("01".to_string()),
+            cached: false,
+        }
+    }
+
+    #[test]
+    fn test_prompt_hash_and_metadata() {
+        let prompt = AssembledPrompt { system: "Be brief".to_string(), user: "Fix it".to_string(), ..Default::default() };
+        let other = AssembledPrompt { user: "Fix it!".to_string(), ..prompt.clone() };
+        assert_eq!(prompt_hash(&prompt), prompt_hash(&prompt.clone()));
+        assert_ne!(prompt_hash(&prompt), prompt_hash(&other));
+        assert_eq!(prompt_hash(&prompt).len(), 16);
+
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("sessions/s.jsonl");
+        let seeded = record_for(Some(42), &LlmProvider::Ollama);
+        append_to(&path, &seeded).unwrap();
+        append_to(&path, &record_for(Some(42), &LlmProvider::Anthropic)).unwrap();
+        let lines: Vec<RequestRecord> = fs::read_to_string(&path).unwrap().lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(lines[0], seeded);
+        assert!(!lines[1].seeded);
+        assert_eq!(seeded.describe(), "#3 14:02:11 ollama m (temp 0 seed 42) prompt 00ff");
+        assert!(lines[1].describe().ends_with("[seed not supported]"));
+    }
+
+    #[test]
+    fn test_parse_args() {
+        assert_eq!(parse_args("3"), Some((3, false)));
+        assert_eq!(parse_args("#12 --current"), Some((12, true)));
+        assert_eq!(parse_args("--current"), None);
+        assert_eq!(parse_args("three"), None);
+    }
+
+    #[test]
+    fn test_comparison() {
+        let mut replay = Replay {
+            record: record_for(None, &LlmProvider::Anthropic),
+            model: "anthropic:m".to_string(),
+            response: "one\ntwo\nthree".to_string(),
+            original: Some("one\ntwo\nthree".to_string()),
+        };
+        assert_eq!(replay.comparison(), "Identical to the original reply");
+        replay.original = Some("one\n2\nthree\nfour".to_string());
+        assert!(replay.comparison().starts_with("Differs from the original reply from line 2 (4 lines then, 3 now); the request had no seed"));
+    }
+}
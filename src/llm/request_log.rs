@@ -0,0 +1,176 @@
+use std::fmt::Display;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::Context;
+use chrono::Local;
+use serde::Serialize;
+
+use super::conversation::Turn;
+use super::{LlmProvider, ModelConfig};
+use crate::history;
+use crate::prompt_pipeline::AssembledPrompt;
+use crate::prompts::PromptsConfig;
+
+pub const TRANSCRIPTS_DIR: &str = ".kota/transcripts";
+
+// Set by /transcript on|off; `[transcript] enabled` decides until then
+static SESSION_OVERRIDE: Mutex<Option<bool>> = Mutex::new(None);
+
+pub fn set_enabled(enabled: bool) {
+    *SESSION_OVERRIDE.lock().unwrap_or_else(|e| e.into_inner()) = Some(enabled);
+}
+
+pub fn enabled() -> bool {
+    let session = *SESSION_OVERRIDE.lock().unwrap_or_else(|e| e.into_inner());
+    session.unwrap_or_else(|| PromptsConfig::load().unwrap_or_default().transcript.enabled)
+}
+
+/// This session's transcript file, under the home directory
+pub fn transcript_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| {
+        PathBuf::from(home)
+            .join(TRANSCRIPTS_DIR)
+            .join(format!("{}.jsonl", history::current_session_id()))
+    })
+}
+
+/// One request as it was sent: the assembled prompt parts after routing and secrets review
+pub struct Exchange<'a> {
+    pub kind: &'a str,
+    pub provider: &'a LlmProvider,
+    pub model: &'a str,
+    pub system: &'a str,
+    pub context: &'a str,
+    pub history: &'a [Turn],
+    pub user: &'a str,
+}
+
+#[derive(Serialize)]
+struct TranscriptLine<'a> {
+    timestamp: String,
+    session: &'a str,
+    kind: &'a str,
+    provider: &'a str,
+    model: &'a str,
+    system: &'a str,
+    context: &'a str,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    history: &'a [Turn],
+    user: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    cached: bool,
+}
+
+impl<'a> Exchange<'a> {
+    pub fn new(kind: &'a str, config: &'a ModelConfig, model: &'a str, prompt: &'a AssembledPrompt) -> Self {
+        Self {
+            kind,
+            provider: &config.provider,
+            model,
+            system: &prompt.system,
+            context: &prompt.context,
+            history: &prompt.history,
+            user: &prompt.user,
+        }
+    }
+
+    /// Append the exchange to the transcript, if transcripts are on. Failures only warn;
+    /// a request never fails because it couldn't be logged.
+    pub fn record<E: Display>(&self, response: Result<&str, E>) {
+        self.write(response, false);
+    }
+
+    pub fn record_cached(&self, response: &str) {
+        self.write(Ok::<&str, String>(response), true);
+    }
+
+    fn write<E: Display>(&self, response: Result<&str, E>, cached: bool) {
+        if !enabled() {
+            return;
+        }
+        let Some(path) = transcript_path() else {
+            eprintln!("Warning: HOME is not set; can't write the transcript");
+            return;
+        };
+        if let Err(e) = self.append_to(&path, response, cached) {
+            eprintln!("Warning: Failed to write transcript: {}", e);
+        }
+    }
+
+    fn append_to<E: Display>(&self, path: &Path, response: Result<&str, E>, cached: bool) -> anyhow::Result<()> {
+        let (response, error) = match response {
+            Ok(text) => (Some(text), None),
+            Err(e) => (None, Some(e.to_string())),
+        };
+        let line = TranscriptLine {
+            timestamp: Local::now().to_rfc3339(),
+            session: history::current_session_id(),
+            kind: self.kind,
+            provider: self.provider.name(),
+            model: self.model,
+            system: self.system,
+            context: self.context,
+            history: self.history,
+            user: self.user,
+            response,
+            error,
+            cached,
+        };
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        writeln!(file, "{}", serde_json::to_string(&line)?)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_exchanges_append_as_json_lines() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("transcripts/session.jsonl");
+        let exchange = Exchange {
+            kind: "chat",
+            provider: &LlmProvider::Ollama,
+            model: "llama3.2",
+            system: "Be brief",
+            context: "--- File: a.rs ---",
+            history: &[],
+            user: "Fix it",
+        };
+
+        exchange.append_to(&path, Ok::<&str, String>("Done"), false).unwrap();
+        exchange.append_to(&path, Err::<&str, _>("timed out"), false).unwrap();
+
+        let lines: Vec<serde_json::Value> = fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["provider"], "ollama");
+        assert_eq!(lines[0]["context"], "--- File: a.rs ---");
+        assert_eq!(lines[0]["response"], "Done");
+        assert!(lines[0].get("error").is_none());
+        assert!(lines[0].get("history").is_none());
+        assert_eq!(lines[1]["error"], "timed out");
+        assert!(lines[1].get("response").is_none());
+    }
+}
@@ -0,0 +1,199 @@
+use std::collections::hash_map::RandomState;
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+use crate::prompts::{PromptsConfig, RetryConfig};
+
+use super::rate_limit;
+
+// A server asking for a longer pause than this is treated as a hard failure
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+/// Status of a failed HTTP request, attached under the readable error message so
+/// the retry logic can tell rate limits and overloads apart from bad requests
+#[derive(Debug)]
+pub struct HttpStatusError {
+    pub status: u16,
+    /// From the Retry-After header, when the server sent one in seconds
+    pub retry_after: Option<Duration>,
+}
+
+impl HttpStatusError {
+    pub fn from_response(response: &reqwest::Response) -> Self {
+        let retry_after = response
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().parse::<u64>().ok())
+            .map(Duration::from_secs);
+        Self { status: response.status().as_u16(), retry_after }
+    }
+}
+
+impl std::fmt::Display for HttpStatusError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "HTTP {}", self.status)
+    }
+}
+
+impl std::error::Error for HttpStatusError {}
+
+/// Run `op` until it succeeds, fails with an error that isn't worth retrying, or runs
+/// out of attempts, using the `[retry]` settings from prompts.toml. Every attempt waits
+/// its turn under the provider's `[rate_limit]` first.
+pub async fn with_retry<T, F, Fut>(label: &str, mut op: F) -> anyhow::Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = anyhow::Result<T>>,
+{
+    let config = PromptsConfig::load().unwrap_or_default();
+    let limits = &config.rate_limit;
+    retry_with(&config.retry, label, || {
+        let attempt = op();
+        async move {
+            rate_limit::acquire(limits, label).await;
+            attempt.await
+        }
+    }).await
+}
+
+pub async fn retry_with<T, F, Fut>(config: &RetryConfig, label: &str, mut op: F) -> anyhow::Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = anyhow::Result<T>>,
+{
+    let mut attempt = 0;
+    loop {
+        match op().await {
+            Ok(value) => return Ok(value),
+            Err(e) if attempt < config.attempts && is_retryable(&e) => {
+                let delay = retry_delay(config, attempt, &e);
+                eprintln!(
+                    "Warning: {} request failed ({}), retrying in {:.1}s ({}/{})",
+                    label, e, delay.as_secs_f64(), attempt + 1, config.attempts
+                );
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Rate limits, server overloads, timeouts and dropped connections are worth another
+/// try; anything else (bad key, unknown model, malformed request) fails straight away
+pub fn is_retryable(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        if let Some(status) = cause.downcast_ref::<HttpStatusError>() {
+            return matches!(status.status, 408 | 429 | 500 | 502 | 503 | 504 | 529)
+                && status.retry_after.is_none_or(|after| after <= MAX_RETRY_AFTER);
+        }
+        if let Some(e) = cause.downcast_ref::<reqwest::Error>() {
+            return e.is_connect() || e.is_timeout();
+        }
+        if let Some(e) = cause.downcast_ref::<std::io::Error>() {
+            return matches!(
+                e.kind(),
+                std::io::ErrorKind::ConnectionReset
+                    | std::io::ErrorKind::ConnectionAborted
+                    | std::io::ErrorKind::BrokenPipe
+                    | std::io::ErrorKind::UnexpectedEof
+                    | std::io::ErrorKind::TimedOut
+            );
+        }
+        cause.is::<tokio::time::error::Elapsed>()
+    })
+}
+
+/// Exponential backoff with jitter, stretched to honor the server's Retry-After
+fn retry_delay(config: &RetryConfig, attempt: u32, error: &anyhow::Error) -> Duration {
+    let jitter = RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64;
+    let delay = backoff_delay(config, attempt, jitter);
+
+    let retry_after = error
+        .chain()
+        .filter_map(|cause| cause.downcast_ref::<HttpStatusError>())
+        .find_map(|status| status.retry_after);
+    match retry_after {
+        Some(after) => delay.max(after),
+        None => delay,
+    }
+}
+
+/// Base delay doubled per attempt and capped, then randomised into its upper half
+/// (`jitter` in 0..=1) so clients that failed together don't retry together
+fn backoff_delay(config: &RetryConfig, attempt: u32, jitter: f64) -> Duration {
+    let exponential = config.base_delay_ms.saturating_mul(1u64 << attempt.min(20));
+    let capped = exponential.min(config.max_delay_ms) as f64;
+    Duration::from_millis((capped / 2.0 + capped / 2.0 * jitter.clamp(0.0, 1.0)) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    fn fast_config(attempts: u32) -> RetryConfig {
+        RetryConfig { attempts, base_delay_ms: 1, max_delay_ms: 2 }
+    }
+
+    fn status_error(status: u16) -> anyhow::Error {
+        anyhow::Error::new(HttpStatusError { status, retry_after: None }).context(format!("Request failed with status {}", status))
+    }
+
+    #[test]
+    fn test_backoff_delay_grows_and_caps() {
+        let config = RetryConfig { attempts: 5, base_delay_ms: 500, max_delay_ms: 4000 };
+        assert_eq!(backoff_delay(&config, 0, 1.0), Duration::from_millis(500));
+        assert_eq!(backoff_delay(&config, 2, 1.0), Duration::from_millis(2000));
+        assert_eq!(backoff_delay(&config, 10, 1.0), Duration::from_millis(4000));
+        // Jitter never goes below half the delay
+        assert_eq!(backoff_delay(&config, 2, 0.0), Duration::from_millis(1000));
+    }
+
+    #[test]
+    fn test_retryable_errors() {
+        assert!(is_retryable(&status_error(429)));
+        assert!(is_retryable(&status_error(529)));
+        assert!(!is_retryable(&status_error(401)));
+        assert!(!is_retryable(&anyhow::anyhow!("Model not found")));
+
+        let reset = std::io::Error::new(std::io::ErrorKind::ConnectionReset, "reset by peer");
+        assert!(is_retryable(&anyhow::Error::new(reset).context("Failed to send request")));
+
+        let long_wait = HttpStatusError { status: 429, retry_after: Some(Duration::from_secs(3600)) };
+        assert!(!is_retryable(&anyhow::Error::new(long_wait)));
+    }
+
+    #[tokio::test]
+    async fn test_retry_until_success_or_permanent_failure() {
+        let calls = AtomicU32::new(0);
+        let result = retry_with(&fast_config(3), "Test", || async {
+            match calls.fetch_add(1, Ordering::SeqCst) {
+                0 | 1 => Err(status_error(503)),
+                _ => Ok("done"),
+            }
+        }).await;
+        assert_eq!(result.unwrap(), "done");
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        // Permanent failures aren't retried
+        calls.store(0, Ordering::SeqCst);
+        let result: anyhow::Result<()> = retry_with(&fast_config(3), "Test", || async {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Err(status_error(400))
+        }).await;
+        assert!(result.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // Retries stop after the configured number of attempts
+        calls.store(0, Ordering::SeqCst);
+        let result: anyhow::Result<()> = retry_with(&fast_config(2), "Test", || async {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Err(status_error(429))
+        }).await;
+        assert_eq!(result.unwrap_err().to_string(), "Request failed with status 429");
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+}
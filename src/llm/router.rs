@@ -0,0 +1,350 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use super::{LlmProvider, ModelConfig, DEFAULT_OLLAMA_MODEL};
+use crate::prompts::{BudgetAction, LocalOnlyConfig, PromptsConfig, RoutingConfig};
+use crate::file_select;
+use crate::sandbox::resolve;
+use crate::usage::{self, BudgetStatus};
+use crate::webhooks::{self, Event};
+
+const FILE_MARKERS: [&str; 3] = ["--- File: ", "--- Diff: ", "--- Prompt File: "];
+
+// Leading words that mark a request as a shell task, a file change or a question
+const SHELL_WORDS: [&str; 20] = [
+    "run", "execute", "install", "uninstall", "start", "stop", "restart", "launch", "kill", "deploy",
+    "git", "cargo", "npm", "yarn", "pip", "docker", "ssh", "ls", "cd", "curl",
+];
+const EDIT_WORDS: [&str; 22] = [
+    "add", "change", "fix", "refactor", "rename", "implement", "remove", "delete", "update", "replace", "rewrite",
+    "create", "write", "modify", "move", "extract", "convert", "optimize", "make", "edit", "insert", "split",
+];
+const QUESTION_WORDS: [&str; 17] = [
+    "what", "why", "how", "when", "where", "which", "who", "is", "are", "does", "do", "should",
+    "explain", "describe", "summarize", "tell", "show",
+];
+// Polite openings dropped before looking at the first word
+const POLITE_PREFIXES: [&str; 6] = ["please", "can you", "could you", "would you", "will you", "i want you to"];
+
+/// What a request asks for, as far as picking a model goes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TaskKind {
+    Question,
+    Edit,
+    Shell,
+}
+
+impl TaskKind {
+    pub fn label(&self) -> &'static str {
+        match self {
+            TaskKind::Question => "question",
+            TaskKind::Edit => "edit",
+            TaskKind::Shell => "shell",
+        }
+    }
+}
+
+/// Classify a request from its wording. Cheap and local, so it can run before every
+/// request; anything unclear counts as an edit, which gets the most capable model.
+pub fn classify(user_prompt: &str) -> TaskKind {
+    let mut text = user_prompt.trim().to_lowercase();
+    while let Some(rest) = POLITE_PREFIXES.iter().find_map(|prefix| text.strip_prefix(prefix)) {
+        text = rest.trim_start_matches([' ', ',']).to_string();
+    }
+    let words: Vec<&str> = text
+        .split(|c: char| !c.is_alphanumeric() && c != '-' && c != '_')
+        .filter(|word| !word.is_empty())
+        .collect();
+    let Some(first) = words.first() else {
+        return TaskKind::Edit;
+    };
+
+    if SHELL_WORDS.contains(first) {
+        TaskKind::Shell
+    } else if EDIT_WORDS.contains(first) {
+        TaskKind::Edit
+    } else if QUESTION_WORDS.contains(first) || text.ends_with('?') {
+        TaskKind::Question
+    } else if words.iter().any(|word| EDIT_WORDS.contains(word)) {
+        TaskKind::Edit
+    } else {
+        TaskKind::Question
+    }
+}
+
+/// The model `[llm.routing]` names for this kind of request, keeping the session's
+/// tool, sampling and verify settings; `config` when none is set
+pub fn route_task(config: &ModelConfig, user_prompt: &str, routing: &RoutingConfig) -> ModelConfig {
+    let kind = classify(user_prompt);
+    let spec = match kind {
+        TaskKind::Question => routing.question.as_deref(),
+        TaskKind::Edit => routing.edit.as_deref(),
+        TaskKind::Shell => routing.shell.as_deref(),
+    };
+    let Some(spec) = spec.map(str::trim).filter(|spec| !spec.is_empty()) else {
+        return config.clone();
+    };
+    match model_spec(spec) {
+        Ok((routed, model_name)) => ModelConfig { provider: routed.provider, model_name, context_window: None, ..config.clone() },
+        Err(e) => {
+            eprintln!("Warning: {} in [llm.routing] {}; using {}", e, kind.label(), config.display_name());
+            config.clone()
+        }
+    }
+}
+
+/// File paths named by the `--- File: x ---` headers in a context string
+pub fn context_files(context_str: &str) -> Vec<String> {
+    context_str
+        .lines()
+        .filter_map(|line| {
+            FILE_MARKERS.iter().find_map(|marker| {
+                line.strip_prefix(marker)
+                    .and_then(|rest| rest.strip_suffix(" ---"))
+                    .map(|path| file_select::strip_line_range(path.trim()).to_string())
+            })
+        })
+        .collect()
+}
+
+/// Why requests must stay local, if the workspace or any of `files` is under a
+/// `[local_only]` path
+pub fn local_only_reason(config: &LocalOnlyConfig, files: &[String]) -> Option<String> {
+    if config.paths.is_empty() {
+        return None;
+    }
+    let workspace = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+    local_only_reason_in(&workspace, config, files)
+}
+
+fn local_only_reason_in(workspace: &Path, config: &LocalOnlyConfig, files: &[String]) -> Option<String> {
+    let roots: Vec<(&String, PathBuf)> = config
+        .paths
+        .iter()
+        .filter(|path| !path.trim().is_empty())
+        .map(|path| (path, resolve(workspace, Path::new(path.trim()))))
+        .collect();
+
+    let root_workspace = resolve(workspace, Path::new("."));
+    if let Some((path, _)) = roots.iter().find(|(_, root)| root_workspace.starts_with(root)) {
+        return Some(format!("project is local-only ({})", path));
+    }
+
+    files.iter().find_map(|file| {
+        let resolved = resolve(workspace, Path::new(file));
+        roots
+            .iter()
+            .find(|(_, root)| resolved.starts_with(root))
+            .map(|(path, _)| format!("{} is local-only ({})", file, path))
+    })
+}
+
+/// The model to actually ask. Ollama when the workspace or a file in `context_str` is
+/// local-only (callers surface the reason with `local_only_reason`); otherwise `config`,
+/// unless the provider's spend budget is used up and `[budget] action` says to downgrade
+/// or stop.
+pub fn route(config: &ModelConfig, context_str: &str, prompts_config: &PromptsConfig) -> anyhow::Result<ModelConfig> {
+    if let LlmProvider::Ollama = config.provider {
+        return Ok(config.clone());
+    }
+    if local_only_reason(&prompts_config.local_only, &context_files(context_str)).is_some() {
+        return Ok(local_model(config, &prompts_config.local_only));
+    }
+    
+    let Some(status) = usage::exceeded_budget(&config.provider, &prompts_config.budget) else {
+        return Ok(config.clone());
+    };
+    let first_time = first_report(&status);
+    if first_time {
+        let action = match prompts_config.budget.action {
+            BudgetAction::Stop => "requests are stopped",
+            BudgetAction::Local => "using the local model",
+            BudgetAction::Cheaper => "using the provider's fast model",
+        };
+        webhooks::fire(Event::BudgetExceeded { budget: status.to_string(), action: action.to_string() });
+    }
+    match prompts_config.budget.action {
+        BudgetAction::Stop => Err(anyhow::anyhow!(
+            "{} reached. Run /budget override to keep using {} this session, or switch to another provider with /provider",
+            status,
+            config.display_name()
+        )),
+        BudgetAction::Local => {
+            let local = local_model(config, &prompts_config.local_only);
+            if first_time {
+                warn_downgrade(&status, &local);
+            }
+            Ok(local)
+        }
+        BudgetAction::Cheaper => {
+            let cheaper = ModelConfig {
+                model_name: Some(config.get_fast_model_name()),
+                context_window: None,
+                ..config.clone()
+            };
+            if first_time {
+                warn_downgrade(&status, &cheaper);
+            }
+            Ok(cheaper)
+        }
+    }
+}
+
+/// Whether `provider` can still be used without going over its budget
+pub fn within_budget(provider: &LlmProvider, prompts_config: &PromptsConfig) -> bool {
+    usage::exceeded_budget(provider, &prompts_config.budget).is_none()
+}
+
+// Budgets already reported this session, by provider and period
+static WARNED_BUDGETS: Mutex<Option<HashSet<String>>> = Mutex::new(None);
+
+/// Whether this is the first time this session `status`'s budget was found used up
+fn first_report(status: &BudgetStatus) -> bool {
+    let key = format!("{} {}", status.provider, status.period.label());
+    let mut warned = WARNED_BUDGETS.lock().unwrap_or_else(|e| e.into_inner());
+    warned.get_or_insert_with(HashSet::new).insert(key)
+}
+
+fn warn_downgrade(status: &BudgetStatus, downgraded: &ModelConfig) {
+    eprintln!(
+        "Warning: {} reached; using {} instead. Run /budget override to lift the limit for this session",
+        status,
+        downgraded.display_name()
+    );
+}
+
+/// The model from `[providers] quick`, if one is set. Without a model in the spec it's the
+/// provider's fast model.
+pub fn quick_model(prompts_config: &PromptsConfig) -> Option<ModelConfig> {
+    let spec = prompts_config.providers.quick.as_deref().map(str::trim).filter(|spec| !spec.is_empty())?;
+    let (config, model) = model_spec(spec).map_err(|e| eprintln!("Warning: {} in [providers] quick", e)).ok()?;
+    let model_name = model.unwrap_or_else(|| config.get_fast_model_name());
+    Some(ModelConfig { model_name: Some(model_name), ..config })
+}
+
+/// The model a `provider` or `provider:model` spec like `[providers] verify` names. Without
+/// a model in the spec it's the provider's default model.
+pub fn spec_model(spec: &str) -> anyhow::Result<ModelConfig> {
+    let (config, model) = model_spec(spec.trim()).map_err(|e| anyhow::anyhow!(e))?;
+    Ok(ModelConfig { model_name: model, ..config })
+}
+
+/// Split `provider` or `provider:model` into the provider's config and the model, if named
+fn model_spec(spec: &str) -> Result<(ModelConfig, Option<String>), String> {
+    let (provider, model) = match spec.split_once(':') {
+        Some((provider, model)) => (provider, Some(model.trim().to_string()).filter(|model| !model.is_empty())),
+        None => (spec, None),
+    };
+    let provider = LlmProvider::from_name(provider).ok_or_else(|| format!("Unknown provider '{}'", provider.trim()))?;
+    Ok((ModelConfig { provider, ..Default::default() }, model))
+}
+
+fn local_model(config: &ModelConfig, local_only: &LocalOnlyConfig) -> ModelConfig {
+    ModelConfig {
+        provider: LlmProvider::Ollama,
+        model_name: Some(local_only.model.clone().unwrap_or_else(|| DEFAULT_OLLAMA_MODEL.to_string())),
+        native_tools: config.native_tools,
+        context_window: None,
+        sampling: config.sampling.clone(),
+        verify_model: config.verify_model.clone(),
+    }
+}
+
+/// "question request -> Groq/llama-3.3-70b-versatile", when `[llm.routing]` sends this
+/// request somewhere other than `config`
+pub fn routing_note(config: &ModelConfig, user_prompt: &str, routing: &RoutingConfig) -> Option<String> {
+    let routed = route_task(config, user_prompt, routing).display_name();
+    (routed != config.display_name()).then(|| format!("{} request -> {}", classify(user_prompt).label(), routed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn config(paths: &[&str]) -> LocalOnlyConfig {
+        LocalOnlyConfig { paths: paths.iter().map(|p| p.to_string()).collect(), model: None }
+    }
+
+    #[test]
+    fn test_context_files() {
+        let context = "--- File: src/main.rs ---\nfn main() {}\n--- End File: src/main.rs ---\n\
+                       --- Snippet --- \nhi\n--- End Snippet ---\n\
+                       --- Diff: src/lib.rs ---\n@@\n-a\n+b\n--- End Diff: src/lib.rs ---\n\
+                       --- Prompt File: notes.md ---\n# notes\n--- End Prompt File: notes.md ---";
+        assert_eq!(context_files(context), vec!["src/main.rs", "src/lib.rs", "notes.md"]);
+    }
+
+    #[test]
+    fn test_quick_model() {
+        let mut prompts_config = PromptsConfig::default();
+        assert!(quick_model(&prompts_config).is_none());
+
+        prompts_config.providers.quick = Some("groq".to_string());
+        let quick = quick_model(&prompts_config).unwrap();
+        assert!(matches!(quick.provider, LlmProvider::Groq));
+        assert_eq!(quick.get_model_name(), "llama-3.1-8b-instant");
+
+        prompts_config.providers.quick = Some("together:Qwen/Qwen2.5-7B-Instruct-Turbo".to_string());
+        assert_eq!(quick_model(&prompts_config).unwrap().display_name(), "Together/Qwen/Qwen2.5-7B-Instruct-Turbo");
+
+        prompts_config.providers.quick = Some("nope".to_string());
+        assert!(quick_model(&prompts_config).is_none());
+
+        assert!(spec_model("anthropic").unwrap().model_name.is_none());
+        assert_eq!(spec_model(" openrouter:openai/gpt-4o ").unwrap().model_name.as_deref(), Some("openai/gpt-4o"));
+        assert!(spec_model("nope").is_err());
+    }
+
+    #[test]
+    fn test_classify() {
+        assert_eq!(classify("What does route() do?"), TaskKind::Question);
+        assert_eq!(classify("the parser panics on empty input, any idea"), TaskKind::Question);
+        assert_eq!(classify("Please fix the panic in the parser"), TaskKind::Edit);
+        assert_eq!(classify("Can you rename parse to parse_blocks?"), TaskKind::Edit);
+        assert_eq!(classify("the tests should use tempfile, update them"), TaskKind::Edit);
+        assert_eq!(classify("run the tests"), TaskKind::Shell);
+        assert_eq!(classify("cargo build --release"), TaskKind::Shell);
+        assert_eq!(classify(""), TaskKind::Edit);
+    }
+
+    #[test]
+    fn test_route_task() {
+        let config = ModelConfig { native_tools: true, ..ModelConfig::default() };
+        let routing = RoutingConfig { question: Some("groq".to_string()), edit: None, shell: Some("ollama:qwen3:8b".to_string()) };
+
+        let question = route_task(&config, "why is this slow?", &routing);
+        assert!(matches!(question.provider, LlmProvider::Groq));
+        assert!(question.model_name.is_none());
+        assert!(question.native_tools);
+
+        assert_eq!(route_task(&config, "run the linter", &routing).display_name(), "Ollama/qwen3:8b");
+        assert_eq!(route_task(&config, "add a test", &routing).display_name(), config.display_name());
+        assert_eq!(routing_note(&config, "run the linter", &routing).as_deref(), Some("shell request -> Ollama/qwen3:8b"));
+        assert_eq!(routing_note(&config, "add a test", &routing), None);
+    }
+
+    #[test]
+    fn test_local_only_reason() {
+        let dir = TempDir::new().unwrap();
+        let workspace = dir.path();
+        std::fs::create_dir_all(workspace.join("clients/acme")).unwrap();
+
+        let files = vec!["src/lib.rs".to_string(), "clients/acme/contract.md".to_string()];
+        assert_eq!(local_only_reason_in(workspace, &config(&[]), &files), None);
+        assert_eq!(local_only_reason_in(workspace, &config(&["clients/"]), &["src/lib.rs".to_string()]), None);
+
+        let reason = local_only_reason_in(workspace, &config(&["clients/"]), &files).unwrap();
+        assert!(reason.starts_with("clients/acme/contract.md"));
+
+        // `..` can't step out of a local-only directory unnoticed
+        let sneaky = vec!["src/../clients/acme/x".to_string()];
+        assert!(local_only_reason_in(workspace, &config(&["clients"]), &sneaky).is_some());
+
+        // A workspace inside a local-only path is local-only whatever is in context
+        let absolute = workspace.to_string_lossy().to_string();
+        let reason = local_only_reason_in(&workspace.join("clients/acme"), &config(&[&absolute]), &[]).unwrap();
+        assert!(reason.starts_with("project is local-only"));
+    }
+}
@@ -0,0 +1,77 @@
+use serde::de::DeserializeOwned;
+
+/// Replies that don't parse are sent back with the error this many times in total
+pub const JSON_MAX_ATTEMPTS: u32 = 3;
+
+/// System prompt for JSON requests, in place of the KOTA edit instructions
+pub const JSON_INSTRUCTIONS: &str = "You are KOTA, a coding assistant that answers in machine-readable form. \
+Reply with a single valid JSON value and nothing else: no explanation before or after it and no markdown code fences. \
+Follow the structure the user asks for exactly, using double-quoted keys and strings.";
+
+/// The JSON value in a reply, without surrounding prose or code fences
+pub fn extract_json(text: &str) -> &str {
+    let text = text.trim();
+    let text = text
+        .strip_prefix("```json")
+        .or_else(|| text.strip_prefix("```"))
+        .and_then(|rest| rest.trim_end().strip_suffix("```"))
+        .map(str::trim)
+        .unwrap_or(text);
+    if text.starts_with('{') || text.starts_with('[') {
+        return text;
+    }
+
+    // Prose around the value: take the outermost object or array
+    let start = text.find(['{', '[']);
+    let end = text.rfind(['}', ']']);
+    match (start, end) {
+        (Some(start), Some(end)) if end > start => &text[start..=end],
+        _ => text,
+    }
+}
+
+pub fn parse_json<T: DeserializeOwned>(text: &str) -> Result<T, serde_json::Error> {
+    serde_json::from_str(extract_json(text))
+}
+
+/// Follow-up for a reply that didn't parse: the original request, what came back and why it failed
+pub fn repair_prompt(user_prompt: &str, reply: &str, error: &str) -> String {
+    format!(
+        "{}\n\nYour previous reply was not valid JSON for this request ({}):\n{}\n\n\
+        Reply again with only the corrected JSON.",
+        user_prompt,
+        error,
+        reply.trim()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Step {
+        description: String,
+        #[serde(default)]
+        depends_on: Vec<usize>,
+    }
+
+    #[test]
+    fn test_extract_json() {
+        assert_eq!(extract_json("  {\"a\": 1}\n"), "{\"a\": 1}");
+        assert_eq!(extract_json("```json\n[1, 2]\n```"), "[1, 2]");
+        assert_eq!(extract_json("Here is the plan:\n{\"a\": {\"b\": 2}}\nLet me know!"), "{\"a\": {\"b\": 2}}");
+        assert_eq!(extract_json("no json here"), "no json here");
+    }
+
+    #[test]
+    fn test_parse_json_checks_the_shape() {
+        let steps: Vec<Step> = parse_json("```\n[{\"description\": \"Write tests\", \"depends_on\": [1]}]\n```").unwrap();
+        assert_eq!(steps, vec![Step { description: "Write tests".to_string(), depends_on: vec![1] }]);
+
+        // Valid JSON with the wrong structure is a parse failure too, so it gets re-prompted
+        assert!(parse_json::<Vec<Step>>("[{\"title\": \"Write tests\"}]").is_err());
+        assert!(parse_json::<Vec<Step>>("[{\"description\": \"unterminated}]").is_err());
+    }
+}
@@ -0,0 +1,272 @@
+use anyhow::Result;
+use serde_json::{json, Value};
+
+use crate::sr_parser::SearchReplaceBlock;
+
+/// Appended to the system instructions when native tools are enabled
+pub const TOOL_USE_INSTRUCTIONS: &str = "Tools are available for this conversation. Use the edit_file tool for file edits, \
+the run_command tool for shell commands and the add_to_context tool to read a file, instead of writing \
+search/replace or command blocks. Every tool call is shown to the user for approval before it runs.";
+
+/// A tool the model can call, described by a JSON schema for its input
+pub struct ToolDefinition {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub parameters: Value,
+}
+
+/// A tool invocation requested by the model
+#[derive(Debug, Clone, PartialEq)]
+pub struct ToolCall {
+    pub id: String,
+    pub name: String,
+    pub input: Value,
+}
+
+/// What a built-in tool call asks KOTA to do
+#[derive(Debug, Clone)]
+pub enum ToolAction {
+    EditFile(SearchReplaceBlock),
+    RunCommand(String),
+    AddToContext(String),
+}
+
+/// A model response that may include tool calls alongside its text
+#[derive(Debug, Clone, Default)]
+pub struct ToolResponse {
+    pub text: String,
+    pub tool_calls: Vec<ToolCall>,
+}
+
+pub fn builtin_tools() -> Vec<ToolDefinition> {
+    vec![
+        ToolDefinition {
+            name: "edit_file",
+            description: "Replace the first occurrence of exact text in a file that is in the user's context",
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "path": { "type": "string", "description": "Path of the file, as shown in the context" },
+                    "search": { "type": "string", "description": "Exact text to find, including whitespace" },
+                    "replace": { "type": "string", "description": "Text to put in its place" }
+                },
+                "required": ["path", "search", "replace"]
+            }),
+        },
+        ToolDefinition {
+            name: "run_command",
+            description: "Run a shell command in the project directory",
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "command": { "type": "string", "description": "Command line to pass to sh -c" }
+                },
+                "required": ["command"]
+            }),
+        },
+        ToolDefinition {
+            name: "add_to_context",
+            description: "Add a file to the conversation context so it can be read and edited",
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "path": { "type": "string", "description": "Path of the file to add" }
+                },
+                "required": ["path"]
+            }),
+        },
+    ]
+}
+
+/// Tool list in the format of Anthropic's messages API
+pub fn anthropic_tools() -> Value {
+    Value::Array(builtin_tools().into_iter().map(|tool| json!({
+        "name": tool.name,
+        "description": tool.description,
+        "input_schema": tool.parameters,
+    })).collect())
+}
+
+/// Tool list in the OpenAI function-calling format, used by OpenRouter and Ollama
+pub fn openai_tools() -> Value {
+    Value::Array(builtin_tools().into_iter().map(|tool| json!({
+        "type": "function",
+        "function": {
+            "name": tool.name,
+            "description": tool.description,
+            "parameters": tool.parameters,
+        }
+    })).collect())
+}
+
+/// Text and `tool_use` blocks from an Anthropic messages response
+pub fn parse_anthropic_response(body: &Value) -> ToolResponse {
+    let mut response = ToolResponse::default();
+    for block in body["content"].as_array().into_iter().flatten() {
+        match block["type"].as_str() {
+            Some("text") => response.text.push_str(block["text"].as_str().unwrap_or_default()),
+            Some("tool_use") => response.tool_calls.push(ToolCall {
+                id: block["id"].as_str().unwrap_or_default().to_string(),
+                name: block["name"].as_str().unwrap_or_default().to_string(),
+                input: block["input"].clone(),
+            }),
+            _ => {}
+        }
+    }
+    response
+}
+
+/// Text and tool calls from an OpenAI-style chat message. OpenAI-compatible APIs send
+/// arguments as a JSON string; Ollama sends them as an object.
+pub fn parse_openai_message(message: &Value) -> ToolResponse {
+    let tool_calls = message["tool_calls"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .enumerate()
+        .map(|(i, call)| {
+            let arguments = &call["function"]["arguments"];
+            let input = match arguments.as_str() {
+                Some(json) => serde_json::from_str(json).unwrap_or(Value::Null),
+                None => arguments.clone(),
+            };
+            ToolCall {
+                id: call["id"].as_str().map(String::from).unwrap_or_else(|| format!("call_{}", i)),
+                name: call["function"]["name"].as_str().unwrap_or_default().to_string(),
+                input,
+            }
+        })
+        .collect();
+
+    ToolResponse {
+        text: message["content"].as_str().unwrap_or_default().to_string(),
+        tool_calls,
+    }
+}
+
+impl ToolCall {
+    pub fn to_action(&self) -> Result<ToolAction> {
+        let field = |name: &str| -> Result<String> {
+            self.input[name]
+                .as_str()
+                .map(String::from)
+                .ok_or_else(|| anyhow::anyhow!("Tool call {} is missing '{}'", self.name, name))
+        };
+
+        match self.name.as_str() {
+            "edit_file" => Ok(ToolAction::EditFile(SearchReplaceBlock {
+                file_path: field("path")?,
+                search_lines: field("search")?,
+                replace_lines: field("replace")?,
+            })),
+            "run_command" => Ok(ToolAction::RunCommand(field("command")?)),
+            "add_to_context" => Ok(ToolAction::AddToContext(field("path")?)),
+            other => Err(anyhow::anyhow!("Unknown tool: {}", other)),
+        }
+    }
+}
+
+impl ToolResponse {
+    /// Response from a provider without function calling; its S/R and command blocks are
+    /// picked up by the response pipeline like any other reply
+    pub fn from_text(text: String) -> Self {
+        Self { text, tool_calls: Vec::new() }
+    }
+
+    /// Render tool calls as the S/R and command blocks the TUI already knows how to
+    /// review, so they go through the same approval steps
+    pub fn to_text_with_blocks(&self) -> String {
+        let mut text = self.text.trim_end().to_string();
+        for call in &self.tool_calls {
+            let rendered = match call.to_action() {
+                Ok(ToolAction::EditFile(block)) => format!(
+                    "{}\n<<<<<<< SEARCH\n{}\n=======\n{}\n>>>>>>> REPLACE",
+                    block.file_path,
+                    block.search_lines.trim_end_matches('\n'),
+                    block.replace_lines.trim_end_matches('\n')
+                ),
+                Ok(ToolAction::RunCommand(command)) => format!("```bash\n{}\n```", command),
+                Ok(ToolAction::AddToContext(path)) => format!("KOTA asked to read `{}`. Add it with :add {}", path, path),
+                Err(e) => format!("[Ignored tool call: {}]", e),
+            };
+            if !text.is_empty() {
+                text.push_str("\n\n");
+            }
+            text.push_str(&rendered);
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_anthropic_tool_use() {
+        let body = json!({
+            "content": [
+                {"type": "text", "text": "Updating the greeting."},
+                {"type": "tool_use", "id": "toolu_1", "name": "edit_file",
+                 "input": {"path": "src/main.rs", "search": "Hello", "replace": "Hi"}}
+            ],
+            "stop_reason": "tool_use"
+        });
+
+        let response = parse_anthropic_response(&body);
+        assert_eq!(response.text, "Updating the greeting.");
+        assert_eq!(response.tool_calls.len(), 1);
+        match response.tool_calls[0].to_action().unwrap() {
+            ToolAction::EditFile(block) => {
+                assert_eq!(block.file_path, "src/main.rs");
+                assert_eq!(block.replace_lines, "Hi");
+            }
+            other => panic!("unexpected action {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_openai_and_ollama_tool_calls() {
+        let openai = json!({
+            "content": null,
+            "tool_calls": [{"id": "call_a", "type": "function",
+                "function": {"name": "run_command", "arguments": "{\"command\":\"cargo test\"}"}}]
+        });
+        let response = parse_openai_message(&openai);
+        assert!(matches!(response.tool_calls[0].to_action().unwrap(), ToolAction::RunCommand(cmd) if cmd == "cargo test"));
+
+        // Ollama sends arguments as an object and no call id
+        let ollama = json!({
+            "content": "",
+            "tool_calls": [{"function": {"name": "add_to_context", "arguments": {"path": "Cargo.toml"}}}]
+        });
+        let response = parse_openai_message(&ollama);
+        assert_eq!(response.tool_calls[0].id, "call_0");
+        assert!(matches!(response.tool_calls[0].to_action().unwrap(), ToolAction::AddToContext(path) if path == "Cargo.toml"));
+
+        let unknown = ToolCall { id: "x".to_string(), name: "delete_repo".to_string(), input: json!({}) };
+        assert!(unknown.to_action().is_err());
+    }
+
+    #[test]
+    fn test_text_fallback_roundtrip() {
+        let response = ToolResponse {
+            text: "Done.".to_string(),
+            tool_calls: vec![
+                ToolCall { id: "1".to_string(), name: "edit_file".to_string(),
+                    input: json!({"path": "a.txt", "search": "old", "replace": "new"}) },
+                ToolCall { id: "2".to_string(), name: "run_command".to_string(), input: json!({"command": "ls"}) },
+            ],
+        };
+
+        // Rendered blocks parse back into the same edit and command
+        let text = response.to_text_with_blocks();
+        let processed = crate::response_pipeline::process_response_as(crate::prompts::EditFormat::default(), "", &text, &[]);
+        assert_eq!(processed.edits.len(), 1);
+        assert_eq!(processed.edits[0].search_lines, "old");
+        assert_eq!(processed.commands, vec!["ls"]);
+
+        // Text-only responses aren't rendered a second time
+        assert_eq!(ToolResponse::from_text(text.clone()).to_text_with_blocks(), text);
+    }
+}
@@ -0,0 +1,50 @@
+use crate::sr_parser;
+
+/// The verifier's reply when the draft's edits are right as they are
+pub const APPROVED: &str = "APPROVED";
+
+pub const VERIFY_INSTRUCTIONS: &str = "You review code edits drafted by a faster model before they are applied. \
+Each edit is a SEARCH/REPLACE block: the file path on its own line, then <<<<<<< SEARCH, the exact text to find, \
+=======, the replacement, and >>>>>>> REPLACE. Check every block against the files in the context: the SEARCH text \
+must match the file exactly, including whitespace, and the REPLACE text must do what the user asked without breaking \
+the code around it. If every block is correct, reply with the single word APPROVED. Otherwise reply with the whole \
+response rewritten, keeping its explanation and commands, with every block corrected and any block that shouldn't be \
+applied removed.";
+
+/// Whether a draft has edits worth a second look
+pub fn has_edits(draft: &str) -> bool {
+    sr_parser::contains_sr_blocks(draft)
+}
+
+pub fn verify_prompt(user_prompt: &str, draft: &str) -> String {
+    format!("The user asked:\n{}\n\nThe drafted response:\n{}", user_prompt, draft)
+}
+
+/// The response to use: the draft when the verifier approved it or said nothing, otherwise the rewrite
+pub fn accept(draft: String, reply: &str) -> String {
+    let reply = reply.trim();
+    if reply.is_empty() || reply.trim_end_matches('.').eq_ignore_ascii_case(APPROVED) {
+        return draft;
+    }
+    reply.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DRAFT: &str = "Renamed it.\n\nsrc/lib.rs\n<<<<<<< SEARCH\nfn old() {}\n=======\nfn new() {}\n>>>>>>> REPLACE\n";
+
+    #[test]
+    fn test_accept_verifier_reply() {
+        assert!(has_edits(DRAFT));
+        assert!(!has_edits("No changes needed."));
+        assert_eq!(accept(DRAFT.to_string(), "APPROVED"), DRAFT);
+        assert_eq!(accept(DRAFT.to_string(), " approved.\n"), DRAFT);
+        assert_eq!(accept(DRAFT.to_string(), ""), DRAFT);
+
+        let corrected = DRAFT.replace("fn old() {}", "pub fn old() {}");
+        assert_eq!(accept(DRAFT.to_string(), &corrected), corrected.trim());
+        assert!(verify_prompt("Rename old", DRAFT).starts_with("The user asked:\nRename old\n\nThe drafted response:\nRenamed it."));
+    }
+}
@@ -16,11 +16,50 @@ mod memory;
 mod commands;
 mod cli;
 mod agents;
+mod web_search;
+mod usage;
+mod db;
+mod history;
+mod prompt_pipeline;
+mod response_pipeline;
+mod approvals;
+mod sandbox;
+mod compare;
+mod vector_index;
+mod secrets;
+mod todos;
+mod change_report;
+mod clock;
+mod diff_review;
+mod validate;
+mod update;
+mod file_select;
+mod project;
+mod explain;
+mod style;
+mod changelog;
+mod issue;
+mod compact;
+mod backup;
+mod webhooks;
+mod instructions;
+mod email;
+mod focus;
+mod output_filter;
+mod udiff;
+mod batch;
+mod summarize;
+mod undo;
+mod trace;
+mod lint;
+mod crash;
 
 use context::ContextManager;
 use llm::ModelConfig;
+use trace::SpanExt;
 
 fn render_markdown(content: &str) -> anyhow::Result<()> {
+    let _span = trace::span("render.markdown").with("bytes", content.len());
     // Create a markdown renderer with customized skin
     let mut skin = MadSkin::default();
     
@@ -77,6 +116,7 @@ fn render_markdown(content: &str) -> anyhow::Result<()> {
 async fn main() -> anyhow::Result<()> {
     // Parse command line arguments
     let args: Vec<String> = env::args().collect();
+    crash::install();
     let use_tui = args.contains(&"--tui".to_string()) || args.contains(&"-t".to_string());
     
     // Show help if requested
@@ -84,12 +124,28 @@ async fn main() -> anyhow::Result<()> {
         println!("KOTA - AI Coding Assistant");
         println!();
         println!("Usage: {} [OPTIONS]", args[0]);
+        println!("       {} update [--check] [--yes]", args[0]);
+        println!("       {} shell-init [bash|zsh|fish]", args[0]);
+        println!("       {} storage [status|init|encrypt]", args[0]);
+        println!("       {} backup [create|restore|verify]", args[0]);
+        println!("       {} email [status|poll|watch]", args[0]);
+        println!("       {} batch [submit|status|list|results]", args[0]);
         println!();
         println!("Options:");
         println!("  -t, --tui       Launch with modern TUI interface");
+        println!("  --maintenance   Run nightly maintenance and exit (add --force to ignore the rate limit)");
+        println!("  --trace-out F   Write timing spans to F as a Chrome trace (open in chrome://tracing, Perfetto or speedscope)");
         println!("  -h, --help      Show this help message");
         println!("  -v, --version   Show version information");
         println!();
+        println!("Commands:");
+        println!("  update          Install the latest release (--check only shows what's new, --yes skips the prompt)");
+        println!("  shell-init      Print a shell hook that makes kota use the nearest project with a .kota/ folder");
+        println!("  storage         Show storage encryption status, create a key in the OS keychain, or encrypt existing data");
+        println!("  backup          Archive ~/.kota and the project's .kota (--encrypt with the storage key), or verify or restore an archive (--only memory,...)");
+        println!("  email           Turn tagged mail from allowed senders into tasks and memory entries ([email] in prompts.toml)");
+        println!("  batch           Send many prompts through the Anthropic or Gemini batch API at about half price, then merge the answers");
+        println!();
         println!("Default: Launch in classic CLI mode");
         return Ok(());
     }
@@ -100,7 +156,79 @@ async fn main() -> anyhow::Result<()> {
         return Ok(());
     }
     
-    let context_manager = ContextManager::new();
+    // Timing spans for diagnosing slow requests, context builds and frames
+    let _tracing = args.iter().position(|arg| arg == "--trace-out").and_then(|i| args.get(i + 1)).and_then(|path| {
+        match trace::start(path) {
+            Ok(tracing) => {
+                println!("Tracing to {}", path);
+                Some(tracing)
+            }
+            Err(e) => {
+                eprintln!("Warning: {}", e);
+                None
+            }
+        }
+    });
+    
+    // Shell hook for per-project sessions and context, e.g. eval "$(kota shell-init bash)"
+    if args.get(1).map(String::as_str) == Some("shell-init") {
+        print!("{}", project::shell_init(args.get(2).map(String::as_str))?);
+        return Ok(());
+    }
+    
+    // Storage encryption key and status
+    if args.get(1).map(String::as_str) == Some("storage") {
+        return db::encryption::run(&args[2..]);
+    }
+    
+    // Archive or restore ~/.kota
+    if args.get(1).map(String::as_str) == Some("backup") {
+        return backup::run(&args[2..]);
+    }
+    
+    // Tasks and notes sent by email
+    if args.get(1).map(String::as_str) == Some("email") {
+        return email::run(&args[2..]);
+    }
+    
+    // Bulk jobs through provider batch APIs
+    if args.get(1).map(String::as_str) == Some("batch") {
+        return batch::run(&args[2..]).await;
+    }
+    
+    // Self-update from GitHub releases
+    if args.get(1).map(String::as_str) == Some("update") {
+        return update::run(&args[2..]).await;
+    }
+    
+    // Inside a project the shell hook found, run from its root
+    if let Some(root) = project::enter_project() {
+        println!("Project: {}", root.display());
+    }
+    if let Some((path, _)) = instructions::project_instructions() {
+        println!("Instructions: {}", path);
+    }
+    
+    // Nightly maintenance, intended to be run from cron
+    if args.contains(&"--maintenance".to_string()) {
+        let agent = agents::maintenance_agent::MaintenanceAgent::new(ModelConfig::default())?;
+        let report = agent.run(args.contains(&"--force".to_string())).await?;
+        match report.skipped {
+            Some(reason) => println!("Maintenance skipped: {}", reason),
+            None => {
+                if let Some(path) = report.briefing_path {
+                    println!("Morning briefing written to {}", path.display());
+                }
+                if let Some(id) = report.transcript_id {
+                    println!("Actions logged in transcript {}", id);
+                }
+            }
+        }
+        return Ok(());
+    }
+    
+    let mut context_manager = ContextManager::new();
+    project::load_context_preset(&mut context_manager);
     let model_config = ModelConfig::default();
     
     // Launch appropriate interface
@@ -0,0 +1,292 @@
+use std::path::Path;
+
+use anyhow::Result;
+use regex::Regex;
+
+use crate::file_select;
+use crate::prompts::{FilterAction, FiltersConfig};
+use crate::response_pipeline::{ProcessedResponse, ResponseInput, ResponseProcessor};
+use crate::sr_parser::SearchReplaceBlock;
+
+// Lines that mark code as carrying someone else's license
+const LICENSE_NOTICES: &[&str] = &[
+    "spdx-license-identifier",
+    "copyright (c)",
+    "copyright ©",
+    "all rights reserved",
+    "licensed under the",
+    "gnu general public license",
+    "gnu lesser general public license",
+    "mozilla public license",
+    "permission is hereby granted, free of charge",
+    "redistribution and use in source and binary forms",
+];
+
+// Extensions a backticked name needs before it's taken for a file path
+const PATH_EXTENSIONS: &[&str] = &[
+    "rs", "toml", "md", "json", "yaml", "yml", "lock", "js", "jsx", "ts", "tsx", "mjs", "py", "go", "rb",
+    "java", "kt", "c", "h", "cc", "cpp", "hpp", "cs", "swift", "php", "sh", "sql", "html", "css", "scss", "txt",
+];
+
+/// Runs last in the response pipeline, checking what the earlier processors found before
+/// any of it is reviewed or applied: placeholders like `YOUR_API_KEY` written into files,
+/// large blocks carrying a license notice, and paths that aren't in the repository.
+/// `[filters]` sets whether each check blocks, warns or is off.
+pub struct OutputFilterProcessor {
+    config: FiltersConfig,
+}
+
+impl OutputFilterProcessor {
+    pub fn new(config: FiltersConfig) -> Self {
+        Self { config }
+    }
+
+    /// Drop or flag edits that write a placeholder the file didn't already have
+    fn check_placeholders(&self, out: &mut ProcessedResponse) {
+        let action = self.config.placeholders;
+        if action == FilterAction::Off {
+            return;
+        }
+        let mut kept = Vec::new();
+        for block in std::mem::take(&mut out.edits) {
+            match added_placeholder(&block, &self.config.placeholder_markers) {
+                Some(marker) if action == FilterAction::Block => {
+                    out.problems.push(format!("Blocked edit to {}: it writes the placeholder {}", block.file_path, marker));
+                }
+                Some(marker) => {
+                    out.warnings.push(format!("Edit to {} writes the placeholder {}; fill it in before using it", block.file_path, marker));
+                    kept.push(block);
+                }
+                None => kept.push(block),
+            }
+        }
+        out.edits = kept;
+    }
+
+    /// Flag large edits and code blocks that carry a license notice, as code that may have been
+    /// reproduced from another project
+    fn check_licensed_code(&self, text: &str, out: &mut ProcessedResponse) {
+        let action = self.config.licensed_code;
+        if action == FilterAction::Off {
+            return;
+        }
+        let min_lines = self.config.licensed_code_lines;
+        let mut kept = Vec::new();
+        for block in std::mem::take(&mut out.edits) {
+            let notice = (block.replace_lines.lines().count() >= min_lines)
+                .then(|| new_license_notice(&block.replace_lines, &block.search_lines))
+                .flatten();
+            match notice {
+                Some(notice) if action == FilterAction::Block => {
+                    out.problems.push(format!(
+                        "Blocked edit to {}: it adds {} lines with a license notice (\"{}\") and may be copied from another project",
+                        block.file_path, block.replace_lines.lines().count(), notice
+                    ));
+                }
+                Some(notice) => {
+                    out.warnings.push(format!(
+                        "Edit to {} adds {} lines with a license notice (\"{}\"); check where the code comes from before applying it",
+                        block.file_path, block.replace_lines.lines().count(), notice
+                    ));
+                    kept.push(block);
+                }
+                None => kept.push(block),
+            }
+        }
+        out.edits = kept;
+
+        // Code blocks in the reply are only shown, so they're flagged either way
+        for code in code_blocks(text) {
+            if code.lines().count() < min_lines {
+                continue;
+            }
+            if let Some(notice) = new_license_notice(&code, "") {
+                out.warnings.push(format!(
+                    "The reply has a {}-line code block with a license notice (\"{}\"); it may be copied from another project",
+                    code.lines().count(), notice
+                ));
+            }
+        }
+    }
+
+    /// Flag edits, reads and mentioned paths for files that don't exist. Blocking drops edits
+    /// that would have to match text in a missing file; edits creating a file are kept.
+    fn check_unknown_paths(&self, text: &str, out: &mut ProcessedResponse, root: &Path) {
+        let action = self.config.unknown_paths;
+        if action == FilterAction::Off {
+            return;
+        }
+        let exists = |path: &str| root.join(file_select::strip_line_range(path)).exists();
+        let mut kept = Vec::new();
+        for block in std::mem::take(&mut out.edits) {
+            if block.search_lines.trim().is_empty() || exists(&block.file_path) {
+                kept.push(block);
+            } else if action == FilterAction::Block {
+                out.problems.push(format!("Blocked edit to {}: the file isn't in the repository", block.file_path));
+            } else {
+                out.warnings.push(format!("Edit to {}, which isn't in the repository; it can't apply", block.file_path));
+                kept.push(block);
+            }
+        }
+        out.edits = kept;
+
+        for path in &out.reads {
+            if !exists(path) {
+                out.warnings.push(format!("Asked to read {}, which isn't in the repository", path));
+            }
+        }
+        let mut mentioned = Vec::new();
+        for path in mentioned_paths(text) {
+            let edited = out.edits.iter().any(|block| block.file_path == path);
+            if !edited && !out.reads.contains(&path) && !mentioned.contains(&path) && !exists(&path) {
+                mentioned.push(path);
+            }
+        }
+        if !mentioned.is_empty() {
+            out.warnings.push(format!("The reply mentions files that aren't in the repository: {}", mentioned.join(", ")));
+        }
+    }
+}
+
+impl ResponseProcessor for OutputFilterProcessor {
+    fn name(&self) -> &str {
+        "filters"
+    }
+
+    fn process(&self, input: &ResponseInput, out: &mut ProcessedResponse) -> Result<String> {
+        self.check_placeholders(out);
+        self.check_licensed_code(input.text, out);
+        self.check_unknown_paths(input.text, out, Path::new("."));
+        Ok(input.text.to_string())
+    }
+}
+
+/// The first marker in the replacement text that wasn't already in the searched text.
+/// Markers match without regard to case.
+fn added_placeholder<'a>(block: &SearchReplaceBlock, markers: &'a [String]) -> Option<&'a str> {
+    let added = block.replace_lines.to_lowercase();
+    let existing = block.search_lines.to_lowercase();
+    markers
+        .iter()
+        .map(|marker| marker.trim())
+        .find(|marker| !marker.is_empty() && added.contains(&marker.to_lowercase()) && !existing.contains(&marker.to_lowercase()))
+}
+
+/// A license notice in `added` that `existing` doesn't have
+fn new_license_notice(added: &str, existing: &str) -> Option<&'static str> {
+    let added = added.to_lowercase();
+    let existing = existing.to_lowercase();
+    LICENSE_NOTICES.iter().copied().find(|notice| added.contains(notice) && !existing.contains(notice))
+}
+
+/// The contents of each fenced code block
+fn code_blocks(text: &str) -> Vec<String> {
+    let mut blocks = Vec::new();
+    let mut current: Option<Vec<&str>> = None;
+    for line in text.lines() {
+        if line.trim_start().starts_with("```") {
+            match current.take() {
+                Some(lines) => blocks.push(lines.join("\n")),
+                None => current = Some(Vec::new()),
+            }
+        } else if let Some(lines) = current.as_mut() {
+            lines.push(line);
+        }
+    }
+    blocks
+}
+
+/// Backticked file paths in the prose, like `src/main.rs` or `Cargo.toml:12`
+fn mentioned_paths(text: &str) -> Vec<String> {
+    let Ok(span) = Regex::new(r"`([\w./-]+\.([A-Za-z0-9]+))(?::[\d-]+)?`") else {
+        return Vec::new();
+    };
+    let mut in_code_block = false;
+    let mut paths = Vec::new();
+    for line in text.lines() {
+        if line.trim_start().starts_with("```") {
+            in_code_block = !in_code_block;
+            continue;
+        }
+        if in_code_block {
+            continue;
+        }
+        for captures in span.captures_iter(line) {
+            if PATH_EXTENSIONS.contains(&captures[2].to_lowercase().as_str()) {
+                paths.push(captures[1].trim_start_matches("./").to_string());
+            }
+        }
+    }
+    paths
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn edit(path: &str, search: &str, replace: &str) -> SearchReplaceBlock {
+        SearchReplaceBlock { file_path: path.to_string(), search_lines: search.to_string(), replace_lines: replace.to_string() }
+    }
+
+    #[test]
+    fn test_placeholders() {
+        let filter = OutputFilterProcessor::new(FiltersConfig::default());
+        let mut out = ProcessedResponse::default();
+        out.edits.push(edit("src/api.rs", "let key = load();", "let key = \"your_api_key\";"));
+        out.edits.push(edit("README.md", "Set YOUR_API_KEY", "Export YOUR_API_KEY first"));
+        filter.check_placeholders(&mut out);
+        assert_eq!(out.edits.len(), 1);
+        assert_eq!(out.edits[0].file_path, "README.md");
+        assert_eq!(out.problems, vec!["Blocked edit to src/api.rs: it writes the placeholder YOUR_API_KEY"]);
+
+        let filter = OutputFilterProcessor::new(FiltersConfig { placeholders: FilterAction::Warn, ..FiltersConfig::default() });
+        let mut out = ProcessedResponse::default();
+        out.edits.push(edit("src/api.rs", "", "token = REPLACE_ME"));
+        filter.check_placeholders(&mut out);
+        assert_eq!(out.edits.len(), 1);
+        assert!(out.warnings[0].contains("REPLACE_ME"));
+    }
+
+    #[test]
+    fn test_licensed_code() {
+        let filter = OutputFilterProcessor::new(FiltersConfig { licensed_code_lines: 3, ..FiltersConfig::default() });
+        let copied = "// Copyright (c) 2019 Someone Else\n// SPDX-License-Identifier: GPL-3.0\nfn a() {}\nfn b() {}";
+        let mut out = ProcessedResponse::default();
+        out.edits.push(edit("src/a.rs", "", copied));
+        out.edits.push(edit("src/b.rs", "// Copyright (c) 2024 Us\nfn a() {}", "// Copyright (c) 2024 Us\nfn a() {}\nfn b() {}"));
+        filter.check_licensed_code(&format!("Here:\n```rust\n{}\n```", copied), &mut out);
+        assert_eq!(out.edits.len(), 2);
+        assert_eq!(out.warnings.len(), 2);
+        assert!(out.warnings[0].starts_with("Edit to src/a.rs adds 4 lines with a license notice"));
+        assert!(out.warnings[1].starts_with("The reply has a 4-line code block"));
+    }
+
+    #[test]
+    fn test_unknown_paths() {
+        let dir = TempDir::new().unwrap();
+        fs::create_dir_all(dir.path().join("src")).unwrap();
+        fs::write(dir.path().join("src/main.rs"), "fn main() {}\n").unwrap();
+        let text = "Change `src/main.rs:1` and `src/missing.rs`, then `src/new.rs`; see `self.items` and `cargo test`.";
+        let mut out = ProcessedResponse::default();
+        out.edits.push(edit("src/main.rs", "fn main() {}", "fn main() { run(); }"));
+        out.edits.push(edit("src/new.rs", "", "pub fn run() {}"));
+        out.edits.push(edit("src/gone.rs", "fn old() {}", "fn new() {}"));
+        out.reads.push("docs/none.md".to_string());
+
+        let filter = OutputFilterProcessor::new(FiltersConfig::default());
+        filter.check_unknown_paths(text, &mut out, dir.path());
+        assert_eq!(out.edits.len(), 3);
+        assert_eq!(out.warnings, vec![
+            "Edit to src/gone.rs, which isn't in the repository; it can't apply".to_string(),
+            "Asked to read docs/none.md, which isn't in the repository".to_string(),
+            "The reply mentions files that aren't in the repository: src/missing.rs".to_string(),
+        ]);
+
+        let filter = OutputFilterProcessor::new(FiltersConfig { unknown_paths: FilterAction::Block, ..FiltersConfig::default() });
+        filter.check_unknown_paths(text, &mut out, dir.path());
+        assert_eq!(out.edits.len(), 2);
+        assert_eq!(out.problems, vec!["Blocked edit to src/gone.rs: the file isn't in the repository"]);
+    }
+}
@@ -0,0 +1,148 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+
+use crate::context::ContextManager;
+
+/// Set by the `kota shell-init` hook to the nearest directory above the shell's with a `.kota/` folder
+pub const PROJECT_ENV: &str = "KOTA_PROJECT";
+/// Paths and globs added to context at startup, one per line
+pub const CONTEXT_PRESET: &str = ".kota/context";
+
+const HOOK_POSIX: &str = r#"_kota_project_hook() {
+  local dir="$PWD"
+  while [ -n "$dir" ]; do
+    if [ "$dir" != "$HOME" ] && [ -d "$dir/.kota" ]; then
+      export KOTA_PROJECT="$dir"
+      return
+    fi
+    [ "$dir" = "/" ] && break
+    dir="$(dirname "$dir")"
+  done
+  unset KOTA_PROJECT
+}
+"#;
+
+const HOOK_BASH: &str = r#"if [[ ";${PROMPT_COMMAND:-};" != *";_kota_project_hook;"* ]]; then
+  PROMPT_COMMAND="_kota_project_hook;${PROMPT_COMMAND:-}"
+fi
+_kota_project_hook
+"#;
+
+const HOOK_ZSH: &str = r#"autoload -Uz add-zsh-hook
+add-zsh-hook chpwd _kota_project_hook
+_kota_project_hook
+"#;
+
+const HOOK_FISH: &str = r#"function __kota_project_hook --on-variable PWD
+    set -l dir $PWD
+    while test -n "$dir"
+        if test "$dir" != "$HOME" -a -d "$dir/.kota"
+            set -gx KOTA_PROJECT $dir
+            return
+        end
+        test "$dir" = /; and break
+        set dir (dirname $dir)
+    end
+    set -e KOTA_PROJECT
+end
+__kota_project_hook
+"#;
+
+/// `kota shell-init [bash|zsh|fish]`: the hook to eval in the shell's rc file. Without an
+/// argument the shell comes from $SHELL.
+pub fn shell_init(shell: Option<&str>) -> Result<String> {
+    let shell = match shell {
+        Some(shell) => shell.to_string(),
+        None => env::var("SHELL").ok().and_then(|path| Path::new(&path).file_name().map(|name| name.to_string_lossy().to_string())).unwrap_or_default(),
+    };
+    match shell.as_str() {
+        "bash" => Ok(format!("{}{}", HOOK_POSIX, HOOK_BASH)),
+        "zsh" => Ok(format!("{}{}", HOOK_POSIX, HOOK_ZSH)),
+        "fish" => Ok(HOOK_FISH.to_string()),
+        "" => Err(anyhow::anyhow!("Can't tell which shell this is; run kota shell-init bash, zsh or fish")),
+        other => Err(anyhow::anyhow!("No shell hook for {}; use bash, zsh or fish", other)),
+    }
+}
+
+/// The project `root` names, if it's where the working directory is and has a `.kota/` folder.
+/// A stale KOTA_PROJECT from another directory, or the home directory's own `~/.kota`, is ignored.
+fn project_root(cwd: &Path, root: &str, home: Option<&Path>) -> Option<PathBuf> {
+    let root = PathBuf::from(root);
+    if !root.is_absolute() || !cwd.starts_with(&root) || !root.join(".kota").is_dir() {
+        return None;
+    }
+    if home.is_some_and(|home| home == root) {
+        return None;
+    }
+    Some(root)
+}
+
+/// Move to the project the shell hook found, so project sessions, todos and settings under
+/// `.kota/` are used from any of its subdirectories. Returns the root when it moved.
+pub fn enter_project() -> Option<PathBuf> {
+    let value = env::var(PROJECT_ENV).ok()?;
+    let cwd = env::current_dir().ok()?;
+    let home = env::var_os("HOME").map(PathBuf::from);
+    let root = project_root(&cwd, &value, home.as_deref())?;
+    if root == cwd {
+        return None;
+    }
+    match env::set_current_dir(&root) {
+        Ok(()) => Some(root),
+        Err(e) => {
+            eprintln!("Warning: Could not switch to project {}: {}", root.display(), e);
+            None
+        }
+    }
+}
+
+/// Add the files and globs listed in `.kota/context`; blank lines and `#` comments are skipped
+pub fn load_context_preset(context: &mut ContextManager) {
+    let Ok(preset) = fs::read_to_string(CONTEXT_PRESET) else { return };
+    for entry in preset_entries(&preset) {
+        if let Err(e) = context.add_file(entry) {
+            eprintln!("Warning: {} entry {}: {}", CONTEXT_PRESET, entry, e);
+        }
+    }
+}
+
+fn preset_entries(preset: &str) -> Vec<&str> {
+    preset.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_shell_hooks() {
+        for shell in ["bash", "zsh", "fish"] {
+            let hook = shell_init(Some(shell)).unwrap();
+            assert!(hook.contains("KOTA_PROJECT"), "{} hook", shell);
+        }
+        assert!(shell_init(Some("bash")).unwrap().contains("PROMPT_COMMAND"));
+        assert!(shell_init(Some("zsh")).unwrap().contains("add-zsh-hook chpwd"));
+        assert!(shell_init(Some("tcsh")).is_err());
+    }
+
+    #[test]
+    fn test_project_root_and_preset() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().to_path_buf();
+        fs::create_dir_all(root.join(".kota")).unwrap();
+        fs::create_dir_all(root.join("src/llm")).unwrap();
+        let root_str = root.to_string_lossy().to_string();
+
+        assert_eq!(project_root(&root.join("src/llm"), &root_str, None), Some(root.clone()));
+        assert_eq!(project_root(Path::new("/"), &root_str, None), None);
+        assert_eq!(project_root(&root.join("src"), &root_str, Some(&root)), None);
+        assert_eq!(project_root(&root.join("src"), "relative/path", None), None);
+        assert_eq!(project_root(&root.join("src"), &root.join("src").to_string_lossy(), None), None);
+
+        assert_eq!(preset_entries("# always\nsrc/main.rs\n\n  src/llm/**/*.rs  \n"), vec!["src/main.rs", "src/llm/**/*.rs"]);
+    }
+}
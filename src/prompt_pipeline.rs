@@ -0,0 +1,411 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::Result;
+
+use crate::context::repo_map;
+use crate::db::{Database, DATABASE_PATH};
+use crate::dynamic_prompts;
+use crate::history;
+use crate::llm::conversation::{self, Turn};
+use crate::llm::estimate_tokens;
+use crate::llm::router;
+use crate::prompts::{PipelineConfig, PromptsConfig};
+use crate::todos::{TodoList, TODO_FILE};
+
+pub const PROJECT_NOTES_FILE: &str = "KOTA.md";
+
+/// Which part of the request a stage's text ends up in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PromptSection {
+    System,
+    Context,
+    User,
+}
+
+/// What the caller already has when a prompt is assembled; stages fetch anything else themselves
+pub struct PromptInput<'a> {
+    pub system_instructions: &'a str,
+    pub context_files: &'a str,
+    pub user_message: &'a str,
+}
+
+/// One step of prompt assembly
+pub trait PromptStage: Send + Sync {
+    fn name(&self) -> &str;
+
+    fn section(&self) -> PromptSection {
+        PromptSection::Context
+    }
+
+    /// Truncation keeps the end of the text instead of the start, for stages where the newest entries come last
+    fn keep_tail(&self) -> bool {
+        false
+    }
+
+    /// Text for this stage, or None when it has nothing to add
+    fn render(&self, input: &PromptInput) -> Result<Option<String>>;
+}
+
+/// How one stage contributed to an assembled prompt
+#[derive(Debug, Clone, PartialEq)]
+pub struct StageTrace {
+    pub stage: String,
+    pub tokens: u64,
+    pub budget: Option<u64>,
+    pub truncated: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AssembledPrompt {
+    pub system: String,
+    pub context: String,
+    pub user: String,
+    /// Earlier turns of the conversation, sent as messages before `user`
+    pub history: Vec<Turn>,
+    pub trace: Vec<StageTrace>,
+}
+
+impl AssembledPrompt {
+    pub fn history_tokens(&self) -> u64 {
+        self.history.iter().map(Turn::tokens).sum()
+    }
+
+    pub fn trace_report(&self) -> String {
+        let mut report = String::from("Prompt assembly:");
+        for stage in &self.trace {
+            let budget = stage.budget.map(|budget| format!(" / {}", budget)).unwrap_or_default();
+            let truncated = if stage.truncated { " (truncated)" } else { "" };
+            report.push_str(&format!("\n  {:<14} {:>6}{} tokens{}", stage.stage, stage.tokens, budget, truncated));
+        }
+        report
+    }
+}
+
+pub struct PromptPipeline {
+    stages: Vec<(Box<dyn PromptStage>, Option<u64>)>,
+    // The "history" stage: earlier turns go in as messages, so it isn't a text stage.
+    // The inner value is its token budget.
+    history: Option<Option<u64>>,
+    trace: bool,
+}
+
+impl PromptPipeline {
+    pub fn new() -> Self {
+        Self { stages: Vec::new(), history: None, trace: false }
+    }
+
+    /// Built-in stages in the order and with the budgets from `[pipeline]` in prompts.toml
+    pub fn from_config(config: &PipelineConfig) -> Self {
+        let mut pipeline = Self::new();
+        pipeline.trace = config.trace;
+        for name in &config.stages {
+            if name == "history" {
+                pipeline.history = Some(config.budgets.get(name).copied());
+                continue;
+            }
+            match builtin_stage(name) {
+                Some(stage) => pipeline = pipeline.stage(stage, config.budgets.get(name).copied()),
+                None => eprintln!("Warning: Unknown prompt stage '{}' in prompts.toml", name),
+            }
+        }
+        pipeline
+    }
+
+    /// Append a stage, optionally capped at `budget` estimated tokens
+    pub fn stage(mut self, stage: Box<dyn PromptStage>, budget: Option<u64>) -> Self {
+        self.stages.push((stage, budget));
+        self
+    }
+
+    pub fn assemble(&self, input: &PromptInput) -> AssembledPrompt {
+        let mut sections: HashMap<PromptSection, Vec<String>> = HashMap::new();
+        let mut trace = Vec::new();
+
+        for (stage, budget) in &self.stages {
+            let text = match stage.render(input) {
+                Ok(Some(text)) if !text.trim().is_empty() => text,
+                Ok(_) => continue,
+                Err(e) => {
+                    eprintln!("Warning: Prompt stage '{}' failed: {}", stage.name(), e);
+                    continue;
+                }
+            };
+
+            let (text, truncated) = match budget {
+                Some(budget) => truncate_to_budget(&text, *budget, stage.keep_tail()),
+                None => (text, false),
+            };
+            trace.push(StageTrace {
+                stage: stage.name().to_string(),
+                tokens: estimate_tokens(&text),
+                budget: *budget,
+                truncated,
+            });
+            sections.entry(stage.section()).or_default().push(text);
+        }
+
+        let history = match self.history {
+            Some(budget) => {
+                let (turns, truncated) = conversation::recent(budget);
+                if !turns.is_empty() {
+                    trace.push(StageTrace {
+                        stage: "history".to_string(),
+                        tokens: turns.iter().map(Turn::tokens).sum(),
+                        budget,
+                        truncated,
+                    });
+                }
+                turns
+            }
+            None => Vec::new(),
+        };
+        
+        let mut join = |section| sections.remove(&section).unwrap_or_default().join("\n\n");
+        let prompt = AssembledPrompt {
+            system: join(PromptSection::System),
+            context: join(PromptSection::Context),
+            user: join(PromptSection::User),
+            history,
+            trace,
+        };
+        if self.trace {
+            eprintln!("{}", prompt.trace_report());
+        }
+        prompt
+    }
+}
+
+impl Default for PromptPipeline {
+    fn default() -> Self {
+        Self::from_config(&PipelineConfig::default())
+    }
+}
+
+fn builtin_stage(name: &str) -> Option<Box<dyn PromptStage>> {
+    match name {
+        "system" => Some(Box::new(SystemStage)),
+        "project_notes" => Some(Box::new(ProjectNotesStage { path: PathBuf::from(PROJECT_NOTES_FILE) })),
+        "memories" => Some(Box::new(MemoriesStage { db_path: PathBuf::from(DATABASE_PATH), limit: 5 })),
+        "context_files" => Some(Box::new(ContextFilesStage)),
+        "repo_map" => Some(Box::new(RepoMapStage { root: PathBuf::from(".") })),
+        "todos" => Some(Box::new(TodosStage { path: PathBuf::from(TODO_FILE) })),
+        "weather" => Some(Box::new(WeatherStage)),
+        "user_message" => Some(Box::new(UserMessageStage)),
+        _ => None,
+    }
+}
+
+// Keep roughly `budget` tokens, cutting on a line boundary where possible
+fn truncate_to_budget(text: &str, budget: u64, keep_tail: bool) -> (String, bool) {
+    if estimate_tokens(text) <= budget {
+        return (text.to_string(), false);
+    }
+
+    let max_bytes = (budget as usize).saturating_mul(4);
+    let marker = format!("[... truncated to {} tokens]", budget);
+    let kept = if keep_tail {
+        let mut start = text.len().saturating_sub(max_bytes);
+        while !text.is_char_boundary(start) {
+            start += 1;
+        }
+        let tail = &text[start..];
+        let tail = tail.find('\n').map(|i| &tail[i + 1..]).unwrap_or(tail);
+        format!("{}\n{}", marker, tail)
+    } else {
+        let mut end = max_bytes.min(text.len());
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        let head = &text[..end];
+        let head = head.rfind('\n').map(|i| &head[..i]).unwrap_or(head);
+        format!("{}\n{}", head, marker)
+    };
+    (kept, true)
+}
+
+struct SystemStage;
+impl PromptStage for SystemStage {
+    fn name(&self) -> &str { "system" }
+    fn section(&self) -> PromptSection { PromptSection::System }
+    fn render(&self, input: &PromptInput) -> Result<Option<String>> {
+        Ok(Some(input.system_instructions.to_string()))
+    }
+}
+
+/// Project-specific notes and conventions checked into the repository
+struct ProjectNotesStage {
+    path: PathBuf,
+}
+impl PromptStage for ProjectNotesStage {
+    fn name(&self) -> &str { "project_notes" }
+    fn render(&self, _input: &PromptInput) -> Result<Option<String>> {
+        if !self.path.exists() {
+            return Ok(None);
+        }
+        let notes = fs::read_to_string(&self.path)?;
+        Ok(Some(format!("Project notes ({}):\n{}", self.path.display(), notes.trim_end())))
+    }
+}
+
+/// Stored learnings that share words with the user's message
+struct MemoriesStage {
+    db_path: PathBuf,
+    limit: usize,
+}
+impl PromptStage for MemoriesStage {
+    fn name(&self) -> &str { "memories" }
+    fn render(&self, input: &PromptInput) -> Result<Option<String>> {
+        let Some(query) = history::fts_any_query(input.user_message) else {
+            return Ok(None);
+        };
+        let memories: Vec<String> = Database::open_at(&self.db_path)?
+            .search_history(&format!("kind:memory AND ({})", query), self.limit)?
+            .into_iter()
+            .map(|hit| format!("- {}", hit.snippet.replace('\n', " ")))
+            .collect();
+
+        if memories.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(format!("Relevant memories:\n{}", memories.join("\n"))))
+    }
+}
+
+struct ContextFilesStage;
+impl PromptStage for ContextFilesStage {
+    fn name(&self) -> &str { "context_files" }
+    fn render(&self, input: &PromptInput) -> Result<Option<String>> {
+        Ok(Some(input.context_files.to_string()))
+    }
+}
+
+/// Signatures from the project's files that aren't in context, so the model knows what
+/// exists without reading every file
+struct RepoMapStage {
+    root: PathBuf,
+}
+impl PromptStage for RepoMapStage {
+    fn name(&self) -> &str { "repo_map" }
+    fn render(&self, input: &PromptInput) -> Result<Option<String>> {
+        let map = repo_map::build(&self.root, &router::context_files(input.context_files), input.user_message);
+        if map.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(format!("Repository map (signatures from files not in context):\n{}", map.trim_end())))
+    }
+}
+
+/// The task list from the model's last ```todo block, with what's been checked off since
+struct TodosStage {
+    path: PathBuf,
+}
+impl PromptStage for TodosStage {
+    fn name(&self) -> &str { "todos" }
+    fn render(&self, _input: &PromptInput) -> Result<Option<String>> {
+        let list = TodoList::load_from(&self.path);
+        if list.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(format!("Current todo list ({} open):\n{}", list.remaining(), list.to_block())))
+    }
+}
+
+/// The last weather fetched for `[weather]`; never waits on the network
+struct WeatherStage;
+impl PromptStage for WeatherStage {
+    fn name(&self) -> &str { "weather" }
+    fn render(&self, _input: &PromptInput) -> Result<Option<String>> {
+        let config = PromptsConfig::load().unwrap_or_default().weather;
+        if !config.enabled {
+            return Ok(None);
+        }
+        dynamic_prompts::refresh_weather_in_background();
+        Ok(dynamic_prompts::cached_weather().map(|weather| format!("Current weather: {}", weather.describe(config.cache_minutes))))
+    }
+}
+
+struct UserMessageStage;
+impl PromptStage for UserMessageStage {
+    fn name(&self) -> &str { "user_message" }
+    fn section(&self) -> PromptSection { PromptSection::User }
+    fn render(&self, input: &PromptInput) -> Result<Option<String>> {
+        Ok(Some(input.user_message.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    struct FixedStage(&'static str, String, bool);
+    impl PromptStage for FixedStage {
+        fn name(&self) -> &str { self.0 }
+        fn keep_tail(&self) -> bool { self.2 }
+        fn render(&self, _input: &PromptInput) -> Result<Option<String>> {
+            Ok(Some(self.1.clone()))
+        }
+    }
+
+    fn input() -> PromptInput<'static> {
+        PromptInput { system_instructions: "Be helpful.", context_files: "--- File: a.rs ---", user_message: "Fix the bug" }
+    }
+
+    #[test]
+    fn test_stages_run_in_order_into_sections() {
+        let pipeline = PromptPipeline::new()
+            .stage(Box::new(SystemStage), None)
+            .stage(Box::new(FixedStage("notes", "Use tabs.".to_string(), false)), None)
+            .stage(Box::new(ContextFilesStage), None)
+            .stage(Box::new(FixedStage("empty", "  ".to_string(), false)), None)
+            .stage(Box::new(UserMessageStage), None);
+
+        let prompt = pipeline.assemble(&input());
+        assert_eq!(prompt.system, "Be helpful.");
+        assert_eq!(prompt.context, "Use tabs.\n\n--- File: a.rs ---");
+        assert_eq!(prompt.user, "Fix the bug");
+
+        // Stages with nothing to add are left out of the trace
+        let stages: Vec<&str> = prompt.trace.iter().map(|t| t.stage.as_str()).collect();
+        assert_eq!(stages, ["system", "notes", "context_files", "user_message"]);
+    }
+
+    #[test]
+    fn test_budgets_truncate_head_or_tail() {
+        let lines: String = (1..=100).map(|i| format!("line {}\n", i)).collect();
+        let pipeline = PromptPipeline::new()
+            .stage(Box::new(FixedStage("docs", lines.clone(), false)), Some(20))
+            .stage(Box::new(FixedStage("history", lines, true)), Some(20));
+
+        let prompt = pipeline.assemble(&input());
+        let (docs, history) = prompt.context.split_once("\n\n").unwrap();
+        assert!(docs.starts_with("line 1\n") && docs.ends_with("[... truncated to 20 tokens]"));
+        assert!(history.starts_with("[... truncated to 20 tokens]") && history.contains("line 100"));
+        assert!(prompt.trace.iter().all(|t| t.truncated && t.tokens <= 30));
+        assert!(prompt.trace_report().contains("docs"));
+    }
+
+    #[test]
+    fn test_project_notes_and_unknown_stages() {
+        let dir = TempDir::new().unwrap();
+        let notes = dir.path().join(PROJECT_NOTES_FILE);
+        std::fs::write(&notes, "Run cargo fmt before committing.\n").unwrap();
+
+        let prompt = PromptPipeline::new()
+            .stage(Box::new(ProjectNotesStage { path: notes }), None)
+            .stage(Box::new(ProjectNotesStage { path: dir.path().join("missing.md") }), None)
+            .assemble(&input());
+        assert!(prompt.context.contains("Run cargo fmt before committing."));
+        assert_eq!(prompt.trace.len(), 1);
+
+        let config = PipelineConfig { stages: vec!["system".to_string(), "bogus".to_string()], ..PipelineConfig::default() };
+        assert_eq!(PromptPipeline::from_config(&config).stages.len(), 1);
+
+        // Earlier turns go out as messages, not as a text stage
+        let pipeline = PromptPipeline::default();
+        assert_eq!(pipeline.history, Some(Some(4000)));
+        assert!(pipeline.stages.iter().all(|(stage, _)| stage.name() != "history"));
+    }
+}
@@ -1,7 +1,13 @@
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 use anyhow::{Result, Context};
 
+use crate::sandbox::SandboxProfile;
+use crate::llm::SamplingParams;
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct SystemConfig {
     pub instructions: String,
@@ -25,12 +31,990 @@ pub struct CommandsConfig {
     pub execution_reminder: String,
 }
 
+/// On-disk cache of LLM responses, keyed by provider, model, prompt and context
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct CacheConfig {
+    #[serde(default = "default_cache_enabled")]
+    pub enabled: bool,
+    /// Entries older than this are ignored and overwritten
+    #[serde(default = "default_cache_ttl_hours")]
+    pub ttl_hours: u64,
+}
+
+fn default_cache_enabled() -> bool {
+    true
+}
+
+fn default_cache_ttl_hours() -> u64 {
+    24
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_cache_enabled(),
+            ttl_hours: default_cache_ttl_hours(),
+        }
+    }
+}
+
+/// Retries for failed LLM requests, with exponential backoff between attempts
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RetryConfig {
+    /// Retries after the first failure; 0 turns retrying off
+    #[serde(default = "default_retry_attempts")]
+    pub attempts: u32,
+    #[serde(default = "default_retry_base_delay_ms")]
+    pub base_delay_ms: u64,
+    #[serde(default = "default_retry_max_delay_ms")]
+    pub max_delay_ms: u64,
+}
+
+fn default_retry_attempts() -> u32 {
+    3
+}
+
+fn default_retry_base_delay_ms() -> u64 {
+    1000
+}
+
+fn default_retry_max_delay_ms() -> u64 {
+    16_000
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            attempts: default_retry_attempts(),
+            base_delay_ms: default_retry_base_delay_ms(),
+            max_delay_ms: default_retry_max_delay_ms(),
+        }
+    }
+}
+
+/// Requests per minute per provider, shared by the UI and every agent; requests over the
+/// limit wait their turn instead of being sent
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RateLimitConfig {
+    /// Limit for providers not listed in `providers`; 0 turns limiting off
+    #[serde(default = "default_requests_per_minute")]
+    pub requests_per_minute: u32,
+    /// Requests that can go out back to back before pacing starts
+    #[serde(default = "default_rate_limit_burst")]
+    pub burst: u32,
+    /// Per-provider limits by lowercase provider name, e.g. "gemini" or "groq"
+    #[serde(default = "default_provider_rate_limits")]
+    pub providers: HashMap<String, u32>,
+}
+
+fn default_requests_per_minute() -> u32 {
+    50
+}
+
+fn default_rate_limit_burst() -> u32 {
+    5
+}
+
+fn default_provider_rate_limits() -> HashMap<String, u32> {
+    // Local models have no limit to trip
+    HashMap::from([("ollama".to_string(), 0)])
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            requests_per_minute: default_requests_per_minute(),
+            burst: default_rate_limit_burst(),
+            providers: default_provider_rate_limits(),
+        }
+    }
+}
+
+/// Order of the prompt assembly stages, with optional per-stage token budgets
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct PipelineConfig {
+    #[serde(default = "default_pipeline_stages")]
+    pub stages: Vec<String>,
+    /// Estimated tokens per stage; stages without a budget are never truncated
+    #[serde(default = "default_stage_budgets")]
+    pub budgets: HashMap<String, u64>,
+    /// Print each stage's token count to stderr when a prompt is assembled
+    #[serde(default)]
+    pub trace: bool,
+}
+
+fn default_pipeline_stages() -> Vec<String> {
+    ["system", "project_notes", "memories", "repo_map", "context_files", "todos", "history", "user_message"]
+        .iter()
+        .map(|stage| stage.to_string())
+        .collect()
+}
+
+fn default_stage_budgets() -> HashMap<String, u64> {
+    HashMap::from([
+        ("project_notes".to_string(), 2000),
+        ("memories".to_string(), 1000),
+        ("repo_map".to_string(), 1500),
+        ("history".to_string(), 4000),
+    ])
+}
+
+impl Default for PipelineConfig {
+    fn default() -> Self {
+        Self {
+            stages: default_pipeline_stages(),
+            budgets: default_stage_budgets(),
+            trace: false,
+        }
+    }
+}
+
+/// Models that `/compare` sends a prompt to, as `provider` or `provider:model`
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct CompareConfig {
+    #[serde(default = "default_compare_models")]
+    pub models: Vec<String>,
+}
+
+fn default_compare_models() -> Vec<String> {
+    vec!["anthropic".to_string(), "gemini".to_string()]
+}
+
+impl Default for CompareConfig {
+    fn default() -> Self {
+        Self { models: default_compare_models() }
+    }
+}
+
+/// Scanning of prompts for credentials before they're sent to a cloud provider
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct SecretsConfig {
+    #[serde(default = "default_secrets_enabled")]
+    pub enabled: bool,
+    /// Strings that are never flagged, e.g. test fixtures or public keys
+    #[serde(default)]
+    pub allow: Vec<String>,
+}
+
+fn default_secrets_enabled() -> bool {
+    true
+}
+
+impl Default for SecretsConfig {
+    fn default() -> Self {
+        Self { enabled: default_secrets_enabled(), allow: Vec::new() }
+    }
+}
+
+/// How requests to a self-hosted endpoint authenticate
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum AuthScheme {
+    /// Key in an `api-key` header, as Azure OpenAI expects
+    ApiKey,
+    /// `Authorization: Bearer` token, e.g. an Entra ID token or a Bedrock API key
+    Bearer,
+    /// Nothing added, for gateways that authenticate requests themselves
+    None,
+}
+
+/// Azure OpenAI deployment, selected with /provider azure
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct AzureConfig {
+    /// e.g. https://my-resource.openai.azure.com
+    #[serde(default)]
+    pub endpoint: String,
+    /// Deployment used after /provider azure; /model picks another
+    #[serde(default)]
+    pub deployment: Option<String>,
+    #[serde(default = "default_azure_api_version")]
+    pub api_version: String,
+    #[serde(default = "default_azure_auth")]
+    pub auth: AuthScheme,
+    /// Environment variable holding the key or token
+    #[serde(default = "default_azure_key_env")]
+    pub api_key_env: String,
+    #[serde(flatten)]
+    pub connection: ConnectionConfig,
+}
+
+fn default_azure_api_version() -> String {
+    "2024-10-21".to_string()
+}
+
+fn default_azure_auth() -> AuthScheme {
+    AuthScheme::ApiKey
+}
+
+fn default_azure_key_env() -> String {
+    "AZURE_OPENAI_API_KEY".to_string()
+}
+
+impl Default for AzureConfig {
+    fn default() -> Self {
+        Self {
+            endpoint: String::new(),
+            deployment: None,
+            api_version: default_azure_api_version(),
+            auth: default_azure_auth(),
+            api_key_env: default_azure_key_env(),
+            connection: ConnectionConfig::default(),
+        }
+    }
+}
+
+/// Claude on AWS Bedrock, selected with /provider bedrock
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct BedrockConfig {
+    #[serde(default = "default_bedrock_region")]
+    pub region: String,
+    /// Replaces https://bedrock-runtime.<region>.amazonaws.com, e.g. for a VPC endpoint or gateway
+    #[serde(default)]
+    pub base_url: Option<String>,
+    /// Model or inference profile id used after /provider bedrock; /model picks another
+    #[serde(default)]
+    pub model: Option<String>,
+    #[serde(default = "default_bedrock_auth")]
+    pub auth: AuthScheme,
+    #[serde(default = "default_bedrock_key_env")]
+    pub api_key_env: String,
+    #[serde(flatten)]
+    pub connection: ConnectionConfig,
+}
+
+fn default_bedrock_region() -> String {
+    "us-east-1".to_string()
+}
+
+fn default_bedrock_auth() -> AuthScheme {
+    AuthScheme::Bearer
+}
+
+fn default_bedrock_key_env() -> String {
+    "AWS_BEARER_TOKEN_BEDROCK".to_string()
+}
+
+impl Default for BedrockConfig {
+    fn default() -> Self {
+        Self {
+            region: default_bedrock_region(),
+            base_url: None,
+            model: None,
+            auth: default_bedrock_auth(),
+            api_key_env: default_bedrock_key_env(),
+            connection: ConnectionConfig::default(),
+        }
+    }
+}
+
+/// How requests to a provider reach it: through a proxy, with extra headers, or trusting a
+/// corporate CA
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
+pub struct ConnectionConfig {
+    /// e.g. "http://proxy.corp.example:8080"; "none" ignores HTTPS_PROXY and the like
+    #[serde(default)]
+    pub proxy: Option<String>,
+    /// Sent with every request, e.g. a gateway's routing or tenant header
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    /// PEM file with an extra root certificate to trust
+    #[serde(default)]
+    pub ca_cert: Option<String>,
+    /// Skip certificate checks entirely; only for local servers with self-signed certificates
+    #[serde(default)]
+    pub accept_invalid_certs: bool,
+}
+
+/// A provider's endpoint: `base_url` replaces the start of its default URLs, e.g.
+/// https://api.anthropic.com, so requests go to a gateway or a compatible server instead
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
+pub struct EndpointConfig {
+    #[serde(default)]
+    pub base_url: Option<String>,
+    #[serde(flatten)]
+    pub connection: ConnectionConfig,
+}
+
+/// Endpoints for enterprise-hosted providers, and where quick requests go
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct ProvidersConfig {
+    #[serde(default)]
+    pub azure: AzureConfig,
+    #[serde(default)]
+    pub bedrock: BedrockConfig,
+    #[serde(default)]
+    pub anthropic: EndpointConfig,
+    /// Any OpenAI-compatible server, like LM Studio or vLLM, can stand in for OpenRouter
+    #[serde(default)]
+    pub openrouter: EndpointConfig,
+    #[serde(default)]
+    pub ollama: EndpointConfig,
+    #[serde(default)]
+    pub groq: EndpointConfig,
+    #[serde(default)]
+    pub together: EndpointConfig,
+    /// `provider` or `provider:model` for quick internal calls and commit messages,
+    /// e.g. "groq"; unset keeps them on the current provider's fast model
+    #[serde(default)]
+    pub quick: Option<String>,
+    /// `provider` or `provider:model` that `/verify on` checks drafted edits with
+    #[serde(default)]
+    pub verify: Option<String>,
+}
+
+/// What happens to requests once a provider's spend budget is used up
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum BudgetAction {
+    /// Send them to Ollama instead
+    #[default]
+    Local,
+    /// Use the provider's fast, cheaper model
+    Cheaper,
+    /// Refuse them until `/budget override`
+    Stop,
+}
+
+/// Logging of every request and raw response to `~/.kota/transcripts/<session>.jsonl`
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct TranscriptConfig {
+    #[serde(default)]
+    pub enabled: bool,
+}
+
+/// Limits on what's kept in context between requests
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ContextConfig {
+    /// Estimated tokens across all context items; past it the least recently used unpinned
+    /// items are evicted. Unset keeps everything.
+    #[serde(default)]
+    pub max_context_tokens: Option<u64>,
+    /// What to do with the local files a file added with `/add_file` imports
+    #[serde(default)]
+    pub related_files: RelatedFiles,
+    /// Files bigger than this are refused, or added as their start and end with --truncate
+    #[serde(default = "default_max_file_kb")]
+    pub max_file_kb: u64,
+}
+
+fn default_max_file_kb() -> u64 {
+    256
+}
+
+impl Default for ContextConfig {
+    fn default() -> Self {
+        Self { max_context_tokens: None, related_files: RelatedFiles::default(), max_file_kb: default_max_file_kb() }
+    }
+}
+
+/// `off` ignores imports, `suggest` lists the referenced files that aren't in context yet,
+/// `add` adds them too
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum RelatedFiles {
+    Off,
+    #[default]
+    Suggest,
+    Add,
+}
+
+/// How long replies should be
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Verbosity {
+    Terse,
+    Normal,
+    Verbose,
+}
+
+/// Response preferences added to the system prompt. `/style` overrides them in
+/// `~/.kota/style.json`; unset fields leave it to the model.
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
+pub struct StyleConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verbosity: Option<Verbosity>,
+    /// Language to reply in, e.g. "French"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    /// false asks for replies without emoji
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub emoji: Option<bool>,
+    /// How code the model writes should be commented, e.g. "doc comments on public items only"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comments: Option<String>,
+}
+
+/// The model that explains suggested commands before they're approved
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct ExplainConfig {
+    /// provider or provider:model; unset uses the current model
+    #[serde(default)]
+    pub model: Option<String>,
+}
+
+/// Parse checks run on edited files before they're committed
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ValidationConfig {
+    #[serde(default = "default_validation_enabled")]
+    pub enabled: bool,
+}
+
+fn default_validation_enabled() -> bool {
+    true
+}
+
+impl Default for ValidationConfig {
+    fn default() -> Self {
+        Self { enabled: default_validation_enabled() }
+    }
+}
+
+/// Encryption of the text KOTA keeps in `.kota/kota.db`: conversations, learnings, command
+/// output, agent sessions, transcripts and the audit log, with ChaCha20-Poly1305 and the
+/// key in KOTA_STORAGE_KEY or the OS keychain.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct StorageConfig {
+    #[serde(default)]
+    pub encrypt: bool,
+}
+
+/// URLs notified when edits are applied, tests fail, agents finish or a budget is reached
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct WebhooksConfig {
+    #[serde(default)]
+    pub hooks: Vec<Webhook>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Webhook {
+    pub url: String,
+    /// Event names to send, like "edit_applied"; empty sends all of them
+    #[serde(default)]
+    pub events: Vec<String>,
+    #[serde(default)]
+    pub format: WebhookFormat,
+    /// Extra request headers, e.g. Authorization
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+}
+
+/// An IMAP mailbox `kota email poll` turns tagged messages from allowed senders into tasks
+/// and memory entries. The password is read from the `password_env` environment variable.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct EmailConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Like imaps://imap.example.com/INBOX
+    #[serde(default)]
+    pub url: String,
+    #[serde(default)]
+    pub username: String,
+    #[serde(default = "default_email_password_env")]
+    pub password_env: String,
+    /// Addresses, or whole domains as "@example.com"; empty ignores every message
+    #[serde(default)]
+    pub allowed_senders: Vec<String>,
+    /// Only subjects containing this are read
+    #[serde(default = "default_email_tag")]
+    pub tag: String,
+    /// How often `kota email watch` checks the mailbox
+    #[serde(default = "default_email_poll_minutes")]
+    pub poll_minutes: u64,
+    /// Holds a secret that, in the subject, vouches for a sender whose mail server doesn't
+    /// pass DKIM, SPF or DMARC
+    #[serde(default = "default_email_secret_env")]
+    pub secret_env: String,
+}
+
+fn default_email_secret_env() -> String {
+    "KOTA_EMAIL_SECRET".to_string()
+}
+
+fn default_email_password_env() -> String {
+    "KOTA_EMAIL_PASSWORD".to_string()
+}
+
+fn default_email_tag() -> String {
+    "[kota]".to_string()
+}
+
+fn default_email_poll_minutes() -> u64 {
+    5
+}
+
+impl Default for EmailConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            url: String::new(),
+            username: String::new(),
+            password_env: default_email_password_env(),
+            allowed_senders: Vec::new(),
+            tag: default_email_tag(),
+            poll_minutes: default_email_poll_minutes(),
+            secret_env: default_email_secret_env(),
+        }
+    }
+}
+
+/// Current conditions from Open-Meteo (no API key) for the TUI, morning briefings and the
+/// `weather` prompt stage. `location` is looked up by name unless latitude and longitude are set.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct WeatherConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default)]
+    pub location: Option<String>,
+    #[serde(default)]
+    pub latitude: Option<f64>,
+    #[serde(default)]
+    pub longitude: Option<f64>,
+    #[serde(default)]
+    pub units: TemperatureUnit,
+    /// How long fetched conditions are used before asking again
+    #[serde(default = "default_weather_cache_minutes")]
+    pub cache_minutes: u64,
+}
+
+fn default_weather_cache_minutes() -> u64 {
+    30
+}
+
+impl Default for WeatherConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            location: None,
+            latitude: None,
+            longitude: None,
+            units: TemperatureUnit::default(),
+            cache_minutes: default_weather_cache_minutes(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TemperatureUnit {
+    #[default]
+    Celsius,
+    Fahrenheit,
+}
+
+/// How text too long for one request is summarized: split into chunks summarized in
+/// parallel, with the summaries merged in groups until one is left
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct SummarizeConfig {
+    /// Estimated tokens per chunk, and per group of summaries merged in one request
+    #[serde(default = "default_chunk_tokens")]
+    pub chunk_tokens: u64,
+    /// Requests in flight at once
+    #[serde(default = "default_summarize_concurrency")]
+    pub concurrency: usize,
+}
+
+fn default_chunk_tokens() -> u64 {
+    24_000
+}
+
+fn default_summarize_concurrency() -> usize {
+    4
+}
+
+/// Formatters and checks run on the files a response's edits touched, before they're
+/// committed; what the checks report is added to context for the next turn. Commands are
+/// keyed by file extension, and `{files}` in one is replaced with the touched files.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct LintConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_lint_step")]
+    pub format: bool,
+    #[serde(default = "default_lint_step")]
+    pub check: bool,
+    /// Replace the detected formatter for an extension; "" turns it off
+    #[serde(default)]
+    pub formatters: BTreeMap<String, String>,
+    /// Replace the detected check for an extension; "" turns it off
+    #[serde(default)]
+    pub checkers: BTreeMap<String, String>,
+}
+
+fn default_lint_step() -> bool {
+    true
+}
+
+impl Default for LintConfig {
+    fn default() -> Self {
+        Self { enabled: false, format: default_lint_step(), check: default_lint_step(), formatters: BTreeMap::new(), checkers: BTreeMap::new() }
+    }
+}
+
+impl Default for SummarizeConfig {
+    fn default() -> Self {
+        Self { chunk_tokens: default_chunk_tokens(), concurrency: default_summarize_concurrency() }
+    }
+}
+
+/// `/focus` blocks: webhooks and suggestions are held back until the block ends
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct FocusConfig {
+    /// Used when `/focus` is given no length, like "50m" or "1h30m"
+    #[serde(default = "default_focus_length")]
+    pub default_length: String,
+    /// ERROR and REQUEST notifications allowed through per block
+    #[serde(default = "default_distraction_budget")]
+    pub distraction_budget: u32,
+}
+
+fn default_focus_length() -> String {
+    "50m".to_string()
+}
+
+fn default_distraction_budget() -> u32 {
+    1
+}
+
+impl Default for FocusConfig {
+    fn default() -> Self {
+        Self { default_length: default_focus_length(), distraction_budget: default_distraction_budget() }
+    }
+}
+
+/// What an output filter does with a response that trips it: `block` drops the edit, `warn`
+/// keeps it with a warning, `off` skips the check
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum FilterAction {
+    Off,
+    #[default]
+    Warn,
+    Block,
+}
+
+/// Checks run on every response before its edits are reviewed or applied
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct FiltersConfig {
+    /// Edits that write one of `placeholder_markers`
+    #[serde(default = "default_block")]
+    pub placeholders: FilterAction,
+    /// Matched without regard to case
+    #[serde(default = "default_placeholder_markers")]
+    pub placeholder_markers: Vec<String>,
+    /// Large edits and code blocks with a license or copyright notice
+    #[serde(default)]
+    pub licensed_code: FilterAction,
+    /// How many lines a block needs before its notice is flagged
+    #[serde(default = "default_licensed_code_lines")]
+    pub licensed_code_lines: usize,
+    /// Edits, reads and backticked paths for files that aren't in the repository
+    #[serde(default)]
+    pub unknown_paths: FilterAction,
+}
+
+fn default_block() -> FilterAction {
+    FilterAction::Block
+}
+
+fn default_placeholder_markers() -> Vec<String> {
+    ["YOUR_API_KEY", "YOUR_API_TOKEN", "YOUR_SECRET", "YOUR_PASSWORD", "REPLACE_ME", "<your-"]
+        .iter()
+        .map(|marker| marker.to_string())
+        .collect()
+}
+
+fn default_licensed_code_lines() -> usize {
+    20
+}
+
+impl Default for FiltersConfig {
+    fn default() -> Self {
+        Self {
+            placeholders: default_block(),
+            placeholder_markers: default_placeholder_markers(),
+            licensed_code: FilterAction::default(),
+            licensed_code_lines: default_licensed_code_lines(),
+            unknown_paths: FilterAction::default(),
+        }
+    }
+}
+
+/// `json` posts the full event, `text` just its one-line summary (for ntfy and the like)
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum WebhookFormat {
+    #[default]
+    Json,
+    Text,
+}
+
+/// Where `kota update` looks for releases and the key their signatures are checked against
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct UpdateConfig {
+    /// GitHub "owner/name"
+    #[serde(default = "default_update_repo")]
+    pub repo: String,
+    /// minisign public key the release binaries are signed with
+    #[serde(default)]
+    pub public_key: Option<String>,
+}
+
+fn default_update_repo() -> String {
+    "jayminwest/kota-rust-cli".to_string()
+}
+
+impl Default for UpdateConfig {
+    fn default() -> Self {
+        Self { repo: default_update_repo(), public_key: None }
+    }
+}
+
+/// How the model is asked to write file edits
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum EditFormat {
+    /// `<<<<<<< SEARCH` / `>>>>>>> REPLACE` blocks
+    #[default]
+    SearchReplace,
+    /// Unified diffs, which some models write more reliably
+    Udiff,
+}
+
+/// How the CLI shows each edit while asking whether to apply it
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum ReviewLayout {
+    /// Removed and added lines one under the other, like `git diff`
+    #[default]
+    Unified,
+    /// The old text on the left and the new on the right
+    SideBySide,
+}
+
+/// The edit format, overall and for particular models, and how edits are shown for review
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct EditsConfig {
+    #[serde(default)]
+    pub format: EditFormat,
+    /// Formats for particular models, by a name or part of one matched against
+    /// "provider:model", e.g. "gpt-4.1" = "udiff"; the longest match wins
+    #[serde(default)]
+    pub models: BTreeMap<String, EditFormat>,
+    /// Added to the system prompt when the model is to write unified diffs
+    #[serde(default = "default_udiff_instructions")]
+    pub udiff_instructions: String,
+    #[serde(default)]
+    pub review_layout: ReviewLayout,
+    /// A response's edits apply all or nothing: when one fails, those applied before it are undone
+    #[serde(default = "default_transactional")]
+    pub transactional: bool,
+    /// Times a block whose SEARCH text isn't in the file is asked for again, with the file's
+    /// current lines; 0 fails it straight away
+    #[serde(default = "default_match_retries")]
+    pub match_retries: u32,
+}
+
+fn default_match_retries() -> u32 {
+    2
+}
+
+fn default_transactional() -> bool {
+    true
+}
+
+fn default_udiff_instructions() -> String {
+    r#"## Edit Format: Unified Diffs
+Write every file edit as a unified diff in a ```diff block instead of a search/replace block:
+```diff
+--- a/src/main.rs
++++ b/src/main.rs
+@@ -1,3 +1,3 @@
+ fn main() {
+-    println!("Hello, world!");
++    println!("Hello, KOTA!");
+ }
+```
+Include at least two unchanged lines of context around each change, copied exactly from the file, and start removed lines with `-`, added lines with `+` and context lines with a space. Use one `---`/`+++` header per file and one `@@` hunk per separate change."#.to_string()
+}
+
+impl Default for EditsConfig {
+    fn default() -> Self {
+        Self {
+            format: EditFormat::default(),
+            models: BTreeMap::new(),
+            udiff_instructions: default_udiff_instructions(),
+            review_layout: ReviewLayout::default(),
+            transactional: default_transactional(),
+            match_retries: default_match_retries(),
+        }
+    }
+}
+
+impl EditsConfig {
+    /// The format for `model` on `provider`
+    pub fn format_for(&self, provider: &str, model: &str) -> EditFormat {
+        let name = format!("{}:{}", provider, model).to_lowercase();
+        self.models
+            .iter()
+            .filter(|(pattern, _)| !pattern.trim().is_empty() && name.contains(&pattern.trim().to_lowercase()))
+            .max_by_key(|(pattern, _)| pattern.trim().len())
+            .map_or(self.format, |(_, format)| *format)
+    }
+}
+
+/// Settings for how requests reach the models
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct LlmConfig {
+    #[serde(default)]
+    pub routing: RoutingConfig,
+}
+
+/// `provider` or `provider:model` per kind of request; unset kinds use the session's model
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct RoutingConfig {
+    /// Questions about the code or anything else, answered without edits
+    #[serde(default)]
+    pub question: Option<String>,
+    /// Requests to change files
+    #[serde(default)]
+    pub edit: Option<String>,
+    /// Requests to run something in the shell
+    #[serde(default)]
+    pub shell: Option<String>,
+}
+
+/// Timezone and formats for dates and times shown to the user; unset fields follow the system
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct TimeConfig {
+    /// "local", "UTC" or a fixed offset like "+05:30"
+    #[serde(default)]
+    pub timezone: Option<String>,
+    /// strftime formats; the defaults come from the locale in LC_TIME or LANG
+    #[serde(default)]
+    pub date_format: Option<String>,
+    #[serde(default)]
+    pub time_format: Option<String>,
+}
+
+/// Spend limits in USD per provider, for the calendar day and the week starting Monday
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct BudgetConfig {
+    #[serde(default)]
+    pub daily: HashMap<String, f64>,
+    #[serde(default)]
+    pub weekly: HashMap<String, f64>,
+    #[serde(default)]
+    pub action: BudgetAction,
+}
+
+/// Paths whose contents must never reach a cloud provider
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct LocalOnlyConfig {
+    /// Files or directories, absolute or relative to the workspace. A workspace inside
+    /// one of them is local-only as a whole.
+    #[serde(default)]
+    pub paths: Vec<String>,
+    /// Ollama model used instead of a cloud model; the usual Ollama default when unset
+    #[serde(default)]
+    pub model: Option<String>,
+}
+
+/// Embedding model for semantic search over memories and context
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct EmbeddingsConfig {
+    /// Off by default; searches fall back to substring matching
+    #[serde(default)]
+    pub enabled: bool,
+    /// openai, gemini or ollama
+    #[serde(default = "default_embeddings_provider")]
+    pub provider: String,
+    #[serde(default = "default_embeddings_model")]
+    pub model: String,
+}
+
+fn default_embeddings_provider() -> String {
+    "ollama".to_string()
+}
+
+fn default_embeddings_model() -> String {
+    "nomic-embed-text".to_string()
+}
+
+impl Default for EmbeddingsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            provider: default_embeddings_provider(),
+            model: default_embeddings_model(),
+        }
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct PromptsConfig {
     pub system: SystemConfig,
     pub commit_generation: CommitGenerationConfig,
     pub search_replace: SearchReplaceConfig,
     pub commands: CommandsConfig,
+    #[serde(default)]
+    pub cache: CacheConfig,
+    #[serde(default)]
+    pub retry: RetryConfig,
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+    #[serde(default)]
+    pub pipeline: PipelineConfig,
+    #[serde(default)]
+    pub sandbox: SandboxProfile,
+    #[serde(default)]
+    pub compare: CompareConfig,
+    #[serde(default)]
+    pub embeddings: EmbeddingsConfig,
+    #[serde(default)]
+    pub secrets: SecretsConfig,
+    /// Defaults for /temp, /top_p and /max_tokens
+    #[serde(default)]
+    pub sampling: SamplingParams,
+    #[serde(default)]
+    pub local_only: LocalOnlyConfig,
+    #[serde(default)]
+    pub budget: BudgetConfig,
+    #[serde(default)]
+    pub providers: ProvidersConfig,
+    #[serde(default)]
+    pub transcript: TranscriptConfig,
+    #[serde(default)]
+    pub time: TimeConfig,
+    #[serde(default)]
+    pub llm: LlmConfig,
+    #[serde(default)]
+    pub validation: ValidationConfig,
+    #[serde(default)]
+    pub update: UpdateConfig,
+    #[serde(default)]
+    pub explain: ExplainConfig,
+    #[serde(default)]
+    pub context: ContextConfig,
+    #[serde(default)]
+    pub style: StyleConfig,
+    #[serde(default)]
+    pub storage: StorageConfig,
+    #[serde(default)]
+    pub webhooks: WebhooksConfig,
+    #[serde(default)]
+    pub email: EmailConfig,
+    #[serde(default)]
+    pub weather: WeatherConfig,
+    #[serde(default)]
+    pub focus: FocusConfig,
+    #[serde(default)]
+    pub filters: FiltersConfig,
+    #[serde(default)]
+    pub edits: EditsConfig,
+    #[serde(default)]
+    pub summarize: SummarizeConfig,
+    #[serde(default)]
+    pub lint: LintConfig,
 }
 
 impl PromptsConfig {
@@ -53,8 +1037,22 @@ impl PromptsConfig {
         Ok(Self::default())
     }
     
-    pub fn get_system_instructions(&self) -> &str {
-        &self.system.instructions
+    /// The provider's own system prompt from `~/.kota/prompts/<provider>.md` if there is
+    /// one, otherwise `[system] instructions`, followed by the unified diff instructions when
+    /// `[edits]` picks that format for `model`, the project's KOTA.md and the `/style` and
+    /// `[style]` preferences
+    pub fn system_instructions_for(&self, provider: &str, model: &str) -> String {
+        let mut instructions = provider_system_prompt(provider).unwrap_or_else(|| self.system.instructions.clone());
+        if self.edits.format_for(provider, model) == EditFormat::Udiff {
+            instructions = format!("{}\n\n{}", instructions.trim_end(), self.edits.udiff_instructions.trim());
+        }
+        if let Some(project) = crate::instructions::system_section() {
+            instructions = format!("{}\n\n{}", instructions.trim_end(), project);
+        }
+        match crate::style::effective(&self.style).instructions() {
+            Some(style) => format!("{}\n\n{}", instructions.trim_end(), style),
+            None => instructions,
+        }
     }
     
     pub fn get_gemini_commit_prompt(&self, original_prompt: &str, git_diff: &str) -> String {
@@ -76,13 +1074,62 @@ impl PromptsConfig {
     }
 }
 
+/// Per-provider system prompts, relative to the home directory
+pub const PROVIDER_PROMPTS_DIR: &str = ".kota/prompts";
+const PROMPT_PROVIDERS: [&str; 8] = ["anthropic", "gemini", "ollama", "openrouter", "azure", "bedrock", "groq", "together"];
+
+// Read on first use and kept until /prompt reload, so edits don't apply halfway through a task
+static PROVIDER_PROMPTS: Mutex<Option<HashMap<String, String>>> = Mutex::new(None);
+
+pub fn provider_prompts_dir() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(PROVIDER_PROMPTS_DIR))
+}
+
+/// Non-empty `<provider>.md` files in `dir`, by provider name
+fn read_provider_prompts(dir: &Path) -> HashMap<String, String> {
+    PROMPT_PROVIDERS
+        .iter()
+        .filter_map(|provider| {
+            let path = dir.join(format!("{}.md", provider));
+            let content = match fs::read_to_string(&path) {
+                Ok(content) => content,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => return None,
+                Err(e) => {
+                    eprintln!("Warning: Failed to read {}: {}", path.display(), e);
+                    return None;
+                }
+            };
+            let content = content.trim();
+            (!content.is_empty()).then(|| (provider.to_string(), content.to_string()))
+        })
+        .collect()
+}
+
+fn load_provider_prompts() -> HashMap<String, String> {
+    provider_prompts_dir().map(|dir| read_provider_prompts(&dir)).unwrap_or_default()
+}
+
+pub fn provider_system_prompt(provider: &str) -> Option<String> {
+    let mut prompts = PROVIDER_PROMPTS.lock().unwrap_or_else(|e| e.into_inner());
+    prompts.get_or_insert_with(load_provider_prompts).get(provider).cloned()
+}
+
+/// Re-read `~/.kota/prompts`. Returns the providers that now have their own prompt.
+pub fn reload_provider_prompts() -> Vec<String> {
+    let loaded = load_provider_prompts();
+    let mut providers: Vec<String> = loaded.keys().cloned().collect();
+    providers.sort();
+    *PROVIDER_PROMPTS.lock().unwrap_or_else(|e| e.into_inner()) = Some(loaded);
+    providers
+}
+
 impl Default for PromptsConfig {
     fn default() -> Self {
         Self {
             system: SystemConfig {
                 instructions: r#"You are KOTA, a helpful coding assistant. You help users with coding tasks by providing search/replace blocks for file edits and command blocks for terminal commands.
 
-When the user asks you to edit files or run commands, you can use two special formats:
+When the user asks you to edit files or run commands, you can use these special formats:
 
 ## Search/Replace Block Format
 ```
@@ -94,11 +1141,32 @@ new content to replace it with
 >>>>>>> REPLACE
 ```
 
+## Creating, Deleting and Renaming Files
+To create a file, use a Search/Replace block with an empty SEARCH section; missing directories are created. To delete or rename a file in the context, put one of these under its path:
+```
+src/old_module.rs
+<<<<<<< DELETE
+>>>>>>> DELETE
+
+src/utils.rs
+<<<<<<< RENAME
+src/utils/mod.rs
+>>>>>>> RENAME
+```
+Edits are applied before deletes and renames, so edit files at their current paths. Renames never replace an existing file.
+
 ## Command Block Format
 ```bash
 command to run
 ```
 
+## Todo Block Format
+For multi-step work, keep a task list. Send the whole updated list each time it changes. Tag a step with the file it edits or the command that finishes it, and it is checked off when that edit is applied or that command succeeds:
+```todo
+- [ ] Add the parser (edit: src/parser.rs)
+- [ ] Run the tests (run: cargo test)
+```
+
 ## Rules:
 1. For file edits: Only edit files that are in the user's context. Use EXACT text in SEARCH blocks.
 2. For commands: Suggest commands that help accomplish the user's goals. They will be executed with user confirmation.
@@ -206,6 +1274,62 @@ The SEARCH content must match the file EXACTLY (including whitespace)."#.to_stri
                 safety_note: "Commands will be presented to the user for confirmation before execution. Suggest helpful commands that accomplish the user's goals.".to_string(),
                 execution_reminder: "Remember: Commands are executed with user confirmation and their output is added to the conversation context for follow-up actions.".to_string(),
             },
+            cache: CacheConfig::default(),
+            retry: RetryConfig::default(),
+            rate_limit: RateLimitConfig::default(),
+            pipeline: PipelineConfig::default(),
+            sandbox: SandboxProfile::default(),
+            compare: CompareConfig::default(),
+            embeddings: EmbeddingsConfig::default(),
+            secrets: SecretsConfig::default(),
+            sampling: SamplingParams::default(),
+            local_only: LocalOnlyConfig::default(),
+            budget: BudgetConfig::default(),
+            providers: ProvidersConfig::default(),
+            transcript: TranscriptConfig::default(),
+            time: TimeConfig::default(),
+            llm: LlmConfig::default(),
+            validation: ValidationConfig::default(),
+            update: UpdateConfig::default(),
+            explain: ExplainConfig::default(),
+            context: ContextConfig::default(),
+            style: StyleConfig::default(),
+            storage: StorageConfig::default(),
+            webhooks: WebhooksConfig::default(),
+            email: EmailConfig::default(),
+            weather: WeatherConfig::default(),
+            focus: FocusConfig::default(),
+            filters: FiltersConfig::default(),
+            edits: EditsConfig::default(),
+            summarize: SummarizeConfig::default(),
+            lint: LintConfig::default(),
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_read_provider_prompts() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("anthropic.md"), "You are KOTA, for Claude.\n").unwrap();
+        fs::write(dir.path().join("gemini.md"), "  \n").unwrap();
+        fs::write(dir.path().join("notes.md"), "not a provider").unwrap();
+
+        let prompts = read_provider_prompts(dir.path());
+        assert_eq!(prompts.len(), 1);
+        assert_eq!(prompts["anthropic"], "You are KOTA, for Claude.");
+    }
+
+    #[test]
+    fn test_edit_format_for_model() {
+        let edits: EditsConfig = toml::from_str("format = \"search-replace\"\n[models]\n\"gpt-4\" = \"udiff\"\n\"gpt-4o\" = \"search-replace\"\n").unwrap();
+        assert_eq!(edits.format_for("openrouter", "openai/gpt-4.1"), EditFormat::Udiff);
+        assert_eq!(edits.format_for("azure", "GPT-4o"), EditFormat::SearchReplace);
+        assert_eq!(edits.format_for("anthropic", "claude-sonnet-4-20250514"), EditFormat::SearchReplace);
+        assert!(edits.udiff_instructions.contains("```diff"));
+    }
+}
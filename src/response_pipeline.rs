@@ -0,0 +1,468 @@
+use std::fs;
+
+use anyhow::Result;
+use regex::Regex;
+
+use crate::cmd_parser;
+use crate::llm::tools::{ToolAction, ToolCall};
+use crate::memory::Citation;
+use crate::output_filter::OutputFilterProcessor;
+use crate::prompts::{EditFormat, PromptsConfig};
+use crate::sr_parser::{self, FileOperation, SearchReplaceBlock};
+use crate::todos::{self, TodoItem};
+use crate::udiff;
+use crate::web_search::normalize_url;
+
+/// Starts a line asking for a file to be read into context, for replies without tool calls
+pub const READ_FILE_MARKER: &str = "READ FILE: ";
+
+/// A model response as it reaches the processors
+pub struct ResponseInput<'a> {
+    /// The user message the response answers
+    pub prompt: &'a str,
+    pub text: &'a str,
+    pub tool_calls: &'a [ToolCall],
+}
+
+/// Everything actionable in a response. Callers handle reads, then edits, then file
+/// operations, then commands.
+#[derive(Debug, Clone, Default)]
+pub struct ProcessedResponse {
+    /// Files the model asked to have added to the context
+    pub reads: Vec<String>,
+    pub edits: Vec<SearchReplaceBlock>,
+    /// Deletes and renames, applied after the edits
+    pub file_operations: Vec<FileOperation>,
+    pub commands: Vec<String>,
+    /// The model's updated task list, from its last ```todo block
+    pub todos: Option<Vec<TodoItem>>,
+    /// Links the response cites, ready for the citation store
+    pub citations: Vec<Citation>,
+    /// Blocks and tool calls that were dropped, for the caller to report
+    pub problems: Vec<String>,
+    /// Output filter findings about what was kept, shown before anything is applied
+    pub warnings: Vec<String>,
+}
+
+impl ProcessedResponse {
+    pub fn add_read(&mut self, path: String) {
+        if !self.reads.contains(&path) {
+            self.reads.push(path);
+        }
+    }
+
+    /// The first edit of a given search text in a file wins; a later one with the same
+    /// search text couldn't match once the first is applied
+    pub fn add_edit(&mut self, block: SearchReplaceBlock) {
+        let existing = self.edits.iter().find(|edit| {
+            edit.file_path == block.file_path && edit.search_lines.trim_end() == block.search_lines.trim_end()
+        });
+        match existing {
+            Some(edit) if edit.replace_lines.trim_end() == block.replace_lines.trim_end() => {}
+            Some(_) => self.problems.push(format!("Conflicting edits to the same text in {}; keeping the first", block.file_path)),
+            None => self.edits.push(block),
+        }
+    }
+
+    /// A second operation on a file that's already being deleted or renamed couldn't apply
+    pub fn add_file_operation(&mut self, operation: FileOperation) {
+        match self.file_operations.iter().find(|existing| existing.path() == operation.path()) {
+            Some(existing) if *existing == operation => {}
+            Some(_) => self.problems.push(format!("Conflicting operations on {}; keeping the first", operation.path())),
+            None => self.file_operations.push(operation),
+        }
+    }
+
+    pub fn add_command(&mut self, command: String) {
+        let command = command.trim().to_string();
+        if !command.is_empty() && !self.commands.contains(&command) {
+            self.commands.push(command);
+        }
+    }
+
+    pub fn add_citation(&mut self, citation: Citation) {
+        let key = normalize_url(&citation.url);
+        if !self.citations.iter().any(|existing| normalize_url(&existing.url) == key) {
+            self.citations.push(citation);
+        }
+    }
+}
+
+/// One step of response post-processing
+pub trait ResponseProcessor: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// Record what this processor recognises in `out`, and return the text later processors
+    /// should see. Processors that claim blocks remove them, so a code fence inside an S/R
+    /// block isn't also taken for a command.
+    fn process(&self, input: &ResponseInput, out: &mut ProcessedResponse) -> Result<String>;
+}
+
+pub struct ResponsePipeline {
+    processors: Vec<Box<dyn ResponseProcessor>>,
+}
+
+impl ResponsePipeline {
+    pub fn new() -> Self {
+        Self { processors: Vec::new() }
+    }
+
+    /// Append a processor; earlier processors take precedence over later ones
+    pub fn processor(mut self, processor: Box<dyn ResponseProcessor>) -> Self {
+        self.processors.push(processor);
+        self
+    }
+
+    pub fn process(&self, prompt: &str, text: &str, tool_calls: &[ToolCall]) -> ProcessedResponse {
+        let mut out = ProcessedResponse::default();
+        let mut remaining = text.to_string();
+
+        for processor in &self.processors {
+            let input = ResponseInput { prompt, text: &remaining, tool_calls };
+            match processor.process(&input, &mut out) {
+                Ok(text) => remaining = text,
+                Err(e) => out.problems.push(format!("{}: {}", processor.name(), e)),
+            }
+        }
+        out
+    }
+
+    /// The built-in processors, taking unified diffs as edits too when the model was asked
+    /// for them
+    pub fn for_format(format: EditFormat) -> Self {
+        let pipeline = Self::new()
+            .processor(Box::new(ToolCallProcessor))
+            .processor(Box::new(SearchReplaceProcessor))
+            .processor(Box::new(FileOperationProcessor));
+        let pipeline = match format {
+            EditFormat::Udiff => pipeline.processor(Box::new(UnifiedDiffProcessor)),
+            EditFormat::SearchReplace => pipeline,
+        };
+        pipeline
+            .processor(Box::new(ReadRequestProcessor))
+            .processor(Box::new(TodoBlockProcessor))
+            .processor(Box::new(CommandBlockProcessor))
+            .processor(Box::new(CitationProcessor))
+            .processor(Box::new(OutputFilterProcessor::new(PromptsConfig::load().unwrap_or_default().filters)))
+    }
+}
+
+impl Default for ResponsePipeline {
+    fn default() -> Self {
+        Self::for_format(EditFormat::SearchReplace)
+    }
+}
+
+/// Run a response from a model asked to write edits in `format` through the built-in processors
+pub fn process_response_as(format: EditFormat, prompt: &str, text: &str, tool_calls: &[ToolCall]) -> ProcessedResponse {
+    ResponsePipeline::for_format(format).process(prompt, text, tool_calls)
+}
+
+/// Native tool calls from providers that support function calling
+struct ToolCallProcessor;
+
+impl ResponseProcessor for ToolCallProcessor {
+    fn name(&self) -> &str {
+        "tool_calls"
+    }
+
+    fn process(&self, input: &ResponseInput, out: &mut ProcessedResponse) -> Result<String> {
+        for call in input.tool_calls {
+            match call.to_action() {
+                Ok(ToolAction::EditFile(block)) => out.add_edit(block),
+                Ok(ToolAction::RunCommand(command)) => out.add_command(command),
+                Ok(ToolAction::AddToContext(path)) => out.add_read(path),
+                Err(e) => out.problems.push(format!("Ignoring tool call {}: {}", call.id, e)),
+            }
+        }
+        Ok(input.text.to_string())
+    }
+}
+
+struct SearchReplaceProcessor;
+
+impl ResponseProcessor for SearchReplaceProcessor {
+    fn name(&self) -> &str {
+        "search_replace"
+    }
+
+    fn process(&self, input: &ResponseInput, out: &mut ProcessedResponse) -> Result<String> {
+        if !sr_parser::contains_sr_blocks(input.text) {
+            return Ok(input.text.to_string());
+        }
+        for block in sr_parser::parse_sr_blocks(input.text)? {
+            out.add_edit(block);
+        }
+        Ok(strip_sr_blocks(input.text))
+    }
+}
+
+/// `<<<<<<< DELETE` and `<<<<<<< RENAME` blocks
+struct FileOperationProcessor;
+
+impl ResponseProcessor for FileOperationProcessor {
+    fn name(&self) -> &str {
+        "file_operations"
+    }
+
+    fn process(&self, input: &ResponseInput, out: &mut ProcessedResponse) -> Result<String> {
+        if !sr_parser::contains_file_operations(input.text) {
+            return Ok(input.text.to_string());
+        }
+        for operation in sr_parser::parse_file_operations(input.text)? {
+            out.add_file_operation(operation);
+        }
+        Ok(strip_file_operations(input.text))
+    }
+}
+
+/// Unified diffs, each hunk located in the file as it is now and turned into an S/R block
+struct UnifiedDiffProcessor;
+
+impl ResponseProcessor for UnifiedDiffProcessor {
+    fn name(&self) -> &str {
+        "unified_diff"
+    }
+
+    fn process(&self, input: &ResponseInput, out: &mut ProcessedResponse) -> Result<String> {
+        if !udiff::contains_udiff(input.text) {
+            return Ok(input.text.to_string());
+        }
+        for patch in udiff::parse_udiff(input.text)? {
+            // Deletes and renames are applied after the edits, so a renamed file's hunks
+            // are applied at its old path
+            if let Some(operation) = patch.operation() {
+                out.add_file_operation(operation);
+                if patch.new_path.is_none() || !patch.changes_content() {
+                    continue;
+                }
+            }
+            let source = patch.old_path.as_deref().unwrap_or(patch.path());
+            let content = if patch.is_new_file() {
+                String::new()
+            } else {
+                match fs::read_to_string(source) {
+                    Ok(content) => content,
+                    Err(e) => {
+                        out.problems.push(format!("Ignoring the diff for {}: {}", patch.path(), e));
+                        continue;
+                    }
+                }
+            };
+            match udiff::to_blocks(&patch, &content) {
+                Ok(blocks) => blocks.into_iter().for_each(|block| out.add_edit(block)),
+                Err(e) => out.problems.push(format!("Ignoring the diff for {}: {}", patch.path(), e)),
+            }
+        }
+        Ok(udiff::strip_udiff(input.text))
+    }
+}
+
+/// `READ FILE: path` lines, the text-only form of the add_to_context tool
+struct ReadRequestProcessor;
+
+impl ResponseProcessor for ReadRequestProcessor {
+    fn name(&self) -> &str {
+        "read_requests"
+    }
+
+    fn process(&self, input: &ResponseInput, out: &mut ProcessedResponse) -> Result<String> {
+        let mut kept = Vec::new();
+        for line in input.text.lines() {
+            match line.trim().strip_prefix(READ_FILE_MARKER) {
+                Some(path) if !path.trim().is_empty() => out.add_read(path.trim().trim_matches('`').to_string()),
+                _ => kept.push(line),
+            }
+        }
+        Ok(kept.join("\n"))
+    }
+}
+
+struct TodoBlockProcessor;
+
+impl ResponseProcessor for TodoBlockProcessor {
+    fn name(&self) -> &str {
+        "todos"
+    }
+
+    fn process(&self, input: &ResponseInput, out: &mut ProcessedResponse) -> Result<String> {
+        if !todos::contains_todo_block(input.text) {
+            return Ok(input.text.to_string());
+        }
+        out.todos = todos::parse_todo_block(input.text);
+        Ok(todos::strip_todo_blocks(input.text))
+    }
+}
+
+struct CommandBlockProcessor;
+
+impl ResponseProcessor for CommandBlockProcessor {
+    fn name(&self) -> &str {
+        "commands"
+    }
+
+    fn process(&self, input: &ResponseInput, out: &mut ProcessedResponse) -> Result<String> {
+        if !cmd_parser::contains_command_blocks(input.text) {
+            return Ok(input.text.to_string());
+        }
+        for block in cmd_parser::parse_command_blocks(input.text)? {
+            out.add_command(block.command);
+        }
+        Ok(input.text.to_string())
+    }
+}
+
+/// Markdown links outside code blocks
+struct CitationProcessor;
+
+impl ResponseProcessor for CitationProcessor {
+    fn name(&self) -> &str {
+        "citations"
+    }
+
+    fn process(&self, input: &ResponseInput, out: &mut ProcessedResponse) -> Result<String> {
+        let link = Regex::new(r"\[([^\]\n]+)\]\((https?://[^)\s]+)\)")?;
+        let retrieved_at = chrono::Local::now().format("%Y-%m-%d").to_string();
+        let topic: String = input.prompt.chars().take(100).collect();
+
+        let mut in_code_block = false;
+        for line in input.text.lines() {
+            if line.trim_start().starts_with("```") {
+                in_code_block = !in_code_block;
+                continue;
+            }
+            if in_code_block {
+                continue;
+            }
+            for captures in link.captures_iter(line) {
+                out.add_citation(Citation {
+                    url: captures[2].to_string(),
+                    title: captures[1].trim().to_string(),
+                    notes: String::new(),
+                    topic: topic.clone(),
+                    retrieved_at: retrieved_at.clone(),
+                });
+            }
+        }
+        Ok(input.text.to_string())
+    }
+}
+
+/// Drop S/R blocks, including the file path line above each one
+fn strip_sr_blocks(text: &str) -> String {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut kept = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        if i + 1 < lines.len() && lines[i + 1].trim() == "<<<<<<< SEARCH" {
+            while i < lines.len() && !lines[i].trim().starts_with(">>>>>>> REPLACE") {
+                i += 1;
+            }
+            i += 1;
+            continue;
+        }
+        kept.push(lines[i]);
+        i += 1;
+    }
+    kept.join("\n")
+}
+
+/// Drop delete and rename blocks, including the file path line above each one
+fn strip_file_operations(text: &str) -> String {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut kept = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let end = match lines.get(i + 1).map(|line| line.trim()) {
+            Some(sr_parser::DELETE_MARKER) => sr_parser::DELETE_END,
+            Some(sr_parser::RENAME_MARKER) => sr_parser::RENAME_END,
+            _ => {
+                kept.push(lines[i]);
+                i += 1;
+                continue;
+            }
+        };
+        while i < lines.len() && lines[i].trim() != end {
+            i += 1;
+        }
+        i += 1;
+    }
+    kept.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_blocks_and_tool_calls_are_merged() {
+        let text = "Fixing the greeting, see [the docs](https://doc.rust-lang.org/std/).\n\n\
+src/main.rs\n<<<<<<< SEARCH\nHello\n=======\nHi\n>>>>>>> REPLACE\n\n\
+```bash\ncargo test\n```";
+        let tool_calls = vec![
+            ToolCall { id: "1".to_string(), name: "run_command".to_string(), input: json!({"command": "cargo test"}) },
+            ToolCall { id: "2".to_string(), name: "add_to_context".to_string(), input: json!({"path": "Cargo.toml"}) },
+            ToolCall { id: "3".to_string(), name: "unknown".to_string(), input: json!({}) },
+        ];
+
+        let processed = process_response_as(EditFormat::default(), "greeting", text, &tool_calls);
+        assert_eq!(processed.reads, vec!["Cargo.toml"]);
+        assert_eq!(processed.edits.len(), 1);
+        assert_eq!(processed.edits[0].file_path, "src/main.rs");
+        // The same command from a tool call and a block only runs once
+        assert_eq!(processed.commands, vec!["cargo test"]);
+        assert_eq!(processed.citations.len(), 1);
+        assert_eq!(processed.citations[0].title, "the docs");
+        assert_eq!(processed.citations[0].topic, "greeting");
+        assert_eq!(processed.problems.len(), 1);
+    }
+
+    #[test]
+    fn test_fences_inside_edits_are_not_commands() {
+        let text = "README.md\n<<<<<<< SEARCH\nOld\n=======\n```bash\nrm -rf target\n```\n>>>>>>> REPLACE\n\n\
+README.md\n<<<<<<< SEARCH\nOld\n=======\nNew\n>>>>>>> REPLACE";
+
+        let processed = process_response_as(EditFormat::default(), "docs", text, &[]);
+        assert!(processed.commands.is_empty());
+        assert_eq!(processed.edits.len(), 1);
+        assert!(processed.problems[0].contains("Conflicting edits"));
+    }
+
+    #[test]
+    fn test_file_operations_are_collected() {
+        let text = "Moving the helpers:\nsrc/util.rs\n<<<<<<< RENAME\nsrc/util/mod.rs\n>>>>>>> RENAME\n\
+src/util.rs\n<<<<<<< DELETE\n>>>>>>> DELETE\nsrc/old.rs\n<<<<<<< DELETE\n>>>>>>> DELETE\n\
+src/util/fmt.rs\n<<<<<<< SEARCH\n=======\npub fn fmt() {}\n>>>>>>> REPLACE\nDone.";
+        let processed = process_response_as(EditFormat::default(), "split", text, &[]);
+        assert_eq!(processed.file_operations, vec![
+            FileOperation::Rename { from: "src/util.rs".to_string(), to: "src/util/mod.rs".to_string() },
+            FileOperation::Delete { path: "src/old.rs".to_string() },
+        ]);
+        assert!(processed.problems[0].contains("Conflicting operations on src/util.rs"));
+        assert_eq!(processed.edits.len(), 1);
+        assert_eq!(strip_file_operations(&strip_sr_blocks(text)), "Moving the helpers:\nDone.");
+    }
+
+    #[test]
+    fn test_read_requests_in_text() {
+        let text = "I need the rest of the file first.\nREAD FILE: src/main.rs\n  READ FILE: `Cargo.toml`\nREAD FILE: ";
+        let processed = process_response_as(EditFormat::default(), "x", text, &[]);
+        assert_eq!(processed.reads, vec!["src/main.rs", "Cargo.toml"]);
+    }
+
+    #[test]
+    fn test_todo_blocks_are_not_commands() {
+        let text = "```todo\n- [ ] Build it (run: cargo build)\n```\n```bash\ncargo build\n```";
+        let processed = process_response_as(EditFormat::default(), "x", text, &[]);
+        assert_eq!(processed.todos.unwrap()[0].command.as_deref(), Some("cargo build"));
+        assert_eq!(processed.commands, vec!["cargo build"]);
+    }
+
+    #[test]
+    fn test_malformed_blocks_are_reported() {
+        let processed = process_response_as(EditFormat::default(), "x", "src/lib.rs\n<<<<<<< SEARCH\nfn a() {}\n", &[]);
+        assert!(processed.edits.is_empty());
+        assert!(processed.problems[0].starts_with("search_replace:"));
+    }
+}
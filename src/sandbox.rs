@@ -0,0 +1,375 @@
+use std::fmt;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::prompts::PromptsConfig;
+
+// How often a running command is checked against its wall-clock limit
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+// Signal sent when a process uses up its CPU time limit; the same number on Linux and macOS
+const SIGXCPU: i32 = 24;
+// Extra CPU seconds before the hard limit, for processes that ignore SIGXCPU
+const CPU_HARD_LIMIT_GRACE_SECS: u64 = 5;
+// How long output is still read after the command exits. A process that left its group,
+// like a daemon that called setsid, can hold the pipes open for good.
+const OUTPUT_GRACE: Duration = Duration::from_secs(1);
+
+/// Limits on what model-suggested changes may touch, set under `[sandbox]` in prompts.toml
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct SandboxProfile {
+    /// Directories S/R blocks may write under, absolute or relative to the workspace.
+    /// The default only allows the workspace itself.
+    #[serde(default = "default_write_roots")]
+    pub write_roots: Vec<String>,
+    /// Seconds a shell command may run before it's killed; 0 means no limit
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+    /// CPU seconds each process of a command may use; 0 means no limit
+    #[serde(default = "default_cpu_seconds")]
+    pub cpu_seconds: u64,
+    /// Address space per process in MB; off by default since some runtimes reserve far more than they use
+    #[serde(default)]
+    pub memory_mb: u64,
+}
+
+fn default_write_roots() -> Vec<String> {
+    vec![".".to_string()]
+}
+
+fn default_timeout_secs() -> u64 {
+    600
+}
+
+fn default_cpu_seconds() -> u64 {
+    600
+}
+
+impl Default for SandboxProfile {
+    fn default() -> Self {
+        Self {
+            write_roots: default_write_roots(),
+            timeout_secs: default_timeout_secs(),
+            cpu_seconds: default_cpu_seconds(),
+            memory_mb: 0,
+        }
+    }
+}
+
+impl SandboxProfile {
+    /// Fails with a message naming the allowed roots when `path` is outside all of them
+    pub fn check_write(&self, path: &str) -> Result<()> {
+        let workspace = std::env::current_dir()?;
+        self.check_write_from(&workspace, path)
+    }
+
+    pub fn check_write_from(&self, workspace: &Path, path: &str) -> Result<()> {
+        let target = resolve(workspace, Path::new(path));
+        let allowed = self
+            .write_roots
+            .iter()
+            .any(|root| target.starts_with(resolve(workspace, Path::new(root))));
+        if allowed {
+            Ok(())
+        } else {
+            Err(anyhow::anyhow!(
+                "{} is outside the allowed write roots ({}). Add its directory to [sandbox] write_roots in prompts.toml to allow it.",
+                path,
+                self.write_roots.join(", ")
+            ))
+        }
+    }
+}
+
+/// The resource limit that stopped a command
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LimitHit {
+    WallClock(u64),
+    CpuTime(u64),
+    Memory(u64),
+}
+
+impl fmt::Display for LimitHit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitHit::WallClock(secs) => write!(f, "was killed after running for {}s (raise [sandbox] timeout_secs in prompts.toml if it needs longer)", secs),
+            LimitHit::CpuTime(secs) => write!(f, "was stopped after using {}s of CPU time (raise [sandbox] cpu_seconds in prompts.toml if it needs more)", secs),
+            LimitHit::Memory(mb) => write!(f, "ran out of memory under the {} MB limit (raise [sandbox] memory_mb in prompts.toml if it needs more)", mb),
+        }
+    }
+}
+
+/// What a command printed, and which limit stopped it, if any
+#[derive(Debug)]
+pub struct ExecOutcome {
+    pub stdout: String,
+    pub stderr: String,
+    pub status: ExitStatus,
+    pub success: bool,
+    pub limit: Option<LimitHit>,
+}
+
+/// Runs shell commands under the `[sandbox]` time and resource limits. CPU and memory are
+/// capped with `ulimit` in the shell that runs the command; the wall-clock limit kills the
+/// command's whole process group.
+pub struct SecureExecutor {
+    profile: SandboxProfile,
+}
+
+impl SecureExecutor {
+    pub fn new(profile: SandboxProfile) -> Self {
+        Self { profile }
+    }
+
+    pub fn from_config() -> Self {
+        Self::new(PromptsConfig::load().unwrap_or_default().sandbox)
+    }
+
+    /// Run `command` with `sh -c`, blocking until it exits or is killed
+    pub fn run(&self, command: &str) -> Result<ExecOutcome> {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c")
+            .arg(self.limited_script())
+            .arg("kota")
+            .arg(command)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        // Own process group, so a timeout can kill everything the command started
+        #[cfg(unix)]
+        std::os::unix::process::CommandExt::process_group(&mut cmd, 0);
+
+        let mut child = cmd.spawn()
+            .with_context(|| format!("Failed to execute command '{}'", command))?;
+        let stdout = read_in_background(child.stdout.take());
+        let stderr = read_in_background(child.stderr.take());
+
+        let started = Instant::now();
+        let timeout = Duration::from_secs(self.profile.timeout_secs);
+        let mut timed_out = false;
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if self.profile.timeout_secs > 0 && started.elapsed() >= timeout {
+                kill_process_group(&mut child);
+                timed_out = true;
+                break child.wait()?;
+            }
+            thread::sleep(POLL_INTERVAL);
+        };
+
+        let deadline = Instant::now() + OUTPUT_GRACE;
+        let stdout = stdout.finish(deadline);
+        let stderr = stderr.finish(deadline);
+        let limit = self.limit_hit(status, &stderr, timed_out);
+        Ok(ExecOutcome { success: status.success() && limit.is_none(), status, stdout, stderr, limit })
+    }
+
+    /// `sh -c` script that sets the limits, then runs the command (passed as `$1`) under them
+    fn limited_script(&self) -> String {
+        let mut script = String::new();
+        if self.profile.cpu_seconds > 0 {
+            // The soft limit sends SIGXCPU, which we can recognize; at an equal hard limit the kernel sends SIGKILL
+            script.push_str(&format!(
+                "ulimit -H -t {} 2>/dev/null; ulimit -S -t {} 2>/dev/null; ",
+                self.profile.cpu_seconds + CPU_HARD_LIMIT_GRACE_SECS,
+                self.profile.cpu_seconds
+            ));
+        }
+        if self.profile.memory_mb > 0 {
+            script.push_str(&format!("ulimit -v {} 2>/dev/null; ", self.profile.memory_mb * 1024));
+        }
+        script.push_str("exec sh -c \"$1\"");
+        script
+    }
+
+    fn limit_hit(&self, status: ExitStatus, stderr: &str, timed_out: bool) -> Option<LimitHit> {
+        if timed_out {
+            return Some(LimitHit::WallClock(self.profile.timeout_secs));
+        }
+        #[cfg(unix)]
+        let signal = std::os::unix::process::ExitStatusExt::signal(&status);
+        #[cfg(not(unix))]
+        let signal: Option<i32> = None;
+        // A shell reports a child killed by a signal as 128 + the signal number
+        if self.profile.cpu_seconds > 0 && (signal == Some(SIGXCPU) || status.code() == Some(128 + SIGXCPU)) {
+            return Some(LimitHit::CpuTime(self.profile.cpu_seconds));
+        }
+        let out_of_memory = ["Cannot allocate memory", "memory allocation of", "out of memory", "std::bad_alloc", "MemoryError"]
+            .iter()
+            .any(|message| stderr.contains(message));
+        if self.profile.memory_mb > 0 && !status.success() && out_of_memory {
+            return Some(LimitHit::Memory(self.profile.memory_mb));
+        }
+        None
+    }
+}
+
+/// A pipe being read on its own thread
+struct PipeReader {
+    bytes: Arc<Mutex<Vec<u8>>>,
+    closed: mpsc::Receiver<()>,
+}
+
+impl PipeReader {
+    /// Everything read once the pipe closes, or what was read by `deadline` if it doesn't.
+    /// The thread is left to finish on its own then.
+    fn finish(self, deadline: Instant) -> String {
+        let _ = self.closed.recv_timeout(deadline.saturating_duration_since(Instant::now()));
+        let bytes = self.bytes.lock().unwrap_or_else(|e| e.into_inner());
+        String::from_utf8_lossy(&bytes).to_string()
+    }
+}
+
+fn read_in_background(pipe: Option<impl Read + Send + 'static>) -> PipeReader {
+    let bytes = Arc::new(Mutex::new(Vec::new()));
+    let (closed_tx, closed) = mpsc::channel();
+    let buffer = bytes.clone();
+    thread::spawn(move || {
+        if let Some(mut pipe) = pipe {
+            let mut chunk = [0u8; 8192];
+            while let Ok(read) = pipe.read(&mut chunk) {
+                if read == 0 {
+                    break;
+                }
+                buffer.lock().unwrap_or_else(|e| e.into_inner()).extend_from_slice(&chunk[..read]);
+            }
+        }
+        let _ = closed_tx.send(());
+    });
+    PipeReader { bytes, closed }
+}
+
+fn kill_process_group(child: &mut Child) {
+    #[cfg(unix)]
+    let _ = Command::new("kill")
+        .args(["-s", "KILL", "--", &format!("-{}", child.id())])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+    let _ = child.kill();
+}
+
+/// Absolute form of `path` with `.` and `..` folded away and symlinks in the existing
+/// part resolved, so `src/../../etc` or a symlinked directory can't slip past a root
+pub(crate) fn resolve(workspace: &Path, path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in workspace.join(path).components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            other => normalized.push(other),
+        }
+    }
+
+    // Canonicalize the deepest ancestor that exists; the rest doesn't exist yet
+    let mut existing = normalized.as_path();
+    let mut rest = Vec::new();
+    while !existing.exists() {
+        match (existing.parent(), existing.file_name()) {
+            (Some(parent), Some(name)) => {
+                rest.push(name.to_os_string());
+                existing = parent;
+            }
+            _ => return normalized,
+        }
+    }
+    let mut resolved = existing.canonicalize().unwrap_or_else(|_| existing.to_path_buf());
+    for name in rest.into_iter().rev() {
+        resolved.push(name);
+    }
+    resolved
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_default_profile_only_allows_workspace() {
+        let dir = TempDir::new().unwrap();
+        let workspace = dir.path().join("project");
+        std::fs::create_dir_all(workspace.join("src")).unwrap();
+        let profile = SandboxProfile::default();
+
+        assert!(profile.check_write_from(&workspace, "src/main.rs").is_ok());
+        assert!(profile.check_write_from(&workspace, "new_dir/new_file.rs").is_ok());
+        assert!(profile.check_write_from(&workspace, "src/../../outside.rs").is_err());
+        assert!(profile.check_write_from(&workspace, "/etc/passwd").is_err());
+    }
+
+    #[test]
+    fn test_extra_write_roots() {
+        let dir = TempDir::new().unwrap();
+        let workspace = dir.path().join("project");
+        let shared = dir.path().join("shared");
+        std::fs::create_dir_all(&workspace).unwrap();
+        std::fs::create_dir_all(&shared).unwrap();
+        let profile = SandboxProfile {
+            write_roots: vec!["src".to_string(), shared.display().to_string()],
+            ..Default::default()
+        };
+
+        assert!(profile.check_write_from(&workspace, "src/lib.rs").is_ok());
+        assert!(profile.check_write_from(&workspace, "Cargo.toml").is_err());
+        assert!(profile.check_write_from(&workspace, "../shared/notes.md").is_ok());
+        let error = profile.check_write_from(&workspace, "../other/notes.md").unwrap_err();
+        assert!(error.to_string().contains("write_roots"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlinks_out_of_the_workspace_are_rejected() {
+        let dir = TempDir::new().unwrap();
+        let workspace = dir.path().join("project");
+        let outside = dir.path().join("outside");
+        std::fs::create_dir_all(&workspace).unwrap();
+        std::fs::create_dir_all(&outside).unwrap();
+        std::os::unix::fs::symlink(&outside, workspace.join("link")).unwrap();
+
+        let profile = SandboxProfile::default();
+        assert!(profile.check_write_from(&workspace, "link/file.txt").is_err());
+    }
+
+    #[test]
+    fn test_executor_runs_commands_and_enforces_timeout() {
+        let executor = SecureExecutor::new(SandboxProfile { timeout_secs: 1, ..Default::default() });
+        let outcome = executor.run("echo hello; echo oops >&2").unwrap();
+        assert!(outcome.success);
+        assert_eq!(outcome.stdout.trim(), "hello");
+        assert_eq!(outcome.stderr.trim(), "oops");
+        assert_eq!(outcome.limit, None);
+
+        let started = Instant::now();
+        let outcome = executor.run("sleep 30; echo done").unwrap();
+        assert!(!outcome.success);
+        assert_eq!(outcome.limit, Some(LimitHit::WallClock(1)));
+        assert!(!outcome.stdout.contains("done"));
+        assert!(started.elapsed() < Duration::from_secs(10));
+
+        // A process that left the group keeps the pipes open, but doesn't keep run waiting
+        let started = Instant::now();
+        let outcome = executor.run("setsid sleep 20 & echo started; sleep 30").unwrap();
+        assert_eq!(outcome.limit, Some(LimitHit::WallClock(1)));
+        assert_eq!(outcome.stdout.trim(), "started");
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_executor_enforces_cpu_limit() {
+        let executor = SecureExecutor::new(SandboxProfile { cpu_seconds: 1, timeout_secs: 30, ..Default::default() });
+        let outcome = executor.run("while :; do :; done").unwrap();
+        assert_eq!(outcome.limit, Some(LimitHit::CpuTime(1)));
+    }
+}
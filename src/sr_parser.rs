@@ -1,6 +1,8 @@
 use regex::Regex;
 use anyhow::Result;
 
+use crate::trace::{self, SpanExt};
+
 #[derive(Debug, Clone)]
 pub struct SearchReplaceBlock {
     pub file_path: String,
@@ -9,6 +11,7 @@ pub struct SearchReplaceBlock {
 }
 
 pub fn parse_sr_blocks(response: &str) -> Result<Vec<SearchReplaceBlock>> {
+    let _span = trace::span("parse.sr_blocks").with("bytes", response.len());
     let mut blocks = Vec::new();
     let lines: Vec<&str> = response.lines().collect();
     let mut i = 0;
@@ -80,6 +83,80 @@ pub fn contains_sr_blocks(response: &str) -> bool {
     search_pattern.is_match(response)
 }
 
+pub const DELETE_MARKER: &str = "<<<<<<< DELETE";
+pub const DELETE_END: &str = ">>>>>>> DELETE";
+pub const RENAME_MARKER: &str = "<<<<<<< RENAME";
+pub const RENAME_END: &str = ">>>>>>> RENAME";
+
+/// A change to a whole file, written as a block under the file's path like an S/R block.
+/// New files need no block of their own: they're S/R blocks with an empty SEARCH section.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FileOperation {
+    Delete { path: String },
+    /// Moves the file, creating the new path's directories
+    Rename { from: String, to: String },
+}
+
+impl FileOperation {
+    /// The file as it is now
+    pub fn path(&self) -> &str {
+        match self {
+            FileOperation::Delete { path } => path,
+            FileOperation::Rename { from, .. } => from,
+        }
+    }
+
+    /// Every path the operation writes, for write-root checks and the commit
+    pub fn paths(&self) -> Vec<&str> {
+        match self {
+            FileOperation::Delete { path } => vec![path.as_str()],
+            FileOperation::Rename { from, to } => vec![from.as_str(), to.as_str()],
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            FileOperation::Delete { path } => format!("Delete {}", path),
+            FileOperation::Rename { from, to } => format!("Rename {} to {}", from, to),
+        }
+    }
+}
+
+pub fn contains_file_operations(response: &str) -> bool {
+    response.lines().any(|line| matches!(line.trim(), DELETE_MARKER | RENAME_MARKER))
+}
+
+/// `path` / `<<<<<<< DELETE` / `>>>>>>> DELETE` and `path` / `<<<<<<< RENAME` / new path /
+/// `>>>>>>> RENAME` blocks
+pub fn parse_file_operations(response: &str) -> Result<Vec<FileOperation>> {
+    let _span = trace::span("parse.file_operations").with("bytes", response.len());
+    let lines: Vec<&str> = response.lines().collect();
+    let mut operations = Vec::new();
+    let mut i = 0;
+    while i + 1 < lines.len() {
+        let path = lines[i].trim().to_string();
+        match lines[i + 1].trim() {
+            DELETE_MARKER => {
+                if lines.get(i + 2).map(|line| line.trim()) != Some(DELETE_END) {
+                    return Err(anyhow::anyhow!("Malformed delete block: missing {} for file {}", DELETE_END, path));
+                }
+                operations.push(FileOperation::Delete { path });
+                i += 3;
+            }
+            RENAME_MARKER => {
+                let to = lines.get(i + 2).map(|line| line.trim()).unwrap_or_default();
+                if to.is_empty() || lines.get(i + 3).map(|line| line.trim()) != Some(RENAME_END) {
+                    return Err(anyhow::anyhow!("Malformed rename block: expected the new path and {} for file {}", RENAME_END, path));
+                }
+                operations.push(FileOperation::Rename { from: path, to: to.to_string() });
+                i += 4;
+            }
+            _ => i += 1,
+        }
+    }
+    Ok(operations)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -108,6 +185,25 @@ Done!"#;
         assert!(blocks[0].replace_lines.contains("new_function"));
     }
 
+    #[test]
+    fn test_file_operations() {
+        let response = "Split the parser out:\n\nsrc/old.rs\n<<<<<<< DELETE\n>>>>>>> DELETE\n\n\
+src/lib.rs\n<<<<<<< RENAME\nsrc/core/lib.rs\n>>>>>>> RENAME\n\n\
+src/main.rs\n<<<<<<< SEARCH\nmod old;\n=======\n>>>>>>> REPLACE";
+        assert!(contains_file_operations(response));
+        let operations = parse_file_operations(response).unwrap();
+        assert_eq!(operations, vec![
+            FileOperation::Delete { path: "src/old.rs".to_string() },
+            FileOperation::Rename { from: "src/lib.rs".to_string(), to: "src/core/lib.rs".to_string() },
+        ]);
+        assert_eq!(operations[1].paths(), vec!["src/lib.rs", "src/core/lib.rs"]);
+        assert_eq!(parse_sr_blocks(response).unwrap().len(), 1);
+
+        assert!(parse_file_operations("src/a.rs\n<<<<<<< RENAME\n>>>>>>> RENAME").is_err());
+        assert!(parse_file_operations("src/a.rs\n<<<<<<< DELETE\nfn a() {}").is_err());
+        assert!(!contains_file_operations("src/a.rs\n<<<<<<< SEARCH\n=======\n>>>>>>> REPLACE"));
+    }
+
     #[test]
     fn test_multiple_sr_blocks() {
         let input = r#"
@@ -0,0 +1,160 @@
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+
+use crate::prompts::{StyleConfig, Verbosity};
+
+/// `/style` changes, relative to the home directory. They apply to every project and
+/// take precedence over `[style]` in prompts.toml.
+pub const STYLE_FILE: &str = ".kota/style.json";
+
+pub fn style_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(STYLE_FILE))
+}
+
+/// Preferences set with `/style`; a missing or unreadable file sets none
+pub fn load_overrides() -> StyleConfig {
+    style_path()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default()
+}
+
+pub fn save_overrides(overrides: &StyleConfig) -> Result<PathBuf> {
+    let path = style_path().ok_or_else(|| anyhow::anyhow!("HOME is not set"))?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+    }
+    fs::write(&path, serde_json::to_string_pretty(overrides)?).with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(path)
+}
+
+/// `[style]` with the `/style` overrides applied
+pub fn effective(config: &StyleConfig) -> StyleConfig {
+    config.merged(&load_overrides())
+}
+
+impl StyleConfig {
+    /// `self` with every preference `overrides` sets replaced
+    pub fn merged(&self, overrides: &StyleConfig) -> StyleConfig {
+        StyleConfig {
+            verbosity: overrides.verbosity.or(self.verbosity),
+            language: overrides.language.clone().or_else(|| self.language.clone()),
+            emoji: overrides.emoji.or(self.emoji),
+            comments: overrides.comments.clone().or_else(|| self.comments.clone()),
+        }
+    }
+
+    /// The preferences as instructions appended to the system prompt, or None when
+    /// nothing is set
+    pub fn instructions(&self) -> Option<String> {
+        let mut lines = Vec::new();
+        match self.verbosity {
+            Some(Verbosity::Terse) => lines.push("Be terse: answer in as few words as the task needs, with no preamble or recap.".to_string()),
+            Some(Verbosity::Verbose) => lines.push("Be thorough: explain your reasoning and the tradeoffs behind each change.".to_string()),
+            Some(Verbosity::Normal) | None => {}
+        }
+        if let Some(language) = non_empty(&self.language) {
+            lines.push(format!("Reply in {}. Keep code, identifiers, commands and file paths as they are.", language));
+        }
+        if self.emoji == Some(false) {
+            lines.push("Don't use emoji.".to_string());
+        }
+        if let Some(comments) = non_empty(&self.comments) {
+            lines.push(format!("Comments in code you write: {}.", comments.trim_end_matches('.')));
+        }
+        if lines.is_empty() {
+            return None;
+        }
+        let lines: Vec<String> = lines.into_iter().map(|line| format!("- {}", line)).collect();
+        Some(format!("## Response Preferences\n{}", lines.join("\n")))
+    }
+
+    /// One line per preference, for `/style`
+    pub fn describe(&self) -> String {
+        let verbosity = match self.verbosity {
+            Some(Verbosity::Terse) => "terse",
+            Some(Verbosity::Verbose) => "verbose",
+            Some(Verbosity::Normal) | None => "normal",
+        };
+        let emoji = if self.emoji == Some(false) { "off" } else { "on" };
+        format!(
+            "  verbosity: {}\n  language:  {}\n  emoji:     {}\n  comments:  {}",
+            verbosity,
+            non_empty(&self.language).unwrap_or("(model's choice)"),
+            emoji,
+            non_empty(&self.comments).unwrap_or("(model's choice)")
+        )
+    }
+}
+
+fn non_empty(value: &Option<String>) -> Option<&str> {
+    value.as_deref().map(str::trim).filter(|value| !value.is_empty())
+}
+
+/// Apply a `/style` argument like "terse", "language French", "emoji off" or
+/// "comments off" to `overrides`. Returns what changed.
+pub fn apply(overrides: &mut StyleConfig, arg: &str) -> Result<String> {
+    let (setting, value) = arg.trim().split_once(char::is_whitespace).map(|(setting, value)| (setting, value.trim())).unwrap_or((arg.trim(), ""));
+    match (setting, value) {
+        ("terse", "") | ("verbose", "") | ("normal", "") => {
+            overrides.verbosity = Some(match setting {
+                "terse" => Verbosity::Terse,
+                "verbose" => Verbosity::Verbose,
+                _ => Verbosity::Normal,
+            });
+            Ok(format!("Responses will be {}", setting))
+        }
+        ("language", "") | ("comments", "") => Err(anyhow::anyhow!("/style {} needs a value, or off", setting)),
+        ("language", "off") => {
+            overrides.language = Some(String::new());
+            Ok("Responses will be in whatever language the model picks".to_string())
+        }
+        ("language", language) => {
+            overrides.language = Some(language.to_string());
+            Ok(format!("Responses will be in {}", language))
+        }
+        ("emoji", "on") | ("emoji", "off") => {
+            overrides.emoji = Some(value == "on");
+            Ok(format!("Emoji {}", if value == "on" { "allowed" } else { "off" }))
+        }
+        ("comments", "off") => {
+            overrides.comments = Some(String::new());
+            Ok("No comment style preference".to_string())
+        }
+        ("comments", comments) => {
+            overrides.comments = Some(comments.to_string());
+            Ok(format!("Code comments: {}", comments))
+        }
+        _ => Err(anyhow::anyhow!("Unknown style setting: {}", arg.trim())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_and_instructions() {
+        let config = StyleConfig { verbosity: Some(Verbosity::Verbose), language: Some("German".to_string()), ..StyleConfig::default() };
+        let mut overrides = StyleConfig::default();
+        apply(&mut overrides, "terse").unwrap();
+        apply(&mut overrides, "emoji off").unwrap();
+        apply(&mut overrides, "comments only for non-obvious code").unwrap();
+        apply(&mut overrides, "language off").unwrap();
+        assert!(apply(&mut overrides, "language").is_err());
+        assert!(apply(&mut overrides, "loud").is_err());
+
+        let style = config.merged(&overrides);
+        assert_eq!(style.verbosity, Some(Verbosity::Terse));
+        let instructions = style.instructions().unwrap();
+        assert!(instructions.starts_with("## Response Preferences\n- Be terse"));
+        assert!(instructions.contains("- Don't use emoji."));
+        assert!(instructions.contains("- Comments in code you write: only for non-obvious code."));
+        assert!(!instructions.contains("German"));
+
+        assert_eq!(StyleConfig::default().instructions(), None);
+        assert_eq!(config.instructions().map(|text| text.contains("Reply in German.")), Some(true));
+    }
+}
@@ -0,0 +1,182 @@
+use std::future::Future;
+
+use anyhow::Result;
+use futures::stream::{self, StreamExt, TryStreamExt};
+
+use crate::llm::{self, estimate_tokens, ModelConfig};
+use crate::prompts::{PromptsConfig, SummarizeConfig};
+
+// Smaller chunks would spend more on instructions than on the text
+const MIN_CHUNK_TOKENS: u64 = 1_000;
+
+const MERGE_INSTRUCTIONS: &str = "The summaries below cover consecutive parts of one longer text, in order. \
+Merge them into a single summary written to the same instructions, keeping every detail they ask for and \
+dropping repetition between parts.";
+
+/// Summarize `text` with `model` by `instructions`. Text over `[summarize] chunk_tokens` is split
+/// into chunks summarized in parallel, and their summaries are merged in groups until one is left.
+pub async fn summarize(text: &str, instructions: &str, model: &ModelConfig) -> Result<String> {
+    let config = PromptsConfig::load().unwrap_or_default().summarize;
+    summarize_with(text, instructions, &config, |prompt| async move { llm::ask_model_pinned(&prompt, model).await }).await
+}
+
+async fn summarize_with<F, Fut>(text: &str, instructions: &str, config: &SummarizeConfig, ask: F) -> Result<String>
+where
+    F: Fn(String) -> Fut,
+    Fut: Future<Output = Result<String>>,
+{
+    let chunk_tokens = config.chunk_tokens.max(MIN_CHUNK_TOKENS);
+    let concurrency = config.concurrency.max(1);
+    let chunks = split_chunks(text, chunk_tokens);
+    if chunks.len() <= 1 {
+        return ask(format!("{}\n\n{}", instructions, text)).await.map(|summary| summary.trim().to_string());
+    }
+
+    let total = chunks.len();
+    let prompts = chunks.into_iter().enumerate().map(|(i, chunk)| {
+        format!("{}\n\nThis is part {} of {} of a longer text; summarize only this part.\n\n{}", instructions, i + 1, total, chunk)
+    });
+    let mut summaries: Vec<String> = stream::iter(prompts.map(&ask)).buffered(concurrency).try_collect().await?;
+
+    // Each round merges neighbouring summaries, so the result keeps the text's order
+    while summaries.len() > 1 {
+        let prompts: Vec<String> = group_summaries(&summaries, chunk_tokens)
+            .into_iter()
+            .map(|group| {
+                let parts: Vec<String> = group.iter().enumerate().map(|(i, summary)| format!("### Part {}\n{}", i + 1, summary.trim())).collect();
+                format!("{}\n\nInstructions for the summary:\n{}\n\n{}", MERGE_INSTRUCTIONS, instructions, parts.join("\n\n"))
+            })
+            .collect();
+        summaries = stream::iter(prompts.into_iter().map(&ask)).buffered(concurrency).try_collect().await?;
+    }
+    Ok(summaries.remove(0).trim().to_string())
+}
+
+/// `text` in chunks of about `max_tokens`, cut between lines where possible and preferably at a
+/// blank line in the chunk's last quarter
+fn split_chunks(text: &str, max_tokens: u64) -> Vec<String> {
+    let max_bytes = (max_tokens * 4) as usize;
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    // Where the last blank line in `current` ends
+    let mut last_break = None;
+    for line in text.split_inclusive('\n') {
+        for piece in split_long_line(line, max_bytes) {
+            if !current.is_empty() && current.len() + piece.len() > max_bytes {
+                match last_break.filter(|&at| at >= max_bytes * 3 / 4) {
+                    Some(at) => {
+                        let rest = current.split_off(at);
+                        chunks.push(std::mem::replace(&mut current, rest));
+                    }
+                    None => chunks.push(std::mem::take(&mut current)),
+                }
+                last_break = None;
+            }
+            current.push_str(piece);
+            if piece.trim().is_empty() {
+                last_break = Some(current.len());
+            }
+        }
+    }
+    if !current.trim().is_empty() {
+        chunks.push(current);
+    }
+    chunks
+}
+
+/// `line` cut into pieces of at most `max_bytes`, on character boundaries
+fn split_long_line(line: &str, max_bytes: usize) -> Vec<&str> {
+    let mut pieces = Vec::new();
+    let mut rest = line;
+    while rest.len() > max_bytes {
+        let mut cut = max_bytes;
+        while !rest.is_char_boundary(cut) {
+            cut -= 1;
+        }
+        pieces.push(&rest[..cut]);
+        rest = &rest[cut..];
+    }
+    pieces.push(rest);
+    pieces
+}
+
+/// Neighbouring summaries in groups of up to `max_tokens`. Every group has at least two, so
+/// each round of merging makes progress.
+fn group_summaries(summaries: &[String], max_tokens: u64) -> Vec<Vec<String>> {
+    let mut groups: Vec<Vec<String>> = Vec::new();
+    let mut tokens = 0;
+    for summary in summaries {
+        let size = estimate_tokens(summary);
+        match groups.last_mut() {
+            Some(group) if group.len() < 2 || tokens + size <= max_tokens => {
+                group.push(summary.clone());
+                tokens += size;
+            }
+            _ => {
+                groups.push(vec![summary.clone()]);
+                tokens = size;
+            }
+        }
+    }
+    if groups.len() > 1 && groups.last().is_some_and(|group| group.len() == 1) {
+        let last = groups.pop().expect("there are groups");
+        groups.last_mut().expect("there are groups").extend(last);
+    }
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_split_chunks() {
+        let text = "a".repeat(30) + "\n" + &"b".repeat(30) + "\n\n" + &"c".repeat(30) + "\n";
+        let chunks = split_chunks(&text, 20);
+        assert_eq!(chunks.concat(), text);
+        assert!(chunks.iter().all(|chunk| chunk.len() <= 80));
+        assert_eq!(chunks[0], format!("{}\n{}\n\n", "a".repeat(30), "b".repeat(30)));
+
+        let long = "é".repeat(100);
+        let chunks = split_chunks(&long, 10);
+        assert_eq!(chunks.concat(), long);
+        assert!(chunks.iter().all(|chunk| chunk.len() <= 40));
+        assert_eq!(split_chunks("short", 10), vec!["short".to_string()]);
+    }
+
+    #[test]
+    fn test_group_summaries() {
+        let summaries: Vec<String> = ["x".repeat(40), "y".repeat(40), "z".repeat(40)].to_vec();
+        let groups = group_summaries(&summaries, 20);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].len(), 3);
+        let summaries: Vec<String> = (0..4).map(|_| "w".repeat(40)).collect();
+        assert_eq!(group_summaries(&summaries, 20).iter().map(Vec::len).collect::<Vec<_>>(), vec![2, 2]);
+    }
+
+    #[tokio::test]
+    async fn test_summarize_in_chunks_then_merge() {
+        let calls = AtomicUsize::new(0);
+        let config = SummarizeConfig { chunk_tokens: 1_000, concurrency: 2 };
+        let text = (0..10).map(|i| format!("{}\n", i.to_string().repeat(999))).collect::<String>();
+        let summary = summarize_with(&text, "Summarize.", &config, |prompt| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            async move {
+                Ok(if prompt.starts_with(MERGE_INSTRUCTIONS) {
+                    format!("merged({})", prompt.matches("### Part").count())
+                } else {
+                    "part".to_string()
+                })
+            }
+        })
+        .await
+        .unwrap();
+        // 10 KB in chunks of 4 KB is 3 chunks, merged in one request
+        assert_eq!(summary, "merged(3)");
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+
+        let short = summarize_with("tiny", "Summarize.", &config, |prompt| async move { Ok(format!(" {} ", prompt)) }).await.unwrap();
+        assert_eq!(short, "Summarize.\n\ntiny");
+    }
+}
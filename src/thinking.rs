@@ -1,7 +1,11 @@
 use indicatif::{ProgressBar, ProgressStyle};
 use colored::*;
+use std::sync::Mutex;
 use std::time::Duration;
 
+// The spinner on screen, so prompts shown mid-request can hide it while they wait for input
+static ACTIVE_SPINNER: Mutex<Option<ProgressBar>> = Mutex::new(None);
+
 pub struct ThinkingIndicator {
     spinner: ProgressBar,
 }
@@ -22,18 +26,29 @@ impl ThinkingIndicator {
         
         // Set the spinner to tick every 80ms for smooth animation
         spinner.enable_steady_tick(Duration::from_millis(80));
+        *ACTIVE_SPINNER.lock().unwrap() = Some(spinner.clone());
         
         Self { spinner }
     }
     
     pub fn finish(&self) {
         self.spinner.finish_and_clear();
+        ACTIVE_SPINNER.lock().unwrap().take();
     }
 }
 
 impl Drop for ThinkingIndicator {
     fn drop(&mut self) {
-        self.spinner.finish_and_clear();
+        self.finish();
+    }
+}
+
+/// Run `f` with the active spinner, if any, hidden
+pub fn suspend<R>(f: impl FnOnce() -> R) -> R {
+    let spinner = ACTIVE_SPINNER.lock().unwrap().clone();
+    match spinner {
+        Some(spinner) => spinner.suspend(f),
+        None => f(),
     }
 }
 
@@ -0,0 +1,249 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+pub const TODO_FILE: &str = ".kota/todos.json";
+
+/// One step of the model's plan. An item tied to a file is checked off when an edit to
+/// that file is applied; one tied to a command, when that command succeeds.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TodoItem {
+    pub text: String,
+    #[serde(default)]
+    pub done: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+}
+
+impl TodoItem {
+    /// `- [ ] Add the parser (edit: src/parser.rs)` or `- [x] Run the tests (run: cargo test)`
+    pub fn parse(line: &str) -> Option<Self> {
+        let line = line.trim();
+        let line = line.strip_prefix("- ").or_else(|| line.strip_prefix("* "))?.trim_start();
+        let (done, rest) = if let Some(rest) = line.strip_prefix("[ ]") {
+            (false, rest)
+        } else if let Some(rest) = line.strip_prefix("[x]").or_else(|| line.strip_prefix("[X]")) {
+            (true, rest)
+        } else {
+            return None;
+        };
+        let mut text = rest.trim();
+        let mut file = None;
+        let mut command = None;
+
+        if let Some(open) = text.rfind(" (") {
+            let tag = text[open + 2..].strip_suffix(')');
+            if let Some(path) = tag.and_then(|tag| tag.strip_prefix("edit:")) {
+                file = Some(path.trim().trim_matches('`').to_string());
+            } else if let Some(run) = tag.and_then(|tag| tag.strip_prefix("run:")) {
+                command = Some(run.trim().trim_matches('`').to_string());
+            }
+            if file.is_some() || command.is_some() {
+                text = text[..open].trim_end();
+            }
+        }
+        if text.is_empty() {
+            return None;
+        }
+        Some(Self { text: text.to_string(), done, file, command })
+    }
+}
+
+impl std::fmt::Display for TodoItem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "- [{}] {}", if self.done { "x" } else { " " }, self.text)?;
+        if let Some(file) = &self.file {
+            write!(f, " (edit: {})", file)?;
+        } else if let Some(command) = &self.command {
+            write!(f, " (run: {})", command)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn contains_todo_block(response: &str) -> bool {
+    response.lines().any(|line| line.trim() == "```todo")
+}
+
+/// Items in the last ```todo block of a response, which is the model's latest plan
+pub fn parse_todo_block(response: &str) -> Option<Vec<TodoItem>> {
+    let mut latest = None;
+    let mut current: Option<Vec<TodoItem>> = None;
+    for line in response.lines() {
+        match current.as_mut() {
+            None if line.trim() == "```todo" => current = Some(Vec::new()),
+            None => {}
+            Some(_) if line.trim() == "```" => latest = current.take(),
+            Some(items) => items.extend(TodoItem::parse(line)),
+        }
+    }
+    latest
+}
+
+/// The response without its ```todo blocks
+pub fn strip_todo_blocks(response: &str) -> String {
+    let mut kept = Vec::new();
+    let mut in_block = false;
+    for line in response.lines() {
+        if in_block {
+            in_block = line.trim() != "```";
+        } else if line.trim() == "```todo" {
+            in_block = true;
+        } else {
+            kept.push(line);
+        }
+    }
+    kept.join("\n")
+}
+
+/// The conversation's task list, kept in `.kota/todos.json` so it outlives the session
+#[derive(Debug, Clone)]
+pub struct TodoList {
+    path: PathBuf,
+    items: Vec<TodoItem>,
+}
+
+impl TodoList {
+    pub fn load() -> Self {
+        Self::load_from(TODO_FILE)
+    }
+
+    /// A missing or unreadable file is an empty list
+    pub fn load_from(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let items = match fs::read_to_string(&path) {
+            Ok(json) => serde_json::from_str(&json).unwrap_or_else(|e| {
+                eprintln!("Warning: Ignoring malformed todo list {}: {}", path.display(), e);
+                Vec::new()
+            }),
+            Err(_) => Vec::new(),
+        };
+        Self { path, items }
+    }
+
+    pub fn items(&self) -> &[TodoItem] {
+        &self.items
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.items.iter().filter(|item| !item.done).count()
+    }
+
+    /// Take the model's latest list. Items it restates stay checked if they were checked before.
+    pub fn replace(&mut self, items: Vec<TodoItem>) -> Result<()> {
+        let previous = std::mem::take(&mut self.items);
+        self.items = items
+            .into_iter()
+            .map(|mut item| {
+                item.done |= previous.iter().any(|old| old.done && old.text == item.text);
+                item
+            })
+            .collect();
+        self.save()
+    }
+
+    /// Check off open items tied to any of `files`; returns the items checked off
+    pub fn complete_edits(&mut self, files: &[String]) -> Result<Vec<String>> {
+        self.complete(|item| {
+            item.file.as_deref().is_some_and(|file| files.iter().any(|edited| same_path(file, edited)))
+        })
+    }
+
+    /// Check off open items tied to `command`, which just succeeded
+    pub fn complete_command(&mut self, command: &str) -> Result<Vec<String>> {
+        self.complete(|item| item.command.as_deref().is_some_and(|run| run.trim() == command.trim()))
+    }
+
+    fn complete(&mut self, matches: impl Fn(&TodoItem) -> bool) -> Result<Vec<String>> {
+        let mut completed = Vec::new();
+        for item in self.items.iter_mut().filter(|item| !item.done) {
+            if matches(item) {
+                item.done = true;
+                completed.push(item.text.clone());
+            }
+        }
+        if !completed.is_empty() {
+            self.save()?;
+        }
+        Ok(completed)
+    }
+
+    pub fn clear(&mut self) -> Result<usize> {
+        let count = self.items.len();
+        self.items.clear();
+        self.save()?;
+        Ok(count)
+    }
+
+    /// The list as the model wrote it, for the prompt
+    pub fn to_block(&self) -> String {
+        self.items.iter().map(|item| item.to_string()).collect::<Vec<_>>().join("\n")
+    }
+
+    fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+        }
+        fs::write(&self.path, serde_json::to_string_pretty(&self.items)?)
+            .with_context(|| format!("Failed to write todo list {}", self.path.display()))
+    }
+}
+
+fn same_path(a: &str, b: &str) -> bool {
+    Path::new(a.trim_start_matches("./")) == Path::new(b.trim_start_matches("./"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_parse_todo_block() {
+        let response = "Here's the plan.\n```todo\n- [ ] Add the parser (edit: src/parser.rs)\n\
+                        - [x] Read the docs\n* [ ] Run the tests (run: `cargo test`)\nnot an item\n```\nStarting now.";
+        assert!(contains_todo_block(response));
+        let items = parse_todo_block(response).unwrap();
+        assert_eq!(items.len(), 3);
+        assert_eq!(items[0].file.as_deref(), Some("src/parser.rs"));
+        assert_eq!(items[0].text, "Add the parser");
+        assert!(items[1].done);
+        assert_eq!(items[2].command.as_deref(), Some("cargo test"));
+        assert_eq!(items[2].to_string(), "- [ ] Run the tests (run: cargo test)");
+
+        assert_eq!(strip_todo_blocks(response), "Here's the plan.\nStarting now.");
+        assert!(parse_todo_block("no plan here").is_none());
+    }
+
+    #[test]
+    fn test_todo_list_checks_off_and_persists() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join(".kota/todos.json");
+
+        let mut list = TodoList::load_from(&path);
+        let plan = parse_todo_block("```todo\n- [ ] Fix it (edit: src/lib.rs)\n- [ ] Test it (run: cargo test)\n```").unwrap();
+        list.replace(plan.clone()).unwrap();
+
+        assert_eq!(list.complete_edits(&["./src/lib.rs".to_string()]).unwrap(), vec!["Fix it"]);
+        assert!(list.complete_command("cargo build").unwrap().is_empty());
+        assert_eq!(TodoList::load_from(&path).remaining(), 1);
+
+        // Restating the plan doesn't uncheck finished items
+        list.replace(plan).unwrap();
+        assert!(list.items()[0].done);
+        assert_eq!(list.complete_command(" cargo test ").unwrap(), vec!["Test it"]);
+        assert_eq!(TodoList::load_from(&path).remaining(), 0);
+
+        assert_eq!(list.clear().unwrap(), 2);
+        assert!(TodoList::load_from(&path).is_empty());
+    }
+}
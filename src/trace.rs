@@ -0,0 +1,165 @@
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::Instant;
+
+use anyhow::{Context, Result};
+use serde_json::{json, Map, Value};
+
+// Spans are only timed once `--trace-out` starts tracing
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static OUTPUT: Mutex<Option<BufWriter<File>>> = Mutex::new(None);
+static START: OnceLock<Instant> = OnceLock::new();
+static NEXT_LANE: AtomicU64 = AtomicU64::new(1);
+static TASK_LANES: Mutex<Option<HashMap<tokio::task::Id, u64>>> = Mutex::new(None);
+
+thread_local! {
+    static THREAD: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Small stable ids for the trace's lanes. Tokio tasks move between worker threads, so each
+/// task gets its own lane and its spans nest under each other there; code outside a task
+/// gets a lane per thread.
+fn lane_id() -> u64 {
+    if let Some(task) = tokio::task::try_id() {
+        let mut lanes = TASK_LANES.lock().unwrap_or_else(|e| e.into_inner());
+        return *lanes.get_or_insert_with(HashMap::new).entry(task).or_insert_with(|| NEXT_LANE.fetch_add(1, Ordering::Relaxed));
+    }
+    THREAD.with(|id| {
+        if id.get() == 0 {
+            id.set(NEXT_LANE.fetch_add(1, Ordering::Relaxed));
+        }
+        id.get()
+    })
+}
+
+/// Write every span from now on to `path` in the Chrome trace event format, which
+/// chrome://tracing, Perfetto and speedscope show as a timeline and flame graph. Events are
+/// buffered as spans end and written out when the returned guard is dropped, on `finish` or
+/// on a panic. The closing `]` the format allows to be missing is never written.
+pub fn start(path: &str) -> Result<Tracing> {
+    let mut file = BufWriter::new(File::create(path).with_context(|| format!("Failed to create trace file {}", path))?);
+    file.write_all(b"[\n")?;
+    *OUTPUT.lock().unwrap_or_else(|e| e.into_inner()) = Some(file);
+    START.get_or_init(Instant::now);
+    ENABLED.store(true, Ordering::Relaxed);
+    Ok(Tracing)
+}
+
+/// Stop tracing and write out what's buffered. Called before exiting the process, which
+/// skips destructors.
+pub fn finish() {
+    ENABLED.store(false, Ordering::Relaxed);
+    if let Some(mut file) = OUTPUT.lock().unwrap_or_else(|e| e.into_inner()).take() {
+        if let Err(e) = file.flush() {
+            eprintln!("Warning: Failed to write trace events: {}", e);
+        }
+    }
+}
+
+/// Finishes the trace when main returns
+pub struct Tracing;
+
+impl Drop for Tracing {
+    fn drop(&mut self) {
+        finish();
+    }
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Time from its creation until it's dropped, recorded as one complete event. Names are
+/// `category.what`, such as `llm.request`; the category lets the viewer color and filter them.
+pub struct Span {
+    name: &'static str,
+    args: Map<String, Value>,
+    start: Instant,
+    thread: u64,
+}
+
+/// A span, or nothing when tracing is off
+pub fn span(name: &'static str) -> Option<Span> {
+    enabled().then(|| Span { name, args: Map::new(), start: Instant::now(), thread: lane_id() })
+}
+
+pub trait SpanExt {
+    /// Attach a detail shown with the span, such as the file or model it was for
+    fn with(self, key: &str, value: impl Into<Value>) -> Self;
+}
+
+impl SpanExt for Option<Span> {
+    fn with(mut self, key: &str, value: impl Into<Value>) -> Self {
+        if let Some(span) = self.as_mut() {
+            span.args.insert(key.to_string(), value.into());
+        }
+        self
+    }
+}
+
+impl Span {
+    fn event(&self, end: Instant) -> Value {
+        let origin = *START.get_or_init(Instant::now);
+        let category = self.name.split('.').next().unwrap_or(self.name);
+        json!({
+            "name": self.name,
+            "cat": category,
+            "ph": "X",
+            "ts": self.start.saturating_duration_since(origin).as_micros() as u64,
+            "dur": end.saturating_duration_since(self.start).as_micros() as u64,
+            "pid": std::process::id(),
+            "tid": self.thread,
+            "args": self.args,
+        })
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        let event = self.event(Instant::now());
+        if let Some(file) = OUTPUT.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+            if let Err(e) = writeln!(file, "{},", event) {
+                eprintln!("Warning: Failed to write trace event: {}", e);
+                ENABLED.store(false, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_span_event() {
+        let start = *START.get_or_init(Instant::now);
+        let mut args = Map::new();
+        args.insert("file".to_string(), json!("src/main.rs"));
+        let span = Span { name: "edit.apply", args, start, thread: 3 };
+        let event = span.event(start + std::time::Duration::from_millis(5));
+        assert_eq!(event["cat"], "edit");
+        assert_eq!(event["ph"], "X");
+        assert_eq!(event["ts"], 0);
+        assert_eq!(event["dur"], 5000);
+        assert_eq!(event["tid"], 3);
+        assert_eq!(event["args"]["file"], "src/main.rs");
+    }
+
+    #[tokio::test]
+    async fn test_lanes_follow_tasks() {
+        let task_lanes = || tokio::spawn(async {
+            let first = lane_id();
+            tokio::task::yield_now().await;
+            (first, lane_id())
+        });
+        let (a, b) = (task_lanes().await.unwrap(), task_lanes().await.unwrap());
+        assert_eq!(a.0, a.1);
+        assert_eq!(b.0, b.1);
+        assert_ne!(a.0, b.0);
+        assert_ne!(std::thread::spawn(lane_id).join().unwrap(), std::thread::spawn(lane_id).join().unwrap());
+    }
+}
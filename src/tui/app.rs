@@ -1,22 +1,47 @@
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 use anyhow::Result;
-use chrono::Local;
-use tokio::sync::mpsc;
+use chrono::Utc;
+use tokio::sync::{mpsc, oneshot};
 use tokio::task;
 
-use crate::context::ContextManager;
+use crate::clock::clock;
+use crate::context::{documents, related, ContextManager, ItemSummary, Origin, SharedContext};
 use crate::llm::{self, LlmProvider, ModelConfig};
 use crate::file_browser::FileBrowser;
-use crate::dynamic_prompts::DynamicPromptData;
-use crate::memory::MemoryManager;
-use crate::sr_parser;
+use crate::file_select;
+use crate::explain;
+use crate::dynamic_prompts::{self, DynamicPromptData};
+use crate::focus;
+use crate::memory::{MemoryManager, MemoryWriter};
 use crate::editor;
-use crate::cmd_parser;
+use crate::llm::tools::ToolResponse;
+use crate::response_pipeline;
+use crate::compare;
+use crate::changelog;
+use crate::compact::Compaction;
+use crate::instructions;
+use crate::diff_review::DiffReview;
+use crate::sr_parser::{FileOperation, SearchReplaceBlock};
+use crate::validate::{self, FailureAction, FileSnapshots};
+use crate::sandbox::SecureExecutor;
+use crate::secrets::{self, ReviewDecision};
+use crate::prompts::PromptsConfig;
+use crate::commands::CommandRegistry;
+use crate::history;
+use crate::todos::TodoList;
+use crate::change_report::ChangeReport;
+use crate::undo;
+use crate::lint;
+use crate::crash;
 
-use super::types::{InputMode, FocusedPane, AppMessage, MessageContent, CommandStatus, CommandSuggestion};
+use super::types::{InputMode, FocusedPane, AppMessage, MessageContent, CommandStatus, CommandSuggestion, ComparisonView, ParseReview, SecretsReview};
+use super::chat_cache::ChatLayoutCache;
+use super::terminal_buffer::TerminalBuffer;
 
 // Threshold for collapsing pasted content
 const PASTE_COLLAPSE_THRESHOLD: usize = 10;
+// Memories shown by :search
+const SEARCH_RESULT_LIMIT: usize = 10;
 
 pub struct App {
     // UI state
@@ -25,24 +50,46 @@ pub struct App {
     pub current_line: usize,      // Current line cursor position
     pub input_mode: InputMode,
     pub messages: Vec<(String, MessageContent)>, // (role, content)
-    pub context_view: String,
+    pub chat_cache: ChatLayoutCache,
+    pub context_view: Arc<str>,
+    /// Context size against its budget, as in "~12000/50000 tokens"
+    pub context_usage: String,
+    /// Each context item, for the context pane
+    pub context_items: Vec<ItemSummary>,
+    /// How many cleared, dropped or evicted items `:rehydrate` can bring back
+    pub context_archived: usize,
+    /// Whether prompts leave out everything but user-added context
+    pub context_user_only: bool,
+    // Context revision shown in context_view
+    context_revision: Option<u64>,
+    /// Why cloud providers are off limits for the current context, per `[local_only]`
+    pub local_only_reason: Option<String>,
     pub status_message: String,
     pub current_time: String,
-    pub scroll_offset: u16,
+    pub scroll_offset: usize,
     pub auto_scroll_enabled: bool,
     pub focused_pane: FocusedPane,
     
     // Core components
-    pub context_manager: Arc<Mutex<ContextManager>>,
+    pub context_manager: SharedContext,
     pub model_config: ModelConfig,
     pub memory_manager: MemoryManager,
+    // Conversation logging happens off the UI path
+    pub memory_writer: MemoryWriter,
+    pub command_registry: CommandRegistry,
     
     // Terminal output buffer
-    pub terminal_output: Vec<String>,
+    pub terminal_output: TerminalBuffer,
     pub terminal_scroll: u16,
     pub suggested_commands: Vec<CommandSuggestion>,
     pub selected_command_index: usize,
     
+    // The model's task list, from ```todo blocks
+    pub todos: TodoList,
+    // "What changed" report for the last response, posted once its suggested commands have run
+    change_report: Option<ChangeReport>,
+    report_commands: Vec<String>,
+    
     // File browser
     pub file_browser: FileBrowser,
     pub show_file_browser: bool,
@@ -56,12 +103,33 @@ pub struct App {
     
     // Processing state
     pub is_processing: bool,
+    // Index of the KOTA message currently receiving streamed text
+    pub streaming_message: Option<usize>,
+    // The chat request in flight and its prompt, so a steering note can cancel and re-send it
+    llm_task: Option<task::JoinHandle<()>>,
+    in_flight_prompt: Option<String>,
+    /// Notes typed while a response streams, sent as the next turn when it finishes
+    pub steering: Option<String>,
+    // Latest :compare result; h/l in the chat pane switch between its answers
+    pub comparison: Option<ComparisonView>,
     
     // Context scroll
     pub context_scroll: u16,
     
     // Application state
     pub should_quit: bool,
+    
+    // First 'g' of a gg (go to top) key sequence
+    pub pending_g: bool,
+    
+    // Secrets found in a prompt that's waiting to be sent
+    pub secrets_review: Option<SecretsReview>,
+    
+    // Edits from the last response, waiting to be accepted hunk by hunk
+    pub diff_review: Option<DiffReview>,
+    
+    // Applied edits that left files unparseable, waiting to be reverted or kept
+    pub parse_review: Option<ParseReview>,
 }
 
 impl App {
@@ -70,6 +138,7 @@ impl App {
         let file_browser = FileBrowser::new()?;
         let memory_manager = MemoryManager::new()?;
         let (tx, rx) = mpsc::unbounded_channel();
+        install_secrets_review(tx.clone());
         
         Ok(Self {
             input: String::new(),
@@ -77,47 +146,128 @@ impl App {
             current_line: 0,
             input_mode: InputMode::Normal,
             messages: Vec::new(),
-            context_view: String::new(),
+            chat_cache: ChatLayoutCache::new(),
+            context_view: Arc::from(""),
+            context_usage: String::new(),
+            context_items: Vec::new(),
+            context_archived: 0,
+            context_user_only: false,
+            context_revision: None,
+            local_only_reason: None,
             status_message: "Ready - Press '?' for help".to_string(),
-            current_time: Local::now().format("%H:%M:%S").to_string(),
+            current_time: clock().time(&Utc::now()),
             scroll_offset: 0,
             auto_scroll_enabled: true,
             focused_pane: FocusedPane::Chat,
-            context_manager: Arc::new(Mutex::new(context_manager)),
+            context_manager: SharedContext::new(context_manager),
             model_config,
+            memory_writer: MemoryWriter::spawn(memory_manager.clone()),
             memory_manager,
-            terminal_output: Vec::new(),
+            command_registry: CommandRegistry::new(),
+            terminal_output: TerminalBuffer::new(),
             terminal_scroll: 0,
             suggested_commands: Vec::new(),
             selected_command_index: 0,
+            todos: TodoList::load(),
+            change_report: None,
+            report_commands: Vec::new(),
             file_browser,
             show_file_browser: true,
             live_data,
             tx,
             rx: Some(rx),
             is_processing: false,
+            streaming_message: None,
+            llm_task: None,
+            in_flight_prompt: None,
+            steering: None,
+            comparison: None,
             context_scroll: 0,
             should_quit: false,
+            pending_g: false,
+            secrets_review: None,
+            diff_review: None,
+            parse_review: None,
         })
     }
     
+    /// Answer the open secrets review dialog, letting the waiting request continue
+    pub fn answer_secrets_review(&mut self, decision: ReviewDecision) {
+        let Some(review) = self.secrets_review.take() else {
+            return;
+        };
+        self.status_message = match decision {
+            ReviewDecision::Redact => format!("Redacted {} possible secret(s)", review.findings.len()),
+            ReviewDecision::SendAnyway => "Sending with possible secrets".to_string(),
+            ReviewDecision::Cancel => "Request cancelled".to_string(),
+        };
+        let _ = review.reply.send(decision);
+    }
+    
     pub fn update_time(&mut self) {
-        self.current_time = Local::now().format("%H:%M:%S").to_string();
+        self.current_time = clock().time(&Utc::now());
     }
     
     pub fn update_context_view(&mut self) {
-        if let Ok(cm) = self.context_manager.lock() {
-            self.context_view = cm.get_formatted_context();
+        // Skipped while a write is in progress; the next tick picks up the change
+        if let Some(cm) = self.context_manager.try_read() {
+            // Re-read the formatted context only when it changed
+            if self.context_revision != Some(cm.revision()) {
+                self.context_view = cm.formatted_context();
+                self.context_usage = cm.usage_label();
+                self.context_items = cm.item_summaries();
+                self.context_archived = cm.archived_summaries().len();
+                self.context_user_only = cm.user_only();
+                self.context_revision = Some(cm.revision());
+                let local_only = PromptsConfig::load().unwrap_or_default().local_only;
+                self.local_only_reason = llm::router::local_only_reason(&local_only, cm.file_paths());
+            }
             // Update live data
             self.live_data = DynamicPromptData::new(&cm);
+            dynamic_prompts::refresh_weather_in_background();
+        }
+        if let Some(summary) = focus::finish_if_due() {
+            for line in summary.lines() {
+                self.add_terminal_output(format!("[FOCUS] {}", line));
+            }
+            self.status_message = "Focus block finished".to_string();
         }
     }
     
     pub fn add_terminal_output(&mut self, output: String) {
+        crash::log(&output);
         self.terminal_output.push(output);
-        // Keep only last 1000 lines
-        if self.terminal_output.len() > 1000 {
-            self.terminal_output.remove(0);
+    }
+    
+    /// Search the terminal pane and jump to the most recent match
+    pub fn search_terminal(&mut self, query: &str) {
+        let found = self.terminal_output.search(query);
+        self.show_terminal_match(found);
+    }
+    
+    /// Step through terminal search matches; forward moves toward newer output
+    pub fn navigate_terminal_matches(&mut self, forward: bool) {
+        if self.terminal_output.search_query().is_none() {
+            return;
+        }
+        let found = if forward {
+            self.terminal_output.next_match()
+        } else {
+            self.terminal_output.prev_match()
+        };
+        self.show_terminal_match(found);
+    }
+    
+    fn show_terminal_match(&mut self, found: Option<usize>) {
+        let query = self.terminal_output.search_query().unwrap_or("").to_string();
+        match (found, self.terminal_output.match_status()) {
+            (Some(line), Some((current, total))) => {
+                self.terminal_scroll = line.min(u16::MAX as usize) as u16;
+                self.status_message = format!("/{} - match {} of {} (n/N: next/prev, Esc: clear)", query, current, total);
+            }
+            _ => {
+                self.status_message = format!("/{} - no matches", query);
+            }
         }
     }
     
@@ -227,6 +377,7 @@ impl App {
             description: Some(format!("Execute: {}", command)),
             status: CommandStatus::Pending,
             output: None,
+            explanation: None,
         };
         self.suggested_commands.push(suggestion);
         self.add_terminal_output(format!("[SUGGESTED] {}", command));
@@ -255,6 +406,30 @@ impl App {
         }
     }
     
+    /// Ask the `[explain]` model what the selected command does, in the background
+    pub fn explain_selected_command(&mut self) {
+        let Some(suggestion) = self.suggested_commands.get(self.selected_command_index) else { return };
+        if suggestion.explanation.is_some() {
+            return;
+        }
+        let command = suggestion.command.clone();
+        self.status_message = format!("Explaining: {}", command);
+        let tx = self.tx.clone();
+        let model_config = self.model_config.clone();
+        task::spawn(async move {
+            let explanation = explain::explain(&command, &model_config).await
+                .unwrap_or_else(|e| format!("Couldn't explain it: {}", e));
+            let _ = tx.send(AppMessage::CommandExplanation(command, explanation));
+        });
+    }
+    
+    pub fn set_command_explanation(&mut self, command: &str, explanation: String) {
+        for suggestion in self.suggested_commands.iter_mut().filter(|suggestion| suggestion.command == command) {
+            suggestion.explanation = Some(explanation.clone());
+        }
+        self.status_message = "Ready".to_string();
+    }
+    
     pub fn execute_selected_command(&mut self) -> Option<String> {
         if self.selected_command_index < self.suggested_commands.len() {
             let command = self.suggested_commands[self.selected_command_index].command.clone();
@@ -269,24 +444,37 @@ impl App {
         if let Some(command) = self.execute_selected_command() {
             self.add_terminal_output(format!("[EXEC] {}", command));
             
-            // Execute the command using tokio process
-            match tokio::process::Command::new("sh")
-                .arg("-c")
-                .arg(&command)
-                .output()
-                .await
-            {
+            // Run under the [sandbox] limits off the async runtime
+            let owned = command.clone();
+            let outcome = task::spawn_blocking(move || SecureExecutor::from_config().run(&owned)).await;
+            let outcome = outcome.map_err(anyhow::Error::from).and_then(|result| result);
+            let succeeded = matches!(&outcome, Ok(output) if output.success && output.limit.is_none());
+            match outcome {
                 Ok(output) => {
-                    let stdout = String::from_utf8_lossy(&output.stdout);
-                    let stderr = String::from_utf8_lossy(&output.stderr);
+                    let stdout = output.stdout.as_str();
+                    let stderr = output.stderr.as_str();
+                    history::record_command(&command, &format!("{}{}", stdout, stderr), output.success);
                     
-                    if output.status.success() {
+                    if let Some(limit) = output.limit {
+                        // Tell the model too, so it doesn't just suggest the same command again
+                        let note = format!("Command '{}' {}", command, limit);
+                        self.add_terminal_output(format!("[LIMIT] {}", note));
+                        if let Some(mut context) = self.context_manager.try_write() {
+                            context.add_snippet_from(note.clone(), Origin::Command);
+                        }
+                        self.update_context_view();
+                        if self.selected_command_index < self.suggested_commands.len() {
+                            self.suggested_commands[self.selected_command_index].status = CommandStatus::Failed(note);
+                        }
+                    } else if output.success {
                         if !stdout.trim().is_empty() {
                             for line in stdout.lines() {
                                 self.add_terminal_output(format!("  {}", line));
                             }
                         }
                         self.add_terminal_output("[SUCCESS] Command completed".to_string());
+                        let completed = self.todos.complete_command(&command);
+                        self.note_checked_off(completed);
                         
                         // Update command status
                         if self.selected_command_index < self.suggested_commands.len() {
@@ -319,17 +507,78 @@ impl App {
                     }
                 }
             }
+            self.record_report_command(&command, succeeded);
         } else {
             self.add_terminal_output("No command selected".to_string());
         }
     }
     
-    pub fn add_file_to_context(&mut self, path: &str) -> Result<()> {
-        if let Ok(mut cm) = self.context_manager.lock() {
-            cm.add_file(path)?;
+    fn note_checked_off(&mut self, completed: Result<Vec<String>>) {
+        match completed {
+            Ok(items) => {
+                for item in items {
+                    self.add_terminal_output(format!("[TODO] Done: {}", item));
+                }
+            }
+            Err(e) => self.add_terminal_output(format!("[WARNING] Failed to update the todo list: {}", e)),
+        }
+    }
+    
+    /// Add a file, some of its lines, a directory or a glob; large directories and globs need
+    /// a trailing `--yes`, and `--ro` adds them for reference only
+    pub async fn add_file_to_context(&mut self, path: &str) -> Result<()> {
+        let (path, read_only) = file_select::split_read_only_option(path);
+        let (path, truncate) = file_select::split_flag(&path, "--truncate");
+        let (path, pages) = documents::split_pages_option(&path)?;
+        let (path, confirmed) = match path.strip_suffix("--yes") {
+            Some(path) => (path.trim(), true),
+            None => (path.as_str(), false),
+        };
+        if let Some(pages) = pages {
+            self.context_manager.write().await.add_document(path, Some(pages))?;
+            self.update_context_view();
+            self.status_message = format!("Added pages {} of {} to context", pages, path);
+            return Ok(());
+        }
+        let (path, lines) = file_select::split_line_range(path)?;
+        let path = path.as_str();
+        if let Some(range) = lines {
+            {
+                let mut context = self.context_manager.write().await;
+                context.add_file_lines(path, range)?;
+                context.set_read_only(&[path.to_string()], read_only);
+            }
+            self.update_context_view();
+            self.status_message = format!("Added lines {} of {} to context", range, path);
+            return Ok(());
+        }
+        let selection = file_select::select(path)?;
+        if selection.needs_confirmation() && !confirmed {
+            self.add_terminal_output(format!("[CONFIRM] {} matches {}. Run :add {} --yes to add them all", path, selection.summary(), path));
+            self.status_message = format!("{} is large; add --yes to confirm", path);
+            return Ok(());
+        }
+        
+        let (added, related) = {
+            let mut context = self.context_manager.write().await;
+            let added = if truncate { context.add_file_truncated(path)? } else { context.add_file(path)? };
+            context.set_read_only(&added.files, read_only);
+            let related = if added.expanded { None } else { related::follow(&mut context, path, read_only) };
+            (added, related)
+        };
+        if let Some(related) = related {
+            self.add_terminal_output(format!("[CONTEXT] {}", related));
+        }
+        if !added.skipped.is_empty() {
+            self.add_terminal_output(format!("[WARNING] Skipped {}: {}", added.skipped.len(), added.skipped.join(", ")));
         }
         self.update_context_view();
-        self.status_message = format!("Added {} to context", path);
+        let note = if read_only { " (read-only)" } else { "" };
+        self.status_message = if added.expanded {
+            format!("Added {} from {} to context{}", added.summary(), path, note)
+        } else {
+            format!("Added {} to context{}", path, note)
+        };
         Ok(())
     }
     
@@ -384,11 +633,7 @@ impl App {
         self.status_message = "Processing LLM request... (UI remains interactive)".to_string();
         
         // Get current context
-        let context = if let Ok(cm) = self.context_manager.lock() {
-            cm.get_formatted_context()
-        } else {
-            String::new()
-        };
+        let context = self.context_manager.formatted().await.to_string();
         
         // Extract the actual content for LLM
         let actual_content = match &message_content {
@@ -396,11 +641,14 @@ impl App {
             MessageContent::CollapsedPaste { full_content, .. } => full_content.clone(),
         };
         
-        // Store conversation in memory
-        if let Err(e) = self.memory_manager.store_conversation_summary(&format!("User: {}", full_input)) {
-            eprintln!("Warning: Failed to store user message in memory: {}", e);
+        let routing = PromptsConfig::load().unwrap_or_default().llm.routing;
+        if let Some(note) = llm::router::routing_note(&self.model_config, &actual_content, &routing) {
+            self.add_terminal_output(format!("Routing: {}", note));
         }
         
+        // Store conversation in memory
+        self.memory_writer.store_conversation_summary(&format!("User: {}", full_input));
+        
         // Clear the input after processing
         self.clear_input();
         
@@ -408,9 +656,22 @@ impl App {
         let tx = self.tx.clone();
         let model_config = self.model_config.clone();
         let prompt = actual_content;
+        self.in_flight_prompt = Some(prompt.clone());
         
-        task::spawn(async move {
-            match llm::ask_model_with_config(&prompt, &context, &model_config).await {
+        self.llm_task = Some(task::spawn(async move {
+            let chunk_tx = tx.clone();
+            let on_chunk = move |chunk: &str| {
+                let _ = chunk_tx.send(AppMessage::LlmStreamChunk(chunk.to_string()));
+            };
+            
+            let result = if model_config.native_tools {
+                llm::ask_model_with_tools(&prompt, &context, &model_config).await
+            } else {
+                llm::ask_model_streaming(&prompt, &context, &model_config, on_chunk).await
+                    .map(ToolResponse::from_text)
+            };
+            
+            match result {
                 Ok(response) => {
                     let _ = tx.send(AppMessage::LlmResponse(prompt, response));
                 }
@@ -419,7 +680,71 @@ impl App {
                 }
             }
             let _ = tx.send(AppMessage::ProcessingComplete);
+        }));
+    }
+    
+    /// Handle input sent while a response is still streaming. A note is queued and sent as the
+    /// next turn once the response finishes; a note starting with `!` cancels the response and
+    /// asks again with the note added.
+    pub async fn steer(&mut self) {
+        let input = self.get_full_input();
+        let note = input.trim();
+        if note.is_empty() {
+            return;
+        }
+        let note = note.to_string();
+        self.clear_input();
+        
+        if let Some(note) = note.strip_prefix('!').map(str::trim).filter(|note| !note.is_empty()) {
+            if let Some(prompt) = self.in_flight_prompt.take() {
+                if let Some(handle) = self.llm_task.take() {
+                    handle.abort();
+                }
+                // The cancelled answer stays in the chat, marked as cut short
+                if let Some(index) = self.streaming_message.take() {
+                    if let Some((_, MessageContent::Text(text))) = self.messages.get_mut(index) {
+                        text.push_str("\n\n[interrupted]");
+                    }
+                }
+                self.secrets_review = None;
+                self.is_processing = false;
+                self.add_terminal_output(format!("[STEER] Cancelled the response; asking again with: {}", note));
+                Box::pin(self.process_user_input(steered_prompt(&prompt, note))).await;
+                return;
+            }
+        }
+        
+        let note = note.trim_start_matches('!').trim();
+        self.steering = Some(match self.steering.take() {
+            Some(queued) => format!("{}\n{}", queued, note),
+            None => note.to_string(),
         });
+        self.add_terminal_output(format!("[STEER] Queued for when the response finishes: {}", note));
+        self.status_message = "Steering note queued (start a note with ! to cancel and re-ask instead)".to_string();
+    }
+    
+    /// Whether a chat request (rather than a comparison) is running, so input can steer it
+    pub fn in_flight(&self) -> bool {
+        self.in_flight_prompt.is_some()
+    }
+    
+    pub fn finish_request(&mut self) {
+        self.llm_task = None;
+        self.in_flight_prompt = None;
+    }
+    
+    /// Send a queued steering note once the response it was written during has finished
+    pub async fn send_steering(&mut self) {
+        let Some(note) = self.steering.take() else {
+            return;
+        };
+        if self.diff_review.is_some() || self.parse_review.is_some() {
+            // Reviews take the keyboard; hand the note back to be sent after them
+            self.input = note;
+            self.status_message = "Steering note kept in the input - send it when the review is done".to_string();
+            return;
+        }
+        Box::pin(self.process_user_input(note)).await;
     }
     
     pub async fn process_command(&mut self, cmd: String) {
@@ -434,23 +759,17 @@ impl App {
             }
             "w" | "write" => {
                 // Save current context to a file
-                if let Ok(cm) = self.context_manager.lock() {
-                    let context = cm.get_formatted_context();
-                    match std::fs::write("kota_context.txt", context) {
-                        Ok(_) => self.status_message = "Context saved to kota_context.txt".to_string(),
-                        Err(e) => self.status_message = format!("Error saving context: {}", e),
-                    }
-                } else {
-                    self.status_message = "Error accessing context".to_string();
+                let context = self.context_manager.formatted().await;
+                match std::fs::write("kota_context.txt", context.as_bytes()) {
+                    Ok(_) => self.status_message = "Context saved to kota_context.txt".to_string(),
+                    Err(e) => self.status_message = format!("Error saving context: {}", e),
                 }
                 return;
             }
             "wq" => {
                 // Save and quit
-                if let Ok(cm) = self.context_manager.lock() {
-                    let context = cm.get_formatted_context();
-                    let _ = std::fs::write("kota_context.txt", context);
-                }
+                let context = self.context_manager.formatted().await;
+                let _ = std::fs::write("kota_context.txt", context.as_bytes());
                 self.should_quit = true;
                 return;
             }
@@ -468,11 +787,29 @@ impl App {
                 self.add_terminal_output("".to_string());
                 self.add_terminal_output("File Commands:".to_string());
                 self.add_terminal_output("  :e <file>         - Edit/add file to context".to_string());
-                self.add_terminal_output("  :add <file>       - Add file to context (alias for :e)".to_string());
+                self.add_terminal_output("  :add <file>       - Add file to context (alias for :e; path:100-250 for a line range, --ro for reference only, --truncate for large files)".to_string());
                 self.add_terminal_output("  :context          - Display current context".to_string());
-                self.add_terminal_output("  :clear            - Clear all context".to_string());
+                self.add_terminal_output("  :context export|import <file> - Save the context to a Markdown bundle, or load one".to_string());
+                self.add_terminal_output("  :clear            - Clear all context (archived for :rehydrate)".to_string());
+                self.add_terminal_output("  :clear --origin <origin> - Clear only items from user, retrieved, agent, command or bridge".to_string());
+                self.add_terminal_output("  :rehydrate [path] - List archived items or bring one back".to_string());
+                self.add_terminal_output("  :sensitive        - Toggle sending only user-added context".to_string());
+                self.add_terminal_output("  :drop_file <path> - Remove one file or item from context".to_string());
+                self.add_terminal_output("  :pin <path>       - Keep an item from eviction (:unpin to undo)".to_string());
                 self.add_terminal_output("  :provider <name>  - Switch LLM provider".to_string());
                 self.add_terminal_output("  :model <name>     - Set model".to_string());
+                self.add_terminal_output("  :usage            - Show token usage and estimated cost".to_string());
+                self.add_terminal_output("  :focus [50m] [goal] - Hold back webhooks and suggestions until the block ends (stop, log)".to_string());
+                self.add_terminal_output("  :cache [clear]    - Show or clear the response cache".to_string());
+                self.add_terminal_output("  :models           - List local Ollama models".to_string());
+                self.add_terminal_output("  :compare <prompt> - Ask the [compare] models side by side (h/l switch answers)".to_string());
+                self.add_terminal_output("  :tools [on|off]   - Use native function calling".to_string());
+                self.add_terminal_output("  :changelog [ref]  - Draft a CHANGELOG.md section since a ref or the latest tag".to_string());
+                self.add_terminal_output("  :compact          - Summarize old turns and idle context into one snippet".to_string());
+                self.add_terminal_output("  :replay [turn] [--current] - List requests, or send one again and compare replies".to_string());
+                self.add_terminal_output("  :init [--force]   - Write KOTA.md of project conventions for the system prompt".to_string());
+                self.add_terminal_output("  :history search <query> - Search past sessions".to_string());
+                self.add_terminal_output("  :history show <id> - Replay a past session".to_string());
                 self.add_terminal_output("".to_string());
                 self.add_terminal_output("Memory Commands:".to_string());
                 self.add_terminal_output("  :memory           - Show recent memories".to_string());
@@ -486,7 +823,7 @@ impl App {
         // Handle vim-style edit command
         if cmd.starts_with("e ") {
             let path = cmd.strip_prefix("e ").unwrap_or("");
-            if let Err(e) = self.add_file_to_context(path) {
+            if let Err(e) = self.add_file_to_context(path).await {
                 self.status_message = format!("Error: {}", e);
             }
             return;
@@ -495,56 +832,254 @@ impl App {
         // Handle file commands
         if cmd.starts_with("add ") {
             let path = cmd.strip_prefix("add ").unwrap_or("");
-            if let Err(e) = self.add_file_to_context(path) {
+            if let Err(e) = self.add_file_to_context(path).await {
                 self.status_message = format!("Error: {}", e);
             }
         } else if cmd.starts_with("add_file ") {
             // Legacy support for old command format
             let path = cmd.strip_prefix("add_file ").unwrap_or("");
-            if let Err(e) = self.add_file_to_context(path) {
+            if let Err(e) = self.add_file_to_context(path).await {
                 self.status_message = format!("Error: {}", e);
             }
         } else if cmd == "context" || cmd == "show_context" {
-            let context = if let Ok(cm) = self.context_manager.lock() {
-                cm.get_formatted_context()
-            } else {
-                "Error accessing context".to_string()
-            };
+            let context = self.context_manager.formatted().await;
             self.add_terminal_output(format!("Context:\n{}", context));
         } else if cmd == "clear" || cmd == "clear_context" {
-            if let Ok(mut cm) = self.context_manager.lock() {
-                cm.clear_context();
-            }
+            self.context_manager.write().await.clear_context();
+            self.update_context_view();
+            self.status_message = "Context cleared; :rehydrate brings items back".to_string();
+        } else if let Some(name) = cmd.strip_prefix("clear --origin ").or_else(|| cmd.strip_prefix("clear_context --origin ")) {
+            let Some(origin) = Origin::parse(name) else {
+                self.status_message = "Usage: :clear --origin <user|retrieved|agent|command|bridge>".to_string();
+                return;
+            };
+            let cleared = self.context_manager.write().await.clear_origin(origin);
             self.update_context_view();
-            self.status_message = "Context cleared".to_string();
+            self.status_message = format!("Cleared {} {} item(s), archived for :rehydrate", cleared, origin.label());
         } else if cmd.starts_with("provider ") {
             let provider = cmd.strip_prefix("provider ").unwrap_or("");
             match provider {
                 "ollama" => {
                     self.model_config.provider = LlmProvider::Ollama;
+                    self.model_config.context_window = None;
                     self.status_message = "Switched to Ollama".to_string();
+                    self.spawn_ollama_model_check();
                 }
                 "gemini" => {
                     self.model_config.provider = LlmProvider::Gemini;
+                    self.model_config.context_window = None;
                     self.status_message = "Switched to Gemini".to_string();
                 }
                 "anthropic" => {
                     self.model_config.provider = LlmProvider::Anthropic;
+                    self.model_config.context_window = None;
                     self.status_message = "Switched to Anthropic Claude".to_string();
                 }
+                "openrouter" => {
+                    self.model_config.provider = LlmProvider::OpenRouter;
+                    self.model_config.context_window = None;
+                    self.model_config.model_name = None;
+                    self.status_message = "Switched to OpenRouter (browse models with :model list)".to_string();
+                }
+                "azure" | "bedrock" => {
+                    let provider = LlmProvider::from_name(provider).unwrap_or_default();
+                    self.model_config.model_name = llm::configured_model(&provider);
+                    self.model_config.provider = provider;
+                    self.model_config.context_window = None;
+                    self.status_message = format!("Switched to {}", self.model_config.display_name());
+                }
+                "groq" | "together" => {
+                    self.model_config.provider = LlmProvider::from_name(provider).unwrap_or_default();
+                    self.model_config.context_window = None;
+                    self.model_config.model_name = None;
+                    self.status_message = format!("Switched to {}", self.model_config.display_name());
+                }
+                _ => {
+                    self.status_message = "Unknown provider. Use 'ollama', 'gemini', 'anthropic', 'openrouter', 'azure', 'bedrock', 'groq', or 'together'".to_string();
+                }
+            }
+        } else if cmd == "compare" || cmd.starts_with("compare ") {
+            let prompt = cmd.trim_start_matches("compare").trim().to_string();
+            if prompt.is_empty() {
+                self.status_message = "Usage: :compare <prompt>".to_string();
+                return;
+            }
+            let targets = match compare::compare_targets(&PromptsConfig::load().unwrap_or_default().compare) {
+                Ok(targets) => targets,
+                Err(e) => {
+                    self.add_terminal_output(format!("[ERROR] {}", e));
+                    return;
+                }
+            };
+            
+            self.messages.push(("User".to_string(), MessageContent::Text(format!("/compare {}", prompt))));
+            self.auto_scroll_to_bottom();
+            self.is_processing = true;
+            self.status_message = format!("Comparing {} models...", targets.len());
+            
+            let context = self.context_manager.formatted().await.to_string();
+            let tx = self.tx.clone();
+            task::spawn(async move {
+                let answers = compare::compare(&prompt, &context, &targets).await
+                    .into_iter()
+                    .map(|answer| {
+                        let text = match answer.result {
+                            Ok(text) => text,
+                            Err(e) => format!("Error: {}", e),
+                        };
+                        (format!("{} ({:.1}s)", answer.model, answer.elapsed.as_secs_f64()), text)
+                    })
+                    .collect();
+                let _ = tx.send(AppMessage::Comparison(answers));
+                let _ = tx.send(AppMessage::ProcessingComplete);
+            });
+        } else if cmd == "compact" {
+            let planned = match self.context_manager.try_read() {
+                Some(cm) => Compaction::plan(&cm),
+                None => {
+                    self.status_message = "Context is busy, try again".to_string();
+                    return;
+                }
+            };
+            let Some(compaction) = planned else {
+                self.add_terminal_output("Nothing old enough to compact yet".to_string());
+                return;
+            };
+            self.add_terminal_output(format!("Compacting {}", compaction.describe()));
+            self.is_processing = true;
+            self.status_message = "Compacting...".to_string();
+            
+            let context = self.context_manager.clone();
+            let model = self.model_config.clone();
+            let tx = self.tx.clone();
+            task::spawn(async move {
+                match compaction.summarize(&model).await {
+                    Ok(summary) => {
+                        let report = compaction.apply(&mut *context.write().await, summary);
+                        let _ = tx.send(AppMessage::TerminalOutput(report));
+                    }
+                    Err(e) => {
+                        let _ = tx.send(AppMessage::TerminalOutput(format!("[ERROR] {}", e)));
+                    }
+                }
+                let _ = tx.send(AppMessage::ProcessingComplete);
+            });
+        } else if let Some(arg) = cmd.strip_prefix("replay ").map(str::trim).filter(|arg| !arg.is_empty()) {
+            let Some((turn, current)) = llm::replay::parse_args(arg) else {
+                self.add_terminal_output("Usage: :replay [<turn>] [--current]".to_string());
+                return;
+            };
+            self.add_terminal_output(format!("Sending request #{} again", turn));
+            self.is_processing = true;
+            self.status_message = "Replaying...".to_string();
+            
+            let model = self.model_config.clone();
+            let tx = self.tx.clone();
+            task::spawn(async move {
+                match llm::replay::replay(turn, current.then_some(&model)).await {
+                    Ok(replay) => {
+                        let _ = tx.send(AppMessage::TerminalOutput(format!("Replay of {} on {}:", replay.record.describe(), replay.model)));
+                        for line in replay.response.lines() {
+                            let _ = tx.send(AppMessage::TerminalOutput(format!("  {}", line)));
+                        }
+                        let _ = tx.send(AppMessage::TerminalOutput(replay.comparison()));
+                    }
+                    Err(e) => {
+                        let _ = tx.send(AppMessage::TerminalOutput(format!("[ERROR] {}", e)));
+                    }
+                }
+                let _ = tx.send(AppMessage::ProcessingComplete);
+            });
+        } else if cmd == "init" || cmd.starts_with("init ") {
+            let force = match cmd.trim_start_matches("init").trim() {
+                "" => false,
+                "--force" => true,
                 _ => {
-                    self.status_message = "Unknown provider. Use 'ollama', 'gemini', or 'anthropic'".to_string();
+                    self.add_terminal_output("Usage: :init [--force]".to_string());
+                    return;
+                }
+            };
+            self.add_terminal_output("Surveying the repository for KOTA.md".to_string());
+            self.is_processing = true;
+            self.status_message = "Writing KOTA.md...".to_string();
+            
+            let model = self.model_config.clone();
+            let tx = self.tx.clone();
+            task::spawn(async move {
+                let message = match instructions::init(&model, force).await {
+                    Ok(path) => format!("Wrote {}; it's now part of every system prompt. Review and edit it, then commit it for your team", path),
+                    Err(e) => format!("[ERROR] {}", e),
+                };
+                let _ = tx.send(AppMessage::TerminalOutput(message));
+                let _ = tx.send(AppMessage::ProcessingComplete);
+            });
+        } else if cmd == "changelog" || cmd.starts_with("changelog ") {
+            let since = cmd.trim_start_matches("changelog").trim().to_string();
+            let prepared = match self.context_manager.try_write() {
+                Some(mut cm) => changelog::prepare(&since, &mut cm),
+                None => Err(anyhow::anyhow!("Context is busy, try again")),
+            };
+            match prepared {
+                Ok(changes) => {
+                    self.update_context_view();
+                    self.add_terminal_output(format!("Drafting a changelog section from {}", changes.summary()));
+                    Box::pin(self.process_user_input(changes.request())).await;
+                }
+                Err(e) => {
+                    self.add_terminal_output(format!("[ERROR] {}", e));
+                    self.status_message = e.to_string();
                 }
             }
+        } else if cmd == "models" {
+            self.status_message = "Fetching Ollama models...".to_string();
+            let tx = self.tx.clone();
+            let current = self.model_config.get_model_name();
+            task::spawn(async move {
+                match llm::list_ollama_models().await {
+                    Ok(models) => {
+                        let _ = tx.send(AppMessage::TerminalOutput(format!("=== Ollama models ({}) ===", models.len())));
+                        for model in &models {
+                            let marker = if llm::ollama_has_model(std::slice::from_ref(model), &current) { "*" } else { " " };
+                            let _ = tx.send(AppMessage::TerminalOutput(format!("{} {} ({:.1} GB)", marker, model.name, model.size as f64 / 1e9)));
+                        }
+                        let _ = tx.send(AppMessage::TerminalOutput("Pick one with :provider ollama and :model <name>".to_string()));
+                    }
+                    Err(e) => {
+                        let _ = tx.send(AppMessage::TerminalOutput(format!("[ERROR] {}", e)));
+                    }
+                }
+            });
+        } else if cmd == "model list" || cmd.starts_with("model list ") {
+            let filter = cmd.trim_start_matches("model list").trim().to_string();
+            self.status_message = "Fetching OpenRouter models...".to_string();
+            let tx = self.tx.clone();
+            task::spawn(async move {
+                match llm::list_openrouter_models().await {
+                    Ok(models) => {
+                        let matches = llm::filter_openrouter_models(&models, &filter);
+                        let _ = tx.send(AppMessage::TerminalOutput(format!("=== OpenRouter models ({} of {}) ===", matches.len(), models.len())));
+                        for model in matches {
+                            let _ = tx.send(AppMessage::TerminalOutput(format!("  {} - {}", model.id, model.name)));
+                        }
+                        let _ = tx.send(AppMessage::TerminalOutput("Pick one with :model <id>".to_string()));
+                    }
+                    Err(e) => {
+                        let _ = tx.send(AppMessage::TerminalOutput(format!("[ERROR] Failed to fetch model list: {}", e)));
+                    }
+                }
+            });
         } else if cmd.starts_with("model ") {
             let model = cmd.strip_prefix("model ").unwrap_or("");
             if model.is_empty() {
                 self.status_message = format!("Current model: {}", self.model_config.display_name());
             } else {
                 self.model_config.model_name = Some(model.to_string());
+                self.model_config.context_window = None;
                 self.status_message = format!("Model set to: {}", self.model_config.display_name());
             }
         } else if cmd == "memory" || cmd == "memories" {
+            // So the latest exchanges, still queued for writing, are included
+            self.memory_writer.flush();
             match self.memory_manager.get_recent_memories(5) {
                 Ok(memories) => {
                     self.add_terminal_output("=== Recent Memories ===".to_string());
@@ -563,21 +1098,34 @@ impl App {
         } else if cmd.starts_with("search ") {
             let query = cmd.strip_prefix("search ").unwrap_or("");
             if !query.is_empty() {
-                match self.memory_manager.search_knowledge(query) {
-                    Ok(results) => {
-                        self.add_terminal_output(format!("=== Search Results for '{}' ===", query));
-                        let is_empty = results.is_empty();
-                        for result in results {
-                            self.add_terminal_output(result);
+                self.memory_writer.flush();
+                let query = query.to_string();
+                let memory_manager = self.memory_manager.clone();
+                let embeddings = PromptsConfig::load().unwrap_or_default().embeddings;
+                let tx = self.tx.clone();
+                task::spawn(async move {
+                    let results = match memory_manager.semantic_search(&embeddings, &query, SEARCH_RESULT_LIMIT).await {
+                        Ok(results) => Ok(results),
+                        Err(e) => {
+                            let _ = tx.send(AppMessage::TerminalOutput(format!("[WARNING] Semantic search failed, using text search: {}", e)));
+                            memory_manager.search_knowledge(&query)
                         }
-                        if is_empty {
-                            self.add_terminal_output("No results found".to_string());
+                    };
+                    match results {
+                        Ok(results) => {
+                            let _ = tx.send(AppMessage::TerminalOutput(format!("=== Search Results for '{}' ===", query)));
+                            if results.is_empty() {
+                                let _ = tx.send(AppMessage::TerminalOutput("No results found".to_string()));
+                            }
+                            for result in results {
+                                let _ = tx.send(AppMessage::TerminalOutput(result));
+                            }
+                        }
+                        Err(e) => {
+                            let _ = tx.send(AppMessage::TerminalOutput(format!("[ERROR] Error searching: {}", e)));
                         }
                     }
-                    Err(e) => {
-                        self.status_message = format!("Error searching: {}", e);
-                    }
-                }
+                });
             } else {
                 self.status_message = "Usage: search <query>".to_string();
             }
@@ -604,79 +1152,503 @@ impl App {
                 self.status_message = "Usage: learn <topic>: <content>".to_string();
             }
         } else {
-            self.status_message = format!("Unknown command: {}", cmd);
+            self.run_registry_command(&cmd).await;
+        }
+    }
+    
+    /// Warn in the terminal pane if the Ollama model isn't pulled; pulling needs the CLI or `ollama pull`
+    fn spawn_ollama_model_check(&self) {
+        let tx = self.tx.clone();
+        let model = self.model_config.get_model_name();
+        task::spawn(async move {
+            let message = match llm::list_ollama_models().await {
+                Ok(models) if llm::ollama_has_model(&models, &model) => return,
+                Ok(_) => format!("[WARNING] {} isn't pulled into Ollama. Run: ollama pull {}", model, model),
+                Err(e) => format!("[WARNING] {}", e),
+            };
+            let _ = tx.send(AppMessage::TerminalOutput(message));
+        });
+    }
+    
+    /// Fall back to the shared command registry for commands without TUI-specific handling
+    async fn run_registry_command(&mut self, cmd: &str) {
+        let parts: Vec<&str> = cmd.trim().splitn(2, ' ').collect();
+        let command = format!("/{}", parts[0]);
+        let arg = if parts.len() > 1 { parts[1] } else { "" };
+        
+        let context_manager = self.context_manager.clone();
+        let result = {
+            let mut cm = context_manager.write().await;
+            self.command_registry.execute(&command, arg, &mut cm, &mut self.model_config)
+        };
+        
+        match result {
+            Ok(Some(result)) => {
+                if result.success {
+                    for line in result.output.lines() {
+                        self.add_terminal_output(line.to_string());
+                    }
+                    self.status_message = format!("Executed: {}", command);
+                } else {
+                    let error = result.error.unwrap_or_else(|| "Command failed".to_string());
+                    self.add_terminal_output(format!("[ERROR] {}", error));
+                    self.status_message = error;
+                }
+            }
+            Ok(None) => {
+                self.status_message = format!("Unknown command: {}", cmd);
+            }
+            Err(e) => {
+                self.status_message = format!("Error: {}", e);
+            }
+        }
+        // /todo clear changes the list on disk
+        self.todos = TodoList::load();
+    }
+    
+    /// Show compared answers as one chat message, starting on the first model's tab
+    pub fn add_comparison(&mut self, answers: Vec<(String, String)>) {
+        if answers.is_empty() {
+            return;
+        }
+        let view = ComparisonView { message_index: self.messages.len(), answers, selected: 0 };
+        self.messages.push(("KOTA".to_string(), MessageContent::Text(view.render())));
+        self.comparison = Some(view);
+        self.auto_scroll_to_bottom();
+        self.add_terminal_output("Comparison ready - focus the chat pane and press h/l to switch answers".to_string());
+    }
+    
+    /// Move the latest comparison to the previous (-1) or next (+1) answer
+    pub fn switch_comparison_tab(&mut self, step: isize) {
+        let Some(view) = self.comparison.as_mut() else {
+            return;
+        };
+        let count = view.answers.len() as isize;
+        view.selected = (view.selected as isize + step).rem_euclid(count) as usize;
+        if let Some((_, content)) = self.messages.get_mut(view.message_index) {
+            *content = MessageContent::Text(view.render());
+        }
+        self.status_message = format!("Showing {}", view.answers[view.selected].0);
+    }
+    
+    /// Append streamed text to the in-progress KOTA message, starting one if needed
+    pub fn append_stream_chunk(&mut self, chunk: &str) {
+        let index = match self.streaming_message {
+            Some(index) => index,
+            None => {
+                self.messages.push(("KOTA".to_string(), MessageContent::Text(String::new())));
+                let index = self.messages.len() - 1;
+                self.streaming_message = Some(index);
+                self.auto_scroll_to_bottom();
+                index
+            }
+        };
+        
+        if let Some((_, MessageContent::Text(text))) = self.messages.get_mut(index) {
+            text.push_str(chunk);
         }
     }
     
-    #[allow(clippy::await_holding_lock)]
-    pub async fn handle_llm_response(&mut self, original_prompt: String, response: String) {
+    pub async fn handle_llm_response(&mut self, original_prompt: String, response: ToolResponse) {
+        // Tool calls are shown as the blocks they stand for
+        let text = response.to_text_with_blocks();
+        
         // Always show KOTA responses in full - don't collapse them
-        let message_content = MessageContent::Text(response.clone());
+        let message_content = MessageContent::Text(text.clone());
         
-        self.messages.push(("KOTA".to_string(), message_content));
+        // Replace the streamed message with the final text rather than adding it twice
+        match self.streaming_message.take() {
+            Some(index) if index < self.messages.len() => {
+                self.messages[index] = ("KOTA".to_string(), message_content);
+            }
+            _ => self.messages.push(("KOTA".to_string(), message_content)),
+        }
         
         // Store KOTA response in memory
-        if let Err(e) = self.memory_manager.store_conversation_summary(&format!("KOTA: {}", &response[..500.min(response.len())])) {
-            eprintln!("Warning: Failed to store KOTA response in memory: {}", e);
-        }
+        self.memory_writer.store_conversation_summary(&format!("KOTA: {}", text.chars().take(500).collect::<String>()));
         
         // Auto-scroll to bottom when KOTA responds
         self.auto_scroll_to_bottom();
         
-        self.add_terminal_output(format!("KOTA: {}", &response[..response.len().min(100)]));
+        self.add_terminal_output(format!("KOTA: {}", text.chars().take(100).collect::<String>()));
         
-        // Check for S/R blocks
-        if sr_parser::contains_sr_blocks(&response) {
-            match sr_parser::parse_sr_blocks(&response) {
-                Ok(blocks) => {
-                    if !blocks.is_empty() {
-                        self.add_terminal_output(format!("Found {} S/R blocks - applying changes...", blocks.len()));
-                        
-                        // Apply blocks (simplified for TUI)
-                        let apply_result = {
-                            if let Ok(cm) = self.context_manager.lock() {
-                                editor::confirm_and_apply_blocks(blocks, &original_prompt, &cm).await
-                            } else {
-                                Err(anyhow::anyhow!("Could not access context manager"))
-                            }
-                        };
-                        
-                        match apply_result {
-                            Ok(_) => {
-                                self.add_terminal_output("Changes applied successfully".to_string());
-                                self.update_context_view();
-                            }
-                            Err(e) => {
-                                self.add_terminal_output(format!("Error applying changes: {}", e));
-                            }
+        let processed = response_pipeline::process_response_as(self.model_config.edit_format(), &original_prompt, &response.text, &response.tool_calls);
+        for problem in &processed.problems {
+            self.add_terminal_output(format!("[WARNING] {}", problem));
+        }
+        for warning in &processed.warnings {
+            self.add_terminal_output(format!("[FILTER] {}", warning));
+        }
+        if !processed.citations.is_empty() {
+            self.memory_writer.store_citations(processed.citations);
+        }
+        
+        if let Some(items) = processed.todos {
+            if let Err(e) = self.todos.replace(items) {
+                self.add_terminal_output(format!("[WARNING] Failed to save the todo list: {}", e));
+            }
+        }
+        
+        for path in &processed.reads {
+            self.add_terminal_output(format!("KOTA asked to read {} - add it with :add {}", path, path));
+        }
+        
+        if processed.edits.is_empty() && processed.file_operations.is_empty() && processed.commands.is_empty() {
+            return;
+        }
+        self.post_change_report();
+        let report = ChangeReport::start(&original_prompt);
+        
+        if !processed.edits.is_empty() || !processed.file_operations.is_empty() {
+            self.add_terminal_output(format!(
+                "Found {} S/R blocks and {} file operation(s) - review them in the diff view",
+                processed.edits.len(), processed.file_operations.len()
+            ));
+            self.open_diff_review(&original_prompt, processed.edits, processed.file_operations).await;
+        }
+        
+        if !processed.commands.is_empty() {
+            self.add_terminal_output(format!("Found {} suggested command(s):", processed.commands.len()));
+            
+            // Show suggested commands in terminal
+            for command in processed.commands {
+                self.report_commands.push(command.clone());
+                self.add_suggested_command(command);
+            }
+            
+            self.add_terminal_output("Press 'x' in terminal mode to execute commands".to_string());
+        }
+        
+        self.change_report = Some(report);
+        if self.report_commands.is_empty() && !self.reviewing_edits() {
+            self.post_change_report();
+        }
+    }
+    
+    /// Open the diff review for edits and file operations on files in context and inside the
+    /// write roots, and for new files; the rest are blocked
+    async fn open_diff_review(&mut self, original_prompt: &str, edits: Vec<SearchReplaceBlock>, operations: Vec<FileOperation>) {
+        let sandbox = PromptsConfig::load().unwrap_or_default().sandbox;
+        let cm = self.context_manager.snapshot().await;
+        let blocked = |paths: Vec<&str>, path: &str, new_file: bool| {
+            if let Some(e) = paths.into_iter().find_map(|path| sandbox.check_write(path).err()) {
+                Some(format!("[BLOCKED] {}: {}", path, e))
+            } else if !new_file && !cm.is_file_in_context(path) {
+                Some(format!("[BLOCKED] {} is not in context - add it with :add {}", path, path))
+            } else if cm.is_read_only(path) {
+                Some(format!("[BLOCKED] {} was added read-only for reference - add it again without --ro to allow edits: :add {}", path, path))
+            } else {
+                None
+            }
+        };
+        let mut reviewable = Vec::new();
+        let mut messages = Vec::new();
+        for block in edits {
+            match blocked(vec![block.file_path.as_str()], &block.file_path, editor::creates_file(&block)) {
+                Some(message) => messages.push(message),
+                None => reviewable.push(block),
+            }
+        }
+        let mut reviewable_operations = Vec::new();
+        for operation in operations {
+            match blocked(operation.paths(), operation.path(), false) {
+                Some(message) => messages.push(message),
+                None => reviewable_operations.push(operation),
+            }
+        }
+        for message in messages {
+            self.add_terminal_output(message);
+        }
+        
+        let review = DiffReview::new(original_prompt, reviewable).with_operations(reviewable_operations);
+        if review.is_empty() {
+            return;
+        }
+        self.status_message = "DIFF REVIEW - j/k: hunks, y/n: accept/reject, Y/N: whole block, Enter: apply, Esc: discard".to_string();
+        self.diff_review = Some(review);
+    }
+    
+    /// Close the diff review, applying the accepted hunks or discarding everything
+    pub async fn finish_diff_review(&mut self, apply: bool) {
+        let Some(review) = self.diff_review.take() else {
+            return;
+        };
+        self.status_message = "Ready".to_string();
+        let (accepted, total) = review.counts();
+        let blocks = if apply { review.accepted_blocks() } else { Vec::new() };
+        let operations = if apply { review.accepted_operations() } else { Vec::new() };
+        
+        let edits_config = PromptsConfig::load().unwrap_or_default().edits;
+        let transactional = edits_config.transactional;
+        let mut snapshots = FileSnapshots::default();
+        let mut applied = Vec::new();
+        let mut failure = None;
+        // Edits that didn't match, written again by the model for another review
+        let mut regenerated = Vec::new();
+        // The response in order with those rewrites in place, reviewed again as a whole when
+        // all-or-nothing apply can't keep part of it
+        let mut batch = Vec::new();
+        let block_count = blocks.len();
+        for (index, block) in blocks.into_iter().enumerate() {
+            snapshots.record(&block.file_path);
+            let error = match editor::apply_sr_block(&block) {
+                Ok(()) => {
+                    batch.push(block.clone());
+                    applied.push(block);
+                    continue;
+                }
+                Err(e) if editor::is_mismatch(&e) && review.retry < edits_config.match_retries => {
+                    self.add_terminal_output(format!("[NO MATCH] {}", e));
+                    self.add_terminal_output(format!("Asking {} for the edit to {} again...", self.model_config.display_name(), block.file_path));
+                    match editor::regenerate_block(&block, &self.model_config).await {
+                        Ok(corrected) => {
+                            batch.push(corrected.clone());
+                            regenerated.push(corrected);
+                            continue;
                         }
+                        Err(e) => e,
                     }
                 }
-                Err(e) => {
-                    self.add_terminal_output(format!("Error parsing S/R blocks: {}", e));
+                Err(e) => e,
+            };
+            if transactional {
+                failure = Some(format!("Edit {} of {} ({}) failed: {}", index + 1, block_count, block.file_path, error));
+                break;
+            }
+            self.add_terminal_output(format!("Error applying changes: {}", error));
+        }
+        let redo = transactional && failure.is_none() && !regenerated.is_empty();
+        let (operations, deferred_operations) = if redo { (Vec::new(), operations) } else { (operations, Vec::new()) };
+        // Deletes and renames come after the edits, which name files by their current paths
+        let mut applied_operations = Vec::new();
+        let operation_count = operations.len();
+        for (index, operation) in operations.into_iter().enumerate() {
+            if failure.is_some() {
+                break;
+            }
+            for path in operation.paths() {
+                snapshots.record(path);
+            }
+            match editor::apply_file_operation(&operation) {
+                Ok(()) => {
+                    self.add_terminal_output(format!("{} - done", operation.describe()));
+                    applied_operations.push(operation);
+                }
+                Err(e) if transactional => {
+                    failure = Some(format!("File operation {} of {} ({}) failed: {}", index + 1, operation_count, operation.describe(), e));
                 }
+                Err(e) => self.add_terminal_output(format!("Error applying changes: {}", e)),
             }
         }
         
-        // Check for command blocks
-        if cmd_parser::contains_command_blocks(&response) {
-            match cmd_parser::parse_command_blocks(&response) {
-                Ok(cmd_blocks) => {
-                    if !cmd_blocks.is_empty() {
-                        self.add_terminal_output(format!("Found {} suggested command(s):", cmd_blocks.len()));
-                        
-                        // Show suggested commands in terminal
-                        for cmd_block in cmd_blocks.iter() {
-                            self.add_suggested_command(cmd_block.command.clone());
-                        }
-                        
-                        self.add_terminal_output("Press 'x' in terminal mode to execute commands".to_string());
-                    }
+        // With transactional apply, one failure undoes the whole response
+        if let Some(failure) = failure {
+            self.add_terminal_output(format!("[ERROR] {}", failure));
+            let mut files = edited_files(&applied);
+            for path in applied_operations.iter().flat_map(|operation| operation.paths()) {
+                if !files.iter().any(|file| file == path) {
+                    files.push(path.to_string());
                 }
-                Err(e) => {
-                    self.add_terminal_output(format!("Error parsing command blocks: {}", e));
+            }
+            if !files.is_empty() {
+                self.add_terminal_output(format!("Rolled back the {} change(s) applied before it: {}", applied.len() + applied_operations.len(), files.join(", ")));
+                for problem in editor::roll_back(&snapshots, &files) {
+                    self.add_terminal_output(format!("Error: {}", problem));
+                }
+            }
+            applied.clear();
+            applied_operations.clear();
+            regenerated.clear();
+        }
+        
+        // The rewrites were made against the files with the earlier edits applied, so undoing
+        // those and reviewing them together puts every file back where the rewrites expect
+        if redo {
+            let files = edited_files(&applied);
+            if !files.is_empty() {
+                self.add_terminal_output(format!("Undid the {} edit(s) applied with them, so the response can be applied as a whole: {}", applied.len(), files.join(", ")));
+                for problem in editor::roll_back(&snapshots, &files) {
+                    self.add_terminal_output(format!("Error: {}", problem));
+                }
+            }
+            applied.clear();
+            self.add_terminal_output(format!("{} edit(s) didn't match their files and were written again - review the response again", regenerated.len()));
+            self.open_diff_review(&review.original_prompt, batch, deferred_operations).await;
+            if let Some(next) = self.diff_review.as_mut() {
+                next.retry = review.retry + 1;
+            }
+        } else if !regenerated.is_empty() {
+            self.add_terminal_output(format!("{} edit(s) didn't match their files and were written again - review them next", regenerated.len()));
+            self.open_diff_review(&review.original_prompt, regenerated, Vec::new()).await;
+            if let Some(next) = self.diff_review.as_mut() {
+                next.retry = review.retry + 1;
+            }
+        }
+        
+        if applied.is_empty() && applied_operations.is_empty() {
+            if self.diff_review.is_none() {
+                self.add_terminal_output("No changes applied".to_string());
+            }
+        } else {
+            if !applied.is_empty() {
+                self.add_terminal_output(format!("Applied {} of {} hunk(s)", accepted, total));
+            }
+            
+            // Edits that broke a file wait on revert, fix-it or keep before they're committed
+            let failures = if PromptsConfig::load().unwrap_or_default().validation.enabled {
+                let existing: Vec<String> = edited_files(&applied).into_iter().filter(|file| std::path::Path::new(file).is_file()).collect();
+                validate::check_all(&existing)
+            } else {
+                Vec::new()
+            };
+            if !failures.is_empty() {
+                for failure in &failures {
+                    self.add_terminal_output(format!("[PARSE ERROR] {} ({}): {}", failure.path, failure.tool, failure.message.lines().next().unwrap_or_default()));
+                }
+                self.status_message = "Edited files no longer parse - r: revert, f: ask for a fix, k: keep".to_string();
+                self.parse_review = Some(ParseReview { failures, applied, operations: applied_operations, snapshots, original_prompt: review.original_prompt });
+                return;
+            }
+            self.record_applied(applied, applied_operations, &snapshots, &review.original_prompt).await;
+        }
+        
+        if self.report_commands.is_empty() && self.diff_review.is_none() {
+            self.post_change_report();
+        }
+    }
+    
+    /// Answer the parse error dialog: revert the broken files, or keep them and maybe ask for a fix
+    pub async fn answer_parse_review(&mut self, action: FailureAction) {
+        let Some(review) = self.parse_review.take() else {
+            return;
+        };
+        self.status_message = "Ready".to_string();
+        let mut applied = review.applied;
+        let mut operations = review.operations;
+        if action == FailureAction::Revert {
+            let failed: Vec<String> = review.failures.iter().map(|failure| failure.path.clone()).collect();
+            for (path, result) in editor::revert_failed(&review.snapshots, &failed, &mut operations) {
+                match result {
+                    Ok(()) => {
+                        self.add_terminal_output(format!("Reverted {}", path));
+                        applied.retain(|block| block.file_path != path);
+                    }
+                    Err(e) => self.add_terminal_output(format!("Error: {}", e)),
                 }
             }
         }
+        
+        if !applied.is_empty() || !operations.is_empty() {
+            self.record_applied(applied, operations, &review.snapshots, &review.original_prompt).await;
+        }
+        if self.report_commands.is_empty() {
+            self.post_change_report();
+        }
+        if action == FailureAction::Fix {
+            self.process_user_input(validate::fix_prompt(&review.failures)).await;
+        }
+    }
+    
+    /// Note applied edits and file operations in the report, context, todo list and undo
+    /// history, then auto-commit them
+    async fn record_applied(&mut self, applied: Vec<SearchReplaceBlock>, operations: Vec<FileOperation>, snapshots: &FileSnapshots, original_prompt: &str) {
+        if let Some(report) = self.change_report.as_mut() {
+            report.record_edits(&applied);
+            report.record_file_operations(&operations);
+        }
+        {
+            let mut cm = self.context_manager.write().await;
+            cm.record_edits(&applied);
+            cm.record_file_operations(&operations);
+        }
+        self.update_context_view();
+        let mut files = edited_files(&applied);
+        for path in operations.iter().flat_map(FileOperation::paths) {
+            if !files.iter().any(|file| file == path) {
+                files.push(path.to_string());
+            }
+        }
+        let completed = self.todos.complete_edits(&files);
+        self.note_checked_off(completed);
+        let lint_config = PromptsConfig::load().unwrap_or_default().lint;
+        if lint_config.enabled {
+            let run = lint::run_all(&files, &lint_config);
+            for file in &run.formatted {
+                self.add_terminal_output(format!("Formatted {}", file));
+            }
+            for finding in &run.findings {
+                self.add_terminal_output(format!("[LINT] {} failed: {}", finding.command, finding.output.lines().next().unwrap_or_default()));
+            }
+            if let Some(note) = run.context_note() {
+                self.context_manager.write().await.add_snippet_from(note, Origin::Command);
+                self.add_terminal_output("The errors were added to context for the next request".to_string());
+                self.update_context_view();
+            }
+        }
+        undo::record(original_prompt, snapshots, &files);
+        editor::commit_applied(original_prompt, &files).await;
+    }
+    
+    /// Count a suggested command towards the pending report, posting it after the last one
+    fn record_report_command(&mut self, command: &str, success: bool) {
+        let Some(position) = self.report_commands.iter().position(|pending| pending == command) else {
+            return;
+        };
+        self.report_commands.remove(position);
+        if let Some(report) = self.change_report.as_mut() {
+            report.record_command(command, success);
+        }
+        if self.report_commands.is_empty() && !self.reviewing_edits() {
+            self.post_change_report();
+        }
+    }
+    
+    /// Edits are still waiting on the diff review or the parse error dialog
+    fn reviewing_edits(&self) -> bool {
+        self.diff_review.is_some() || self.parse_review.is_some()
+    }
+    
+    /// Append the pending "what changed" report to the chat if the response did more than one thing
+    fn post_change_report(&mut self) {
+        self.report_commands.clear();
+        let Some(mut report) = self.change_report.take() else {
+            return;
+        };
+        report.finish();
+        if report.is_multi_step() {
+            self.memory_writer.store_conversation_summary(&report.summary());
+            self.messages.push(("KOTA".to_string(), MessageContent::Text(report.render())));
+            self.auto_scroll_to_bottom();
+        }
+    }
+}
+
+/// The prompt to send again after a steering note cancelled its answer
+pub(crate) fn steered_prompt(prompt: &str, note: &str) -> String {
+    format!("{}\n\n[While you were answering I added: {}]", prompt.trim_end(), note)
+}
+
+/// Each edited file once, in the order first edited
+fn edited_files(applied: &[SearchReplaceBlock]) -> Vec<String> {
+    let mut files: Vec<String> = Vec::new();
+    for block in applied {
+        if !files.contains(&block.file_path) {
+            files.push(block.file_path.clone());
+        }
     }
-}
\ No newline at end of file
+    files
+}
+
+/// Route secrets reviews to the TUI's dialog. Without a running TUI to answer, findings are redacted.
+fn install_secrets_review(tx: mpsc::UnboundedSender<AppMessage>) {
+    secrets::set_review_handler(Arc::new(move |findings| {
+        let tx = tx.clone();
+        Box::pin(async move {
+            let (reply, answer) = oneshot::channel();
+            if tx.send(AppMessage::SecretsReview(SecretsReview { findings, reply })).is_err() {
+                return ReviewDecision::Redact;
+            }
+            answer.await.unwrap_or(ReviewDecision::Redact)
+        })
+    }));
+}
@@ -0,0 +1,129 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use ratatui::{
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+};
+
+use super::types::MessageContent;
+use super::widgets::process_markdown_for_display;
+
+struct CachedMessage {
+    hash: u64,
+    lines: Vec<Line<'static>>,
+}
+
+/// Wrapped and styled chat lines, kept per message so long sessions don't
+/// re-run markdown processing and wrapping for every frame
+pub struct ChatLayoutCache {
+    width: u16,
+    entries: Vec<CachedMessage>,
+}
+
+impl Default for ChatLayoutCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ChatLayoutCache {
+    pub fn new() -> Self {
+        Self {
+            width: 0,
+            entries: Vec::new(),
+        }
+    }
+
+    /// Re-lay out only messages that are new or changed; a width change invalidates everything
+    pub fn sync(&mut self, messages: &[(String, MessageContent)], width: u16) {
+        if width != self.width {
+            self.entries.clear();
+            self.width = width;
+        }
+        self.entries.truncate(messages.len());
+
+        for (i, (role, content)) in messages.iter().enumerate() {
+            let hash = message_hash(role, content);
+            match self.entries.get(i) {
+                Some(entry) if entry.hash == hash => {}
+                Some(_) => {
+                    self.entries[i] = CachedMessage { hash, lines: layout_message(role, content, width) };
+                }
+                None => {
+                    self.entries.push(CachedMessage { hash, lines: layout_message(role, content, width) });
+                }
+            }
+        }
+    }
+
+    /// The `height` lines starting at line `offset`. Messages entirely above the
+    /// window are skipped without cloning, so frame cost tracks the pane size
+    /// rather than the length of the conversation.
+    pub fn visible_lines(&self, offset: usize, height: usize) -> Vec<Line<'static>> {
+        let mut visible = Vec::with_capacity(height);
+        let mut skip = offset;
+
+        for entry in &self.entries {
+            if visible.len() >= height {
+                break;
+            }
+            if skip >= entry.lines.len() {
+                skip -= entry.lines.len();
+                continue;
+            }
+            let remaining = height - visible.len();
+            visible.extend(entry.lines.iter().skip(skip).take(remaining).cloned());
+            skip = 0;
+        }
+
+        visible
+    }
+
+    pub fn total_lines(&self) -> usize {
+        self.entries.iter().map(|entry| entry.lines.len()).sum()
+    }
+}
+
+fn message_hash(role: &str, content: &MessageContent) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    role.hash(&mut hasher);
+    content.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn layout_message(role: &str, content: &MessageContent, width: u16) -> Vec<Line<'static>> {
+    let width = (width as usize).max(1);
+    let mut lines = Vec::new();
+
+    let style = if role == "User" {
+        Style::default().fg(Color::Green)
+    } else {
+        Style::default().fg(Color::Cyan)
+    };
+
+    // Add role header
+    lines.push(Line::from(vec![
+        Span::styled(format!("{}: ", role), style.add_modifier(Modifier::BOLD)),
+    ]));
+
+    // Process content based on type
+    match content {
+        MessageContent::Text(text) => {
+            let processed_content = process_markdown_for_display(text);
+            for line in processed_content.lines() {
+                for wrapped in textwrap::wrap(line, width) {
+                    lines.push(Line::from(wrapped.into_owned()));
+                }
+            }
+        }
+        MessageContent::CollapsedPaste { summary, .. } => {
+            lines.push(Line::from(vec![
+                Span::styled(summary.clone(), Style::default().fg(Color::DarkGray).add_modifier(Modifier::ITALIC)),
+            ]));
+        }
+    }
+
+    lines.push(Line::from("")); // Empty line for spacing
+    lines
+}
@@ -1,6 +1,8 @@
 // Re-export main TUI components
 pub mod app;
+pub mod chat_cache;
 pub mod rendering;
+pub mod terminal_buffer;
 pub mod types;
 pub mod widgets;
 
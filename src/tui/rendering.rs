@@ -1,20 +1,27 @@
 use std::io;
 use std::time::Duration;
 use anyhow::Result;
+use futures::StreamExt;
 use crossterm::{
-    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyModifiers},
+    event::{DisableMouseCapture, EnableMouseCapture, Event, EventStream, KeyCode, KeyEvent, KeyModifiers},
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
 use ratatui::{
     backend::{Backend, CrosstermBackend},
-    layout::{Constraint, Direction, Layout},
+    layout::{Constraint, Direction, Layout, Rect},
+    widgets::Clear,
     Frame, Terminal,
 };
 use tokio::sync::mpsc;
 
 use crate::context::ContextManager;
 use crate::llm::ModelConfig;
+use crate::llm::rate_limit;
+use crate::secrets::ReviewDecision;
+use crate::validate::FailureAction;
+use crate::trace;
+use crate::crash;
 
 use super::app::App;
 use super::types::{AppMessage, InputMode, FocusedPane};
@@ -28,6 +35,7 @@ pub async fn run_tui(
     enable_raw_mode()?;
     let mut stdout = io::stdout();
     execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
+    crash::set_tui_active(true);
     let backend = CrosstermBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
     
@@ -49,6 +57,7 @@ pub async fn run_tui(
         DisableMouseCapture
     )?;
     terminal.show_cursor()?;
+    crash::set_tui_active(false);
     
     res
 }
@@ -58,294 +67,435 @@ async fn run_app<B: Backend>(
     app: &mut App,
     rx: &mut mpsc::UnboundedReceiver<AppMessage>,
 ) -> Result<()> {
+    let mut events = EventStream::new();
+    // Clock tick keeps the header time and live data fresh while idle
+    let mut tick = tokio::time::interval(Duration::from_secs(1));
+    let mut needs_redraw = true;
+    
     loop {
         // Check if we should quit
         if app.should_quit {
             return Ok(());
         }
         
-        // Update time and live data
-        app.update_time();
-        app.update_context_view();
-        
-        // Draw UI
-        terminal.draw(|f| ui(f, app))?;
+        // Only redraw after something changed
+        if needs_redraw {
+            let _span = trace::span("render.frame");
+            app.update_context_view();
+            terminal.draw(|f| ui(f, app))?;
+            needs_redraw = false;
+        }
         
-        // Handle async messages first
-        while let Ok(msg) = rx.try_recv() {
-            match msg {
-                AppMessage::LlmResponse(prompt, response) => {
-                    app.handle_llm_response(prompt, response).await;
+        tokio::select! {
+            maybe_event = events.next() => match maybe_event {
+                Some(Ok(Event::Key(key))) => {
+                    // Allow most interactions during LLM processing
+                    // Only block sending new messages to prevent conflicts
+                    if handle_key_event(app, key).await? {
+                        return Ok(());
+                    }
+                    needs_redraw = true;
                 }
-                AppMessage::TerminalOutput(output) => {
-                    app.add_terminal_output(output);
+                Some(Ok(Event::Resize(_, _))) => needs_redraw = true,
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return Err(e.into()),
+                None => return Ok(()),
+            },
+            Some(load) = app.file_browser.next_load() => {
+                app.file_browser.apply_load(load);
+                needs_redraw = true;
+            }
+            Some(msg) = rx.recv() => {
+                handle_app_message(app, msg).await;
+                // Drain anything else that queued up before redrawing
+                while let Ok(msg) = rx.try_recv() {
+                    handle_app_message(app, msg).await;
                 }
-                AppMessage::ProcessingComplete => {
-                    app.is_processing = false;
-                    app.status_message = "Ready".to_string();
+                needs_redraw = true;
+            }
+            _ = tick.tick() => {
+                app.update_time();
+                if let Some(notice) = rate_limit::take_notice() {
+                    app.status_message = notice;
                 }
+                needs_redraw = true;
             }
         }
-        
-        // Handle keyboard events
-        if event::poll(Duration::from_millis(100))? {
-            if let Event::Key(key) = event::read()? {
-                // Allow most interactions during LLM processing
-                // Only block sending new messages to prevent conflicts
-                match app.input_mode {
-                    InputMode::Normal => match key.code {
-                        KeyCode::Char('q') => {
-                            if key.modifiers.contains(KeyModifiers::CONTROL) {
-                                return Ok(());
-                            }
-                        }
-                        KeyCode::Char('i') => {
-                            app.input_mode = InputMode::Insert;
-                            app.status_message = "INSERT MODE - Type your message".to_string();
-                        }
-                        KeyCode::Char(':') => {
-                            app.input_mode = InputMode::Command;
-                            app.input = String::new();
-                            app.status_message = "COMMAND MODE".to_string();
-                        }
-                        KeyCode::Char('f') => {
-                            // Only switch to file browser if we're not processing input and input is empty
-                            if !app.is_processing && app.input.is_empty() && app.input_lines.len() <= 1 {
-                                app.input_mode = InputMode::FileBrowser;
-                                app.focused_pane = FocusedPane::FileBrowser;
-                                app.status_message = "FILE BROWSER - Navigate with hjkl, Enter to add file".to_string();
-                            }
-                        }
-                        KeyCode::Char('g') => {
-                            // Check if next key is also 'g' for gg command
-                            if event::poll(Duration::from_millis(500))? {
-                                if let Event::Key(next_key) = event::read()? {
-                                    if next_key.code == KeyCode::Char('g') {
-                                        // gg - go to top
-                                        match app.focused_pane {
-                                            FocusedPane::Chat => app.scroll_offset = 0,
-                                            FocusedPane::Terminal => app.terminal_scroll = 0,
-                                            FocusedPane::Context => app.context_scroll = 0,
-                                            _ => {}
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                        KeyCode::Char('G') => {
-                            // G - go to bottom (set scroll to reasonable max)
-                            match app.focused_pane {
-                                FocusedPane::Chat => app.scroll_offset = 1000, // More reasonable max
-                                FocusedPane::Terminal => app.terminal_scroll = 1000,
-                                FocusedPane::Context => app.context_scroll = 1000,
-                                _ => {}
-                            }
-                        }
-                        KeyCode::Char('?') => {
-                            app.status_message = "Help: :q=quit, i=insert, :=cmd, f=files, Tab=focus, hjkl=nav, gg/G=top/bottom, a=auto-scroll, x=exec, n/p=nav-cmds, c=clear".to_string();
-                        }
-                        KeyCode::Char('a') => {
-                            app.toggle_auto_scroll();
-                        }
-                        KeyCode::Tab => {
-                            // Cycle through panes
-                            app.focused_pane = match app.focused_pane {
-                                FocusedPane::Chat => FocusedPane::Terminal,
-                                FocusedPane::Terminal => FocusedPane::Context,
-                                FocusedPane::Context => if app.show_file_browser { FocusedPane::FileBrowser } else { FocusedPane::Chat },
-                                FocusedPane::FileBrowser => FocusedPane::Chat,
-                            };
-                        }
-                        KeyCode::Up | KeyCode::Char('k') => {
-                            match app.focused_pane {
-                                FocusedPane::Chat => {
-                                    if app.scroll_offset > 0 {
-                                        app.scroll_offset -= 1;
-                                        // Disable auto-scroll when user manually scrolls
-                                        app.auto_scroll_enabled = false;
-                                    }
-                                }
-                                FocusedPane::Terminal => {
-                                    if app.terminal_scroll > 0 {
-                                        app.terminal_scroll -= 1;
-                                    }
-                                }
-                                FocusedPane::Context => {
-                                    if app.context_scroll > 0 {
-                                        app.context_scroll -= 1;
-                                    }
-                                }
-                                _ => {}
-                            }
-                        }
-                        KeyCode::Down | KeyCode::Char('j') => {
-                            match app.focused_pane {
-                                FocusedPane::Chat => {
-                                    app.scroll_offset += 1;
-                                    // Disable auto-scroll when user manually scrolls
-                                    app.auto_scroll_enabled = false;
-                                }
-                                FocusedPane::Terminal => app.terminal_scroll += 1,
-                                FocusedPane::Context => app.context_scroll += 1,
-                                _ => {}
-                            }
-                        }
-                        KeyCode::Left => {
-                            // Cycle through panes backwards
-                            app.focused_pane = match app.focused_pane {
-                                FocusedPane::Chat => if app.show_file_browser { FocusedPane::FileBrowser } else { FocusedPane::Context },
-                                FocusedPane::Terminal => FocusedPane::Chat,
-                                FocusedPane::Context => FocusedPane::Terminal,
-                                FocusedPane::FileBrowser => FocusedPane::Context,
-                            };
-                        }
-                        KeyCode::Right => {
-                            // Cycle through panes forwards (same as Tab)
-                            app.focused_pane = match app.focused_pane {
-                                FocusedPane::Chat => FocusedPane::Terminal,
-                                FocusedPane::Terminal => FocusedPane::Context,
-                                FocusedPane::Context => if app.show_file_browser { FocusedPane::FileBrowser } else { FocusedPane::Chat },
-                                FocusedPane::FileBrowser => FocusedPane::Chat,
-                            };
-                        }
-                        KeyCode::Char('h') => {
-                            // h for scrolling left in content (currently not used but reserved for future horizontal scrolling)
-                        }
-                        KeyCode::Char('l') => {
-                            // l for scrolling right in content (currently not used but reserved for future horizontal scrolling)
-                        }
-                        KeyCode::PageUp => {
-                            match app.focused_pane {
-                                FocusedPane::Chat => {
-                                    app.scroll_offset = app.scroll_offset.saturating_sub(10);
-                                    app.auto_scroll_enabled = false;
-                                }
-                                FocusedPane::Terminal => app.terminal_scroll = app.terminal_scroll.saturating_sub(10),
-                                FocusedPane::Context => app.context_scroll = app.context_scroll.saturating_sub(10),
-                                _ => {}
-                            }
-                        }
-                        KeyCode::PageDown => {
-                            match app.focused_pane {
-                                FocusedPane::Chat => {
-                                    app.scroll_offset += 10;
-                                    app.auto_scroll_enabled = false;
-                                }
-                                FocusedPane::Terminal => app.terminal_scroll += 10,
-                                FocusedPane::Context => app.context_scroll += 10,
-                                _ => {}
-                            }
-                        }
-                        KeyCode::Char('x') => {
-                            // Execute selected command when terminal is focused
-                            if matches!(app.focused_pane, FocusedPane::Terminal) && !app.suggested_commands.is_empty() {
-                                app.execute_selected_command_async().await;
-                            }
-                        }
-                        KeyCode::Char('n') => {
-                            // Navigate to next command when terminal is focused
-                            if matches!(app.focused_pane, FocusedPane::Terminal) && !app.suggested_commands.is_empty() {
-                                app.navigate_commands(1);
-                            }
-                        }
-                        KeyCode::Char('p') => {
-                            // Navigate to previous command when terminal is focused
-                            if matches!(app.focused_pane, FocusedPane::Terminal) && !app.suggested_commands.is_empty() {
-                                app.navigate_commands(-1);
-                            }
-                        }
-                        KeyCode::Char('c') => {
-                            // Clear all commands when terminal is focused
-                            if matches!(app.focused_pane, FocusedPane::Terminal) {
-                                app.suggested_commands.clear();
-                                app.selected_command_index = 0;
-                                app.add_terminal_output("Cleared all suggested commands".to_string());
-                            }
-                        }
-                        _ => {}
-                    },
-                    InputMode::Insert => match key.code {
-                        KeyCode::Esc => {
-                            app.input_mode = InputMode::Normal;
-                            app.clear_input();
-                            app.status_message = "NORMAL MODE".to_string();
-                        }
-                        KeyCode::Enter => {
-                            if !app.is_processing {
-                                // Check if we should auto-continue to next line
-                                if app.should_auto_continue() {
-                                    app.add_new_line();
-                                    app.status_message = "Multi-line mode - Ctrl+D to send, Esc to cancel".to_string();
-                                } else if !app.get_full_input().trim().is_empty() {
-                                    // Send the message
-                                    app.input_mode = InputMode::Normal;
-                                    app.process_user_input(String::new()).await; // Empty string means use full input
-                                }
-                            }
-                        }
-                        KeyCode::Char(c) if key.modifiers.contains(KeyModifiers::CONTROL) && c == 'd' => {
-                            // Ctrl+D to force send multi-line input
-                            if !app.is_processing && !app.get_full_input().trim().is_empty() {
-                                app.input_mode = InputMode::Normal;
-                                app.process_user_input(String::new()).await;
-                            }
-                        }
-                        KeyCode::Char(c) => {
-                            app.input.push(c);
-                        }
-                        KeyCode::Backspace => {
-                            app.input.pop();
-                        }
+    }
+}
+
+async fn handle_app_message(app: &mut App, msg: AppMessage) {
+    match msg {
+        AppMessage::LlmResponse(prompt, response) => {
+            app.handle_llm_response(prompt, response).await;
+        }
+        AppMessage::LlmStreamChunk(chunk) => {
+            app.append_stream_chunk(&chunk);
+        }
+        AppMessage::TerminalOutput(output) => {
+            app.add_terminal_output(output);
+        }
+        AppMessage::Comparison(answers) => {
+            app.add_comparison(answers);
+        }
+        AppMessage::SecretsReview(review) => {
+            app.status_message = "Possible secrets in the prompt - r: redact, s: send anyway, c: cancel".to_string();
+            app.secrets_review = Some(review);
+        }
+        AppMessage::CommandExplanation(command, explanation) => {
+            app.set_command_explanation(&command, explanation);
+        }
+        AppMessage::ProcessingComplete => {
+            // A failed request can leave a partial streamed message behind; keep it as is
+            app.streaming_message = None;
+            app.is_processing = false;
+            app.finish_request();
+            app.status_message = "Ready".to_string();
+            app.send_steering().await;
+        }
+    }
+}
+
+/// Apply a key press to the app state. Returns true when the app should exit.
+async fn handle_key_event(app: &mut App, key: KeyEvent) -> Result<bool> {
+    // A lone 'g' waits for a second 'g'; any other key cancels it
+    let pending_g = std::mem::take(&mut app.pending_g);
+    
+    // The secrets dialog takes every key until it's answered
+    if app.secrets_review.is_some() {
+        match key.code {
+            KeyCode::Char('r') | KeyCode::Enter => app.answer_secrets_review(ReviewDecision::Redact),
+            KeyCode::Char('s') => app.answer_secrets_review(ReviewDecision::SendAnyway),
+            KeyCode::Char('c') | KeyCode::Esc => app.answer_secrets_review(ReviewDecision::Cancel),
+            _ => {}
+        }
+        return Ok(false);
+    }
+    
+    // As does the parse error dialog
+    if app.parse_review.is_some() {
+        match key.code {
+            KeyCode::Char('r') => app.answer_parse_review(FailureAction::Revert).await,
+            KeyCode::Char('f') => app.answer_parse_review(FailureAction::Fix).await,
+            KeyCode::Char('k') | KeyCode::Esc => app.answer_parse_review(FailureAction::Keep).await,
+            _ => {}
+        }
+        return Ok(false);
+    }
+    
+    // So does the diff review, until the edits are applied or discarded
+    if let Some(review) = app.diff_review.as_mut() {
+        match key.code {
+            KeyCode::Char('j') | KeyCode::Down => review.next(),
+            KeyCode::Char('k') | KeyCode::Up => review.previous(),
+            KeyCode::Char('y') => review.decide(true),
+            KeyCode::Char('n') => review.decide(false),
+            KeyCode::Char('Y') => review.decide_block(true),
+            KeyCode::Char('N') => review.decide_block(false),
+            KeyCode::Enter => app.finish_diff_review(true).await,
+            KeyCode::Esc | KeyCode::Char('q') => app.finish_diff_review(false).await,
+            _ => {}
+        }
+        return Ok(false);
+    }
+    
+    match app.input_mode {
+        InputMode::Normal => match key.code {
+            KeyCode::Char('q') => {
+                if key.modifiers.contains(KeyModifiers::CONTROL) {
+                    return Ok(true);
+                }
+            }
+            KeyCode::Char('i') => {
+                app.input_mode = InputMode::Insert;
+                app.status_message = "INSERT MODE - Type your message".to_string();
+            }
+            KeyCode::Char(':') => {
+                app.input_mode = InputMode::Command;
+                app.input = String::new();
+                app.status_message = "COMMAND MODE".to_string();
+            }
+            KeyCode::Char('f') => {
+                // Only switch to file browser if we're not processing input and input is empty
+                if !app.is_processing && app.input.is_empty() && app.input_lines.len() <= 1 {
+                    app.input_mode = InputMode::FileBrowser;
+                    app.focused_pane = FocusedPane::FileBrowser;
+                    app.status_message = "FILE BROWSER - Navigate with hjkl, Enter to add file".to_string();
+                }
+            }
+            KeyCode::Char('g') => {
+                // Second 'g' of gg - go to top
+                if pending_g {
+                    match app.focused_pane {
+                        FocusedPane::Chat => app.scroll_offset = 0,
+                        FocusedPane::Terminal => app.terminal_scroll = 0,
+                        FocusedPane::Context => app.context_scroll = 0,
                         _ => {}
-                    },
-                    InputMode::Command => match key.code {
-                        KeyCode::Esc => {
-                            app.input_mode = InputMode::Normal;
-                            app.input.clear();
-                            app.status_message = "NORMAL MODE".to_string();
-                        }
-                        KeyCode::Enter => {
-                            // Allow most commands during processing, but not LLM requests
-                            let cmd = app.input.clone();
-                            app.input.clear();
-                            app.input_mode = InputMode::Normal;
-                            app.process_command(cmd).await;
+                    }
+                } else {
+                    app.pending_g = true;
+                }
+            }
+            KeyCode::Char('G') => {
+                // G - go to bottom (set scroll to reasonable max)
+                match app.focused_pane {
+                    FocusedPane::Chat => app.scroll_offset = usize::MAX, // Clamped to the last page on draw
+                    FocusedPane::Terminal => app.terminal_scroll = app.terminal_output.len().min(u16::MAX as usize) as u16,
+                    FocusedPane::Context => app.context_scroll = 1000,
+                    _ => {}
+                }
+            }
+            KeyCode::Char('/') => {
+                if matches!(app.focused_pane, FocusedPane::Terminal) {
+                    app.input_mode = InputMode::Search;
+                    app.input = String::new();
+                    app.status_message = "SEARCH - Find in terminal output".to_string();
+                }
+            }
+            KeyCode::Esc if app.terminal_output.search_query().is_some() => {
+                app.terminal_output.clear_search();
+                app.status_message = "Search cleared".to_string();
+            }
+            KeyCode::Char('?') => {
+                app.status_message = "Help: :q=quit, i=insert, :=cmd, f=files, Tab=focus, hjkl=nav, gg/G=top/bottom, a=auto-scroll, x=exec, n/p=nav-cmds, c=clear".to_string();
+            }
+            KeyCode::Char('a') => {
+                app.toggle_auto_scroll();
+            }
+            KeyCode::Tab => {
+                // Cycle through panes
+                app.focused_pane = match app.focused_pane {
+                    FocusedPane::Chat => FocusedPane::Terminal,
+                    FocusedPane::Terminal => FocusedPane::Context,
+                    FocusedPane::Context => if app.show_file_browser { FocusedPane::FileBrowser } else { FocusedPane::Chat },
+                    FocusedPane::FileBrowser => FocusedPane::Chat,
+                };
+            }
+            KeyCode::Up | KeyCode::Char('k') => {
+                match app.focused_pane {
+                    FocusedPane::Chat => {
+                        if app.scroll_offset > 0 {
+                            app.scroll_offset -= 1;
+                            // Disable auto-scroll when user manually scrolls
+                            app.auto_scroll_enabled = false;
                         }
-                        KeyCode::Char(c) => {
-                            app.input.push(c);
+                    }
+                    FocusedPane::Terminal => {
+                        if app.terminal_scroll > 0 {
+                            app.terminal_scroll -= 1;
                         }
-                        KeyCode::Backspace => {
-                            app.input.pop();
+                    }
+                    FocusedPane::Context => {
+                        if app.context_scroll > 0 {
+                            app.context_scroll -= 1;
                         }
-                        _ => {}
-                    },
-                    InputMode::FileBrowser => {
-                        match key.code {
-                            KeyCode::Esc => {
-                                app.input_mode = InputMode::Normal;
-                                app.status_message = "NORMAL MODE".to_string();
-                            }
-                            KeyCode::Enter => {
-                                // Add selected file to context
-                                if let Some(path) = app.file_browser.enter_selected()? {
-                                    if let Err(e) = app.add_file_to_context(path.to_str().unwrap()) {
-                                        app.status_message = format!("Error adding file: {}", e);
-                                    }
-                                }
-                            }
-                            _ => {
-                                // Let file browser handle other keys
-                                app.file_browser.handle_key(key)?;
-                            }
+                    }
+                    _ => {}
+                }
+            }
+            KeyCode::Down | KeyCode::Char('j') => {
+                match app.focused_pane {
+                    FocusedPane::Chat => {
+                        app.scroll_offset += 1;
+                        // Disable auto-scroll when user manually scrolls
+                        app.auto_scroll_enabled = false;
+                    }
+                    FocusedPane::Terminal => app.terminal_scroll += 1,
+                    FocusedPane::Context => app.context_scroll += 1,
+                    _ => {}
+                }
+            }
+            KeyCode::Left => {
+                // Cycle through panes backwards
+                app.focused_pane = match app.focused_pane {
+                    FocusedPane::Chat => if app.show_file_browser { FocusedPane::FileBrowser } else { FocusedPane::Context },
+                    FocusedPane::Terminal => FocusedPane::Chat,
+                    FocusedPane::Context => FocusedPane::Terminal,
+                    FocusedPane::FileBrowser => FocusedPane::Context,
+                };
+            }
+            KeyCode::Right => {
+                // Cycle through panes forwards (same as Tab)
+                app.focused_pane = match app.focused_pane {
+                    FocusedPane::Chat => FocusedPane::Terminal,
+                    FocusedPane::Terminal => FocusedPane::Context,
+                    FocusedPane::Context => if app.show_file_browser { FocusedPane::FileBrowser } else { FocusedPane::Chat },
+                    FocusedPane::FileBrowser => FocusedPane::Chat,
+                };
+            }
+            KeyCode::Char('h') => {
+                // Previous answer of the latest :compare
+                if matches!(app.focused_pane, FocusedPane::Chat) {
+                    app.switch_comparison_tab(-1);
+                }
+            }
+            KeyCode::Char('l') => {
+                // Next answer of the latest :compare
+                if matches!(app.focused_pane, FocusedPane::Chat) {
+                    app.switch_comparison_tab(1);
+                }
+            }
+            KeyCode::PageUp => {
+                match app.focused_pane {
+                    FocusedPane::Chat => {
+                        app.scroll_offset = app.scroll_offset.saturating_sub(10);
+                        app.auto_scroll_enabled = false;
+                    }
+                    FocusedPane::Terminal => app.terminal_scroll = app.terminal_scroll.saturating_sub(10),
+                    FocusedPane::Context => app.context_scroll = app.context_scroll.saturating_sub(10),
+                    _ => {}
+                }
+            }
+            KeyCode::PageDown => {
+                match app.focused_pane {
+                    FocusedPane::Chat => {
+                        app.scroll_offset += 10;
+                        app.auto_scroll_enabled = false;
+                    }
+                    FocusedPane::Terminal => app.terminal_scroll += 10,
+                    FocusedPane::Context => app.context_scroll += 10,
+                    _ => {}
+                }
+            }
+            KeyCode::Char('x') => {
+                // Execute selected command when terminal is focused
+                if matches!(app.focused_pane, FocusedPane::Terminal) && !app.suggested_commands.is_empty() {
+                    app.execute_selected_command_async().await;
+                }
+            }
+            KeyCode::Char('e') if matches!(app.focused_pane, FocusedPane::Terminal) && !app.suggested_commands.is_empty() => {
+                app.explain_selected_command();
+            }
+            KeyCode::Char('n') => {
+                // Next search match, or next command when terminal is focused
+                if matches!(app.focused_pane, FocusedPane::Terminal) && app.terminal_output.search_query().is_some() {
+                    app.navigate_terminal_matches(true);
+                } else if matches!(app.focused_pane, FocusedPane::Terminal) && !app.suggested_commands.is_empty() {
+                    app.navigate_commands(1);
+                }
+            }
+            KeyCode::Char('N') if matches!(app.focused_pane, FocusedPane::Terminal) => {
+                app.navigate_terminal_matches(false);
+            }
+            KeyCode::Char('p') => {
+                // Navigate to previous command when terminal is focused
+                if matches!(app.focused_pane, FocusedPane::Terminal) && !app.suggested_commands.is_empty() {
+                    app.navigate_commands(-1);
+                }
+            }
+            KeyCode::Char('c') => {
+                // Clear all commands when terminal is focused
+                if matches!(app.focused_pane, FocusedPane::Terminal) {
+                    app.suggested_commands.clear();
+                    app.selected_command_index = 0;
+                    app.add_terminal_output("Cleared all suggested commands".to_string());
+                }
+            }
+            _ => {}
+        },
+        InputMode::Insert => match key.code {
+            KeyCode::Esc => {
+                app.input_mode = InputMode::Normal;
+                app.clear_input();
+                app.status_message = "NORMAL MODE".to_string();
+            }
+            KeyCode::Enter if app.is_processing && app.in_flight() => {
+                app.steer().await;
+            }
+            KeyCode::Enter => {
+                if !app.is_processing {
+                    // Check if we should auto-continue to next line
+                    if app.should_auto_continue() {
+                        app.add_new_line();
+                        app.status_message = "Multi-line mode - Ctrl+D to send, Esc to cancel".to_string();
+                    } else if !app.get_full_input().trim().is_empty() {
+                        // Send the message
+                        app.input_mode = InputMode::Normal;
+                        app.process_user_input(String::new()).await; // Empty string means use full input
+                    }
+                }
+            }
+            KeyCode::Char(c) if key.modifiers.contains(KeyModifiers::CONTROL) && c == 'd' => {
+                // Ctrl+D to force send multi-line input
+                if !app.is_processing && !app.get_full_input().trim().is_empty() {
+                    app.input_mode = InputMode::Normal;
+                    app.process_user_input(String::new()).await;
+                }
+            }
+            KeyCode::Char(c) => {
+                app.input.push(c);
+            }
+            KeyCode::Backspace => {
+                app.input.pop();
+            }
+            _ => {}
+        },
+        InputMode::Command => match key.code {
+            KeyCode::Esc => {
+                app.input_mode = InputMode::Normal;
+                app.input.clear();
+                app.status_message = "NORMAL MODE".to_string();
+            }
+            KeyCode::Enter => {
+                // Allow most commands during processing, but not LLM requests
+                let cmd = app.input.clone();
+                app.input.clear();
+                app.input_mode = InputMode::Normal;
+                app.process_command(cmd).await;
+            }
+            KeyCode::Char(c) => {
+                app.input.push(c);
+            }
+            KeyCode::Backspace => {
+                app.input.pop();
+            }
+            _ => {}
+        },
+        InputMode::Search => match key.code {
+            KeyCode::Esc => {
+                app.input_mode = InputMode::Normal;
+                app.input.clear();
+                app.status_message = "NORMAL MODE".to_string();
+            }
+            KeyCode::Enter => {
+                let query = std::mem::take(&mut app.input);
+                app.input_mode = InputMode::Normal;
+                app.search_terminal(&query);
+            }
+            KeyCode::Char(c) => {
+                app.input.push(c);
+            }
+            KeyCode::Backspace => {
+                app.input.pop();
+            }
+            _ => {}
+        },
+        InputMode::FileBrowser => {
+            match key.code {
+                KeyCode::Esc => {
+                    app.input_mode = InputMode::Normal;
+                    app.status_message = "NORMAL MODE".to_string();
+                }
+                KeyCode::Enter => {
+                    // Add selected file to context
+                    if let Some(path) = app.file_browser.enter_selected()? {
+                        if let Err(e) = app.add_file_to_context(path.to_str().unwrap()).await {
+                            app.status_message = format!("Error adding file: {}", e);
                         }
                     }
                 }
+                _ => {
+                    // Let file browser handle other keys
+                    app.file_browser.handle_key(key)?;
+                }
             }
         }
     }
+    
+    Ok(false)
 }
 
-fn ui(f: &mut Frame, app: &App) {
+fn ui(f: &mut Frame, app: &mut App) {
     let chunks = Layout::default()
         .direction(Direction::Vertical)
         .constraints([
@@ -381,18 +531,33 @@ fn ui(f: &mut Frame, app: &App) {
         .constraints([Constraint::Percentage(60), Constraint::Percentage(40)])
         .split(main_chunks[chat_area_idx]);
     
-    // Chat history
-    let chat = widgets::create_chat_view(app);
+    // Chat history, laid out for the pane's inner width (minus borders)
+    app.chat_cache.sync(&app.messages, chat_terminal_chunks[0].width.saturating_sub(2));
+    let chat_height = chat_terminal_chunks[0].height.saturating_sub(2) as usize;
+    app.scroll_offset = app.scroll_offset.min(app.chat_cache.total_lines().saturating_sub(chat_height));
+    let chat = widgets::create_chat_view(app, chat_height);
     f.render_widget(chat, chat_terminal_chunks[0]);
     
-    // Terminal output
-    let terminal = widgets::create_terminal_view(app);
+    // Terminal output, windowed to the pane's inner height (minus borders)
+    let terminal = widgets::create_terminal_view(app, chat_terminal_chunks[1].height.saturating_sub(2) as usize);
     f.render_widget(terminal, chat_terminal_chunks[1]);
     
-    // Context view
+    // Todo list above the context view, while there is one
     let context_idx = if app.show_file_browser { 2 } else { 1 };
+    let mut context_area = main_chunks[context_idx];
+    if !app.todos.is_empty() {
+        let todo_height = (app.todos.items().len() as u16 + 2).min(context_area.height / 2);
+        let todo_chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Length(todo_height), Constraint::Min(0)])
+            .split(context_area);
+        f.render_widget(widgets::create_todo_view(app), todo_chunks[0]);
+        context_area = todo_chunks[1];
+    }
+    
+    // Context view
     let context = widgets::create_context_view(app);
-    f.render_widget(context, main_chunks[context_idx]);
+    f.render_widget(context, context_area);
     
     // Input area
     let input = widgets::create_input_area(app);
@@ -401,4 +566,37 @@ fn ui(f: &mut Frame, app: &App) {
     // Status bar
     let status_bar = widgets::create_status_bar(app);
     f.render_widget(status_bar, chunks[3]);
+    
+    // Diff review, under the secrets dialog
+    if let Some(review) = &app.diff_review {
+        let area = centered_rect(90, f.area().height.saturating_sub(4), f.area());
+        f.render_widget(Clear, area);
+        f.render_widget(widgets::create_diff_review(review, area.height.saturating_sub(2) as usize), area);
+    }
+    
+    if let Some(review) = &app.parse_review {
+        let lines: usize = review.failures.iter().map(|failure| failure.message.lines().count() + 1).sum();
+        let area = centered_rect(80, (lines as u16 + 5).min(f.area().height), f.area());
+        f.render_widget(Clear, area);
+        f.render_widget(widgets::create_parse_dialog(review), area);
+    }
+    
+    // Secrets review dialog, over everything else
+    if let Some(review) = &app.secrets_review {
+        let area = centered_rect(70, (review.findings.len() as u16 + 6).min(f.area().height), f.area());
+        f.render_widget(Clear, area);
+        f.render_widget(widgets::create_secrets_dialog(review), area);
+    }
+}
+
+/// A `width`% wide, `height`-line box in the middle of `area`
+fn centered_rect(width: u16, height: u16, area: Rect) -> Rect {
+    let vertical = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Fill(1), Constraint::Length(height), Constraint::Fill(1)])
+        .split(area);
+    Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage((100 - width) / 2), Constraint::Percentage(width), Constraint::Percentage((100 - width) / 2)])
+        .split(vertical[1])[1]
 }
\ No newline at end of file
@@ -0,0 +1,213 @@
+use std::collections::VecDeque;
+use std::ops::Index;
+
+/// Lines kept in the terminal pane before the oldest start dropping off
+pub const TERMINAL_BUFFER_CAPACITY: usize = 50_000;
+
+/// Terminal pane output, kept as a ring buffer so dropping the oldest line is O(1)
+pub struct TerminalBuffer {
+    lines: VecDeque<String>,
+    capacity: usize,
+    // Lines dropped from the front so far; matches are stored as absolute line
+    // numbers so they stay valid as output scrolls off
+    dropped: usize,
+    search: Option<TerminalSearch>,
+}
+
+struct TerminalSearch {
+    query: String,
+    matches: VecDeque<usize>,
+    current: usize,
+}
+
+impl Default for TerminalBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TerminalBuffer {
+    pub fn new() -> Self {
+        Self::with_capacity(TERMINAL_BUFFER_CAPACITY)
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            lines: VecDeque::new(),
+            capacity: capacity.max(1),
+            dropped: 0,
+            search: None,
+        }
+    }
+
+    pub fn push(&mut self, line: String) {
+        if self.lines.len() == self.capacity {
+            self.lines.pop_front();
+            self.dropped += 1;
+
+            if let Some(search) = &mut self.search {
+                if search.matches.front().is_some_and(|&first| first < self.dropped) {
+                    search.matches.pop_front();
+                    search.current = search.current.saturating_sub(1);
+                }
+            }
+        }
+
+        if let Some(search) = &mut self.search {
+            if contains_ignore_case(&line, &search.query) {
+                search.matches.push_back(self.dropped + self.lines.len());
+            }
+        }
+        self.lines.push_back(line);
+    }
+
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &String> {
+        self.lines.iter()
+    }
+
+    /// Start a case-insensitive search. Returns the line of the most recent match.
+    pub fn search(&mut self, query: &str) -> Option<usize> {
+        if query.is_empty() {
+            self.search = None;
+            return None;
+        }
+
+        let matches: VecDeque<usize> = self.lines
+            .iter()
+            .enumerate()
+            .filter(|(_, line)| contains_ignore_case(line, query))
+            .map(|(i, _)| self.dropped + i)
+            .collect();
+        let current = matches.len().saturating_sub(1);
+
+        self.search = Some(TerminalSearch {
+            query: query.to_string(),
+            matches,
+            current,
+        });
+        self.current_match()
+    }
+
+    pub fn clear_search(&mut self) {
+        self.search = None;
+    }
+
+    pub fn search_query(&self) -> Option<&str> {
+        self.search.as_ref().map(|search| search.query.as_str())
+    }
+
+    /// Move to the next (newer) match, wrapping around. Returns its line.
+    pub fn next_match(&mut self) -> Option<usize> {
+        let search = self.search.as_mut()?;
+        if search.matches.is_empty() {
+            return None;
+        }
+        search.current = (search.current + 1) % search.matches.len();
+        self.current_match()
+    }
+
+    /// Move to the previous (older) match, wrapping around. Returns its line.
+    pub fn prev_match(&mut self) -> Option<usize> {
+        let search = self.search.as_mut()?;
+        if search.matches.is_empty() {
+            return None;
+        }
+        search.current = search.current.checked_sub(1).unwrap_or(search.matches.len() - 1);
+        self.current_match()
+    }
+
+    /// Line index of the selected match
+    pub fn current_match(&self) -> Option<usize> {
+        let search = self.search.as_ref()?;
+        search.matches.get(search.current).map(|&line| line - self.dropped)
+    }
+
+    /// (1-based position of the selected match, total matches)
+    pub fn match_status(&self) -> Option<(usize, usize)> {
+        let search = self.search.as_ref()?;
+        if search.matches.is_empty() {
+            Some((0, 0))
+        } else {
+            Some((search.current + 1, search.matches.len()))
+        }
+    }
+
+    /// Byte ranges of the search query within a line, for highlighting
+    pub fn match_ranges(&self, line: &str) -> Vec<(usize, usize)> {
+        let Some(search) = &self.search else {
+            return Vec::new();
+        };
+
+        // ASCII lowercasing keeps byte offsets identical to the original line
+        let haystack = line.to_ascii_lowercase();
+        let needle = search.query.to_ascii_lowercase();
+        haystack
+            .match_indices(&needle)
+            .map(|(start, matched)| (start, start + matched.len()))
+            .collect()
+    }
+}
+
+impl Index<usize> for TerminalBuffer {
+    type Output = String;
+
+    fn index(&self, index: usize) -> &String {
+        &self.lines[index]
+    }
+}
+
+fn contains_ignore_case(line: &str, query: &str) -> bool {
+    line.to_ascii_lowercase().contains(&query.to_ascii_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ring_buffer_drops_oldest() {
+        let mut buffer = TerminalBuffer::with_capacity(3);
+        for i in 0..5 {
+            buffer.push(format!("line {}", i));
+        }
+
+        assert_eq!(buffer.len(), 3);
+        assert_eq!(buffer[0], "line 2");
+        assert_eq!(buffer[2], "line 4");
+    }
+
+    #[test]
+    fn test_search_navigation() {
+        let mut buffer = TerminalBuffer::with_capacity(10);
+        buffer.push("[EXEC] cargo build".to_string());
+        buffer.push("Compiling kota".to_string());
+        buffer.push("[ERROR] build failed".to_string());
+
+        // Starts at the most recent match and wraps around
+        assert_eq!(buffer.search("BUILD"), Some(2));
+        assert_eq!(buffer.match_status(), Some((2, 2)));
+        assert_eq!(buffer.next_match(), Some(0));
+        assert_eq!(buffer.prev_match(), Some(2));
+        assert_eq!(buffer.match_ranges("cargo build build"), vec![(6, 11), (12, 17)]);
+
+        // New output is searched as it arrives
+        buffer.push("build again".to_string());
+        assert_eq!(buffer.match_status(), Some((2, 3)));
+    }
+
+    #[test]
+    fn test_search_survives_dropped_lines() {
+        let mut buffer = TerminalBuffer::with_capacity(2);
+        buffer.push("match one".to_string());
+        buffer.push("other".to_string());
+        buffer.search("match");
+
+        buffer.push("match two".to_string());
+        assert_eq!(buffer.match_status(), Some((1, 1)));
+        assert_eq!(buffer.current_match(), Some(1));
+    }
+}
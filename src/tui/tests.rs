@@ -2,8 +2,9 @@
 mod tests {
     use crate::context::ContextManager;
     use crate::llm::ModelConfig;
-    use crate::tui::app::App;
-    use crate::tui::types::{InputMode, FocusedPane, CommandStatus};
+    use crate::tui::app::{steered_prompt, App};
+    use crate::tui::chat_cache::ChatLayoutCache;
+    use crate::tui::types::{InputMode, FocusedPane, CommandStatus, MessageContent};
     use crate::tui::widgets::process_markdown_for_display;
 
     #[tokio::test]
@@ -44,6 +45,36 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_comparison_tabs() {
+        let context_manager = ContextManager::new();
+        let model_config = ModelConfig::default();
+        
+        if let Ok(mut app) = App::new(context_manager, model_config) {
+            app.add_comparison(vec![
+                ("Claude/a".to_string(), "First answer".to_string()),
+                ("Gemini/b".to_string(), "Second answer".to_string()),
+            ]);
+            let shown = |app: &App| match &app.messages[0].1 {
+                MessageContent::Text(text) => text.clone(),
+                _ => String::new(),
+            };
+            assert!(shown(&app).contains("**[Claude/a]**"));
+            assert!(shown(&app).contains("First answer"));
+            
+            app.switch_comparison_tab(1);
+            assert!(shown(&app).contains("**[Gemini/b]**"));
+            assert!(shown(&app).contains("Second answer"));
+            
+            // Wraps around in both directions
+            app.switch_comparison_tab(1);
+            assert!(shown(&app).contains("First answer"));
+            app.switch_comparison_tab(-1);
+            assert!(shown(&app).contains("Second answer"));
+            assert_eq!(app.messages.len(), 1);
+        }
+    }
+
     #[tokio::test]
     async fn test_add_suggested_command() {
         let context_manager = ContextManager::new();
@@ -71,6 +102,56 @@ mod tests {
         assert!(processed.contains("  - List item"));
     }
 
+    #[test]
+    fn test_chat_layout_cache() {
+        let mut cache = ChatLayoutCache::new();
+        let mut messages = vec![
+            ("User".to_string(), MessageContent::Text("hello".to_string())),
+            ("KOTA".to_string(), MessageContent::Text("a fairly long reply that needs wrapping".to_string())),
+        ];
+        
+        cache.sync(&messages, 20);
+        // Header + content + spacer for the first, header + two wrapped lines + spacer for the second
+        assert_eq!(cache.total_lines(), 3 + 4);
+        
+        // Narrower pane invalidates and re-wraps everything
+        cache.sync(&messages, 10);
+        assert!(cache.total_lines() > 7);
+        
+        // Changing a message re-lays out just that message
+        messages[1].1 = MessageContent::Text("short".to_string());
+        cache.sync(&messages, 10);
+        assert_eq!(cache.total_lines(), 6);
+        
+        messages.pop();
+        cache.sync(&messages, 10);
+        assert_eq!(cache.total_lines(), 3);
+    }
+
+    #[test]
+    fn test_chat_cache_visible_window() {
+        let mut cache = ChatLayoutCache::new();
+        let long_response: String = (0..500).map(|i| format!("line {}\n", i)).collect();
+        let messages = vec![
+            ("User".to_string(), MessageContent::Text("show me everything".to_string())),
+            ("KOTA".to_string(), MessageContent::Text(long_response)),
+        ];
+        cache.sync(&messages, 40);
+        
+        // Header, text and spacer for each message; nothing is dropped
+        assert_eq!(cache.total_lines(), 3 + 502);
+        
+        let window = cache.visible_lines(4, 3);
+        assert_eq!(window.len(), 3);
+        assert_eq!(window[0].to_string(), "line 0");
+        assert_eq!(window[2].to_string(), "line 2");
+        
+        // The end of the response is reachable
+        let tail = cache.visible_lines(cache.total_lines() - 2, 10);
+        assert_eq!(tail.len(), 2);
+        assert_eq!(tail[0].to_string(), "line 499");
+    }
+
     #[test]
     fn test_input_mode_transitions() {
         // Test that input modes are properly defined
@@ -79,6 +160,7 @@ mod tests {
             InputMode::Insert,
             InputMode::Command,
             InputMode::FileBrowser,
+            InputMode::Search,
         ];
         
         for mode in &modes {
@@ -87,6 +169,7 @@ mod tests {
                 InputMode::Insert => assert!(true),
                 InputMode::Command => assert!(true),
                 InputMode::FileBrowser => assert!(true),
+                InputMode::Search => assert!(true),
             }
         }
     }
@@ -228,4 +311,11 @@ mod tests {
         assert!(!app.has_unmatched_delimiters("\"closed string\""));
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_steered_prompt() {
+        let prompt = steered_prompt("Refactor the parser", "keep the public API unchanged");
+        assert!(prompt.starts_with("Refactor the parser\n\n"));
+        assert!(prompt.ends_with("[While you were answering I added: keep the public API unchanged]"));
+    }
+}
@@ -5,6 +5,7 @@ pub enum InputMode {
     Insert,
     Command,
     FileBrowser,
+    Search,  // Typing a terminal pane search
 }
 
 #[derive(Clone)]
@@ -15,14 +16,60 @@ pub enum FocusedPane {
     FileBrowser,
 }
 
-#[derive(Clone)]
+use tokio::sync::oneshot;
+
+use crate::llm::tools::ToolResponse;
+use crate::secrets::{ReviewDecision, SecretFinding};
+use crate::sr_parser::{FileOperation, SearchReplaceBlock};
+use crate::validate::{FileSnapshots, ParseFailure};
+
 pub enum AppMessage {
-    LlmResponse(String, String), // (original_prompt, response)
+    LlmResponse(String, ToolResponse), // (original_prompt, response)
+    LlmStreamChunk(String),      // Partial response text while streaming
     TerminalOutput(String),
+    Comparison(Vec<(String, String)>), // (model, answer) per compared model
+    SecretsReview(SecretsReview),
+    CommandExplanation(String, String), // (command, explanation)
     ProcessingComplete,
 }
 
+/// Possible secrets found in an outgoing prompt, waiting on the user's decision
+pub struct SecretsReview {
+    pub findings: Vec<SecretFinding>,
+    pub reply: oneshot::Sender<ReviewDecision>,
+}
+
+/// Applied edits that left files unparseable, held back from the auto-commit until the
+/// user reverts or keeps them
+pub struct ParseReview {
+    pub failures: Vec<ParseFailure>,
+    pub applied: Vec<SearchReplaceBlock>,
+    /// Deletes and renames applied with the edits, recorded once the dialog is answered
+    pub operations: Vec<FileOperation>,
+    pub snapshots: FileSnapshots,
+    pub original_prompt: String,
+}
+
+/// Answers from `:compare`, shown one at a time in a chat message
 #[derive(Clone)]
+pub struct ComparisonView {
+    pub message_index: usize,
+    pub answers: Vec<(String, String)>,
+    pub selected: usize,
+}
+
+impl ComparisonView {
+    /// Tab bar with the selected model in brackets, followed by that model's answer
+    pub fn render(&self) -> String {
+        let tabs: Vec<String> = self.answers.iter().enumerate().map(|(i, (model, _))| {
+            if i == self.selected { format!("**[{}]**", model) } else { model.clone() }
+        }).collect();
+        let answer = self.answers.get(self.selected).map(|(_, answer)| answer.as_str()).unwrap_or_default();
+        format!("Compare: {}  (h/l to switch)\n\n{}", tabs.join(" | "), answer)
+    }
+}
+
+#[derive(Clone, Hash)]
 pub enum MessageContent {
     Text(String),
     CollapsedPaste { 
@@ -45,4 +92,6 @@ pub struct CommandSuggestion {
     pub description: Option<String>,
     pub status: CommandStatus,
     pub output: Option<String>,
+    /// What the command does and its risks, once asked for with `e`
+    pub explanation: Option<String>,
 }
\ No newline at end of file
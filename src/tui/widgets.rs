@@ -5,9 +5,14 @@ use ratatui::{
     widgets::{Block, Borders, Paragraph, Wrap, Table, Row, Cell},
 };
 
+use crate::clock::clock;
+use crate::context::Origin;
 use crate::file_browser::FileBrowser;
+use crate::llm::LlmProvider;
+use crate::usage;
+use crate::diff_review::{DiffLine, DiffReview};
 use super::app::App;
-use super::types::{MessageContent, CommandStatus, InputMode, FocusedPane};
+use super::types::{CommandStatus, InputMode, FocusedPane, ParseReview, SecretsReview};
 
 pub fn process_markdown_for_display(content: &str) -> String {
     let mut processed = String::new();
@@ -63,8 +68,10 @@ pub fn create_header(app: &App) -> Paragraph {
         .alignment(Alignment::Center)
 }
 
-pub fn create_chat_view(app: &App) -> Paragraph {
-    let mut lines = Vec::new();
+pub fn create_chat_view(app: &App, height: usize) -> Paragraph {
+    // Lines come pre-wrapped from the layout cache, synced before each draw; only
+    // the visible window is materialized, so long responses are never cut short
+    let mut lines = app.chat_cache.visible_lines(app.scroll_offset, height);
     
     // Debug: Add message count to title
     if app.messages.is_empty() {
@@ -74,37 +81,11 @@ pub fn create_chat_view(app: &App) -> Paragraph {
         ]));
     }
     
-    for (role, content) in &app.messages {
-        let style = if role == "User" {
-            Style::default().fg(Color::Green)
-        } else {
-            Style::default().fg(Color::Cyan)
-        };
-        
-        // Add role header
-        lines.push(Line::from(vec![
-            Span::styled(format!("{}: ", role), style.add_modifier(Modifier::BOLD)),
-        ]));
-        
-        // Process content based on type
-        match content {
-            MessageContent::Text(text) => {
-                let processed_content = process_markdown_for_display(text);
-                for line in processed_content.lines() {
-                    lines.push(Line::from(line.to_string()));
-                }
-            }
-            MessageContent::CollapsedPaste { summary, .. } => {
-                lines.push(Line::from(vec![
-                    Span::styled(summary, Style::default().fg(Color::DarkGray).add_modifier(Modifier::ITALIC)),
-                ]));
-            }
-        }
-        
-        lines.push(Line::from("")); // Empty line for spacing
-    }
-    
-    let title = format!(" Chat History ({} messages) ", app.messages.len());
+    let title = if app.streaming_message.is_some() {
+        format!(" Chat History ({} messages) - KOTA is responding... ", app.messages.len())
+    } else {
+        format!(" Chat History ({} messages) ", app.messages.len())
+    };
     
     Paragraph::new(lines)
         .block(Block::default()
@@ -115,14 +96,21 @@ pub fn create_chat_view(app: &App) -> Paragraph {
             } else {
                 Style::default().fg(Color::Gray)
             }))
-        .wrap(Wrap { trim: true })
-        .scroll((app.scroll_offset, 0))
 }
 
-pub fn create_terminal_view(app: &App) -> Paragraph {
-    let mut lines: Vec<Line> = app.terminal_output
+/// Only the visible window of the terminal buffer is turned into lines, so long
+/// sessions don't slow down every frame. `terminal_scroll` is the first visible line.
+pub fn create_terminal_view(app: &App, height: usize) -> Paragraph {
+    let output = &app.terminal_output;
+    let start = (app.terminal_scroll as usize).min(output.len().saturating_sub(height));
+    let current_match = output.current_match();
+    
+    let mut lines: Vec<Line> = output
         .iter()
-        .map(|s| Line::from(s.as_str()))
+        .enumerate()
+        .skip(start)
+        .take(height)
+        .map(|(i, s)| highlight_matches(s, output.match_ranges(s), current_match == Some(i)))
         .collect();
     
     // Add enhanced command display if there are suggested commands
@@ -162,19 +150,27 @@ pub fn create_terminal_view(app: &App) -> Paragraph {
             lines.push(Line::from(vec![
                 Span::styled(format!("{}{}[{}] {}", prefix, i + 1, status_indicator, cmd.command), style)
             ]));
+            if let (true, Some(explanation)) = (is_selected, &cmd.explanation) {
+                for line in explanation.lines() {
+                    lines.push(Line::from(Span::styled(format!("     {}", line), Style::default().fg(Color::Gray))));
+                }
+            }
         }
         
         lines.push(Line::from(""));
         lines.push(Line::from(vec![
-            Span::styled("Commands: x=execute n=next p=prev c=clear", Style::default().fg(Color::DarkGray))
+            Span::styled("Commands: x=execute e=explain n=next p=prev c=clear", Style::default().fg(Color::DarkGray))
         ]));
     }
     
-    let title = if !app.suggested_commands.is_empty() {
+    let mut title = if !app.suggested_commands.is_empty() {
         format!(" KOTA Terminal ({} commands) ", app.suggested_commands.len())
     } else {
         " KOTA Terminal ".to_string()
     };
+    if let (Some(query), Some((current, total))) = (output.search_query(), output.match_status()) {
+        title.push_str(&format!("[/{} {}/{}] ", query, current, total));
+    }
     
     Paragraph::new(lines)
         .block(Block::default()
@@ -186,7 +182,33 @@ pub fn create_terminal_view(app: &App) -> Paragraph {
                 Style::default().fg(Color::DarkGray)
             }))
         .wrap(Wrap { trim: true })
-        .scroll((app.terminal_scroll, 0))
+}
+
+fn highlight_matches(line: &str, ranges: Vec<(usize, usize)>, is_current: bool) -> Line<'_> {
+    if ranges.is_empty() {
+        return Line::from(line);
+    }
+    
+    let match_style = if is_current {
+        Style::default().bg(Color::Yellow).fg(Color::Black).add_modifier(Modifier::BOLD)
+    } else {
+        Style::default().bg(Color::DarkGray).fg(Color::Yellow)
+    };
+    
+    let mut spans = Vec::new();
+    let mut last = 0;
+    for (start, end) in ranges {
+        if start > last {
+            spans.push(Span::raw(&line[last..start]));
+        }
+        spans.push(Span::styled(&line[start..end], match_style));
+        last = end;
+    }
+    if last < line.len() {
+        spans.push(Span::raw(&line[last..]));
+    }
+    
+    Line::from(spans)
 }
 
 pub fn create_context_view(app: &App) -> Paragraph {
@@ -194,7 +216,7 @@ pub fn create_context_view(app: &App) -> Paragraph {
     
     // Add live data section (compact format)
     content.push_str("=== Live Data ===\n");
-    content.push_str(&format!("Time: {}\n", app.live_data.time));
+    content.push_str(&format!("Time: {} ({})\n", app.live_data.time, app.live_data.timezone));
     content.push_str(&format!("Date: {}\n", app.live_data.date));
     
     // Truncate long paths
@@ -210,14 +232,36 @@ pub fn create_context_view(app: &App) -> Paragraph {
         content.push_str(&format!("Git: {}\n", branch));
     }
     content.push_str(&format!("User: {}\n", app.live_data.system_info.username));
+    if let Some(minutes) = app.live_data.focus_minutes_left {
+        content.push_str(&format!("Focus: {} min left\n", minutes));
+    }
+    if let Some(weather) = &app.live_data.weather {
+        content.push_str(&format!("Weather: {:.0}{}, {}\n", weather.temperature, weather.unit, weather.description));
+    }
     content.push('\n');
     
     // Add context (truncated for display)
-    content.push_str("=== Context ===\n");
+    let mode = if app.context_user_only { ", user-added only" } else { "" };
+    content.push_str(&format!("=== Context ({}{}) ===\n", app.context_usage, mode));
+    for item in &app.context_items {
+        let marker = if item.pinned { "[pin]" } else { "-" };
+        let read_only = if item.read_only { " [ro]" } else { "" };
+        let left_out = if app.context_user_only && item.origin != Origin::User { " (left out)" } else { "" };
+        content.push_str(&format!(
+            "{} {}{} ~{} {} {}{}\n",
+            marker, item.label, read_only, item.tokens, item.origin.label(), clock().time(&item.updated), left_out
+        ));
+    }
+    if app.context_archived > 0 {
+        content.push_str(&format!("({} archived, :rehydrate to list)\n", app.context_archived));
+    }
+    if !app.context_items.is_empty() || app.context_archived > 0 {
+        content.push('\n');
+    }
     let context_preview = if app.context_view.len() > 500 {
         format!("{}...\n[{} more chars]", &app.context_view[..500], app.context_view.len() - 500)
     } else {
-        app.context_view.clone()
+        app.context_view.to_string()
     };
     content.push_str(&context_preview);
     
@@ -234,6 +278,31 @@ pub fn create_context_view(app: &App) -> Paragraph {
         .scroll((app.context_scroll, 0))
 }
 
+/// The model's task list; items tied to an edit or command are checked off when it succeeds
+pub fn create_todo_view(app: &App) -> Paragraph {
+    let lines: Vec<Line> = app.todos.items().iter().map(|item| {
+        if item.done {
+            Line::from(vec![
+                Span::styled("✓ ", Style::default().fg(Color::Green)),
+                Span::styled(item.text.as_str(), Style::default().fg(Color::DarkGray).add_modifier(Modifier::CROSSED_OUT)),
+            ])
+        } else {
+            Line::from(vec![
+                Span::styled("○ ", Style::default().fg(Color::Yellow)),
+                Span::raw(item.text.as_str()),
+            ])
+        }
+    }).collect();
+    
+    let done = app.todos.items().len() - app.todos.remaining();
+    Paragraph::new(lines)
+        .block(Block::default()
+            .borders(Borders::ALL)
+            .title(format!(" Todo {}/{} ", done, app.todos.items().len()))
+            .border_style(Style::default().fg(Color::Gray)))
+        .wrap(Wrap { trim: true })
+}
+
 pub fn create_file_browser(app: &App) -> Table {
     let header = Row::new(vec![
         Cell::from("Name").style(Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD)),
@@ -241,7 +310,7 @@ pub fn create_file_browser(app: &App) -> Table {
         Cell::from("Perm").style(Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD)),
     ]);
     
-    let rows: Vec<Row> = app.file_browser.items
+    let mut rows: Vec<Row> = app.file_browser.items
         .iter()
         .enumerate()
         .map(|(i, item)| {
@@ -274,12 +343,22 @@ pub fn create_file_browser(app: &App) -> Table {
             
             Row::new(vec![
                 Cell::from(name),
-                Cell::from(if item.is_dir { "-".to_string() } else { FileBrowser::format_size(item.size) }),
-                Cell::from(item.permissions.clone()),
+                Cell::from(if item.is_dir {
+                    "-".to_string()
+                } else if !item.metadata_loaded {
+                    "…".to_string()
+                } else {
+                    FileBrowser::format_size(item.size)
+                }),
+                Cell::from(if item.metadata_loaded { item.permissions.clone() } else { "…".to_string() }),
             ]).style(style)
         })
         .collect();
     
+    if let Some(error) = &app.file_browser.load_error {
+        rows.push(Row::new(vec![Cell::from(error.clone())]).style(Style::default().fg(Color::Red)));
+    }
+    
     let widths = [
         Constraint::Min(12),    // Name column - flexible but smaller
         Constraint::Length(6),  // Size column - shorter
@@ -294,10 +373,18 @@ pub fn create_file_browser(app: &App) -> Table {
         dir_str.to_string()
     };
     
+    let status = if app.file_browser.load_error.is_some() {
+        "[ERROR]"
+    } else if app.file_browser.loading {
+        "[loading...]"
+    } else {
+        ""
+    };
     let title = format!(
-        " {} {} ",
+        " {} {}{} ",
         short_dir,
-        if app.file_browser.use_sudo { "[SUDO]" } else { "" }
+        if app.file_browser.use_sudo { "[SUDO]" } else { "" },
+        status
     );
     
     Table::new(rows, widths)
@@ -318,6 +405,7 @@ pub fn create_input_area(app: &App) -> Paragraph {
         InputMode::Insert => ("[I]", Color::Green),
         InputMode::Command => ("[:]", Color::Yellow),
         InputMode::FileBrowser => ("[F]", Color::Magenta),
+        InputMode::Search => ("[/]", Color::LightYellow),
     };
     
     let mut input_lines = Vec::new();
@@ -364,11 +452,13 @@ pub fn create_input_area(app: &App) -> Paragraph {
         
         if matches!(app.input_mode, InputMode::Command) {
             spans.push(Span::raw(":"));
+        } else if matches!(app.input_mode, InputMode::Search) {
+            spans.push(Span::raw("/"));
         }
         
         spans.push(Span::raw(&app.input));
         
-        if matches!(app.input_mode, InputMode::Insert | InputMode::Command) {
+        if matches!(app.input_mode, InputMode::Insert | InputMode::Command | InputMode::Search) {
             spans.push(Span::styled("_", Style::default().add_modifier(Modifier::SLOW_BLINK)));
         }
         
@@ -388,16 +478,143 @@ pub fn create_input_area(app: &App) -> Paragraph {
             .border_style(Style::default().fg(mode_color)))
 }
 
+pub fn create_secrets_dialog(review: &SecretsReview) -> Paragraph {
+    let mut lines = vec![Line::from("The prompt contains what look like secrets:")];
+    for finding in &review.findings {
+        lines.push(Line::from(Span::styled(format!("  - {}", finding), Style::default().fg(Color::Yellow))));
+    }
+    lines.push(Line::from(""));
+    lines.push(Line::from(vec![
+        Span::styled("r", Style::default().fg(Color::Green).add_modifier(Modifier::BOLD)),
+        Span::raw(": redact and send   "),
+        Span::styled("s", Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD)),
+        Span::raw(": send anyway   "),
+        Span::styled("c", Style::default().fg(Color::Red).add_modifier(Modifier::BOLD)),
+        Span::raw(": cancel"),
+    ]));
+    
+    Paragraph::new(lines)
+        .wrap(Wrap { trim: false })
+        .block(Block::default()
+            .borders(Borders::ALL)
+            .title(" Secrets Review ")
+            .border_style(Style::default().fg(Color::Red)))
+}
+
+pub fn create_parse_dialog(review: &ParseReview) -> Paragraph {
+    let mut lines = vec![Line::from("These files no longer parse:")];
+    for failure in &review.failures {
+        lines.push(Line::from(Span::styled(format!("{} ({})", failure.path, failure.tool), Style::default().fg(Color::White).add_modifier(Modifier::BOLD))));
+        for line in failure.message.lines() {
+            lines.push(Line::from(Span::styled(format!("  {}", line), Style::default().fg(Color::Red))));
+        }
+    }
+    lines.push(Line::from(""));
+    lines.push(Line::from(vec![
+        Span::styled("r", Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD)),
+        Span::raw(": revert   "),
+        Span::styled("f", Style::default().fg(Color::Green).add_modifier(Modifier::BOLD)),
+        Span::raw(": keep and ask for a fix   "),
+        Span::styled("k", Style::default().fg(Color::Red).add_modifier(Modifier::BOLD)),
+        Span::raw(": keep"),
+    ]));
+    
+    Paragraph::new(lines)
+        .wrap(Wrap { trim: false })
+        .block(Block::default()
+            .borders(Borders::ALL)
+            .title(" Parse Errors ")
+            .border_style(Style::default().fg(Color::Red)))
+}
+
+/// The selected block of the diff review, scrolled so the selected hunk stays in view
+pub fn create_diff_review(review: &DiffReview, height: usize) -> Paragraph {
+    let mut lines = Vec::new();
+    let mut selected_line = 0;
+    if let Some(block) = review.current() {
+        for line in &block.lines {
+            match line {
+                DiffLine::Same(text) => lines.push(Line::from(Span::styled(format!("  {}", text), Style::default().fg(Color::DarkGray)))),
+                DiffLine::Changed(index) => {
+                    let selected = *index == review.hunk;
+                    if selected {
+                        selected_line = lines.len();
+                    }
+                    let mark = if block.accepted[*index] { "[x]" } else { "[ ]" };
+                    let style = if selected {
+                        Style::default().fg(Color::Black).bg(Color::Yellow).add_modifier(Modifier::BOLD)
+                    } else {
+                        Style::default().fg(Color::Yellow)
+                    };
+                    lines.push(Line::from(Span::styled(format!("{} hunk {}/{}", mark, index + 1, block.hunks.len()), style)));
+                    let hunk = &block.hunks[*index];
+                    for removed in &hunk.removed {
+                        lines.push(Line::from(Span::styled(format!("- {}", removed), Style::default().fg(Color::Red))));
+                    }
+                    for added in &hunk.added {
+                        lines.push(Line::from(Span::styled(format!("+ {}", added), Style::default().fg(Color::Green))));
+                    }
+                }
+            }
+        }
+    } else if let Some((operation, accepted)) = review.current_operation() {
+        let mark = if *accepted { "[x]" } else { "[ ]" };
+        lines.push(Line::from(Span::styled(
+            format!("{} {}", mark, operation.describe()),
+            Style::default().fg(Color::Black).bg(Color::Yellow).add_modifier(Modifier::BOLD),
+        )));
+    }
+    
+    // Keep the selected hunk a third of the way down, leaving room for the key help
+    let visible = height.saturating_sub(2);
+    let skip = selected_line.saturating_sub(visible / 3).min(lines.len().saturating_sub(visible));
+    let mut lines: Vec<Line> = lines.into_iter().skip(skip).take(visible).collect();
+    lines.push(Line::from(""));
+    lines.push(Line::from(vec![
+        Span::styled("j/k", Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD)),
+        Span::raw(": hunks   "),
+        Span::styled("y/n", Style::default().fg(Color::Green).add_modifier(Modifier::BOLD)),
+        Span::raw(": accept/reject   "),
+        Span::styled("Y/N", Style::default().fg(Color::Green).add_modifier(Modifier::BOLD)),
+        Span::raw(": whole block   "),
+        Span::styled("Enter", Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD)),
+        Span::raw(": apply   "),
+        Span::styled("Esc", Style::default().fg(Color::Red).add_modifier(Modifier::BOLD)),
+        Span::raw(": discard"),
+    ]));
+    
+    let (accepted, total) = review.counts();
+    let title = match (review.current(), review.current_operation()) {
+        (Some(block), _) => format!(" Diff Review: {} (edit {}/{}, {} of {} hunks accepted) ", block.block.file_path, review.block + 1, review.blocks.len(), accepted, total),
+        (None, Some((operation, _))) => format!(
+            " Diff Review: {} (file operation {}/{}) ",
+            operation.path(), review.block - review.blocks.len() + 1, review.operations.len()
+        ),
+        (None, None) => " Diff Review ".to_string(),
+    };
+    Paragraph::new(lines)
+        .block(Block::default()
+            .borders(Borders::ALL)
+            .title(title)
+            .border_style(Style::default().fg(Color::Yellow)))
+}
+
 pub fn create_status_bar(app: &App) -> Paragraph {
     let shortcuts = match app.input_mode {
         InputMode::Normal => {
-            if matches!(app.focused_pane, FocusedPane::Terminal) && !app.suggested_commands.is_empty() {
-                "^Q:quit i:insert f:files Tab/←→:focus x:exec n/p:nav c:clear ?:help"
+            if matches!(app.focused_pane, FocusedPane::Terminal) && app.terminal_output.search_query().is_some() {
+                "^Q:quit n/N:next/prev match /:search Esc:clear search ?:help"
+            } else if matches!(app.focused_pane, FocusedPane::Terminal) && !app.suggested_commands.is_empty() {
+                "^Q:quit i:insert f:files Tab/←→:focus x:exec e:explain n/p:nav c:clear /:search ?:help"
+            } else if matches!(app.focused_pane, FocusedPane::Terminal) {
+                "^Q:quit i:insert f:files Tab/←→:focus kj:scroll /:search ?:help"
             } else {
                 "^Q:quit i:insert f:files Tab/←→:focus kj:scroll a:auto-scroll ?:help"
             }
         },
-        InputMode::Insert => if app.is_processing { 
+        InputMode::Insert => if app.is_processing && app.in_flight() {
+            "Enter:queue steering note (!note cancels and re-asks) Esc:normal"
+        } else if app.is_processing { 
             "Processing..." 
         } else if app.is_multi_line_input() {
             "Esc:cancel Ctrl+D:send Enter:newline"
@@ -405,6 +622,7 @@ pub fn create_status_bar(app: &App) -> Paragraph {
             "Esc:normal Enter:send Ctrl+D:force-send"
         },
         InputMode::Command => "Esc:cancel Enter:execute",
+        InputMode::Search => "Esc:cancel Enter:search",
         InputMode::FileBrowser => "hjkl:nav Enter:add .:hidden s:sudo Esc:back",
     };
     
@@ -420,6 +638,25 @@ pub fn create_status_bar(app: &App) -> Paragraph {
         Span::styled("MANUAL", Style::default().fg(Color::Yellow))
     };
     
+    let (session_tokens, session_cost) = usage::session_totals();
+    let usage_indicator = if session_tokens >= 1000 {
+        format!("{:.1}k tok ${:.3}", session_tokens as f64 / 1000.0, session_cost)
+    } else {
+        format!("{} tok ${:.3}", session_tokens, session_cost)
+    };
+    
+    let sampling = app.model_config.sampling.summary();
+    let sampling_indicator = if sampling.is_empty() { String::new() } else { format!(" ({})", sampling) };
+    
+    // Requests go to Ollama while local-only files are in context
+    let local_only_indicator = match &app.local_only_reason {
+        Some(reason) if !matches!(app.model_config.provider, LlmProvider::Ollama) => Span::styled(
+            format!(" [LOCAL ONLY → Ollama: {}]", reason),
+            Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD),
+        ),
+        _ => Span::raw(""),
+    };
+    
     let status = vec![
         Line::from(vec![
             processing_indicator,
@@ -427,12 +664,16 @@ pub fn create_status_bar(app: &App) -> Paragraph {
                 app.model_config.display_name(),
                 Style::default().fg(Color::Green),
             ),
+            Span::styled(sampling_indicator, Style::default().fg(Color::LightGreen)),
+            local_only_indicator,
             Span::raw(" | "),
             Span::styled(
                 format!("{} files", app.live_data.context_file_count),
                 Style::default().fg(Color::Cyan),
             ),
             Span::raw(" | "),
+            Span::styled(usage_indicator, Style::default().fg(Color::Magenta)),
+            Span::raw(" | "),
             auto_scroll_indicator,
             Span::raw(" | "),
             Span::raw(&app.status_message),
@@ -0,0 +1,362 @@
+use anyhow::Result;
+use regex::Regex;
+
+use crate::sr_parser::{FileOperation, SearchReplaceBlock};
+
+// Context lines patch may drop from each end of a hunk that no longer matches as written
+const MAX_FUZZ: usize = 2;
+
+#[derive(Debug, Clone, PartialEq)]
+enum HunkLine {
+    Context(String),
+    Remove(String),
+    Add(String),
+}
+
+/// One `@@` section of a diff
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hunk {
+    /// The 1-based line the hunk starts at in the old file, when the header gives one
+    old_start: Option<usize>,
+    lines: Vec<HunkLine>,
+}
+
+impl Hunk {
+    /// The lines the file has to have: context and removed lines
+    fn old_lines(&self) -> Vec<&str> {
+        self.lines
+            .iter()
+            .filter_map(|line| match line {
+                HunkLine::Context(text) | HunkLine::Remove(text) => Some(text.as_str()),
+                HunkLine::Add(_) => None,
+            })
+            .collect()
+    }
+
+    fn changes(&self) -> bool {
+        self.lines.iter().any(|line| !matches!(line, HunkLine::Context(_)))
+    }
+
+    fn leading_context(&self) -> usize {
+        self.lines.iter().take_while(|line| matches!(line, HunkLine::Context(_))).count()
+    }
+
+    fn trailing_context(&self) -> usize {
+        self.lines.iter().rev().take_while(|line| matches!(line, HunkLine::Context(_))).count()
+    }
+}
+
+/// Everything a diff changes in one file. A missing old path is a new file, a missing new
+/// path a deleted one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FilePatch {
+    pub old_path: Option<String>,
+    pub new_path: Option<String>,
+    hunks: Vec<Hunk>,
+    // Lines of the response the diff took up, header to last hunk line
+    span: (usize, usize),
+}
+
+impl FilePatch {
+    pub fn path(&self) -> &str {
+        self.new_path.as_deref().or(self.old_path.as_deref()).unwrap_or_default()
+    }
+
+    pub fn is_new_file(&self) -> bool {
+        self.old_path.is_none()
+    }
+
+    /// The delete or rename the diff makes, which is applied after its edits
+    pub fn operation(&self) -> Option<FileOperation> {
+        match (&self.old_path, &self.new_path) {
+            (Some(path), None) => Some(FileOperation::Delete { path: path.clone() }),
+            (Some(from), Some(to)) if from != to => Some(FileOperation::Rename { from: from.clone(), to: to.clone() }),
+            _ => None,
+        }
+    }
+
+    /// Whether any hunk adds or removes lines; a rename's diff may have none
+    pub fn changes_content(&self) -> bool {
+        self.hunks.iter().any(Hunk::changes)
+    }
+}
+
+/// The path in a `--- a/src/lib.rs` or `+++ b/src/lib.rs` line; None inside for /dev/null
+fn header_path(line: &str, marker: &str) -> Option<Option<String>> {
+    let path = line.strip_prefix(marker)?.split('\t').next()?.trim();
+    if path.is_empty() {
+        return None;
+    }
+    if path == "/dev/null" {
+        return Some(None);
+    }
+    let path = path.strip_prefix("a/").or_else(|| path.strip_prefix("b/")).unwrap_or(path);
+    Some(Some(path.to_string()))
+}
+
+fn is_file_header(lines: &[&str], i: usize) -> bool {
+    i + 1 < lines.len() && header_path(lines[i], "--- ").is_some() && header_path(lines[i + 1], "+++ ").is_some()
+}
+
+/// Whether a response has a unified diff in it: a `---`/`+++` header followed by a hunk
+pub fn contains_udiff(text: &str) -> bool {
+    let lines: Vec<&str> = text.lines().collect();
+    (0..lines.len()).any(|i| is_file_header(&lines, i) && lines.get(i + 2).is_some_and(|line| line.starts_with("@@")))
+}
+
+/// Every file diff in a response. Hunk headers may leave out line numbers (`@@ ... @@`);
+/// such hunks are found by their text alone.
+pub fn parse_udiff(text: &str) -> Result<Vec<FilePatch>> {
+    let header = Regex::new(r"^@@ -(\d+)(?:,\d+)? \+\d+(?:,\d+)? @@")?;
+    let lines: Vec<&str> = text.lines().collect();
+    let mut patches = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        if !is_file_header(&lines, i) {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        let old_path = header_path(lines[i], "--- ").flatten();
+        let new_path = header_path(lines[i + 1], "+++ ").flatten();
+        i += 2;
+
+        let mut hunks = Vec::new();
+        while i < lines.len() && lines[i].starts_with("@@") {
+            let old_start = header.captures(lines[i]).and_then(|captures| captures[1].parse().ok());
+            i += 1;
+            let mut hunk = Vec::new();
+            // Blank lines without the leading space are taken as context, unless they end the hunk
+            let mut trailing_blank = 0;
+            while i < lines.len() && !lines[i].starts_with("@@") && !lines[i].starts_with("```") && !is_file_header(&lines, i) {
+                let line = lines[i];
+                match line.chars().next() {
+                    Some(' ') => hunk.push(HunkLine::Context(line[1..].to_string())),
+                    Some('-') => hunk.push(HunkLine::Remove(line[1..].to_string())),
+                    Some('+') => hunk.push(HunkLine::Add(line[1..].to_string())),
+                    // "\ No newline at end of file"
+                    Some('\\') => {}
+                    None => hunk.push(HunkLine::Context(String::new())),
+                    _ => break,
+                }
+                trailing_blank = if line.is_empty() { trailing_blank + 1 } else { 0 };
+                i += 1;
+            }
+            hunk.truncate(hunk.len() - trailing_blank);
+            i -= trailing_blank;
+            hunks.push(Hunk { old_start, lines: hunk });
+        }
+        if old_path.is_none() && new_path.is_none() {
+            return Err(anyhow::anyhow!("Diff header at line {} names no file", start + 1));
+        }
+        if hunks.is_empty() {
+            return Err(anyhow::anyhow!("Diff for {} has no @@ hunks", new_path.as_deref().or(old_path.as_deref()).unwrap_or_default()));
+        }
+        patches.push(FilePatch { old_path, new_path, hunks, span: (start, i) });
+    }
+    Ok(patches)
+}
+
+/// The response without its diffs, and without the fences left empty by taking them out
+pub fn strip_udiff(text: &str) -> String {
+    let Ok(patches) = parse_udiff(text) else { return text.to_string() };
+    let lines: Vec<&str> = text.lines().collect();
+    let kept: Vec<&str> = lines
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| !patches.iter().any(|patch| (patch.span.0..patch.span.1).contains(i)))
+        .map(|(_, line)| *line)
+        .collect();
+    let mut stripped = Vec::new();
+    let mut i = 0;
+    while i < kept.len() {
+        let opens_fence = kept[i].trim_start().starts_with("```") && kept[i].trim() != "```";
+        if opens_fence && kept.get(i + 1).is_some_and(|line| line.trim() == "```") {
+            i += 2;
+            continue;
+        }
+        stripped.push(kept[i]);
+        i += 1;
+    }
+    stripped.join("\n")
+}
+
+/// Where `old` sits in `lines`, searching outward from `hint` but not before `floor`.
+/// Lines compare exactly, then ignoring trailing whitespace.
+fn find_lines(lines: &[String], old: &[&str], hint: usize, floor: usize) -> Option<usize> {
+    if lines.len() < old.len() || floor > lines.len() - old.len() {
+        return None;
+    }
+    let last = lines.len() - old.len();
+    let hint = hint.clamp(floor, last);
+    let matches_at = |pos: usize, loose: bool| {
+        old.iter().enumerate().all(|(k, line)| {
+            let actual = &lines[pos + k];
+            if loose { actual.trim_end() == line.trim_end() } else { actual == line }
+        })
+    };
+    for loose in [false, true] {
+        for distance in 0..=(last - floor) {
+            let candidates = [hint.checked_add(distance), hint.checked_sub(distance)];
+            for pos in candidates.into_iter().flatten().filter(|pos| (floor..=last).contains(pos)) {
+                if matches_at(pos, loose) {
+                    return Some(pos);
+                }
+            }
+        }
+    }
+    None
+}
+
+/// Apply a file's hunks to `content` the way patch does: each hunk is looked for near the line
+/// its header gives, allowing for the lines earlier hunks added or removed, and if it doesn't
+/// match, again with up to two of its context lines dropped from each end. Each applied hunk
+/// becomes a search/replace block whose search text first occurs where the hunk applies, so
+/// it goes through the usual review and apply path.
+/// A renamed file's blocks edit it at its old path, since edits apply before renames.
+pub fn to_blocks(patch: &FilePatch, content: &str) -> Result<Vec<SearchReplaceBlock>> {
+    let Some(new_path) = &patch.new_path else {
+        return Err(anyhow::anyhow!("the diff deletes {}; that's a file operation, not an edit", patch.path()));
+    };
+    let path = patch.old_path.clone().unwrap_or_else(|| new_path.clone());
+    if patch.is_new_file() {
+        let added: Vec<&str> = patch
+            .hunks
+            .iter()
+            .flat_map(|hunk| &hunk.lines)
+            .filter_map(|line| match line {
+                HunkLine::Add(text) => Some(text.as_str()),
+                _ => None,
+            })
+            .collect();
+        return Ok(vec![SearchReplaceBlock { file_path: path, search_lines: String::new(), replace_lines: added.join("\n") }]);
+    }
+
+    let mut current: Vec<String> = content.lines().map(str::to_string).collect();
+    let mut blocks = Vec::new();
+    // Lines added minus lines removed by the hunks so far, to adjust later headers
+    let mut shift: isize = 0;
+    let mut floor = 0;
+    for (number, hunk) in patch.hunks.iter().enumerate() {
+        if !hunk.changes() {
+            continue;
+        }
+        let old = hunk.old_lines();
+        let located = (0..=MAX_FUZZ).find_map(|fuzz| {
+            let lead = fuzz.min(hunk.leading_context());
+            let trail = fuzz.min(hunk.trailing_context());
+            if fuzz > 0 && lead + trail == 0 {
+                return None;
+            }
+            let hint = match hunk.old_start {
+                Some(start) => (start as isize - 1 + shift + lead as isize).max(0) as usize,
+                None => floor,
+            };
+            find_lines(&current, &old[lead..old.len() - trail], hint, floor).map(|pos| (pos, lead, trail))
+        });
+        let Some((pos, lead, trail)) = located else {
+            return Err(anyhow::anyhow!("hunk {} doesn't match {} as it is now", number + 1, path));
+        };
+
+        // The hunk's own lines, less any context dropped, with context taken from the file
+        // so whitespace it differed in is kept
+        let kept = &hunk.lines[lead..hunk.lines.len() - trail];
+        let mut line = pos;
+        let mut replacement = Vec::new();
+        for hunk_line in kept {
+            match hunk_line {
+                HunkLine::Context(_) => {
+                    replacement.push(current[line].clone());
+                    line += 1;
+                }
+                HunkLine::Remove(_) => line += 1,
+                HunkLine::Add(text) => replacement.push(text.clone()),
+            }
+        }
+        let end = line;
+
+        // Widen the search upward until its first occurrence is this one
+        let text = current.join("\n");
+        let offset_of = |index: usize| current[..index].iter().map(|line| line.len() + 1).sum::<usize>();
+        let mut start = pos;
+        let search = loop {
+            let search = current[start..end].join("\n");
+            if start == 0 || text.find(&search) == Some(offset_of(start)) {
+                break search;
+            }
+            start -= 1;
+        };
+        let mut replace = current[start..pos].to_vec();
+        replace.extend(replacement.iter().cloned());
+        blocks.push(SearchReplaceBlock { file_path: path.clone(), search_lines: search, replace_lines: replace.join("\n") });
+
+        shift += replacement.len() as isize - (end - pos) as isize;
+        floor = pos + replacement.len();
+        current.splice(pos..end, replacement);
+    }
+    if blocks.is_empty() {
+        return Err(anyhow::anyhow!("the diff for {} changes nothing", path));
+    }
+    Ok(blocks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(content: &str, blocks: &[SearchReplaceBlock]) -> String {
+        blocks.iter().fold(content.to_string(), |content, block| content.replacen(&block.search_lines, &block.replace_lines, 1))
+    }
+
+    #[test]
+    fn test_parse_and_strip() {
+        let text = "Here's the fix:\n```diff\n--- a/src/lib.rs\n+++ b/src/lib.rs\n@@ -1,3 +1,3 @@\n fn a() {}\n-fn b() {}\n+fn b() { a(); }\n\n fn c() {}\n```\nThen run the tests.";
+        assert!(contains_udiff(text));
+        assert!(!contains_udiff("--- a list\n+++ not a diff"));
+        let patches = parse_udiff(text).unwrap();
+        assert_eq!(patches.len(), 1);
+        assert_eq!(patches[0].path(), "src/lib.rs");
+        assert_eq!(patches[0].hunks[0].old_start, Some(1));
+        assert_eq!(patches[0].hunks[0].old_lines(), vec!["fn a() {}", "fn b() {}", "", "fn c() {}"]);
+        assert_eq!(strip_udiff(text), "Here's the fix:\nThen run the tests.");
+
+        let new_file = parse_udiff("--- /dev/null\n+++ b/notes.md\n@@ -0,0 +1,2 @@\n+# Notes\n+- one\n").unwrap();
+        assert!(new_file[0].is_new_file());
+        let blocks = to_blocks(&new_file[0], "").unwrap();
+        assert_eq!((blocks[0].search_lines.as_str(), blocks[0].replace_lines.as_str()), ("", "# Notes\n- one"));
+        let deleted = parse_udiff("--- a/old.rs\n+++ /dev/null\n@@ -1 +0,0 @@\n-fn old() {}\n").unwrap();
+        assert!(to_blocks(&deleted[0], "fn old() {}\n").is_err());
+        assert_eq!(deleted[0].operation(), Some(FileOperation::Delete { path: "old.rs".to_string() }));
+
+        let renamed = parse_udiff("--- a/src/util.rs\n+++ b/src/util/mod.rs\n@@ -1 +1 @@\n-fn a() {}\n+pub fn a() {}\n").unwrap();
+        assert_eq!(renamed[0].operation(), Some(FileOperation::Rename { from: "src/util.rs".to_string(), to: "src/util/mod.rs".to_string() }));
+        assert_eq!(to_blocks(&renamed[0], "fn a() {}\n").unwrap()[0].file_path, "src/util.rs");
+    }
+
+    #[test]
+    fn test_hunks_apply_with_offsets_and_fuzz() {
+        let content = "// header\n// added since\nfn a() {}\n\nfn b() {\n    1\n}\n\nfn c() {\n    2\n}\n";
+        // Line numbers are off by one and the second hunk's last context line is wrong
+        let diff = "--- a/src/lib.rs\n+++ b/src/lib.rs\n@@ -4,3 +4,3 @@\n fn b() {\n-    1\n+    10\n }\n@@ -8,4 +8,5 @@\n fn c() {\n-    2\n+    20\n+    21\n }\n// gone\n";
+        let patch = &parse_udiff(diff).unwrap()[0];
+        let blocks = to_blocks(patch, content).unwrap();
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(apply(content, &blocks), "// header\n// added since\nfn a() {}\n\nfn b() {\n    10\n}\n\nfn c() {\n    20\n    21\n}\n");
+
+        let stale = "--- a/src/lib.rs\n+++ b/src/lib.rs\n@@ -1,2 +1,2 @@\n fn x() {\n-    3\n+    4\n";
+        assert!(to_blocks(&parse_udiff(stale).unwrap()[0], content).is_err());
+    }
+
+    #[test]
+    fn test_repeated_text_gets_unique_search() {
+        let content = "fn a() {\n    done();\n}\nfn b() {\n    done();\n}\n";
+        let diff = "--- a/x.rs\n+++ b/x.rs\n@@ ... @@\n fn b() {\n-    done();\n+    finish();\n";
+        let blocks = to_blocks(&parse_udiff(diff).unwrap()[0], content).unwrap();
+        assert_eq!(apply(content, &blocks), "fn a() {\n    done();\n}\nfn b() {\n    finish();\n}\n");
+
+        let no_context = "--- a/x.rs\n+++ b/x.rs\n@@ -5 +5 @@\n-    done();\n+    finish();\n";
+        let blocks = to_blocks(&parse_udiff(no_context).unwrap()[0], content).unwrap();
+        assert!(blocks[0].search_lines.starts_with("fn b() {"));
+        assert_eq!(apply(content, &blocks), "fn a() {\n    done();\n}\nfn b() {\n    finish();\n}\n");
+    }
+}
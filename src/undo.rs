@@ -0,0 +1,208 @@
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+
+use anyhow::{Context, Result};
+
+use crate::validate::FileSnapshots;
+
+// Batches kept for /undo; older ones can no longer be undone
+const MAX_UNDO: usize = 20;
+
+#[derive(Debug, Clone, PartialEq)]
+struct FileChange {
+    path: String,
+    /// None when the file didn't exist
+    before: Option<String>,
+    after: Option<String>,
+}
+
+/// The files one response's applied edits changed, as they were before and after, undone and
+/// redone as a unit
+#[derive(Debug, Clone, PartialEq)]
+pub struct EditBatch {
+    pub prompt: String,
+    files: Vec<FileChange>,
+}
+
+impl EditBatch {
+    /// The request the edits answered, shortened to one line
+    pub fn label(&self) -> String {
+        let line = self.prompt.lines().next().unwrap_or_default().trim();
+        if line.chars().count() > 60 {
+            format!("{}...", line.chars().take(57).collect::<String>())
+        } else {
+            line.to_string()
+        }
+    }
+
+    pub fn paths(&self) -> Vec<String> {
+        self.files.iter().map(|change| change.path.clone()).collect()
+    }
+
+    /// Files whose contents aren't what `expected` picks from the batch; writing over them would
+    /// lose changes made since
+    fn changed_since(&self, expected: fn(&FileChange) -> &Option<String>) -> Vec<String> {
+        self.files
+            .iter()
+            .filter(|change| read(&change.path) != *expected(change))
+            .map(|change| change.path.clone())
+            .collect()
+    }
+
+    fn write(&self, contents: fn(&FileChange) -> &Option<String>) -> Result<()> {
+        for change in &self.files {
+            match contents(change) {
+                Some(content) => {
+                    if let Some(parent) = Path::new(&change.path).parent().filter(|parent| !parent.as_os_str().is_empty()) {
+                        fs::create_dir_all(parent)?;
+                    }
+                    fs::write(&change.path, content).with_context(|| format!("Failed to restore {}", change.path))?;
+                }
+                None if Path::new(&change.path).exists() => {
+                    fs::remove_file(&change.path).with_context(|| format!("Failed to remove {}", change.path))?;
+                }
+                None => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+fn read(path: &str) -> Option<String> {
+    fs::read_to_string(path).ok()
+}
+
+fn before(change: &FileChange) -> &Option<String> {
+    &change.before
+}
+
+fn after(change: &FileChange) -> &Option<String> {
+    &change.after
+}
+
+/// What `/undo` or `/redo` did
+pub struct Restored {
+    pub batch: EditBatch,
+    /// Batches left to undo, or to redo
+    pub remaining: usize,
+}
+
+struct UndoStack {
+    undo: Vec<EditBatch>,
+    redo: Vec<EditBatch>,
+}
+
+impl UndoStack {
+    const fn new() -> Self {
+        Self { undo: Vec::new(), redo: Vec::new() }
+    }
+
+    /// A new batch of edits; anything undone before it can't be redone any more
+    fn push(&mut self, batch: EditBatch) {
+        self.undo.push(batch);
+        let excess = self.undo.len().saturating_sub(MAX_UNDO);
+        self.undo.drain(..excess);
+        self.redo.clear();
+    }
+
+    fn undo(&mut self) -> Result<Restored> {
+        let batch = self.undo.last().ok_or_else(|| anyhow::anyhow!("Nothing to undo"))?;
+        let changed = batch.changed_since(after);
+        if !changed.is_empty() {
+            return Err(anyhow::anyhow!("Not undoing: {} changed since the edits were applied, and undoing would lose that", changed.join(", ")));
+        }
+        batch.write(before)?;
+        let batch = self.undo.pop().expect("checked above");
+        self.redo.push(batch.clone());
+        Ok(Restored { batch, remaining: self.undo.len() })
+    }
+
+    fn redo(&mut self) -> Result<Restored> {
+        let batch = self.redo.last().ok_or_else(|| anyhow::anyhow!("Nothing to redo"))?;
+        let changed = batch.changed_since(before);
+        if !changed.is_empty() {
+            return Err(anyhow::anyhow!("Not redoing: {} changed since the edits were undone, and redoing would lose that", changed.join(", ")));
+        }
+        batch.write(after)?;
+        let batch = self.redo.pop().expect("checked above");
+        self.undo.push(batch.clone());
+        Ok(Restored { batch, remaining: self.redo.len() })
+    }
+}
+
+static STACK: Mutex<UndoStack> = Mutex::new(UndoStack::new());
+
+/// Remember the edits just applied to `files` as one undo step, using the copies `snapshots`
+/// took before each file was first changed
+pub fn record(prompt: &str, snapshots: &FileSnapshots, files: &[String]) {
+    let files: Vec<FileChange> = files
+        .iter()
+        .filter_map(|path| {
+            let before = snapshots.original(path)?;
+            let after = read(path);
+            (before != after).then(|| FileChange { path: path.clone(), before, after })
+        })
+        .collect();
+    if files.is_empty() {
+        return;
+    }
+    STACK.lock().unwrap_or_else(|e| e.into_inner()).push(EditBatch { prompt: prompt.to_string(), files });
+}
+
+/// Put the files of the last applied batch back as they were before it
+pub fn undo() -> Result<Restored> {
+    STACK.lock().unwrap_or_else(|e| e.into_inner()).undo()
+}
+
+/// Apply the last undone batch again
+pub fn redo() -> Result<Restored> {
+    STACK.lock().unwrap_or_else(|e| e.into_inner()).redo()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_undo_and_redo_batches() {
+        let dir = TempDir::new().unwrap();
+        let edited = dir.path().join("a.rs").to_string_lossy().to_string();
+        let created = dir.path().join("new/b.rs").to_string_lossy().to_string();
+        fs::write(&edited, "old\n").unwrap();
+
+        let mut snapshots = FileSnapshots::default();
+        snapshots.record(&edited);
+        snapshots.record(&created);
+        fs::write(&edited, "new\n").unwrap();
+        fs::create_dir_all(dir.path().join("new")).unwrap();
+        fs::write(&created, "fn b() {}\n").unwrap();
+
+        let files = vec![edited.clone(), created.clone()];
+        let batch = EditBatch {
+            prompt: "Split it".to_string(),
+            files: files.iter().map(|path| FileChange { path: path.clone(), before: snapshots.original(path).unwrap(), after: read(path) }).collect(),
+        };
+        let mut stack = UndoStack::new();
+        stack.push(batch);
+
+        let undone = stack.undo().unwrap();
+        assert_eq!(undone.batch.paths(), files);
+        assert_eq!(undone.remaining, 0);
+        assert_eq!(fs::read_to_string(&edited).unwrap(), "old\n");
+        assert!(!Path::new(&created).exists());
+        assert!(stack.undo().is_err());
+
+        stack.redo().unwrap();
+        assert_eq!(fs::read_to_string(&edited).unwrap(), "new\n");
+        assert_eq!(fs::read_to_string(&created).unwrap(), "fn b() {}\n");
+
+        // A file changed by hand since isn't written over
+        fs::write(&edited, "by hand\n").unwrap();
+        let error = stack.undo().err().unwrap().to_string();
+        assert!(error.contains("a.rs changed since"));
+        assert_eq!(fs::read_to_string(&edited).unwrap(), "by hand\n");
+        assert_eq!(fs::read_to_string(&created).unwrap(), "fn b() {}\n");
+    }
+}
@@ -0,0 +1,255 @@
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use colored::*;
+use reqwest::Client;
+use serde::Deserialize;
+
+use crate::prompts::PromptsConfig;
+use crate::render_markdown;
+
+// Release notes longer than this are cut short before confirming
+const CHANGELOG_SUMMARY_LINES: usize = 30;
+const UPDATE_TIMEOUT_SECS: u64 = 300;
+
+#[derive(Debug, Deserialize)]
+struct Release {
+    tag_name: String,
+    #[serde(default)]
+    body: Option<String>,
+    #[serde(default)]
+    assets: Vec<Asset>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Asset {
+    name: String,
+    browser_download_url: String,
+}
+
+/// `kota update [--check] [--yes]`: install the latest GitHub release over this binary.
+/// The download must carry a valid minisign signature from `[update] public_key`, and
+/// replaces the running binary with a rename so it's never left half-written.
+pub async fn run(args: &[String]) -> Result<()> {
+    let check_only = args.iter().any(|arg| arg == "--check");
+    let assume_yes = args.iter().any(|arg| arg == "--yes" || arg == "-y");
+    let config = PromptsConfig::load().unwrap_or_default().update;
+    let client = Client::builder()
+        .timeout(Duration::from_secs(UPDATE_TIMEOUT_SECS))
+        .user_agent(format!("kota/{}", env!("CARGO_PKG_VERSION")))
+        .build()
+        .context("Failed to create HTTP client")?;
+
+    println!("{}", "Checking for updates...".dimmed());
+    let release = latest_release(&client, &config.repo).await?;
+    let current = env!("CARGO_PKG_VERSION");
+    if !is_newer(&release.tag_name, current) {
+        println!("KOTA {} is up to date (latest release is {})", current, release.tag_name);
+        return Ok(());
+    }
+
+    println!("{} {} -> {}", "Update available:".bright_green().bold(), current, release.tag_name);
+    println!();
+    render_markdown(&summarize_changelog(release.body.as_deref().unwrap_or("No release notes."), CHANGELOG_SUMMARY_LINES))?;
+    println!();
+    if check_only {
+        println!("Run `kota update` to install it");
+        return Ok(());
+    }
+
+    let name = asset_name(env::consts::OS, env::consts::ARCH);
+    let binary = find_asset(&release.assets, &name)
+        .ok_or_else(|| anyhow::anyhow!("Release {} has no build for this platform ({})", release.tag_name, name))?;
+    let signature = find_asset(&release.assets, &format!("{}.minisig", name))
+        .ok_or_else(|| anyhow::anyhow!("Release {} has no signature for {}; refusing to install it", release.tag_name, name))?;
+    let public_key = config.public_key.as_deref().map(str::trim).filter(|key| !key.is_empty()).ok_or_else(|| {
+        anyhow::anyhow!("Set [update] public_key in prompts.toml to the release signing key so downloads can be verified")
+    })?;
+
+    if !assume_yes && !confirm(&format!("Install KOTA {}? [y/N] ", release.tag_name))? {
+        println!("Update cancelled");
+        return Ok(());
+    }
+
+    let exe = env::current_exe().context("Can't find the running KOTA binary")?;
+    let staged = staging_path(&exe);
+    let sig_path = staged.with_extension("minisig");
+    let result = async {
+        download(&client, binary, &staged).await?;
+        download(&client, signature, &sig_path).await?;
+        verify_signature(&staged, &sig_path, public_key)?;
+        swap_binary(&staged, &exe)
+    }
+    .await;
+    let _ = fs::remove_file(&sig_path);
+    if result.is_err() {
+        let _ = fs::remove_file(&staged);
+    }
+    result?;
+
+    println!("{} KOTA {} installed at {}", "Updated:".bright_green().bold(), release.tag_name, exe.display());
+    Ok(())
+}
+
+async fn latest_release(client: &Client, repo: &str) -> Result<Release> {
+    let url = format!("https://api.github.com/repos/{}/releases/latest", repo);
+    let response = client
+        .get(&url)
+        .header("Accept", "application/vnd.github+json")
+        .send()
+        .await
+        .with_context(|| format!("Failed to reach GitHub for {}", repo))?;
+    if !response.status().is_success() {
+        return Err(anyhow::anyhow!("GitHub returned {} for the latest release of {}", response.status(), repo));
+    }
+    response.json().await.context("Failed to parse the GitHub release")
+}
+
+async fn download(client: &Client, asset: &Asset, path: &Path) -> Result<()> {
+    println!("{} {}", "Downloading".dimmed(), asset.name);
+    let response = client
+        .get(&asset.browser_download_url)
+        .send()
+        .await
+        .with_context(|| format!("Failed to download {}", asset.name))?;
+    if !response.status().is_success() {
+        return Err(anyhow::anyhow!("Download of {} failed with status {}", asset.name, response.status()));
+    }
+    let bytes = response.bytes().await.with_context(|| format!("Failed to download {}", asset.name))?;
+    fs::write(path, &bytes).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+fn verify_signature(binary: &Path, signature: &Path, public_key: &str) -> Result<()> {
+    let output = Command::new("minisign")
+        .arg("-V")
+        .arg("-P")
+        .arg(public_key)
+        .arg("-m")
+        .arg(binary)
+        .arg("-x")
+        .arg(signature)
+        .output()
+        .context("Failed to run minisign; install it to verify updates")?;
+    if !output.status.success() {
+        return Err(anyhow::anyhow!(
+            "Signature check failed, not installing: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    println!("{}", "Signature verified".green());
+    Ok(())
+}
+
+/// Make the staged binary executable and rename it over the running one. Renames within a
+/// directory are atomic on Unix; Windows can't replace a running binary, so it's moved aside first.
+fn swap_binary(staged: &Path, exe: &Path) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(staged, fs::Permissions::from_mode(0o755))
+            .with_context(|| format!("Failed to make {} executable", staged.display()))?;
+    }
+    #[cfg(windows)]
+    let old = exe.with_extension("old.exe");
+    #[cfg(windows)]
+    {
+        let _ = fs::remove_file(&old);
+        fs::rename(exe, &old).with_context(|| format!("Failed to move {} aside", exe.display()))?;
+    }
+    let result = fs::rename(staged, exe);
+    // Put the old binary back so kota is still installed
+    #[cfg(windows)]
+    if result.is_err() {
+        if let Err(e) = fs::rename(&old, exe) {
+            eprintln!("Warning: Failed to restore {} from {}: {}", exe.display(), old.display(), e);
+        }
+    }
+    result.with_context(|| format!("Failed to replace {}; check you can write to it", exe.display()))
+}
+
+/// Next to the binary, so the final rename doesn't cross filesystems
+fn staging_path(exe: &Path) -> PathBuf {
+    exe.with_file_name(format!(".kota-update-{}", std::process::id()))
+}
+
+fn confirm(question: &str) -> Result<bool> {
+    print!("{}", question.bright_white());
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// The release asset built for this platform, e.g. "kota-linux-x86_64"
+fn asset_name(os: &str, arch: &str) -> String {
+    let extension = if os == "windows" { ".exe" } else { "" };
+    format!("kota-{}-{}{}", os, arch, extension)
+}
+
+fn find_asset<'a>(assets: &'a [Asset], name: &str) -> Option<&'a Asset> {
+    assets.iter().find(|asset| asset.name == name)
+}
+
+/// "v1.2.3" or "1.2.3-beta" as numbers; missing parts count as 0
+fn parse_version(version: &str) -> Option<(u64, u64, u64)> {
+    let version = version.trim().trim_start_matches('v');
+    let core = version.split(['-', '+']).next()?;
+    let mut parts = core.split('.').map(|part| part.parse::<u64>());
+    let major = parts.next()?.ok()?;
+    let minor = parts.next().unwrap_or(Ok(0)).ok()?;
+    let patch = parts.next().unwrap_or(Ok(0)).ok()?;
+    Some((major, minor, patch))
+}
+
+fn is_newer(tag: &str, current: &str) -> bool {
+    match (parse_version(tag), parse_version(current)) {
+        (Some(tag), Some(current)) => tag > current,
+        _ => false,
+    }
+}
+
+/// The first `max_lines` lines of the release notes, noting how many more there are
+fn summarize_changelog(body: &str, max_lines: usize) -> String {
+    let lines: Vec<&str> = body.trim().lines().collect();
+    if lines.len() <= max_lines {
+        return lines.join("\n");
+    }
+    format!("{}\n\n*...and {} more lines in the release notes*", lines[..max_lines].join("\n"), lines.len() - max_lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_versions() {
+        assert_eq!(parse_version("v1.2.3"), Some((1, 2, 3)));
+        assert_eq!(parse_version("0.4.0-beta.1"), Some((0, 4, 0)));
+        assert_eq!(parse_version("2"), Some((2, 0, 0)));
+        assert_eq!(parse_version("nightly"), None);
+        assert!(is_newer("v0.2.0", "0.1.9"));
+        assert!(!is_newer("v0.1.0", "0.1.0"));
+        assert!(!is_newer("nightly", "0.1.0"));
+    }
+
+    #[test]
+    fn test_assets_and_changelog() {
+        assert_eq!(asset_name("linux", "x86_64"), "kota-linux-x86_64");
+        assert_eq!(asset_name("windows", "x86_64"), "kota-windows-x86_64.exe");
+        let assets = vec![
+            Asset { name: "kota-linux-x86_64".to_string(), browser_download_url: "https://example.com/a".to_string() },
+            Asset { name: "kota-linux-x86_64.minisig".to_string(), browser_download_url: "https://example.com/a.minisig".to_string() },
+        ];
+        assert_eq!(find_asset(&assets, "kota-linux-x86_64.minisig").map(|asset| asset.browser_download_url.as_str()), Some("https://example.com/a.minisig"));
+        assert!(find_asset(&assets, "kota-macos-aarch64").is_none());
+
+        assert_eq!(summarize_changelog("- one\n- two\n", 5), "- one\n- two");
+        assert!(summarize_changelog("a\nb\nc\nd", 2).ends_with("*...and 2 more lines in the release notes*"));
+        assert_eq!(staging_path(Path::new("/usr/local/bin/kota")), PathBuf::from(format!("/usr/local/bin/.kota-update-{}", std::process::id())));
+    }
+}
@@ -0,0 +1,367 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use chrono::{DateTime, Datelike, Duration, Utc};
+
+use crate::clock::{clock, Clock};
+use crate::db::Database;
+use crate::llm::{estimate_tokens, LlmProvider};
+use crate::prompts::BudgetConfig;
+
+/// Token counts for one LLM call
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TokenUsage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+}
+
+impl TokenUsage {
+    /// Fallback for providers that don't report usage
+    pub fn estimate(prompt: &str, completion: &str) -> Self {
+        Self {
+            prompt_tokens: estimate_tokens(prompt),
+            completion_tokens: estimate_tokens(completion),
+        }
+    }
+
+    pub fn add(&mut self, other: &TokenUsage) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+    }
+
+    pub fn total(&self) -> u64 {
+        self.prompt_tokens + self.completion_tokens
+    }
+}
+
+/// Usage accumulated for one provider/model pair this session
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModelUsage {
+    pub provider: String,
+    pub model: String,
+    pub requests: u64,
+    pub tokens: TokenUsage,
+    /// True if any of the counts were estimated rather than reported by the API
+    pub estimated: bool,
+}
+
+impl ModelUsage {
+    pub fn cost(&self) -> Option<f64> {
+        estimate_cost(&self.model, &self.tokens)
+    }
+}
+
+static SESSION_USAGE: Mutex<Vec<ModelUsage>> = Mutex::new(Vec::new());
+
+// USD per million (prompt, completion) tokens, matched against the model name.
+// More specific names come first. Local models are free.
+const MODEL_PRICES: &[(&str, f64, f64)] = &[
+    ("claude-opus-4", 15.0, 75.0),
+    ("claude-sonnet-4", 3.0, 15.0),
+    ("claude-3.7-sonnet", 3.0, 15.0),
+    ("claude-3-7-sonnet", 3.0, 15.0),
+    ("claude-3.5-sonnet", 3.0, 15.0),
+    ("claude-3-5-sonnet", 3.0, 15.0),
+    ("claude-3.5-haiku", 0.8, 4.0),
+    ("claude-3-5-haiku", 0.8, 4.0),
+    ("gemini-2.5-pro", 1.25, 10.0),
+    ("gemini-2.5-flash", 0.15, 0.6),
+    ("gpt-4o-mini", 0.15, 0.6),
+    ("gpt-4o", 2.5, 10.0),
+    ("llama-3.3-70b-versatile", 0.59, 0.79),
+    ("llama-3.1-8b-instant", 0.05, 0.08),
+    ("llama-3.3-70b-instruct-turbo", 0.88, 0.88),
+    ("llama-3.2-3b-instruct-turbo", 0.06, 0.06),
+];
+
+/// Record one LLM call against the session totals and the all-time history in the database
+pub fn record(provider: &LlmProvider, model: &str, tokens: TokenUsage, estimated: bool) {
+    let provider = provider_label(provider);
+    if let Err(e) = Database::open().and_then(|db| db.insert_usage(provider, model, &tokens, estimated)) {
+        eprintln!("Warning: Failed to record token usage: {}", e);
+    }
+    
+    let Ok(mut usage) = SESSION_USAGE.lock() else { return };
+
+    match usage.iter_mut().find(|entry| entry.provider == provider && entry.model == model) {
+        Some(entry) => {
+            entry.requests += 1;
+            entry.tokens.add(&tokens);
+            entry.estimated |= estimated;
+        }
+        None => usage.push(ModelUsage {
+            provider: provider.to_string(),
+            model: model.to_string(),
+            requests: 1,
+            tokens,
+            estimated,
+        }),
+    }
+}
+
+pub fn session_usage() -> Vec<ModelUsage> {
+    SESSION_USAGE.lock().map(|usage| usage.clone()).unwrap_or_default()
+}
+
+/// (total tokens, estimated cost in USD) for the session so far
+pub fn session_totals() -> (u64, f64) {
+    session_usage().iter().fold((0, 0.0), |(tokens, cost), entry| {
+        (tokens + entry.tokens.total(), cost + entry.cost().unwrap_or(0.0))
+    })
+}
+
+pub fn estimate_cost(model: &str, tokens: &TokenUsage) -> Option<f64> {
+    let model = model.to_lowercase();
+    let (_, prompt_price, completion_price) = MODEL_PRICES
+        .iter()
+        .find(|(name, _, _)| model.contains(name))?;
+
+    Some((tokens.prompt_tokens as f64 * prompt_price + tokens.completion_tokens as f64 * completion_price) / 1_000_000.0)
+}
+
+fn provider_label(provider: &LlmProvider) -> &'static str {
+    match provider {
+        LlmProvider::Ollama => "Ollama",
+        LlmProvider::Gemini => "Gemini",
+        LlmProvider::Anthropic => "Anthropic",
+        LlmProvider::OpenRouter => "OpenRouter",
+        LlmProvider::Azure => "Azure",
+        LlmProvider::Bedrock => "Bedrock",
+        LlmProvider::Groq => "Groq",
+        LlmProvider::Together => "Together",
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BudgetPeriod {
+    Daily,
+    Weekly,
+}
+
+impl BudgetPeriod {
+    pub fn label(&self) -> &'static str {
+        match self {
+            BudgetPeriod::Daily => "daily",
+            BudgetPeriod::Weekly => "weekly",
+        }
+    }
+
+    /// The start of the user's day today, or of the Monday of this week
+    fn start(&self, clock: &Clock, now: DateTime<Utc>) -> DateTime<Utc> {
+        let today = clock.to_user(&now);
+        let days_back = match self {
+            BudgetPeriod::Daily => 0,
+            BudgetPeriod::Weekly => today.weekday().num_days_from_monday() as i64,
+        };
+        clock.start_of_day(today.date_naive() - Duration::days(days_back))
+    }
+}
+
+/// A provider's spend against one of its limits
+#[derive(Debug, Clone, PartialEq)]
+pub struct BudgetStatus {
+    pub provider: String,
+    pub period: BudgetPeriod,
+    pub limit: f64,
+    pub spent: f64,
+}
+
+impl BudgetStatus {
+    pub fn exceeded(&self) -> bool {
+        self.spent >= self.limit
+    }
+}
+
+impl std::fmt::Display for BudgetStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} budget: ${:.2} of ${:.2}", self.provider, self.period.label(), self.spent, self.limit)
+    }
+}
+
+// Set by /budget override; lets over-budget requests through for the rest of the session
+static BUDGET_OVERRIDE: AtomicBool = AtomicBool::new(false);
+
+pub fn set_budget_override(enabled: bool) {
+    BUDGET_OVERRIDE.store(enabled, Ordering::Relaxed);
+}
+
+pub fn budget_overridden() -> bool {
+    BUDGET_OVERRIDE.load(Ordering::Relaxed)
+}
+
+/// Estimated spend for `provider` (a label like "Anthropic") in `usage`; unpriced models count as free
+fn provider_spend(usage: &[ModelUsage], provider: &str) -> f64 {
+    usage
+        .iter()
+        .filter(|entry| entry.provider.eq_ignore_ascii_case(provider))
+        .map(|entry| entry.cost().unwrap_or(0.0))
+        .sum()
+}
+
+fn limit_for(limits: &std::collections::HashMap<String, f64>, provider: &str) -> Option<f64> {
+    limits
+        .iter()
+        .find(|(name, limit)| name.eq_ignore_ascii_case(provider) && **limit > 0.0)
+        .map(|(_, limit)| *limit)
+}
+
+/// Spend against every limit configured for `provider`
+pub fn budget_statuses(provider: &LlmProvider, config: &BudgetConfig) -> anyhow::Result<Vec<BudgetStatus>> {
+    let label = provider_label(provider);
+    let periods = [
+        (BudgetPeriod::Daily, limit_for(&config.daily, provider.name())),
+        (BudgetPeriod::Weekly, limit_for(&config.weekly, provider.name())),
+    ];
+    if periods.iter().all(|(_, limit)| limit.is_none()) {
+        return Ok(Vec::new());
+    }
+
+    let db = Database::open()?;
+    let now = Utc::now();
+    let mut statuses = Vec::new();
+    for (period, limit) in periods {
+        let Some(limit) = limit else { continue };
+        let spent = provider_spend(&db.usage_totals_since(period.start(clock(), now))?, label);
+        statuses.push(BudgetStatus { provider: label.to_string(), period, limit, spent });
+    }
+    Ok(statuses)
+}
+
+/// The first limit `provider` has used up, unless the budget was overridden this session
+pub fn exceeded_budget(provider: &LlmProvider, config: &BudgetConfig) -> Option<BudgetStatus> {
+    if budget_overridden() {
+        return None;
+    }
+    match budget_statuses(provider, config) {
+        Ok(statuses) => statuses.into_iter().find(|status| status.exceeded()),
+        Err(e) => {
+            eprintln!("Warning: Failed to check the spend budget: {}", e);
+            None
+        }
+    }
+}
+
+/// All-time usage per provider/model, from the database
+pub fn all_time_usage() -> anyhow::Result<Vec<ModelUsage>> {
+    Database::open()?.usage_totals()
+}
+
+/// Per-model breakdown shown by `/usage`; `period` is e.g. "this session"
+pub fn format_usage_report(usage: &[ModelUsage], period: &str) -> String {
+    if usage.is_empty() {
+        return format!("No LLM calls {}", period);
+    }
+
+    let mut report = format!("Token usage {}:\n", period);
+    let mut total_tokens = 0;
+    let mut total_cost = 0.0;
+    for entry in usage {
+        let cost = match (entry.provider.as_str(), entry.cost()) {
+            ("Ollama", _) => "free (local)".to_string(),
+            (_, Some(cost)) => format!("${:.4}", cost),
+            (_, None) => "unknown price".to_string(),
+        };
+        report.push_str(&format!(
+            "  {}/{}: {} request(s), {} prompt + {} completion tokens, {}{}\n",
+            entry.provider,
+            entry.model,
+            entry.requests,
+            entry.tokens.prompt_tokens,
+            entry.tokens.completion_tokens,
+            cost,
+            if entry.estimated { " (estimated)" } else { "" }
+        ));
+        total_tokens += entry.tokens.total();
+        total_cost += entry.cost().unwrap_or(0.0);
+    }
+    report.push_str(&format!("Total: {} tokens, ~${:.4}", total_tokens, total_cost));
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use crate::prompts::TimeConfig;
+
+    #[test]
+    fn test_estimate_cost() {
+        let tokens = TokenUsage { prompt_tokens: 1_000_000, completion_tokens: 100_000 };
+        let cost = estimate_cost("claude-sonnet-4-20250514", &tokens).unwrap();
+        assert!((cost - 4.5).abs() < 1e-9);
+
+        // OpenRouter ids carry a vendor prefix
+        assert!(estimate_cost("anthropic/claude-3.5-haiku", &tokens).is_some());
+        assert_eq!(estimate_cost("qwen3:8b", &tokens), None);
+    }
+
+    #[test]
+    fn test_budget_periods_and_spend() {
+        let fixed = Clock::from_config(&TimeConfig { timezone: Some("-05:00".to_string()), ..TimeConfig::default() });
+        // Tuesday 03:00 UTC is still Monday evening at UTC-5
+        let now = Utc.with_ymd_and_hms(2026, 3, 3, 3, 0, 0).unwrap();
+        let daily = BudgetPeriod::Daily.start(&fixed, now);
+        let weekly = BudgetPeriod::Weekly.start(&fixed, now);
+        assert_eq!(daily, Utc.with_ymd_and_hms(2026, 3, 2, 5, 0, 0).unwrap());
+        assert_eq!(weekly, daily);
+        assert_eq!(BudgetPeriod::Weekly.start(&fixed, now + Duration::days(3)), daily);
+
+        let now = Utc::now();
+        let daily = BudgetPeriod::Daily.start(clock(), now);
+        let weekly = BudgetPeriod::Weekly.start(clock(), now);
+        assert!(weekly <= daily && daily <= now);
+        assert!(now - weekly < Duration::days(8));
+        assert_eq!(clock().to_user(&weekly).weekday(), chrono::Weekday::Mon);
+
+        let usage = vec![
+            ModelUsage {
+                provider: "Anthropic".to_string(),
+                model: "claude-sonnet-4-20250514".to_string(),
+                requests: 1,
+                tokens: TokenUsage { prompt_tokens: 1_000_000, completion_tokens: 0 },
+                estimated: false,
+            },
+            ModelUsage {
+                provider: "Anthropic".to_string(),
+                model: "some-unpriced-model".to_string(),
+                requests: 1,
+                tokens: TokenUsage { prompt_tokens: 1_000_000, completion_tokens: 0 },
+                estimated: false,
+            },
+        ];
+        assert!((provider_spend(&usage, "anthropic") - 3.0).abs() < 1e-9);
+        assert_eq!(provider_spend(&usage, "Gemini"), 0.0);
+
+        let limits = [("Anthropic".to_string(), 5.0), ("gemini".to_string(), 0.0)].into_iter().collect();
+        assert_eq!(limit_for(&limits, "anthropic"), Some(5.0));
+        // A zero limit means no limit
+        assert_eq!(limit_for(&limits, "gemini"), None);
+    }
+
+    #[test]
+    fn test_format_usage_report() {
+        let usage = vec![
+            ModelUsage {
+                provider: "Anthropic".to_string(),
+                model: "claude-sonnet-4-20250514".to_string(),
+                requests: 2,
+                tokens: TokenUsage { prompt_tokens: 1000, completion_tokens: 500 },
+                estimated: false,
+            },
+            ModelUsage {
+                provider: "Ollama".to_string(),
+                model: "qwen3:8b".to_string(),
+                requests: 1,
+                tokens: TokenUsage { prompt_tokens: 10, completion_tokens: 20 },
+                estimated: false,
+            },
+        ];
+
+        let report = format_usage_report(&usage, "this session");
+        assert!(report.contains("Anthropic/claude-sonnet-4-20250514: 2 request(s)"));
+        assert!(report.contains("free (local)"));
+        assert!(report.contains("Total: 1530 tokens, ~$0.0105"));
+        assert_eq!(format_usage_report(&[], "this session"), "No LLM calls this session");
+    }
+}
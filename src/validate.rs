@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::sync::Mutex;
+
+use anyhow::{Context, Result};
+
+// Lines of parser output kept per file; the first error is what matters
+const MAX_MESSAGE_LINES: usize = 12;
+
+/// An edited file that its language's parser now rejects
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseFailure {
+    pub path: String,
+    pub tool: &'static str,
+    pub message: String,
+}
+
+/// What to do about edits that broke a file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FailureAction {
+    /// Put the files back the way they were before the edits
+    Revert,
+    /// Keep the edits and ask the model to fix them
+    Fix,
+    Keep,
+}
+
+/// The parse-only check for a file, as (tool, program, args), from its extension.
+/// Nothing is compiled or written: rustfmt prints to stdout, and Python compiles in memory.
+fn command_for(path: &str) -> Option<(&'static str, &'static str, Vec<String>)> {
+    let extension = Path::new(path).extension()?.to_str()?.to_lowercase();
+    let path = path.to_string();
+    match extension.as_str() {
+        "rs" => Some(("rustfmt", "rustfmt", vec!["--edition".to_string(), "2021".to_string(), "--emit".to_string(), "stdout".to_string(), path])),
+        "py" => Some((
+            "python",
+            "python3",
+            vec!["-c".to_string(), "import sys; compile(open(sys.argv[1], encoding='utf-8').read(), sys.argv[1], 'exec')".to_string(), path],
+        )),
+        "js" | "mjs" | "cjs" => Some(("node --check", "node", vec!["--check".to_string(), path])),
+        _ => None,
+    }
+}
+
+/// Parse `path` with its language's tool. None when it parses, when there's no check for
+/// the language, or when the tool isn't installed.
+pub fn check(path: &str) -> Option<ParseFailure> {
+    let (tool, program, args) = command_for(path)?;
+    let output = Command::new(program).args(&args).output().ok()?;
+    if output.status.success() {
+        return None;
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let message = stderr.lines().take(MAX_MESSAGE_LINES).collect::<Vec<_>>().join("\n");
+    Some(ParseFailure { path: path.to_string(), tool, message: message.trim().to_string() })
+}
+
+pub fn check_all(paths: &[String]) -> Vec<ParseFailure> {
+    paths.iter().filter_map(|path| check(path)).collect()
+}
+
+/// Follow-up request asking the model to repair the files it broke
+pub fn fix_prompt(failures: &[ParseFailure]) -> String {
+    let mut prompt = String::from("Your last edits left these files unparseable. Fix them with SEARCH/REPLACE blocks:\n");
+    for failure in failures {
+        prompt.push_str(&format!("\n{} ({}):\n{}\n", failure.path, failure.tool, failure.message));
+    }
+    prompt
+}
+
+/// Contents of files as they were before the first edit to each, for reverting. Files the
+/// edits created are remembered as missing, and reverting removes them.
+#[derive(Debug, Clone, Default)]
+pub struct FileSnapshots {
+    // Bytes, so files that aren't UTF-8 can be put back too
+    contents: HashMap<String, Option<Vec<u8>>>,
+}
+
+impl FileSnapshots {
+    /// Remember `path` unless it was already; call before each edit
+    pub fn record(&mut self, path: &str) {
+        if !self.contents.contains_key(path) {
+            if let Ok(content) = fs::read(path) {
+                self.contents.insert(path.to_string(), Some(content));
+            } else if !Path::new(path).exists() {
+                self.contents.insert(path.to_string(), None);
+            }
+        }
+    }
+
+    /// The copy taken of `path`: None if it wasn't recorded or isn't text, Some(None) if it
+    /// didn't exist yet
+    pub fn original(&self, path: &str) -> Option<Option<String>> {
+        match self.contents.get(path)? {
+            Some(content) => String::from_utf8(content.clone()).ok().map(Some),
+            None => Some(None),
+        }
+    }
+
+    pub fn restore(&self, path: &str) -> Result<()> {
+        match self.contents.get(path).ok_or_else(|| anyhow::anyhow!("No copy of {} from before the edits", path))? {
+            Some(content) => fs::write(path, content).with_context(|| format!("Failed to restore {}", path)),
+            None => fs::remove_file(path).with_context(|| format!("Failed to remove {}, which the edits created", path)),
+        }
+    }
+}
+
+// A fix-it prompt chosen while applying edits, picked up by the CLI once the current request is done
+static FIX_REQUEST: Mutex<Option<String>> = Mutex::new(None);
+
+pub fn request_fix(prompt: String) {
+    *FIX_REQUEST.lock().unwrap_or_else(|e| e.into_inner()) = Some(prompt);
+}
+
+pub fn take_fix_request() -> Option<String> {
+    FIX_REQUEST.lock().unwrap_or_else(|e| e.into_inner()).take()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_checks_by_language() {
+        assert_eq!(command_for("src/main.rs").map(|(tool, _, _)| tool), Some("rustfmt"));
+        assert_eq!(command_for("scripts/build.PY").map(|(_, program, _)| program), Some("python3"));
+        assert_eq!(command_for("web/app.mjs").map(|(_, _, args)| args), Some(vec!["--check".to_string(), "web/app.mjs".to_string()]));
+        assert!(command_for("README.md").is_none());
+        assert!(command_for("Makefile").is_none());
+        assert!(check("README.md").is_none());
+    }
+
+    #[test]
+    fn test_snapshots_and_fix_prompt() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("lib.py").to_string_lossy().to_string();
+        fs::write(&path, "x = 1\n").unwrap();
+
+        let mut snapshots = FileSnapshots::default();
+        snapshots.record(&path);
+        fs::write(&path, "x = (\n").unwrap();
+        snapshots.record(&path);
+        snapshots.restore(&path).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "x = 1\n");
+        assert!(snapshots.restore("missing.py").is_err());
+
+        let latin1 = dir.path().join("notes.txt").to_string_lossy().to_string();
+        fs::write(&latin1, b"caf\xe9\n").unwrap();
+        snapshots.record(&latin1);
+        fs::write(&latin1, "cafe\n").unwrap();
+        snapshots.restore(&latin1).unwrap();
+        assert_eq!(fs::read(&latin1).unwrap(), b"caf\xe9\n");
+        assert_eq!(snapshots.original(&latin1), None);
+
+        let created = dir.path().join("new.py").to_string_lossy().to_string();
+        snapshots.record(&created);
+        fs::write(&created, "y = (\n").unwrap();
+        snapshots.restore(&created).unwrap();
+        assert!(!Path::new(&created).exists());
+
+        let failures = vec![ParseFailure { path: "lib.py".to_string(), tool: "python", message: "SyntaxError: '(' was never closed".to_string() }];
+        assert!(fix_prompt(&failures).ends_with("\nlib.py (python):\nSyntaxError: '(' was never closed\n"));
+    }
+}
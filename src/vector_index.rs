@@ -0,0 +1,169 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::llm::cache::fnv1a;
+use crate::llm::embeddings::{cosine_similarity, embed_text, embed_texts};
+use crate::prompts::EmbeddingsConfig;
+
+pub const VECTOR_DIR: &str = ".kota/vectors";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VectorEntry {
+    pub id: String,
+    /// Hash of the embedded text, to tell when a document changed
+    pub text_hash: u64,
+    pub vector: Vec<f32>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct IndexFile {
+    /// Vectors from different models can't be compared, so a model change empties the index
+    model: String,
+    entries: Vec<VectorEntry>,
+}
+
+/// Embeddings for one kind of document, kept in a JSON file under `.kota/vectors`.
+/// Search is a linear scan, which is plenty for a personal knowledge base.
+pub struct VectorIndex {
+    path: PathBuf,
+    index: IndexFile,
+}
+
+impl VectorIndex {
+    pub fn open(name: &str, model: &str) -> Self {
+        Self::open_at(Path::new(VECTOR_DIR).join(format!("{}.json", name)), model)
+    }
+
+    /// A missing, unreadable or other-model index starts out empty
+    pub fn open_at(path: impl Into<PathBuf>, model: &str) -> Self {
+        let path = path.into();
+        let index = fs::read_to_string(&path)
+            .ok()
+            .and_then(|json| serde_json::from_str::<IndexFile>(&json).ok())
+            .filter(|index| index.model == model)
+            .unwrap_or_else(|| IndexFile { model: model.to_string(), entries: Vec::new() });
+        Self { path, index }
+    }
+
+    /// Whether `id` is indexed with exactly this text
+    pub fn contains(&self, id: &str, text: &str) -> bool {
+        let text_hash = fnv1a(&[text]);
+        self.index.entries.iter().any(|entry| entry.id == id && entry.text_hash == text_hash)
+    }
+
+    pub fn upsert(&mut self, id: &str, text: &str, vector: Vec<f32>) {
+        self.index.entries.retain(|entry| entry.id != id);
+        self.index.entries.push(VectorEntry { id: id.to_string(), text_hash: fnv1a(&[text]), vector });
+    }
+
+    /// Drop entries whose ids aren't in `ids`, e.g. deleted memories. Returns how many went.
+    pub fn retain_ids(&mut self, ids: &HashSet<String>) -> usize {
+        let before = self.index.entries.len();
+        self.index.entries.retain(|entry| ids.contains(&entry.id));
+        before - self.index.entries.len()
+    }
+
+    /// The `limit` entries most similar to `query`, best first
+    pub fn search(&self, query: &[f32], limit: usize) -> Vec<(f32, &VectorEntry)> {
+        let mut scored: Vec<(f32, &VectorEntry)> = self
+            .index
+            .entries
+            .iter()
+            .map(|entry| (cosine_similarity(query, &entry.vector), entry))
+            .collect();
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+        scored.truncate(limit);
+        scored
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+        }
+        fs::write(&self.path, serde_json::to_string(&self.index)?)
+            .with_context(|| format!("Failed to write vector index {}", self.path.display()))
+    }
+
+    /// Embed the documents that are new or changed, forget ones that are gone, then rank all
+    /// of them against `query`. Returns (score, id) pairs, best first.
+    pub async fn refresh_and_search(
+        &mut self,
+        config: &EmbeddingsConfig,
+        documents: &[(String, String)],
+        query: &str,
+        limit: usize,
+    ) -> Result<Vec<(f32, String)>> {
+        let ids: HashSet<String> = documents.iter().map(|(id, _)| id.clone()).collect();
+        let removed = self.retain_ids(&ids);
+
+        let stale: Vec<&(String, String)> = documents.iter().filter(|(id, text)| !self.contains(id, text)).collect();
+        let embedded = stale.len();
+        if !stale.is_empty() {
+            let texts: Vec<String> = stale.iter().map(|(_, text)| text.clone()).collect();
+            let vectors = embed_texts(config, &texts).await?;
+            for ((id, text), vector) in stale.into_iter().zip(vectors) {
+                self.upsert(id, text, vector);
+            }
+        }
+        if removed > 0 || embedded > 0 {
+            self.save()?;
+        }
+
+        let query_vector = embed_text(config, query).await?;
+        Ok(self
+            .search(&query_vector, limit)
+            .into_iter()
+            .map(|(score, entry)| (score, entry.id.clone()))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_vector_index_search_and_persistence() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("memories.json");
+
+        let mut index = VectorIndex::open_at(&path, "test-model");
+        index.upsert("rust", "Rust ownership", vec![1.0, 0.0, 0.0]);
+        index.upsert("python", "Python decorators", vec![0.0, 1.0, 0.0]);
+        index.upsert("mixed", "Rust and Python", vec![0.7, 0.7, 0.0]);
+        index.save().unwrap();
+
+        let index = VectorIndex::open_at(&path, "test-model");
+        assert_eq!(index.index.entries.len(), 3);
+        assert!(index.contains("rust", "Rust ownership"));
+        assert!(!index.contains("rust", "Rust borrowing"));
+
+        let hits = index.search(&[0.9, 0.1, 0.0], 2);
+        assert_eq!(hits[0].1.id, "rust");
+        assert_eq!(hits[1].1.id, "mixed");
+
+        // Another model's vectors aren't comparable, so they're ignored
+        assert_eq!(VectorIndex::open_at(&path, "other-model").index.entries.len(), 0);
+    }
+
+    #[test]
+    fn test_upsert_replaces_and_retain_drops() {
+        let dir = TempDir::new().unwrap();
+        let mut index = VectorIndex::open_at(dir.path().join("context.json"), "m");
+        index.upsert("a", "old", vec![1.0]);
+        index.upsert("a", "new", vec![1.0]);
+        index.upsert("b", "other", vec![1.0]);
+        assert_eq!(index.index.entries.len(), 2);
+        assert!(index.contains("a", "new"));
+
+        let keep: HashSet<String> = ["a".to_string()].into_iter().collect();
+        assert_eq!(index.retain_ids(&keep), 1);
+        assert_eq!(index.index.entries.len(), 1);
+    }
+}
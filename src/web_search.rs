@@ -0,0 +1,182 @@
+use anyhow::{Context, Result};
+use regex::Regex;
+use reqwest::ClientBuilder;
+use std::time::Duration;
+
+const DUCKDUCKGO_API_URL: &str = "https://api.duckduckgo.com/";
+const FETCH_TIMEOUT_SECS: u64 = 20;
+const MAX_PAGE_CHARS: usize = 6000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchResult {
+    pub title: String,
+    pub url: String,
+    pub snippet: String,
+}
+
+/// Query the DuckDuckGo Instant Answer API. No API key is needed, but results are
+/// limited to the abstract and related topics rather than a full web index.
+pub async fn search(query: &str) -> Result<Vec<SearchResult>> {
+    let client = ClientBuilder::new()
+        .timeout(Duration::from_secs(FETCH_TIMEOUT_SECS))
+        .build()
+        .context("Failed to create HTTP client")?;
+
+    let response = client
+        .get(DUCKDUCKGO_API_URL)
+        .query(&[("q", query), ("format", "json"), ("no_html", "1"), ("skip_disambig", "1")])
+        .send()
+        .await
+        .with_context(|| format!("Search request failed for '{}'", query))?;
+
+    if !response.status().is_success() {
+        return Err(anyhow::anyhow!("Search request failed with status {}", response.status()));
+    }
+
+    let body: serde_json::Value = response
+        .json()
+        .await
+        .context("Failed to parse search response")?;
+
+    Ok(parse_instant_answer(&body))
+}
+
+fn parse_instant_answer(body: &serde_json::Value) -> Vec<SearchResult> {
+    let mut results = Vec::new();
+
+    let abstract_url = body["AbstractURL"].as_str().unwrap_or("");
+    if !abstract_url.is_empty() {
+        results.push(SearchResult {
+            title: body["Heading"].as_str().unwrap_or(abstract_url).to_string(),
+            url: abstract_url.to_string(),
+            snippet: body["AbstractText"].as_str().unwrap_or("").to_string(),
+        });
+    }
+
+    let mut topics: Vec<&serde_json::Value> = Vec::new();
+    for key in ["Results", "RelatedTopics"] {
+        if let Some(items) = body[key].as_array() {
+            for item in items {
+                // Topic groups nest their entries one level down
+                match item["Topics"].as_array() {
+                    Some(nested) => topics.extend(nested.iter()),
+                    None => topics.push(item),
+                }
+            }
+        }
+    }
+
+    for topic in topics {
+        let (Some(url), Some(text)) = (topic["FirstURL"].as_str(), topic["Text"].as_str()) else {
+            continue;
+        };
+        // Topic text starts with the title, followed by " - " and a description
+        let title = text.split(" - ").next().unwrap_or(text).to_string();
+        results.push(SearchResult {
+            title,
+            url: url.to_string(),
+            snippet: text.to_string(),
+        });
+    }
+
+    results
+}
+
+/// Download a page and reduce it to readable text
+pub async fn fetch_page_text(url: &str) -> Result<String> {
+    let client = ClientBuilder::new()
+        .timeout(Duration::from_secs(FETCH_TIMEOUT_SECS))
+        .build()
+        .context("Failed to create HTTP client")?;
+
+    let response = client
+        .get(url)
+        .header("User-Agent", "kota-rust-cli")
+        .send()
+        .await
+        .with_context(|| format!("Failed to fetch {}", url))?;
+
+    if !response.status().is_success() {
+        return Err(anyhow::anyhow!("Fetching {} failed with status {}", url, response.status()));
+    }
+
+    let html = response.text().await?;
+    let mut text = html_to_text(&html);
+    if text.len() > MAX_PAGE_CHARS {
+        let mut cut = MAX_PAGE_CHARS;
+        while !text.is_char_boundary(cut) {
+            cut -= 1;
+        }
+        text.truncate(cut);
+    }
+
+    Ok(text)
+}
+
+pub fn html_to_text(html: &str) -> String {
+    let scripts = Regex::new(r"(?is)<(script|style|noscript)[^>]*>.*?</(script|style|noscript)>").unwrap();
+    let tags = Regex::new(r"(?s)<[^>]+>").unwrap();
+    let whitespace = Regex::new(r"\s+").unwrap();
+
+    let without_scripts = scripts.replace_all(html, " ");
+    let without_tags = tags.replace_all(&without_scripts, " ");
+    let decoded = without_tags
+        .replace("&nbsp;", " ")
+        .replace("&amp;", "&")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'");
+
+    whitespace.replace_all(&decoded, " ").trim().to_string()
+}
+
+/// Canonical form of a URL used to spot the same source reached through different links
+pub fn normalize_url(url: &str) -> String {
+    let without_fragment = url.split('#').next().unwrap_or(url);
+    let without_scheme = without_fragment
+        .trim_start_matches("https://")
+        .trim_start_matches("http://");
+    let without_www = without_scheme.strip_prefix("www.").unwrap_or(without_scheme);
+
+    without_www.trim_end_matches('/').to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_instant_answer() {
+        let body = serde_json::json!({
+            "Heading": "Tokio",
+            "AbstractURL": "https://en.wikipedia.org/wiki/Tokio_(software)",
+            "AbstractText": "Tokio is an asynchronous runtime for Rust.",
+            "Results": [],
+            "RelatedTopics": [
+                {"FirstURL": "https://duckduckgo.com/Async_Rust", "Text": "Async Rust - Asynchronous programming in Rust"},
+                {"Name": "See also", "Topics": [
+                    {"FirstURL": "https://duckduckgo.com/Futures", "Text": "Futures - Deferred values"}
+                ]}
+            ]
+        });
+
+        let results = parse_instant_answer(&body);
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].title, "Tokio");
+        assert_eq!(results[1].title, "Async Rust");
+        assert_eq!(results[2].url, "https://duckduckgo.com/Futures");
+    }
+
+    #[test]
+    fn test_html_to_text() {
+        let html = "<html><head><style>p { color: red; }</style></head><body><p>Hello&nbsp;<b>world</b></p><script>alert(1)</script></body></html>";
+        assert_eq!(html_to_text(html), "Hello world");
+    }
+
+    #[test]
+    fn test_normalize_url() {
+        assert_eq!(normalize_url("https://www.Example.com/docs/#intro"), "example.com/docs");
+        assert_eq!(normalize_url("http://example.com/docs"), "example.com/docs");
+    }
+}
@@ -0,0 +1,176 @@
+use std::time::Duration;
+
+use chrono::Utc;
+use serde_json::{json, Value};
+
+use crate::focus::{self, Category};
+use crate::prompts::{PromptsConfig, Webhook, WebhookFormat};
+
+const WEBHOOK_TIMEOUT_SECS: u64 = 10;
+// Test output beyond this is cut from the front; the failures are usually at the end
+const MAX_OUTPUT_CHARS: usize = 2000;
+
+// Programs and subcommands whose failure means tests failed
+const TEST_RUNNERS: &[&str] = &["test", "tests", "pytest", "jest", "vitest", "mocha", "rspec", "ctest", "phpunit", "nextest", "tox"];
+
+/// Something `[webhooks]` can be told about
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    EditApplied { files: Vec<String>, prompt: String },
+    TestsFailed { command: String, output: String },
+    AgentFinished { agent: String, task: String, success: bool, status: String },
+    BudgetExceeded { budget: String, action: String },
+}
+
+impl Event {
+    /// The name webhooks filter on
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::EditApplied { .. } => "edit_applied",
+            Event::TestsFailed { .. } => "tests_failed",
+            Event::AgentFinished { .. } => "agent_finished",
+            Event::BudgetExceeded { .. } => "budget_exceeded",
+        }
+    }
+
+    /// The bridge message type a focus block sorts it under
+    pub fn category(&self) -> Category {
+        match self {
+            Event::EditApplied { .. } => Category::Update,
+            Event::AgentFinished { success: true, .. } => Category::Info,
+            Event::TestsFailed { .. } | Event::AgentFinished { .. } | Event::BudgetExceeded { .. } => Category::Error,
+        }
+    }
+
+    pub fn summary(&self) -> String {
+        match self {
+            Event::EditApplied { files, .. } => format!("KOTA applied edits to {}", files.join(", ")),
+            Event::TestsFailed { command, .. } => format!("Tests failed: {}", command),
+            Event::AgentFinished { agent, task, success, .. } => {
+                format!("{} {}: {}", agent, if *success { "finished" } else { "failed" }, task)
+            }
+            Event::BudgetExceeded { budget, action } => format!("{} reached; {}", budget, action),
+        }
+    }
+
+    fn data(&self) -> Value {
+        match self {
+            Event::EditApplied { files, prompt } => json!({ "files": files, "prompt": prompt }),
+            Event::TestsFailed { command, output } => {
+                let skip = output.chars().count().saturating_sub(MAX_OUTPUT_CHARS);
+                json!({ "command": command, "output": output.chars().skip(skip).collect::<String>() })
+            }
+            Event::AgentFinished { agent, task, success, status } => {
+                json!({ "agent": agent, "task": task, "success": success, "status": status })
+            }
+            Event::BudgetExceeded { budget, action } => json!({ "budget": budget, "action": action }),
+        }
+    }
+
+    /// The JSON body. `text` repeats the summary so chat webhooks like Slack's show it as is.
+    pub fn payload(&self, project: &str) -> Value {
+        json!({
+            "event": self.name(),
+            "summary": self.summary(),
+            "text": self.summary(),
+            "timestamp": Utc::now().to_rfc3339(),
+            "project": project,
+            "data": self.data(),
+        })
+    }
+}
+
+/// Whether a failed `command` was a test run, like `cargo test` or `npm run test:unit`
+pub fn is_test_command(command: &str) -> bool {
+    command.split_whitespace().any(|word| {
+        let program = word.rsplit('/').next().unwrap_or(word);
+        let name = program.split(':').next().unwrap_or(program);
+        TEST_RUNNERS.contains(&name)
+    })
+}
+
+fn wants(hook: &Webhook, event: &Event) -> bool {
+    hook.events.is_empty() || hook.events.iter().any(|name| name.trim() == event.name())
+}
+
+/// Send `event` to every webhook that wants it, in the background. Failures are warnings;
+/// they never hold up the session.
+pub fn fire(event: Event) {
+    let hooks: Vec<Webhook> = PromptsConfig::load()
+        .unwrap_or_default()
+        .webhooks
+        .hooks
+        .into_iter()
+        .filter(|hook| wants(hook, &event))
+        .collect();
+    if hooks.is_empty() || focus::intercept(event.category(), &event.summary()) {
+        return;
+    }
+    let Ok(runtime) = tokio::runtime::Handle::try_current() else { return };
+    let project = std::env::current_dir()
+        .ok()
+        .and_then(|dir| dir.file_name().map(|name| name.to_string_lossy().to_string()))
+        .unwrap_or_default();
+    runtime.spawn(async move {
+        let client = match reqwest::Client::builder().timeout(Duration::from_secs(WEBHOOK_TIMEOUT_SECS)).build() {
+            Ok(client) => client,
+            Err(e) => return eprintln!("Warning: Failed to create HTTP client for webhooks: {}", e),
+        };
+        for hook in hooks {
+            let mut request = client.post(&hook.url);
+            for (name, value) in &hook.headers {
+                request = request.header(name, value);
+            }
+            request = match hook.format {
+                WebhookFormat::Json => request.json(&event.payload(&project)),
+                WebhookFormat::Text => request.header("Content-Type", "text/plain").body(event.summary()),
+            };
+            match request.send().await {
+                Ok(response) if !response.status().is_success() => {
+                    eprintln!("Warning: Webhook {} for {} returned {}", hook.url, event.name(), response.status());
+                }
+                Ok(_) => {}
+                Err(e) => eprintln!("Warning: Webhook {} for {} failed: {}", hook.url, event.name(), e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_payload_and_filters() {
+        let event = Event::AgentFinished { agent: "CodeAgent".to_string(), task: "Add /compact".to_string(), success: true, status: "Completed".to_string() };
+        let payload = event.payload("kota-rust-cli");
+        assert_eq!(payload["event"], "agent_finished");
+        assert_eq!(payload["text"], "CodeAgent finished: Add /compact");
+        assert_eq!(payload["project"], "kota-rust-cli");
+        assert_eq!(payload["data"]["success"], true);
+
+        let hook = |events: &[&str]| Webhook {
+            url: "http://localhost".to_string(),
+            events: events.iter().map(|name| name.to_string()).collect(),
+            format: WebhookFormat::Json,
+            headers: Default::default(),
+        };
+        assert!(wants(&hook(&[]), &event));
+        assert!(wants(&hook(&["tests_failed", "agent_finished"]), &event));
+        assert!(!wants(&hook(&["edit_applied"]), &event));
+
+        let output = "x".repeat(MAX_OUTPUT_CHARS + 10);
+        let failed = Event::TestsFailed { command: "cargo test".to_string(), output };
+        assert_eq!(failed.payload("p")["data"]["output"].as_str().unwrap().len(), MAX_OUTPUT_CHARS);
+    }
+
+    #[test]
+    fn test_is_test_command() {
+        assert!(is_test_command("cargo test --workspace"));
+        assert!(is_test_command("npm run test:unit"));
+        assert!(is_test_command("./node_modules/.bin/jest src"));
+        assert!(is_test_command("python -m pytest -x"));
+        assert!(!is_test_command("cargo build"));
+        assert!(!is_test_command("cat tests.txt"));
+    }
+}